@@ -0,0 +1,256 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::Result;
+use argh::FromArgs;
+use lium::dut_file;
+use lium::dut_file::DUT_FILE_ENV;
+use lium::policy;
+use lium::policy::Operation;
+use lium::ui::set_color_mode;
+use lium::ui::set_max_column_width;
+use lium::ui::set_no_ellipsis;
+use lium::ui::set_porcelain;
+use lium::ui::set_quiet;
+use lium::ui::ColorMode;
+use lium::util::set_offline_mode;
+
+pub mod arc;
+pub mod bisect;
+pub mod boards;
+pub mod build;
+pub mod cache;
+pub mod chroot;
+pub mod cl;
+pub mod config;
+pub mod deploy;
+pub mod dev;
+pub mod dut;
+pub mod experiment;
+pub mod flash;
+pub mod fleet;
+pub mod forward;
+pub mod listen;
+pub mod meta;
+pub mod metrics;
+pub mod net;
+pub mod play;
+pub mod plugin;
+pub mod repo;
+pub mod report;
+pub mod results;
+pub mod resume;
+pub mod script;
+pub mod self_update;
+pub mod servo;
+pub mod setup;
+pub mod share;
+pub mod statusd;
+pub mod symbols;
+pub mod sync;
+pub mod tast;
+pub mod toolchain;
+pub mod version;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// yet another wrapper for CrOS developers.
+/// For more information, see: https://chromium.googlesource.com/chromiumos/platform/dev-util/+/refs/heads/main/contrib/lium/ .
+/// For Googlers, see go/lium and go/lium-bug
+pub struct TopLevel {
+    /// use only cached data and fail fast on operations that need the network
+    #[argh(switch)]
+    offline: bool,
+
+    /// colorize the output: auto (default), always or never
+    #[argh(option, default = "ColorMode::Auto")]
+    color: ColorMode,
+
+    /// do not print progress messages
+    #[argh(switch, short = 'q')]
+    quiet: bool,
+
+    /// stable output for scripts: no colors, no unicode symbols and no progress messages
+    #[argh(switch)]
+    porcelain: bool,
+
+    /// truncate the columns of tables (e.g. dut list) wider than this
+    #[argh(option)]
+    max_column_width: Option<usize>,
+
+    /// truncate the columns without the ellipsis
+    #[argh(switch)]
+    no_ellipsis: bool,
+
+    /// a YAML file of DUTs to use instead of the DUT list, without adding them to it
+    /// (default: $LIUM_DUT_FILE)
+    #[argh(option)]
+    dut_file: Option<String>,
+
+    #[argh(subcommand)]
+    nested: Args,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+/// hikalium's ChromiumOS dev commands
+pub enum Args {
+    Arc(arc::Args),
+    Bisect(bisect::Args),
+    Boards(boards::Args),
+    Build(build::Args),
+    Cache(cache::Args),
+    Cl(cl::Args),
+    Chroot(chroot::Args),
+    Config(config::Args),
+    Deploy(deploy::Args),
+    Dev(dev::Args),
+    Dut(dut::Args),
+    Experiment(experiment::Args),
+    Flash(flash::Args),
+    Fleet(fleet::Args),
+    Forward(forward::Args),
+    Listen(listen::Args),
+    Meta(meta::Args),
+    Metrics(metrics::Args),
+    Net(net::Args),
+    Play(play::Args),
+    Repo(repo::Args),
+    Report(report::Args),
+    Results(results::Args),
+    Resume(resume::Args),
+    Script(script::Args),
+    SelfUpdate(self_update::Args),
+    Servo(servo::Args),
+    Setup(setup::Args),
+    Share(share::Args),
+    Statusd(statusd::Args),
+    Symbols(symbols::Args),
+    Sync(sync::Args),
+    Tast(tast::Args),
+    Toolchain(toolchain::Args),
+    Version(version::Args),
+    #[argh(dynamic)]
+    Plugin(plugin::Plugin),
+}
+
+pub fn run(args: &TopLevel) -> Result<()> {
+    set_offline_mode(args.offline);
+    set_color_mode(args.color);
+    set_quiet(args.quiet);
+    set_porcelain(args.porcelain);
+    set_max_column_width(args.max_column_width);
+    set_no_ellipsis(args.no_ellipsis);
+    if let Some(path) = args
+        .dut_file
+        .clone()
+        .or_else(|| std::env::var(DUT_FILE_ENV).ok())
+    {
+        dut_file::load(&path)?;
+    }
+    if let Some((op, dut)) = guarded_operation(&args.nested) {
+        policy::enforce(op, dut)?;
+    }
+    match &args.nested {
+        Args::Arc(args) => arc::run(args),
+        Args::Bisect(args) => bisect::run(args),
+        Args::Boards(args) => boards::run(args),
+        Args::Build(args) => build::run(args),
+        Args::Cache(args) => cache::run(args),
+        Args::Cl(args) => cl::run(args),
+        Args::Chroot(args) => chroot::run(args),
+        Args::Config(args) => config::run(args),
+        Args::Deploy(args) => deploy::run(args),
+        Args::Dev(args) => dev::run(args),
+        Args::Dut(args) => dut::run(args),
+        Args::Experiment(args) => experiment::run(args),
+        Args::Flash(args) => flash::run(args),
+        Args::Fleet(args) => fleet::run(args),
+        Args::Forward(args) => forward::run(args),
+        Args::Listen(args) => listen::run(args),
+        Args::Meta(args) => meta::run(args),
+        Args::Metrics(args) => metrics::run(args),
+        Args::Net(args) => net::run(args),
+        Args::Play(args) => play::run(args),
+        Args::Repo(args) => repo::run(args),
+        Args::Report(args) => report::run(args),
+        Args::Results(args) => results::run(args),
+        Args::Resume(args) => resume::run(args),
+        Args::Script(args) => script::run(args),
+        Args::SelfUpdate(args) => self_update::run(args),
+        Args::Servo(args) => servo::run(args),
+        Args::Setup(args) => setup::run(args),
+        Args::Share(args) => share::run(args),
+        Args::Statusd(args) => statusd::run(args),
+        Args::Symbols(args) => symbols::run(args),
+        Args::Sync(args) => sync::run(args),
+        Args::Tast(args) => tast::run(args),
+        Args::Toolchain(args) => toolchain::run(args),
+        Args::Version(args) => version::run(args),
+        Args::Plugin(args) => plugin::run(args),
+    }
+}
+
+/// Returns the destructive operation of the command, which is restricted by the policy file
+fn guarded_operation(args: &Args) -> Option<(Operation, Option<&str>)> {
+    match args {
+        Args::Arc(args) => arc::guarded_operation(args),
+        Args::Cache(args) => cache::guarded_operation(args).map(|op| (op, None)),
+        Args::Dut(args) => dut::guarded_operation(args),
+        Args::Flash(args) => flash::guarded_operation(args),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guarded_operations() {
+        let op = |cmd: &str| {
+            let cmd: Vec<&str> = cmd.split_whitespace().collect();
+            let args = TopLevel::from_args(&["lium"], &cmd).unwrap();
+            guarded_operation(&args.nested).map(|(op, dut)| (op, dut.map(str::to_string)))
+        };
+        let on = |op, dut: &str| Some((op, Some(dut.to_string())));
+        // Every guarded subcommand
+        assert_eq!(op("flash --dut d"), on(Operation::OsFlash, "d"));
+        assert_eq!(
+            op("dut recover --dut s --image usb"),
+            on(Operation::OsFlash, "s")
+        );
+        assert_eq!(op("arc flash --dut d"), on(Operation::OsFlash, "d"));
+        assert_eq!(
+            op("dut ec --dut d flash ec.bin"),
+            on(Operation::FirmwareWrite, "d")
+        );
+        assert_eq!(op("dut powerwash --dut d"), on(Operation::Powerwash, "d"));
+        assert_eq!(
+            op("dut sanitize --dut d --powerwash"),
+            on(Operation::Powerwash, "d")
+        );
+        assert_eq!(op("dut tpm clear --dut d"), on(Operation::TpmClear, "d"));
+        assert_eq!(op("dut sanitize --dut d"), on(Operation::UserDataWipe, "d"));
+        assert_eq!(
+            op("dut profiles --dut d remove u@example.com"),
+            on(Operation::UserDataWipe, "d")
+        );
+        assert_eq!(op("dut list --clear"), Some((Operation::CacheClear, None)));
+        assert_eq!(op("cache gc"), Some((Operation::CacheClear, None)));
+        assert_eq!(op("cache rm a"), Some((Operation::CacheClear, None)));
+        // Not destructive
+        for cmd in [
+            "flash --board b --usb",
+            "dut sanitize --dut d --dry-run",
+            "dut profiles --dut d list",
+            "dut ec --dut d version",
+            "dut list",
+            "cache ls",
+        ] {
+            assert_eq!(op(cmd), None, "{cmd}");
+        }
+    }
+}
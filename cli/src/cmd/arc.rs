@@ -9,6 +9,7 @@ use argh::FromArgs;
 use lium::chroot::Chroot;
 use lium::cros::ensure_testing_rsa_is_there;
 use lium::dut::SshInfo;
+use lium::policy::Operation;
 use lium::repo::get_repo_dir;
 use std::process::Command;
 
@@ -33,6 +34,13 @@ pub fn run(args: &Args) -> Result<()> {
         SubCommand::Logcat(args) => run_logcat(args),
     }
 }
+/// Returns the operation to check with the policy, and the target DUT
+pub fn guarded_operation(args: &Args) -> Option<(Operation, Option<&str>)> {
+    match &args.nested {
+        SubCommand::Flash(args) => Some((Operation::OsFlash, Some(&args.dut))),
+        SubCommand::GuestKernelUprev(_) | SubCommand::Logcat(_) => None,
+    }
+}
 
 #[derive(FromArgs, PartialEq, Debug)]
 /// ARCVM kernel sync to ACK
@@ -0,0 +1,307 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::cmd::build::build_pkg;
+use crate::cmd::flash::cros_flash;
+use crate::list_gs_files;
+use crate::lookup_full_version;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::chroot::Chroot;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::notify::notify_result;
+use lium::repo::get_repo_dir;
+use lium::util::get_stdout;
+use lium::util::run_bash_command;
+use regex_macro::regex;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// find the first bad OS version (or commit, with --build) by flashing and testing a DUT
+#[argh(subcommand, name = "bisect")]
+pub struct Args {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// known good version (e.g. R120-15662.0.0) or commit (with --build)
+    #[argh(option)]
+    good: String,
+
+    /// known bad version (e.g. R121-15699.0.0) or commit (with --build)
+    #[argh(option)]
+    bad: String,
+
+    /// a command to test the DUT. {{dut}} is replaced with the DUT identifier.
+    /// exit 0 means good, 125 means skip (untestable), and others mean bad.
+    #[argh(option)]
+    test: String,
+
+    /// bisect local commits in --dir instead of released images
+    #[argh(switch)]
+    build: bool,
+
+    /// dir to bisect commits in, relative to cros checkout (e.g. src/platform2) (with --build)
+    #[argh(option)]
+    dir: Option<String>,
+
+    /// packages to build and deploy for each commit, space separated (with --build)
+    #[argh(option)]
+    packages: Option<String>,
+
+    /// USE flags to be used for --build, space separated
+    #[argh(
+        option,
+        default = "String::from(\"chrome_internal -cros-debug pcserial\")"
+    )]
+    use_flags: String,
+
+    /// notify when finished, e.g. desktop,bell (default: `lium config set notify`)
+    #[argh(option)]
+    notify: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Verdict {
+    Good,
+    Bad,
+    Skip,
+}
+
+/// Binary-searches `candidates` where the first one is known good and the last one is known
+/// bad. Returns the indexes of the last good and the first bad candidates.
+fn bisect<F: FnMut(&str) -> Result<Verdict>>(
+    candidates: &[String],
+    mut test: F,
+) -> Result<(usize, usize)> {
+    if candidates.len() < 2 {
+        return Err(anyhow!("Need at least a good and a bad candidate"));
+    }
+    let mut lo = 0;
+    let mut hi = candidates.len() - 1;
+    let mut skipped = vec![false; candidates.len()];
+    while hi - lo > 1 {
+        // Pick an untested candidate closest to the middle
+        let mid = (lo + hi) / 2;
+        let mid = match (lo + 1..hi)
+            .filter(|i| !skipped[*i])
+            .min_by_key(|i| i.abs_diff(mid))
+        {
+            Some(mid) => mid,
+            None => break,
+        };
+        eprintln!(
+            "Testing {} ({} candidates left)...",
+            candidates[mid],
+            hi - lo - 1
+        );
+        let verdict = test(&candidates[mid])?;
+        eprintln!("{}: {verdict:?}", candidates[mid]);
+        match verdict {
+            Verdict::Good => lo = mid,
+            Verdict::Bad => hi = mid,
+            Verdict::Skip => skipped[mid] = true,
+        }
+    }
+    Ok((lo, hi))
+}
+
+fn run_test_cmd(test: &str, dut: &str) -> Result<Verdict> {
+    let cmd = test.replace("{dut}", dut);
+    eprintln!("Running {cmd}");
+    let status = std::process::Command::new("bash")
+        .arg("-c")
+        .arg(&cmd)
+        .status()
+        .context(anyhow!("Failed to run {cmd}"))?;
+    Ok(match status.code() {
+        Some(0) => Verdict::Good,
+        Some(125) => Verdict::Skip,
+        _ => Verdict::Bad,
+    })
+}
+
+/// Returns (build, branch, patch) of a version string like R120-15662.0.0
+fn parse_cros_version(version: &str) -> Option<(u32, u32, u32)> {
+    let c = regex!(r"^R\d+-(\d+)\.(\d+)\.(\d+)$").captures(version)?;
+    Some((c[1].parse().ok()?, c[2].parse().ok()?, c[3].parse().ok()?))
+}
+
+/// Returns released versions of the board between good and bad (inclusive), oldest first
+fn list_versions_between(board: &str, good: &str, bad: &str) -> Result<Vec<String>> {
+    let good_v = parse_cros_version(good).context(anyhow!("Invalid version: {good}"))?;
+    let bad_v = parse_cros_version(bad).context(anyhow!("Invalid version: {bad}"))?;
+    let main_only = good_v.1 == 0 && good_v.2 == 0 && bad_v.1 == 0 && bad_v.2 == 0;
+    let list = list_gs_files(&format!("gs://chromeos-image-archive/{board}-release/"))?;
+    let re_version = regex!(r"/(R\d+-\d+\.\d+\.\d+)/$");
+    let mut versions: Vec<((u32, u32, u32), String)> = list
+        .lines()
+        .filter_map(|line| {
+            let version = re_version.captures(line.trim())?[1].to_string();
+            let v = parse_cros_version(&version)?;
+            (good_v < v && v < bad_v && (!main_only || (v.1 == 0 && v.2 == 0)))
+                .then_some((v, version))
+        })
+        .collect();
+    versions.sort();
+    versions.dedup_by_key(|(v, _)| *v);
+    let mut candidates = vec![good.to_string()];
+    candidates.extend(versions.into_iter().map(|(_, version)| version));
+    candidates.push(bad.to_string());
+    Ok(candidates)
+}
+
+fn run_bisect_versions(args: &Args) -> Result<()> {
+    let repo = get_repo_dir(&args.repo)?;
+    let good = lookup_full_version(&args.good)?;
+    let bad = lookup_full_version(&args.bad)?;
+    let ssh = SshInfo::new(&args.dut)?;
+    let board = ssh.get_board()?;
+    let candidates = list_versions_between(&board, &good, &bad)?;
+    eprintln!(
+        "Bisecting {} versions between {good} and {bad} on {board}",
+        candidates.len() - 2
+    );
+    let (lo, hi) = bisect(&candidates, |version| {
+        if cros_flash(
+            &repo,
+            &ssh.host_and_port(),
+            &format!("xBuddy://remote/{board}/{version}/test"),
+            false,
+        )
+        .is_err()
+        {
+            eprintln!("Failed to flash {version}. Skipping...");
+            return Ok(Verdict::Skip);
+        }
+        run_test_cmd(&args.test, &args.dut)
+    })?;
+    print_result(&candidates, lo, hi);
+    Ok(())
+}
+
+fn run_bisect_commits(args: &Args) -> Result<()> {
+    let repo = get_repo_dir(&args.repo)?;
+    let dir = args
+        .dir
+        .as_ref()
+        .context("Please specify --dir to bisect commits in")?;
+    let packages: Vec<String> = args
+        .packages
+        .as_ref()
+        .context("Please specify --packages to build")?
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect();
+    let git_dir = format!("{repo}/{dir}");
+    let git = |cmd: &str| -> Result<String> {
+        let output = run_bash_command(&format!("git {cmd}"), Some(&git_dir))?;
+        output
+            .status
+            .exit_ok()
+            .context(anyhow!("git {cmd} failed"))?;
+        Ok(get_stdout(&output))
+    };
+    let orig_head = git("rev-parse HEAD")?;
+    let good = git(&format!("rev-parse {}", args.good))?;
+    let bad = git(&format!("rev-parse {}", args.bad))?;
+    let mut candidates = vec![good.clone()];
+    candidates.extend(
+        git(&format!(
+            "rev-list --reverse --first-parent --ancestry-path {good}..{bad}"
+        ))?
+        .lines()
+        .map(|s| s.to_string()),
+    );
+    eprintln!(
+        "Bisecting {} commits in {dir}",
+        candidates.len().saturating_sub(2)
+    );
+
+    let ssh = SshInfo::new(&args.dut)?;
+    let board = ssh.get_board()?;
+    let target = if ssh.needs_port_forwarding_in_chroot() {
+        let port = ssh.start_ssh_forwarding_range_background(4100..4200)?;
+        SshInfo::new_host_and_port("localhost", port)?
+    } else {
+        ssh
+    };
+    let chroot = Chroot::new(&repo)?;
+    let result = bisect(&candidates, |commit| {
+        git(&format!("checkout -q {commit}"))?;
+        if build_pkg(&chroot, &board, &packages, &args.use_flags).is_err() {
+            eprintln!("Failed to build {commit}. Skipping...");
+            return Ok(Verdict::Skip);
+        }
+        chroot.run_bash_script_in_chroot(
+            "bisect_deploy",
+            &format!(
+                "cros deploy {} {}",
+                target.host_and_port(),
+                packages.join(" ")
+            ),
+            None,
+        )?;
+        run_test_cmd(&args.test, &args.dut)
+    });
+    git(&format!("checkout -q {orig_head}"))?;
+    let (lo, hi) = result?;
+    print_result(&candidates, lo, hi);
+    Ok(())
+}
+
+fn print_result(candidates: &[String], lo: usize, hi: usize) {
+    if hi - lo > 1 {
+        println!(
+            "Could not narrow down further because of skipped candidates. The first bad one is one of:"
+        );
+        for c in &candidates[lo + 1..=hi] {
+            println!("  {c}");
+        }
+    } else {
+        println!("Last good: {}", candidates[lo]);
+        println!("First bad: {}", candidates[hi]);
+    }
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let result = if args.build {
+        run_bisect_commits(args)
+    } else {
+        run_bisect_versions(args)
+    };
+    notify_result("bisect", &result, &args.notify);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bisect_finds_first_bad() {
+        let candidates: Vec<String> = (0..10).map(|i| i.to_string()).collect();
+        let verdict = |c: &str| -> Result<Verdict> {
+            Ok(match c.parse::<u32>().unwrap() {
+                4 => Verdict::Skip,
+                i if i < 6 => Verdict::Good,
+                _ => Verdict::Bad,
+            })
+        };
+        assert_eq!(bisect(&candidates, verdict).unwrap(), (5, 6));
+        let all_skipped = |_: &str| -> Result<Verdict> { Ok(Verdict::Skip) };
+        assert_eq!(bisect(&candidates, all_skipped).unwrap(), (0, 9));
+        assert_eq!(parse_cros_version("R120-15662.0.0"), Some((15662, 0, 0)));
+        assert_eq!(parse_cros_version("15662.0.0"), None);
+    }
+}
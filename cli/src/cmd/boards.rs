@@ -0,0 +1,120 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::boards::board_caps;
+use lium::boards::builtin_boards;
+use lium::boards::BoardCaps;
+use lium::boards::BOARD_OVERRIDES;
+use lium::boards::CAP_KEYS;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// capability hints of boards and models (EC, servo header, ARC, kernel)
+#[argh(subcommand, name = "boards")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Info(ArgsInfo),
+    Ls(ArgsLs),
+    Reset(ArgsReset),
+    Set(ArgsSet),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Info(args) => run_info(args),
+        SubCommand::Ls(args) => run_ls(args),
+        SubCommand::Reset(args) => run_reset(args),
+        SubCommand::Set(args) => run_set(args),
+    }
+}
+
+fn show_caps(caps: &BoardCaps) {
+    let show_bool = |v: Option<bool>| v.map_or("unknown".to_string(), |v| v.to_string());
+    let show = |v: &Option<String>| v.clone().unwrap_or_else(|| "unknown".to_string());
+    println!("has_ec: {}", show_bool(caps.has_ec));
+    println!("has_servo_header: {}", show_bool(caps.has_servo_header));
+    println!("arc: {}", show(&caps.arc));
+    println!("kernel: {}", show(&caps.kernel));
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the capabilities of a board
+#[argh(subcommand, name = "info")]
+pub struct ArgsInfo {
+    /// board name (e.g. brya)
+    #[argh(positional)]
+    board: String,
+
+    /// model name, to apply the overrides of the model
+    #[argh(option)]
+    model: Option<String>,
+}
+fn run_info(args: &ArgsInfo) -> Result<()> {
+    show_caps(&board_caps(&args.board, args.model.as_deref())?);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the built-in boards and the overrides
+#[argh(subcommand, name = "ls")]
+pub struct ArgsLs {}
+fn run_ls(_args: &ArgsLs) -> Result<()> {
+    println!("Built-in: {}", builtin_boards().join(" "));
+    let mut overrides: Vec<_> = BOARD_OVERRIDES.entries()?.into_iter().collect();
+    overrides.sort_by(|a, b| a.0.cmp(&b.0));
+    for (name, caps) in overrides {
+        println!("Overridden: {name} {}", serde_json::to_string(&caps)?);
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// override capabilities of a board or a model (e.g. `lium boards set brya has_ec=false`).
+/// an empty value (e.g. kernel=) makes it unknown.
+#[argh(subcommand, name = "set")]
+pub struct ArgsSet {
+    /// board or model name
+    #[argh(positional)]
+    name: String,
+
+    /// key=value (keys: has_ec, has_servo_header, arc, kernel)
+    #[argh(positional)]
+    values: Vec<String>,
+}
+fn run_set(args: &ArgsSet) -> Result<()> {
+    if args.values.is_empty() {
+        return Err(anyhow!(
+            "Please specify key=value (keys: {})",
+            CAP_KEYS.join(", ")
+        ));
+    }
+    let mut caps = BOARD_OVERRIDES.get(&args.name)?.unwrap_or_default();
+    for value in &args.values {
+        caps.set(value)?;
+    }
+    BOARD_OVERRIDES.set(&args.name, caps)
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove the overrides of a board or a model
+#[argh(subcommand, name = "reset")]
+pub struct ArgsReset {
+    /// board or model name
+    #[argh(positional)]
+    name: String,
+}
+fn run_reset(args: &ArgsReset) -> Result<()> {
+    BOARD_OVERRIDES
+        .remove(&args.name)?
+        .ok_or_else(|| anyhow!("{} is not overridden", args.name))?;
+    Ok(())
+}
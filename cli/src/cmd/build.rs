@@ -0,0 +1,318 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cache::KvCache;
+use lium::chroot::Chroot;
+use lium::notify::notify_result;
+use lium::repo::get_repo_dir;
+use lium::util::gen_path_in_lium_dir;
+use lium::util::get_stdout;
+use lium::util::run_bash_command;
+use regex_macro::regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::time::Instant;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// build a package
+#[argh(subcommand, name = "build")]
+pub struct Args {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// target board
+    #[argh(option)]
+    board: Option<String>,
+
+    /// packages to build (or workon, for a full build), space separated
+    #[argh(option)]
+    packages: Option<String>,
+
+    /// if specified, skip update_chroot and setup_board
+    #[argh(switch)]
+    skip_setup: bool,
+
+    /// if specified, do not stop working on packages already working on
+    #[argh(switch)]
+    keep_workon: bool,
+
+    /// USE flags to be used, space separated
+    #[argh(
+        option,
+        default = "String::from(\"chrome_internal -cros-debug pcserial\")"
+    )]
+    use_flags: String,
+
+    /// do full build (build_packages + build_image)
+    #[argh(switch)]
+    full: bool,
+
+    /// notify when finished, e.g. desktop,bell (default: `lium config set notify`)
+    #[argh(option)]
+    notify: Option<String>,
+
+    #[argh(subcommand)]
+    nested: Option<SubCommand>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Pkg(ArgsPkg),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// cros-workon start and emerge packages, then record the build time
+#[argh(subcommand, name = "pkg")]
+struct ArgsPkg {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// target board
+    #[argh(option)]
+    board: String,
+
+    /// USE flags to be used, space separated
+    #[argh(
+        option,
+        default = "String::from(\"chrome_internal -cros-debug pcserial\")"
+    )]
+    use_flags: String,
+
+    /// packages to build
+    #[argh(positional)]
+    packages: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BuildRecord {
+    /// UNIX time (in seconds) when the build was started
+    pub started_at: u64,
+    pub duration_secs: u64,
+}
+/// Keyed by "{board}/{package}"
+pub static BUILD_CACHE: KvCache<BuildRecord> = KvCache::new("build_cache");
+
+/// Extracts failed packages and compiler errors from an emerge log
+fn parse_build_errors(log: &str) -> Vec<String> {
+    let re_failed = regex!(r"^ \* ERROR: (?P<pkg>\S+) failed");
+    let re_error = regex!(r"(^|\s)(error|fatal error|ERROR):\s");
+    let mut errors: Vec<String> = Vec::new();
+    for line in log.lines() {
+        let line = line.trim_end();
+        if let Some(c) = re_failed.captures(line) {
+            errors.push(format!("{} failed", &c["pkg"]));
+        } else if re_error.is_match(line) && !errors.iter().any(|e| e == line.trim()) {
+            errors.push(line.trim().to_string());
+        }
+    }
+    errors
+}
+
+/// cros-workon start and emerge the given packages.
+/// The build log is kept at ~/.lium/tmp/build_pkg.log and the build time is recorded in
+/// BUILD_CACHE.
+pub fn build_pkg(chroot: &Chroot, board: &str, packages: &[String], use_flags: &str) -> Result<()> {
+    let packages = packages.join(" ");
+    let started_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let timer = Instant::now();
+    // Remove the log of the last build so that only the log of this build is summarized below
+    let log_path = gen_path_in_lium_dir("tmp/build_pkg.log")?;
+    match std::fs::remove_file(&log_path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(e).context(anyhow!("Failed to remove {log_path:?}"));
+        }
+        _ => {}
+    }
+    let result = chroot.run_bash_script_in_chroot(
+        "build_pkg",
+        &format!(
+            r###"
+cros-workon-{board} start {packages}
+export USE='{use_flags}'
+set -o pipefail
+emerge-{board} --jobs=$(nproc) {packages} 2>&1 | tee /lium/tmp/build_pkg.log
+"###
+        ),
+        None,
+    );
+    if let Err(e) = result {
+        // The log does not exist if the build failed before emerge
+        let log = std::fs::read_to_string(&log_path).unwrap_or_default();
+        let errors = parse_build_errors(&log);
+        if errors.is_empty() {
+            return Err(e);
+        }
+        return Err(anyhow!(
+            "Failed to build {packages}:\n{}\n(see ~/.lium/tmp/build_pkg.log for the full log)",
+            errors.join("\n")
+        ));
+    }
+    let duration_secs = timer.elapsed().as_secs();
+    for p in packages.split_whitespace() {
+        BUILD_CACHE.set(
+            &format!("{board}/{p}"),
+            BuildRecord {
+                started_at,
+                duration_secs,
+            },
+        )?;
+    }
+    eprintln!("Succesfully built {packages} in {duration_secs} sec");
+    Ok(())
+}
+
+/// Returns true if the package has not been built by `lium build pkg` yet, or if its
+/// workon sources have been changed since the last build.
+pub fn needs_rebuild(chroot: &Chroot, repo: &str, board: &str, package: &str) -> Result<bool> {
+    let record = if let Some(record) = BUILD_CACHE.get(&format!("{board}/{package}"))? {
+        record
+    } else {
+        return Ok(true);
+    };
+    // output: "<package> <project>[,<project>...] <srcpath>[,<srcpath>...]"
+    let info =
+        chroot.exec_in_chroot(&["cros_workon", &format!("--board={board}"), "info", package])?;
+    let srcpaths = info
+        .split_whitespace()
+        .nth(2)
+        .context(anyhow!("Failed to get the source paths of {package}"))?;
+    for path in srcpaths.split(',') {
+        let path = path.replacen("/mnt/host/source", repo, 1);
+        // Check both the last commit and uncommitted changes
+        let newest = get_stdout(&run_bash_command(
+            "(git log -1 --format=%ct; git status --porcelain --no-renames | cut -c 4- | xargs -r stat -c %Y 2>/dev/null) | sort -n | tail -n 1",
+            Some(&path),
+        )?);
+        if newest.trim().parse::<u64>().unwrap_or(u64::MAX) >= record.started_at {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+fn run_pkg(args: &ArgsPkg) -> Result<()> {
+    if args.packages.is_empty() {
+        return Err(anyhow!("Please specify packages to build"));
+    }
+    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    build_pkg(&chroot, &args.board, &args.packages, &args.use_flags)
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    let result = if let Some(SubCommand::Pkg(args)) = &args.nested {
+        run_pkg(args)
+    } else {
+        run_build(args)
+    };
+    notify_result("build", &result, &args.notify);
+    result
+}
+fn run_build(args: &Args) -> Result<()> {
+    let board = args
+        .board
+        .as_ref()
+        .context("Please specify --board. `lium build --help` for more details.")?;
+    let use_flags = &args.use_flags;
+    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    if !args.skip_setup {
+        chroot.run_bash_script_in_chroot(
+            "board_setup",
+            &format!(
+                r###"
+setup_board --force --board={board}
+./update_chroot
+"###,
+            ),
+            None,
+        )?;
+    }
+    if !args.keep_workon {
+        chroot.run_bash_script_in_chroot(
+            "stop_workon",
+            &format!(
+                r###"
+cros-workon-{board} stop --all
+"###
+            ),
+            None,
+        )?;
+    }
+    if let Some(packages) = &args.packages {
+        chroot.run_bash_script_in_chroot(
+            "start_workon",
+            &format!(
+                r###"
+cros-workon-{board} start {packages}
+"###
+            ),
+            None,
+        )?;
+    }
+    if args.full {
+        eprintln!("building a full image...");
+        chroot.run_bash_script_in_chroot(
+            "build_packages",
+            &format!(
+                r###"
+export USE='{use_flags}'
+build_packages --board={board} --withdev
+build_image --board={board} --noenable_rootfs_verification test
+"###
+            ),
+            None,
+        )?;
+        eprintln!("Succesfully built a test image!");
+    } else if let Some(packages) = &args.packages {
+        eprintln!("Building {packages}...");
+        chroot.run_bash_script_in_chroot(
+            "emerge_packages",
+            &format!(
+                r###"
+export USE='{use_flags}'
+emerge-{board} {packages}
+"###
+            ),
+            None,
+        )?;
+        eprintln!("Succesfully built {packages}!");
+    } else {
+        return Err(anyhow!(
+            "Please specify --full or --packages. `lium build --help` for more details."
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_emerge_errors() {
+        let log = r###"
+>>> Compiling source in /build/brya/tmp/portage/chromeos-base/foo-9999/work/foo-9999 ...
+../foo/bar.cc:12:3: error: use of undeclared identifier 'baz'
+ninja: build stopped: subcommand failed.
+ * ERROR: chromeos-base/foo-9999::chromiumos failed (compile phase):
+ *   ninja failed
+"###;
+        assert_eq!(
+            parse_build_errors(log),
+            vec![
+                "../foo/bar.cc:12:3: error: use of undeclared identifier 'baz'".to_string(),
+                "chromeos-base/foo-9999::chromiumos failed".to_string(),
+            ]
+        );
+        assert!(parse_build_errors(">>> Emerging (1 of 1) chromeos-base/foo-9999").is_empty());
+    }
+}
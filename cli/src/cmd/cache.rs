@@ -0,0 +1,148 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use chrono::TimeZone;
+use lium::cache::total_artifact_size;
+use lium::cache::ARTIFACT_STORE;
+use lium::config::Config;
+use lium::fetch::session_id;
+use lium::fetch::DOWNLOAD_STATS;
+use lium::policy::Operation;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// manage the artifact store (downloaded images, symbols, test bundles...)
+#[argh(subcommand, name = "cache")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Downloads(ArgsDownloads),
+    Gc(ArgsGc),
+    Ls(ArgsLs),
+    Rm(ArgsRm),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Downloads(args) => run_downloads(args),
+        SubCommand::Gc(args) => run_gc(args),
+        SubCommand::Ls(args) => run_ls(args),
+        SubCommand::Rm(args) => run_rm(args),
+    }
+}
+/// Returns the operation to check with the policy
+pub fn guarded_operation(args: &Args) -> Option<Operation> {
+    match &args.nested {
+        SubCommand::Gc(_) | SubCommand::Rm(_) => Some(Operation::CacheClear),
+        SubCommand::Downloads(_) | SubCommand::Ls(_) => None,
+    }
+}
+
+fn to_mb(bytes: u64) -> f64 {
+    bytes as f64 / 1024.0 / 1024.0
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list artifacts in the store, least recently used first
+#[argh(subcommand, name = "ls")]
+pub struct ArgsLs {}
+fn run_ls(_args: &ArgsLs) -> Result<()> {
+    let entries = ARTIFACT_STORE.entries()?;
+    let mut list: Vec<_> = entries.iter().collect();
+    list.sort_by_key(|(name, e)| (e.last_used, name.to_string()));
+    for (name, e) in list {
+        let last_used = Local
+            .timestamp_opt(e.last_used as i64, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        println!(
+            "{name:60} {:>10.1} MB  {last_used}  {}",
+            to_mb(e.size),
+            &e.hash[..12.min(e.hash.len())]
+        );
+    }
+    println!(
+        "Total: {:.1} MB (limit: {:.1} MB)",
+        to_mb(total_artifact_size(&entries)),
+        to_mb(Config::read()?.artifact_cache_max_bytes())
+    );
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove artifacts from the store
+#[argh(subcommand, name = "rm")]
+pub struct ArgsRm {
+    /// names of artifacts to remove
+    #[argh(positional)]
+    names: Vec<String>,
+}
+fn run_rm(args: &ArgsRm) -> Result<()> {
+    for name in &args.names {
+        ARTIFACT_STORE.remove(name)?;
+        eprintln!("Removed {name}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// evict least recently used artifacts to fit in the size limit
+#[argh(subcommand, name = "gc")]
+pub struct ArgsGc {
+    /// size limit in GB (default: artifact_cache_size_gb in config, or 50)
+    #[argh(option)]
+    max_size_gb: Option<u64>,
+}
+fn run_gc(args: &ArgsGc) -> Result<()> {
+    let max_bytes = if let Some(gb) = args.max_size_gb {
+        gb * 1024 * 1024 * 1024
+    } else {
+        Config::read()?.artifact_cache_max_bytes()
+    };
+    let before = total_artifact_size(&ARTIFACT_STORE.entries()?);
+    for name in ARTIFACT_STORE.gc(max_bytes)? {
+        eprintln!("Evicted {name}");
+    }
+    let after = total_artifact_size(&ARTIFACT_STORE.entries()?);
+    println!(
+        "Freed {:.1} MB ({:.1} MB in use)",
+        to_mb(before - after),
+        to_mb(after)
+    );
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the bytes downloaded per session (see $LIUM_DOWNLOAD_SESSION) and per source
+#[argh(subcommand, name = "downloads")]
+pub struct ArgsDownloads {}
+fn run_downloads(_args: &ArgsDownloads) -> Result<()> {
+    let mut sessions: Vec<_> = DOWNLOAD_STATS.entries()?.into_iter().collect();
+    sessions.sort_by(|a, b| a.0.cmp(&b.0));
+    let current = session_id();
+    for (session, stats) in sessions {
+        let marker = if session == current { " (current)" } else { "" };
+        println!(
+            "{session}{marker}: {:.1} MB in {} files ({:.1} MB/s)",
+            to_mb(stats.bytes),
+            stats.files,
+            to_mb(stats.bytes) / stats.seconds.max(0.001)
+        );
+        for (source, bytes) in &stats.by_source {
+            println!("  {source:50} {:>10.1} MB", to_mb(*bytes));
+        }
+    }
+    if let Some(quota) = Config::read()?.download_quota_bytes() {
+        println!("Quota per session: {:.1} MB", to_mb(quota));
+    }
+    Ok(())
+}
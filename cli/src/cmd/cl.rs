@@ -0,0 +1,311 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lazy_static::lazy_static;
+use lium::chroot::Chroot;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::repo::get_repo_dir;
+use lium::util::ensure_online;
+use lium::util::get_stdout;
+use lium::util::run_bash_command;
+use regex::Regex;
+
+lazy_static! {
+    static ref RE_GERRIT_CL: Regex = Regex::new(r"^(?P<cl>[0-9]+)/(?P<patchset>[0-9+])$").unwrap();
+    static ref RE_GERRIT_CL_OPTIONAL_PATCHSET: Regex =
+        Regex::new(r"^(?P<cl>[0-9]+)(/(?P<patchset>[0-9]+))?$").unwrap();
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// CL (Change List) helpers
+#[argh(subcommand, name = "cl")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Pick(ArgsPick),
+    Test(ArgsTest),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Pick(args) => run_pick(args),
+        SubCommand::Test(args) => run_test(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// cherry-pick a CL
+#[argh(subcommand, name = "pick")]
+pub struct ArgsPick {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// dir to run git commands, relative to cros checkout (e.g. src/platform/crosvm)
+    #[argh(option)]
+    dir: String,
+
+    /// CLs to checkout (e.g. "4196467", "4196467/2")
+    #[argh(positional)]
+    cl: String,
+}
+fn run_pick(args: &ArgsPick) -> Result<()> {
+    let capture = RE_GERRIT_CL
+        .captures(&args.cl)
+        .context("Invalid CL id. please specify patchset number as well (like '1234/5').")?;
+    let cl = &capture["cl"];
+    let cl_suffix = &cl[cl.len() - 2..];
+    let patchset = &capture["patchset"];
+    let dir = &args.dir;
+    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    chroot.run_bash_script_in_chroot(
+        "checkout",
+        &format!(
+            r###"
+cd ~/chromiumos
+cd {dir}
+export PROJ=`repo info . | grep -e 'Project:' | cut -d ' ' -f 2`
+echo "PROJ=${{PROJ}}"
+git fetch https://chromium.googlesource.com/${{PROJ}} \
+  refs/changes/{cl_suffix}/{cl}/{patchset}
+git cherry-pick FETCH_HEAD || git cherry-pick --abort
+"###,
+        ),
+        None,
+    )?;
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// fetch a CL, build and deploy the affected packages, then run tast tests on a DUT
+#[argh(subcommand, name = "test")]
+pub struct ArgsTest {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// CL to test (e.g. "4196467", "4196467/2"). The latest patchset is used if omitted.
+    #[argh(option)]
+    cl: String,
+
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// tast test name or pattern to run
+    #[argh(option)]
+    tests: String,
+
+    /// packages to build and deploy (space separated). Inferred from the project if omitted.
+    #[argh(option)]
+    packages: Option<String>,
+
+    /// gerrit host to fetch the CL from
+    #[argh(option, default = "String::from(\"chromium-review.googlesource.com\")")]
+    gerrit: String,
+}
+
+#[derive(Debug, PartialEq)]
+struct GerritChange {
+    project: String,
+    git_ref: String,
+}
+
+/// Parses a response of the gerrit "Get Change" API with ALL_REVISIONS
+fn parse_gerrit_change(response: &str, patchset: Option<&str>) -> Result<GerritChange> {
+    // Gerrit prepends ")]}'" to prevent XSSI
+    let json = response.trim_start_matches(")]}'").trim();
+    let change: serde_json::Value =
+        serde_json::from_str(json).context("Failed to parse a response from gerrit")?;
+    let project = change["project"]
+        .as_str()
+        .context("project not found")?
+        .to_string();
+    let revisions = change["revisions"]
+        .as_object()
+        .context("revisions not found")?;
+    let revision = if let Some(patchset) = patchset {
+        let number: u64 = patchset.parse()?;
+        revisions
+            .values()
+            .find(|r| r["_number"].as_u64() == Some(number))
+            .context(anyhow!("patchset {patchset} not found"))?
+    } else {
+        let current = change["current_revision"]
+            .as_str()
+            .context("current_revision not found")?;
+        revisions
+            .get(current)
+            .context(anyhow!("current_revision {current} not found in revisions"))?
+    };
+    let git_ref = revision["ref"]
+        .as_str()
+        .context("ref not found")?
+        .to_string();
+    Ok(GerritChange { project, git_ref })
+}
+
+fn run_test(args: &ArgsTest) -> Result<()> {
+    let capture = RE_GERRIT_CL_OPTIONAL_PATCHSET
+        .captures(&args.cl)
+        .context("Invalid CL id (e.g. 1234, 1234/5)")?;
+    let cl = &capture["cl"];
+    let patchset = capture.name("patchset").map(|m| m.as_str());
+    let gerrit = &args.gerrit;
+    ensure_testing_rsa_is_there()?;
+    let repo = get_repo_dir(&args.repo)?;
+    let dut = SshInfo::new(&args.dut)?;
+    let board = dut.get_board()?;
+
+    ensure_online("Querying gerrit")?;
+    let response = run_bash_command(
+        &format!("curl -sf 'https://{gerrit}/changes/{cl}?o=ALL_REVISIONS'"),
+        None,
+    )?;
+    response
+        .status
+        .exit_ok()
+        .context(anyhow!("Failed to query CL {cl} on {gerrit}"))?;
+    let change = parse_gerrit_change(&get_stdout(&response), patchset)?;
+    eprintln!("CL {cl} is {} ({})", change.project, change.git_ref);
+
+    let chroot = Chroot::new(&repo)?;
+    let project = &change.project;
+    let git_ref = &change.git_ref;
+    let git_host = gerrit.replace("-review", "");
+    let project_dir = chroot.exec_in_chroot(&[
+        "bash",
+        "-c",
+        &format!("cd ~/chromiumos && repo list -p -r '^{project}$' | head -n 1"),
+    ])?;
+    if project_dir.is_empty() {
+        return Err(anyhow!("{project} is not checked out in {repo}"));
+    }
+    let git_dir = format!("{repo}/{project_dir}");
+    let git = |cmd: &str| -> Result<String> {
+        let output = run_bash_command(&format!("git {cmd}"), Some(&git_dir))?;
+        output
+            .status
+            .exit_ok()
+            .context(anyhow!("git {cmd} failed"))?;
+        Ok(get_stdout(&output))
+    };
+
+    let packages = if let Some(packages) = &args.packages {
+        packages.clone()
+    } else {
+        // lines of "<package> <project>[,<project>...] <srcpath>[,<srcpath>...]"
+        let info = chroot.exec_in_chroot(&[
+            "cros_workon",
+            &format!("--board={board}"),
+            "info",
+            "--all",
+        ])?;
+        let packages: Vec<&str> = info
+            .lines()
+            .filter_map(|line| {
+                let mut it = line.split_whitespace();
+                let package = it.next()?;
+                it.next()?
+                    .split(',')
+                    .any(|p| p == project)
+                    .then_some(package)
+            })
+            .collect();
+        if packages.is_empty() {
+            return Err(anyhow!(
+                "No cros-workon packages found for {project}. Please specify --packages."
+            ));
+        }
+        packages.join(" ")
+    };
+    eprintln!("Packages to test: {packages}");
+
+    let dut = if dut.needs_port_forwarding_in_chroot() {
+        let port = dut.start_ssh_forwarding_range_background(4100..4200)?;
+        SshInfo::new_host_and_port("localhost", port)?
+    } else {
+        dut
+    };
+    // Go back to the branch (or the commit if detached) after the test
+    let orig_head = match git("rev-parse --abbrev-ref HEAD")?.as_str() {
+        "HEAD" => git("rev-parse HEAD")?,
+        branch => branch.to_string(),
+    };
+    git(&format!("fetch https://{git_host}/{project} {git_ref}"))?;
+    git("checkout -q FETCH_HEAD")?;
+    let stages = [
+        (
+            "build",
+            format!("cros-workon-{board} start {packages} && emerge-{board} {packages}"),
+        ),
+        (
+            "deploy",
+            format!("cros deploy {} {packages}", dut.host_and_port()),
+        ),
+        (
+            "test",
+            format!(
+                "tast run -installbuilddeps {} {}",
+                dut.host_and_port(),
+                args.tests
+            ),
+        ),
+    ];
+    let result = stages.iter().try_for_each(|(stage, script)| {
+        chroot
+            .run_bash_script_in_chroot(&format!("cl_test_{stage}"), script, None)
+            .map(|_| ())
+            .map_err(|e| {
+                println!("VERDICT: FAIL ({stage} failed for CL {cl})");
+                e
+            })
+    });
+    git(&format!("checkout -q {orig_head}"))?;
+    result?;
+    println!("VERDICT: PASS (CL {cl}, {})", args.tests);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn regex() {
+        assert!(&RE_GERRIT_CL.captures("1234").is_none());
+        assert_eq!(&RE_GERRIT_CL.captures("1234/5").unwrap()["cl"], "1234");
+        assert_eq!(&RE_GERRIT_CL.captures("1234/5").unwrap()["patchset"], "5");
+    }
+    #[test]
+    fn gerrit_change() {
+        let response = r#")]}'
+{"project":"chromiumos/platform2","current_revision":"bbb","revisions":{
+"aaa":{"_number":1,"ref":"refs/changes/67/4196467/1"},
+"bbb":{"_number":2,"ref":"refs/changes/67/4196467/2"}}}"#;
+        assert_eq!(
+            parse_gerrit_change(response, None).unwrap(),
+            GerritChange {
+                project: "chromiumos/platform2".to_string(),
+                git_ref: "refs/changes/67/4196467/2".to_string(),
+            }
+        );
+        assert_eq!(
+            parse_gerrit_change(response, Some("1")).unwrap().git_ref,
+            "refs/changes/67/4196467/1"
+        );
+        assert!(parse_gerrit_change(response, Some("3")).is_err());
+        let response = r#"{"project":"p","current_revision":"ccc","revisions":{}}"#;
+        assert!(parse_gerrit_change(response, None).is_err());
+    }
+}
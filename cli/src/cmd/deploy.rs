@@ -4,6 +4,8 @@
 // license that can be found in the LICENSE file or at
 // https://developers.google.com/open-source/licenses/bsd
 
+use crate::cmd::build::build_pkg;
+use crate::cmd::build::needs_rebuild;
 use anyhow::anyhow;
 use anyhow::Result;
 use argh::FromArgs;
@@ -32,6 +34,17 @@ pub struct Args {
     /// if specified, it will skip automatic reboot
     #[argh(switch)]
     skip_reboot: bool,
+
+    /// if specified, rebuild packages whose sources have changed since the last `lium build pkg`
+    #[argh(switch)]
+    build: bool,
+
+    /// USE flags to be used for --build, space separated
+    #[argh(
+        option,
+        default = "String::from(\"chrome_internal -cros-debug pcserial\")"
+    )]
+    use_flags: String,
 }
 pub fn run(args: &Args) -> Result<()> {
     ensure_testing_rsa_is_there()?;
@@ -47,7 +60,21 @@ pub fn run(args: &Args) -> Result<()> {
     } else {
         target
     };
-    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    let repo = get_repo_dir(&args.repo)?;
+    let chroot = Chroot::new(&repo)?;
+    if args.build {
+        let mut stale = Vec::new();
+        for p in packages.split_whitespace() {
+            if needs_rebuild(&chroot, &repo, &board, p)? {
+                stale.push(p.to_string());
+            }
+        }
+        if stale.is_empty() {
+            eprintln!("All packages are up to date. Skipping build.");
+        } else {
+            build_pkg(&chroot, &board, &stale, &args.use_flags)?;
+        }
+    }
 
     let mut iter = args
         .packages
@@ -0,0 +1,379 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cancel;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::repo::get_repo_dir;
+use lium::toolchain::Toolchain;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::env;
+use std::hash::Hasher;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use std::time::SystemTime;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// on-device development loop helpers
+#[argh(subcommand, name = "dev")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Run(ArgsRun),
+    Watch(ArgsWatch),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Run(args) => run_run(args),
+        SubCommand::Watch(args) => run_watch(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// build a program for the DUT on the host, run it on the DUT and clean it up
+#[argh(subcommand, name = "run")]
+struct ArgsRun {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// build the binary with this name with cargo (statically linked with musl)
+    #[argh(option)]
+    cargo: Option<String>,
+
+    /// build this make target (the output is expected at <dir>/<target>)
+    #[argh(option)]
+    make: Option<String>,
+
+    /// dir to build in
+    #[argh(option, default = "String::from(\".\")")]
+    dir: String,
+
+    /// cros repo dir to find the board sysroot in (for --make outside the chroot)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// keep the binary on the DUT after running it
+    #[argh(switch)]
+    keep: bool,
+
+    /// arguments for the program (use -- before them)
+    #[argh(positional)]
+    args: Vec<String>,
+}
+
+fn run_build_cmd(mut cmd: Command) -> Result<()> {
+    eprintln!("Running {cmd:?}");
+    cmd.status()
+        .context(anyhow!("Failed to execute {cmd:?}"))?
+        .exit_ok()
+        .context(anyhow!("{cmd:?} failed"))
+}
+
+/// Builds the program and returns the path to the artifact
+fn build_for_dut(args: &ArgsRun, toolchain: &Toolchain) -> Result<PathBuf> {
+    match (&args.cargo, &args.make) {
+        (Some(bin), None) => {
+            let triple = toolchain.rust_target;
+            let mut cmd = Command::new("cargo");
+            cmd.current_dir(&args.dir).args([
+                "build",
+                "--release",
+                "--target",
+                triple,
+                "--bin",
+                bin,
+            ]);
+            toolchain.apply_env(&mut cmd);
+            run_build_cmd(cmd)?;
+            let target_dir = env::var("CARGO_TARGET_DIR").unwrap_or(format!("{}/target", args.dir));
+            Ok(PathBuf::from(format!(
+                "{target_dir}/{triple}/release/{bin}"
+            )))
+        }
+        (None, Some(target)) => {
+            let cc = env::var("CC").or_else(|_| toolchain.cc())?;
+            let cxx = env::var("CXX").or_else(|_| toolchain.cxx())?;
+            let mut cmd = Command::new("make");
+            cmd.current_dir(&args.dir)
+                .arg(target)
+                .arg(format!("CC={cc}"))
+                .arg(format!("CXX={cxx}"))
+                .arg("LDFLAGS=-static");
+            run_build_cmd(cmd)?;
+            Ok(PathBuf::from(format!("{}/{target}", args.dir)))
+        }
+        _ => Err(anyhow!("Please specify one of --cargo or --make")),
+    }
+}
+
+fn run_run(args: &ArgsRun) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    let repo = get_repo_dir(&args.repo).ok();
+    let toolchain = Toolchain::for_dut(&target, repo.as_deref())?;
+    let artifact = build_for_dut(args, &toolchain)?;
+    if !artifact.is_file() {
+        return Err(anyhow!("{artifact:?} was not found after the build"));
+    }
+    let name = artifact
+        .file_name()
+        .context("Invalid artifact path")?
+        .to_string_lossy();
+    // /tmp is mounted with noexec on ChromeOS
+    let remote_dir = "/usr/local/tmp/lium_run";
+    let remote = format!("{remote_dir}/{name}");
+    target.run_cmd_piped(&[format!("mkdir -p {remote_dir}")])?;
+    let files = [artifact.to_string_lossy().to_string()];
+    if args.keep {
+        // Record it to be removed by `dut sanitize`
+        target.send_files_audited(&files, Some(&remote))?;
+    } else {
+        target.send_files(&files, Some(&remote))?;
+    }
+    let mut cmd = vec![remote.clone()];
+    cmd.extend(args.args.iter().cloned());
+    let result = target.run_cmd_piped(&cmd);
+    if !args.keep {
+        target.run_cmd_piped(&[format!("rm -f {remote}")])?;
+    }
+    result
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// watch local files and push them to the DUT when they are changed
+#[argh(subcommand, name = "watch")]
+struct ArgsWatch {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// a pair of <local>:<remote> to sync (e.g. ./out/foo:/usr/local/bin/foo). can be repeated.
+    /// if <local> is a dir, files under it are synced into the <remote> dir.
+    #[argh(option)]
+    map: Vec<String>,
+
+    /// a command to run on the DUT after each sync (e.g. 'restart ui')
+    #[argh(option)]
+    on_sync: Option<String>,
+
+    /// milliseconds to wait for changes to settle before pushing
+    #[argh(option, default = "300")]
+    debounce_ms: u64,
+
+    /// milliseconds between polls of the local files
+    #[argh(option, default = "500")]
+    interval_ms: u64,
+
+    /// do not push all the files at startup
+    #[argh(switch)]
+    no_initial_sync: bool,
+}
+
+#[derive(Debug, PartialEq)]
+struct Mapping {
+    local: PathBuf,
+    remote: String,
+}
+impl Mapping {
+    fn parse(s: &str) -> Result<Self> {
+        let (local, remote) = s
+            .split_once(':')
+            .filter(|(l, r)| !l.is_empty() && r.starts_with('/'))
+            .context(anyhow!(
+                "Invalid --map {s:?}. Use <local>:<remote> with an absolute remote path"
+            ))?;
+        Ok(Self {
+            local: PathBuf::from(local),
+            remote: remote.to_string(),
+        })
+    }
+    /// Returns the remote path for a local file under this mapping
+    fn remote_path(&self, file: &Path) -> String {
+        match file.strip_prefix(&self.local) {
+            Ok(rel) if !rel.as_os_str().is_empty() => format!(
+                "{}/{}",
+                self.remote.trim_end_matches('/'),
+                rel.to_string_lossy()
+            ),
+            _ => self.remote.clone(),
+        }
+    }
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        for e in std::fs::read_dir(path)? {
+            collect_files(&e?.path(), files)?;
+        }
+    } else if path.is_file() {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+/// Returns the mtimes of all the files to watch, with the index of their mappings
+fn scan(mappings: &[Mapping]) -> Result<HashMap<PathBuf, (usize, SystemTime)>> {
+    let mut result = HashMap::new();
+    for (i, m) in mappings.iter().enumerate() {
+        let mut files = Vec::new();
+        collect_files(&m.local, &mut files)?;
+        for f in files {
+            // The file may be removed while scanning (e.g. during a build)
+            if let Ok(mtime) = f.metadata().and_then(|m| m.modified()) {
+                result.insert(f, (i, mtime));
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn file_hash(path: &Path) -> Result<u64> {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(&std::fs::read(path)?);
+    Ok(hasher.finish())
+}
+
+/// Pushes the files that differ from the last pushed contents. Returns true if anything was
+/// pushed.
+fn push_files(
+    target: &SshInfo,
+    mappings: &[Mapping],
+    files: &[(PathBuf, usize)],
+    pushed: &mut HashMap<PathBuf, u64>,
+) -> Result<bool> {
+    let mut to_push = Vec::new();
+    for (f, i) in files {
+        let Ok(hash) = file_hash(f) else {
+            continue;
+        };
+        if pushed.get(f) != Some(&hash) {
+            to_push.push((f, mappings[*i].remote_path(f), hash));
+        }
+    }
+    if to_push.is_empty() {
+        return Ok(false);
+    }
+    let mut dirs: Vec<&str> = to_push
+        .iter()
+        .filter_map(|(_, remote, _)| remote.rsplit_once('/').map(|(d, _)| d))
+        .filter(|d| !d.is_empty())
+        .collect();
+    dirs.sort();
+    dirs.dedup();
+    if !dirs.is_empty() {
+        target.run_cmd_piped(&[format!("mkdir -p {}", dirs.join(" "))])?;
+    }
+    for (f, remote, hash) in to_push {
+        eprintln!("{} -> {remote}", f.to_string_lossy());
+        target.send_files_audited(&[f.to_string_lossy().to_string()], Some(&remote))?;
+        pushed.insert(f.clone(), hash);
+    }
+    Ok(true)
+}
+
+fn run_watch(args: &ArgsWatch) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    if args.map.is_empty() {
+        return Err(anyhow!("Please specify at least one --map"));
+    }
+    let mappings = args
+        .map
+        .iter()
+        .map(|s| Mapping::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    let target = SshInfo::new(&args.dut)?;
+    let mut pushed: HashMap<PathBuf, u64> = HashMap::new();
+    let mut prev = scan(&mappings)?;
+    let mut pending: Vec<(PathBuf, usize)> = if args.no_initial_sync {
+        Vec::new()
+    } else {
+        prev.iter().map(|(f, (i, _))| (f.clone(), *i)).collect()
+    };
+    cancel::install_handler()?;
+    eprintln!("Watching {} files. Press Ctrl-C to stop.", prev.len());
+    'watch: loop {
+        if !pending.is_empty() {
+            // Wait until the files stop changing
+            loop {
+                if cancel::sleep(Duration::from_millis(args.debounce_ms)).is_err() {
+                    break 'watch;
+                }
+                let cur = scan(&mappings)?;
+                if cur == prev {
+                    break;
+                }
+                pending.extend(
+                    cur.iter()
+                        .filter(|(f, v)| prev.get(*f) != Some(v))
+                        .map(|(f, (i, _))| (f.clone(), *i)),
+                );
+                prev = cur;
+            }
+            pending.sort();
+            pending.dedup();
+            match push_files(&target, &mappings, &pending, &mut pushed) {
+                Ok(true) => {
+                    if let Some(cmd) = &args.on_sync {
+                        eprintln!("Running {cmd:?} on the DUT...");
+                        if let Err(e) = target.run_cmd_piped(&[cmd]) {
+                            eprintln!("on-sync hook failed: {e:#}");
+                        }
+                    }
+                    eprintln!("Synced.");
+                }
+                Ok(false) => {}
+                Err(e) => eprintln!("Failed to push: {e:#}"),
+            }
+            pending.clear();
+        }
+        if cancel::sleep(Duration::from_millis(args.interval_ms)).is_err() {
+            break;
+        }
+        let cur = scan(&mappings)?;
+        pending = cur
+            .iter()
+            .filter(|(f, v)| prev.get(*f) != Some(v))
+            .map(|(f, (i, _))| (f.clone(), *i))
+            .collect();
+        prev = cur;
+    }
+    eprintln!("Stopped. {} files were synced.", pushed.len());
+    if !pending.is_empty() {
+        eprintln!("{} changed files were not synced yet.", pending.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_mapping() {
+        let m = Mapping::parse("./out/foo:/usr/local/bin/foo").unwrap();
+        assert_eq!(m.local, PathBuf::from("./out/foo"));
+        assert_eq!(m.remote_path(Path::new("./out/foo")), "/usr/local/bin/foo");
+        let m = Mapping::parse("out/share:/usr/local/share/").unwrap();
+        assert_eq!(
+            m.remote_path(Path::new("out/share/a/b.txt")),
+            "/usr/local/share/a/b.txt"
+        );
+        assert!(Mapping::parse("foo").is_err());
+        assert!(Mapping::parse("foo:relative").is_err());
+    }
+}
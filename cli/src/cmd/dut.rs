@@ -0,0 +1,5473 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Local;
+use chrono::NaiveDate;
+use chrono::NaiveDateTime;
+use chrono::TimeZone;
+use futures::executor::block_on;
+use futures::future::join_all;
+use lazy_static::lazy_static;
+use lium::agent::install_agent;
+use lium::agent::uninstall_agent;
+use lium::agent::DUT_AGENTS;
+use lium::baseline::Baseline;
+use lium::baseline::DEFAULT_BASELINE_FILES;
+use lium::baseline::DUT_BASELINES;
+use lium::boards::ensure_ec;
+use lium::cache::KvCache;
+use lium::cancel;
+use lium::certs::install_cert;
+use lium::certs::remove_cert;
+use lium::certs::CertStore;
+use lium::certs::DUT_CERTS;
+use lium::chroot::Chroot;
+use lium::cros;
+use lium::devtools::ensure_remote_debugging;
+use lium::devtools::evaluate;
+use lium::devtools::geolocation_override;
+use lium::devtools::list_targets;
+use lium::devtools::localize_targets;
+use lium::devtools::open_url;
+use lium::devtools::start_geolocation_override;
+use lium::devtools::stop_geolocation_override;
+use lium::devtools::AUTOTEST_EXT_ID;
+use lium::dns::add_host_overrides;
+use lium::dns::clear_dns_overrides;
+use lium::dns::dns_overrides_status;
+use lium::dns::remove_host_overrides;
+use lium::dns::set_name_servers;
+use lium::dns::HostOverride;
+use lium::dut::discover_local_nodes;
+use lium::dut::dut_group;
+use lium::dut::expand_targets;
+use lium::dut::export_ssh_config;
+use lium::dut::exported_ssh_config_path;
+use lium::dut::fetch_dut_info_in_parallel;
+use lium::dut::known_duts;
+use lium::dut::render_ssh_config;
+use lium::dut::ssh_config_alias;
+use lium::dut::update_exported_ssh_config;
+use lium::dut::CachedValue;
+use lium::dut::DutInfo;
+use lium::dut::DutNote;
+use lium::dut::DutProvenance;
+use lium::dut::MonitoredDut;
+use lium::dut::PushedFile;
+use lium::dut::SshInfo;
+use lium::dut::DUT_INFO_CACHE;
+use lium::dut::DUT_INFO_DEFAULT_MAX_AGE;
+use lium::dut::DUT_NOTES;
+use lium::dut::DUT_PROBE_TIMEOUT;
+use lium::dut::DUT_PROVENANCE;
+use lium::dut::DUT_PUSH_AUDIT_LOG;
+use lium::dut::LOG_BUNDLE_NAME;
+use lium::dut::MAX_PARALLEL_SSH;
+use lium::dut::SSH_CACHE;
+use lium::events::read_events;
+use lium::inject::parse_battery;
+use lium::inject::InjectSession;
+use lium::inject::INJECT_SESSIONS;
+use lium::journal::Journal;
+use lium::mitm::find_mitmproxy;
+use lium::mitm::mitmproxy_ca_path;
+use lium::mitm::start_recorder;
+use lium::monitor_db::MonitorDb;
+use lium::monitor_db::MonitorSample;
+use lium::notify::notify_result;
+use lium::output;
+use lium::output::Sink;
+use lium::policy::Operation;
+use lium::ports::active_ports;
+use lium::ports::PortAllocation;
+use lium::ports::PortAllocator;
+use lium::ports::MONITOR_PORTS;
+use lium::ports::VNC_PORTS;
+use lium::progress::Progress;
+use lium::proxy::proxy_status;
+use lium::proxy::set_proxy;
+use lium::proxy::unset_proxy;
+use lium::query::attribute_of;
+use lium::query::parse_select;
+use lium::query::project;
+use lium::query::Filter;
+use lium::redact::Redactor;
+use lium::repo::get_repo_dir;
+use lium::results::record_results;
+use lium::results::TestResult;
+use lium::results::TestTarget;
+use lium::servo::get_cr50_attached_to_servo;
+use lium::servo::get_servo_attached_to_cr50;
+use lium::servo::LocalServo;
+use lium::servo::ServoList;
+use lium::servo::ServodConnection;
+use lium::status;
+use lium::statusd;
+use lium::summary::parse_report_specs;
+use lium::summary::write_reports;
+use lium::symbols::builder_path_of_dut;
+use lium::symbols::fetch_symbols;
+use lium::symbols::SymbolKind;
+use lium::toolchain::board_sysroot_on_host;
+use lium::toolchain::Toolchain;
+use lium::tui::Event as TuiEvent;
+use lium::tui::Key;
+use lium::tui::Screen;
+use lium::ui::is_porcelain;
+use lium::ui::styled;
+use lium::ui::Stream;
+use lium::ui::Style;
+use lium::ui::Table;
+use lium::ui::Template;
+use lium::util::ensure_online;
+use lium::util::gen_path_in_lium_dir;
+use lium::util::get_stdout;
+use lium::util::is_offline_mode;
+use lium::util::lium_dir;
+use lium::util::run_bash_command;
+use lium::util::run_concurrently;
+use lium::util::sha256sum;
+use lium::util::spawn_lium_in_background;
+use lium::util::with_timeout;
+use lium::warning;
+use lium::watchdog::mount_sshfs;
+use lium::watchdog::watch_agents;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::env::current_exe;
+use std::fs::read_to_string;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::net::TcpListener;
+use std::path::Path;
+use std::process::Command;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// DUT controller
+#[argh(subcommand, name = "dut")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Addrs(ArgsAddrs),
+    Agent(ArgsAgent),
+    ArcInfo(ArgsArcInfo),
+    Authorize(ArgsAuthorize),
+    Baseline(ArgsBaseline),
+    Certs(ArgsCerts),
+    Chrome(ArgsChrome),
+    Devtools(ArgsDevtools),
+    DiagnoseSsh(ArgsDiagnoseSsh),
+    Discover(ArgsDiscover),
+    Dns(ArgsDns),
+    Code(ArgsCode),
+    Coredump(ArgsCoredump),
+    Strace(ArgsStrace),
+    Do(ArgsDutDo),
+    Ec(ArgsEc),
+    Events(ArgsEvents),
+    Gdb(ArgsGdb),
+    Geo(ArgsGeo),
+    Gsc(ArgsGsc),
+    Info(ArgsDutInfo),
+    Inject(ArgsInject),
+    KernelConfig(ArgsDutKernelConfig),
+    List(ArgsDutList),
+    Locale(ArgsLocale),
+    Netperf(ArgsNetperf),
+    Sanitize(ArgsSanitize),
+    SecurityCheck(ArgsSecurityCheck),
+    Shell(ArgsDutShell),
+    SshConfig(ArgsSshConfig),
+    SuspendStress(ArgsSuspendStress),
+    Tpm(ArgsTpm),
+    Tz(ArgsTz),
+    Mitm(ArgsMitm),
+    Monitor(ArgsDutMonitor),
+    Note(ArgsNote),
+    Ports(ArgsPorts),
+    Powerwash(ArgsPowerwash),
+    Profiles(ArgsProfiles),
+    Proxy(ArgsProxy),
+    Pull(ArgsPull),
+    Push(ArgsPush),
+    RebootLoop(ArgsRebootLoop),
+    Recover(ArgsRecover),
+    Vnc(ArgsVnc),
+    WithForward(ArgsWithForward),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Addrs(args) => run_dut_addrs(args),
+        SubCommand::Agent(args) => run_dut_agent(args),
+        SubCommand::ArcInfo(args) => run_arc_info(args),
+        SubCommand::Authorize(args) => run_dut_authorize(args),
+        SubCommand::Baseline(args) => run_dut_baseline(args),
+        SubCommand::Certs(args) => run_dut_certs(args),
+        SubCommand::Chrome(args) => run_dut_chrome(args),
+        SubCommand::Devtools(args) => run_dut_devtools(args),
+        SubCommand::DiagnoseSsh(args) => run_dut_diagnose_ssh(args),
+        SubCommand::Discover(args) => run_discover(args),
+        SubCommand::Dns(args) => run_dut_dns(args),
+        SubCommand::Code(args) => run_dut_code(args),
+        SubCommand::Coredump(args) => run_dut_coredump(args),
+        SubCommand::Strace(args) => run_dut_strace(args),
+        SubCommand::Do(args) => run_dut_do(args),
+        SubCommand::Ec(args) => run_dut_ec(args),
+        SubCommand::Events(args) => run_dut_events(args),
+        SubCommand::Gdb(args) => run_dut_gdb(args),
+        SubCommand::Geo(args) => run_dut_geo(args),
+        SubCommand::Gsc(args) => run_dut_gsc(args),
+        SubCommand::Info(args) => run_dut_info(args),
+        SubCommand::Inject(args) => run_dut_inject(args),
+        SubCommand::KernelConfig(args) => run_dut_kernel_config(args),
+        SubCommand::List(args) => run_dut_list(args),
+        SubCommand::Locale(args) => run_dut_locale(args),
+        SubCommand::Netperf(args) => run_dut_netperf(args),
+        SubCommand::Sanitize(args) => run_dut_sanitize(args),
+        SubCommand::SecurityCheck(args) => run_dut_security_check(args),
+        SubCommand::Shell(args) => run_dut_shell(args),
+        SubCommand::SshConfig(args) => run_dut_ssh_config(args),
+        SubCommand::SuspendStress(args) => run_dut_suspend_stress(args),
+        SubCommand::Tpm(args) => run_dut_tpm(args),
+        SubCommand::Tz(args) => run_dut_tz(args),
+        SubCommand::Mitm(args) => run_dut_mitm(args),
+        SubCommand::Monitor(args) => run_dut_monitor(args),
+        SubCommand::Note(args) => run_dut_note(args),
+        SubCommand::Ports(args) => run_dut_ports(args),
+        SubCommand::Powerwash(args) => run_dut_powerwash(args),
+        SubCommand::Profiles(args) => run_dut_profiles(args),
+        SubCommand::Proxy(args) => run_dut_proxy(args),
+        SubCommand::Pull(args) => run_dut_pull(args),
+        SubCommand::Push(args) => run_dut_push(args),
+        SubCommand::RebootLoop(args) => run_dut_reboot_loop(args),
+        SubCommand::Recover(args) => run_dut_recover(args),
+        SubCommand::Vnc(args) => run_dut_vnc(args),
+        SubCommand::WithForward(args) => run_dut_with_forward(args),
+    }
+}
+/// Returns the operation to check with the policy, and the target DUT
+pub fn guarded_operation(args: &Args) -> Option<(Operation, Option<&str>)> {
+    match &args.nested {
+        SubCommand::Ec(ArgsEc {
+            dut,
+            nested: EcSubCommand::Flash(_),
+        }) => Some((Operation::FirmwareWrite, Some(dut))),
+        SubCommand::Powerwash(args) => Some((Operation::Powerwash, Some(&args.dut))),
+        SubCommand::Recover(args) => Some((Operation::OsFlash, Some(&args.dut))),
+        SubCommand::Tpm(ArgsTpm {
+            nested: TpmSubCommand::Clear(args),
+        }) => Some((Operation::TpmClear, Some(&args.dut))),
+        SubCommand::Sanitize(args) if args.dry_run => None,
+        SubCommand::Sanitize(args) if args.powerwash => {
+            Some((Operation::Powerwash, Some(&args.dut)))
+        }
+        SubCommand::Sanitize(args) => Some((Operation::UserDataWipe, Some(&args.dut))),
+        SubCommand::Profiles(ArgsProfiles {
+            dut,
+            nested: ProfilesSubCommand::Remove(_),
+        }) => Some((Operation::UserDataWipe, Some(dut))),
+        SubCommand::List(args) if args.clear => Some((Operation::CacheClear, None)),
+        _ => None,
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Pull files from DUT
+#[argh(subcommand, name = "pull")]
+struct ArgsPull {
+    /// DUT which the files are pulled from
+    #[argh(option)]
+    dut: String,
+
+    /// pulled file names
+    #[argh(positional)]
+    files: Vec<String>,
+
+    /// destination directory (current directory by default)
+    #[argh(option)]
+    dest: Option<String>,
+}
+
+fn run_dut_pull(args: &ArgsPull) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+
+    target.get_files(&args.files, args.dest.as_ref())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Push files from DUT
+#[argh(subcommand, name = "push")]
+struct ArgsPush {
+    /// destination DUT
+    #[argh(option)]
+    dut: String,
+
+    /// destination directory on a DUT
+    #[argh(option)]
+    dest: Option<String>,
+
+    /// source files
+    #[argh(positional)]
+    files: Vec<String>,
+}
+
+fn run_dut_push(args: &ArgsPush) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    cancel::install_handler()?;
+    let target = &SshInfo::new(&args.dut)?;
+    target.ensure_test_image("lium dut push")?;
+
+    target.send_files_audited(&args.files, args.dest.as_ref())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// reboot a DUT repeatedly and report the reliability
+#[argh(subcommand, name = "reboot-loop")]
+struct ArgsRebootLoop {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// number of reboots (default: 100)
+    #[argh(option, default = "100")]
+    count: usize,
+
+    /// seconds to wait for each boot (default: 300)
+    #[argh(option, default = "300")]
+    timeout: u64,
+
+    /// stop at the first failure
+    #[argh(switch)]
+    stop_on_failure: bool,
+
+    /// notify when finished, e.g. desktop,bell (default: `lium config set notify`)
+    #[argh(option)]
+    notify: Option<String>,
+
+    /// redact the logs collected on failures (see `lium dut info --redact`)
+    #[argh(switch)]
+    redact: bool,
+
+    /// write a summary of the results (repeatable, e.g. junit:out.xml, md:summary.md)
+    #[argh(option)]
+    report: Vec<String>,
+}
+
+fn run_dut_reboot_loop(args: &ArgsRebootLoop) -> Result<()> {
+    let result = run_dut_reboot_loop_inner(args);
+    notify_result("dut reboot-loop", &result, &args.notify);
+    result
+}
+fn run_dut_reboot_loop_inner(args: &ArgsRebootLoop) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    cancel::install_handler()?;
+    let reports = parse_report_specs(&args.report)?;
+    let target = &SshInfo::new(&args.dut)?;
+    let test_target = TestTarget::from_dut_or_default(&args.dut);
+    let timeout = time::Duration::from_secs(args.timeout);
+    let log_dir = gen_path_in_lium_dir(&format!(
+        "reboot_loop/{}",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ))?;
+    let redactor = args.redact.then(Redactor::from_config).transpose()?;
+    let mut boot_id = target
+        .get_boot_id()
+        .context("Failed to get the initial boot_id")?;
+    let mut boot_times: Vec<time::Duration> = Vec::new();
+    let mut failures: Vec<(usize, String)> = Vec::new();
+    for i in 1..=args.count {
+        if cancel::is_cancelled() {
+            break;
+        }
+        let start = time::Instant::now();
+        // ssh may exit with an error since the connection is closed by the reboot
+        drop(target.run_cmd_piped(&["reboot; exit"]));
+        match target.wait_for_new_boot_id(&boot_id, timeout) {
+            Ok(new_boot_id) => {
+                let elapsed = start.elapsed();
+                eprintln!(
+                    "[{i}/{}] rebooted in {:.1} sec",
+                    args.count,
+                    elapsed.as_secs_f64()
+                );
+                boot_times.push(elapsed);
+                boot_id = new_boot_id;
+            }
+            Err(_) if cancel::is_cancelled() => {
+                warning!("Interrupted at iteration {i}. Showing the results so far.");
+                break;
+            }
+            Err(e) => {
+                eprintln!("[{i}/{}] FAILED: {e:#}", args.count);
+                failures.push((i, format!("{e:#}")));
+                // Give the DUT another chance to come back to collect logs
+                if let Ok(new_boot_id) = target.wait_for_new_boot_id(&boot_id, timeout) {
+                    boot_id = new_boot_id;
+                    let dest = log_dir.join(format!("iteration_{i}"));
+                    let dest = dest.to_string_lossy().to_string();
+                    let collected = target.collect_logs(&dest).and_then(|()| {
+                        if let Some(redactor) = &redactor {
+                            redactor.redact_log_bundle(&Path::new(&dest).join(LOG_BUNDLE_NAME))?;
+                        }
+                        Ok(())
+                    });
+                    match collected {
+                        Ok(()) => eprintln!("Logs are collected at {dest}"),
+                        Err(e) => eprintln!("Failed to collect logs: {e:#}"),
+                    }
+                } else {
+                    eprintln!("DUT did not come back. Aborting...");
+                    break;
+                }
+                if args.stop_on_failure {
+                    break;
+                }
+            }
+        }
+    }
+    let attempts = boot_times.len() + failures.len();
+    println!("attempts: {attempts}");
+    println!("successes: {}", boot_times.len());
+    println!("failures: {}", failures.len());
+    if attempts > 0 {
+        println!(
+            "success rate: {:.2}%",
+            boot_times.len() as f64 * 100.0 / attempts as f64
+        );
+    }
+    if let (Some(min), Some(max)) = (boot_times.iter().min(), boot_times.iter().max()) {
+        let mean = boot_times.iter().sum::<time::Duration>() / boot_times.len() as u32;
+        println!(
+            "boot time (sec): min {:.1} / mean {:.1} / max {:.1}",
+            min.as_secs_f64(),
+            mean.as_secs_f64(),
+            max.as_secs_f64()
+        );
+    }
+    for (i, e) in &failures {
+        println!("iteration {i} failed: {e}");
+    }
+    if attempts > 0 {
+        let status = if failures.is_empty() { "PASS" } else { "FAIL" };
+        let detail = format!("{} of {attempts} reboots failed", failures.len());
+        let results = [TestResult::new(
+            "reboot-loop",
+            "reboot-loop",
+            status,
+            &test_target,
+            &detail,
+        )];
+        record_results(&results);
+        write_reports(&reports, "dut reboot-loop", &results)?;
+    }
+    cancel::check()?;
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(anyhow!("{} of {attempts} reboots failed", failures.len()))
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run suspend_stress_test on a DUT and analyze failures and wake sources
+#[argh(subcommand, name = "suspend-stress")]
+struct ArgsSuspendStress {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// number of suspend/resume cycles (default: 50)
+    #[argh(option, default = "50")]
+    count: usize,
+
+    /// seconds to stay suspended in each cycle (default: 10)
+    #[argh(option, default = "10")]
+    suspend_sec: u32,
+
+    /// stop at the first failure
+    #[argh(switch)]
+    stop_on_failure: bool,
+
+    /// notify when finished, e.g. desktop,bell (default: `lium config set notify`)
+    #[argh(option)]
+    notify: Option<String>,
+
+    /// write a summary of the results (repeatable, e.g. junit:out.xml, md:summary.md)
+    #[argh(option)]
+    report: Vec<String>,
+}
+
+/// Summary counters printed at the end of suspend_stress_test
+#[derive(Debug, Default, PartialEq, Eq)]
+struct SuspendStressResult {
+    suspend_failures: u32,
+    wake_failures: u32,
+    firmware_log_errors: u32,
+    s0ix_errors: u32,
+    premature_wakes: u32,
+}
+impl SuspendStressResult {
+    fn parse(output: &str) -> Result<Self> {
+        let mut result = Self::default();
+        let mut found = false;
+        for line in output.lines() {
+            let (key, value) = if let Some(kv) = line.split_once(':') {
+                kv
+            } else {
+                continue;
+            };
+            let counter = match key.trim() {
+                "Suspend failures" => &mut result.suspend_failures,
+                "Wake failures" => &mut result.wake_failures,
+                "Firmware log errors" => &mut result.firmware_log_errors,
+                "s0ix errors" => &mut result.s0ix_errors,
+                "Premature wakes" => &mut result.premature_wakes,
+                _ => continue,
+            };
+            if let Ok(v) = value.trim().parse() {
+                *counter = v;
+                found = true;
+            }
+        }
+        if found {
+            Ok(result)
+        } else {
+            Err(anyhow!("No summary found in suspend_stress_test output"))
+        }
+    }
+    fn has_failure(&self) -> bool {
+        self.suspend_failures + self.wake_failures + self.firmware_log_errors + self.s0ix_errors > 0
+    }
+    fn add(&mut self, other: &Self) {
+        self.suspend_failures += other.suspend_failures;
+        self.wake_failures += other.wake_failures;
+        self.firmware_log_errors += other.firmware_log_errors;
+        self.s0ix_errors += other.s0ix_errors;
+        self.premature_wakes += other.premature_wakes;
+    }
+}
+
+const CMD_GET_WAKE_SOURCE: &str = r#"irq=`cat /sys/power/pm_wakeup_irq 2>/dev/null`; if [ -n "$irq" ]; then grep -E "^ *$irq:" /proc/interrupts | sed -E 's/\s+/ /g'; else grep -i 'wake' /var/log/power_manager/powerd.LATEST | tail -n 3; fi"#;
+const CMD_GET_FIRMWARE_LOG_SNIPPET: &str =
+    r"cbmem -1 2>/dev/null | grep -i -E 'error|fail|warn' | tail -n 20";
+
+fn run_dut_suspend_stress(args: &ArgsSuspendStress) -> Result<()> {
+    let result = run_dut_suspend_stress_inner(args);
+    notify_result("dut suspend-stress", &result, &args.notify);
+    result
+}
+fn run_dut_suspend_stress_inner(args: &ArgsSuspendStress) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let reports = parse_report_specs(&args.report)?;
+    let target = &SshInfo::new(&args.dut)?;
+    let test_target = TestTarget::from_dut_or_default(&args.dut);
+    let mut total = SuspendStressResult::default();
+    let mut wake_sources: HashMap<String, u32> = HashMap::new();
+    let mut cycles = 0;
+    for i in 1..=args.count {
+        // Run one cycle at a time to inspect the DUT right after each failure
+        let output = target.run_cmd_stdio(&format!(
+            "suspend_stress_test -c 1 --suspend_min {0} --suspend_max {0} 2>&1",
+            args.suspend_sec
+        ));
+        let output = match output {
+            Ok(output) => output,
+            // suspend_stress_test exits with non-zero on failures, but still prints the summary
+            Err(e) => format!("{e:#}"),
+        };
+        let result = SuspendStressResult::parse(&output)
+            .context(anyhow!("Failed to run suspend_stress_test: {output}"))?;
+        cycles += 1;
+        total.add(&result);
+        if result.premature_wakes > 0 {
+            let source = target
+                .run_cmd_stdio(CMD_GET_WAKE_SOURCE)
+                .unwrap_or_else(|_| "unknown".to_string());
+            eprintln!("[{i}/{}] premature wake. source: {source}", args.count);
+            *wake_sources.entry(source).or_default() += 1;
+        }
+        if result.has_failure() {
+            eprintln!("[{i}/{}] FAILED: {result:?}", args.count);
+            let fw_log = target
+                .run_cmd_stdio(CMD_GET_FIRMWARE_LOG_SNIPPET)
+                .unwrap_or_default();
+            eprintln!("Firmware log snippet:\n{fw_log}");
+            if args.stop_on_failure {
+                break;
+            }
+        } else {
+            eprintln!("[{i}/{}] OK", args.count);
+        }
+    }
+    println!("cycles: {cycles}");
+    println!("suspend failures: {}", total.suspend_failures);
+    println!("wake failures: {}", total.wake_failures);
+    println!("firmware log errors: {}", total.firmware_log_errors);
+    println!("s0ix errors: {}", total.s0ix_errors);
+    println!("premature wakes: {}", total.premature_wakes);
+    for (source, count) in &wake_sources {
+        println!("  {count:4} {source}");
+    }
+    let status = if total.has_failure() { "FAIL" } else { "PASS" };
+    let results = [TestResult::new(
+        "suspend-stress",
+        "suspend-stress",
+        status,
+        &test_target,
+        &format!("cycles: {cycles}, {total:?}"),
+    )];
+    record_results(&results);
+    write_reports(&reports, "dut suspend-stress", &results)?;
+    if total.has_failure() {
+        Err(anyhow!("suspend_stress_test reported failures"))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// measure network throughput between this machine and a DUT with iperf3
+#[argh(subcommand, name = "netperf")]
+struct ArgsNetperf {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// measure only the DUT to host direction (both directions by default)
+    #[argh(switch)]
+    reverse: bool,
+
+    /// use UDP instead of TCP to measure jitter and loss as well
+    #[argh(switch)]
+    udp: bool,
+
+    /// seconds to run each measurement (default: 10)
+    #[argh(option, default = "10")]
+    duration: u32,
+
+    /// port for the iperf3 server on the DUT (default: 5201)
+    #[argh(option, default = "5201")]
+    port: u16,
+}
+
+/// Checks that `file_desc` (the output of `file -bL`) is a static binary for `arch` (as returned
+/// by SshInfo::get_arch), so that it runs on the DUT without the libraries of this machine
+fn check_binary_for_dut(file_desc: &str, arch: &str) -> Result<()> {
+    let expected = match arch {
+        "x86_64" => "x86-64",
+        "arm64" => "aarch64",
+        arch if arch.starts_with("arm") => "ARM,",
+        arch => return Err(anyhow!("Unknown architecture of the DUT: {arch}")),
+    };
+    if !file_desc.contains(expected) {
+        return Err(anyhow!("It is not built for {arch}: {file_desc}"));
+    }
+    if !file_desc.contains("statically linked") && !file_desc.contains("static-pie linked") {
+        return Err(anyhow!("It is not statically linked: {file_desc}"));
+    }
+    Ok(())
+}
+
+fn ensure_iperf3_on_dut(target: &SshInfo) -> Result<()> {
+    if target.run_cmd_stdio("which iperf3").is_ok() {
+        return Ok(());
+    }
+    status!("iperf3 is not found on the DUT. Pushing the local one...");
+    let local = run_bash_command("which iperf3", None)?;
+    local
+        .status
+        .exit_ok()
+        .context("iperf3 is not installed on this machine either")?;
+    let local = get_stdout(&local);
+    let desc = Command::new("file").args(["-bL", &local]).output()?;
+    desc.status
+        .exit_ok()
+        .context(anyhow!("Failed to run file on {local}"))?;
+    let arch = target.get_arch()?;
+    check_binary_for_dut(&get_stdout(&desc), &arch).context(anyhow!(
+        "Cannot push {local} to the DUT. Please install a static iperf3 for {arch} on the DUT"
+    ))?;
+    target.send_files_audited(&[local], Some(&"/usr/local/bin/".to_string()))
+}
+
+fn summarize_iperf3_result(result: &serde_json::Value, udp: bool) -> Result<serde_json::Value> {
+    let end = result
+        .get("end")
+        .context(anyhow!("Unexpected iperf3 output: {result}"))?;
+    if udp {
+        let sum = end.get("sum").context("sum is not found")?;
+        Ok(serde_json::json!({
+            "bits_per_second": sum.get("bits_per_second"),
+            "jitter_ms": sum.get("jitter_ms"),
+            "lost_percent": sum.get("lost_percent"),
+        }))
+    } else {
+        Ok(serde_json::json!({
+            "sent_bits_per_second": end.pointer("/sum_sent/bits_per_second"),
+            "received_bits_per_second": end.pointer("/sum_received/bits_per_second"),
+            "retransmits": end.pointer("/sum_sent/retransmits"),
+        }))
+    }
+}
+
+fn run_iperf3_once(
+    args: &ArgsNetperf,
+    target: &SshInfo,
+    reverse: bool,
+) -> Result<serde_json::Value> {
+    let port = args.port;
+    let firewall = |op: &str| {
+        format!(
+            "for t in iptables ip6tables; do $t {op} INPUT -p tcp --dport {port} -j ACCEPT; $t {op} INPUT -p udp --dport {port} -j ACCEPT; done"
+        )
+    };
+    // Open the port on the DUT firewall and start a one-off server in background
+    target.run_cmd_stdio(&format!("{}; iperf3 -s -D -1 -p {port}", firewall("-I")))?;
+    let result = (|| {
+        thread::sleep(time::Duration::from_secs(1));
+        let mut cmd = format!(
+            "iperf3 -J -c {} -p {port} -t {}",
+            target.host(),
+            args.duration
+        );
+        if reverse {
+            cmd += " -R";
+        }
+        if args.udp {
+            cmd += " -u -b 0";
+        }
+        let output = run_bash_command(&cmd, None)?;
+        let result: serde_json::Value = serde_json::from_str(&get_stdout(&output))
+            .context("Failed to parse the output of iperf3")?;
+        if let Some(e) = result.get("error") {
+            return Err(anyhow!("iperf3 failed: {e}"));
+        }
+        summarize_iperf3_result(&result, args.udp)
+    })();
+    if let Err(e) = target.run_cmd_stdio(&firewall("-D")) {
+        warning!("Failed to close port {port} on the DUT firewall: {e:#}");
+    }
+    result
+}
+
+fn run_dut_netperf(args: &ArgsNetperf) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    if !target.needs_port_forwarding_in_chroot() {
+        return Err(anyhow!(
+            "netperf needs a direct connection to the DUT, but {} is a forwarded address",
+            target.host_and_port()
+        ));
+    }
+    ensure_iperf3_on_dut(target)?;
+    let mut report = serde_json::Map::new();
+    if !args.reverse {
+        status!("Measuring host -> DUT...");
+        report.insert(
+            "host_to_dut".to_string(),
+            run_iperf3_once(args, target, false)?,
+        );
+    }
+    status!("Measuring DUT -> host...");
+    report.insert(
+        "dut_to_host".to_string(),
+        run_iperf3_once(args, target, true)?,
+    );
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show or edit the candidate addresses of a cached DUT
+#[argh(subcommand, name = "addrs")]
+struct ArgsAddrs {
+    /// a DUT ID in the cache
+    #[argh(option)]
+    dut: String,
+
+    /// an address to add as a fallback (e.g. a DNS name or a link-local address). can be repeated.
+    #[argh(option)]
+    add: Vec<String>,
+
+    /// an address to remove from the fallbacks. can be repeated.
+    #[argh(option)]
+    remove: Vec<String>,
+
+    /// probe the addresses and make the first reachable one primary
+    #[argh(switch)]
+    probe: bool,
+}
+
+fn run_dut_addrs(args: &ArgsAddrs) -> Result<()> {
+    let mut ssh = SSH_CACHE
+        .get(&args.dut)?
+        .context(anyhow!("{} is not a DUT ID in the cache", args.dut))?;
+    for addr in &args.add {
+        let a = SshInfo::new(addr)?;
+        ssh.add_alternative(a.host(), a.port());
+    }
+    for addr in &args.remove {
+        let a = SshInfo::new(addr)?;
+        if !ssh.remove_alternative(a.host(), a.port()) {
+            return Err(anyhow!("{addr} is not a fallback address of {}", args.dut));
+        }
+    }
+    if !args.add.is_empty() || !args.remove.is_empty() {
+        SSH_CACHE.set(&args.dut, ssh.clone())?;
+    }
+    if args.probe {
+        ssh = ssh.connect(&args.dut);
+    }
+    for (i, c) in ssh.candidates().iter().enumerate() {
+        let addr = SshInfo::new_host_and_port(&c.host, c.port)?.host_and_port();
+        let role = if i == 0 { "primary" } else { "fallback" };
+        println!("{role:8} {addr:40} failures={}", c.failures);
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// capture the configuration of a DUT and detect drift from it later
+#[argh(subcommand, name = "baseline")]
+struct ArgsBaseline {
+    #[argh(subcommand)]
+    nested: BaselineSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum BaselineSubCommand {
+    Capture(ArgsBaselineCapture),
+    Check(ArgsBaselineCheck),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// record the flags, packages, kernel cmdline, firmware versions and file hashes of a DUT
+#[argh(subcommand, name = "capture")]
+struct ArgsBaselineCapture {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// additional file on the DUT to hash (can be repeated)
+    #[argh(option)]
+    file: Vec<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// report differences from the captured baseline. fails if there is any drift.
+#[argh(subcommand, name = "check")]
+struct ArgsBaselineCheck {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+}
+
+fn run_dut_baseline(args: &ArgsBaseline) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    match &args.nested {
+        BaselineSubCommand::Capture(args) => {
+            let info = DutInfo::new(&args.dut)?;
+            let mut files: Vec<String> = DEFAULT_BASELINE_FILES
+                .iter()
+                .map(|f| f.to_string())
+                .collect();
+            files.extend(args.file.iter().cloned());
+            let baseline = Baseline::capture(info.ssh(), &files)?;
+            println!(
+                "Captured {} items as the baseline of {}",
+                baseline.items.len(),
+                info.id()
+            );
+            DUT_BASELINES.set(info.id(), baseline)
+        }
+        BaselineSubCommand::Check(args) => {
+            let info = DutInfo::new(&args.dut)?;
+            let id = info.id();
+            let baseline = DUT_BASELINES.get(id)?.context(anyhow!(
+                "No baseline for {id}. Please run `lium dut baseline capture` first"
+            ))?;
+            let current = Baseline::capture(info.ssh(), &baseline.files)?;
+            let drift = baseline.drift(&current);
+            for (key, before, after) in &drift {
+                println!(
+                    "{key}: {} -> {}",
+                    before.unwrap_or("(none)"),
+                    after.unwrap_or("(none)")
+                );
+            }
+            if drift.is_empty() {
+                println!("{id} matches the baseline");
+                Ok(())
+            } else {
+                Err(anyhow!(
+                    "{} items of {id} drifted from the baseline",
+                    drift.len()
+                ))
+            }
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// install test CA certificates to DUTs (e.g. to test against staging servers)
+#[argh(subcommand, name = "certs")]
+struct ArgsCerts {
+    #[argh(subcommand)]
+    nested: CertsSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum CertsSubCommand {
+    Install(ArgsCertsInstall),
+    List(ArgsCertsList),
+    Remove(ArgsCertsRemove),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// install a CA certificate (PEM). installs to the NSS DB of the user if no store is given.
+#[argh(subcommand, name = "install")]
+struct ArgsCertsInstall {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// install to /etc/ssl/certs for system services (needs the rootfs verification removed)
+    #[argh(switch)]
+    system: bool,
+
+    /// install to the NSS DB of the logged-in user for Chrome
+    #[argh(switch)]
+    nssdb: bool,
+
+    /// do not restart the UI to make Chrome load the NSS DB again
+    #[argh(switch)]
+    no_restart: bool,
+
+    /// path to the certificate
+    #[argh(positional)]
+    cert: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the certificates installed by lium
+#[argh(subcommand, name = "list")]
+struct ArgsCertsList {
+    /// a DUT ID (all DUTs if omitted)
+    #[argh(option)]
+    dut: Option<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove a certificate installed by lium from all the stores
+#[argh(subcommand, name = "remove")]
+struct ArgsCertsRemove {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// name of the certificate (shown by `lium dut certs list`)
+    #[argh(positional)]
+    name: String,
+}
+
+fn run_dut_certs(args: &ArgsCerts) -> Result<()> {
+    match &args.nested {
+        CertsSubCommand::Install(args) => {
+            cros::ensure_testing_rsa_is_there()?;
+            let info = DutInfo::new(&args.dut)?;
+            let mut stores = Vec::new();
+            if args.system {
+                stores.push(CertStore::System);
+            }
+            if args.nssdb || !args.system {
+                stores.push(CertStore::NssDb);
+            }
+            let name = install_cert(info.ssh(), info.id(), &args.cert, &stores)?;
+            println!("Installed {name} to {stores:?} of {}", info.id());
+            if stores.contains(&CertStore::NssDb) && !args.no_restart {
+                status!("Restarting the UI. Please log in again to use the certificate.");
+                info.ssh().run_cmd_stdio("restart ui")?;
+            }
+            Ok(())
+        }
+        CertsSubCommand::List(args) => {
+            for (id, certs) in DUT_CERTS.entries()? {
+                if args.dut.as_ref().map_or(false, |dut| dut != &id) {
+                    continue;
+                }
+                for c in certs {
+                    println!("{id:32} {:32} {:?}", c.name, c.stores);
+                }
+            }
+            Ok(())
+        }
+        CertsSubCommand::Remove(args) => {
+            cros::ensure_testing_rsa_is_there()?;
+            let info = DutInfo::new(&args.dut)?;
+            let cert = remove_cert(info.ssh(), info.id(), &args.name)?;
+            println!(
+                "Removed {} from {:?} of {}",
+                cert.name,
+                cert.stores,
+                info.id()
+            );
+            Ok(())
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// configure an HTTP(S) proxy for Chrome and the system of a DUT
+#[argh(subcommand, name = "proxy")]
+struct ArgsProxy {
+    #[argh(subcommand)]
+    nested: ProxySubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ProxySubCommand {
+    Set(ArgsProxySet),
+    Status(ArgsProxyStatus),
+    Unset(ArgsProxyUnset),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// set the proxy for the current network, Chrome and shells (needs the rootfs verification removed)
+#[argh(subcommand, name = "set")]
+struct ArgsProxySet {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// proxy URL (e.g. http://proxy.example.com:3128)
+    #[argh(option)]
+    url: String,
+
+    /// do not restart the UI to apply the proxy to Chrome
+    #[argh(switch)]
+    no_restart: bool,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the proxy settings
+#[argh(subcommand, name = "status")]
+struct ArgsProxyStatus {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// restore the settings before `lium dut proxy set`
+#[argh(subcommand, name = "unset")]
+struct ArgsProxyUnset {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// do not restart the UI to apply the change to Chrome
+    #[argh(switch)]
+    no_restart: bool,
+}
+
+fn run_dut_proxy(args: &ArgsProxy) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let (target, no_restart) = match &args.nested {
+        ProxySubCommand::Set(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            set_proxy(&target, &args.url)?;
+            println!("Set the proxy of {} to {}", args.dut, args.url);
+            (target, args.no_restart)
+        }
+        ProxySubCommand::Unset(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            unset_proxy(&target)?;
+            println!("Unset the proxy of {}", args.dut);
+            (target, args.no_restart)
+        }
+        ProxySubCommand::Status(args) => {
+            println!("{}", proxy_status(&SshInfo::new(&args.dut)?)?);
+            return Ok(());
+        }
+    };
+    if !no_restart {
+        status!("Restarting the UI to apply the change to Chrome...");
+        target.run_cmd_stdio("restart ui")?;
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// route the traffic of a DUT through mitmproxy (or a built-in recorder) on this machine until
+/// Ctrl-C
+#[argh(subcommand, name = "mitm")]
+struct ArgsMitm {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// port of the proxy on this machine and the DUT (default: 8080)
+    #[argh(option, default = "8080")]
+    port: u16,
+
+    /// use mitmweb instead of mitmdump
+    #[argh(switch)]
+    web: bool,
+
+    /// use the built-in recorder, which logs the destinations without decrypting TLS
+    #[argh(switch)]
+    builtin: bool,
+}
+
+/// Reverts the changes to the DUT made for `dut mitm`, even on errors
+struct MitmCleanup<'a> {
+    ssh: &'a SshInfo,
+    id: String,
+    cert: Option<String>,
+    proxy_set: bool,
+}
+impl Drop for MitmCleanup<'_> {
+    fn drop(&mut self) {
+        if self.proxy_set {
+            if let Err(e) = unset_proxy(self.ssh) {
+                warning!("Failed to unset the proxy: {e:#}");
+            }
+        }
+        if let Some(cert) = &self.cert {
+            if let Err(e) = remove_cert(self.ssh, &self.id, cert) {
+                warning!("Failed to remove the CA: {e:#}");
+            }
+        }
+        if self.proxy_set || self.cert.is_some() {
+            status!("Restarting the UI to apply the restored settings...");
+            let _ = self.ssh.run_cmd_stdio("restart ui");
+        }
+    }
+}
+
+fn run_dut_mitm(args: &ArgsMitm) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let info = DutInfo::new(&args.dut)?;
+    cancel::install_handler()?;
+    let mitmproxy = if args.builtin {
+        None
+    } else {
+        find_mitmproxy(args.web)
+    };
+    // Kept until the end to stop mitmproxy
+    let _mitmproxy = match mitmproxy {
+        Some(cmd) => {
+            status!("Starting {cmd} on port {}...", args.port);
+            let child = async_process::Command::new(cmd)
+                .args([
+                    "--listen-host",
+                    "127.0.0.1",
+                    "--listen-port",
+                    &args.port.to_string(),
+                ])
+                .kill_on_drop(true)
+                .stdin(Stdio::null())
+                .spawn()
+                .context(anyhow!("Failed to start {cmd}"))?;
+            Some(child)
+        }
+        None => {
+            if !args.builtin {
+                warning!("mitmproxy is not installed. Using the built-in recorder, which does not decrypt TLS.");
+            }
+            let listener = TcpListener::bind(("127.0.0.1", args.port))
+                .context(anyhow!("Failed to listen on port {}", args.port))?;
+            start_recorder(listener);
+            None
+        }
+    };
+    let mut tunnel = info
+        .ssh()
+        .start_reverse_port_forwarding(args.port, args.port)?;
+    let mut cleanup = MitmCleanup {
+        ssh: info.ssh(),
+        id: info.id().to_string(),
+        cert: None,
+        proxy_set: false,
+    };
+    if mitmproxy.is_some() {
+        let ca = mitmproxy_ca_path()?;
+        // mitmproxy generates the CA on the first run
+        for _ in 0..50 {
+            if ca.exists() {
+                break;
+            }
+            cancel::sleep(time::Duration::from_millis(100))?;
+        }
+        let ca = ca.to_str().context("Invalid CA path")?;
+        cleanup.cert = Some(install_cert(
+            info.ssh(),
+            info.id(),
+            ca,
+            &[CertStore::NssDb],
+        )?);
+    }
+    set_proxy(info.ssh(), &format!("http://127.0.0.1:{}", args.port))?;
+    cleanup.proxy_set = true;
+    info.ssh().run_cmd_stdio("restart ui")?;
+    status!(
+        "The traffic of {} goes through the proxy now. Please log in again on the DUT. Press Ctrl-C to stop.",
+        info.id()
+    );
+    while !cancel::is_cancelled() {
+        if let Ok(Some(status)) = tunnel.try_status() {
+            return Err(anyhow!("The ssh tunnel to the DUT exited with {status}"));
+        }
+        cancel::sleep(time::Duration::from_secs(1)).ok();
+    }
+    status!("Stopping...");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// override DNS on a DUT (shows the overrides made by lium if no change is given)
+#[argh(subcommand, name = "dns")]
+struct ArgsDns {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// resolve a host to an address via /etc/hosts (e.g. staging.example.com=10.1.2.3). Can be
+    /// repeated.
+    #[argh(option)]
+    add: Vec<String>,
+
+    /// remove the override of a host added with --add. Can be repeated.
+    #[argh(option)]
+    remove: Vec<String>,
+
+    /// use the name server for the current network. Can be repeated.
+    #[argh(option)]
+    nameserver: Vec<String>,
+
+    /// revert all the DNS changes made by lium
+    #[argh(switch)]
+    clear: bool,
+}
+
+fn run_dut_dns(args: &ArgsDns) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let overrides = args
+        .add
+        .iter()
+        .map(|s| HostOverride::parse(s))
+        .collect::<Result<Vec<_>>>()?;
+    let name_servers = args
+        .nameserver
+        .iter()
+        .map(|s| s.parse().context(anyhow!("Invalid name server {s:?}")))
+        .collect::<Result<Vec<_>>>()?;
+    let target = SshInfo::new(&args.dut)?;
+    if args.clear {
+        clear_dns_overrides(&target)?;
+        println!("Reverted the DNS changes on {}", args.dut);
+    }
+    if !args.remove.is_empty() {
+        remove_host_overrides(&target, &args.remove)?;
+    }
+    if !overrides.is_empty() {
+        add_host_overrides(&target, &overrides)?;
+    }
+    if !name_servers.is_empty() {
+        set_name_servers(&target, &name_servers)?;
+    }
+    if !args.clear {
+        println!("{}", dns_overrides_status(&target)?.trim_end());
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// keep free-text notes about DUTs (e.g. "replaced battery 2024-05"). The latest one is shown in
+/// `lium dut list`.
+#[argh(subcommand, name = "note")]
+struct ArgsNote {
+    #[argh(subcommand)]
+    nested: NoteSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum NoteSubCommand {
+    Add(ArgsNoteAdd),
+    List(ArgsNoteList),
+    Rm(ArgsNoteRm),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// add a note
+#[argh(subcommand, name = "add")]
+struct ArgsNoteAdd {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// text of the note
+    #[argh(positional, greedy)]
+    text: Vec<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the notes of a DUT, or of all DUTs
+#[argh(subcommand, name = "list")]
+struct ArgsNoteList {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: Option<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove a note
+#[argh(subcommand, name = "rm")]
+struct ArgsNoteRm {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// number of the note shown by `lium dut note list`
+    #[argh(positional)]
+    index: Option<usize>,
+
+    /// remove all the notes of the DUT
+    #[argh(switch)]
+    all: bool,
+}
+
+/// Returns the ID of the DUT, without accessing it if it is already known
+fn dut_id_for_note(dut: &str) -> Result<String> {
+    if SSH_CACHE.get(dut)?.is_some() || DUT_NOTES.get(dut)?.is_some() {
+        return Ok(dut.to_string());
+    }
+    Ok(DutInfo::new(dut)?.id().to_string())
+}
+
+fn print_notes(id: &str, notes: &[DutNote]) {
+    println!("{id}");
+    for (i, note) in notes.iter().enumerate() {
+        println!("  {}: {note}", i + 1);
+    }
+}
+
+fn run_dut_note(args: &ArgsNote) -> Result<()> {
+    match &args.nested {
+        NoteSubCommand::Add(args) => {
+            let text = args.text.join(" ");
+            if text.trim().is_empty() {
+                return Err(anyhow!("The note is empty"));
+            }
+            let id = dut_id_for_note(&args.dut)?;
+            let mut notes = DUT_NOTES.get(&id)?.unwrap_or_default();
+            notes.push(DutNote {
+                text,
+                timestamp: Local::now().timestamp(),
+            });
+            DUT_NOTES.set(&id, notes)?;
+            status!("Added a note to {id}");
+        }
+        NoteSubCommand::List(args) => {
+            if let Some(dut) = &args.dut {
+                let id = dut_id_for_note(dut)?;
+                print_notes(&id, &DUT_NOTES.get(&id)?.unwrap_or_default());
+            } else {
+                let mut entries: Vec<(String, Vec<DutNote>)> =
+                    DUT_NOTES.entries()?.into_iter().collect();
+                entries.sort_by(|a, b| a.0.cmp(&b.0));
+                for (id, notes) in entries {
+                    print_notes(&id, &notes);
+                }
+            }
+        }
+        NoteSubCommand::Rm(args) => {
+            let id = dut_id_for_note(&args.dut)?;
+            let mut notes = DUT_NOTES.get(&id)?.unwrap_or_default();
+            if args.all {
+                DUT_NOTES.remove(&id)?;
+                status!("Removed {} notes of {id}", notes.len());
+                return Ok(());
+            }
+            let index = args
+                .index
+                .context("Specify the number of the note or --all")?;
+            if index == 0 || index > notes.len() {
+                return Err(anyhow!("{id} has no note #{index}"));
+            }
+            let note = notes.remove(index - 1);
+            if notes.is_empty() {
+                DUT_NOTES.remove(&id)?;
+            } else {
+                DUT_NOTES.set(&id, notes)?;
+            }
+            status!("Removed: {note}");
+        }
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run a local command against a port of the DUT forwarded to an ephemeral local port
+/// (e.g. `lium dut with-forward --dut x --remote 9222 -- chrome-client --port {{port}}`)
+#[argh(subcommand, name = "with-forward")]
+struct ArgsWithForward {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// port on the DUT to forward
+    #[argh(option)]
+    remote: u16,
+
+    /// command to run. {{port}} is replaced with the local port, which is also set to
+    /// LIUM_FORWARD_PORT
+    #[argh(positional, greedy)]
+    command: Vec<String>,
+}
+fn run_dut_with_forward(args: &ArgsWithForward) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let Some((program, program_args)) = args.command.split_first() else {
+        return Err(anyhow!("Specify a command to run after --"));
+    };
+    let target = SshInfo::new(&args.dut)?;
+    let (forward, port) = target.start_local_forwarding(args.remote)?;
+    status!(
+        "Forwarding 127.0.0.1:{port} to port {} of {}",
+        args.remote,
+        args.dut
+    );
+    let port = port.to_string();
+    let status = Command::new(program.replace("{port}", &port))
+        .args(program_args.iter().map(|a| a.replace("{port}", &port)))
+        .env("LIUM_FORWARD_PORT", &port)
+        .status()
+        .context(anyhow!("Failed to run {program}"))?;
+    // The forwarding is stopped here since the ssh process is killed on drop
+    drop(forward);
+    status
+        .exit_ok()
+        .context(anyhow!("{program} exited with {status}"))
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// enable Chrome remote debugging on the DUT, forward the port and print the inspectable targets
+/// as JSON. the forwarding is kept until Ctrl-C.
+#[argh(subcommand, name = "devtools")]
+struct ArgsDevtools {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// open the DevTools of the pages in the local Chrome
+    #[argh(switch)]
+    open: bool,
+
+    /// command of the local Chrome (default: google-chrome)
+    #[argh(option, default = "String::from(\"google-chrome\")")]
+    browser: String,
+
+    /// do not restart the UI to enable remote debugging, failing if it is not enabled yet
+    #[argh(switch)]
+    no_restart: bool,
+}
+fn run_dut_devtools(args: &ArgsDevtools) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    ensure_remote_debugging(&target, args.port, args.no_restart)?;
+    cancel::install_handler()?;
+    let (mut forward, port) = target.start_local_forwarding(args.port)?;
+    let mut targets = list_targets(&target, args.port)?;
+    localize_targets(&mut targets, args.port, port);
+    println!("{}", serde_json::to_string_pretty(&targets)?);
+    if args.open {
+        for t in &targets {
+            if t["type"] != "page" {
+                continue;
+            }
+            if let Some(url) = t["localFrontendUrl"].as_str() {
+                Command::new(&args.browser)
+                    .arg(url)
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::null())
+                    .spawn()
+                    .context(anyhow!("Failed to run {}", args.browser))?;
+            }
+        }
+    }
+    status!(
+        "Forwarding 127.0.0.1:{port} to the DevTools of {}. Press Ctrl-C to stop",
+        args.dut
+    );
+    loop {
+        if let Some(status) = forward.try_status()? {
+            return Err(anyhow!("The port forwarding stopped: {status}"));
+        }
+        // The ssh process is killed when the child is dropped
+        cancel::sleep(time::Duration::from_secs(5))?;
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// automate Chrome on the DUT via the DevTools protocol and the test APIs (autotestPrivate, OOBE),
+/// printing typed results as JSON
+#[argh(subcommand, name = "chrome")]
+struct ArgsChrome {
+    #[argh(subcommand)]
+    nested: ChromeSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ChromeSubCommand {
+    Eval(ArgsChromeEval),
+    Login(ArgsChromeLogin),
+    Open(ArgsChromeOpen),
+    Windows(ArgsChromeWindows),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// evaluate a JavaScript expression (promises are awaited), e.g.
+/// `lium dut chrome eval --dut x 'document.title'`
+#[argh(subcommand, name = "eval")]
+struct ArgsChromeEval {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// ID, part of the URL or the title of the target (default: the first page). "autotest" is
+    /// the background page of the autotest private extension.
+    #[argh(option)]
+    target: Option<String>,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// do not restart the UI to enable remote debugging
+    #[argh(switch)]
+    no_restart: bool,
+
+    /// a JavaScript expression
+    #[argh(positional)]
+    expr: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// open a URL in a new tab
+#[argh(subcommand, name = "open")]
+struct ArgsChromeOpen {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// do not restart the UI to enable remote debugging
+    #[argh(switch)]
+    no_restart: bool,
+
+    /// the URL to open
+    #[argh(positional)]
+    url: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the app windows and their states (needs the autotest private extension)
+#[argh(subcommand, name = "windows")]
+struct ArgsChromeWindows {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// do not restart the UI to enable remote debugging
+    #[argh(switch)]
+    no_restart: bool,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// log in on the login screen with a fake account, as Tast does
+#[argh(subcommand, name = "login")]
+struct ArgsChromeLogin {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// user name (default: testuser@gmail.com)
+    #[argh(option, default = "String::from(\"testuser@gmail.com\")")]
+    user: String,
+
+    /// password (default: testpass)
+    #[argh(option, default = "String::from(\"testpass\")")]
+    password: String,
+
+    /// GAIA ID (default: gaia-id)
+    #[argh(option, default = "String::from(\"gaia-id\")")]
+    gaia_id: String,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// do not restart the UI to enable remote debugging
+    #[argh(switch)]
+    no_restart: bool,
+}
+fn run_dut_chrome(args: &ArgsChrome) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let result = match &args.nested {
+        ChromeSubCommand::Eval(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            ensure_remote_debugging(&target, args.port, args.no_restart)?;
+            let target_filter = match args.target.as_deref() {
+                Some("autotest") => Some(AUTOTEST_EXT_ID),
+                t => t,
+            };
+            evaluate(&target, args.port, target_filter, &args.expr)?
+        }
+        ChromeSubCommand::Open(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            ensure_remote_debugging(&target, args.port, args.no_restart)?;
+            open_url(&target, args.port, &args.url)?
+        }
+        ChromeSubCommand::Windows(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            ensure_remote_debugging(&target, args.port, args.no_restart)?;
+            evaluate(
+                &target,
+                args.port,
+                Some(AUTOTEST_EXT_ID),
+                "new Promise((resolve) => chrome.autotestPrivate.getAppWindowList(resolve))",
+            )
+            .context("Failed to get the windows. Is Chrome running with the autotest private extension (e.g. started by Tast)?")?
+        }
+        ChromeSubCommand::Login(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            ensure_remote_debugging(&target, args.port, args.no_restart)?;
+            let expr = format!(
+                "Oobe.loginForTesting({}, {}, {}, false)",
+                serde_json::to_string(&args.user)?,
+                serde_json::to_string(&args.password)?,
+                serde_json::to_string(&args.gaia_id)?
+            );
+            evaluate(&target, args.port, Some("chrome://oobe"), &expr)
+                .context("Failed to log in. Is the login screen shown?")?
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show or switch the UI language of the sign-in screen (and the signed-in user with --session)
+/// and the region, restarting the UI. e.g. `lium dut locale --dut x --set ja --region jp`
+#[argh(subcommand, name = "locale")]
+struct ArgsLocale {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// the locale to switch to (e.g. ja, pt-BR). the current settings are shown if omitted.
+    #[argh(option)]
+    set: Option<String>,
+
+    /// the region code written to the VPD (e.g. jp, us). needs the write protection disabled.
+    #[argh(option)]
+    region: Option<String>,
+
+    /// also switch the signed-in user via the settings API (needs remote debugging enabled,
+    /// e.g. by `lium dut chrome`). applied at the next sign-in.
+    #[argh(switch)]
+    session: bool,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+}
+
+const LOCAL_STATE_PATH: &str = "/home/chronos/Local State";
+
+/// Returns true if the locale looks like a BCP 47 tag Chrome accepts (e.g. ja, en-GB, es-419)
+fn is_valid_locale(locale: &str) -> bool {
+    let mut parts = locale.split('-');
+    let lang = parts.next().unwrap_or_default();
+    (2..=3).contains(&lang.len())
+        && lang.chars().all(|c| c.is_ascii_lowercase())
+        && parts.all(|p| (2..=8).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Returns the Local State of Chrome with the locale of the sign-in screen set
+fn set_sign_in_locale(local_state: &str, locale: &str) -> Result<String> {
+    let mut state: serde_json::Value = if local_state.trim().is_empty() {
+        serde_json::json!({})
+    } else {
+        serde_json::from_str(local_state).context("Invalid Local State")?
+    };
+    let intl = state
+        .as_object_mut()
+        .context("Local State is not an object")?
+        .entry("intl")
+        .or_insert_with(|| serde_json::json!({}));
+    let intl = intl.as_object_mut().context("intl is not an object")?;
+    // The owner locale is used on the sign-in screen once the device has an owner
+    for key in ["app_locale", "owner_locale"] {
+        intl.insert(key.to_string(), serde_json::json!(locale));
+    }
+    Ok(serde_json::to_string(&state)?)
+}
+
+fn run_dut_locale(args: &ArgsLocale) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    let Some(locale) = &args.set else {
+        let local_state = target
+            .run_cmd_stdio(&format!("cat '{LOCAL_STATE_PATH}' 2>/dev/null"))
+            .unwrap_or_default();
+        let state: serde_json::Value = serde_json::from_str(&local_state).unwrap_or_default();
+        let region = target
+            .run_cmd_stdio("vpd_get_value region")
+            .unwrap_or_default();
+        println!(
+            "locale: {}",
+            state["intl"]["app_locale"].as_str().unwrap_or("-")
+        );
+        println!(
+            "region: {}",
+            Some(region.trim()).filter(|r| !r.is_empty()).unwrap_or("-")
+        );
+        return Ok(());
+    };
+    if !is_valid_locale(locale) {
+        return Err(anyhow!("Invalid locale {locale:?} (e.g. ja, pt-BR)"));
+    }
+    if let Some(region) = &args.region {
+        if region.is_empty()
+            || !region
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '.')
+        {
+            return Err(anyhow!("Invalid region {region:?} (e.g. jp, us)"));
+        }
+    }
+    if args.session {
+        // Restarting the UI here would sign the user out, so never do it
+        ensure_remote_debugging(&target, args.port, true)?;
+        open_url(&target, args.port, "chrome://os-settings/osLanguages")?;
+        let expr = format!(
+            "new Promise((resolve) => chrome.settingsPrivate.setPref('intl.app_locale', {}, '', resolve))",
+            serde_json::to_string(locale)?
+        );
+        // Retry until the page is loaded
+        let mut result = Err(anyhow!("not evaluated"));
+        for _ in 0..5 {
+            result = evaluate(&target, args.port, Some("os-settings"), &expr);
+            if result.is_ok() {
+                break;
+            }
+            thread::sleep(time::Duration::from_secs(2));
+        }
+        result.context("Failed to set the locale of the signed-in user")?;
+        status!("Switched the signed-in user to {locale}");
+    }
+    // Chrome writes Local State when it exits, so edit it while the UI is stopped
+    status!("Stopping the UI...");
+    target.run_cmd_stdio("stop ui || true")?;
+    let result = (|| -> Result<()> {
+        let local_state =
+            target.run_cmd_stdio(&format!("cat '{LOCAL_STATE_PATH}' 2>/dev/null || true"))?;
+        let local_state = set_sign_in_locale(&local_state, locale)?;
+        target.run_cmd_stdio(&format!(
+            "echo {} | base64 -d > '{LOCAL_STATE_PATH}' && chown chronos:chronos '{LOCAL_STATE_PATH}'",
+            STANDARD.encode(local_state)
+        ))?;
+        if let Some(region) = &args.region {
+            target.run_privileged_cmd_piped(
+                "write the region to the VPD",
+                &format!("vpd -i RO_VPD -s region={region} && dump_vpd_log --force"),
+            )?;
+        }
+        Ok(())
+    })();
+    status!("Starting the UI...");
+    target.run_cmd_stdio("start ui")?;
+    result?;
+    println!("locale: {locale}");
+    if let Some(region) = &args.region {
+        println!("region: {region}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show or switch the time zone of the DUT, e.g. `lium dut tz --dut x --set Asia/Tokyo`
+#[argh(subcommand, name = "tz")]
+struct ArgsTz {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// the time zone to switch to (a name in /usr/share/zoneinfo, e.g. Asia/Tokyo)
+    #[argh(option)]
+    set: Option<String>,
+
+    /// switch back to the time zone before the first --set
+    #[argh(switch)]
+    restore: bool,
+
+    /// restart the UI so that Chrome picks up the time zone (signs the user out)
+    #[argh(switch)]
+    restart_ui: bool,
+}
+
+const TIMEZONE_LINK: &str = "/var/lib/timezone/localtime";
+const ZONEINFO_DIR: &str = "/usr/share/zoneinfo/";
+
+/// Time zones of DUTs before `lium dut tz --set`, to be restored by --restore
+static DUT_ORIGINAL_TIMEZONES: KvCache<String> = KvCache::new("dut_original_timezones");
+
+fn get_timezone(target: &SshInfo) -> Result<String> {
+    let path = target.run_cmd_stdio(&format!("readlink {TIMEZONE_LINK}"))?;
+    Ok(path
+        .trim()
+        .strip_prefix(ZONEINFO_DIR)
+        .unwrap_or(path.trim())
+        .to_string())
+}
+
+fn set_timezone(target: &SshInfo, tz: &str) -> Result<()> {
+    if tz.is_empty()
+        || tz.starts_with('/')
+        || tz.contains("..")
+        || !tz
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "/_-+".contains(c))
+    {
+        return Err(anyhow!("Invalid time zone {tz:?} (e.g. Asia/Tokyo)"));
+    }
+    target
+        .run_cmd_stdio(&format!("test -f {ZONEINFO_DIR}{tz}"))
+        .context(anyhow!("Unknown time zone {tz}"))?;
+    target.run_cmd_stdio(&format!("ln -sf {ZONEINFO_DIR}{tz} {TIMEZONE_LINK}"))?;
+    Ok(())
+}
+
+fn run_dut_tz(args: &ArgsTz) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    let key = target.host_and_port();
+    if args.restore {
+        let original = DUT_ORIGINAL_TIMEZONES
+            .get(&key)?
+            .context(anyhow!("The time zone of {} is not switched", args.dut))?;
+        set_timezone(&target, &original)?;
+        DUT_ORIGINAL_TIMEZONES.remove(&key)?;
+    } else if let Some(tz) = &args.set {
+        let current = get_timezone(&target)?;
+        set_timezone(&target, tz)?;
+        // Keep the first one so that repeated --set can be restored at once
+        if DUT_ORIGINAL_TIMEZONES.get(&key)?.is_none() {
+            DUT_ORIGINAL_TIMEZONES.set(&key, current)?;
+        }
+    }
+    if (args.restore || args.set.is_some()) && args.restart_ui {
+        status!("Restarting the UI...");
+        target.run_cmd_stdio("restart ui")?;
+    }
+    println!("time zone: {}", get_timezone(&target)?);
+    println!("date: {}", target.run_cmd_stdio("date")?.trim());
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show or override the geolocation seen by the pages in Chrome (via the DevTools protocol),
+/// e.g. `lium dut geo --dut x --set 35.68,139.76`. the override lasts until --restore or a
+/// restart of Chrome.
+#[argh(subcommand, name = "geo")]
+struct ArgsGeo {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// latitude,longitude in degrees
+    #[argh(option)]
+    set: Option<String>,
+
+    /// accuracy in meters (default: 100)
+    #[argh(option, default = "100.0")]
+    accuracy: f64,
+
+    /// stop overriding the geolocation
+    #[argh(switch)]
+    restore: bool,
+
+    /// remote debugging port of Chrome on the DUT (default: 9222)
+    #[argh(option, default = "9222")]
+    port: u16,
+
+    /// do not restart the UI to enable remote debugging
+    #[argh(switch)]
+    no_restart: bool,
+}
+
+fn parse_lat_long(s: &str) -> Result<(f64, f64)> {
+    let (lat, long) = s
+        .split_once(',')
+        .ok_or_else(|| anyhow!("Invalid location {s:?}. Use latitude,longitude"))?;
+    let lat: f64 = lat.trim().parse().context("Invalid latitude")?;
+    let long: f64 = long.trim().parse().context("Invalid longitude")?;
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&long) {
+        return Err(anyhow!("Location {s:?} is out of range"));
+    }
+    Ok((lat, long))
+}
+
+fn run_dut_geo(args: &ArgsGeo) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    if args.restore {
+        if !stop_geolocation_override(&target)? {
+            warning!("The geolocation of {} is not overridden", args.dut);
+        }
+    } else if let Some(location) = &args.set {
+        let (lat, long) = parse_lat_long(location)?;
+        ensure_remote_debugging(&target, args.port, args.no_restart)?;
+        start_geolocation_override(&target, args.port, lat, long, args.accuracy)?;
+    }
+    match geolocation_override(&target)? {
+        Some(location) => println!("geolocation: {location} (overridden)"),
+        None => println!("geolocation: not overridden"),
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// simulate hardware states (AC, battery level, lid) on a DUT, e.g.
+/// `lium dut inject --dut x --power-ac off --battery 15%`. the states are applied again after
+/// reboots until --end restores the real ones. the current session is shown if no state is given.
+#[argh(subcommand, name = "inject")]
+struct ArgsInject {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// on or off. off discharges the battery as if the AC was unplugged.
+    #[argh(option)]
+    power_ac: Option<String>,
+
+    /// battery level reported by the EC (e.g. 15%)
+    #[argh(option)]
+    battery: Option<String>,
+
+    /// closed or open (needs --servo)
+    #[argh(option)]
+    lid: Option<String>,
+
+    /// serial of the servo connected to the DUT, to simulate the lid via the EC console
+    #[argh(option)]
+    servo: Option<String>,
+
+    /// end the session and restore the real states
+    #[argh(switch)]
+    end: bool,
+}
+
+fn parse_on_off(name: &str, value: &Option<String>, on: &str, off: &str) -> Result<Option<bool>> {
+    match value.as_deref() {
+        None => Ok(None),
+        Some(v) if v == on => Ok(Some(true)),
+        Some(v) if v == off => Ok(Some(false)),
+        Some(v) => Err(anyhow!("Invalid {name} {v:?}. Use {on} or {off}")),
+    }
+}
+
+fn run_dut_inject(args: &ArgsInject) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    let key = target.host_and_port();
+    if args.end {
+        let session = INJECT_SESSIONS
+            .get(&key)?
+            .context(anyhow!("No inject session on {}", args.dut))?;
+        session.restore(&target)?;
+        INJECT_SESSIONS.remove(&key)?;
+        println!("Restored the real states of {}", args.dut);
+        return Ok(());
+    }
+    let states = InjectSession {
+        power_ac: parse_on_off("--power-ac", &args.power_ac, "on", "off")?,
+        battery: args.battery.as_deref().map(parse_battery).transpose()?,
+        lid_closed: parse_on_off("--lid", &args.lid, "closed", "open")?,
+        servo: args.servo.clone(),
+        started_at: Local::now().timestamp(),
+    };
+    let mut session = INJECT_SESSIONS.get(&key)?;
+    if states.power_ac.is_some() || states.battery.is_some() || states.lid_closed.is_some() {
+        let session = session.get_or_insert_with(|| states.clone());
+        session.merge(&states);
+        session.apply(&target)?;
+        INJECT_SESSIONS.set(&key, session.clone())?;
+    }
+    let Some(session) = session else {
+        println!("No inject session on {}", args.dut);
+        return Ok(());
+    };
+    let show = |v: Option<String>| v.unwrap_or_else(|| "-".to_string());
+    println!(
+        "power-ac: {}",
+        show(
+            session
+                .power_ac
+                .map(|on| (if on { "on" } else { "off" }).to_string())
+        )
+    );
+    println!(
+        "battery: {}",
+        show(session.battery.map(|l| format!("{l}%")))
+    );
+    println!(
+        "lid: {}",
+        show(
+            session
+                .lid_closed
+                .map(|c| (if c { "closed" } else { "open" }).to_string())
+        )
+    );
+    println!(
+        "Run `lium dut inject --dut {} --end` to restore the real states",
+        args.dut
+    );
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// manage the cryptohome users accumulated on a DUT (e.g. by tests) without powerwashing it
+#[argh(subcommand, name = "profiles")]
+struct ArgsProfiles {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    #[argh(subcommand)]
+    nested: ProfilesSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum ProfilesSubCommand {
+    Backup(ArgsProfilesBackup),
+    List(ArgsProfilesList),
+    Remove(ArgsProfilesRemove),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the users with their vault hashes, sizes and whether they are signed in
+#[argh(subcommand, name = "list")]
+struct ArgsProfilesList {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove the vault of a user (an email, or a hash shown by list)
+#[argh(subcommand, name = "remove")]
+struct ArgsProfilesRemove {
+    /// users to remove
+    #[argh(positional)]
+    users: Vec<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// save the home of a user as a tar.gz. the decrypted files are saved if the user is signed
+/// in, and the encrypted vault (only usable on the same DUT) otherwise.
+#[argh(subcommand, name = "backup")]
+struct ArgsProfilesBackup {
+    /// the user (an email, or a hash shown by list)
+    #[argh(positional)]
+    user: String,
+
+    /// destination file (default: <user>_<date>.tar.gz)
+    #[argh(option)]
+    dest: Option<String>,
+}
+
+/// A cryptohome vault on a DUT
+#[derive(Debug, PartialEq, Eq)]
+struct Profile {
+    /// The obfuscated user name, which is the directory name in /home/.shadow
+    hash: String,
+    user: Option<String>,
+    size_kb: u64,
+    mounted: bool,
+}
+
+/// Parses "<hash> <size in KB> <yes|no>" lines with `users` of "<hash> <email>" lines
+fn parse_profiles(listing: &str, users: &str) -> Vec<Profile> {
+    let users: HashMap<&str, &str> = users
+        .lines()
+        .filter_map(|l| l.trim().split_once(' '))
+        .collect();
+    listing
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            let hash = fields.next()?;
+            if hash.len() != 40 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                return None;
+            }
+            Some(Profile {
+                hash: hash.to_string(),
+                user: users.get(hash).map(|u| u.to_string()),
+                size_kb: fields.next()?.parse().ok()?,
+                mounted: fields.next() == Some("yes"),
+            })
+        })
+        .collect()
+}
+
+fn is_valid_user(user: &str) -> bool {
+    !user.is_empty()
+        && user
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "@._+-".contains(c))
+}
+
+fn list_profiles(target: &SshInfo) -> Result<Vec<Profile>> {
+    let listing = target.run_cmd_stdio(
+        "for d in /home/.shadow/*/; do h=$(basename $d); \
+         echo $h $(du -sk $d | cut -f1) $(mountpoint -q /home/user/$h && echo yes || echo no); done",
+    )?;
+    let local_state = target
+        .run_cmd_stdio(&format!("cat '{LOCAL_STATE_PATH}' 2>/dev/null"))
+        .unwrap_or_default();
+    let state: serde_json::Value = serde_json::from_str(&local_state).unwrap_or_default();
+    let emails: Vec<&str> = state["LoggedInUsers"]
+        .as_array()
+        .map(|users| users.iter().filter_map(|u| u.as_str()).collect())
+        .unwrap_or_default();
+    let emails: Vec<&str> = emails.into_iter().filter(|u| is_valid_user(u)).collect();
+    let users = if emails.is_empty() {
+        String::new()
+    } else {
+        target.run_cmd_stdio(&format!(
+            "for u in {}; do echo $(cryptohome --action=obfuscate_user --user=$u) $u; done",
+            emails.join(" ")
+        ))?
+    };
+    Ok(parse_profiles(&listing, &users))
+}
+
+fn find_profile(target: &SshInfo, user: &str) -> Result<Profile> {
+    list_profiles(target)?
+        .into_iter()
+        .find(|p| p.hash == user || p.user.as_deref() == Some(user))
+        .context(anyhow!(
+            "No vault of {user} found. See `lium dut profiles --dut <dut> list`"
+        ))
+}
+
+fn run_dut_profiles(args: &ArgsProfiles) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = SshInfo::new(&args.dut)?;
+    match &args.nested {
+        ProfilesSubCommand::List(_) => {
+            let mut table = Table::new();
+            table.push(["HASH", "USER", "SIZE(MB)", "SIGNED-IN"]);
+            for p in list_profiles(&target)? {
+                table.push([
+                    p.hash,
+                    p.user.unwrap_or_else(|| "-".to_string()),
+                    format!("{:.1}", p.size_kb as f64 / 1024.0),
+                    (if p.mounted { "yes" } else { "no" }).to_string(),
+                ]);
+            }
+            for line in table.lines("  ") {
+                println!("{line}");
+            }
+        }
+        ProfilesSubCommand::Remove(args) => {
+            for user in &args.users {
+                let p = find_profile(&target, user)?;
+                if p.mounted {
+                    return Err(anyhow!("{user} is signed in. Sign out first"));
+                }
+                let cmd = match &p.user {
+                    Some(email) => format!("cryptohome --action=remove --force --user={email}"),
+                    // cryptohome needs the email, which is unknown for vaults left by
+                    // removed users, so remove the directory of the vault instead
+                    None => format!("rm -rf /home/.shadow/{}", p.hash),
+                };
+                target
+                    .run_cmd_stdio(&cmd)
+                    .context(anyhow!("Failed to remove the vault of {user}"))?;
+                println!("Removed {user} ({:.1} MB)", p.size_kb as f64 / 1024.0);
+            }
+        }
+        ProfilesSubCommand::Backup(args) => {
+            let p = find_profile(&target, &args.user)?;
+            let dir = if p.mounted {
+                format!("/home/user/{}", p.hash)
+            } else {
+                warning!(
+                    "{} is not signed in. Saving the encrypted vault, which can only be restored on this DUT",
+                    args.user
+                );
+                format!("/home/.shadow/{}", p.hash)
+            };
+            let dest = args.dest.clone().unwrap_or_else(|| {
+                format!(
+                    "{}_{}.tar.gz",
+                    p.user.as_deref().unwrap_or(&p.hash),
+                    Local::now().format("%Y%m%d_%H%M%S")
+                )
+            });
+            let file = std::fs::File::create(&dest).context(anyhow!("Failed to create {dest}"))?;
+            status!("Saving {dir} to {dest}...");
+            target
+                .ssh_cmd(None)?
+                .arg(format!("tar -C {dir} -czf - ."))
+                .stdout(file)
+                .status()?
+                .exit_ok()
+                .context(anyhow!("Failed to save {dir}"))?;
+            println!("{dest}");
+        }
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// manage the TPM of the DUT (see also the tpm_version, tpm_owned and attestation_status keys of
+/// `lium dut info`)
+#[argh(subcommand, name = "tpm")]
+struct ArgsTpm {
+    #[argh(subcommand)]
+    nested: TpmSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum TpmSubCommand {
+    Clear(ArgsTpmClear),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// clear the TPM ownership (e.g. when enrollment testing wedged it). the DUT is rebooted since
+/// the firmware clears it at the next boot.
+#[argh(subcommand, name = "clear")]
+struct ArgsTpmClear {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// seconds to wait for the DUT to come back (default: 300)
+    #[argh(option, default = "300")]
+    timeout: u64,
+}
+fn run_dut_tpm(args: &ArgsTpm) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    match &args.nested {
+        TpmSubCommand::Clear(args) => {
+            let target = SshInfo::new(&args.dut)?;
+            let boot_id = target.get_boot_id()?;
+            target.run_privileged_cmd_piped(
+                "request clearing the TPM owner",
+                "crossystem clear_tpm_owner_request=1",
+            )?;
+            status!("Rebooting {} to clear the TPM owner...", args.dut);
+            // ssh may exit with an error since the connection is closed by the reboot
+            drop(target.run_cmd_piped(&["reboot; exit"]));
+            target.wait_for_new_boot_id(&boot_id, time::Duration::from_secs(args.timeout))?;
+            status!("The DUT is back. Verifying...");
+            if target.run_cmd_stdio("crossystem clear_tpm_owner_request")? != "0" {
+                return Err(anyhow!(
+                    "The firmware did not process the request to clear the TPM owner"
+                ));
+            }
+            // tpm_manager may take ownership again on its own, so just report the new state
+            let keys = ["tpm_owned", "attestation_status"];
+            let info = DutInfo::fetch_keys(&target, &keys)?;
+            for k in keys {
+                println!("{k}: {}", info.get(k).map(|v| v.trim()).unwrap_or("-"));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the local ports used by forwardings of running lium instances (e.g. dut monitor)
+#[argh(subcommand, name = "ports")]
+struct ArgsPorts {}
+
+fn run_dut_ports(_args: &ArgsPorts) -> Result<()> {
+    let mut table = Table::new();
+    for r in active_ports()? {
+        table.push([
+            r.port.to_string(),
+            format!("pid {}", r.pid),
+            format_local_time(r.allocated_at),
+            r.purpose,
+        ]);
+    }
+    for line in table.lines("  ") {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show events of DUTs reported by background processes (e.g. reboots seen by lium forward)
+#[argh(subcommand, name = "events")]
+struct ArgsEvents {
+    /// only show events of this DUT
+    #[argh(option)]
+    dut: Option<String>,
+
+    /// keep printing new events
+    #[argh(switch)]
+    follow: bool,
+}
+
+fn run_dut_events(args: &ArgsEvents) -> Result<()> {
+    cancel::install_handler()?;
+    let mut printed = 0;
+    loop {
+        let events = read_events()?;
+        // The log was trimmed
+        if events.len() < printed {
+            printed = 0;
+        }
+        for e in &events[printed..] {
+            if args.dut.as_ref().map_or(true, |dut| *dut == e.dut) {
+                println!(
+                    "{}  {}  {}  {}",
+                    format_local_time(e.at),
+                    e.dut,
+                    e.kind,
+                    e.message
+                );
+            }
+        }
+        printed = events.len();
+        if !args.follow || cancel::sleep(time::Duration::from_secs(1)).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// manage the keep-alive agent that keeps DUTs behind NATs reachable via a reverse tunnel
+#[argh(subcommand, name = "agent")]
+struct ArgsAgent {
+    #[argh(subcommand)]
+    nested: AgentSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum AgentSubCommand {
+    Install(ArgsAgentInstall),
+    List(ArgsAgentList),
+    Uninstall(ArgsAgentUninstall),
+    Watch(ArgsAgentWatch),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// install the agent to the DUT and accept its tunnel on this machine
+#[argh(subcommand, name = "install")]
+struct ArgsAgentInstall {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// host:port of the sshd of this machine as seen from the DUT
+    #[argh(option)]
+    callback: String,
+
+    /// user to log in to this machine as (default: $USER)
+    #[argh(option)]
+    user: Option<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the installed agents and their tunnel status
+#[argh(subcommand, name = "list")]
+struct ArgsAgentList {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove the agent from the DUT and this machine
+#[argh(subcommand, name = "uninstall")]
+struct ArgsAgentUninstall {
+    /// a DUT ID
+    #[argh(option)]
+    dut: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// watch the tunnels of the agents, recovering from DUT reboots and stuck agents
+#[argh(subcommand, name = "watch")]
+struct ArgsAgentWatch {
+    /// seconds between checks
+    #[argh(option, default = "10")]
+    interval: u64,
+}
+
+fn run_dut_agent(args: &ArgsAgent) -> Result<()> {
+    match &args.nested {
+        AgentSubCommand::Install(args) => {
+            cros::ensure_testing_rsa_is_there()?;
+            let info = DutInfo::new(&args.dut)?;
+            let id = info.id();
+            let user = match &args.user {
+                Some(user) => user.clone(),
+                None => std::env::var("USER").context("Please specify --user")?,
+            };
+            let agent = install_agent(info.ssh(), id, &args.callback, &user)?;
+            // Use the tunnel when the DUT is not reachable directly
+            let mut ssh = info.ssh().clone();
+            ssh.add_alternative("127.0.0.1", agent.port);
+            SSH_CACHE.set(id, ssh)?;
+            println!(
+                "Installed the agent to {id}. It will be reachable at 127.0.0.1:{} while the tunnel is up.",
+                agent.port
+            );
+            Ok(())
+        }
+        AgentSubCommand::List(_) => {
+            let mut table = Table::with_min_widths(&[32, 15, 4]);
+            for (id, agent) in DUT_AGENTS.entries()? {
+                let status = match SshInfo::new_host_and_port("127.0.0.1", agent.port)?
+                    .probe_tcp_rtt(time::Duration::from_secs(1))
+                {
+                    Ok(_) => "up",
+                    Err(_) => "down",
+                };
+                table.push([
+                    id,
+                    format!("127.0.0.1:{}", agent.port),
+                    status.to_string(),
+                    format!("via {}@{}", agent.user, agent.callback),
+                ]);
+            }
+            for line in table.lines(" ") {
+                println!("{line}");
+            }
+            Ok(())
+        }
+        AgentSubCommand::Uninstall(args) => {
+            let agent = DUT_AGENTS
+                .get(&args.dut)?
+                .context(anyhow!("No agent is installed on {}", args.dut))?;
+            let mut ssh = SshInfo::new(&args.dut)?;
+            uninstall_agent(&ssh, &args.dut)?;
+            if ssh.remove_alternative("127.0.0.1", agent.port) {
+                SSH_CACHE.set(&args.dut, ssh)?;
+            }
+            println!("Uninstalled the agent from {}", args.dut);
+            Ok(())
+        }
+        AgentSubCommand::Watch(args) => watch_agents(time::Duration::from_secs(args.interval)),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// diagnose ssh connection problems step by step
+#[argh(subcommand, name = "diagnose-ssh")]
+struct ArgsDiagnoseSsh {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// print the results as JSON
+    #[argh(switch)]
+    json: bool,
+}
+
+fn run_dut_diagnose_ssh(args: &ArgsDiagnoseSsh) -> Result<()> {
+    let target = &SshInfo::new(&args.dut)?;
+    status!("Diagnosing ssh connection to {}...", target.host_and_port());
+    let results = target.diagnose();
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for r in &results {
+            println!(
+                "[{}] {:12} {}",
+                if r.ok { " OK " } else { "FAIL" },
+                r.step,
+                r.detail
+            );
+            if let Some(suggestion) = &r.suggestion {
+                println!("       {:12} -> {suggestion}", "");
+            }
+        }
+    }
+    if results.iter().all(|r| r.ok) {
+        Ok(())
+    } else {
+        Err(anyhow!("ssh connection to {} has a problem", args.dut))
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// install the testing key (and your key optionally) to a DUT via password auth
+#[argh(subcommand, name = "authorize")]
+struct ArgsAuthorize {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// let ssh prompt the password instead of using the default test image password via sshpass
+    #[argh(switch)]
+    password_prompt: bool,
+
+    /// path to your public key to install as well (e.g. ~/.ssh/id_ed25519.pub)
+    #[argh(option)]
+    user_key: Option<String>,
+}
+
+// Default password of root on test images
+const DEFAULT_TEST_IMAGE_PASSWORD: &str = "test0000";
+
+fn testing_rsa_public_key() -> Result<String> {
+    let testing_rsa_pub = run_bash_command("ssh-keygen -y -f ~/.ssh/testing_rsa", None)?;
+    testing_rsa_pub
+        .status
+        .exit_ok()
+        .context("Failed to get the public key of testing_rsa")?;
+    Ok(get_stdout(&testing_rsa_pub) + " testing_rsa")
+}
+
+fn run_dut_authorize(args: &ArgsAuthorize) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    if target.run_cmd_stdio("true").is_ok() {
+        status!("testing_rsa is already accepted by the DUT");
+        if args.user_key.is_none() {
+            return Ok(());
+        }
+    }
+    let mut keys = vec![testing_rsa_public_key()?];
+    if let Some(user_key) = &args.user_key {
+        // Expand ~ like a shell does, as in the example of --user-key
+        let user_key = if let Some(rest) = user_key.strip_prefix("~/") {
+            format!("{}/{rest}", std::env::var("HOME")?)
+        } else {
+            user_key.clone()
+        };
+        let user_key =
+            read_to_string(&user_key).context(anyhow!("Failed to read --user-key {user_key}"))?;
+        keys.push(user_key.trim().to_string());
+    }
+    let password = if args.password_prompt {
+        None
+    } else {
+        run_bash_command("which sshpass", None)?
+            .status
+            .exit_ok()
+            .context("sshpass is not installed. Please install it or use --password-prompt")?;
+        Some(DEFAULT_TEST_IMAGE_PASSWORD)
+    };
+    status!("Installing {} key(s) to the DUT...", keys.len());
+    target.install_authorized_keys(&keys, password)?;
+    target
+        .run_cmd_stdio("true")
+        .context("Keys are installed but login with testing_rsa still fails")?;
+    status!("Verified: login with testing_rsa works");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// GSC (Cr50 / Ti50) console and CCD management
+#[argh(subcommand, name = "gsc")]
+struct ArgsGsc {
+    /// a DUT identifier (gsctool over ssh) or a servo serial (GSC console over USB)
+    #[argh(option)]
+    dut: String,
+
+    #[argh(subcommand)]
+    nested: GscSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum GscSubCommand {
+    Console(ArgsGscConsole),
+    OpenCcd(ArgsGscOpenCcd),
+    State(ArgsGscState),
+    Testlab(ArgsGscTestlab),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the CCD state
+#[argh(subcommand, name = "state")]
+struct ArgsGscState {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// open CCD (physical presence or rma_auth may be required)
+#[argh(subcommand, name = "open-ccd")]
+struct ArgsGscOpenCcd {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// change the testlab mode (enable, disable or open)
+#[argh(subcommand, name = "testlab")]
+struct ArgsGscTestlab {
+    /// enable, disable or open
+    #[argh(positional)]
+    action: String,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// run a command on the GSC console
+#[argh(subcommand, name = "console")]
+struct ArgsGscConsole {
+    /// command to run (e.g. version)
+    #[argh(positional)]
+    cmd: Vec<String>,
+}
+
+enum GscTarget {
+    Servo(LocalServo),
+    Dut(SshInfo),
+}
+impl GscTarget {
+    fn from_id(id: &str) -> Result<Self> {
+        if let Ok(servo) = ServoList::read()?.find_by_serial(id) {
+            let cr50 = if servo.is_cr50() {
+                servo.clone()
+            } else {
+                get_cr50_attached_to_servo(servo)?
+            };
+            return Ok(Self::Servo(cr50));
+        }
+        cros::ensure_testing_rsa_is_there()?;
+        let ssh = SshInfo::new(id)?;
+        ensure_ec(&ssh, "ectool")?;
+        Ok(Self::Dut(ssh))
+    }
+    fn console_only(verb: &str) -> Result<()> {
+        Err(anyhow!(
+            "{verb} needs the GSC console. Please specify a servo serial with --dut instead (see `lium servo list`)"
+        ))
+    }
+}
+
+fn run_dut_gsc(args: &ArgsGsc) -> Result<()> {
+    let target = GscTarget::from_id(&args.dut)?;
+    match (&args.nested, &target) {
+        (GscSubCommand::State(_), GscTarget::Servo(cr50)) => {
+            println!("{}", cr50.ccd_state()?);
+        }
+        (GscSubCommand::State(_), GscTarget::Dut(ssh)) => {
+            println!("{}", ssh.run_cmd_stdio("gsctool -a -I")?);
+        }
+        (GscSubCommand::OpenCcd(_), GscTarget::Servo(cr50)) => cr50.open_ccd()?,
+        (GscSubCommand::OpenCcd(_), GscTarget::Dut(ssh)) => {
+            eprintln!("Follow the instructions to press the power button when asked.");
+            ssh.run_cmd_piped(&["gsctool -a -o"])?;
+        }
+        (GscSubCommand::Testlab(args), GscTarget::Servo(cr50)) => {
+            if !["enable", "disable", "open"].contains(&args.action.as_str()) {
+                return Err(anyhow!(
+                    "Unknown testlab action: {}. enable, disable or open is expected.",
+                    args.action
+                ));
+            }
+            println!(
+                "{}",
+                cr50.run_cmd("Shell", &format!("ccd testlab {}", args.action))?
+            );
+        }
+        (GscSubCommand::Testlab(_), GscTarget::Dut(_)) => GscTarget::console_only("testlab")?,
+        (GscSubCommand::Console(args), GscTarget::Servo(cr50)) => {
+            println!("{}", cr50.run_cmd("Shell", &args.cmd.join(" "))?);
+        }
+        (GscSubCommand::Console(_), GscTarget::Dut(_)) => GscTarget::console_only("console")?,
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// EC (Embedded Controller) version, flashing and console
+#[argh(subcommand, name = "ec")]
+struct ArgsEc {
+    /// a DUT identifier (ectool over ssh) or a servo serial (flash_ec / EC console via servo)
+    #[argh(option)]
+    dut: String,
+
+    #[argh(subcommand)]
+    nested: EcSubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum EcSubCommand {
+    Console(ArgsEcConsole),
+    Flash(ArgsEcFlash),
+    Reboot(ArgsEcReboot),
+    Version(ArgsEcVersion),
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the EC version
+#[argh(subcommand, name = "version")]
+struct ArgsEcVersion {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// reboot the EC
+#[argh(subcommand, name = "reboot")]
+struct ArgsEcReboot {}
+#[derive(FromArgs, PartialEq, Debug)]
+/// run a command on the EC console (servo), or dump the console buffer (ssh)
+#[argh(subcommand, name = "console")]
+struct ArgsEcConsole {
+    /// command to run (e.g. battery)
+    #[argh(positional)]
+    cmd: Vec<String>,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// flash an EC image
+#[argh(subcommand, name = "flash")]
+struct ArgsEcFlash {
+    /// path to an EC image (ec.bin)
+    #[argh(positional)]
+    image: String,
+
+    /// target cros repo dir (needed to run flash_ec via servo)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// flash even if the board name of the image does not match with the EC
+    #[argh(switch)]
+    force: bool,
+}
+
+lazy_static! {
+    static ref RE_EC_VERSION_BOARD: Regex =
+        Regex::new(r"^(?P<board>[0-9A-Za-z_\-]+?)_v[0-9]+\.[0-9]+\.[0-9]+").unwrap();
+    static ref RE_EC_IMAGE_VERSION: regex::bytes::Regex =
+        regex::bytes::Regex::new(r"(?P<board>[0-9a-z_\-]+?)_v[0-9]+\.[0-9]+\.[0-9]+-[0-9a-f]+")
+            .unwrap();
+}
+
+/// Returns the board name of an EC version string (e.g. "soraka_v2.0.2394-7c0ab4c37" -> "soraka")
+fn ec_board_from_version(version: &str) -> Option<String> {
+    RE_EC_VERSION_BOARD
+        .captures(version.trim())
+        .map(|c| c["board"].to_lowercase())
+}
+
+/// Returns the board name embedded in the version string of an EC image
+fn ec_board_from_image(image: &[u8]) -> Option<String> {
+    RE_EC_IMAGE_VERSION
+        .captures(image)
+        .map(|c| String::from_utf8_lossy(&c["board"]).to_string())
+}
+
+enum EcTarget {
+    Servo(LocalServo),
+    Dut(SshInfo),
+}
+impl EcTarget {
+    fn from_id(id: &str) -> Result<Self> {
+        if let Ok(servo) = ServoList::read()?.find_by_serial(id) {
+            return Ok(Self::Servo(servo.clone()));
+        }
+        cros::ensure_testing_rsa_is_there()?;
+        Ok(Self::Dut(SshInfo::new(id)?))
+    }
+    fn version(&self) -> Result<String> {
+        match self {
+            Self::Servo(servo) => {
+                let cr50 = if servo.is_cr50() {
+                    servo.clone()
+                } else {
+                    get_cr50_attached_to_servo(servo)?
+                };
+                cr50.read_ec_version()
+            }
+            Self::Dut(ssh) => ssh.run_cmd_stdio(
+                "ectool version | grep 'RO version:' | sed -E 's/^RO version:\\s*//'",
+            ),
+        }
+    }
+    fn console(&self) -> Result<LocalServo> {
+        match self {
+            Self::Servo(servo) if servo.is_cr50() => Ok(servo.clone()),
+            Self::Servo(servo) => get_cr50_attached_to_servo(servo),
+            Self::Dut(_) => Err(anyhow!("EC console is only available via servo")),
+        }
+    }
+}
+
+fn run_dut_ec(args: &ArgsEc) -> Result<()> {
+    let target = EcTarget::from_id(&args.dut)?;
+    match &args.nested {
+        EcSubCommand::Version(_) => println!("{}", target.version()?),
+        EcSubCommand::Reboot(_) => match &target {
+            EcTarget::Dut(ssh) => ssh.run_cmd_piped(&["ectool reboot_ec cold; exit"])?,
+            EcTarget::Servo(_) => println!("{}", target.console()?.run_cmd("EC", "reboot")?),
+        },
+        EcSubCommand::Console(args) => match &target {
+            EcTarget::Dut(ssh) if args.cmd.is_empty() => {
+                println!("{}", ssh.run_cmd_stdio("ectool console")?)
+            }
+            EcTarget::Dut(_) => {
+                return Err(anyhow!(
+                    "EC console commands need servo. Please specify a servo serial with --dut"
+                ));
+            }
+            EcTarget::Servo(_) => {
+                println!("{}", target.console()?.run_cmd("EC", &args.cmd.join(" "))?)
+            }
+        },
+        EcSubCommand::Flash(args) => {
+            let image =
+                std::fs::read(&args.image).context(anyhow!("Failed to read {}", args.image))?;
+            let image_board = ec_board_from_image(&image)
+                .context("Failed to find the board name in the image")?;
+            let ec_board = ec_board_from_version(&target.version()?)
+                .context("Failed to get the board name of the EC")?;
+            if image_board != ec_board {
+                if args.force {
+                    warning!("flashing {image_board} image to {ec_board} EC");
+                } else {
+                    return Err(anyhow!(
+                        "The image is for {image_board} but the EC is {ec_board}. Use --force to flash anyway."
+                    ));
+                }
+            }
+            match &target {
+                EcTarget::Dut(ssh) => {
+                    ssh.send_files(&[args.image.clone()], Some(&"/tmp/ec.bin".to_string()))?;
+                    ssh.run_privileged_cmd_piped(
+                        "write the EC firmware",
+                        "flashrom -p ec -w /tmp/ec.bin",
+                    )?;
+                }
+                EcTarget::Servo(servo) => {
+                    let servo = if servo.is_servo() {
+                        servo.clone()
+                    } else {
+                        get_servo_attached_to_cr50(servo)?
+                    };
+                    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+                    // ~/.lium is mounted at /lium in the chroot
+                    std::fs::write(gen_path_in_lium_dir("tmp/ec.bin")?, &image)?;
+                    chroot.run_bash_script_in_chroot(
+                        "flash_ec",
+                        &format!(
+                            "~/trunk/src/platform/ec/util/flash_ec --board={ec_board} --image=/lium/tmp/ec.bin --servo={}",
+                            servo.serial()
+                        ),
+                        None,
+                    )?;
+                }
+            }
+            status!("EC is flashed. New version: {}", target.version()?);
+        }
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// print (or write) OpenSSH Host entries for all cached DUTs
+#[argh(subcommand, name = "ssh-config")]
+struct ArgsSshConfig {
+    /// write to this file instead of stdout. the file is kept in sync by `dut list --update`.
+    /// add `Include <file>` to ~/.ssh/config to use DUTs by name
+    #[argh(option)]
+    out: Option<String>,
+}
+fn run_dut_ssh_config(args: &ArgsSshConfig) -> Result<()> {
+    if let Some(out) = &args.out {
+        let out = if let Some(rest) = out.strip_prefix("~/") {
+            format!("{}/{rest}", std::env::var("HOME")?)
+        } else {
+            out.clone()
+        };
+        if let Some(dir) = std::path::Path::new(&out).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        export_ssh_config(&out)?;
+        status!("Wrote {out}. Add `Include {out}` to the top of ~/.ssh/config to use it.");
+    } else {
+        print!("{}", render_ssh_config()?);
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// open a VSCode remote session on a DUT
+#[argh(subcommand, name = "code")]
+struct ArgsCode {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// path on the DUT to open (default: /usr/local/src)
+    #[argh(option, default = "String::from(\"/usr/local/src\")")]
+    path: String,
+
+    /// mount the path with sshfs and open it locally instead of using the Remote-SSH extension
+    #[argh(switch)]
+    sshfs: bool,
+}
+
+/// Remote-SSH installs its server to ~/.vscode-server, but /root is on the (possibly read-only)
+/// rootfs, so redirect it to the stateful partition.
+const CMD_PREPARE_VSCODE_SERVER: &str = r#"
+for c in bash tar; do command -v $c >/dev/null || { echo "$c is missing" >&2; exit 1; }; done
+mkdir -p /usr/local/vscode-server
+[ -L /root/.vscode-server ] || ln -sfn /usr/local/vscode-server /root/.vscode-server
+"#;
+
+fn run_dut_code(args: &ArgsCode) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let info = DutInfo::new(&args.dut)?;
+    let ssh = info.ssh();
+    let path = &args.path;
+    ssh.run_cmd_stdio(&format!("mkdir -p {path}"))?;
+    let remote_ready = !args.sshfs
+        && ssh
+            .run_cmd_stdio(CMD_PREPARE_VSCODE_SERVER)
+            .map_err(|e| warning!("Failed to prepare the DUT for Remote-SSH: {e:#}"))
+            .is_ok();
+    if !remote_ready {
+        status!("Mounting {path} on the DUT with sshfs...");
+        let mountpoint = mount_sshfs(ssh, info.id(), path)?;
+        return Command::new("code")
+            .arg(&mountpoint)
+            .status()
+            .context("Failed to launch code")?
+            .exit_ok()
+            .context("code failed");
+    }
+
+    let config_path = if let Some(path) = exported_ssh_config_path()? {
+        update_exported_ssh_config()?;
+        path
+    } else {
+        let path = format!("{}/.ssh/config.d/lium", std::env::var("HOME")?);
+        std::fs::create_dir_all(format!("{}/.ssh/config.d", std::env::var("HOME")?))?;
+        export_ssh_config(&path)?;
+        path
+    };
+    let user_config =
+        read_to_string(format!("{}/.ssh/config", std::env::var("HOME")?)).unwrap_or_default();
+    if !user_config.contains(&config_path) {
+        warning!(
+            "Please add `Include {config_path}` to the top of ~/.ssh/config so that VSCode can find the DUT"
+        );
+    }
+    let alias = ssh_config_alias(info.id());
+    Command::new("code")
+        .args(["--remote", &format!("ssh-remote+{alias}"), path])
+        .status()
+        .context("Failed to launch code")?
+        .exit_ok()
+        .context("code failed")
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// attach gdb to a process on the DUT via gdbserver, with symbols from the local build
+#[argh(subcommand, name = "gdb")]
+struct ArgsGdb {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// pid or process name to attach (the oldest one is used if there are multiple)
+    #[argh(option)]
+    attach: String,
+
+    /// target cros repo dir (the build sysroot in its chroot is used for symbols)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// local port to forward to gdbserver (default: 2345)
+    #[argh(option, default = "2345")]
+    port: u16,
+}
+
+fn run_dut_gdb(args: &ArgsGdb) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    let board = target.get_board()?;
+    if target.run_cmd_stdio("command -v gdbserver").is_err() {
+        return Err(anyhow!(
+            "gdbserver is not found on the DUT. Please use a test image or `cros deploy` dev-util/gdb"
+        ));
+    }
+    let pid: u32 = if let Ok(pid) = args.attach.parse() {
+        pid
+    } else {
+        target
+            .run_cmd_stdio(&format!("pgrep -o -x {}", args.attach))
+            .context(anyhow!("Process {} is not running on the DUT", args.attach))?
+            .trim()
+            .parse()?
+    };
+    let exe = target.run_cmd_stdio(&format!("readlink /proc/{pid}/exe"))?;
+    let exe = exe.trim();
+    status!("Attaching to {exe} (pid {pid})...");
+    // ARM boards may run 32-bit userland on a 64-bit kernel, so check EI_CLASS of the binary
+    let is_32bit = target
+        .run_cmd_stdio(&format!(
+            "head -c 5 /proc/{pid}/exe | tail -c 1 | od -An -tu1"
+        ))?
+        .trim()
+        == "1";
+    let arch = match target.get_arch()?.as_str() {
+        "arm64" if is_32bit => "armv7l".to_string(),
+        arch => arch.to_string(),
+    };
+    let gdb = Toolchain::for_arch(&arch)?.gdb();
+
+    // gdb in the chroot can access the host network, so just forward the port
+    let dut_port = 2345;
+    let mut child = target.start_port_forwarding(
+        args.port,
+        dut_port,
+        &format!("gdbserver --attach 127.0.0.1:{dut_port} {pid}"),
+    )?;
+    thread::sleep(time::Duration::from_secs(2));
+    if let Some(status) = child.try_status()? {
+        return Err(anyhow!("gdbserver exited unexpectedly: {status}"));
+    }
+
+    let sysroot = format!("/build/{board}");
+    std::fs::write(
+        gen_path_in_lium_dir("tmp/gdbinit")?,
+        format!(
+            r#"set sysroot {sysroot}
+set debug-file-directory {sysroot}/usr/lib/debug
+file {sysroot}{exe}
+target remote localhost:{}
+"#,
+            args.port
+        ),
+    )?;
+    chroot.open_chroot(&[
+        "--".to_string(),
+        gdb,
+        "-x".to_string(),
+        "/lium/tmp/gdbinit".to_string(),
+    ])?;
+    drop(child);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// pull a crash dump from the DUT and print a symbolized backtrace
+#[argh(subcommand, name = "coredump")]
+struct ArgsCoredump {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// use the latest dump (default)
+    #[argh(switch)]
+    latest: bool,
+
+    /// use the dump of the process with this pid
+    #[argh(option)]
+    pid: Option<u32>,
+
+    /// list dumps on the DUT and exit
+    #[argh(switch)]
+    list: bool,
+
+    /// target cros repo dir (the build sysroot in its chroot is used for symbols)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// use symbols of the exact build on the DUT from GS instead of the build sysroot
+    #[argh(switch)]
+    symbols: bool,
+}
+
+/// Lists crash dumps (core files and minidumps) on the DUT, newest first
+const CMD_LIST_CRASH_DUMPS: &str =
+    "ls -t /var/spool/crash/*.core /var/spool/crash/*.dmp /home/chronos/crash/*.dmp /home/chronos/u-*/crash/*.dmp 2>/dev/null";
+
+/// Extracts paths of mapped files (NT_FILE) from the output of `readelf -n <core>`
+fn parse_core_mapped_files(readelf: &str) -> Vec<String> {
+    let mut files: Vec<String> = Vec::new();
+    for line in readelf.lines().map(|l| l.trim()) {
+        if line.starts_with('/') && !files.iter().any(|f| f == line) {
+            files.push(line.to_string());
+        }
+    }
+    files
+}
+
+fn run_dut_coredump(args: &ArgsCoredump) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let dumps = target
+        .run_cmd_stdio(CMD_LIST_CRASH_DUMPS)
+        .unwrap_or_default();
+    let dumps: Vec<&str> = dumps.lines().collect();
+    if args.list {
+        for d in dumps {
+            println!("{d}");
+        }
+        return Ok(());
+    }
+    if args.latest && args.pid.is_some() {
+        return Err(anyhow!("--latest and --pid are exclusive"));
+    }
+    let dump = if let Some(pid) = args.pid {
+        // e.g. /var/spool/crash/foo.20230101.123456.12345.0.core
+        dumps
+            .iter()
+            .find(|d| d.contains(&format!(".{pid}.")))
+            .context(anyhow!("No dump found for pid {pid}"))?
+    } else {
+        dumps.first().context("No dumps found on the DUT")?
+    };
+    let name = dump.rsplit('/').next().unwrap_or(dump);
+    let dir_in_lium = format!("coredump/{}/{name}", target.host_and_port());
+    let dir = gen_path_in_lium_dir(&format!("{dir_in_lium}/.keep"))?;
+    let dir = dir
+        .parent()
+        .context("Failed to create a dir")?
+        .to_string_lossy()
+        .to_string();
+    status!("Pulling {dump} to {dir}...");
+    target.get_files(&[dump.to_string()], Some(&dir))?;
+    let local_dump = format!("{dir}/{name}");
+    let dump_in_chroot = format!("/lium/{dir_in_lium}/{name}");
+
+    let chroot = Chroot::new(&get_repo_dir(&args.repo)?)?;
+    let board = target.get_board()?;
+    let is_minidump = name.ends_with(".dmp");
+    let symbol_dir = if args.symbols {
+        let kind = if is_minidump {
+            SymbolKind::Breakpad
+        } else {
+            SymbolKind::Debug
+        };
+        let dir = fetch_symbols(&builder_path_of_dut(target)?, kind)?;
+        format!("/lium/{}", dir.strip_prefix(lium_dir()?)?.to_string_lossy())
+    } else if is_minidump {
+        format!("/build/{board}/usr/lib/debug/breakpad")
+    } else {
+        format!("/build/{board}/usr/lib/debug")
+    };
+    let report = if is_minidump {
+        chroot.exec_in_chroot(&["minidump_stackwalk", &dump_in_chroot, &symbol_dir])?
+    } else {
+        let readelf = get_stdout(&run_bash_command(
+            &format!("readelf -n '{local_dump}'"),
+            None,
+        )?);
+        let files = parse_core_mapped_files(&readelf);
+        let exe = files
+            .first()
+            .context("No mapped files found in the core")?
+            .clone();
+        // Pull the exact binaries mapped by the process to match the core
+        status!("Pulling {} mapped files...", files.len());
+        for f in &files {
+            let dst = format!("{dir}/sysroot{f}");
+            if let Some(parent) = std::path::Path::new(&dst).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            if let Err(e) = target.get_files(&[f.clone()], Some(&dst)) {
+                warning!("Failed to pull {f}: {e:#}");
+            }
+        }
+        let is_32bit = std::fs::read(&local_dump)?.get(4) == Some(&1);
+        let arch = match target.get_arch()?.as_str() {
+            "arm64" if is_32bit => "armv7l".to_string(),
+            arch => arch.to_string(),
+        };
+        let sysroot_in_chroot = format!("/lium/{dir_in_lium}/sysroot");
+        chroot.exec_in_chroot(&[
+            &Toolchain::for_arch(&arch)?.gdb(),
+            "-batch",
+            "-ex",
+            &format!("set sysroot {sysroot_in_chroot}"),
+            "-ex",
+            &format!("set debug-file-directory {symbol_dir}"),
+            "-ex",
+            &format!("file {sysroot_in_chroot}{exe}"),
+            "-ex",
+            &format!("core-file {dump_in_chroot}"),
+            "-ex",
+            "info sharedlibrary",
+            "-ex",
+            "thread apply all bt full",
+        ])?
+    };
+    let report_path = format!("{dir}/report.txt");
+    std::fs::write(&report_path, &report)?;
+    println!("{report}");
+    status!("Report is saved to {report_path}");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run strace on the DUT and summarize syscalls by count and latency
+#[argh(subcommand, name = "strace")]
+struct ArgsStrace {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// a pid to attach to, or a command to run under strace
+    #[argh(option)]
+    target: String,
+
+    /// seconds to trace
+    #[argh(option, default = "10")]
+    duration: u64,
+
+    /// local strace binary to push if the DUT does not have one.
+    /// the one in the board sysroot of the chroot is used if omitted.
+    #[argh(option)]
+    strace_bin: Option<String>,
+
+    /// target cros repo dir (used to find strace in the board sysroot)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// number of syscalls to show in the summary
+    #[argh(option, default = "20")]
+    top: usize,
+}
+
+lazy_static! {
+    // e.g. 1234  12:34:56.789012 openat(AT_FDCWD, "/etc", O_RDONLY) = -1 ENOENT (No such file) <0.000012>
+    //      1234  12:34:56.789012 <... read resumed>"abc", 3) = 3 <0.100000>
+    static ref RE_STRACE_LINE: Regex = Regex::new(
+        r"^(?:\d+\s+)?[\d:.]+\s+(?:<\.\.\. (?P<resumed>\w+) resumed>|(?P<name>\w+)\().*\)\s+=\s+(?P<ret>\S+)(?:\s+(?P<errno>E[A-Z0-9]+))?.*<(?P<time>[\d.]+)>$"
+    )
+    .unwrap();
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct SyscallStats {
+    count: u64,
+    errors: u64,
+    total_secs: f64,
+    max_secs: f64,
+}
+
+/// Aggregates an strace log taken with -tt -T per syscall, sorted by the total time spent
+fn summarize_strace(log: &str) -> Vec<(String, SyscallStats)> {
+    let mut stats: HashMap<String, SyscallStats> = HashMap::new();
+    for c in log
+        .lines()
+        .filter_map(|l| RE_STRACE_LINE.captures(l.trim()))
+    {
+        let name = c
+            .name("resumed")
+            .or_else(|| c.name("name"))
+            .map(|m| m.as_str().to_string())
+            .unwrap_or_default();
+        let secs: f64 = c["time"].parse().unwrap_or_default();
+        let s = stats.entry(name).or_default();
+        s.count += 1;
+        s.errors += c.name("errno").is_some() as u64;
+        s.total_secs += secs;
+        s.max_secs = s.max_secs.max(secs);
+    }
+    let mut stats: Vec<(String, SyscallStats)> = stats.into_iter().collect();
+    stats.sort_by(|a, b| b.1.total_secs.total_cmp(&a.1.total_secs));
+    stats
+}
+
+fn ensure_strace_on_dut(target: &SshInfo, args: &ArgsStrace) -> Result<()> {
+    if target.run_cmd_stdio("which strace").is_ok() {
+        return Ok(());
+    }
+    let local = if let Some(path) = &args.strace_bin {
+        path.clone()
+    } else {
+        let repo = get_repo_dir(&args.repo)?;
+        let board = target.get_board()?;
+        let sysroot =
+            board_sysroot_on_host(&repo, &board).unwrap_or(format!("{repo}/out/build/{board}"));
+        format!("{sysroot}/usr/bin/strace")
+    };
+    if !std::path::Path::new(&local).exists() {
+        return Err(anyhow!(
+            "strace is not on the DUT and {local} does not exist. Please build it (emerge-<board> strace) or specify --strace-bin"
+        ));
+    }
+    status!("Pushing {local} to the DUT...");
+    target.send_files_audited(&[local], Some(&"/usr/local/bin/strace".to_string()))?;
+    target.run_cmd_stdio("chmod +x /usr/local/bin/strace")?;
+    Ok(())
+}
+
+fn run_dut_strace(args: &ArgsStrace) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    ensure_strace_on_dut(target, args)?;
+    let remote_log = "/tmp/lium_strace.log";
+    let traced = if args.target.parse::<u32>().is_ok() {
+        format!("-p {}", args.target)
+    } else {
+        format!("-- sh -c '{}'", args.target.replace('\'', "'\\''"))
+    };
+    status!("Tracing {} for {}s...", args.target, args.duration);
+    // strace detaches from the target on SIGINT; a non-zero exit is expected here
+    drop(target.run_cmd_piped(&[format!(
+        "timeout -s INT {} strace -f -tt -T -o {remote_log} {traced}",
+        args.duration
+    )]));
+    let dir = gen_path_in_lium_dir(&format!(
+        "strace/{}/{}/.keep",
+        target.host_and_port(),
+        Local::now().format("%Y%m%d_%H%M%S")
+    ))?;
+    let dir = dir
+        .parent()
+        .context("Failed to create a dir")?
+        .to_string_lossy()
+        .to_string();
+    target.get_files(&[remote_log.to_string()], Some(&dir))?;
+    let log_path = format!("{dir}/lium_strace.log");
+    let log = read_to_string(&log_path)?;
+    let stats = summarize_strace(&log);
+    println!(
+        "{:20} {:>8} {:>8} {:>12} {:>12} {:>12}",
+        "syscall", "calls", "errors", "total(s)", "avg(us)", "max(us)"
+    );
+    for (name, s) in stats.iter().take(args.top) {
+        println!(
+            "{:20} {:>8} {:>8} {:>12.6} {:>12.1} {:>12.1}",
+            name,
+            s.count,
+            s.errors,
+            s.total_secs,
+            s.total_secs * 1e6 / s.count as f64,
+            s.max_secs * 1e6
+        );
+    }
+    status!("Raw trace is saved to {log_path}");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// boot the DUT into recovery via servo and install a recovery image from the servo USB key
+#[argh(subcommand, name = "recover")]
+struct ArgsRecover {
+    /// a servo (or GSC) serial attached to the DUT. see `lium servo list`
+    #[argh(option)]
+    dut: String,
+
+    /// how to drive the DUT. only "servo" is supported for now.
+    #[argh(option, default = "String::from(\"servo\")")]
+    via: String,
+
+    /// a recovery image to write to the servo USB key (a local path, or an xBuddy path for
+    /// cros flash), or "usb" to use the image already on it
+    #[argh(option)]
+    image: String,
+
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// seconds to wait for the recovery to finish (default: 1800)
+    #[argh(option, default = "1800")]
+    timeout: u64,
+
+    /// a regex for the AP console line that indicates the recovery is done
+    #[argh(
+        option,
+        default = "String::from(\"(?i)recovery (is )?complete|installation complete\")"
+    )]
+    done_pattern: String,
+
+    /// a regex for the AP console line that indicates the recovery failed
+    #[argh(
+        option,
+        default = "String::from(\"(?i)recovery (has )?failed|installation failed\")"
+    )]
+    fail_pattern: String,
+}
+
+/// Streams lines from a console tty through a channel
+fn spawn_console_reader(tty: &str) -> Result<std::sync::mpsc::Receiver<String>> {
+    run_bash_command(&format!("stty -F {tty} 115200 raw -echo"), None)?
+        .status
+        .exit_ok()
+        .context(anyhow!("Failed to configure {tty}"))?;
+    let file = std::fs::File::open(tty).context(anyhow!("Failed to open {tty}"))?;
+    let (tx, rx) = std::sync::mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(file).split(b'\n').flatten() {
+            if tx
+                .send(String::from_utf8_lossy(&line).trim().to_string())
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    Ok(rx)
+}
+
+fn run_dut_recover(args: &ArgsRecover) -> Result<()> {
+    if args.via != "servo" {
+        return Err(anyhow!("--via {} is not supported", args.via));
+    }
+    let done = Regex::new(&args.done_pattern).context("Invalid --done-pattern")?;
+    let fail = Regex::new(&args.fail_pattern).context("Invalid --fail-pattern")?;
+    let servo = ServoList::read()?.find_by_serial(&args.dut)?.clone();
+    let (servo, gsc) = if servo.is_cr50() {
+        (get_servo_attached_to_cr50(&servo)?, servo)
+    } else {
+        let gsc = get_cr50_attached_to_servo(&servo)?;
+        (servo, gsc)
+    };
+    let repo = get_repo_dir(&args.repo)?;
+    let chroot = Chroot::new(&repo)?;
+    let servod =
+        ServodConnection::from_serial(servo.serial()).or_else(|_| servo.start_servod(&chroot))?;
+    let dut_control = |control: &str| -> Result<String> {
+        status!("dut-control {control}");
+        servod.run_dut_control(&chroot, &[control])
+    };
+
+    dut_control("power_state:off")?;
+    if args.image != "usb" {
+        dut_control("image_usbkey_direction:servo_sees_usbkey")?;
+        let dev = dut_control("image_usbkey_dev")?;
+        let dev = dev
+            .trim()
+            .rsplit(':')
+            .next()
+            .context("Failed to get the USB key device")?
+            .to_string();
+        status!("Writing {} to {dev}...", args.image);
+        if std::path::Path::new(&args.image).is_file() {
+            run_bash_command(
+                &format!(
+                    "sudo dd if='{}' of={dev} bs=4M oflag=sync status=progress",
+                    args.image
+                ),
+                None,
+            )?
+            .status
+            .exit_ok()
+            .context("Failed to write the image to the USB key")?;
+        } else {
+            chroot.run_bash_script_in_chroot(
+                "recover_usb",
+                &format!("cros flash usb://{dev} '{}'", args.image),
+                None,
+            )?;
+        }
+    }
+    dut_control("image_usbkey_direction:dut_sees_usbkey")?;
+    let console = spawn_console_reader(&gsc.tty_path("AP")?)?;
+    dut_control("power_state:rec")?;
+
+    status!("Waiting for the recovery to finish...");
+    let deadline = time::Instant::now() + time::Duration::from_secs(args.timeout);
+    while let Some(left) = deadline.checked_duration_since(time::Instant::now()) {
+        match console.recv_timeout(left) {
+            Ok(line) => {
+                println!("{line}");
+                if fail.is_match(&line) {
+                    return Err(anyhow!("Recovery failed: {line}"));
+                }
+                if done.is_match(&line) {
+                    dut_control("image_usbkey_direction:servo_sees_usbkey")?;
+                    println!("Recovery finished. The DUT will reboot into the installed image.");
+                    return Ok(());
+                }
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => break,
+            Err(e) => return Err(anyhow!("AP console was closed: {e}")),
+        }
+    }
+    Err(anyhow!(
+        "Recovery did not finish within {} sec",
+        args.timeout
+    ))
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// powerwash the DUT and wait until it is usable again
+#[argh(subcommand, name = "powerwash")]
+struct ArgsPowerwash {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// stay in dev mode. otherwise, the DUT leaves dev mode and will not be reachable via SSH.
+    #[argh(switch)]
+    keep_dev_mode: bool,
+
+    /// seconds to wait for the DUT to come back (default: 900)
+    #[argh(option, default = "900")]
+    timeout: u64,
+
+    /// do not run autologin after the powerwash
+    #[argh(switch)]
+    no_autologin: bool,
+}
+
+fn run_dut_powerwash(args: &ArgsPowerwash) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let mut journal = Journal::start(Some(&args.dut))?;
+    let boot_id = journal.value("boot_id", || target.get_boot_id())?;
+    if !args.keep_dev_mode {
+        journal.step("request leaving dev mode", || {
+            target.run_privileged_cmd_piped(
+                "request leaving the developer mode",
+                "crossystem disable_dev_request=1",
+            )
+        })?;
+    }
+    journal.step("reboot to powerwash", || {
+        // keepimg keeps the rootfs images so that the test image stays usable
+        target.run_cmd_stdio(
+            "echo 'fast safe keepimg' > /mnt/stateful_partition/factory_install_reset",
+        )?;
+        status!("Rebooting {} to powerwash...", args.dut);
+        // ssh may exit with an error since the connection is closed by the reboot
+        drop(target.run_cmd_piped(&["reboot; exit"]));
+        Ok(())
+    })?;
+    if !args.keep_dev_mode {
+        journal.finish()?;
+        println!(
+            "Powerwash started. The DUT will leave dev mode and will not be reachable via SSH."
+        );
+        return Ok(());
+    }
+
+    journal.step("wait for the reboot", || {
+        let timeout = time::Duration::from_secs(args.timeout);
+        match target.wait_for_new_boot_id(&boot_id, timeout) {
+            Ok(_) => {}
+            Err(e) => {
+                // The keys may have been wiped. Try the default password of test images.
+                warning!("{e:#}. Trying to re-authorize testing_rsa...");
+                target.install_authorized_keys(
+                    &[testing_rsa_public_key()?],
+                    Some(DEFAULT_TEST_IMAGE_PASSWORD),
+                )?;
+                if target.get_boot_id()? == boot_id {
+                    return Err(anyhow!("The DUT did not reboot"));
+                }
+            }
+        }
+        Ok(())
+    })?;
+    journal.step("verify", || {
+        status!("The DUT is back. Verifying...");
+        let vaults =
+            target.run_cmd_stdio("ls /home/.shadow | grep -c '^[0-9a-f]\\{40\\}$' || true")?;
+        if vaults != "0" {
+            return Err(anyhow!("{vaults} user vaults remain after the powerwash"));
+        }
+        if target.run_cmd_stdio("crossystem devsw_boot")? != "1" {
+            return Err(anyhow!("The DUT is not in dev mode after the powerwash"));
+        }
+        Ok(())
+    })?;
+    if !args.no_autologin {
+        journal.step("autologin", || {
+            target
+                .run_autologin()
+                .context("autologin failed (/usr/local may have been wiped; consider re-flashing)")
+        })?;
+    }
+    // Files on the stateful partition are wiped, so forget them
+    let key = target.host_and_port();
+    if let Some(mut log) = DUT_PUSH_AUDIT_LOG.get(&key)? {
+        log.retain(|f| {
+            !["/usr/local/", "/home/", "/var/", "/mnt/stateful_partition/"]
+                .iter()
+                .any(|d| f.path.starts_with(d))
+        });
+        DUT_PUSH_AUDIT_LOG.set(&key, log)?;
+    }
+    journal.finish()?;
+    println!("{} is powerwashed and ready", args.dut);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// clean up the DUT before returning it (pushed files, test accounts, crash dumps)
+#[argh(subcommand, name = "sanitize")]
+struct ArgsSanitize {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// keep user accounts (cryptohomes) on the DUT
+    #[argh(switch)]
+    keep_accounts: bool,
+
+    /// powerwash the DUT at the end (this also wipes the test image stateful partition)
+    #[argh(switch)]
+    powerwash: bool,
+
+    /// print what would be done without doing it
+    #[argh(switch)]
+    dry_run: bool,
+}
+
+/// Returns shell commands to undo the pushes: restoring backups or removing the files
+fn undo_pushes_cmds(log: &[PushedFile]) -> Vec<String> {
+    // Undo in the reverse order in case of nested paths
+    log.iter()
+        .rev()
+        .map(|f| match &f.backup {
+            Some(backup) => format!("mv -f '{backup}' '{}'", f.path),
+            None => format!("rm -f '{}'", f.path),
+        })
+        .collect()
+}
+
+const CMD_CLEAR_CRASH_SPOOLS: &str =
+    "rm -rf /var/spool/crash/* /home/chronos/crash/* /home/chronos/u-*/crash/* /home/root/*/crash/*";
+const CMD_CLEAR_ACCOUNTS: &str = "stop ui; cryptohome --action=unmount; \
+    rm -rf /home/.shadow/[0-9a-f]*; rm -f '/home/chronos/Local State'; start ui";
+const CMD_POWERWASH: &str =
+    "echo 'fast safe' > /mnt/stateful_partition/factory_install_reset && reboot";
+
+fn run_dut_sanitize(args: &ArgsSanitize) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let key = target.host_and_port();
+    let log = DUT_PUSH_AUDIT_LOG.get(&key)?.unwrap_or_default();
+    let mut steps: Vec<(String, String)> = undo_pushes_cmds(&log)
+        .into_iter()
+        .map(|cmd| ("Undo a push".to_string(), cmd))
+        .collect();
+    steps.push((
+        "Clear crash spools".to_string(),
+        CMD_CLEAR_CRASH_SPOOLS.to_string(),
+    ));
+    if !args.keep_accounts {
+        steps.push((
+            "Clear user accounts".to_string(),
+            CMD_CLEAR_ACCOUNTS.to_string(),
+        ));
+    }
+    if args.powerwash {
+        steps.push(("Powerwash".to_string(), CMD_POWERWASH.to_string()));
+    }
+    if args.dry_run {
+        for (what, cmd) in &steps {
+            println!("{what}: {cmd}");
+        }
+        return Ok(());
+    }
+    let mut journal = Journal::start(Some(&args.dut))?;
+    let mut failures = 0;
+    for (i, (what, cmd)) in steps.iter().enumerate() {
+        let result = journal.step(&format!("{i}: {what}"), || {
+            println!("{what}: {cmd}");
+            target.run_cmd_stdio(cmd).map(|_| ())
+        });
+        if let Err(e) = result {
+            eprintln!("Failed: {e:#}");
+            failures += 1;
+        }
+    }
+    if failures > 0 {
+        return Err(anyhow!(
+            "{failures} steps failed. The audit log is kept. Run `lium resume {}` to retry them",
+            journal.id()
+        ));
+    }
+    DUT_PUSH_AUDIT_LOG.remove(&key)?;
+    journal.finish()?;
+    println!("{} is sanitized", args.dut);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// check the security posture of the DUT and report deviations
+#[argh(subcommand, name = "securitycheck")]
+struct ArgsSecurityCheck {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// expected dev mode state (true or false). not checked if omitted.
+    #[argh(option)]
+    expect_dev_mode: Option<bool>,
+
+    /// expect rootfs verification to be removed (e.g. for DUTs used with cros deploy)
+    #[argh(switch)]
+    allow_rootfs_rw: bool,
+
+    /// a file with additional public keys allowed in authorized_keys.
+    /// the testing key and ~/.ssh/*.pub are always allowed.
+    #[argh(option)]
+    allowed_keys: Option<String>,
+}
+
+/// Flags returned by org.chromium.debugd.QueryDevFeatures
+const DEBUGD_DEV_FEATURES: &[(u32, &str)] = &[
+    (1 << 1, "rootfs verification removed"),
+    (1 << 2, "boot from USB enabled"),
+    (1 << 3, "SSH server configured"),
+    (1 << 4, "dev mode root password set"),
+    (1 << 5, "system root password set"),
+    (1 << 6, "Chrome remote debugging enabled"),
+];
+
+/// Returns the names of the enabled dev features in the bitmask from debugd
+fn enabled_dev_features(flags: u32) -> Vec<&'static str> {
+    // Bit 0 means that dev features cannot be queried (e.g. not in dev mode)
+    if flags & 1 != 0 {
+        return Vec::new();
+    }
+    DEBUGD_DEV_FEATURES
+        .iter()
+        .filter(|(bit, _)| flags & bit != 0)
+        .map(|(_, name)| *name)
+        .collect()
+}
+
+/// Returns lines of authorized_keys whose key blobs are not in `allowed`
+fn unexpected_authorized_keys<'a>(authorized_keys: &'a str, allowed: &str) -> Vec<&'a str> {
+    // The key blob is the field after the key type, options may precede them
+    let blob = |line: &str| -> Option<String> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let i = fields.iter().position(|f| {
+            f.starts_with("ssh-") || f.starts_with("ecdsa-") || f.starts_with("sk-")
+        })?;
+        fields.get(i + 1).map(|s| s.to_string())
+    };
+    let allowed: Vec<String> = allowed.lines().filter_map(blob).collect();
+    authorized_keys
+        .lines()
+        .map(|l| l.trim())
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .filter(|l| blob(l).map(|b| !allowed.contains(&b)).unwrap_or(true))
+        .collect()
+}
+
+fn allowed_public_keys(args: &ArgsSecurityCheck) -> Result<String> {
+    let mut keys = get_stdout(&run_bash_command(
+        "ssh-keygen -y -f ~/.ssh/testing_rsa; cat ~/.ssh/*.pub 2>/dev/null",
+        None,
+    )?);
+    if let Some(path) = &args.allowed_keys {
+        keys += "\n";
+        keys += &read_to_string(path).context(anyhow!("Failed to read {path}"))?;
+    }
+    Ok(keys)
+}
+
+fn run_dut_security_check(args: &ArgsSecurityCheck) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let mut results: Vec<(&str, bool, String)> = Vec::new();
+
+    let rootdev = target.run_cmd_stdio("rootdev")?;
+    let verified = rootdev.starts_with("/dev/dm-");
+    results.push((
+        "rootfs verification",
+        verified || args.allow_rootfs_rw,
+        if verified {
+            "enabled".to_string()
+        } else {
+            format!("removed (rootdev: {rootdev})")
+        },
+    ));
+
+    let dev_mode = target.run_cmd_stdio("crossystem devsw_boot")? == "1";
+    results.push((
+        "dev mode",
+        args.expect_dev_mode.map(|e| e == dev_mode).unwrap_or(true),
+        if dev_mode { "on" } else { "off" }.to_string(),
+    ));
+
+    let authorized_keys = target
+        .run_cmd_stdio(
+            "cat /root/.ssh/authorized_keys /home/chronos/.ssh/authorized_keys 2>/dev/null",
+        )
+        .unwrap_or_default();
+    let allowed = allowed_public_keys(args)?;
+    let unexpected = unexpected_authorized_keys(&authorized_keys, &allowed);
+    results.push((
+        "authorized_keys",
+        unexpected.is_empty(),
+        if unexpected.is_empty() {
+            "only expected keys".to_string()
+        } else {
+            format!("unexpected keys:\n    {}", unexpected.join("\n    "))
+        },
+    ));
+
+    for iptables in ["iptables", "ip6tables"] {
+        let policy = target
+            .run_cmd_stdio(&format!("{iptables} -S INPUT | head -n 1"))
+            .unwrap_or_default();
+        results.push((
+            iptables,
+            policy == "-P INPUT DROP",
+            format!("INPUT policy: {policy}"),
+        ));
+    }
+
+    let flags = target.run_cmd_stdio(
+        "dbus-send --system --print-reply --dest=org.chromium.debugd /org/chromium/debugd org.chromium.debugd.QueryDevFeatures | awk '/uint32/ {print $2}'",
+    );
+    match flags.map(|f| f.parse::<u32>()) {
+        Ok(Ok(flags)) => {
+            let features = enabled_dev_features(flags);
+            results.push((
+                "debugd dev features",
+                features.is_empty() || args.expect_dev_mode != Some(false),
+                if features.is_empty() {
+                    "none enabled".to_string()
+                } else {
+                    features.join(", ")
+                },
+            ));
+        }
+        _ => results.push(("debugd dev features", false, "failed to query".to_string())),
+    }
+
+    let mut deviations = 0;
+    for (name, ok, detail) in &results {
+        if !ok {
+            deviations += 1;
+        }
+        println!("[{}] {name}: {detail}", if *ok { "OK" } else { "NG" });
+    }
+    if deviations > 0 {
+        Err(anyhow!("{deviations} deviations found on {}", args.dut))
+    } else {
+        Ok(())
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Open Vnc from DUT
+#[argh(subcommand, name = "vnc")]
+struct ArgsVnc {
+    /// DUT which the files are pushed to
+    #[argh(option)]
+    dut: String,
+
+    /// local port (default: a free port from 5900)
+    #[argh(option)]
+    port: Option<u16>,
+}
+
+fn run_dut_vnc(args: &ArgsVnc) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let allocator = PortAllocator::new(VNC_PORTS)?;
+    let purpose = format!("vnc {}", args.dut);
+    let port = match args.port {
+        Some(port) => allocator
+            .try_allocate(port, &purpose)?
+            .context(anyhow!("Port {port} is already in use"))?,
+        None => allocator.allocate(&purpose)?,
+    };
+    cancel::install_handler()?;
+    let mut child = target.start_port_forwarding(port.port(), 5900, "kmsvnc")?;
+    let mut shown = false;
+
+    loop {
+        if let Some(status) = child.try_status()? {
+            warning!("Failed to connect to {}: {}", &args.dut, status);
+            return Ok(());
+        } else if !shown {
+            println!(
+                "Connected. Please run `xtightvncviewer -encodings raw localhost:{}`",
+                port.port()
+            );
+            shown = true;
+        }
+        // The ssh process is killed when the child is dropped
+        cancel::sleep(time::Duration::from_secs(5))?;
+    }
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// open a SSH monitor
+#[argh(subcommand, name = "monitor")]
+struct ArgsDutMonitor {
+    /// DUT identifiers to monitor
+    #[argh(positional)]
+    duts: Vec<String>,
+
+    /// monitor the DUTs in a group defined with `lium config set dut_group <name> <DUT>...`
+    #[argh(option)]
+    group: Option<String>,
+
+    /// only monitor the DUTs matching the filter on the last known info (e.g. 'board==eve').
+    /// all the known DUTs are candidates if no DUT is given.
+    #[argh(option)]
+    filter: Option<String>,
+
+    /// group the rows by an attribute of the last known info (e.g. board, model). press the
+    /// number of a group or click it to collapse or expand it, a for all the groups and q to quit.
+    #[argh(option)]
+    group_by: Option<String>,
+
+    /// record the state of the DUTs at each check to the sqlite db (e.g. monitor.sqlite)
+    #[argh(option)]
+    record: Option<String>,
+
+    /// run without the screen, printing only the changes of the state. use with --record
+    #[argh(switch)]
+    headless: bool,
+
+    /// show the state recorded in the db around --at instead of monitoring the DUTs
+    #[argh(option)]
+    replay: Option<String>,
+
+    /// local time to replay (e.g. '2024-06-01 03:00'). default: the end of the recording
+    #[argh(option)]
+    at: Option<String>,
+
+    /// minutes around --at to list the changes of the state in (default: 10)
+    #[argh(option, default = "10")]
+    window: i64,
+
+    /// interval of the latency probe in seconds (default: 1)
+    #[argh(option, default = "1.0")]
+    probe_interval: f64,
+
+    /// send WoL packets to DUTs on the local network while they are offline
+    #[argh(switch)]
+    wake: bool,
+
+    /// format of each line (e.g. '{{dut}}\t{{status}}\t{{rtt}}'). keys: dut, forward, address,
+    /// status, rtt
+    #[argh(option)]
+    format: Option<String>,
+}
+
+/// Returns the last known info of a DUT for filters, without accessing it
+fn known_info_of(dut: &str) -> Option<HashMap<String, String>> {
+    let mut info: HashMap<String, String> = DutInfo::cached_info(dut)
+        .ok()?
+        .into_iter()
+        .map(|(k, v)| (k, v.value))
+        .collect();
+    info.insert("id".to_string(), dut.to_string());
+    Some(info)
+}
+
+fn select_monitored_duts(args: &ArgsDutMonitor) -> Result<Vec<String>> {
+    let mut duts = args.duts.clone();
+    if let Some(group) = &args.group {
+        duts.extend(dut_group(group)?);
+    }
+    if let Some(filter) = &args.filter {
+        let filter = Filter::from_str(filter)?;
+        if duts.is_empty() {
+            duts = known_duts()?.into_keys().collect();
+            duts.sort();
+        }
+        duts.retain(|dut| known_info_of(dut).map_or(false, |info| filter.matches(&info)));
+    }
+    let mut seen = HashSet::new();
+    duts.retain(|dut| seen.insert(dut.clone()));
+    if duts.is_empty() {
+        return Err(anyhow!("No DUT to monitor"));
+    }
+    Ok(duts)
+}
+
+/// Returns the aggregate line of `dut monitor` (e.g. "3 online / 1 offline, mean RTT 12.3 ms")
+fn monitor_summary(online: usize, offline: usize, rtts: &[time::Duration]) -> String {
+    let mean = if rtts.is_empty() {
+        "-".to_string()
+    } else {
+        let sum: time::Duration = rtts.iter().sum();
+        format!("{:.1} ms", sum.as_secs_f64() * 1000.0 / rtts.len() as f64)
+    };
+    format!("{online} online / {offline} offline, mean RTT {mean}")
+}
+
+/// Returns the interval of the latency probe, which must be long enough not to flood the DUT
+fn parse_probe_interval(secs: f64) -> Result<time::Duration> {
+    const MIN_SECS: f64 = 0.1;
+    if !secs.is_finite() || secs < MIN_SECS {
+        return Err(anyhow!(
+            "--probe-interval must be at least {MIN_SECS} seconds, got {secs}"
+        ));
+    }
+    Ok(time::Duration::from_secs_f64(secs))
+}
+
+/// Parses a local time like "2024-06-01 03:00" into UNIX time
+fn parse_local_time(s: &str) -> Result<i64> {
+    let t = ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|f| NaiveDateTime::parse_from_str(s.trim(), f).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(s.trim(), "%Y-%m-%d")
+                .ok()?
+                .and_hms_opt(0, 0, 0)
+        })
+        .context(anyhow!("Invalid time {s:?}. Use e.g. '2024-06-01 03:00'"))?;
+    Ok(Local
+        .from_local_datetime(&t)
+        .earliest()
+        .context(anyhow!("{s:?} does not exist in the local time zone"))?
+        .timestamp())
+}
+
+fn format_local_time(t: i64) -> String {
+    Local
+        .timestamp_opt(t, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the samples as aligned lines
+fn format_samples(samples: &[MonitorSample]) -> Vec<String> {
+    let mut table = Table::new();
+    for s in samples {
+        table.push([
+            s.dut.clone(),
+            s.address.clone(),
+            if s.online { "online" } else { "offline" }.to_string(),
+            s.rtt_ms
+                .map(|ms| format!("{ms:.1} ms"))
+                .unwrap_or("-".to_string()),
+            format_local_time(s.recorded_at),
+        ]);
+    }
+    table.lines("  ")
+}
+
+fn run_dut_monitor_replay(args: &ArgsDutMonitor, path: &str) -> Result<()> {
+    let db = MonitorDb::open_existing(Path::new(path))?;
+    let Some((first, last)) = db.time_range()? else {
+        return Err(anyhow!("Nothing is recorded in {path}"));
+    };
+    let at = args
+        .at
+        .as_deref()
+        .map(parse_local_time)
+        .transpose()?
+        .unwrap_or(last);
+    println!(
+        "Recorded from {} to {}",
+        format_local_time(first),
+        format_local_time(last)
+    );
+    println!("\nState at {}:", format_local_time(at));
+    let state = db.state_at(at)?;
+    if state.is_empty() {
+        println!("(no sample)");
+    }
+    for line in format_samples(&state) {
+        println!("{line}");
+    }
+    let window = args.window * 60;
+    println!(
+        "\nChanges from {} to {}:",
+        format_local_time(at - window),
+        format_local_time(at + window)
+    );
+    let transitions = db.transitions(at - window, at + window)?;
+    if transitions.is_empty() {
+        println!("(none)");
+    }
+    for line in format_samples(&transitions) {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+fn run_dut_monitor(args: &ArgsDutMonitor) -> Result<()> {
+    if let Some(db) = &args.replay {
+        return run_dut_monitor_replay(args, db);
+    }
+    if args.headless && args.record.is_none() {
+        return Err(anyhow!("--headless is only for --record"));
+    }
+    cros::ensure_testing_rsa_is_there()?;
+    let mut db = args
+        .record
+        .as_deref()
+        .map(|path| MonitorDb::open(Path::new(path)))
+        .transpose()?;
+    let format = args.format.as_deref().map(Template::from_str).transpose()?;
+    let probe_interval = parse_probe_interval(args.probe_interval)?;
+    let duts = select_monitored_duts(args)?;
+    cancel::install_handler()?;
+    let mut targets: Vec<MonitoredDut> = Vec::new();
+    // Kept until the end to keep the ports from other lium instances
+    let mut ports: Vec<PortAllocation> = Vec::new();
+    let allocator = PortAllocator::new(MONITOR_PORTS)?;
+    for dut in &duts {
+        let port = allocator.allocate(&format!("monitor {dut}"))?;
+        targets.push(MonitoredDut::new(
+            dut,
+            port.port(),
+            probe_interval,
+            args.wake,
+        )?);
+        ports.push(port);
+    }
+    // The group of each target, from the last known info
+    let groups: Vec<String> = targets
+        .iter()
+        .map(|t| {
+            args.group_by
+                .as_deref()
+                .and_then(|key| known_info_of(t.dut())?.get(key).cloned())
+                .unwrap_or_else(|| "unknown".to_string())
+        })
+        .collect();
+    let mut group_names: Vec<String> = groups.clone();
+    group_names.sort();
+    group_names.dedup();
+    let mut collapsed: HashSet<String> = HashSet::new();
+
+    let mut screen = (!args.headless).then(Screen::new).transpose()?;
+    let mut last_online: Vec<Option<bool>> = vec![None; targets.len()];
+    loop {
+        if let Some(states) = statusd::query(None) {
+            for target in targets.iter_mut() {
+                target.update_from_statusd(&states);
+            }
+        }
+        // Check all the DUTs concurrently so that an offline DUT does not delay the others
+        let rows = block_on(join_all(targets.iter_mut().map(|target| async {
+            match &format {
+                Some(format) => target
+                    .get_status_fields()
+                    .await
+                    .map(|fields| vec![format.render(&fields)]),
+                None => target.get_status().await,
+            }
+        })))
+        .into_iter()
+        .collect::<Result<Vec<Vec<String>>>>()?;
+        // Align the columns over all the DUTs
+        let mut table = Table::new();
+        if format.is_none() {
+            table.push(MonitoredDut::get_status_header());
+        }
+        for row in rows {
+            table.push(row);
+        }
+        let mut lines = table.lines("  ");
+        let status_header = format.is_none().then(|| lines.remove(0));
+        let online: Vec<bool> = targets.iter().map(|t| t.is_online()).collect();
+        let rtts: Vec<time::Duration> = targets
+            .iter()
+            .filter(|t| t.is_online())
+            .filter_map(|t| t.latency_stats().map(|s| s.p50))
+            .collect();
+        let num_online = online.iter().filter(|o| **o).count();
+        if let Some(db) = &mut db {
+            let now = Local::now().timestamp();
+            let samples: Vec<MonitorSample> = targets
+                .iter()
+                .map(|t| {
+                    let stats = t.latency_stats();
+                    MonitorSample {
+                        dut: t.dut().to_string(),
+                        address: t.address(),
+                        online: t.is_online(),
+                        rtt_ms: stats.as_ref().map(|s| s.p50.as_secs_f64() * 1000.0),
+                        loss: stats.map(|s| s.loss),
+                        recorded_at: now,
+                    }
+                })
+                .collect();
+            db.record(&samples)?;
+        }
+        let Some(screen) = &mut screen else {
+            for (t, last) in targets.iter().zip(last_online.iter_mut()) {
+                if *last != Some(t.is_online()) {
+                    let state = if t.is_online() { "online" } else { "offline" };
+                    status!("{} is {state}", t.dut());
+                    *last = Some(t.is_online());
+                }
+            }
+            cancel::sleep(time::Duration::from_secs(5))?;
+            continue;
+        };
+        // Redraw on events until the next check
+        let deadline = time::Instant::now() + time::Duration::from_secs(5);
+        loop {
+            let mut header = vec![monitor_summary(
+                num_online,
+                targets.len() - num_online,
+                &rtts,
+            )];
+            header.extend(status_header.clone());
+            let mut body: Vec<String> = Vec::new();
+            // The group of each line of the body if it is a group header
+            let mut group_of_line: Vec<Option<usize>> = Vec::new();
+            match &args.group_by {
+                Some(key) => {
+                    for (i, name) in group_names.iter().enumerate() {
+                        let members: Vec<usize> =
+                            (0..targets.len()).filter(|j| &groups[*j] == name).collect();
+                        let is_collapsed = collapsed.contains(name);
+                        body.push(format!(
+                            "[{}] {} {key}={name}: {} DUTs, {} online",
+                            if is_collapsed { '+' } else { '-' },
+                            i + 1,
+                            members.len(),
+                            members.iter().filter(|j| online[**j]).count()
+                        ));
+                        group_of_line.push(Some(i));
+                        if !is_collapsed {
+                            for j in members {
+                                body.push(lines[j].clone());
+                                group_of_line.push(None);
+                            }
+                        }
+                    }
+                }
+                None => body.extend(lines.iter().cloned()),
+            }
+            screen.draw(&header, &body)?;
+
+            let timeout = deadline.saturating_duration_since(time::Instant::now());
+            let mut toggle = |i: usize| {
+                if let Some(name) = group_names.get(i) {
+                    if !collapsed.remove(name) {
+                        collapsed.insert(name.clone());
+                    }
+                }
+            };
+            // Dropping the targets and the screen stops the forwarding and restores the terminal
+            match screen.next_event(timeout)? {
+                None => break,
+                Some(TuiEvent::Key(Key::Char('q') | Key::Ctrl('c'))) => return Ok(()),
+                Some(TuiEvent::Key(Key::Char('a'))) => {
+                    if collapsed.is_empty() {
+                        collapsed.extend(group_names.iter().cloned());
+                    } else {
+                        collapsed.clear();
+                    }
+                }
+                Some(TuiEvent::Key(Key::Char(c @ '1'..='9'))) => {
+                    toggle(c as usize - '1' as usize);
+                }
+                Some(TuiEvent::Click(line)) => {
+                    if let Some(Some(i)) = group_of_line.get(line) {
+                        toggle(*i);
+                    }
+                }
+                Some(_) => {}
+            }
+        }
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// open a SSH shell
+#[argh(subcommand, name = "shell")]
+struct ArgsDutShell {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: Option<String>,
+
+    /// a DUT group defined with `lium config set dut_group <name> <DUT>...`
+    #[argh(option)]
+    group: Option<String>,
+
+    /// with --group, open a tmux session with one pane per DUT
+    #[argh(switch)]
+    tmux: bool,
+
+    /// with --tmux, send input to all panes at once
+    #[argh(switch)]
+    sync: bool,
+
+    /// print ssh_config(5) entries for the DUTs instead of connecting, for use with other tools
+    #[argh(switch)]
+    ssh_config: bool,
+
+    /// if specified, it will invoke autologin before opening a shell
+    #[argh(switch)]
+    autologin: bool,
+
+    /// if specified, run the command on dut and exit. if not, it will open an interactive shell.
+    #[argh(positional)]
+    args: Vec<String>,
+}
+fn run_dut_shell(args: &ArgsDutShell) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let duts = match (&args.dut, &args.group) {
+        (Some(dut), None) => vec![dut.clone()],
+        (None, Some(group)) => dut_group(group)?,
+        _ => return Err(anyhow!("Please specify either --dut or --group")),
+    };
+    if args.ssh_config {
+        for dut in &duts {
+            println!(
+                "{}",
+                SshInfo::new(dut)?.ssh_config_entry(&ssh_config_alias(dut))?
+            );
+        }
+        return Ok(());
+    }
+    if args.tmux {
+        return open_tmux_session(args.group.as_deref().unwrap_or("dut"), &duts, args.sync);
+    }
+    if duts.len() > 1 {
+        if args.args.is_empty() {
+            return Err(anyhow!(
+                "Please specify --tmux or a command to run on the DUTs in the group"
+            ));
+        }
+        for dut in &duts {
+            println!("===== {dut} =====");
+            if let Err(e) = SshInfo::new(dut).and_then(|t| t.run_cmd_piped(&args.args)) {
+                eprintln!("{dut}: {e:#}");
+            }
+        }
+        return Ok(());
+    }
+    let target = &SshInfo::new(&duts[0])?;
+    if args.autologin {
+        target.run_autologin()?;
+    }
+    if args.args.is_empty() {
+        target.open_ssh()
+    } else {
+        target.run_cmd_piped(&args.args)
+    }
+}
+
+fn open_tmux_session(name: &str, duts: &[String], sync: bool) -> Result<()> {
+    let session = format!("lium-{name}");
+    let lium = current_exe()?;
+    let lium = lium.to_string_lossy();
+    let tmux = |tmux_args: &[&str]| -> Result<()> {
+        Command::new("tmux")
+            .args(tmux_args)
+            .status()
+            .context("Failed to run tmux")?
+            .exit_ok()
+            .context(anyhow!("tmux {} failed", tmux_args.join(" ")))
+    };
+    for (i, dut) in duts.iter().enumerate() {
+        let shell = format!("{lium} dut shell --dut {dut}");
+        if i == 0 {
+            tmux(&["new-session", "-d", "-s", &session, &shell])?;
+        } else {
+            tmux(&["split-window", "-t", &session, &shell])?;
+            // Re-layout every time to have enough space for the next pane
+            tmux(&["select-layout", "-t", &session, "tiled"])?;
+        }
+    }
+    if sync {
+        tmux(&[
+            "set-window-option",
+            "-t",
+            &session,
+            "synchronize-panes",
+            "on",
+        ])?;
+    }
+    if std::env::var("TMUX").is_ok() {
+        tmux(&["switch-client", "-t", &session])
+    } else {
+        tmux(&["attach-session", "-t", &session])
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// get the kernel configuration from the DUT
+#[argh(subcommand, name = "kernel_config")]
+struct ArgsDutKernelConfig {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(positional)]
+    dut: String,
+}
+fn run_dut_kernel_config(args: &ArgsDutKernelConfig) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    let config = target.get_host_kernel_config()?;
+    println!("{}", config);
+    Ok(())
+}
+
+type DutAction = Box<fn(&SshInfo) -> Result<()>>;
+fn do_reboot(s: &SshInfo) -> Result<()> {
+    s.run_cmd_piped(&["reboot; exit"])
+}
+fn do_login(s: &SshInfo) -> Result<()> {
+    s.run_autologin()
+}
+fn do_tail_messages(s: &SshInfo) -> Result<()> {
+    s.run_cmd_piped(&["tail -f /var/log/messages"])
+}
+fn do_wake(s: &SshInfo) -> Result<()> {
+    s.wake_and_wait(time::Duration::from_secs(120))
+}
+fn do_collect_logs(s: &SshInfo) -> Result<()> {
+    let dest = gen_path_in_lium_dir(&format!(
+        "collected_logs/{}/.keep",
+        Local::now().format("%Y%m%d_%H%M%S")
+    ))?;
+    let dest = dest
+        .parent()
+        .context("no parent")?
+        .to_string_lossy()
+        .to_string();
+    s.collect_logs(&dest)?;
+    println!("{dest}/{LOG_BUNDLE_NAME}");
+    Ok(())
+}
+lazy_static! {
+    static ref DUT_ACTIONS: HashMap<&'static str, DutAction> = {
+        let mut m: HashMap<&'static str, DutAction> = HashMap::new();
+        m.insert("reboot", Box::new(do_reboot));
+        m.insert("login", Box::new(do_login));
+        m.insert("tail_messages", Box::new(do_tail_messages));
+        m.insert("wake", Box::new(do_wake));
+        m.insert("collect_logs", Box::new(do_collect_logs));
+        m
+    };
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// send actions
+#[argh(subcommand, name = "do")]
+struct ArgsDutDo {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: Option<String>,
+    /// actions to do (--list-actions to see available options)
+    #[argh(positional)]
+    actions: Vec<String>,
+    /// list available actions
+    #[argh(switch)]
+    list_actions: bool,
+    /// wake the DUT up with WoL first if it is offline and on the local network
+    #[argh(switch)]
+    wake: bool,
+}
+fn run_dut_do(args: &ArgsDutDo) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    if args.list_actions {
+        println!(
+            "{}",
+            DUT_ACTIONS
+                .keys()
+                .map(|s| s.to_owned())
+                .collect::<Vec<&str>>()
+                .join(" ")
+        );
+        return Ok(());
+    }
+    let unknown_actions: Vec<&String> = args
+        .actions
+        .iter()
+        .filter(|s| !DUT_ACTIONS.contains_key(s.as_str()))
+        .collect();
+    if !unknown_actions.is_empty() || args.actions.is_empty() {
+        return Err(anyhow!(
+            "Unknown action: {unknown_actions:?}. See `lium dut do --list-actions` for available actions."
+        ));
+    }
+    let dut = &SshInfo::new(args.dut.as_ref().context(anyhow!("Please specify --dut"))?)?;
+    if args.wake && dut.is_on_local_segment() {
+        dut.wake_and_wait(time::Duration::from_secs(120))?;
+    }
+    let actions: Vec<&DutAction> = args
+        .actions
+        .iter()
+        .flat_map(|s| DUT_ACTIONS.get(s.as_str()))
+        .collect();
+    let actions: Vec<(&String, &&DutAction)> = args.actions.iter().zip(actions.iter()).collect();
+    for (name, f) in actions {
+        f(dut).context(anyhow!("DUT action: {name}"))?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum DutStatus {
+    Online,
+    Offline,
+    AddressReused,
+    Unknown,
+}
+impl DutStatus {
+    /// Returns the status padded for the table, colored if enabled
+    fn label(&self) -> String {
+        let style = match self {
+            DutStatus::Online => Style::Good,
+            DutStatus::Offline => Style::Bad,
+            DutStatus::AddressReused => Style::Warning,
+            DutStatus::Unknown => Style::Dim,
+        };
+        styled(
+            &format!("{:13}", format!("{self:?}")),
+            style,
+            Stream::Stdout,
+        )
+    }
+    /// Parses the status reported by statusd
+    fn from_name(name: &str) -> Self {
+        match name {
+            "Online" => DutStatus::Online,
+            "Offline" => DutStatus::Offline,
+            "AddressReused" => DutStatus::AddressReused,
+            _ => DutStatus::Unknown,
+        }
+    }
+}
+#[derive(FromArgs, PartialEq, Debug)]
+/// list all cached DUTs
+#[argh(subcommand, name = "list")]
+struct ArgsDutList {
+    /// clear all DUT caches
+    #[argh(switch)]
+    clear: bool,
+
+    /// display space-separated DUT IDs on one line (stable)
+    #[argh(switch)]
+    ids: bool,
+
+    /// display current status of DUTs (may take a few moments)
+    #[argh(switch)]
+    status: bool,
+
+    /// add a DUT to the list with the connection provided
+    #[argh(option)]
+    add: Option<String>,
+
+    /// remove a DUT with a specified ID from the list
+    #[argh(option)]
+    remove: Option<String>,
+
+    /// update the DUT list and show their status
+    #[argh(switch)]
+    update: bool,
+
+    /// with --status, show the last known status without accessing DUTs. statuses older than
+    /// --max-age (default: 1 hour) are shown as Unknown and refreshed in the background
+    #[argh(switch)]
+    cached: bool,
+
+    /// with --status, reuse the status of DUTs checked within this many seconds
+    #[argh(option)]
+    max_age: Option<u64>,
+
+    /// check all DUTs and show only what changed (status, release, address) since the last check
+    #[argh(switch)]
+    changes: bool,
+
+    /// only list DUTs matching the filter on the last known info
+    /// (e.g. 'release>=15400 && model==soraka')
+    #[argh(option, long = "where")]
+    filter: Option<String>,
+
+    /// comma-separated list of attributes to show from the last known info (e.g. id,ip,release)
+    #[argh(option)]
+    select: Option<String>,
+
+    /// format of each line with the last known info (e.g. '{{id}}\t{{model}}\t{{release}}')
+    #[argh(option)]
+    format: Option<String>,
+
+    /// where to write the list: a path, gs://bucket/key or http(s)://collector (default: stdout)
+    #[argh(option)]
+    output: Option<String>,
+}
+
+/// Status of a DUT recorded by `lium dut list --changes`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+struct DutSnapshot {
+    status: String,
+    release: Option<String>,
+    address: String,
+}
+static DUT_SNAPSHOT_CACHE: KvCache<DutSnapshot> = KvCache::new("dut_snapshot_cache");
+
+/// Status of a DUT at the last check by `lium dut list --status`, for `--cached` and `--max-age`
+static DUT_STATUS_CACHE: KvCache<CachedValue> = KvCache::new("dut_status_cache");
+
+fn record_dut_status(id: &str, status: DutStatus) -> Result<()> {
+    DUT_STATUS_CACHE.set(
+        id,
+        CachedValue {
+            value: format!("{status:?}"),
+            fetched_at: Local::now().timestamp(),
+        },
+    )
+}
+
+/// Returns the recorded status of the DUT if it was checked within max_age
+fn recorded_dut_status(id: &str, max_age: time::Duration) -> Option<DutStatus> {
+    DUT_STATUS_CACHE
+        .get(id)
+        .ok()
+        .flatten()
+        .filter(|s| s.age() <= max_age)
+        .map(|s| DutStatus::from_name(&s.value))
+}
+
+fn diff_dut_snapshots(
+    prev: &HashMap<String, DutSnapshot>,
+    cur: &HashMap<String, DutSnapshot>,
+) -> Vec<String> {
+    let mut ids: Vec<&String> = prev.keys().chain(cur.keys()).collect();
+    ids.sort();
+    ids.dedup();
+    let mut diffs = Vec::new();
+    for id in ids {
+        match (prev.get(id), cur.get(id)) {
+            (None, Some(c)) => diffs.push(format!("{id:32} added ({}, {})", c.status, c.address)),
+            (Some(_), None) => diffs.push(format!("{id:32} removed from the list")),
+            (Some(p), Some(c)) => {
+                if p.status != c.status {
+                    diffs.push(format!("{id:32} {} -> {}", p.status, c.status));
+                }
+                if let (Some(pr), Some(cr)) = (&p.release, &c.release) {
+                    if pr != cr {
+                        diffs.push(format!("{id:32} reflashed: {pr} -> {cr}"));
+                    }
+                }
+                if p.address != c.address {
+                    diffs.push(format!(
+                        "{id:32} address changed: {} -> {}",
+                        p.address, c.address
+                    ));
+                }
+            }
+            (None, None) => {}
+        }
+    }
+    diffs
+}
+
+/// Returns the info of the DUT, giving up after DUT_PROBE_TIMEOUT
+async fn check_dut(id: &str) -> Result<HashMap<String, String>> {
+    with_timeout(DUT_PROBE_TIMEOUT, DutInfo::new_async(id))
+        .await
+        .map(|dut| dut.info().clone())
+}
+
+fn run_dut_list_changes(duts: &HashMap<String, SshInfo>, out: &mut dyn Sink) -> Result<()> {
+    ensure_online("Checking status of DUTs")?;
+    let prev = DUT_SNAPSHOT_CACHE.entries()?;
+    status!(
+        "Checking status of {} DUTs. It will take a minute...",
+        duts.len()
+    );
+    cancel::install_handler()?;
+    let progress = Progress::new("Checking DUTs", duts.len() as u64);
+    let cur: HashMap<String, DutSnapshot> =
+        run_concurrently(duts, MAX_PARALLEL_SSH, |(id, ssh)| {
+            let (prev, progress) = (&prev, &progress);
+            async move {
+                if cancel::is_cancelled() {
+                    return None;
+                }
+                progress.set_detail(id);
+                let info = check_dut(id).await;
+                let (status, release) = match info {
+                    Ok(info) if Some(id) == info.get("dut_id") => {
+                        (DutStatus::Online, info.get("release").cloned())
+                    }
+                    Ok(_) => (DutStatus::AddressReused, None),
+                    Err(_) => (DutStatus::Offline, None),
+                };
+                // Keep the last known release while the DUT is not reachable
+                let release = release.or_else(|| prev.get(id).and_then(|p| p.release.clone()));
+                let snapshot = DutSnapshot {
+                    status: format!("{status:?}"),
+                    release,
+                    address: ssh.host_and_port(),
+                };
+                progress.inc(1);
+                Some((id.clone(), snapshot))
+            }
+        })
+        .into_iter()
+        .flatten()
+        .collect();
+    drop(progress);
+    if cancel::is_cancelled() {
+        // Do not record a partial snapshot, which would show the unchecked DUTs as removed
+        warning!(
+            "Interrupted after checking {} of {} DUTs",
+            cur.len(),
+            duts.len()
+        );
+        return cancel::check();
+    }
+    let diffs = diff_dut_snapshots(&prev, &cur);
+    if diffs.is_empty() {
+        out.write_line("No changes since the last check")?;
+    }
+    for d in diffs {
+        out.write_line(&d.to_string())?;
+    }
+    DUT_SNAPSHOT_CACHE.clear()?;
+    for (id, snapshot) in cur {
+        DUT_SNAPSHOT_CACHE.set(&id, snapshot)?;
+    }
+    Ok(())
+}
+fn run_dut_list(args: &ArgsDutList) -> Result<()> {
+    let mut out = output::open(args.output.as_deref())?;
+    list_duts(args, out.as_mut())?;
+    out.finish()
+}
+fn list_duts(args: &ArgsDutList, out: &mut dyn Sink) -> Result<()> {
+    if args.clear {
+        DUT_PROVENANCE.clear()?;
+        return SSH_CACHE.clear();
+    }
+    let mut duts = known_duts()?;
+    let filter = args.filter.as_deref().map(Filter::from_str).transpose()?;
+    let select = args.select.as_deref().map(parse_select);
+    let format = args.format.as_deref().map(Template::from_str).transpose()?;
+    // The last known info of DUTs, to evaluate the queries without accessing them
+    let mut known_info: HashMap<String, HashMap<String, String>> = HashMap::new();
+    if filter.is_some() || select.is_some() || format.is_some() {
+        let cached = DUT_INFO_CACHE.entries()?;
+        let notes = DUT_NOTES.entries()?;
+        for (id, ssh) in &duts {
+            let mut info: HashMap<String, String> = cached
+                .get(id)
+                .map(|c| {
+                    c.iter()
+                        .map(|(k, v)| (k.clone(), v.value.clone()))
+                        .collect()
+                })
+                .unwrap_or_default();
+            info.insert("id".to_string(), id.clone());
+            info.insert("address".to_string(), ssh.host_and_port());
+            if let Some(note) = notes.get(id).and_then(|n| n.last()) {
+                info.insert("note".to_string(), note.text.clone());
+            }
+            known_info.insert(id.clone(), info);
+        }
+    }
+    if let Some(filter) = &filter {
+        duts.retain(|id, _| known_info.get(id).map_or(false, |i| filter.matches(i)));
+    }
+    if args.ids {
+        let keys: Vec<String> = duts.keys().map(|s| s.to_string()).collect();
+        out.write_line(&keys.join(" "))?;
+        return Ok(());
+    }
+    if let Some(dut_to_add) = &args.add {
+        status!("Checking DutInfo of {dut_to_add}...");
+        let info = DutInfo::new(dut_to_add)?;
+        let id = info.id();
+        let ssh = info.ssh();
+        SSH_CACHE.set(id, ssh.clone())?;
+        DUT_PROVENANCE.set(id, DutProvenance::now(None))?;
+        println!("Added: {:32} {}", id, serde_json::to_string(ssh)?);
+        return Ok(());
+    }
+    if let Some(dut_to_remove) = &args.remove {
+        SSH_CACHE.remove(dut_to_remove)?;
+        DUT_PROVENANCE.remove(dut_to_remove)?;
+        status!("Removed: {dut_to_remove}",);
+        return Ok(());
+    }
+    if args.changes {
+        return run_dut_list_changes(&duts, out);
+    }
+    if args.status && (args.cached || is_offline_mode()) {
+        let max_age = args
+            .max_age
+            .map(time::Duration::from_secs)
+            .unwrap_or(DUT_INFO_DEFAULT_MAX_AGE);
+        let mut has_stale = false;
+        let mut table = Table::with_min_widths(&[32]);
+        for (id, ssh) in &duts {
+            let status = recorded_dut_status(id, max_age).unwrap_or_else(|| {
+                has_stale = true;
+                DutStatus::Unknown
+            });
+            table.push([id.clone(), status.label(), format!("{ssh:?}")]);
+        }
+        for line in table.lines(" ") {
+            out.write_line(&line)?;
+        }
+        if has_stale && !is_offline_mode() {
+            spawn_lium_in_background(&["dut", "list", "--status"])?;
+        }
+        return Ok(());
+    }
+    if args.status || args.update {
+        ensure_online("Checking status of DUTs")?;
+        // The DUTs checked by statusd do not need to be accessed
+        let known = statusd::query(None).unwrap_or_default();
+        if known.is_empty() {
+            status!(
+                "Checking status of {} DUTs. It will take a minute...",
+                duts.len()
+            );
+        }
+        cancel::install_handler()?;
+        let total = duts.len();
+        let progress = Progress::new("Checking DUTs", total as u64);
+        let duts: Vec<(String, DutStatus, SshInfo)> =
+            run_concurrently(&duts, MAX_PARALLEL_SSH, |(id, ssh)| {
+                let (progress, known) = (&progress, &known);
+                async move {
+                    if cancel::is_cancelled() {
+                        return None;
+                    }
+                    let recorded = args.max_age.and_then(|max_age| {
+                        recorded_dut_status(id, time::Duration::from_secs(max_age))
+                    });
+                    let status = if let Some(state) = known.get(id) {
+                        DutStatus::from_name(&state.status)
+                    } else if let Some(recorded) = recorded {
+                        recorded
+                    } else if let Ok(info) = check_dut(id).await {
+                        if Some(id) == info.get("dut_id") {
+                            DutStatus::Online
+                        } else {
+                            DutStatus::AddressReused
+                        }
+                    } else {
+                        DutStatus::Offline
+                    };
+                    if known.contains_key(id) || recorded.is_none() {
+                        if let Err(e) = record_dut_status(id, status) {
+                            warning!("Failed to record the status of {id}: {e:#}");
+                        }
+                    }
+                    progress.set_detail(id);
+                    progress.inc(1);
+                    Some((id.to_owned(), status, ssh.clone()))
+                }
+            })
+            .into_iter()
+            .flatten()
+            .collect();
+        drop(progress);
+        let checked = duts.len();
+        let (duts_to_be_removed, duts) = if args.update {
+            (
+                duts.iter()
+                    .filter(|e| e.1 == DutStatus::AddressReused)
+                    .cloned()
+                    .collect(),
+                duts.iter()
+                    .filter(|e| e.1 != DutStatus::AddressReused)
+                    .cloned()
+                    .collect(),
+            )
+        } else {
+            (Vec::new(), duts)
+        };
+        let status_lines = |duts: &[(String, DutStatus, SshInfo)]| {
+            let mut table = Table::with_min_widths(&[32]);
+            for dut in duts {
+                table.push([dut.0.clone(), dut.1.label(), format!("{:?}", dut.2)]);
+            }
+            table.lines(" ")
+        };
+        for line in status_lines(&duts) {
+            out.write_line(&line)?;
+        }
+        if !duts_to_be_removed.is_empty() {
+            out.write_line("\nFollowing DUTs are removed: ")?;
+            for line in status_lines(&duts_to_be_removed) {
+                out.write_line(&line)?;
+            }
+            for dut in duts_to_be_removed {
+                SSH_CACHE.remove(&dut.0)?;
+            }
+        }
+        if args.update {
+            update_exported_ssh_config()?;
+        }
+        if cancel::is_cancelled() {
+            warning!("Interrupted after checking {checked} of {total} DUTs");
+        }
+        return cancel::check();
+    }
+    if let Some(format) = &format {
+        for id in duts.keys() {
+            if let Some(info) = known_info.get(id) {
+                out.write_line(&format.render(info))?;
+            }
+        }
+        return Ok(());
+    }
+    if let Some(select) = &select {
+        out.write_line(&select.join("\t"))?;
+        for id in duts.keys() {
+            if let Some(info) = known_info.get(id) {
+                let values: Vec<String> = project(info, select).into_iter().map(|e| e.1).collect();
+                out.write_line(&values.join("\t"))?;
+            }
+        }
+        return Ok(());
+    }
+    // List cached DUTs
+    let provenance = DUT_PROVENANCE.entries()?;
+    let notes = DUT_NOTES.entries()?;
+    let mut table = Table::with_min_widths(&[32]);
+    for it in duts.iter() {
+        let mut comments = Vec::new();
+        if let Some(note) = notes.get(it.0).and_then(|n| n.last()) {
+            comments.push(format!("note: {note}"));
+        }
+        if let Some(p) = provenance.get(it.0) {
+            comments.push(p.to_string());
+        }
+        let mut row = vec![it.0.clone(), serde_json::to_string(it.1)?];
+        if !comments.is_empty() && !is_porcelain() {
+            row.push(format!("# {}", comments.join("; ")));
+        }
+        table.push(row);
+    }
+    for line in table.lines(" ") {
+        out.write_line(&line)?;
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show DUT info
+#[argh(subcommand, name = "info")]
+struct ArgsDutInfo {
+    /// DUT identifiers (e.g. 127.0.0.1, localhost:2222, droid_NXHKDSJ003138124257611)
+    #[argh(option)]
+    dut: String,
+    /// comma-separated list of attribute names. to show the full list, try `lium dut info --keys ?`
+    #[argh(positional)]
+    keys: Vec<String>,
+    /// show the last known values instead of accessing the DUT (implied by --offline).
+    /// values older than --max-age (default: 1 hour) are refreshed in the background.
+    #[argh(switch)]
+    cached: bool,
+    /// use cached values if all of them were fetched within this many seconds
+    #[argh(option)]
+    max_age: Option<u64>,
+    /// fail unless the DUT matches the filter (e.g. 'release>=15400 && model==soraka')
+    #[argh(option, long = "where")]
+    filter: Option<String>,
+    /// comma-separated list of attributes to show (e.g. id,ip,release)
+    #[argh(option)]
+    select: Option<String>,
+    /// format of the output instead of JSON (e.g. '{{id}}\t{{model}}\t{{release}}')
+    #[argh(option)]
+    format: Option<String>,
+    /// mask addresses, emails, serial numbers and the things configured with
+    /// `lium config set redact_domains|redact_patterns`
+    #[argh(switch)]
+    redact: bool,
+}
+fn run_dut_info(args: &ArgsDutInfo) -> Result<()> {
+    let dut = &args.dut;
+    let filter = args.filter.as_deref().map(Filter::from_str).transpose()?;
+    let select = args.select.as_deref().map(parse_select);
+    let format = args.format.as_deref().map(Template::from_str).transpose()?;
+    let mut keys = if (select.is_some() || format.is_some()) && args.keys.is_empty() {
+        // Fetch only the selected ones
+        Vec::new()
+    } else if args.keys.is_empty() {
+        vec![
+            "timestamp",
+            "dut_id",
+            "hwid",
+            "release",
+            "model",
+            "serial",
+            "mac",
+        ]
+    } else {
+        args.keys.iter().map(|s| s.as_str()).collect()
+    };
+    let query_keys = filter
+        .iter()
+        .flat_map(|f| f.keys())
+        .chain(select.iter().flatten().map(|k| k.as_str()))
+        .chain(format.iter().flat_map(|f| f.keys()));
+    for key in query_keys.map(attribute_of) {
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    let max_age = args.max_age.map(time::Duration::from_secs);
+    let info = if let Some(info) = statusd::query_keys(dut, &keys) {
+        info
+    } else if args.cached || is_offline_mode() {
+        let (info, age) = DutInfo::cached_keys(dut, &keys)?;
+        if !is_offline_mode() && age > max_age.unwrap_or(DUT_INFO_DEFAULT_MAX_AGE) {
+            DutInfo::refresh_in_background(dut, &keys)?;
+        }
+        info
+    } else {
+        match (max_age, DutInfo::cached_keys(dut, &keys)) {
+            (Some(max_age), Ok((info, age))) if age <= max_age => info,
+            _ => {
+                let ssh = SshInfo::new(dut)?;
+                DutInfo::fetch_keys(&ssh, &keys)?
+            }
+        }
+    };
+    if let Some(filter) = &filter {
+        if !filter.matches(&info) {
+            return Err(anyhow!("{dut} does not match --where"));
+        }
+    }
+    let result = match (&format, &select) {
+        (Some(format), _) => format.render(&info),
+        (None, Some(select)) => {
+            let selected: serde_json::Map<String, serde_json::Value> = project(&info, select)
+                .into_iter()
+                .map(|(k, v)| (k, v.into()))
+                .collect();
+            serde_json::to_string(&selected)?
+        }
+        (None, None) => serde_json::to_string(&info)?,
+    };
+    let result = if args.redact {
+        let mut redactor = Redactor::from_config()?;
+        if let Some(serial) = info.get("serial") {
+            redactor.add_secret(serial, "<serial>");
+        }
+        redactor.redact(&result)
+    } else {
+        result
+    };
+    println!("{}", result);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// discover DUTs on the same network
+#[argh(subcommand, name = "discover")]
+pub struct ArgsDiscover {
+    /// A network interface to be used for the scan.
+    /// if not specified, the first interface in the routing table will be used.
+    #[argh(option)]
+    interface: Option<String>,
+    /// remote machine to do the scan. If not specified, run the discovery locally.
+    #[argh(option)]
+    remote: Option<String>,
+    /// path to a list of addresses to scan, one per line. IPv4 networks (e.g. 192.168.0.0/24)
+    /// are expanded to their host addresses.
+    #[argh(option)]
+    target_list: Option<String>,
+    /// print each DUT as a line of JSON as soon as it is found
+    #[argh(switch)]
+    stream: bool,
+    /// do not add the discovered DUTs to the DUT list
+    #[argh(switch)]
+    no_cache: bool,
+    /// where to write the results: a path, gs://bucket/key or http(s)://collector
+    /// (default: stdout)
+    #[argh(option)]
+    output: Option<String>,
+    /// additional attributes to retrieve
+    #[argh(positional, greedy)]
+    extra_attr: Vec<String>,
+}
+/// Runs the discovery on the remote machine. ~/lium on the remote is reused if it is the same
+/// binary as this one, and the results are streamed back as they are found.
+fn run_discover_remote(args: &ArgsDiscover, remote: &str) -> Result<()> {
+    status!("Using remote machine: {}", remote);
+    let lium_path = current_exe()?;
+    status!("lium executable path: {:?}", lium_path);
+    let remote = SshInfo::new(remote)?;
+    let local_hash = sha256sum(&lium_path)?;
+    // Do not fail here since ~/lium may not exist yet
+    let handshake = remote
+        .run_cmd_stdio("~/lium version 2>/dev/null; sha256sum ~/lium 2>/dev/null; true")
+        .unwrap_or_default();
+    if handshake.contains(&local_hash) {
+        status!("~/lium on the remote is up to date. Skipping the upload.");
+    } else {
+        if let Some(version) = handshake.lines().find(|l| l.starts_with("lium v")) {
+            status!("Replacing ~/lium ({version}) on the remote");
+        }
+        remote.send_files(
+            &[lium_path.to_string_lossy().to_string()],
+            Some(&"~/".to_string()),
+        )?;
+    }
+    let mut cmd = "~/lium dut discover --stream".to_string();
+    if args.no_cache {
+        cmd += " --no-cache";
+    }
+    if let Some(interface) = &args.interface {
+        cmd += &format!(" --interface {interface}");
+    }
+    for ea in &args.extra_attr {
+        cmd += " ";
+        cmd += ea;
+    }
+    let mut out = output::open(args.output.as_deref())?;
+    let mut ssh = remote.ssh_cmd(None)?;
+    cancel::install_handler()?;
+    let mut child = ssh.arg(cmd).stdout(Stdio::piped()).spawn()?;
+    let stdout = child.stdout.take().context("Failed to get stdout")?;
+    let mut duts: Vec<HashMap<String, String>> = Vec::new();
+    for line in BufReader::new(stdout).lines() {
+        let line = line?;
+        match serde_json::from_str::<HashMap<String, String>>(&line) {
+            Ok(dut) => {
+                if args.stream {
+                    out.write_line(&line)?;
+                }
+                duts.push(dut);
+            }
+            Err(_) => eprintln!("{line}"),
+        }
+    }
+    if cancel::is_cancelled() {
+        // ssh may be still running if the remote ignored the signal
+        let _ = child.kill();
+        let _ = child.wait();
+        if !args.stream {
+            out.write_line(&serde_json::to_string_pretty(&duts)?)?;
+        }
+        out.finish()?;
+        warning!("Interrupted. Found {} DUTs so far", duts.len());
+        return cancel::check();
+    }
+    child
+        .wait()?
+        .exit_ok()
+        .context("Discovery on the remote failed")?;
+    if !args.stream {
+        out.write_line(&serde_json::to_string_pretty(&duts)?)?;
+    }
+    out.finish()
+}
+pub fn run_discover(args: &ArgsDiscover) -> Result<()> {
+    if let Some(remote) = &args.remote {
+        return run_discover_remote(args, remote);
+    }
+    let out = Mutex::new(output::open(args.output.as_deref())?);
+    cancel::install_handler()?;
+    let discovering = Progress::spinner("Discovering DUTs");
+    let addrs = if let Some(target_list) = &args.target_list {
+        let addrs: String = if target_list == "-" {
+            let mut buffer = Vec::new();
+            std::io::stdin().read_to_end(&mut buffer)?;
+            Ok(std::str::from_utf8(&buffer)?.to_string())
+        } else {
+            read_to_string(target_list)
+        }?;
+        let addrs: Vec<String> = addrs
+            .trim()
+            .split('\n')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(str::to_string)
+            .collect();
+        expand_targets(&addrs)
+    } else {
+        discover_local_nodes(args.interface.to_owned())
+    }?;
+    status!("Found {} candidates. Checking...", addrs.len());
+    let provenance = DutProvenance::now(args.interface.clone());
+    let provenance = (!args.no_cache).then_some(&provenance);
+    let progress = discovering.child("Checking candidates", addrs.len() as u64);
+    let duts =
+        fetch_dut_info_in_parallel(&addrs, &args.extra_attr, provenance, &progress, &|dut| {
+            if args.stream {
+                if let Ok(line) = serde_json::to_string(dut.info()) {
+                    if let Err(e) = out.lock().unwrap().write_line(&line) {
+                        warning!("Failed to write {line}: {e:#}");
+                    }
+                }
+            }
+        })?;
+    drop(progress);
+    drop(discovering);
+    if cancel::is_cancelled() {
+        warning!(
+            "Interrupted. Found {} DUTs in the checked candidates",
+            duts.len()
+        );
+    } else {
+        status!("Discovery completed with {} DUTs", duts.len());
+    }
+    let mut out = out.into_inner().unwrap();
+    if !args.stream {
+        let duts: Vec<HashMap<String, String>> = duts.iter().map(|e| e.info().to_owned()).collect();
+        out.write_line(&serde_json::to_string_pretty(&duts)?)?;
+    }
+    // Partial results are written on Ctrl-C too
+    out.finish()?;
+    cancel::check()
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// get ARC information
+#[argh(subcommand, name = "arc_info")]
+struct ArgsArcInfo {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(positional)]
+    dut: String,
+}
+fn run_arc_info(args: &ArgsArcInfo) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let target = &SshInfo::new(&args.dut)?;
+    println!("arch: {}", target.get_arch()?);
+    println!("ARC version: {}", target.get_arc_version()?);
+    println!("ARC device: {}", target.get_arc_device()?);
+    println!("image type: {}", target.get_arc_image_type()?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn probe_interval() {
+        assert_eq!(
+            parse_probe_interval(0.5).unwrap(),
+            time::Duration::from_millis(500)
+        );
+        for secs in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(parse_probe_interval(secs).is_err());
+        }
+    }
+    #[test]
+    fn binary_for_dut() {
+        let x86_64 =
+            "ELF 64-bit LSB executable, x86-64, version 1 (SYSV), statically linked, stripped";
+        assert!(check_binary_for_dut(x86_64, "x86_64").is_ok());
+        assert!(check_binary_for_dut(x86_64, "arm64").is_err());
+        let arm64 =
+            "ELF 64-bit LSB pie executable, ARM aarch64, version 1 (SYSV), static-pie linked";
+        assert!(check_binary_for_dut(arm64, "arm64").is_ok());
+        assert!(check_binary_for_dut(arm64, "armv7l").is_err());
+        let dynamic = "ELF 64-bit LSB pie executable, x86-64, version 1 (SYSV), dynamically linked, interpreter /lib64/ld-linux-x86-64.so.2";
+        assert!(check_binary_for_dut(dynamic, "x86_64").is_err());
+    }
+    #[test]
+    fn locale() {
+        assert!(is_valid_locale("ja"));
+        assert!(is_valid_locale("pt-BR"));
+        assert!(is_valid_locale("es-419"));
+        assert!(!is_valid_locale("JA"));
+        assert!(!is_valid_locale("ja_JP"));
+        assert!(!is_valid_locale("ja'; rm"));
+        let state = set_sign_in_locale(r#"{"intl":{"app_locale":"en-US"},"a":1}"#, "ja").unwrap();
+        let state: serde_json::Value = serde_json::from_str(&state).unwrap();
+        assert_eq!(state["intl"]["app_locale"], "ja");
+        assert_eq!(state["intl"]["owner_locale"], "ja");
+        assert_eq!(state["a"], 1);
+        assert!(set_sign_in_locale("", "ja").is_ok());
+    }
+    #[test]
+    fn lat_long() {
+        assert_eq!(parse_lat_long("35.68, 139.76").unwrap(), (35.68, 139.76));
+        assert!(parse_lat_long("35.68").is_err());
+        assert!(parse_lat_long("91,0").is_err());
+        assert!(parse_lat_long("0,x").is_err());
+    }
+    #[test]
+    fn profiles() {
+        let a = "a".repeat(40);
+        let b = "b".repeat(40);
+        let listing = format!("{a} 2048 yes\n{b} 10 no\nsalt 1 no\n");
+        let users = format!("{a} user@example.com\n");
+        assert_eq!(
+            parse_profiles(&listing, &users),
+            vec![
+                Profile {
+                    hash: a,
+                    user: Some("user@example.com".to_string()),
+                    size_kb: 2048,
+                    mounted: true
+                },
+                Profile {
+                    hash: b,
+                    user: None,
+                    size_kb: 10,
+                    mounted: false
+                }
+            ]
+        );
+        assert!(is_valid_user("user+1@example.com"));
+        assert!(!is_valid_user("a;reboot"));
+    }
+    #[test]
+    fn ec_board_name() {
+        assert_eq!(
+            ec_board_from_version("soraka_v2.0.2394-7c0ab4c37").as_deref(),
+            Some("soraka")
+        );
+        assert_eq!(
+            ec_board_from_version("dewatt_v2.0.19476-0cd1b33a2b").as_deref(),
+            Some("dewatt")
+        );
+        assert_eq!(ec_board_from_version("unknown"), None);
+        assert_eq!(
+            ec_board_from_image(b"\0\0nami_v2.0.2394-7c0ab4c37\0\0").as_deref(),
+            Some("nami")
+        );
+        assert_eq!(ec_board_from_image(b"\0\0\0"), None);
+    }
+
+    #[test]
+    fn monitor_summary_line() {
+        assert_eq!(
+            monitor_summary(
+                2,
+                1,
+                &[
+                    time::Duration::from_millis(10),
+                    time::Duration::from_millis(15)
+                ]
+            ),
+            "2 online / 1 offline, mean RTT 12.5 ms"
+        );
+        assert_eq!(
+            monitor_summary(0, 3, &[]),
+            "0 online / 3 offline, mean RTT -"
+        );
+    }
+
+    #[test]
+    fn dut_snapshot_diff() {
+        let snapshot = |status: &str, release: Option<&str>, address: &str| DutSnapshot {
+            status: status.to_string(),
+            release: release.map(|s| s.to_string()),
+            address: address.to_string(),
+        };
+        let prev = HashMap::from([
+            (
+                "a".to_string(),
+                snapshot("Online", Some("R120-1.0.0"), "10.0.0.1:22"),
+            ),
+            (
+                "b".to_string(),
+                snapshot("Online", Some("R120-1.0.0"), "10.0.0.2:22"),
+            ),
+        ]);
+        let cur = HashMap::from([
+            (
+                "a".to_string(),
+                snapshot("Online", Some("R121-2.0.0"), "10.0.0.3:22"),
+            ),
+            (
+                "b".to_string(),
+                snapshot("Offline", Some("R120-1.0.0"), "10.0.0.2:22"),
+            ),
+        ]);
+        let diffs = diff_dut_snapshots(&prev, &cur);
+        assert_eq!(diffs.len(), 3);
+        assert!(diffs[0].contains("reflashed: R120-1.0.0 -> R121-2.0.0"));
+        assert!(diffs[1].contains("address changed: 10.0.0.1:22 -> 10.0.0.3:22"));
+        assert!(diffs[2].contains("Online -> Offline"));
+        assert!(diff_dut_snapshots(&cur, &cur).is_empty());
+    }
+
+    #[test]
+    fn core_mapped_files() {
+        let readelf = r#"
+Displaying notes found at file offset 0x00000b28 with length 0x00001a0c:
+  Owner                Data size 	Description
+  CORE                 0x00000150	NT_PRSTATUS (prstatus structure)
+  CORE                 0x00000b5c	NT_FILE (mapped files)
+    Page size: 4096
+                 Start                 End         Page Offset
+    0x00005a1e1f200000  0x00005a1e1f205000  0x0000000000000000
+        /usr/bin/foo
+    0x00005a1e1f205000  0x00005a1e1f20a000  0x0000000000000005
+        /usr/bin/foo
+    0x00007f5c3a800000  0x00007f5c3a828000  0x0000000000000000
+        /lib64/libc.so.6
+"#;
+        assert_eq!(
+            parse_core_mapped_files(readelf),
+            vec!["/usr/bin/foo", "/lib64/libc.so.6"]
+        );
+    }
+
+    #[test]
+    fn strace_summary() {
+        let log = r#"1234  12:34:56.000001 openat(AT_FDCWD, "/etc/foo", O_RDONLY) = -1 ENOENT (No such file or directory) <0.000010>
+1234  12:34:56.000002 openat(AT_FDCWD, "/etc/bar", O_RDONLY) = 3 <0.000030>
+1235  12:34:56.000003 read(3,  <unfinished ...>
+1234  12:34:56.000004 close(3) = 0 <0.000001>
+1235  12:34:56.100000 <... read resumed>"abc", 3) = 3 <0.100000>
+1234  12:34:56.200000 +++ exited with 0 +++"#;
+        let stats = summarize_strace(log);
+        let names: Vec<&str> = stats.iter().map(|(n, _)| n.as_str()).collect();
+        assert_eq!(names, vec!["read", "openat", "close"]);
+        assert_eq!(stats[1].1.count, 2);
+        assert_eq!(stats[1].1.errors, 1);
+        assert_eq!(stats[1].1.max_secs, 0.00003);
+    }
+
+    #[test]
+    fn undo_pushes() {
+        let log = vec![
+            PushedFile {
+                path: "/usr/local/bin/foo".to_string(),
+                backup: None,
+                pushed_at: 0,
+            },
+            PushedFile {
+                path: "/etc/init/ui.conf".to_string(),
+                backup: Some("/etc/init/ui.conf.lium-backup".to_string()),
+                pushed_at: 1,
+            },
+        ];
+        assert_eq!(
+            undo_pushes_cmds(&log),
+            vec![
+                "mv -f '/etc/init/ui.conf.lium-backup' '/etc/init/ui.conf'",
+                "rm -f '/usr/local/bin/foo'"
+            ]
+        );
+    }
+
+    #[test]
+    fn security_check_helpers() {
+        assert_eq!(
+            enabled_dev_features((1 << 3) | (1 << 6)),
+            vec!["SSH server configured", "Chrome remote debugging enabled"]
+        );
+        assert!(enabled_dev_features(1 | (1 << 3)).is_empty());
+        let authorized_keys = "ssh-rsa AAAAtesting testing@chromium.org
+# comment
+from=\"10.0.0.1\" ssh-ed25519 AAAAunknown someone@example.com
+";
+        assert_eq!(
+            unexpected_authorized_keys(authorized_keys, "ssh-rsa AAAAtesting"),
+            vec!["from=\"10.0.0.1\" ssh-ed25519 AAAAunknown someone@example.com"]
+        );
+    }
+
+    #[test]
+    fn parse_suspend_stress_result() {
+        let output = r"
+Finished 1 iterations.
+Suspend failures: 0
+Wake failures: 1
+Firmware log errors: 2
+s0ix errors: 0
+Premature wakes: 3
+";
+        assert_eq!(
+            SuspendStressResult::parse(output).unwrap(),
+            SuspendStressResult {
+                suspend_failures: 0,
+                wake_failures: 1,
+                firmware_log_errors: 2,
+                s0ix_errors: 0,
+                premature_wakes: 3,
+            }
+        );
+        assert!(SuspendStressResult::parse("ssh: connect to host").is_err());
+    }
+}
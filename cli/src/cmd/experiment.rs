@@ -0,0 +1,308 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cancel;
+use lium::cros;
+use lium::dut::SshInfo;
+use lium::progress::Progress;
+use lium::status;
+use lium::warning;
+use std::path::Path;
+use std::thread;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run experiments on DUTs
+#[argh(subcommand, name = "experiment")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Ab(ArgsAb),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Ab(args) => run_ab(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// apply different setups to two similar DUTs and compare a measurement side by side
+#[argh(subcommand, name = "ab")]
+pub struct ArgsAb {
+    /// DUT for the setup A
+    #[argh(option)]
+    dut_a: String,
+
+    /// DUT for the setup B
+    #[argh(option)]
+    dut_b: String,
+
+    /// command to run once on the DUT A before the measurements
+    #[argh(option)]
+    setup_a: Option<String>,
+
+    /// command to run once on the DUT B before the measurements
+    #[argh(option)]
+    setup_b: Option<String>,
+
+    /// command or local script to run on the DUTs. The last line of its stdout is the value.
+    #[argh(option)]
+    measure: String,
+
+    /// number of measurements on each DUT (default: 10)
+    #[argh(option, default = "10")]
+    times: usize,
+}
+
+/// Summary statistics of measured values
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Summary {
+    n: usize,
+    mean: f64,
+    /// Sample standard deviation
+    stddev: f64,
+    median: f64,
+    min: f64,
+    max: f64,
+}
+impl Summary {
+    fn new(values: &[f64]) -> Option<Self> {
+        if values.is_empty() {
+            return None;
+        }
+        let n = values.len();
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let var = if n < 2 {
+            0.0
+        } else {
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1) as f64
+        };
+        let mut sorted = values.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let median = if n % 2 == 0 {
+            (sorted[n / 2 - 1] + sorted[n / 2]) / 2.0
+        } else {
+            sorted[n / 2]
+        };
+        Some(Self {
+            n,
+            mean,
+            stddev: var.sqrt(),
+            median,
+            min: sorted[0],
+            max: sorted[n - 1],
+        })
+    }
+}
+
+/// ln(Gamma(x)) by the Lanczos approximation
+fn ln_gamma(x: f64) -> f64 {
+    const G: [f64; 6] = [
+        76.18009172947146,
+        -86.50532032941677,
+        24.01409824083091,
+        -1.231739572450155,
+        1.208_650_973_866_179e-3,
+        -5.395_239_384_953e-6,
+    ];
+    let tmp = x + 5.5 - (x + 0.5) * (x + 5.5).ln();
+    let ser = G
+        .iter()
+        .enumerate()
+        .fold(1.000000000190015, |s, (i, g)| s + g / (x + 1.0 + i as f64));
+    -tmp + (2.5066282746310005 * ser / x).ln()
+}
+
+/// The regularized incomplete beta function I_x(a, b), by the continued fraction
+fn incomplete_beta(x: f64, a: f64, b: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+    if x > (a + 1.0) / (a + b + 2.0) {
+        return 1.0 - incomplete_beta(1.0 - x, b, a);
+    }
+    let front =
+        (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b) + a * x.ln() + b * (1.0 - x).ln()).exp() / a;
+    const TINY: f64 = 1e-30;
+    let (mut c, mut d) = (1.0, 1.0 - (a + b) * x / (a + 1.0));
+    d = 1.0 / if d.abs() < TINY { TINY } else { d };
+    let mut f = d;
+    for m in 1..200 {
+        let m = m as f64;
+        for numerator in [
+            m * (b - m) * x / ((a + 2.0 * m - 1.0) * (a + 2.0 * m)),
+            -(a + m) * (a + b + m) * x / ((a + 2.0 * m) * (a + 2.0 * m + 1.0)),
+        ] {
+            d = 1.0 + numerator * d;
+            d = 1.0 / if d.abs() < TINY { TINY } else { d };
+            c = 1.0 + numerator / c;
+            c = if c.abs() < TINY { TINY } else { c };
+            f *= c * d;
+        }
+        if (c * d - 1.0).abs() < 1e-12 {
+            break;
+        }
+    }
+    front * f
+}
+
+/// Two-sided p-value of Welch's t-test. None if it cannot be estimated (e.g. too few samples).
+fn welch_p_value(a: &Summary, b: &Summary) -> Option<f64> {
+    if a.n < 2 || b.n < 2 {
+        return None;
+    }
+    let (va, vb) = (a.stddev.powi(2) / a.n as f64, b.stddev.powi(2) / b.n as f64);
+    if va + vb == 0.0 {
+        return if a.mean == b.mean {
+            Some(1.0)
+        } else {
+            Some(0.0)
+        };
+    }
+    let t = (a.mean - b.mean) / (va + vb).sqrt();
+    let df = (va + vb).powi(2) / (va.powi(2) / (a.n - 1) as f64 + vb.powi(2) / (b.n - 1) as f64);
+    Some(incomplete_beta(df / (df + t * t), df / 2.0, 0.5))
+}
+
+fn parse_value(output: &str) -> Result<f64> {
+    let line = output
+        .lines()
+        .rev()
+        .find(|l| !l.trim().is_empty())
+        .context("The measurement printed nothing")?;
+    line.trim().parse().context(anyhow!(
+        "The last line of the measurement is not a number: {line:?}"
+    ))
+}
+
+/// Prepares a DUT and returns the command to measure on it
+fn prepare(ssh: &SshInfo, setup: &Option<String>, measure: &str) -> Result<String> {
+    if let Some(setup) = setup {
+        status!("Setting up {ssh:?}: {setup}");
+        ssh.run_cmd_stdio(setup)
+            .context(anyhow!("Failed to set up {ssh:?}"))?;
+    }
+    if !Path::new(measure).is_file() {
+        return Ok(measure.to_string());
+    }
+    let name = Path::new(measure)
+        .file_name()
+        .context("Invalid measure script")?
+        .to_string_lossy();
+    let dest = "/usr/local/tmp/".to_string();
+    ssh.run_cmd_stdio(&format!("mkdir -p {dest}"))?;
+    ssh.send_files(&[measure.to_string()], Some(&dest))?;
+    Ok(format!("chmod +x '{dest}{name}' && '{dest}{name}'"))
+}
+
+fn print_summary(a: &Summary, b: &Summary) {
+    println!("{:8} {:>14} {:>14}", "", "A", "B");
+    println!("{:8} {:>14} {:>14}", "n", a.n, b.n);
+    for (label, va, vb) in [
+        ("mean", a.mean, b.mean),
+        ("stddev", a.stddev, b.stddev),
+        ("median", a.median, b.median),
+        ("min", a.min, b.min),
+        ("max", a.max, b.max),
+    ] {
+        println!("{label:8} {va:>14.4} {vb:>14.4}");
+    }
+    let diff = b.mean - a.mean;
+    if a.mean != 0.0 {
+        println!(
+            "diff (B - A): {diff:+.4} ({:+.2}%)",
+            diff * 100.0 / a.mean.abs()
+        );
+    } else {
+        println!("diff (B - A): {diff:+.4}");
+    }
+    match welch_p_value(a, b) {
+        Some(p) => println!(
+            "p-value (Welch's t-test): {p:.4} ({})",
+            if p < 0.05 {
+                "significant at 5%"
+            } else {
+                "not significant at 5%"
+            }
+        ),
+        None => println!("p-value: not enough samples"),
+    }
+}
+
+fn run_ab(args: &ArgsAb) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let ssh_a = SshInfo::new(&args.dut_a)?;
+    let ssh_b = SshInfo::new(&args.dut_b)?;
+    let cmd_a = prepare(&ssh_a, &args.setup_a, &args.measure)?;
+    let cmd_b = prepare(&ssh_b, &args.setup_b, &args.measure)?;
+    cancel::install_handler()?;
+    let progress = Progress::new("Measuring", args.times as u64);
+    let (mut values_a, mut values_b) = (Vec::new(), Vec::new());
+    for i in 1..=args.times {
+        if cancel::is_cancelled() {
+            warning!("Interrupted after {} measurements", i - 1);
+            break;
+        }
+        // Measure both sides at the same time so that external conditions affect them equally
+        let (a, b) = thread::scope(|s| {
+            let a = s.spawn(|| ssh_a.run_cmd_stdio(&cmd_a));
+            let b = s.spawn(|| ssh_b.run_cmd_stdio(&cmd_b));
+            (a.join().unwrap(), b.join().unwrap())
+        });
+        progress.inc(1);
+        for (name, output, values) in [("A", a, &mut values_a), ("B", b, &mut values_b)] {
+            match output.and_then(|o| parse_value(&o)) {
+                Ok(v) => values.push(v),
+                Err(_) if cancel::is_cancelled() => {}
+                Err(e) => warning!("[{i}/{}] {name} failed: {e:#}", args.times),
+            }
+        }
+    }
+    drop(progress);
+    match (Summary::new(&values_a), Summary::new(&values_b)) {
+        (Some(a), Some(b)) => print_summary(&a, &b),
+        _ => return Err(anyhow!("No successful measurements on one of the DUTs")),
+    }
+    cancel::check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summary_and_p_value() {
+        let a = Summary::new(&[1.0, 2.0, 3.0, 4.0]).unwrap();
+        assert_eq!(a.mean, 2.5);
+        assert_eq!(a.median, 2.5);
+        assert!((a.stddev - 1.2910).abs() < 1e-4);
+        assert!(Summary::new(&[]).is_none());
+
+        let same = welch_p_value(&a, &a).unwrap();
+        assert!((same - 1.0).abs() < 1e-9);
+        // t = -2.19 with 6 degrees of freedom
+        let b = Summary::new(&[3.0, 4.0, 5.0, 6.0]).unwrap();
+        let p = welch_p_value(&a, &b).unwrap();
+        assert!((p - 0.0710).abs() < 1e-3, "p = {p}");
+    }
+
+    #[test]
+    fn parse_measurement() {
+        assert_eq!(parse_value("warming up\n12.5\n\n").unwrap(), 12.5);
+        assert!(parse_value("done").is_err());
+        assert!(parse_value("").is_err());
+    }
+}
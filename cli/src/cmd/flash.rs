@@ -10,9 +10,16 @@ use anyhow::Context;
 use anyhow::Result;
 use argh::FromArgs;
 use lium::cros::ensure_testing_rsa_is_there;
+use lium::delta::delta_flash;
 use lium::dut::DutInfo;
+use lium::dut::SshInfo;
+use lium::notify::notify_result;
+use lium::policy::Operation;
+use lium::progress::Progress;
 use lium::repo::get_repo_dir;
+use lium::util::ensure_online;
 use regex::Regex;
+use std::path::Path;
 use std::process::Command;
 
 #[derive(FromArgs, PartialEq, Debug)]
@@ -50,8 +57,35 @@ pub struct Args {
     /// flash image with rootfs verification (disable by default)
     #[argh(switch)]
     enable_rootfs_verification: bool,
+
+    /// flash the kernel and the rootfs of a local image (e.g. chromiumos_test_image.bin) to the
+    /// inactive slot of --dut, sending only a delta against the image flashed last time with
+    /// --delta. the stateful partition is kept.
+    #[argh(option)]
+    delta: Option<String>,
+
+    /// notify when finished, e.g. desktop,bell (default: `lium config set notify`)
+    #[argh(option)]
+    notify: Option<String>,
 }
 pub fn run(args: &Args) -> Result<()> {
+    let result = run_flash(args);
+    notify_result("flash", &result, &args.notify);
+    result
+}
+/// Returns the operation to check with the policy, and the target DUT
+pub fn guarded_operation(args: &Args) -> Option<(Operation, Option<&str>)> {
+    args.dut
+        .as_deref()
+        .map(|dut| (Operation::OsFlash, Some(dut)))
+}
+fn run_flash(args: &Args) -> Result<()> {
+    if let Some(image) = &args.delta {
+        let dut = args.dut.as_ref().context("--delta needs --dut")?;
+        ensure_testing_rsa_is_there()?;
+        let ssh = SshInfo::new(dut)?;
+        return delta_flash(&ssh, Path::new(image), args.enable_rootfs_verification);
+    }
     // repo path is needed since cros flash outside chroot only works within the cros checkout
     let repo = &get_repo_dir(&args.repo)?;
 
@@ -60,7 +94,9 @@ pub fn run(args: &Args) -> Result<()> {
         (Some(board), None) => board.clone(),
         (_, Some(dut)) => {
             ensure_testing_rsa_is_there()?;
+            let spinner = Progress::spinner(&format!("Connecting to {dut}"));
             let dut = &DutInfo::new(dut)?;
+            drop(spinner);
             let board_from_dut = dut
                 .info()
                 .get("board")
@@ -116,20 +152,39 @@ pub fn run(args: &Args) -> Result<()> {
     let destination = match (&args.dut, args.usb) {
         (Some(dut), false) => {
             ensure_testing_rsa_is_there()?;
+            let spinner = Progress::spinner(&format!("Connecting to {dut}"));
             let dut = &DutInfo::new(dut)?;
+            drop(spinner);
             dut.ssh().host_and_port()
         }
         (None, true) => "usb://".to_string(),
         _ => return Err(anyhow!("Please specify either --dut or --usb")),
     };
 
+    cros_flash(
+        repo,
+        &destination,
+        &image_path,
+        args.enable_rootfs_verification,
+    )
+}
+
+/// Run `cros flash` to write `image_path` (xBuddy path) to `destination`.
+/// Returns an error if the flash failed.
+pub fn cros_flash(
+    repo: &str,
+    destination: &str,
+    image_path: &str,
+    enable_rootfs_verification: bool,
+) -> Result<()> {
+    ensure_online("cros flash")?;
     let mut cmd_args: Vec<&str> =
         Vec::from(["flash", "--clobber-stateful", "--clear-tpm-owner", "-vvv"]);
-    if !args.enable_rootfs_verification {
+    if !enable_rootfs_verification {
         cmd_args.push("--disable-rootfs-verification");
     }
-    cmd_args.push(&destination);
-    cmd_args.push(&image_path);
+    cmd_args.push(destination);
+    cmd_args.push(image_path);
 
     let cmd = Command::new("cros")
         .current_dir(repo)
@@ -138,6 +193,7 @@ pub fn run(args: &Args) -> Result<()> {
     let result = cmd.wait_with_output()?;
     if !result.status.success() {
         println!("cros sdk failed");
+        return Err(anyhow!("cros flash failed"));
     }
     Ok(())
 }
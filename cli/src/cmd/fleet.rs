@@ -0,0 +1,75 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::Result;
+use argh::FromArgs;
+use lium::fleet::insert_into_bigquery;
+use lium::fleet::take_snapshot;
+use lium::output;
+use lium::status;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// commands for all the DUTs in the DUT list
+#[argh(subcommand, name = "fleet")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Snapshot(ArgsSnapshot),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Snapshot(args) => run_snapshot(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// check all the DUTs and record their status and info with a schema version, e.g. from cron:
+/// `0 * * * * lium -q fleet snapshot --to gs://lab-status/`
+#[argh(subcommand, name = "snapshot")]
+struct ArgsSnapshot {
+    /// where to write the snapshot: a path, gs://bucket/key or http(s)://collector. a name
+    /// with the time is appended if it ends with '/'. (default: stdout unless --bigquery)
+    #[argh(option)]
+    to: Option<String>,
+
+    /// insert a row per DUT into the BigQuery table (e.g. lab.fleet_status) with bq
+    #[argh(option)]
+    bigquery: Option<String>,
+}
+fn run_snapshot(args: &ArgsSnapshot) -> Result<()> {
+    let snapshot = take_snapshot()?;
+    let online = snapshot
+        .duts
+        .iter()
+        .filter(|d| d.state.status == "Online")
+        .count();
+    status!("{online} of {} DUTs are online", snapshot.duts.len());
+    if let Some(table) = &args.bigquery {
+        insert_into_bigquery(&snapshot, table)?;
+        status!("Inserted {} rows into {table}", snapshot.duts.len());
+    }
+    if args.to.is_none() && args.bigquery.is_some() {
+        return Ok(());
+    }
+    let dest = args.to.as_ref().map(|to| {
+        if to.ends_with('/') {
+            format!("{to}{}", snapshot.file_name())
+        } else {
+            to.clone()
+        }
+    });
+    let mut out = output::open(dest.as_deref())?;
+    out.write_line(&serde_json::to_string_pretty(&snapshot)?)?;
+    out.finish()?;
+    if let Some(dest) = dest {
+        status!("Wrote the snapshot to {dest}");
+    }
+    Ok(())
+}
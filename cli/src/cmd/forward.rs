@@ -0,0 +1,191 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use lium::dut::SshInfo;
+use lium::forward::read_state;
+use lium::forward::remove_state;
+use lium::forward::supervise;
+use lium::forward::ForwardSpec;
+use lium::forward::ForwardStatus;
+use lium::forward::FORWARDS;
+use lium::status;
+use lium::ui::Table;
+use lium::util::spawn_lium_in_background;
+use nix::sys::signal::kill;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use std::net::TcpListener;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// manage port forwardings to DUTs that keep running in the background
+#[argh(subcommand, name = "forward")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Add(ArgsAdd),
+    List(ArgsList),
+    Rm(ArgsRm),
+    Supervise(ArgsSupervise),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Add(args) => run_add(args),
+        SubCommand::List(args) => run_list(args),
+        SubCommand::Rm(args) => run_rm(args),
+        SubCommand::Supervise(args) => supervise(&args.name),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// start a named forwarding of a port on the DUT to a local port
+#[argh(subcommand, name = "add")]
+struct ArgsAdd {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// local port to listen on
+    #[argh(option)]
+    local: u16,
+
+    /// port on the DUT
+    #[argh(option)]
+    remote: u16,
+
+    /// name of the forwarding
+    #[argh(option)]
+    name: String,
+}
+fn run_add(args: &ArgsAdd) -> Result<()> {
+    if FORWARDS.get(&args.name)?.is_some() {
+        return Err(anyhow!(
+            "Forward {} already exists. Remove it with `lium forward rm {}` first",
+            args.name,
+            args.name
+        ));
+    }
+    // Fail early instead of letting the supervisor retry forever
+    SshInfo::new(&args.dut)?;
+    TcpListener::bind(("127.0.0.1", args.local))
+        .context(anyhow!("Local port {} is not available", args.local))?;
+    FORWARDS.set(
+        &args.name,
+        ForwardSpec {
+            dut: args.dut.clone(),
+            local: args.local,
+            remote: args.remote,
+        },
+    )?;
+    spawn_lium_in_background(&["forward", "supervise", &args.name])?;
+    status!("Waiting for {} to be up...", args.name);
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while Instant::now() < deadline {
+        match read_state(&args.name) {
+            Some(state) if state.status == ForwardStatus::Up => {
+                println!(
+                    "{}: 127.0.0.1:{} -> {}:{}",
+                    args.name, args.local, args.dut, args.remote
+                );
+                return Ok(());
+            }
+            Some(state) if state.status == ForwardStatus::Restarting => {
+                return Err(anyhow!(
+                    "Forward {} failed: {}. It is retried in the background (see `lium forward list`)",
+                    args.name,
+                    state.last_error.unwrap_or_default()
+                ));
+            }
+            _ => thread::sleep(Duration::from_millis(200)),
+        }
+    }
+    Err(anyhow!(
+        "Forward {} is not up yet. See `lium forward list`",
+        args.name
+    ))
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list the forwardings and their status
+#[argh(subcommand, name = "list")]
+struct ArgsList {}
+fn run_list(_args: &ArgsList) -> Result<()> {
+    let mut forwards: Vec<(String, ForwardSpec)> = FORWARDS.entries()?.into_iter().collect();
+    forwards.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut table = Table::new();
+    table.push(["NAME", "LOCAL", "DUT", "STATUS", "RESTARTS", "ERROR"]);
+    for (name, spec) in forwards {
+        let state = read_state(&name);
+        let status = match &state {
+            Some(state) => {
+                let secs = Local::now().timestamp() - state.since;
+                format!("{} ({}s)", state.status, secs)
+            }
+            None => "stopped".to_string(),
+        };
+        table.push([
+            name,
+            spec.local.to_string(),
+            format!("{}:{}", spec.dut, spec.remote),
+            status,
+            state
+                .as_ref()
+                .map(|s| s.restarts.to_string())
+                .unwrap_or_default(),
+            // The last line of ssh errors is usually the most specific one
+            state
+                .and_then(|s| s.last_error)
+                .and_then(|e| e.lines().last().map(|l| l.to_string()))
+                .unwrap_or_default(),
+        ]);
+    }
+    for line in table.lines("  ") {
+        println!("{line}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// stop and remove a forwarding
+#[argh(subcommand, name = "rm")]
+struct ArgsRm {
+    /// name of the forwarding
+    #[argh(positional)]
+    name: String,
+}
+fn run_rm(args: &ArgsRm) -> Result<()> {
+    FORWARDS
+        .remove(&args.name)?
+        .context(anyhow!("Forward {} does not exist", args.name))?;
+    if let Some(state) = read_state(&args.name) {
+        kill(Pid::from_raw(state.pid as i32), Signal::SIGTERM)
+            .context("Failed to stop the supervisor")?;
+    } else {
+        remove_state(&args.name)?;
+    }
+    status!("Removed {}", args.name);
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run the supervisor of a forwarding in the foreground (started by add)
+#[argh(subcommand, name = "supervise")]
+struct ArgsSupervise {
+    /// name of the forwarding
+    #[argh(positional)]
+    name: String,
+}
@@ -0,0 +1,169 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::dut::known_duts;
+use lium::events;
+use lium::status;
+use lium::warning;
+use lium::webhook::listen_token;
+use lium::webhook::read_request;
+use lium::webhook::write_response;
+use lium::webhook::ActionRequest;
+use lium::webhook::HttpError;
+use serde_json::json;
+use std::env::current_exe;
+use std::io::BufReader;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// serve an authenticated HTTP endpoint to trigger allowed actions on DUTs (see `lium dut do`).
+/// requests are `POST /actions/<action>` with `{{"dut": "<DUT ID>"}}` and
+/// `Authorization: Bearer <token>`, where the token is $LIUM_LISTEN_TOKEN or in
+/// ~/.lium/listen_token. the traffic is not encrypted, so use it on trusted networks or behind
+/// a TLS proxy.
+#[argh(subcommand, name = "listen")]
+pub struct Args {
+    /// port to listen on
+    #[argh(option, default = "9000")]
+    port: u16,
+
+    /// address to listen on (default: 127.0.0.1). use 0.0.0.0 to accept other machines.
+    #[argh(option, default = "String::from(\"127.0.0.1\")")]
+    bind: String,
+
+    /// comma-separated actions that can be triggered (e.g. reboot,collect_logs)
+    #[argh(option)]
+    allow_action: String,
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    let allowed: Vec<String> = args
+        .allow_action
+        .split(',')
+        .map(|a| a.trim().to_string())
+        .filter(|a| !a.is_empty())
+        .collect();
+    let known = known_actions()?;
+    if let Some(a) = allowed.iter().find(|a| !known.contains(a)) {
+        return Err(anyhow!(
+            "Unknown action {a:?}. Available actions: {}",
+            known.join(" ")
+        ));
+    }
+    let token = listen_token()?;
+    let listener = TcpListener::bind((args.bind.as_str(), args.port)).context(anyhow!(
+        "Failed to listen on {}:{}",
+        args.bind,
+        args.port
+    ))?;
+    status!(
+        "Listening on {}:{} for {}",
+        args.bind,
+        args.port,
+        allowed.join(", ")
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warning!("Failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        let (allowed, token) = (allowed.clone(), token.clone());
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &allowed, &token) {
+                warning!("Failed to respond to a client: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Returns the actions of `lium dut do`
+fn known_actions() -> Result<Vec<String>> {
+    let output = Command::new(current_exe()?)
+        .args(["dut", "do", "--list-actions"])
+        .output()?;
+    output
+        .status
+        .exit_ok()
+        .context("Failed to list the actions")?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect())
+}
+
+fn handle_client(stream: TcpStream, allowed: &[String], token: &str) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut writer = stream.try_clone()?;
+    let request = read_request(&mut BufReader::new(stream), token).and_then(|r| {
+        if !allowed.contains(&r.action) {
+            return Err(HttpError {
+                status: 403,
+                message: format!("Action {} is not allowed", r.action),
+            });
+        }
+        // Do not let the clients reach arbitrary hosts via this machine
+        if !known_duts().map_or(false, |e| e.contains_key(&r.dut)) {
+            return Err(HttpError {
+                status: 404,
+                message: format!("{} is not in the DUT list", r.dut),
+            });
+        }
+        Ok(r)
+    });
+    let request = match request {
+        Ok(r) => r,
+        Err(e) => {
+            status!("{peer}: rejected: {}", e.message);
+            return write_response(&mut writer, e.status, &json!({ "error": e.message }));
+        }
+    };
+    let (ok, output) = run_action(&request)?;
+    events::emit(
+        &request.dut,
+        "remote_action",
+        &format!(
+            "{} requested by {peer} {}",
+            request.action,
+            if ok { "succeeded" } else { "failed" }
+        ),
+    )?;
+    write_response(
+        &mut writer,
+        if ok { 200 } else { 500 },
+        &json!({ "ok": ok, "output": output }),
+    )
+}
+
+/// Runs the action in a separate lium process, so that it is recorded in the command log
+fn run_action(request: &ActionRequest) -> Result<(bool, String)> {
+    let output = Command::new(current_exe()?)
+        .args([
+            "--porcelain",
+            "dut",
+            "do",
+            "--dut",
+            &request.dut,
+            &request.action,
+        ])
+        .output()
+        .context("Failed to run lium dut do")?;
+    let text = String::from_utf8_lossy(&output.stdout).to_string()
+        + String::from_utf8_lossy(&output.stderr).as_ref();
+    Ok((output.status.success(), text.trim().to_string()))
+}
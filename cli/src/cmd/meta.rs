@@ -0,0 +1,300 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Self-description of the CLI for external tools. The tree is built from the help of each
+//! subcommand generated by argh, so it always matches the installed version.
+
+use crate::cmd;
+use crate::cmd::plugin::Plugin;
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::DynamicSubCommand;
+use argh::FromArgs;
+use serde::Serialize;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// describe the CLI itself
+#[argh(subcommand, name = "meta")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Schema(ArgsSchema),
+    Validate(ArgsValidate),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Schema(args) => run_schema(args),
+        SubCommand::Validate(args) => run_validate(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// print the tree of subcommands and their flags as JSON
+#[argh(subcommand, name = "schema")]
+struct ArgsSchema {
+    /// print the schema of this subcommand only (e.g. "dut shell")
+    #[argh(option)]
+    command: Option<String>,
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// check if the arguments are valid for this version of lium, without running the command
+#[argh(subcommand, name = "validate")]
+struct ArgsValidate {
+    /// arguments after `lium` (use -- before them)
+    #[argh(positional)]
+    args: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct OptionSchema {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    short: Option<String>,
+    description: String,
+    takes_value: bool,
+    required: bool,
+    repeated: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct PositionalSchema {
+    name: String,
+    description: String,
+    required: bool,
+    repeated: bool,
+}
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+struct CommandSchema {
+    /// the command path (e.g. ["dut", "shell"])
+    path: Vec<String>,
+    usage: String,
+    description: String,
+    options: Vec<OptionSchema>,
+    positionals: Vec<PositionalSchema>,
+    subcommands: Vec<CommandSchema>,
+    /// true for external plugins, whose flags are unknown
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    plugin: bool,
+}
+
+/// Splits the entries of a section (e.g. "Options:") into (name, description)
+fn parse_entries(lines: &[&str]) -> Vec<(String, String)> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    for line in lines {
+        let indent = line.len() - line.trim_start().len();
+        match entries.last_mut() {
+            // Continuation of the description
+            Some((_, desc)) if indent > 2 => {
+                if !desc.is_empty() {
+                    desc.push(' ');
+                }
+                desc.push_str(line.trim());
+            }
+            _ => {
+                let line = line.trim();
+                let (name, desc) = match line.find("  ") {
+                    Some(i) => (&line[..i], line[i..].trim()),
+                    None => (line, ""),
+                };
+                entries.push((name.to_string(), desc.to_string()));
+            }
+        }
+    }
+    entries
+}
+
+/// Parses the help output of argh
+fn parse_help(path: &[String], help: &str) -> CommandSchema {
+    let mut schema = CommandSchema {
+        path: path.to_vec(),
+        ..Default::default()
+    };
+    let mut paragraphs: Vec<Vec<&str>> = vec![Vec::new()];
+    for line in help.lines() {
+        if line.trim().is_empty() {
+            paragraphs.push(Vec::new());
+        } else if let Some(p) = paragraphs.last_mut() {
+            p.push(line);
+        }
+    }
+    for p in paragraphs.iter().filter(|p| !p.is_empty()) {
+        match p[0] {
+            usage if usage.starts_with("Usage: ") => {
+                schema.usage = usage.trim_start_matches("Usage: ").to_string();
+            }
+            "Options:" => {
+                for (name, description) in parse_entries(&p[1..]) {
+                    let (short, name) = match name.split_once(", ") {
+                        Some((short, long)) => (Some(short.to_string()), long.to_string()),
+                        None => (None, name),
+                    };
+                    let usage = &schema.usage;
+                    schema.options.push(OptionSchema {
+                        takes_value: usage.contains(&format!("{name} <")),
+                        required: usage.contains(&format!("{name} <"))
+                            && !usage.contains(&format!("[{name} <")),
+                        repeated: usage.contains(&format!("{name} <"))
+                            && usage.contains(&format!("{name} <{}...>", &name[2..])),
+                        name,
+                        short,
+                        description,
+                    });
+                }
+            }
+            "Positional Arguments:" => {
+                for (name, description) in parse_entries(&p[1..]) {
+                    let usage = &schema.usage;
+                    schema.positionals.push(PositionalSchema {
+                        required: !usage.contains(&format!("[<{name}")),
+                        repeated: usage.contains(&format!("<{name}...>")),
+                        name,
+                        description,
+                    });
+                }
+            }
+            "Commands:" => {
+                // Filled by the caller
+            }
+            _ if schema.description.is_empty() => {
+                schema.description = p.iter().map(|l| l.trim()).collect::<Vec<_>>().join(" ");
+            }
+            _ => {}
+        }
+    }
+    schema
+}
+
+/// Returns the names of the subcommands in the help output
+fn parse_subcommand_names(help: &str) -> Vec<String> {
+    let lines: Vec<&str> = help
+        .lines()
+        .skip_while(|l| *l != "Commands:")
+        .skip(1)
+        .take_while(|l| !l.trim().is_empty())
+        .collect();
+    parse_entries(&lines).into_iter().map(|(n, _)| n).collect()
+}
+
+fn help_of(path: &[String]) -> Result<String> {
+    let mut args: Vec<&str> = path.iter().map(|s| s.as_str()).collect();
+    args.push("--help");
+    match cmd::TopLevel::from_args(&["lium"], &args) {
+        Err(early_exit) if early_exit.status.is_ok() => Ok(early_exit.output),
+        _ => Err(anyhow!("Failed to get the help of {path:?}")),
+    }
+}
+
+fn build_schema(path: &[String]) -> Result<CommandSchema> {
+    let help = help_of(path)?;
+    let mut schema = parse_help(path, &help);
+    let plugins: Vec<&str> = if path.is_empty() {
+        Plugin::commands().iter().map(|c| c.name).collect()
+    } else {
+        Vec::new()
+    };
+    for name in parse_subcommand_names(&help) {
+        let mut sub_path = path.to_vec();
+        sub_path.push(name.clone());
+        if plugins.contains(&name.as_str()) {
+            schema.subcommands.push(CommandSchema {
+                path: sub_path,
+                plugin: true,
+                ..Default::default()
+            });
+            continue;
+        }
+        schema.subcommands.push(build_schema(&sub_path)?);
+    }
+    Ok(schema)
+}
+
+fn run_schema(args: &ArgsSchema) -> Result<()> {
+    let path: Vec<String> = args
+        .command
+        .as_deref()
+        .unwrap_or_default()
+        .split_whitespace()
+        .map(|s| s.to_string())
+        .collect();
+    println!("{}", serde_json::to_string_pretty(&build_schema(&path)?)?);
+    Ok(())
+}
+
+fn run_validate(args: &ArgsValidate) -> Result<()> {
+    let args: Vec<&str> = args.args.iter().map(|s| s.as_str()).collect();
+    match cmd::TopLevel::from_args(&["lium"], &args) {
+        Ok(_) => {
+            println!("{}", serde_json::json!({ "valid": true }));
+            Ok(())
+        }
+        Err(early_exit) => {
+            let valid = early_exit.status.is_ok();
+            println!(
+                "{}",
+                serde_json::json!({ "valid": valid, "message": early_exit.output.trim() })
+            );
+            if valid {
+                Ok(())
+            } else {
+                Err(anyhow!("Invalid arguments"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn help_schema() {
+        let help = "Usage: lium x run <file> [<args...>] --dut <dut> [--set <set...>] [--keep]
+
+run something
+on the DUT
+
+Positional Arguments:
+  file              path to the script
+  args              arguments
+
+Options:
+  --dut             a DUT identifier (e.g. 127.0.0.1,
+                    localhost:2222)
+  --set             a variable. can be repeated.
+  --keep            keep it
+  --help            display usage information
+
+Commands:
+  a                 do a
+  bb                do b
+";
+        let s = parse_help(&["x".to_string(), "run".to_string()], help);
+        assert_eq!(s.description, "run something on the DUT");
+        assert_eq!(s.positionals.len(), 2);
+        assert!(s.positionals[0].required && !s.positionals[0].repeated);
+        assert!(!s.positionals[1].required && s.positionals[1].repeated);
+        assert_eq!(
+            s.options[0],
+            OptionSchema {
+                name: "--dut".to_string(),
+                short: None,
+                description: "a DUT identifier (e.g. 127.0.0.1, localhost:2222)".to_string(),
+                takes_value: true,
+                required: true,
+                repeated: false,
+            }
+        );
+        assert!(s.options[1].takes_value && !s.options[1].required && s.options[1].repeated);
+        assert!(!s.options[2].takes_value);
+        assert_eq!(parse_subcommand_names(help), vec!["a", "bb"]);
+    }
+}
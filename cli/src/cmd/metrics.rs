@@ -0,0 +1,230 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use super::TopLevel;
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use chrono::TimeZone;
+use lium::metrics::buffered_events;
+use lium::metrics::command_stats;
+use lium::metrics::flush_events;
+use lium::metrics::metrics_mode;
+use lium::metrics::record_usage;
+use lium::metrics::set_metrics_mode;
+use lium::metrics::MetricsMode;
+use lium::metrics::METRICS_STATS;
+use lium::status;
+use lium::util::spawn_lium_in_background;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// opt-in usage metrics (subcommand, duration and result only; never arguments or DUTs)
+#[argh(subcommand, name = "metrics")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Disable(ArgsDisable),
+    Enable(ArgsEnable),
+    Flush(ArgsFlush),
+    Show(ArgsShow),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Disable(args) => run_disable(args),
+        SubCommand::Enable(args) => run_enable(args),
+        SubCommand::Flush(args) => run_flush(args),
+        SubCommand::Show(args) => run_show(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// start recording usage metrics
+#[argh(subcommand, name = "enable")]
+pub struct ArgsEnable {
+    /// only keep the stats on this machine (see `lium metrics show`)
+    #[argh(switch)]
+    local_only: bool,
+
+    /// also send the events in batches to the endpoint (POST of a JSON array)
+    #[argh(option)]
+    url: Option<String>,
+}
+fn run_enable(args: &ArgsEnable) -> Result<()> {
+    let mode = match (&args.url, args.local_only) {
+        (Some(url), false) => {
+            if !url.starts_with("https://") && !url.starts_with("http://") {
+                return Err(anyhow!("--url should start with https:// or http://"));
+            }
+            MetricsMode::Upload(url.clone())
+        }
+        (None, true) => MetricsMode::LocalOnly,
+        _ => return Err(anyhow!("Specify either --local-only or --url")),
+    };
+    set_metrics_mode(Some(mode))?;
+    status!("Enabled the usage metrics. Thank you!");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// stop recording usage metrics and drop the events not sent yet
+#[argh(subcommand, name = "disable")]
+pub struct ArgsDisable {
+    /// also delete the local stats
+    #[argh(switch)]
+    purge: bool,
+}
+fn run_disable(args: &ArgsDisable) -> Result<()> {
+    set_metrics_mode(None)?;
+    if args.purge {
+        METRICS_STATS.clear()?;
+    }
+    status!("Disabled the usage metrics");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the settings and the local stats
+#[argh(subcommand, name = "show")]
+pub struct ArgsShow {}
+fn run_show(_args: &ArgsShow) -> Result<()> {
+    match metrics_mode()? {
+        None => println!("mode: disabled"),
+        Some(MetricsMode::LocalOnly) => println!("mode: local only"),
+        Some(MetricsMode::Upload(url)) => {
+            println!("mode: upload to {url}");
+            println!("buffered events: {}", buffered_events()?.len());
+        }
+    }
+    let mut stats: Vec<_> = command_stats()?.into_iter().collect();
+    if stats.is_empty() {
+        return Ok(());
+    }
+    stats.sort_by(|a, b| b.1.count.cmp(&a.1.count).then(a.0.cmp(&b.0)));
+    println!(
+        "\n{:24} {:>6} {:>8} {:>9} last used",
+        "command", "count", "failures", "avg secs"
+    );
+    for (command, s) in stats {
+        let last_used = Local
+            .timestamp_opt(s.last_used, 0)
+            .single()
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        println!(
+            "{command:24} {:>6} {:>8} {:>9.1} {last_used}",
+            s.count,
+            s.failures,
+            s.total_secs / s.count.max(1) as f64
+        );
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// send the buffered events now
+#[argh(subcommand, name = "flush")]
+pub struct ArgsFlush {}
+fn run_flush(_args: &ArgsFlush) -> Result<()> {
+    let sent = flush_events()?;
+    status!("Sent {sent} events");
+    Ok(())
+}
+
+/// Returns whether the top-level option takes a value (e.g. --color never), as told by argh
+fn takes_value(option: &str) -> bool {
+    matches!(
+        TopLevel::from_args(&["lium"], &[option]),
+        Err(e) if e.output.starts_with("No value provided for option")
+    )
+}
+
+/// Returns the subcommand (e.g. "dut info") of the command line without any argument. Positional
+/// arguments are told apart from nested subcommands by asking argh for the help of the command.
+fn command_name(args: &[String]) -> String {
+    let mut words: Vec<&str> = Vec::new();
+    let mut it = args.iter().skip(1);
+    while let Some(a) = it.next() {
+        if a.starts_with('-') {
+            if takes_value(a) {
+                it.next();
+            }
+        } else if !a.starts_with('-') {
+            words.push(a);
+            break;
+        }
+    }
+    let Some(top) = words.first().copied() else {
+        return String::new();
+    };
+    if !<super::Args as argh::SubCommands>::COMMANDS
+        .iter()
+        .any(|c| c.name == top)
+    {
+        // Names of plugins can be anything
+        return "plugin".to_string();
+    }
+    let mut name = top.to_string();
+    for next in it.filter(|a| !a.starts_with('-')).take(2) {
+        let candidate = format!("{name} {next}");
+        let mut help_args: Vec<&str> = candidate.split(' ').collect();
+        help_args.push("--help");
+        match TopLevel::from_args(&["lium"], &help_args) {
+            Err(e) if e.output.starts_with(&format!("Usage: lium {candidate} ")) => {
+                name = candidate
+            }
+            _ => break,
+        }
+    }
+    name
+}
+
+/// Records the command of this process if the metrics are enabled
+pub fn record(duration_secs: f64, success: bool) -> Result<()> {
+    let args: Vec<String> = std::env::args().collect();
+    let command = command_name(&args);
+    // Flushing runs `lium metrics flush`, which should not be counted in turn
+    if command.is_empty() || command.starts_with("metrics") {
+        return Ok(());
+    }
+    if record_usage(&command, duration_secs, success)? {
+        spawn_lium_in_background(&["metrics", "flush"])?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn name(args: &str) -> String {
+        let args: Vec<String> = args.split(' ').map(|s| s.to_string()).collect();
+        command_name(&args)
+    }
+
+    #[test]
+    fn command_names() {
+        assert_eq!(name("lium version"), "version");
+        assert_eq!(name("lium dut info --dut 192.168.0.2 release"), "dut info");
+        assert_eq!(
+            name("lium --color never dut certs list --dut x"),
+            "dut certs list"
+        );
+        assert_eq!(name("lium --dut-file t.yaml dut list"), "dut list");
+        assert_eq!(
+            name("lium --max-column-width 20 --no-ellipsis dut list"),
+            "dut list"
+        );
+        assert_eq!(name("lium -q --offline version"), "version");
+        assert_eq!(name("lium tast run --dut x tast.Test"), "tast run");
+        assert_eq!(name("lium some-plugin secret"), "plugin");
+        assert_eq!(name("lium"), "");
+    }
+}
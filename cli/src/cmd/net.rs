@@ -0,0 +1,196 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cancel;
+use lium::cros;
+use lium::dut::SshInfo;
+use lium::mitm::start_recorder;
+use lium::netns::BlockRule;
+use lium::netns::Sandbox;
+use lium::netns::SANDBOX_ADDR;
+use lium::proxy::set_proxy;
+use lium::proxy::unset_proxy;
+use lium::status;
+use lium::warning;
+use std::net::IpAddr;
+use std::net::TcpListener;
+use std::process::Child;
+use std::process::Stdio;
+use std::time;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// network experiments with DUTs
+#[argh(subcommand, name = "net")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Relay(ArgsRelay),
+    Sandbox(ArgsSandbox),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Relay(args) => run_relay(args),
+        SubCommand::Sandbox(args) => run_sandbox(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// route the traffic of a DUT through a network namespace on this machine with firewall rules
+/// until Ctrl-C (needs sudo)
+#[argh(subcommand, name = "sandbox")]
+pub struct ArgsSandbox {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// destination to make unreachable: host, host:port, IPv4 address or CIDR (e.g.
+    /// gstatic.com, 10.0.0.0/8:443). Can be repeated.
+    #[argh(option)]
+    block: Vec<String>,
+
+    /// drop the blocked packets silently to simulate timeouts, instead of rejecting them
+    #[argh(switch)]
+    drop: bool,
+
+    /// capture the traffic of the sandbox to the pcap file
+    #[argh(option)]
+    capture: Option<String>,
+
+    /// name server used in the sandbox (default: 8.8.8.8)
+    #[argh(option, default = "\"8.8.8.8\".to_string()")]
+    dns: String,
+
+    /// port of the proxy on the DUT (default: 8080)
+    #[argh(option, default = "8080")]
+    port: u16,
+
+    /// do not restart the UI to apply the proxy to Chrome
+    #[argh(switch)]
+    no_restart: bool,
+}
+
+/// Restores the proxy of the DUT and stops the processes in the sandbox before it is removed
+struct SandboxCleanup<'a> {
+    ssh: &'a SshInfo,
+    proxy_set: bool,
+    restart_ui: bool,
+    children: Vec<Child>,
+}
+impl Drop for SandboxCleanup<'_> {
+    fn drop(&mut self) {
+        if self.proxy_set {
+            if let Err(e) = unset_proxy(self.ssh) {
+                warning!("Failed to unset the proxy: {e:#}");
+            } else if self.restart_ui {
+                status!("Restarting the UI to apply the restored settings...");
+                let _ = self.ssh.run_cmd_stdio("restart ui");
+            }
+        }
+        for c in &mut self.children {
+            let _ = c.kill();
+            let _ = c.wait();
+        }
+    }
+}
+
+fn run_sandbox(args: &ArgsSandbox) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let name_server: IpAddr = args
+        .dns
+        .parse()
+        .context(anyhow!("Invalid name server {:?}", args.dns))?;
+    let rules = args
+        .block
+        .iter()
+        .map(|b| BlockRule::parse(b))
+        .collect::<Result<Vec<_>>>()?;
+    let ssh = SshInfo::new(&args.dut)?;
+    cancel::install_handler()?;
+
+    status!("Creating the network sandbox...");
+    let sandbox = Sandbox::create(name_server)?;
+    for rule in &rules {
+        sandbox.block(rule, args.drop)?;
+        status!("Blocked {} ({})", rule.target, rule.addrs.join(", "));
+    }
+    // Dropped before the sandbox, since the processes in it have to stop before it is removed
+    let mut cleanup = SandboxCleanup {
+        ssh: &ssh,
+        proxy_set: false,
+        restart_ui: !args.no_restart,
+        children: Vec::new(),
+    };
+    if let Some(path) = &args.capture {
+        cleanup.children.push(
+            sandbox
+                .capture_command(path)
+                .stdin(Stdio::null())
+                .spawn()
+                .context("Failed to start tcpdump")?,
+        );
+        status!("Capturing the traffic to {path}");
+    }
+    let listen = format!("{SANDBOX_ADDR}:{}", args.port);
+    cleanup.children.push(
+        sandbox
+            .lium_command(&["net", "relay", "--listen", &listen])?
+            .stdin(Stdio::null())
+            .spawn()
+            .context("Failed to start the relay in the sandbox")?,
+    );
+    let mut tunnel =
+        ssh.start_reverse_port_forwarding_to(args.port, &SANDBOX_ADDR.to_string(), args.port)?;
+    set_proxy(&ssh, &format!("http://127.0.0.1:{}", args.port))?;
+    cleanup.proxy_set = true;
+    if !args.no_restart {
+        ssh.run_cmd_stdio("restart ui")?;
+    }
+    status!(
+        "The traffic of {} goes through the sandbox now. Press Ctrl-C to stop.",
+        args.dut
+    );
+    while !cancel::is_cancelled() {
+        if let Ok(Some(status)) = tunnel.try_status() {
+            return Err(anyhow!("The ssh tunnel to the DUT exited with {status}"));
+        }
+        for c in &mut cleanup.children {
+            if let Ok(Some(status)) = c.try_wait() {
+                return Err(anyhow!("A process in the sandbox exited with {status}"));
+            }
+        }
+        cancel::sleep(time::Duration::from_secs(1)).ok();
+    }
+    status!("Stopping...");
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run the built-in recorder of `lium dut mitm` as a proxy until killed (used by `net sandbox`)
+#[argh(subcommand, name = "relay")]
+pub struct ArgsRelay {
+    /// address to listen on (e.g. 127.0.0.1:8080)
+    #[argh(option)]
+    listen: String,
+}
+
+fn run_relay(args: &ArgsRelay) -> Result<()> {
+    let listener =
+        TcpListener::bind(&args.listen).context(anyhow!("Failed to listen on {}", args.listen))?;
+    start_recorder(listener);
+    cancel::install_handler()?;
+    while !cancel::is_cancelled() {
+        cancel::sleep(time::Duration::from_secs(1)).ok();
+    }
+    Ok(())
+}
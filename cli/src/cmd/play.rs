@@ -0,0 +1,699 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Declarative playbooks of steps run against DUTs, as an alternative to shell scripts around
+//! lium. `${NAME}` in strings is replaced with the variables, and `${DUT}` with the target.
+//!
+//! ```yaml
+//! vars:
+//!   BIN: out/my_test
+//! duts: ["@lab", "192.168.0.2"]  # can be overridden with --dut
+//! steps:
+//!   - push: { src: "${BIN}", dest: /usr/local/bin }
+//!   - run: my_test --setup
+//!     retries: 2
+//!   - reboot: {}
+//!   - wait-for: { cmd: pgrep -x chrome, timeout: 120 }
+//!   - assert-info-key: { key: board, equals: eve }
+//!   - assert-info: { key: release, op: ">=", value: "15400" }
+//!   - assert-cmd: { cmd: my_test --check, exit_code: 0, stdout_matches: PASS }
+//!   - assert-file-exists: { path: /var/log/my_test.log }
+//! ```
+//!
+//! Assertions do not stop the DUT when they fail, so that all of them are reported (e.g. in the
+//! JUnit XML given with --junit to gate CI pipelines).
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cancel;
+use lium::cros;
+use lium::dut::dut_group;
+use lium::dut::DutInfo;
+use lium::dut::SshInfo;
+use lium::output;
+use lium::status;
+use lium::util::get_stderr;
+use lium::util::get_stdout;
+use regex::Regex;
+use regex_macro::regex;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run declarative playbooks against DUTs
+#[argh(subcommand, name = "play")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Run(ArgsRun),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Run(args) => run_play(args),
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct Playbook {
+    #[serde(default)]
+    vars: BTreeMap<String, String>,
+    /// DUT identifiers, or @group
+    #[serde(default)]
+    duts: Vec<String>,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+struct Step {
+    /// Shown in the progress (default: the kind of the action)
+    name: Option<String>,
+    /// Times to retry the step when it fails
+    #[serde(default)]
+    retries: u32,
+    /// Seconds to wait before a retry
+    #[serde(default = "default_retry_delay")]
+    retry_delay: u64,
+    #[serde(flatten)]
+    action: Action,
+}
+fn default_retry_delay() -> u64 {
+    5
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+enum Action {
+    Push {
+        src: String,
+        dest: Option<String>,
+    },
+    Run(String),
+    WaitFor {
+        cmd: String,
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+    },
+    Reboot {
+        #[serde(default = "default_timeout")]
+        timeout: u64,
+    },
+    AssertInfoKey {
+        key: String,
+        equals: Option<String>,
+        /// A regex
+        matches: Option<String>,
+    },
+    AssertInfo {
+        key: String,
+        op: CompareOp,
+        value: String,
+    },
+    AssertCmd {
+        cmd: String,
+        #[serde(default)]
+        exit_code: i32,
+        /// A regex
+        stdout_matches: Option<String>,
+    },
+    AssertFileExists {
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+enum CompareOp {
+    #[serde(rename = "==")]
+    Eq,
+    #[serde(rename = "!=")]
+    Ne,
+    #[serde(rename = "<")]
+    Lt,
+    #[serde(rename = "<=")]
+    Le,
+    #[serde(rename = ">")]
+    Gt,
+    #[serde(rename = ">=")]
+    Ge,
+    /// Matches a regex
+    #[serde(rename = "=~")]
+    Matches,
+}
+impl CompareOp {
+    fn symbol(&self) -> &'static str {
+        match self {
+            Self::Eq => "==",
+            Self::Ne => "!=",
+            Self::Lt => "<",
+            Self::Le => "<=",
+            Self::Gt => ">",
+            Self::Ge => ">=",
+            Self::Matches => "=~",
+        }
+    }
+    /// Compares the values as versions (e.g. 15400 < 15662.0.0) if both are dotted numbers,
+    /// otherwise as strings
+    fn compare(&self, lhs: &str, rhs: &str) -> Result<bool> {
+        if *self == Self::Matches {
+            return Ok(Regex::new(rhs)?.is_match(lhs));
+        }
+        let as_version =
+            |s: &str| -> Option<Vec<u64>> { s.split('.').map(|c| c.parse().ok()).collect() };
+        let ordering = match (as_version(lhs), as_version(rhs)) {
+            (Some(l), Some(r)) => l.cmp(&r),
+            _ => lhs.cmp(rhs),
+        };
+        Ok(match self {
+            Self::Eq => ordering.is_eq(),
+            Self::Ne => ordering.is_ne(),
+            Self::Lt => ordering.is_lt(),
+            Self::Le => ordering.is_le(),
+            Self::Gt => ordering.is_gt(),
+            Self::Ge => ordering.is_ge(),
+            Self::Matches => unreachable!(),
+        })
+    }
+}
+fn default_timeout() -> u64 {
+    300
+}
+impl Action {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Push { .. } => "push",
+            Self::Run(_) => "run",
+            Self::WaitFor { .. } => "wait-for",
+            Self::Reboot { .. } => "reboot",
+            Self::AssertInfoKey { .. } => "assert-info-key",
+            Self::AssertInfo { .. } => "assert-info",
+            Self::AssertCmd { .. } => "assert-cmd",
+            Self::AssertFileExists { .. } => "assert-file-exists",
+        }
+    }
+    fn is_assertion(&self) -> bool {
+        matches!(
+            self,
+            Self::AssertInfoKey { .. }
+                | Self::AssertInfo { .. }
+                | Self::AssertCmd { .. }
+                | Self::AssertFileExists { .. }
+        )
+    }
+    fn run(&self, ssh: &SshInfo, vars: &HashMap<String, String>) -> Result<()> {
+        match self {
+            Self::Push { src, dest } => {
+                let dest = dest.as_deref().map(|d| expand(d, vars)).transpose()?;
+                ssh.send_files_audited(&[expand(src, vars)?], dest.as_ref())
+            }
+            Self::Run(cmd) => {
+                let output = ssh.run_cmd_stdio(&expand(cmd, vars)?)?;
+                if !output.is_empty() {
+                    println!("{output}");
+                }
+                Ok(())
+            }
+            Self::WaitFor { cmd, timeout } => {
+                let cmd = expand(cmd, vars)?;
+                let start = Instant::now();
+                while ssh.run_cmd_stdio(&cmd).is_err() {
+                    if start.elapsed() > Duration::from_secs(*timeout) {
+                        return Err(anyhow!("`{cmd}` did not succeed in {timeout}s"));
+                    }
+                    cancel::sleep(Duration::from_secs(2))?;
+                }
+                Ok(())
+            }
+            Self::Reboot { timeout } => {
+                let boot_id = ssh.get_boot_id()?;
+                // ssh may exit with an error since the connection is closed by the reboot
+                drop(ssh.run_cmd_stdio("reboot; exit"));
+                ssh.wait_for_new_boot_id(&boot_id, Duration::from_secs(*timeout))?;
+                Ok(())
+            }
+            Self::AssertInfoKey {
+                key,
+                equals,
+                matches,
+            } => {
+                let key = expand(key, vars)?;
+                let info = DutInfo::fetch_keys(ssh, &[&key])?;
+                let value = info.get(&key).context(anyhow!("No {key} in the info"))?;
+                if let Some(expected) = equals {
+                    let expected = expand(expected, vars)?;
+                    if value != &expected {
+                        return Err(anyhow!("{key} is {value:?}, not {expected:?}"));
+                    }
+                }
+                if let Some(re) = matches {
+                    let re = Regex::new(&expand(re, vars)?)?;
+                    if !re.is_match(value) {
+                        return Err(anyhow!("{key} is {value:?}, which does not match {re}"));
+                    }
+                }
+                Ok(())
+            }
+            Self::AssertInfo { key, op, value } => {
+                let key = expand(key, vars)?;
+                let expected = expand(value, vars)?;
+                let info = DutInfo::fetch_keys(ssh, &[&key])?;
+                let actual = info.get(&key).context(anyhow!("No {key} in the info"))?;
+                if !op.compare(actual, &expected)? {
+                    return Err(anyhow!(
+                        "{key} is {actual:?}, which is not {} {expected:?}",
+                        op.symbol()
+                    ));
+                }
+                Ok(())
+            }
+            Self::AssertCmd {
+                cmd,
+                exit_code,
+                stdout_matches,
+            } => {
+                let cmd = expand(cmd, vars)?;
+                let output = ssh.ssh_cmd(None)?.arg(&cmd).stdin(Stdio::null()).output()?;
+                let code = output.status.code().unwrap_or(-1);
+                if code == 255 && *exit_code != 255 {
+                    return Err(anyhow!("ssh failed: {}", get_stderr(&output)));
+                }
+                if code != *exit_code {
+                    return Err(anyhow!(
+                        "`{cmd}` exited with {code}, not {exit_code}: {}",
+                        get_stderr(&output)
+                    ));
+                }
+                if let Some(re) = stdout_matches {
+                    let re = Regex::new(&expand(re, vars)?)?;
+                    let stdout = get_stdout(&output);
+                    if !re.is_match(&stdout) {
+                        return Err(anyhow!(
+                            "The output of `{cmd}` does not match {re}: {stdout}"
+                        ));
+                    }
+                }
+                Ok(())
+            }
+            Self::AssertFileExists { path } => {
+                let path = expand(path, vars)?;
+                let quoted = path.replace('\'', r"'\''");
+                // Tell a missing file from a failure of ssh
+                match ssh
+                    .run_cmd_stdio(&format!("test -e '{quoted}' && echo yes || echo no"))?
+                    .as_str()
+                {
+                    "yes" => Ok(()),
+                    _ => Err(anyhow!("{path} does not exist")),
+                }
+            }
+        }
+    }
+}
+
+/// Replaces `${NAME}` with the variable. Returns an error if it is not set.
+fn expand(word: &str, vars: &HashMap<String, String>) -> Result<String> {
+    let mut missing = None;
+    let expanded =
+        regex!(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").replace_all(word, |c: &regex::Captures| {
+            vars.get(&c[1]).cloned().unwrap_or_else(|| {
+                missing = Some(c[1].to_string());
+                String::new()
+            })
+        });
+    match missing {
+        Some(name) => Err(anyhow!("Variable {name} is not set")),
+        None => Ok(expanded.to_string()),
+    }
+}
+
+fn parse_playbook(s: &str) -> Result<Playbook> {
+    let playbook: Playbook = serde_yaml::from_str(s)?;
+    for (i, step) in playbook.steps.iter().enumerate() {
+        if let Action::AssertInfoKey {
+            equals: None,
+            matches: None,
+            ..
+        } = &step.action
+        {
+            return Err(anyhow!("step {}: give `equals` or `matches`", i + 1));
+        }
+    }
+    Ok(playbook)
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run the steps of a playbook (YAML) against the DUTs in parallel. a DUT stops at the first
+/// action which fails after the retries, while failed assertions do not stop it.
+#[argh(subcommand, name = "run")]
+struct ArgsRun {
+    /// path to the playbook
+    #[argh(positional)]
+    file: String,
+
+    /// a target DUT or @group, overriding the duts in the playbook. can be repeated.
+    #[argh(option)]
+    dut: Vec<String>,
+
+    /// a variable in NAME=VALUE form, overriding the vars in the playbook. can be repeated.
+    #[argh(option)]
+    set: Vec<String>,
+
+    /// write a JUnit XML report of the steps to a path, gs://bucket/key or http(s)://collector
+    #[argh(option)]
+    junit: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+    /// Not run since a previous step failed
+    Skipped,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct StepResult {
+    name: String,
+    time: Duration,
+    outcome: Outcome,
+}
+
+/// Runs the steps against the DUT. A failed assertion is recorded and the next steps are run,
+/// while a failed action skips the rest.
+fn play_on_dut(playbook: &Playbook, dut: &str, vars: &HashMap<String, String>) -> Vec<StepResult> {
+    let mut vars = vars.clone();
+    vars.insert("DUT".to_string(), dut.to_string());
+    let ssh = match SshInfo::new(dut) {
+        Ok(ssh) => ssh,
+        Err(e) => {
+            return vec![StepResult {
+                name: "connect".to_string(),
+                time: Duration::ZERO,
+                outcome: Outcome::Failed(format!("{e:#}")),
+            }]
+        }
+    };
+    let mut results = Vec::new();
+    let mut aborted = false;
+    for (i, step) in playbook.steps.iter().enumerate() {
+        let name = format!(
+            "{}/{} {}",
+            i + 1,
+            playbook.steps.len(),
+            step.name.as_deref().unwrap_or(step.action.kind())
+        );
+        if aborted || cancel::is_cancelled() {
+            results.push(StepResult {
+                name,
+                time: Duration::ZERO,
+                outcome: Outcome::Skipped,
+            });
+            continue;
+        }
+        status!("{dut}: {name}");
+        let start = Instant::now();
+        let mut attempt = 0;
+        let outcome = loop {
+            match step.action.run(&ssh, &vars) {
+                Ok(()) => break Outcome::Passed,
+                Err(e) if attempt < step.retries && !cancel::is_cancelled() => {
+                    attempt += 1;
+                    status!(
+                        "{dut}: {name} failed: {e:#}. Retrying ({attempt}/{})...",
+                        step.retries
+                    );
+                    if let Err(e) = cancel::sleep(Duration::from_secs(step.retry_delay)) {
+                        break Outcome::Failed(format!("{e:#}"));
+                    }
+                }
+                Err(e) => break Outcome::Failed(format!("{e:#}")),
+            }
+        };
+        if let Outcome::Failed(e) = &outcome {
+            status!("{dut}: {name} failed: {e}");
+            aborted = !step.action.is_assertion();
+        }
+        results.push(StepResult {
+            name,
+            time: start.elapsed(),
+            outcome,
+        });
+    }
+    results
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Returns a JUnit XML report with a testsuite per DUT and a testcase per step
+fn junit_xml(playbook_name: &str, results: &[(String, Vec<StepResult>)]) -> String {
+    let count = |steps: &[StepResult], f: fn(&Outcome) -> bool| {
+        steps.iter().filter(|s| f(&s.outcome)).count()
+    };
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for (dut, steps) in results {
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">\n",
+            xml_escape(dut),
+            steps.len(),
+            count(steps, |o| matches!(o, Outcome::Failed(_))),
+            count(steps, |o| matches!(o, Outcome::Skipped)),
+            steps.iter().map(|s| s.time.as_secs_f64()).sum::<f64>()
+        );
+        for step in steps {
+            xml += &format!(
+                "    <testcase classname=\"{}\" name=\"{}\" time=\"{:.3}\"",
+                xml_escape(playbook_name),
+                xml_escape(&step.name),
+                step.time.as_secs_f64()
+            );
+            match &step.outcome {
+                Outcome::Passed => xml += "/>\n",
+                Outcome::Skipped => xml += ">\n      <skipped/>\n    </testcase>\n",
+                Outcome::Failed(e) => {
+                    let first_line = e.lines().next().unwrap_or_default();
+                    xml += &format!(
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                        xml_escape(first_line),
+                        xml_escape(e)
+                    );
+                }
+            }
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+fn run_play(args: &ArgsRun) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    cancel::install_handler()?;
+    let s = read_to_string(&args.file).context(anyhow!("Failed to read {}", args.file))?;
+    let playbook = parse_playbook(&s).context(anyhow!("Failed to parse {}", args.file))?;
+    let mut vars: HashMap<String, String> = playbook.vars.clone().into_iter().collect();
+    for kv in &args.set {
+        let (k, v) = kv
+            .split_once('=')
+            .context(anyhow!("Invalid --set {kv:?}. Use NAME=VALUE"))?;
+        vars.insert(k.to_string(), v.to_string());
+    }
+    let targets = if args.dut.is_empty() {
+        &playbook.duts
+    } else {
+        &args.dut
+    };
+    let mut duts = Vec::new();
+    for target in targets {
+        match expand(target, &vars)?.strip_prefix('@') {
+            Some(group) => duts.extend(dut_group(group)?),
+            None => duts.push(expand(target, &vars)?),
+        }
+    }
+    if duts.is_empty() {
+        return Err(anyhow!(
+            "No DUTs to play. Give --dut or duts in the playbook"
+        ));
+    }
+    let results: Vec<(String, Vec<StepResult>)> = thread::scope(|s| {
+        let handles: Vec<_> = duts
+            .iter()
+            .map(|dut| {
+                let (playbook, vars) = (&playbook, &vars);
+                (dut, s.spawn(move || play_on_dut(playbook, dut, vars)))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|(dut, h)| {
+                let steps = h.join().unwrap_or_else(|_| {
+                    vec![StepResult {
+                        name: "?".to_string(),
+                        time: Duration::ZERO,
+                        outcome: Outcome::Failed("panicked".to_string()),
+                    }]
+                });
+                (dut.clone(), steps)
+            })
+            .collect()
+    });
+    if let Some(dest) = &args.junit {
+        let name = Path::new(&args.file)
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let mut sink = output::open(Some(dest))?;
+        sink.write_line(junit_xml(&name, &results).trim_end())?;
+        sink.finish()?;
+    }
+    let mut failures = 0;
+    for (dut, steps) in &results {
+        let failed: Vec<String> = steps
+            .iter()
+            .filter_map(|s| match &s.outcome {
+                Outcome::Failed(e) => Some(format!("{}: {e}", s.name)),
+                _ => None,
+            })
+            .collect();
+        if failed.is_empty() {
+            println!("{dut}: OK");
+        } else {
+            failures += 1;
+            println!("{dut}: FAILED");
+            for f in failed {
+                println!("  {f}");
+            }
+        }
+    }
+    if failures > 0 {
+        return Err(anyhow!("{failures} of {} DUTs failed", results.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_playbooks() {
+        let playbook = parse_playbook(
+            r#"
+vars:
+  BIN: out/test
+steps:
+  - push: { src: "${BIN}", dest: /usr/local/bin }
+  - name: setup
+    run: my_test --setup
+    retries: 2
+  - reboot: {}
+  - wait-for: { cmd: pgrep chrome, timeout: 60 }
+  - assert-info-key: { key: board, matches: "^eve" }
+"#,
+        )
+        .unwrap();
+        assert!(playbook.duts.is_empty());
+        assert_eq!(playbook.steps.len(), 5);
+        assert_eq!(
+            playbook.steps[1],
+            Step {
+                name: Some("setup".to_string()),
+                retries: 2,
+                retry_delay: 5,
+                action: Action::Run("my_test --setup".to_string()),
+            }
+        );
+        assert_eq!(
+            playbook.steps[2].action,
+            Action::Reboot {
+                timeout: default_timeout()
+            }
+        );
+        assert!(parse_playbook("steps:\n  - format: {}\n").is_err());
+        assert_eq!(
+            parse_playbook("steps:\n  - assert-info: { key: release, op: \">=\", value: \"1\" }\n")
+                .unwrap()
+                .steps[0]
+                .action,
+            Action::AssertInfo {
+                key: "release".to_string(),
+                op: CompareOp::Ge,
+                value: "1".to_string()
+            }
+        );
+        assert!(parse_playbook("steps:\n  - assert-info-key: { key: board }\n").is_err());
+    }
+
+    #[test]
+    fn expand_vars() {
+        let vars = HashMap::from([("D".to_string(), "dut1".to_string())]);
+        assert_eq!(expand("--dut=${D}", &vars).unwrap(), "--dut=dut1");
+        assert!(expand("${E}", &vars).is_err());
+    }
+
+    #[test]
+    fn compare() {
+        assert!(CompareOp::Ge.compare("15662.0.0", "15400").unwrap());
+        assert!(CompareOp::Lt.compare("9", "10").unwrap());
+        assert!(CompareOp::Lt.compare("1.2", "1.10").unwrap());
+        assert!(CompareOp::Eq.compare("eve", "eve").unwrap());
+        assert!(CompareOp::Ne.compare("eve", "octopus").unwrap());
+        assert!(CompareOp::Gt.compare("b", "a").unwrap());
+        assert!(CompareOp::Matches
+            .compare("R120-15662.0.0", "^R12[0-9]-")
+            .unwrap());
+        assert!(CompareOp::Matches.compare("x", "(").is_err());
+    }
+
+    #[test]
+    fn junit_report() {
+        let step = |name: &str, outcome| StepResult {
+            name: name.to_string(),
+            time: Duration::from_millis(1500),
+            outcome,
+        };
+        let xml = junit_xml(
+            "check",
+            &[(
+                "eve<1>".to_string(),
+                vec![
+                    step("1/3 run", Outcome::Passed),
+                    step(
+                        "2/3 assert-info",
+                        Outcome::Failed("release is \"1\"\nmore".to_string()),
+                    ),
+                    step("3/3 reboot", Outcome::Skipped),
+                ],
+            )],
+        );
+        assert!(xml.contains(
+            r#"<testsuite name="eve&lt;1&gt;" tests="3" failures="1" skipped="1" time="4.500">"#
+        ));
+        assert!(xml.contains(r#"<testcase classname="check" name="1/3 run" time="1.500"/>"#));
+        assert!(xml.contains(
+            r#"<failure message="release is &quot;1&quot;">release is &quot;1&quot;
+more</failure>"#
+        ));
+        assert!(xml.contains("<skipped/>"));
+    }
+}
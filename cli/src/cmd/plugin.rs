@@ -0,0 +1,158 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! External subcommands. An executable named `lium-<name>` in PATH or in ~/.lium/plugins is
+//! surfaced as `lium <name>`, like git does, so that teams can ship their own subcommands.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::CommandInfo;
+use argh::DynamicSubCommand;
+use argh::EarlyExit;
+use lazy_static::lazy_static;
+use lium::util::is_offline_mode;
+use lium::util::lium_dir;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::Command;
+
+const PLUGIN_PREFIX: &str = "lium-";
+
+/// Returns the name of the plugin if the file name is the one of a plugin
+fn plugin_name(file_name: &str) -> Option<&str> {
+    file_name.strip_prefix(PLUGIN_PREFIX).filter(|name| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    })
+}
+
+/// Returns the plugins found in ~/.lium/plugins and PATH. Earlier ones win.
+pub fn discover_plugins() -> Vec<(String, PathBuf)> {
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    if let Ok(dir) = lium_dir() {
+        dirs.push(PathBuf::from(dir).join("plugins"));
+    }
+    if let Some(path) = env::var_os("PATH") {
+        dirs.extend(env::split_paths(&path));
+    }
+    let mut plugins: Vec<(String, PathBuf)> = Vec::new();
+    for dir in dirs {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        let mut found: Vec<(String, PathBuf)> = entries
+            .flatten()
+            .filter(|e| {
+                e.metadata()
+                    .map(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+                    .unwrap_or(false)
+            })
+            .filter_map(|e| {
+                let file_name = e.file_name().to_string_lossy().to_string();
+                plugin_name(&file_name).map(|name| (name.to_string(), e.path()))
+            })
+            .collect();
+        found.sort();
+        for (name, path) in found {
+            if !plugins.iter().any(|(n, _)| *n == name) {
+                plugins.push((name, path));
+            }
+        }
+    }
+    plugins
+}
+
+lazy_static! {
+    static ref PLUGINS: Vec<(String, PathBuf)> = discover_plugins();
+    // argh requires the info to be 'static
+    static ref PLUGIN_COMMANDS: Vec<&'static CommandInfo> = PLUGINS
+        .iter()
+        .map(|(name, path)| {
+            &*Box::leak(Box::new(CommandInfo {
+                name: Box::leak(name.clone().into_boxed_str()),
+                description: Box::leak(
+                    format!("plugin at {}", path.to_string_lossy()).into_boxed_str(),
+                ),
+            }))
+        })
+        .collect();
+}
+
+/// A subcommand provided by an external `lium-<name>` executable
+#[derive(PartialEq, Debug)]
+pub struct Plugin {
+    name: String,
+    path: PathBuf,
+    args: Vec<String>,
+}
+impl Plugin {
+    fn find(command_name: &[&str]) -> Option<&'static (String, PathBuf)> {
+        // Plugins are only available at the top level (e.g. `lium foo`)
+        if command_name.len() != 2 {
+            return None;
+        }
+        PLUGINS
+            .iter()
+            .find(|(name, _)| command_name.last() == Some(&name.as_str()))
+    }
+}
+impl DynamicSubCommand for Plugin {
+    fn commands() -> &'static [&'static CommandInfo] {
+        &PLUGIN_COMMANDS
+    }
+    fn try_redact_arg_values(
+        command_name: &[&str],
+        args: &[&str],
+    ) -> Option<Result<Vec<String>, EarlyExit>> {
+        Self::find(command_name)
+            .map(|_| Ok(args.iter().map(|_| "[redacted]".to_string()).collect()))
+    }
+    fn try_from_args(command_name: &[&str], args: &[&str]) -> Option<Result<Self, EarlyExit>> {
+        Self::find(command_name).map(|(name, path)| {
+            Ok(Plugin {
+                name: name.clone(),
+                path: path.clone(),
+                args: args.iter().map(|s| s.to_string()).collect(),
+            })
+        })
+    }
+}
+
+/// Runs the plugin. The path to lium is passed as $LIUM so that plugins can call back into it,
+/// and the offline mode is passed as $LIUM_OFFLINE.
+pub fn run(plugin: &Plugin) -> Result<()> {
+    let mut cmd = Command::new(&plugin.path);
+    cmd.args(&plugin.args);
+    if let Ok(exe) = env::current_exe() {
+        cmd.env("LIUM", exe);
+    }
+    if is_offline_mode() {
+        cmd.env("LIUM_OFFLINE", "1");
+    }
+    cmd.status()
+        .context(anyhow!("Failed to run the plugin {:?}", plugin.path))?
+        .exit_ok()
+        .context(anyhow!("Plugin {} failed", plugin.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_names() {
+        assert_eq!(plugin_name("lium-lab"), Some("lab"));
+        assert_eq!(plugin_name("lium-lab-tools"), Some("lab-tools"));
+        assert_eq!(plugin_name("lium-"), None);
+        assert_eq!(plugin_name("lium-Lab.sh"), None);
+        assert_eq!(plugin_name("lium"), None);
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::repo::get_repo_dir;
+use lium::util::ensure_online;
+use lium::util::get_stdout;
+use lium::util::run_bash_command;
+use std::collections::HashMap;
+use std::process::Command;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// repo wrapper for the ChromiumOS checkout
+#[argh(subcommand, name = "repo")]
+pub struct Args {
+    /// target cros repo dir
+    #[argh(option)]
+    repo: Option<String>,
+
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Branch(ArgsBranch),
+    Status(ArgsStatus),
+    Sync(ArgsSync),
+}
+pub fn run(args: &Args) -> Result<()> {
+    let repo = get_repo_dir(&args.repo)?;
+    match &args.nested {
+        SubCommand::Branch(args) => run_branch(args, &repo),
+        SubCommand::Status(args) => run_status(args, &repo),
+        SubCommand::Sync(args) => run_sync(args, &repo),
+    }
+}
+
+fn run_repo_cmd(repo: &str, args: &[&str]) -> Result<()> {
+    let mut cmd = Command::new("repo");
+    cmd.current_dir(repo).args(args);
+    eprintln!("Running {cmd:?}");
+    cmd.status()
+        .context(anyhow!("Failed to execute {cmd:?}"))?
+        .exit_ok()
+        .context(anyhow!("{cmd:?} failed"))
+}
+
+/// Returns a map from project paths to their HEAD commits
+fn project_heads(repo: &str) -> Result<HashMap<String, String>> {
+    let output = run_bash_command(
+        "repo forall -j 16 -c 'echo $REPO_PATH $(git rev-parse HEAD 2>/dev/null)'",
+        Some(repo),
+    )?;
+    output
+        .status
+        .exit_ok()
+        .context("Failed to get the HEADs of projects")?;
+    Ok(get_stdout(&output)
+        .lines()
+        .filter_map(|line| {
+            let (path, head) = line.split_once(' ')?;
+            Some((path.to_string(), head.to_string()))
+        })
+        .collect())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run repo sync in parallel and show which projects were changed
+#[argh(subcommand, name = "sync")]
+struct ArgsSync {
+    /// number of parallel jobs
+    #[argh(option, default = "16")]
+    jobs: usize,
+
+    /// pass --force-sync to repo sync
+    #[argh(switch)]
+    force: bool,
+}
+fn run_sync(args: &ArgsSync, repo: &str) -> Result<()> {
+    ensure_online("repo sync")?;
+    let before = project_heads(repo)?;
+    let jobs = args.jobs.to_string();
+    let mut sync_args = vec!["sync", "-j", &jobs];
+    if args.force {
+        sync_args.push("--force-sync");
+    }
+    run_repo_cmd(repo, &sync_args)?;
+    let after = project_heads(repo)?;
+
+    let mut changed: Vec<(&String, &String)> = after
+        .iter()
+        .filter(|(path, head)| before.get(*path) != Some(head))
+        .collect();
+    changed.sort();
+    if changed.is_empty() {
+        println!("No projects were changed.");
+        return Ok(());
+    }
+    println!("{} projects were changed:", changed.len());
+    for (path, head) in changed {
+        let detail = if let Some(prev) = before.get(path) {
+            let count = get_stdout(&run_bash_command(
+                &format!("git rev-list --count {prev}..{head}"),
+                Some(&format!("{repo}/{path}")),
+            )?);
+            format!("+{count} commits")
+        } else {
+            "new project".to_string()
+        };
+        println!("  {path:60} {detail}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show local changes in the checkout (repo status)
+#[argh(subcommand, name = "status")]
+struct ArgsStatus {
+    /// number of parallel jobs
+    #[argh(option, default = "16")]
+    jobs: usize,
+}
+fn run_status(args: &ArgsStatus, repo: &str) -> Result<()> {
+    run_repo_cmd(repo, &["status", "-j", &args.jobs.to_string()])
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list, start or abandon topic branches (repo branches / start / abandon)
+#[argh(subcommand, name = "branch")]
+struct ArgsBranch {
+    /// start a new branch with this name on the projects given
+    #[argh(option)]
+    start: Option<String>,
+
+    /// abandon the branch with this name (on the projects given, or everywhere)
+    #[argh(option)]
+    abandon: Option<String>,
+
+    /// projects (paths or names) to operate on
+    #[argh(positional)]
+    projects: Vec<String>,
+}
+fn run_branch(args: &ArgsBranch, repo: &str) -> Result<()> {
+    let projects: Vec<&str> = args.projects.iter().map(|s| s.as_str()).collect();
+    match (&args.start, &args.abandon) {
+        (Some(_), Some(_)) => Err(anyhow!("--start and --abandon are exclusive")),
+        (Some(branch), None) => {
+            if projects.is_empty() {
+                return Err(anyhow!("Please specify projects to start the branch on"));
+            }
+            run_repo_cmd(
+                repo,
+                &[&["start", branch.as_str()], projects.as_slice()].concat(),
+            )
+        }
+        (None, Some(branch)) => run_repo_cmd(
+            repo,
+            &[&["abandon", branch.as_str()], projects.as_slice()].concat(),
+        ),
+        (None, None) => run_repo_cmd(repo, &[&["branches"], projects.as_slice()].concat()),
+    }
+}
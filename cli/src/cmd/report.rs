@@ -0,0 +1,257 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::NaiveDateTime;
+use lium::audit::read_command_log;
+use lium::config::Config;
+use lium::cros;
+use lium::dut::DutInfo;
+use lium::dut::SshInfo;
+use lium::redact::Redactor;
+use lium::status;
+use lium::util::ensure_online;
+use lium::warning;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::write;
+use std::process::Command;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const REPORT_DUT_INFO_KEYS: [&str; 12] = [
+    "dut_id",
+    "model",
+    "board",
+    "release",
+    "hwid",
+    "fwid",
+    "ro_fwid",
+    "uptime",
+    "soc",
+    "microcode",
+    "mitigations",
+    "crypto",
+];
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// generate reports to share
+#[argh(subcommand, name = "report")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Bug(ArgsBug),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Bug(args) => run_bug(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// assemble a bug report about a DUT (lium version, commands run by lium, DUT info, device logs
+/// and ssh diagnostics) in Markdown
+#[argh(subcommand, name = "bug")]
+pub struct ArgsBug {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: String,
+
+    /// title of the report
+    #[argh(option)]
+    title: Option<String>,
+
+    /// number of the latest lium commands on the DUT to include (default: 20)
+    #[argh(option, default = "20")]
+    commands: usize,
+
+    /// number of the lines of each device log to include (default: 100)
+    #[argh(option, default = "100")]
+    log_lines: usize,
+
+    /// write the report to the file instead of stdout
+    #[argh(option)]
+    out: Option<String>,
+
+    /// file the report to `issue_tracker_url` in the config
+    #[argh(switch)]
+    file: bool,
+
+    /// do not mask addresses, emails, serial numbers and the things configured with
+    /// `lium config set redact_domains|redact_patterns`
+    #[argh(switch)]
+    no_redact: bool,
+}
+
+fn code_block(s: &str) -> String {
+    format!("```\n{}\n```\n", s.trim_end())
+}
+
+fn run_bug(args: &ArgsBug) -> Result<()> {
+    cros::ensure_testing_rsa_is_there()?;
+    let ssh = SshInfo::new(&args.dut)?;
+    let mut report = String::new();
+    writeln!(
+        report,
+        "# {}\n",
+        args.title.as_deref().unwrap_or("<summary of the problem>")
+    )?;
+    writeln!(
+        report,
+        "## What happened\n\n<steps, expected and actual behavior>\n"
+    )?;
+
+    let host = Command::new("uname")
+        .arg("-sr")
+        .output()
+        .map(|o| String::from_utf8_lossy(&o.stdout).trim().to_string())
+        .unwrap_or_default();
+    writeln!(
+        report,
+        "## Environment\n\n- lium v{VERSION}\n- host: {host}\n"
+    )?;
+
+    status!("Fetching the DUT info...");
+    // The serial is only included when it is masked, so that it can be redacted in the logs
+    let mut keys = REPORT_DUT_INFO_KEYS.to_vec();
+    if !args.no_redact {
+        keys.push("serial");
+    }
+    let (info, note) = match DutInfo::fetch_keys(&ssh, &keys) {
+        Ok(info) => (info.into_iter().collect::<BTreeMap<_, _>>(), ""),
+        Err(e) => {
+            warning!("Failed to access the DUT: {e:#}");
+            let cached = DutInfo::cached_info(&args.dut).unwrap_or_default();
+            (
+                cached
+                    .into_iter()
+                    .filter(|(k, _)| keys.contains(&k.as_str()))
+                    .map(|(k, v)| (k, v.value))
+                    .collect(),
+                " (last known values, the DUT was not reachable)",
+            )
+        }
+    };
+    writeln!(report, "## DUT{note}\n")?;
+    for (k, v) in &info {
+        if k != "timestamp" {
+            writeln!(report, "- {k}: {}", v.trim())?;
+        }
+    }
+    writeln!(report)?;
+
+    let id = info.get("dut_id").cloned();
+    let mut commands: Vec<_> = read_command_log()?
+        .into_iter()
+        .filter(|c| c.mentions(&args.dut) || id.as_deref().map_or(false, |id| c.mentions(id)))
+        .collect();
+    let skip = commands.len().saturating_sub(args.commands);
+    commands.drain(..skip);
+    writeln!(report, "## lium commands on the DUT\n")?;
+    let mut lines = Vec::new();
+    for c in &commands {
+        let time = NaiveDateTime::from_timestamp_opt(c.started_at, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        lines.push(format!("# {time} UTC, {:.1}s", c.duration_secs));
+        lines.push(format!("$ {}", c.command_line()));
+        if let Some(e) = &c.error {
+            lines.push(format!("# failed: {}", e.replace('\n', " ")));
+        }
+    }
+    if lines.is_empty() {
+        writeln!(report, "(none recorded)\n")?;
+    } else {
+        writeln!(report, "{}", code_block(&lines.join("\n")))?;
+    }
+
+    status!("Diagnosing the ssh connection...");
+    let diagnosis: Vec<String> = ssh
+        .diagnose()
+        .iter()
+        .map(|r| {
+            format!(
+                "[{}] {:12} {}",
+                if r.ok { " OK " } else { "FAIL" },
+                r.step,
+                r.detail
+            )
+        })
+        .collect();
+    writeln!(
+        report,
+        "## ssh diagnostics\n\n{}",
+        code_block(&diagnosis.join("\n"))
+    )?;
+
+    for log in ["/var/log/messages", "/var/log/ui/ui.LATEST"] {
+        status!("Fetching {log}...");
+        match ssh.run_cmd_stdio(&format!("tail -n {} {log}", args.log_lines)) {
+            Ok(s) => writeln!(report, "## {log}\n\n{}", code_block(&s))?,
+            Err(e) => warning!("Failed to get {log}: {e:#}"),
+        }
+    }
+
+    let report = if !args.no_redact {
+        let mut redactor = Redactor::from_config()?;
+        if let Some(serial) = info.get("serial") {
+            redactor.add_secret(serial, "<serial>");
+        }
+        redactor.redact(&report)
+    } else {
+        report
+    };
+    if let Some(out) = &args.out {
+        write(out, &report).context(anyhow!("Failed to write {out}"))?;
+        status!("Wrote the report to {out}");
+    } else {
+        print!("{report}");
+    }
+    if args.file {
+        file_report(
+            args.title.as_deref().unwrap_or("Bug report from lium"),
+            &report,
+        )?;
+    }
+    Ok(())
+}
+
+/// POSTs {"title": ..., "body": ...} to the issue tracker and prints the response
+fn file_report(title: &str, body: &str) -> Result<()> {
+    ensure_online("Filing the report")?;
+    let url = Config::read()?.issue_tracker_url().context(
+        "issue_tracker_url is not configured. Use `lium config set issue_tracker_url <url>`",
+    )?;
+    let json = serde_json::json!({
+        "title": title,
+        "body": body,
+    });
+    let mut c = Command::new("curl");
+    c.args(["-sSf", "-X", "POST", "-H", "Content-Type: application/json"]);
+    if let Ok(token) = std::env::var("LIUM_ISSUE_TRACKER_TOKEN") {
+        c.arg("-H").arg(format!("Authorization: Bearer {token}"));
+    }
+    let output = c
+        .arg("--data-binary")
+        .arg(json.to_string())
+        .arg(&url)
+        .output()
+        .context("Failed to run curl")?;
+    output.status.exit_ok().context(anyhow!(
+        "Failed to file the report to {url}: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))?;
+    status!("Filed the report to {url}");
+    println!("{}", String::from_utf8_lossy(&output.stdout).trim());
+    Ok(())
+}
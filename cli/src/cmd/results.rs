@@ -0,0 +1,267 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use chrono::TimeZone;
+use lium::results::Filter;
+use lium::results::ResultsDb;
+use lium::results::TestResult;
+use lium::results::TestTarget;
+use lium::ui::styled;
+use lium::ui::Stream;
+use lium::ui::Style;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// query the history of test results (tast, gtest, reboot-loop, suspend-stress)
+#[argh(subcommand, name = "results")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Add(ArgsAdd),
+    Compare(ArgsCompare),
+    List(ArgsList),
+    Show(ArgsShow),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Add(args) => run_add(args),
+        SubCommand::Compare(args) => run_compare(args),
+        SubCommand::List(args) => run_list(args),
+        SubCommand::Show(args) => run_show(args),
+    }
+}
+
+fn format_time(t: i64) -> String {
+    Local
+        .timestamp_opt(t, 0)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn styled_status(status: &str) -> String {
+    let style = match status {
+        "PASS" => Style::Good,
+        "FAIL" => Style::Bad,
+        _ => Style::Dim,
+    };
+    styled(&format!("{status:4}"), style, Stream::Stdout)
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list results, newest first. e.g. has it ever passed on eve?
+/// `lium results list --test tast.foo.Bar --model eve --status PASS`
+#[argh(subcommand, name = "list")]
+pub struct ArgsList {
+    /// glob pattern of test names
+    #[argh(option)]
+    test: Option<String>,
+
+    /// kind of the run (tast, gtest, reboot-loop, suspend-stress...)
+    #[argh(option)]
+    kind: Option<String>,
+
+    /// PASS, FAIL or SKIP
+    #[argh(option)]
+    status: Option<String>,
+
+    /// glob pattern of DUT IDs
+    #[argh(option)]
+    dut: Option<String>,
+
+    /// glob pattern of models
+    #[argh(option)]
+    model: Option<String>,
+
+    /// glob pattern of OS versions (e.g. '*R120-*')
+    #[argh(option)]
+    version: Option<String>,
+
+    /// max number of results to show (default: 50)
+    #[argh(option, default = "50")]
+    limit: usize,
+
+    /// output as JSON
+    #[argh(switch)]
+    json: bool,
+}
+fn run_list(args: &ArgsList) -> Result<()> {
+    let filter = Filter {
+        test: args.test.clone(),
+        kind: args.kind.clone(),
+        status: args.status.clone(),
+        dut: args.dut.clone(),
+        model: args.model.clone(),
+        version: args.version.clone(),
+    };
+    let results = ResultsDb::open()?.list(&filter, Some(args.limit))?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+        return Ok(());
+    }
+    for r in &results {
+        println!(
+            "{:6} {} {} {:14} {} {} {}",
+            r.id,
+            format_time(r.recorded_at),
+            styled_status(&r.status),
+            r.kind,
+            r.test,
+            r.target.dut,
+            r.target.version
+        );
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the details of a result
+#[argh(subcommand, name = "show")]
+pub struct ArgsShow {
+    /// ID of the result (shown by `lium results list`)
+    #[argh(positional)]
+    id: i64,
+
+    /// output as JSON
+    #[argh(switch)]
+    json: bool,
+}
+fn run_show(args: &ArgsShow) -> Result<()> {
+    let r = ResultsDb::open()?.get(args.id)?;
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&r)?);
+        return Ok(());
+    }
+    println!("id: {}", r.id);
+    println!("test: {}", r.test);
+    println!("kind: {}", r.kind);
+    println!("status: {}", r.status);
+    println!("dut: {}", r.target.dut);
+    println!("model: {}", r.target.model);
+    println!("board: {}", r.target.board);
+    println!("version: {}", r.target.version);
+    println!("recorded at: {}", format_time(r.recorded_at));
+    if !r.detail.is_empty() {
+        println!("detail:\n{}", r.detail);
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// compare the latest status of each test between two OS versions (or DUTs, or models)
+#[argh(subcommand, name = "compare")]
+pub struct ArgsCompare {
+    /// what the two patterns select: version (default), dut or model
+    #[argh(option, default = "String::from(\"version\")")]
+    by: String,
+
+    /// glob pattern of the base side (e.g. '*R119-*')
+    #[argh(positional)]
+    base: String,
+
+    /// glob pattern of the other side (e.g. '*R120-*')
+    #[argh(positional)]
+    target: String,
+
+    /// glob pattern of test names
+    #[argh(option)]
+    test: Option<String>,
+
+    /// glob pattern of models, to compare versions on the same model
+    #[argh(option)]
+    model: Option<String>,
+
+    /// show only the tests whose status differs
+    #[argh(switch)]
+    changed: bool,
+}
+
+/// Returns the status of the newest result of each test
+fn latest_status(results: &[TestResult]) -> BTreeMap<&str, &str> {
+    let mut latest = BTreeMap::new();
+    // results are sorted from the newest
+    for r in results {
+        latest.entry(r.test.as_str()).or_insert(r.status.as_str());
+    }
+    latest
+}
+
+fn run_compare(args: &ArgsCompare) -> Result<()> {
+    let db = ResultsDb::open()?;
+    let select = |pattern: &str| -> Result<Vec<TestResult>> {
+        let mut filter = Filter {
+            test: args.test.clone(),
+            model: args.model.clone(),
+            ..Default::default()
+        };
+        let field = match args.by.as_str() {
+            "version" => &mut filter.version,
+            "dut" => &mut filter.dut,
+            "model" => &mut filter.model,
+            by => return Err(anyhow!("Invalid --by {by:?}. Use version, dut or model")),
+        };
+        *field = Some(pattern.to_string());
+        db.list(&filter, None)
+    };
+    let base = select(&args.base)?;
+    let target = select(&args.target)?;
+    let (base, target) = (latest_status(&base), latest_status(&target));
+    let tests: BTreeSet<&str> = base.keys().chain(target.keys()).copied().collect();
+    for test in tests {
+        let b = base.get(test).copied().unwrap_or("-");
+        let t = target.get(test).copied().unwrap_or("-");
+        if args.changed && b == t {
+            continue;
+        }
+        println!("{} -> {} {test}", styled_status(b), styled_status(t));
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// record a result of a test run outside of lium (e.g. gtest)
+#[argh(subcommand, name = "add")]
+pub struct ArgsAdd {
+    /// a DUT identifier to fetch the model and the version from
+    #[argh(option)]
+    dut: String,
+
+    /// kind of the run (default: gtest)
+    #[argh(option, default = "String::from(\"gtest\")")]
+    kind: String,
+
+    /// test name
+    #[argh(option)]
+    test: String,
+
+    /// PASS, FAIL or SKIP
+    #[argh(option)]
+    status: String,
+
+    /// details like error messages
+    #[argh(option, default = "String::new()")]
+    detail: String,
+}
+fn run_add(args: &ArgsAdd) -> Result<()> {
+    let status = args.status.to_uppercase();
+    if !["PASS", "FAIL", "SKIP"].contains(&status.as_str()) {
+        return Err(anyhow!("Invalid status {:?}", args.status));
+    }
+    let target = TestTarget::from_dut(&args.dut)?;
+    let result = TestResult::new(&args.kind, &args.test, &status, &target, &args.detail);
+    let ids = ResultsDb::open()?.record(&[result])?;
+    println!("{}", ids[0]);
+    Ok(())
+}
@@ -0,0 +1,76 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use chrono::TimeZone;
+use lium::journal::JournalEntry;
+use lium::journal::OP_JOURNAL;
+use lium::journal::RESUME_ENV;
+use lium::ui::Table;
+use std::env::current_exe;
+use std::process::Command;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// continue an interrupted operation (e.g. dut powerwash, dut sanitize) from the failed step.
+/// unfinished operations are listed if no op-id is given.
+#[argh(subcommand, name = "resume")]
+pub struct Args {
+    /// the op-id printed when the operation started
+    #[argh(positional)]
+    op_id: Option<String>,
+
+    /// remove the operation from the journal instead of resuming it
+    #[argh(switch)]
+    discard: bool,
+}
+pub fn run(args: &Args) -> Result<()> {
+    let Some(id) = &args.op_id else {
+        return list_operations();
+    };
+    let entry = OP_JOURNAL.get(id)?.context(anyhow!(
+        "Operation {id} is not in the journal. See `lium resume`"
+    ))?;
+    if args.discard {
+        OP_JOURNAL.remove(id)?;
+        println!("Discarded {id}");
+        return Ok(());
+    }
+    let status = Command::new(current_exe()?)
+        .args(&entry.command)
+        .env(RESUME_ENV, id)
+        .status()?;
+    status.exit_ok().context(anyhow!(
+        "Operation {id} failed again. Run `lium resume {id}` to retry"
+    ))
+}
+
+fn list_operations() -> Result<()> {
+    let mut ops: Vec<(String, JournalEntry)> = OP_JOURNAL.entries()?.into_iter().collect();
+    ops.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut table = Table::new();
+    table.push(["OP-ID", "DUT", "UPDATED", "DONE", "COMMAND"]);
+    for (id, op) in ops {
+        table.push([
+            id,
+            op.dut.unwrap_or_default(),
+            Local
+                .timestamp_opt(op.updated_at, 0)
+                .single()
+                .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default(),
+            op.done_steps.len().to_string(),
+            format!("lium {}", op.command.join(" ")),
+        ]);
+    }
+    for line in table.lines("  ") {
+        println!("{line}");
+    }
+    Ok(())
+}
@@ -0,0 +1,275 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Runs [Rhai](https://rhai.rs) scripts to orchestrate DUTs in one process, so that the caches
+//! and the connections are shared between the steps.
+//!
+//! ```text
+//! // variables given with --set NAME=VALUE are constants
+//! for dut in group("lab") + discover(["192.168.0.0/30"]).map(|d| d.id) {
+//!     let ssh = ssh(dut);
+//!     print(`${dut}: ${ssh.arch()} ${dut_info(dut)["release"]}`);
+//!     ssh.push(["out/my_test"], "/usr/local/bin");
+//!     try {
+//!         ssh.run("my_test --check");
+//!     } catch (e) {
+//!         print(`${dut}: ${e}`);
+//!     }
+//!     lium(["flash", "--dut", dut, "--version", IMAGE]);
+//! }
+//! ```
+//!
+//! Bindings:
+//! - `ssh(dut)` returns an `SshInfo` with `run(cmd)`, `push(files, dest)`, `pull(files, dest)`,
+//!   `arch()` and `address`.
+//! - `dut_info(dut)` connects to a DUT and returns a `DutInfo` with `id`, `ssh`, `info` and
+//!   `info[key]`. `fetch_info(dut, keys)` returns only the given keys.
+//! - `cached_info(dut)`, `list_duts()` and `group(name)` read the caches and the config.
+//! - `discover()` scans the local network, and `discover(targets)` checks the given addresses
+//!   and subnets (e.g. "192.168.0.0/24"). Both register the DUTs found and return `DutInfo`s.
+//! - `lium(args)` runs a lium command (e.g. `lium(["dut", "do", "--dut", dut, "login"])`).
+
+use crate::cmd;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::dut::discover_local_nodes;
+use lium::dut::dut_group;
+use lium::dut::expand_targets;
+use lium::dut::fetch_dut_info_in_parallel;
+use lium::dut::DutInfo;
+use lium::dut::DutProvenance;
+use lium::dut::SshInfo;
+use lium::dut::SSH_CACHE;
+use lium::progress::Progress;
+use lium::util::is_offline_mode;
+use rhai::Array;
+use rhai::Dynamic;
+use rhai::Engine;
+use rhai::EvalAltResult;
+use rhai::Map;
+use rhai::Scope;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run lium scripts
+#[argh(subcommand, name = "script")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Run(ArgsRun),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Run(args) => run_run(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run a Rhai script with bindings for DUTs, discovery and lium commands
+#[argh(subcommand, name = "run")]
+struct ArgsRun {
+    /// path to the script
+    #[argh(positional)]
+    file: String,
+
+    /// a constant in NAME=VALUE form. can be repeated.
+    #[argh(option)]
+    set: Vec<String>,
+}
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+/// Converts an error of lium into an exception of the script
+fn to_script<T>(r: Result<T>) -> ScriptResult<T> {
+    r.map_err(|e| format!("{e:#}").into())
+}
+
+fn to_strings(a: Array) -> ScriptResult<Vec<String>> {
+    a.into_iter()
+        .map(|v| {
+            v.into_string()
+                .map_err(|t| format!("Expected an array of strings, got {t}").into())
+        })
+        .collect()
+}
+
+fn to_map<V: Clone + Into<Dynamic>>(m: &HashMap<String, V>) -> Map {
+    m.iter()
+        .map(|(k, v)| (k.into(), v.clone().into()))
+        .collect()
+}
+
+fn to_array<T: Clone + Send + Sync + 'static>(v: Vec<T>) -> Array {
+    v.into_iter().map(Dynamic::from).collect()
+}
+
+/// Runs a lium command in this process
+fn run_lium(words: &[String]) -> Result<()> {
+    let mut args: Vec<&str> = Vec::new();
+    if is_offline_mode() {
+        args.push("--offline");
+    }
+    args.extend(words.iter().map(|s| s.as_str()));
+    match cmd::TopLevel::from_args(&["lium"], &args) {
+        Ok(top) => cmd::run(&top),
+        Err(early_exit) => match early_exit.status {
+            Ok(()) => {
+                println!("{}", early_exit.output);
+                Ok(())
+            }
+            Err(()) => Err(anyhow!("{}", early_exit.output.trim())),
+        },
+    }
+}
+
+/// Checks the given addresses, or the local network if there are none, and registers the DUTs
+fn discover(targets: Option<&[String]>) -> Result<Vec<DutInfo>> {
+    let addrs = match targets {
+        Some(targets) => expand_targets(targets)?,
+        None => discover_local_nodes(None)?,
+    };
+    let provenance = DutProvenance::now(None);
+    let progress = Progress::new("checking DUTs", addrs.len() as u64);
+    fetch_dut_info_in_parallel(&addrs, &[], Some(&provenance), &progress, &|_| {})
+}
+
+fn register_ssh(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<SshInfo>("SshInfo")
+        .register_fn("ssh", |dut: &str| to_script(SshInfo::new(dut)))
+        .register_fn("run", |ssh: &mut SshInfo, cmd: &str| {
+            to_script(ssh.run_cmd_stdio(cmd))
+        })
+        .register_fn("push", |ssh: &mut SshInfo, files: Array, dest: &str| {
+            to_script(ssh.send_files_audited(&to_strings(files)?, Some(&dest.to_string())))
+        })
+        .register_fn("pull", |ssh: &mut SshInfo, files: Array, dest: &str| {
+            to_script(ssh.get_files(&to_strings(files)?, Some(&dest.to_string())))
+        })
+        .register_fn("arch", |ssh: &mut SshInfo| to_script(ssh.get_arch()))
+        .register_get("address", |ssh: &mut SshInfo| ssh.host_and_port())
+        .register_fn("to_string", |ssh: &mut SshInfo| ssh.host_and_port());
+}
+
+fn register_dut_info(engine: &mut Engine) {
+    engine
+        .register_type_with_name::<DutInfo>("DutInfo")
+        .register_fn("dut_info", |dut: &str| to_script(DutInfo::new(dut)))
+        .register_fn(
+            "fetch_info",
+            |dut: &str, keys: Array| -> ScriptResult<Map> {
+                let keys = to_strings(keys)?;
+                let keys: Vec<&str> = keys.iter().map(|k| k.as_str()).collect();
+                let ssh = to_script(SshInfo::new(dut))?;
+                Ok(to_map(&to_script(DutInfo::fetch_keys(&ssh, &keys))?))
+            },
+        )
+        .register_fn("cached_info", |dut: &str| -> ScriptResult<Map> {
+            let info = to_script(DutInfo::cached_info(dut))?;
+            let info: HashMap<String, String> =
+                info.into_iter().map(|(k, v)| (k, v.value)).collect();
+            Ok(to_map(&info))
+        })
+        .register_get("id", |d: &mut DutInfo| d.id().to_string())
+        .register_get("ssh", |d: &mut DutInfo| d.ssh().clone())
+        .register_get("info", |d: &mut DutInfo| to_map(d.info()))
+        .register_indexer_get(|d: &mut DutInfo, key: &str| {
+            d.info()
+                .get(key)
+                .map(|v| Dynamic::from(v.clone()))
+                .unwrap_or(Dynamic::UNIT)
+        })
+        .register_fn("to_string", |d: &mut DutInfo| d.id().to_string());
+}
+
+fn register_discovery(engine: &mut Engine) {
+    engine
+        .register_fn("list_duts", || -> ScriptResult<Map> {
+            let duts = to_script(SSH_CACHE.entries())?;
+            let duts: HashMap<String, String> = duts
+                .into_iter()
+                .map(|(id, ssh)| (id, ssh.host_and_port()))
+                .collect();
+            Ok(to_map(&duts))
+        })
+        .register_fn("group", |name: &str| -> ScriptResult<Array> {
+            Ok(to_array(to_script(dut_group(name))?))
+        })
+        .register_fn("discover", || -> ScriptResult<Array> {
+            Ok(to_array(to_script(discover(None))?))
+        })
+        .register_fn("discover", |targets: Array| -> ScriptResult<Array> {
+            let targets = to_strings(targets)?;
+            Ok(to_array(to_script(discover(Some(&targets)))?))
+        })
+        .register_fn("lium", |args: Array| {
+            let args = to_strings(args)?;
+            eprintln!("+ lium {}", args.join(" "));
+            to_script(run_lium(&args))
+        });
+}
+
+fn new_engine() -> Engine {
+    let mut engine = Engine::new();
+    register_ssh(&mut engine);
+    register_dut_info(&mut engine);
+    register_discovery(&mut engine);
+    engine
+}
+
+fn parse_constants(set: &[String]) -> Result<Scope<'static>> {
+    let mut scope = Scope::new();
+    for kv in set {
+        let (k, v) = kv
+            .split_once('=')
+            .context(anyhow!("Invalid --set {kv:?}. Use NAME=VALUE"))?;
+        scope.push_constant(k.to_string(), v.to_string());
+    }
+    Ok(scope)
+}
+
+fn run_run(args: &ArgsRun) -> Result<()> {
+    let script = read_to_string(&args.file).context(anyhow!("Failed to read {}", args.file))?;
+    let mut scope = parse_constants(&args.set)?;
+    let engine = new_engine();
+    let ast = engine
+        .compile(script)
+        .map_err(|e| anyhow!("Failed to parse {}: {e}", args.file))?;
+    engine
+        .run_ast_with_scope(&mut scope, &ast)
+        .map_err(|e| anyhow!("{}: {e}", args.file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_script() {
+        let engine = new_engine();
+        let mut scope = parse_constants(&["IMAGE=R120-15662.0.0".to_string()]).unwrap();
+        assert_eq!(
+            engine
+                .eval_with_scope::<String>(&mut scope, r#"`${IMAGE}`.split("-")[0]"#)
+                .unwrap(),
+            "R120"
+        );
+        assert!(engine
+            .run_with_scope(&mut scope, "IMAGE = \"R121\";")
+            .is_err());
+        assert!(parse_constants(&["IMAGE".to_string()]).is_err());
+        assert_eq!(to_strings(vec!["a".into()]).unwrap(), vec!["a"]);
+        assert!(to_strings(vec![Dynamic::from(1_i64)]).is_err());
+        assert!(engine.run(r#"lium([1])"#).is_err());
+    }
+}
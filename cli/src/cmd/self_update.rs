@@ -0,0 +1,181 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Updates the lium binary from the releases at `release_url` in the config:
+//!
+//! ```text
+//! <release_url>/<channel>/LATEST                  # the latest version (e.g. 0.1.2)
+//! <release_url>/<channel>/<version>/lium-<arch>   # the binary
+//! <release_url>/<channel>/<version>/lium-<arch>.sig
+//! ```
+//!
+//! The signature is made with `ssh-keygen -Y sign -n lium-release` and verified against the
+//! allowed_signers file at `release_signers` in the config. The principal of the release key in
+//! that file must be `lium-release` (e.g. `lium-release ssh-ed25519 AAAA...`).
+//!
+//! LATEST is not signed, so the version in it is validated, an older version is not installed
+//! without --force, and the new binary must report that version. This keeps a compromised
+//! endpoint from serving an older signed release as the latest.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::config::Config;
+use lium::fetch::download;
+use lium::util::ensure_online;
+use regex_macro::regex;
+use std::env::consts::ARCH;
+use std::env::current_exe;
+use std::fs;
+use std::fs::File;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::Command;
+use tempdir::TempDir;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const SIGNATURE_NAMESPACE: &str = "lium-release";
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// update lium to the latest release
+#[argh(subcommand, name = "self-update")]
+pub struct Args {
+    /// release channel (stable or dev, default: stable)
+    #[argh(option, default = "String::from(\"stable\")")]
+    channel: String,
+
+    /// only check if an update is available
+    #[argh(switch)]
+    check: bool,
+
+    /// install the release even if it is the running version or older
+    #[argh(switch)]
+    force: bool,
+}
+
+fn latest_path(url: &str, channel: &str) -> String {
+    format!("{url}/{channel}/LATEST")
+}
+
+fn binary_path(url: &str, channel: &str, version: &str) -> String {
+    format!("{url}/{channel}/{version}/lium-{ARCH}")
+}
+
+/// Parses a version like "0.1.2"
+fn parse_version(version: &str) -> Result<(u64, u64, u64)> {
+    let c = regex!(r"^(\d+)\.(\d+)\.(\d+)$")
+        .captures(version)
+        .context(anyhow!("Invalid version: {version:?}"))?;
+    Ok((c[1].parse()?, c[2].parse()?, c[3].parse()?))
+}
+
+fn verify_signature(signers: &str, file: &Path, signature: &Path) -> Result<()> {
+    let output = Command::new("ssh-keygen")
+        .args(["-Y", "verify", "-f", signers, "-I", SIGNATURE_NAMESPACE])
+        .args(["-n", SIGNATURE_NAMESPACE, "-s"])
+        .arg(signature)
+        .stdin(File::open(file)?)
+        .output()
+        .context("Failed to run ssh-keygen")?;
+    output.status.exit_ok().context(anyhow!(
+        "Failed to verify the signature: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    if args.channel != "stable" && args.channel != "dev" {
+        return Err(anyhow!("--channel should be stable or dev"));
+    }
+    ensure_online("Updating lium")?;
+    let config = Config::read()?;
+    let url = config
+        .release_url()
+        .context("Please run `lium config set release_url <gs:// or https:// URL>` first")?;
+    let signers = config.release_signers().context(
+        "Please run `lium config set release_signers <path to an allowed_signers file>` first",
+    )?;
+
+    let tmp = TempDir::new("lium_self_update")?;
+    let latest = tmp.path().join("LATEST");
+    download(&latest_path(&url, &args.channel), &latest)?;
+    let version = fs::read_to_string(&latest)?.trim().to_string();
+    if version.is_empty() {
+        return Err(anyhow!(
+            "No release is found in the {} channel",
+            args.channel
+        ));
+    }
+    let latest_version = parse_version(&version).context("Invalid LATEST")?;
+    println!("Current: v{VERSION}, latest ({}): v{version}", args.channel);
+    if args.check {
+        return Ok(());
+    }
+    if version == VERSION && !args.force {
+        println!("lium is up to date");
+        return Ok(());
+    }
+    if latest_version < parse_version(VERSION)? && !args.force {
+        return Err(anyhow!(
+            "v{version} is older than v{VERSION}. Use --force to downgrade"
+        ));
+    }
+
+    let url = binary_path(&url, &args.channel, &version);
+    let exe = current_exe()?;
+    let dir = exe.parent().context("Failed to get the dir of lium")?;
+    // Download next to the executable so that the rename below is atomic
+    let new_exe = dir.join(format!(".lium-{version}.tmp"));
+    let signature = tmp.path().join("lium.sig");
+    download(&format!("{url}.sig"), &signature)?;
+    let result = download(&url, &new_exe)
+        .and_then(|_| verify_signature(&signers, &new_exe, &signature))
+        .and_then(|_| {
+            fs::set_permissions(&new_exe, fs::Permissions::from_mode(0o755))?;
+            let output = Command::new(&new_exe)
+                .arg("version")
+                .output()
+                .context("Failed to run the new binary")?;
+            output
+                .status
+                .exit_ok()
+                .context("The new binary is not runnable on this machine")?;
+            let reported = String::from_utf8_lossy(&output.stdout).trim().to_string();
+            if reported != format!("lium v{version}") {
+                return Err(anyhow!(
+                    "The new binary reports {reported:?} instead of v{version}"
+                ));
+            }
+            fs::rename(&new_exe, &exe).context(anyhow!("Failed to replace {exe:?}"))
+        });
+    if result.is_err() {
+        let _ = fs::remove_file(&new_exe);
+    }
+    result?;
+    println!("Updated {exe:?} to v{version}");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn release_paths() {
+        assert_eq!(
+            latest_path("gs://bucket/lium", "dev"),
+            "gs://bucket/lium/dev/LATEST"
+        );
+        assert_eq!(
+            binary_path("https://example.com/lium", "stable", "0.1.2"),
+            format!("https://example.com/lium/stable/0.1.2/lium-{ARCH}")
+        );
+        assert_eq!(parse_version("0.10.2").unwrap(), (0, 10, 2));
+        assert!(parse_version("0.1.2/../../x").is_err());
+        assert!(parse_version("0.1").is_err());
+    }
+}
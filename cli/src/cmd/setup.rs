@@ -45,83 +45,6 @@ pub fn run(args: &Args) -> Result<()> {
     }
 }
 
-fn is_ccd_opened(cr50: &LocalServo) -> Result<bool> {
-    let ccd_state = cr50.run_cmd("Shell", "ccd")?;
-    let ccd_state = ccd_state
-        .split('\n')
-        .rev()
-        .find(|line| line.starts_with("State: "))
-        .context("Could not detect CCD state")?
-        .trim();
-    if ccd_state == "State: Locked" {
-        Ok(false)
-    } else if ccd_state == "State: Opened" {
-        Ok(true)
-    } else {
-        Err(anyhow!("Unexpected ccd state: {}", ccd_state))
-    }
-}
-
-/// Make DUTs connected via Servo ready for development
-/// "Ready for development" means:
-/// - CCD (Closed Case Debugging) is in "Open" state
-/// - A Servo is attached correctly
-/// - At least one Ethernet connection is available (so MAC addr and an IP address is known)
-fn setup_dut_ccd_open(cr50: &LocalServo) -> Result<()> {
-    if is_ccd_opened(cr50)? {
-        eprintln!("CCD is Opened");
-        return Ok(());
-    }
-    // Get rma_auth_challenge first, to get the code correctly
-    let rma_auth_challenge = cr50.run_cmd("Shell", "rma_auth")?;
-    // Try ccd open first since pre-MP devices may be able to open ccd without rma_auth
-    cr50.run_cmd("Shell", "ccd open")?;
-    for _ in 0..3 {
-        // Generate rma_auth URL to unlock and abort
-        let rma_auth_challenge: Vec<&str> = rma_auth_challenge
-            .split('\n')
-            .map(|s| s.trim())
-            .filter(|s| !s.is_empty())
-            .collect();
-        eprintln!("{:?}", rma_auth_challenge);
-        let rma_auth_challenge = rma_auth_challenge
-            .iter()
-            .skip_while(|s| *s != &"generated challenge:")
-            .nth(1)
-            .context("Could not get rma_auth challenge")?;
-        if !rma_auth_challenge.starts_with("RMA Auth error") {
-            eprintln!("CCD unlock is required.");
-            eprintln!(
-                r#"If you are eligible, visit https://chromeos.google.com/partner/console/cr50reset?challenge={rma_auth_challenge} to get the unlock code and paste the output below. ( For Googlers, go/rma-auth has more details. )"#,
-            );
-            eprintln!("If not, follow https://chromium.googlesource.com/chromiumos/platform/ec/+/cr50_stab/docs/case_closed_debugging_cr50.md#ccd-open to do this manually.");
-            let mut input = String::new();
-            std::io::stdin()
-                .read_line(&mut input)
-                .context("Failed to read a line")?;
-            let response = cr50
-                .run_cmd(
-                    "Shell",
-                    &format!(
-                        "rma_auth {}",
-                        input
-                            .trim()
-                            .split(':')
-                            .last()
-                            .context("code is invalid")?
-                            .trim()
-                    ),
-                )
-                .context("Failed to run rma_auth command")?;
-            return Err(anyhow!("response: {response}"));
-        }
-        eprintln!("Failed: {rma_auth_challenge}");
-        eprintln!("retrying in 3 sec...");
-        std::thread::sleep(std::time::Duration::from_secs(3));
-    }
-    Err(anyhow!("Failed to get rma_auth code."))
-}
-
 fn ensure_dut_network_connection(servo: &mut LocalServo) -> Result<DutInfo> {
     register_dut(&servo.read_ipv6_addr()?)
 }
@@ -149,6 +72,11 @@ fn ensure_dev_gbb_flags(repo: &str, cr50: &LocalServo) -> Result<()> {
     Ok(())
 }
 
+/// Make DUTs connected via Servo ready for development
+/// "Ready for development" means:
+/// - CCD (Closed Case Debugging) is in "Open" state
+/// - A Servo is attached correctly
+/// - At least one Ethernet connection is available (so MAC addr and an IP address is known)
 fn setup_dut(repo: &str, cr50: &LocalServo) -> Result<()> {
     ensure_testing_rsa_is_there()?;
     let config = Config::read()?;
@@ -159,7 +87,7 @@ fn setup_dut(repo: &str, cr50: &LocalServo) -> Result<()> {
     }
 
     eprintln!("Setting up DUT: {}", cr50.serial());
-    setup_dut_ccd_open(cr50)?;
+    cr50.open_ccd()?;
     let mut servo = get_servo_attached_to_cr50(cr50)?;
     eprintln!("Using Servo: {}", servo.serial());
     if ensure_dut_network_connection(&mut servo).is_err() {
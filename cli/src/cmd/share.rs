@@ -0,0 +1,83 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::share::fetch_from_peer;
+use lium::share::peer_index;
+use lium::share::serve;
+use lium::share::DEFAULT_SHARE_PORT;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// share the artifact store with other workstations over HTTP. the server and the clients need
+/// the same token in $LIUM_SHARE_TOKEN or ~/.lium/share_token (generated on the first use).
+#[argh(subcommand, name = "share")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Fetch(ArgsFetch),
+    Serve(ArgsServe),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Fetch(args) => run_fetch(args),
+        SubCommand::Serve(args) => run_serve(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// serve the artifact store until interrupted
+#[argh(subcommand, name = "serve")]
+pub struct ArgsServe {
+    /// port to listen on (default: 9100)
+    #[argh(option, default = "DEFAULT_SHARE_PORT")]
+    port: u16,
+
+    /// address to listen on (default: 127.0.0.1). use 0.0.0.0 to share with other machines.
+    #[argh(option, default = "String::from(\"127.0.0.1\")")]
+    bind: String,
+}
+fn run_serve(args: &ArgsServe) -> Result<()> {
+    serve(&args.bind, args.port)
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// fetch artifacts from a trusted peer (host[:port]) into the local store, verifying their
+/// checksums in the index of the peer. lists the artifacts of the peer if no names are given.
+#[argh(subcommand, name = "fetch")]
+pub struct ArgsFetch {
+    /// peer running `lium share serve`
+    #[argh(positional)]
+    peer: String,
+
+    /// names of the artifacts (as shown by `lium cache ls`)
+    #[argh(positional)]
+    names: Vec<String>,
+}
+fn run_fetch(args: &ArgsFetch) -> Result<()> {
+    let index = peer_index(&args.peer)?;
+    if args.names.is_empty() {
+        let mut names: Vec<_> = index.iter().collect();
+        names.sort_by_key(|(name, _)| name.to_string());
+        for (name, e) in names {
+            println!("{name:60} {:>10.1} MB", e.size as f64 / 1024.0 / 1024.0);
+        }
+        return Ok(());
+    }
+    for name in &args.names {
+        let entry = index
+            .get(name)
+            .ok_or_else(|| anyhow!("{name} is not shared by {}", args.peer))?;
+        let path = fetch_from_peer(&args.peer, name, entry, None)?;
+        println!("{}", path.display());
+    }
+    Ok(())
+}
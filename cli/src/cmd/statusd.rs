@@ -0,0 +1,33 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::statusd;
+use std::time::Duration;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// keep the status of the cached DUTs fresh in the background (run with & or as a service)
+#[argh(subcommand, name = "statusd")]
+pub struct Args {
+    /// seconds between the checks of the DUTs (default: 60)
+    #[argh(option, default = "60")]
+    interval: u64,
+
+    /// print the state held by the running statusd as JSON and exit
+    #[argh(switch)]
+    query: bool,
+}
+
+pub fn run(args: &Args) -> Result<()> {
+    if args.query {
+        let states = statusd::query(None).context("statusd is not running")?;
+        println!("{}", serde_json::to_string_pretty(&states)?);
+        return Ok(());
+    }
+    statusd::serve(Duration::from_secs(args.interval))
+}
@@ -0,0 +1,109 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Result;
+use argh::FromArgs;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::symbols::builder_path_of_dut;
+use lium::symbols::cached_symbols;
+use lium::symbols::fetch_symbols;
+use lium::symbols::remove_symbols;
+use lium::symbols::SymbolKind;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// fetch and manage debug symbols of builds
+#[argh(subcommand, name = "symbols")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    Fetch(ArgsFetch),
+    Ls(ArgsLs),
+    Rm(ArgsRm),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::Fetch(args) => run_fetch(args),
+        SubCommand::Ls(args) => run_ls(args),
+        SubCommand::Rm(args) => run_rm(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// download symbols of the build on a DUT (or of a builder path) into the local cache
+#[argh(subcommand, name = "fetch")]
+struct ArgsFetch {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(option)]
+    dut: Option<String>,
+
+    /// builder path of the build (e.g. brya-release/R120-15662.0.0)
+    #[argh(option)]
+    builder_path: Option<String>,
+
+    /// fetch breakpad symbols instead of the debug info for gdb
+    #[argh(switch)]
+    breakpad: bool,
+}
+fn run_fetch(args: &ArgsFetch) -> Result<()> {
+    let builder_path = match (&args.dut, &args.builder_path) {
+        (Some(dut), None) => {
+            ensure_testing_rsa_is_there()?;
+            builder_path_of_dut(&SshInfo::new(dut)?)?
+        }
+        (None, Some(path)) => path.clone(),
+        _ => return Err(anyhow!("Please specify one of --dut or --builder-path")),
+    };
+    let kind = if args.breakpad {
+        SymbolKind::Breakpad
+    } else {
+        SymbolKind::Debug
+    };
+    let dir = fetch_symbols(&builder_path, kind)?;
+    println!("{}", dir.to_string_lossy());
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// list symbols in the local cache
+#[argh(subcommand, name = "ls")]
+struct ArgsLs {}
+fn run_ls(_args: &ArgsLs) -> Result<()> {
+    for (builder_path, kinds) in cached_symbols()? {
+        println!("{builder_path:50} {kinds:?}");
+    }
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// remove symbols from the local cache
+#[argh(subcommand, name = "rm")]
+struct ArgsRm {
+    /// builder paths to remove (e.g. brya-release/R120-15662.0.0)
+    #[argh(positional)]
+    builder_paths: Vec<String>,
+
+    /// remove all the cached symbols
+    #[argh(switch)]
+    all: bool,
+}
+fn run_rm(args: &ArgsRm) -> Result<()> {
+    let builder_paths = if args.all {
+        cached_symbols()?.into_iter().map(|(p, _)| p).collect()
+    } else {
+        args.builder_paths.clone()
+    };
+    for p in builder_paths {
+        remove_symbols(&p)?;
+        println!("Removed {p}");
+    }
+    Ok(())
+}
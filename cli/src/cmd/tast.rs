@@ -0,0 +1,429 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use argh::FromArgs;
+use chrono::Local;
+use glob::Pattern;
+use lium::cache::KvCache;
+use lium::cancel;
+use lium::chroot::Chroot;
+use lium::config::Config;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::progress::Progress;
+use lium::repo::get_repo_dir;
+use lium::results::parse_tast_results;
+use lium::results::record_results;
+use lium::results::TestResult;
+use lium::results::TestTarget;
+use lium::status;
+use lium::summary::parse_report_specs;
+use lium::summary::write_reports;
+use lium::util::gen_path_in_lium_dir;
+use lium::warning;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Tast test wrapper
+#[argh(subcommand, name = "tast")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+
+pub static TEST_CACHE: KvCache<Vec<String>> = KvCache::new("tast_cache");
+static DEFAULT_BUNDLE: &str = "cros";
+
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    List(ArgsList),
+    RetryAnalyze(ArgsRetryAnalyze),
+    Run(ArgsRun),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::List(args) => run_tast_list(args),
+        SubCommand::RetryAnalyze(args) => run_tast_retry_analyze(args),
+        SubCommand::Run(args) => run_tast_run(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Get tast test for the target DUT
+#[argh(subcommand, name = "list")]
+pub struct ArgsList {
+    /// target cros repo directory
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// target DUT
+    #[argh(option)]
+    dut: Option<String>,
+
+    /// glob pattern of the listint test
+    #[argh(positional)]
+    tests: Option<String>,
+
+    /// only show cached list
+    #[argh(switch)]
+    cached: bool,
+}
+
+fn print_cached_tests_in_bundle(filter: &Pattern, bundle: &str) -> Result<()> {
+    if let Ok(Some(tests)) = TEST_CACHE.get(bundle) {
+        for t in &tests {
+            if filter.matches(t) {
+                println!("{t}");
+            }
+        }
+        return Ok(());
+    }
+    Err(anyhow!("No cache found"))
+}
+
+fn print_cached_tests(filter: &Pattern, bundles: &Vec<&str>) -> Result<()> {
+    if bundles.is_empty() {
+        print_cached_tests_in_bundle(filter, DEFAULT_BUNDLE)
+    } else {
+        // Ensure all bundles are cached.
+        for b in bundles {
+            if TEST_CACHE.get(b)?.is_none() {
+                return Err(anyhow!("No cache found for {b}."));
+            }
+        }
+        for b in bundles {
+            print_cached_tests_in_bundle(filter, b)?
+        }
+        Ok(())
+    }
+}
+
+fn update_cached_tests_in_bundle(bundle: &str, chroot: &Chroot, port: u16) -> Result<()> {
+    let list = chroot.exec_in_chroot(&[
+        "tast",
+        "list",
+        "-installbuilddeps",
+        &format!("--buildbundle={}", bundle),
+        &format!("127.0.0.1:{}", port),
+    ])?;
+    let tests: Vec<String> = list.lines().map(|s| s.to_string()).collect::<Vec<_>>();
+    TEST_CACHE.set(bundle, tests)?;
+    Ok(())
+}
+
+fn update_cached_tests(bundles: &Vec<&str>, dut: &str, repodir: &str) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let chroot = Chroot::new(repodir)?;
+    let ssh = SshInfo::new(dut).context("failed to create SshInfo")?;
+    let port = ssh.start_ssh_forwarding_range_background(4100..4200)?;
+
+    // To avoid "build failed: failed checking build deps:" error
+    chroot.run_bash_script_in_chroot("update_chroot", "./update_chroot", None)?;
+
+    if bundles.is_empty() {
+        update_cached_tests_in_bundle(DEFAULT_BUNDLE, &chroot, port)
+    } else {
+        for b in bundles {
+            update_cached_tests_in_bundle(b, &chroot, port)?
+        }
+        Ok(())
+    }
+}
+
+fn run_tast_list(args: &ArgsList) -> Result<()> {
+    let filter = if let Some(_tests) = &args.tests {
+        Pattern::new(_tests)?
+    } else {
+        Pattern::new("*")?
+    };
+    let config = Config::read()?;
+    let bundles = config.tast_bundles();
+
+    if print_cached_tests(&filter, &bundles).is_ok() || args.cached {
+        return Ok(());
+    }
+
+    let dut = if let Some(_dut) = &args.dut {
+        _dut
+    } else {
+        return Err(anyhow!(
+            "Please re-run with --dut option to cache test names"
+        ));
+    };
+
+    update_cached_tests(&bundles, dut, &get_repo_dir(&args.repo)?)?;
+
+    print_cached_tests(&filter, &bundles)?;
+
+    Ok(())
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// Get tast test for the target DUT
+#[argh(subcommand, name = "run")]
+pub struct ArgsRun {
+    /// target cros repo directory
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// target DUT
+    #[argh(option)]
+    dut: String,
+
+    /// write a summary of the results (repeatable, e.g. junit:out.xml, md:summary.md)
+    #[argh(option)]
+    report: Vec<String>,
+
+    /// test name or pattern
+    #[argh(positional)]
+    tests: String,
+}
+
+fn bundle_has_test(bundle: &str, filter: &Pattern) -> bool {
+    if let Ok(Some(tests)) = TEST_CACHE.get(bundle) {
+        for t in tests {
+            if filter.matches(&t) {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Runs the tests and records their results. `results_dir` is relative to ~/.lium, which is
+/// mounted at /lium in the chroot so that the results can be read from outside.
+fn run_test_with_bundle(
+    bundle: &str,
+    filter: &Pattern,
+    chroot: &Chroot,
+    port: u16,
+    target: &TestTarget,
+    results_dir: &str,
+) -> Result<Vec<TestResult>> {
+    let result = chroot.run_bash_script_in_chroot(
+        "tast_run_cmd",
+        &format!("tast run -installbuilddeps -buildbundle={bundle} -resultsdir=/lium/{results_dir} 127.0.0.1:{port} {filter}"),
+        None,
+    );
+    // Record the tests that ran even if tast failed in the middle
+    let results = gen_path_in_lium_dir(&format!("{results_dir}/results.json"))
+        .and_then(|path| Ok(fs::read_to_string(path)?))
+        .and_then(|json| parse_tast_results(&json, target));
+    match &results {
+        Ok(results) => record_results(results),
+        Err(e) => warning!("Failed to read the tast results: {e:#}"),
+    }
+    result?;
+    results
+}
+
+fn gen_results_dir(bundle: &str) -> String {
+    format!(
+        "tmp/tast_results/{}_{bundle}",
+        Local::now().format("%Y%m%d_%H%M%S")
+    )
+}
+
+fn run_tast_run(args: &ArgsRun) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let reports = parse_report_specs(&args.report)?;
+    let filter = Pattern::new(&args.tests)?;
+    let repodir = get_repo_dir(&args.repo)?;
+    let chroot = Chroot::new(&repodir)?;
+    let ssh = SshInfo::new(&args.dut).context("failed to create SshInfo")?;
+    // setup port forwarding for chroot.
+    let port = ssh.start_ssh_forwarding_range_background(4100..4200)?;
+
+    let target = TestTarget::from_dut_or_default(&args.dut);
+
+    let config = Config::read()?;
+    let bundles = config.tast_bundles();
+    let mut results = Vec::new();
+    // Report the bundles that ran even if a later one fails
+    let run = (|| -> Result<()> {
+        if bundles.is_empty() {
+            let results_dir = gen_results_dir(DEFAULT_BUNDLE);
+            results.extend(run_test_with_bundle(
+                DEFAULT_BUNDLE,
+                &filter,
+                &chroot,
+                port,
+                &target,
+                &results_dir,
+            )?);
+        } else {
+            for b in bundles {
+                if bundle_has_test(b, &filter) {
+                    results.extend(run_test_with_bundle(
+                        b,
+                        &filter,
+                        &chroot,
+                        port,
+                        &target,
+                        &gen_results_dir(b),
+                    )?);
+                }
+            }
+        }
+        Ok(())
+    })();
+    write_reports(&reports, &format!("tast run {}", args.tests), &results)?;
+    run
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// run a test repeatedly and classify its failures to triage flakiness
+#[argh(subcommand, name = "retry-analyze")]
+pub struct ArgsRetryAnalyze {
+    /// target cros repo directory
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// target DUT
+    #[argh(option)]
+    dut: String,
+
+    /// number of runs (default: 20)
+    #[argh(option, default = "20")]
+    times: usize,
+
+    /// test name
+    #[argh(positional)]
+    test: String,
+}
+
+/// Returns the first line of an error with the numbers masked, so that the same failure in
+/// different runs (e.g. with different timestamps, PIDs or durations) has the same signature
+fn failure_signature(detail: &str) -> String {
+    let line = detail.lines().next().unwrap_or_default();
+    let mut signature = String::new();
+    let mut in_number = false;
+    for c in line.chars() {
+        if c.is_ascii_digit() {
+            if !in_number {
+                signature.push('N');
+            }
+            in_number = true;
+        } else {
+            in_number = false;
+            signature.push(c);
+        }
+    }
+    signature.trim().chars().take(160).collect()
+}
+
+struct FailureGroup {
+    runs: Vec<usize>,
+    /// Test logs of the first run with this signature
+    log_dir: PathBuf,
+}
+
+fn run_tast_retry_analyze(args: &ArgsRetryAnalyze) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let filter = Pattern::new(&args.test)?;
+    let repodir = get_repo_dir(&args.repo)?;
+    let chroot = Chroot::new(&repodir)?;
+    let ssh = SshInfo::new(&args.dut).context("failed to create SshInfo")?;
+    let port = ssh.start_ssh_forwarding_range_background(4100..4200)?;
+    let target = TestTarget::from_dut_or_default(&args.dut);
+    let config = Config::read()?;
+    let bundle = config
+        .tast_bundles()
+        .into_iter()
+        .find(|b| bundle_has_test(b, &filter))
+        .unwrap_or(DEFAULT_BUNDLE);
+    cancel::install_handler()?;
+
+    let analysis_dir = format!("tast_retry/{}", Local::now().format("%Y%m%d_%H%M%S"));
+    let progress = Progress::new(&format!("Running {}", args.test), args.times as u64);
+    let mut passed = 0;
+    let mut groups: BTreeMap<String, FailureGroup> = BTreeMap::new();
+    for i in 1..=args.times {
+        if cancel::is_cancelled() {
+            warning!("Interrupted after {} runs", i - 1);
+            break;
+        }
+        let results_dir = format!("{analysis_dir}/run_{i}");
+        let results = run_test_with_bundle(bundle, &filter, &chroot, port, &target, &results_dir);
+        progress.inc(1);
+        let failure = match results {
+            Ok(results) => match results.iter().find(|r| r.test == args.test) {
+                Some(r) if r.status == "PASS" => None,
+                Some(r) if r.status == "SKIP" => {
+                    return Err(anyhow!("{} is skipped: {}", args.test, r.detail));
+                }
+                Some(r) => Some(r.detail.clone()),
+                None => return Err(anyhow!("{} did not run. Is the name correct?", args.test)),
+            },
+            Err(_) if cancel::is_cancelled() => continue,
+            Err(e) => Some(format!("tast run failed: {e:#}")),
+        };
+        match failure {
+            None => {
+                passed += 1;
+                status!("[{i}/{}] PASS", args.times);
+            }
+            Some(detail) => {
+                let signature = failure_signature(&detail);
+                status!("[{i}/{}] FAIL: {signature}", args.times);
+                groups
+                    .entry(signature)
+                    .or_insert_with(|| FailureGroup {
+                        runs: Vec::new(),
+                        log_dir: gen_path_in_lium_dir(&format!(
+                            "{results_dir}/tests/{}",
+                            args.test
+                        ))
+                        .unwrap_or_default(),
+                    })
+                    .runs
+                    .push(i);
+            }
+        }
+    }
+    drop(progress);
+
+    let failed: usize = groups.values().map(|g| g.runs.len()).sum();
+    let runs = passed + failed;
+    println!("runs: {runs}");
+    println!("passed: {passed}");
+    println!("failed: {failed}");
+    if runs > 0 {
+        println!("flake rate: {:.2}%", failed as f64 * 100.0 / runs as f64);
+    }
+    let mut groups: Vec<_> = groups.into_iter().collect();
+    groups.sort_by_key(|(_, g)| std::cmp::Reverse(g.runs.len()));
+    for (signature, group) in &groups {
+        println!("{:4}x {signature}", group.runs.len());
+        println!("      runs: {:?}", group.runs);
+        println!("      logs: {}", group.log_dir.display());
+    }
+    cancel::check()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failure_signature_masks_numbers() {
+        assert_eq!(
+            failure_signature("Timed out after 30.5s waiting for pid 1234\nstack trace"),
+            "Timed out after N.Ns waiting for pid N"
+        );
+        assert_eq!(
+            failure_signature("Timed out after 12.0s waiting for pid 99"),
+            failure_signature("Timed out after 30.5s waiting for pid 1234")
+        );
+        assert_eq!(failure_signature(""), "");
+    }
+}
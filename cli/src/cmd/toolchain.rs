@@ -0,0 +1,80 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::Result;
+use argh::FromArgs;
+use lium::chroot::Chroot;
+use lium::cros::ensure_testing_rsa_is_there;
+use lium::dut::SshInfo;
+use lium::repo::get_repo_dir;
+use lium::toolchain::Toolchain;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// resolve cross toolchains for DUTs
+#[argh(subcommand, name = "toolchain")]
+pub struct Args {
+    #[argh(subcommand)]
+    nested: SubCommand,
+}
+#[derive(FromArgs, PartialEq, Debug)]
+#[argh(subcommand)]
+enum SubCommand {
+    ForDut(ArgsForDut),
+}
+pub fn run(args: &Args) -> Result<()> {
+    match &args.nested {
+        SubCommand::ForDut(args) => run_for_dut(args),
+    }
+}
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// show the cross toolchain and build environment for a DUT
+#[argh(subcommand, name = "for-dut")]
+struct ArgsForDut {
+    /// a DUT identifier (e.g. 127.0.0.1, localhost:2222)
+    #[argh(positional)]
+    dut: String,
+
+    /// target cros repo dir (to find the board sysroot)
+    #[argh(option)]
+    repo: Option<String>,
+
+    /// print the environment as shell exports (e.g. eval "$(lium toolchain for-dut ... --export)")
+    #[argh(switch)]
+    export: bool,
+
+    /// install missing pieces (the Rust target, and the toolchain and sysroot in the chroot)
+    #[argh(switch)]
+    install: bool,
+}
+fn run_for_dut(args: &ArgsForDut) -> Result<()> {
+    ensure_testing_rsa_is_there()?;
+    let ssh = SshInfo::new(&args.dut)?;
+    let repo = get_repo_dir(&args.repo).ok();
+    let mut toolchain = Toolchain::for_dut(&ssh, repo.as_deref())?;
+    if args.install {
+        let chroot = repo.as_ref().map(|r| Chroot::new(r)).transpose()?;
+        toolchain.install(chroot.as_ref())?;
+        // The sysroot may be created by the install
+        toolchain = Toolchain::for_dut(&ssh, repo.as_deref())?;
+    }
+    if args.export {
+        for (k, v) in toolchain.env() {
+            println!("export {k}='{v}'");
+        }
+        return Ok(());
+    }
+    println!("arch: {}", toolchain.arch);
+    println!("board: {}", toolchain.board.as_deref().unwrap_or("-"));
+    println!("cros target: {}", toolchain.cros_target);
+    println!("rust target: {}", toolchain.rust_target);
+    println!("sysroot: {}", toolchain.sysroot.as_deref().unwrap_or("-"));
+    match toolchain.cc() {
+        Ok(cc) => println!("CC: {cc}"),
+        Err(e) => println!("CC: not available ({e:#})"),
+    }
+    Ok(())
+}
@@ -13,7 +13,11 @@ use anyhow::Result;
 use regex_macro::regex;
 use std::process::Command;
 
+use chrono::Local;
+use lium::audit::record_command;
 use lium::cache::KvCache;
+use lium::cancel;
+use lium::util::ensure_online;
 
 extern crate lazy_static;
 
@@ -22,6 +26,7 @@ mod cmd;
 static VERSION_TO_MILESTONE_CACHE: KvCache<String> = KvCache::new("version_cache");
 
 fn list_gs_files(pattern: &str) -> Result<String> {
+    ensure_online("Listing files on Google Storage")?;
     let cmd = format!("gsutil.py ls {}", pattern.trim());
     println!("{:?}", cmd);
     let output = Command::new("bash")
@@ -63,5 +68,15 @@ fn lookup_full_version(input: &str) -> Result<String> {
 
 fn main() -> Result<()> {
     let args: cmd::TopLevel = argh::from_env();
-    cmd::run(&args)
+    let started_at = Local::now();
+    let result = cmd::run(&args);
+    // The log and the metrics are best-effort and must not change the result of the command
+    let _ = record_command(started_at, &result);
+    let duration = (Local::now() - started_at).num_milliseconds() as f64 / 1000.0;
+    let _ = cmd::metrics::record(duration, result.is_ok());
+    if result.is_err() && cancel::is_cancelled() {
+        eprintln!("Cancelled");
+        std::process::exit(cancel::EXIT_CODE);
+    }
+    result
 }
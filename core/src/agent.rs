@@ -0,0 +1,246 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A keep-alive agent on DUTs behind NATs. The agent keeps a reverse ssh tunnel to the
+//! workstation, so that the sshd of the DUT is reachable via 127.0.0.1:<port> on it.
+
+use crate::cache::KvCache;
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use dirs::home_dir;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::PermissionsExt;
+
+/// An agent installed on a DUT
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct AgentInfo {
+    /// host:port of the sshd on the workstation that the agent dials back to
+    pub callback: String,
+    /// user on the workstation
+    pub user: String,
+    /// port on the workstation forwarded to the sshd of the DUT
+    pub port: u16,
+    /// UNIX time in seconds
+    pub installed_at: i64,
+}
+/// Installed agents keyed by DUT ID
+pub static DUT_AGENTS: KvCache<AgentInfo> = KvCache::new("dut_agents");
+
+const AGENT_DIR: &str = "/usr/local/lium_agent";
+const AGENT_JOB: &str = "lium-agent";
+const FIRST_AGENT_PORT: u16 = 22200;
+
+/// Splits "host:port" (or "[v6addr]:port") of a callback address
+pub fn parse_callback(callback: &str) -> Result<(String, u16)> {
+    let (host, port) = callback
+        .rsplit_once(':')
+        .filter(|(h, _)| !h.is_empty())
+        .context(anyhow!("Invalid callback {callback:?}. Use <host>:<port>"))?;
+    let port = port
+        .parse()
+        .context(anyhow!("Invalid port in callback {callback:?}"))?;
+    Ok((
+        host.trim_matches(|c| c == '[' || c == ']').to_string(),
+        port,
+    ))
+}
+
+/// Returns a port on the workstation not used by other agents
+pub fn allocate_agent_port() -> Result<u16> {
+    let used: Vec<u16> = DUT_AGENTS.entries()?.values().map(|a| a.port).collect();
+    (FIRST_AGENT_PORT..u16::MAX)
+        .find(|p| !used.contains(p))
+        .context("No port is available for the agent")
+}
+
+fn key_comment(id: &str) -> String {
+    format!("lium-agent:{id}")
+}
+
+fn agent_script(info: &AgentInfo) -> Result<String> {
+    let (host, port) = parse_callback(&info.callback)?;
+    Ok(format!(
+        r#"#!/bin/sh
+# Generated by lium. Keeps a reverse tunnel to {host} so that this DUT is reachable there.
+while true; do
+  ssh -F none -i {AGENT_DIR}/id_ed25519 -o BatchMode=yes \
+    -o StrictHostKeyChecking=no -o UserKnownHostsFile=/dev/null \
+    -o ServerAliveInterval=15 -o ServerAliveCountMax=3 -o ExitOnForwardFailure=yes \
+    -N -R {}:localhost:22 -p {port} {}@{host}
+  sleep 10
+done
+"#,
+        info.port, info.user
+    ))
+}
+
+fn install_script(id: &str, info: &AgentInfo) -> Result<String> {
+    let agent = agent_script(info)?;
+    let comment = key_comment(id);
+    Ok(format!(
+        r#"set -e
+mount -o remount,rw / 2>/dev/null || true
+mkdir -p {AGENT_DIR}
+test -f {AGENT_DIR}/id_ed25519 || ssh-keygen -q -t ed25519 -N '' -C '{comment}' -f {AGENT_DIR}/id_ed25519
+cat > {AGENT_DIR}/agent.sh <<'LIUM_EOF'
+{agent}LIUM_EOF
+chmod 755 {AGENT_DIR}/agent.sh
+if command -v initctl >/dev/null; then
+  cat > /etc/init/{AGENT_JOB}.conf <<'LIUM_EOF'
+description "lium reverse tunnel agent"
+start on started system-services
+stop on stopping system-services
+respawn
+exec {AGENT_DIR}/agent.sh
+LIUM_EOF
+  initctl stop {AGENT_JOB} >/dev/null 2>&1 || true
+  initctl start {AGENT_JOB} >/dev/null
+else
+  cat > /etc/systemd/system/{AGENT_JOB}.service <<'LIUM_EOF'
+[Unit]
+Description=lium reverse tunnel agent
+After=network-online.target
+[Service]
+ExecStart={AGENT_DIR}/agent.sh
+Restart=always
+[Install]
+WantedBy=multi-user.target
+LIUM_EOF
+  systemctl daemon-reload
+  systemctl enable --now {AGENT_JOB}
+fi
+cat {AGENT_DIR}/id_ed25519.pub
+"#
+    ))
+}
+
+const UNINSTALL_SCRIPT: &str = r#"
+mount -o remount,rw / 2>/dev/null
+if command -v initctl >/dev/null; then
+  initctl stop lium-agent
+  rm -f /etc/init/lium-agent.conf
+else
+  systemctl disable --now lium-agent
+  rm -f /etc/systemd/system/lium-agent.service
+fi
+rm -rf /usr/local/lium_agent
+true
+"#;
+
+fn host_authorized_keys_path() -> Result<std::path::PathBuf> {
+    Ok(home_dir()
+        .context("Failed to get the home dir")?
+        .join(".ssh")
+        .join("authorized_keys"))
+}
+
+/// Returns an authorized_keys line that only allows the agent to listen on its port
+fn authorized_keys_line(pubkey: &str, port: u16) -> String {
+    format!(
+        r#"restrict,port-forwarding,permitlisten="localhost:{port}",command="/bin/false" {}"#,
+        pubkey.trim()
+    )
+}
+
+fn remove_host_authorized_key(id: &str) -> Result<()> {
+    let path = host_authorized_keys_path()?;
+    let Ok(keys) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+    let comment = key_comment(id);
+    let kept: Vec<&str> = keys
+        .lines()
+        .filter(|l| !l.ends_with(&format!(" {comment}")))
+        .collect();
+    fs::write(&path, kept.join("\n") + "\n")?;
+    Ok(())
+}
+
+fn add_host_authorized_key(id: &str, line: &str) -> Result<()> {
+    remove_host_authorized_key(id)?;
+    let path = host_authorized_keys_path()?;
+    fs::create_dir_all(path.parent().context("Invalid authorized_keys path")?)?;
+    let mut f = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)?;
+    f.set_permissions(fs::Permissions::from_mode(0o600))?;
+    writeln!(f, "{line}")?;
+    Ok(())
+}
+
+/// Installs the agent to the DUT and accepts its key on this workstation. The same port is
+/// kept on reinstallation.
+pub fn install_agent(ssh: &SshInfo, id: &str, callback: &str, user: &str) -> Result<AgentInfo> {
+    let port = match DUT_AGENTS.get(id)? {
+        Some(prev) => prev.port,
+        None => allocate_agent_port()?,
+    };
+    let info = AgentInfo {
+        callback: callback.to_string(),
+        user: user.to_string(),
+        port,
+        installed_at: Local::now().timestamp(),
+    };
+    let output = ssh
+        .run_cmd_stdio(&install_script(id, &info)?)
+        .context("Failed to install the agent (is the rootfs verification removed?)")?;
+    let pubkey = output
+        .lines()
+        .last()
+        .filter(|l| l.starts_with("ssh-"))
+        .context("Failed to get the public key of the agent")?;
+    add_host_authorized_key(id, &authorized_keys_line(pubkey, port))?;
+    DUT_AGENTS.set(id, info.clone())?;
+    Ok(info)
+}
+
+/// Removes the agent from the DUT (if reachable) and from this workstation
+pub fn uninstall_agent(ssh: &SshInfo, id: &str) -> Result<()> {
+    if let Err(e) = ssh.run_cmd_stdio(UNINSTALL_SCRIPT) {
+        eprintln!("Failed to remove the agent from the DUT: {e:#}");
+    }
+    remove_host_authorized_key(id)?;
+    DUT_AGENTS.remove(id)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn agent_config() {
+        assert_eq!(
+            parse_callback("ws.example.com:22").unwrap(),
+            ("ws.example.com".to_string(), 22)
+        );
+        assert_eq!(
+            parse_callback("[2001:db8::1]:2222").unwrap(),
+            ("2001:db8::1".to_string(), 2222)
+        );
+        assert!(parse_callback("ws.example.com").is_err());
+        assert_eq!(
+            authorized_keys_line("ssh-ed25519 AAAA lium-agent:x\n", 22200),
+            r#"restrict,port-forwarding,permitlisten="localhost:22200",command="/bin/false" ssh-ed25519 AAAA lium-agent:x"#
+        );
+        let info = AgentInfo {
+            callback: "ws:22".to_string(),
+            user: "me".to_string(),
+            port: 22200,
+            installed_at: 0,
+        };
+        assert!(agent_script(&info)
+            .unwrap()
+            .contains("-N -R 22200:localhost:22 -p 22 me@ws"));
+    }
+}
@@ -0,0 +1,105 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A log of the lium commands run on this machine, to reproduce what happened to a DUT (e.g. in
+//! `lium report bug`). Each line of ~/.lium/command_log.jsonl is a CommandRecord.
+
+use crate::util::gen_path_in_lium_dir;
+use anyhow::Result;
+use chrono::DateTime;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::read_to_string;
+use std::fs::write;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+const COMMAND_LOG: &str = "command_log.jsonl";
+/// The log is trimmed to the latest half of this when it gets longer
+const MAX_RECORDS: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CommandRecord {
+    pub args: Vec<String>,
+    pub cwd: String,
+    /// UNIX time in seconds
+    pub started_at: i64,
+    pub duration_secs: f64,
+    /// The error message if the command failed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub error: Option<String>,
+}
+impl CommandRecord {
+    /// Returns the command line in a form that can be pasted to a shell
+    pub fn command_line(&self) -> String {
+        self.args
+            .iter()
+            .map(|a| {
+                if !a.is_empty()
+                    && a.chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,%+".contains(c))
+                {
+                    a.clone()
+                } else {
+                    format!("'{}'", a.replace('\'', r"'\''"))
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+    /// Returns true if any argument mentions the DUT
+    pub fn mentions(&self, dut: &str) -> bool {
+        self.args.iter().any(|a| a.contains(dut))
+    }
+}
+
+/// Appends the command of this process to the log
+pub fn record_command<T>(started_at: DateTime<Local>, result: &Result<T>) -> Result<()> {
+    let mut args: Vec<String> = std::env::args().collect();
+    if let Some(name) = args
+        .first()
+        .and_then(|a| std::path::Path::new(a).file_name())
+    {
+        args[0] = name.to_string_lossy().to_string();
+    }
+    let record = CommandRecord {
+        args,
+        cwd: std::env::current_dir()?.to_string_lossy().to_string(),
+        started_at: started_at.timestamp(),
+        duration_secs: (Local::now() - started_at).num_milliseconds() as f64 / 1000.0,
+        error: result.as_ref().err().map(|e| format!("{e:#}")),
+    };
+    let path = gen_path_in_lium_dir(COMMAND_LOG)?;
+    let mut f = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(f, "{}", serde_json::to_string(&record)?)?;
+    drop(f);
+    let log = read_to_string(&path)?;
+    let lines: Vec<&str> = log.lines().collect();
+    if lines.len() > MAX_RECORDS {
+        write(
+            &path,
+            lines[lines.len() - MAX_RECORDS / 2..].join("\n") + "\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the recorded commands, oldest first
+pub fn read_command_log() -> Result<Vec<CommandRecord>> {
+    let path = gen_path_in_lium_dir(COMMAND_LOG)?;
+    let log = match read_to_string(path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    // Skip broken lines (e.g. written by two processes at once)
+    Ok(log
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect())
+}
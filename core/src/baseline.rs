@@ -0,0 +1,157 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Configuration baselines of DUTs. A baseline is a snapshot of the things that silently change
+//! the behavior of a DUT (flags, packages, kernel cmdline, firmware versions and hashes of key
+//! system files), captured once and compared later to detect drift.
+
+use crate::cache::KvCache;
+use crate::dut::SshInfo;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Files hashed in addition to the ones given by the user
+pub const DEFAULT_BASELINE_FILES: [&str; 6] = [
+    "/etc/chrome_dev.conf",
+    "/etc/lsb-release",
+    "/etc/hosts",
+    "/etc/init/ui.conf",
+    "/etc/sysctl.conf",
+    "/usr/share/power_manager/board_specific/suspend_to_idle",
+];
+
+const CMD_CAPTURE_BASELINE: &str = r#"p() { printf '%s\t%s\n' "$1" "$(echo "$2" | tr '\n' ' ' | sed 's/ *$//')"; }
+p release "$(grep CHROMEOS_RELEASE_DESCRIPTION /etc/lsb-release | cut -d = -f 2)"
+p kernel "$(uname -r)"
+p kernel_cmdline "$(cat /proc/cmdline)"
+p fwid "$(crossystem fwid)"
+p ro_fwid "$(crossystem ro_fwid)"
+p ec_version "$(ectool version 2>/dev/null | grep 'RW version' | cut -d : -f 2)"
+p gbb_flags "$(/usr/bin/futility gbb --flash --get --flags 2>/dev/null | cut -d : -f 2)"
+p chrome_flags "$(grep -v '^#' /etc/chrome_dev.conf 2>/dev/null)"
+p cpu_governors "$(cat /sys/devices/system/cpu/cpu*/cpufreq/scaling_governor 2>/dev/null | sort | uniq -c)"
+for d in /var/db/pkg/*/*; do [ -d "$d" ] && p pkg "${d#/var/db/pkg/}"; done
+for f in "$@"; do p "file:$f" "$(sha256sum "$f" 2>/dev/null | cut -d ' ' -f 1)"; done
+"#;
+
+/// A snapshot of the configuration of a DUT
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Baseline {
+    /// e.g. "kernel" -> "5.15.0", "package:chromeos-base/shill" -> "0.0.1-r4000",
+    /// "file:/etc/hosts" -> sha256 (empty if missing)
+    pub items: BTreeMap<String, String>,
+    /// Files hashed in the baseline, to hash the same files on check
+    pub files: Vec<String>,
+    /// UNIX time in seconds
+    pub captured_at: i64,
+}
+/// Baselines keyed by DUT ID
+pub static DUT_BASELINES: KvCache<Baseline> = KvCache::new("dut_baselines");
+
+/// Splits "category/name-version" of an installed package into the name and the version
+fn split_package(pkg: &str) -> (&str, &str) {
+    let start = pkg.find('/').map_or(0, |i| i + 1);
+    let is_version_start = |i: usize| {
+        pkg[i..].starts_with('-') && pkg[i + 1..].starts_with(|c: char| c.is_ascii_digit())
+    };
+    // The revision (e.g. -r3) does not start with a digit, so it stays in the version
+    match (start..pkg.len()).rev().find(|i| is_version_start(*i)) {
+        Some(i) => (&pkg[..i], &pkg[i + 1..]),
+        None => (pkg, ""),
+    }
+}
+
+/// Parses "key\tvalue" lines printed by CMD_CAPTURE_BASELINE
+fn parse_items(output: &str) -> BTreeMap<String, String> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.split_once('\t')?;
+            if key == "pkg" {
+                let (name, version) = split_package(value);
+                Some((format!("package:{name}"), version.to_string()))
+            } else {
+                Some((key.to_string(), value.to_string()))
+            }
+        })
+        .collect()
+}
+
+impl Baseline {
+    pub fn capture(ssh: &SshInfo, files: &[String]) -> Result<Self> {
+        let quoted: Vec<String> = files
+            .iter()
+            .map(|f| format!("'{}'", f.replace('\'', r"'\''")))
+            .collect();
+        let output = ssh.run_cmd_stdio(&format!(
+            "set -- {}\n{CMD_CAPTURE_BASELINE}",
+            quoted.join(" ")
+        ))?;
+        Ok(Self {
+            items: parse_items(&output),
+            files: files.to_vec(),
+            captured_at: Local::now().timestamp(),
+        })
+    }
+    /// Returns the items that differ from `other` as (key, value here, value in other)
+    pub fn drift<'a>(
+        &'a self,
+        other: &'a Baseline,
+    ) -> Vec<(&'a str, Option<&'a str>, Option<&'a str>)> {
+        let mut keys: Vec<&String> = self.items.keys().chain(other.items.keys()).collect();
+        keys.sort();
+        keys.dedup();
+        keys.into_iter()
+            .filter_map(|k| {
+                let before = self.items.get(k).map(|s| s.as_str());
+                let after = other.items.get(k).map(|s| s.as_str());
+                (before != after).then_some((k.as_str(), before, after))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn baseline_drift() {
+        assert_eq!(
+            split_package("chromeos-base/shill-0.0.1-r4000"),
+            ("chromeos-base/shill", "0.0.1-r4000")
+        );
+        assert_eq!(
+            split_package("sys-libs/libcap-ng-0.8.3"),
+            ("sys-libs/libcap-ng", "0.8.3")
+        );
+        assert_eq!(split_package("x/noversion"), ("x/noversion", ""));
+
+        let before = Baseline {
+            items: parse_items("kernel\t5.15\npkg\ta/b-1.0\nfile:/etc/hosts\tabc\nbroken line"),
+            files: vec![],
+            captured_at: 0,
+        };
+        assert_eq!(before.items.len(), 3);
+        let after = Baseline {
+            items: parse_items("kernel\t5.15\npkg\ta/b-1.1\npkg\ta/c-2\nfile:/etc/hosts\t"),
+            files: vec![],
+            captured_at: 0,
+        };
+        assert_eq!(
+            before.drift(&after),
+            vec![
+                ("file:/etc/hosts", Some("abc"), Some("")),
+                ("package:a/b", Some("1.0"), Some("1.1")),
+                ("package:a/c", None, Some("2")),
+            ]
+        );
+        assert!(before.drift(&before).is_empty());
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Capability hints of boards and models (`lium boards info`), used by commands to tailor
+//! their behavior (e.g. not running ectool on boards without a CrOS EC). The built-in table
+//! only covers well-known boards. Unknown capabilities are None, and the values can be
+//! overridden per board or model with `lium boards set`.
+
+use crate::cache::KvCache;
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct BoardCaps {
+    /// has a Chrome OS EC (ectool works)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub has_ec: Option<bool>,
+    /// has a debug header for servo (otherwise only CCD is available)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub has_servo_header: Option<bool>,
+    /// container, vm or none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub arc: Option<String>,
+    /// kernel version family the board shipped with (newer releases may use a newer one)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub kernel: Option<String>,
+}
+/// Overrides of the built-in capabilities keyed by board or model
+pub static BOARD_OVERRIDES: KvCache<BoardCaps> = KvCache::new("board_overrides");
+
+pub const CAP_KEYS: [&str; 4] = ["has_ec", "has_servo_header", "arc", "kernel"];
+
+fn caps(has_ec: bool, arc: Option<&str>, kernel: Option<&str>) -> BoardCaps {
+    BoardCaps {
+        has_ec: Some(has_ec),
+        has_servo_header: None,
+        arc: arc.map(str::to_string),
+        kernel: kernel.map(str::to_string),
+    }
+}
+
+lazy_static! {
+    static ref BUILTIN_BOARDS: HashMap<&'static str, BoardCaps> = {
+        let mut m = HashMap::new();
+        // Chromebooks and Chromeboxes with a CrOS EC
+        m.insert("grunt", caps(true, Some("container"), Some("4.14")));
+        m.insert("octopus", caps(true, Some("container"), Some("4.14")));
+        m.insert("hatch", caps(true, Some("container"), Some("4.19")));
+        m.insert("trogdor", caps(true, Some("container"), Some("5.4")));
+        m.insert("volteer", caps(true, Some("container"), Some("5.4")));
+        m.insert("zork", caps(true, Some("container"), Some("5.4")));
+        m.insert("dedede", caps(true, Some("container"), Some("5.4")));
+        m.insert("brya", caps(true, Some("vm"), Some("5.10")));
+        m.insert("cherry", caps(true, Some("vm"), Some("5.10")));
+        m.insert("nissa", caps(true, Some("vm"), Some("5.15")));
+        m.insert("skyrim", caps(true, Some("vm"), Some("5.15")));
+        m.insert("corsola", caps(true, Some("vm"), Some("5.15")));
+        m.insert("rex", caps(true, Some("vm"), Some("6.1")));
+        // Boards without a CrOS EC
+        let no_ec = |arc: &str| BoardCaps {
+            has_ec: Some(false),
+            has_servo_header: Some(false),
+            arc: Some(arc.to_string()),
+            kernel: None,
+        };
+        m.insert("reven", no_ec("none"));
+        m.insert("betty", no_ec("vm"));
+        m.insert("amd64-generic", no_ec("none"));
+        m.insert("arm64-generic", no_ec("none"));
+        m
+    };
+}
+
+impl BoardCaps {
+    /// Overwrites the capabilities known in `other`
+    pub fn merge(&mut self, other: &BoardCaps) {
+        self.has_ec = other.has_ec.or(self.has_ec);
+        self.has_servo_header = other.has_servo_header.or(self.has_servo_header);
+        self.arc = other.arc.clone().or_else(|| self.arc.clone());
+        self.kernel = other.kernel.clone().or_else(|| self.kernel.clone());
+    }
+    /// Sets a capability from `key=value`. An empty value makes it unknown.
+    pub fn set(&mut self, assignment: &str) -> Result<()> {
+        let (key, value) = assignment
+            .split_once('=')
+            .context(anyhow!("{assignment:?} should be key=value"))?;
+        let parse_bool = |v: &str| match v {
+            "" => Ok(None),
+            "true" | "yes" => Ok(Some(true)),
+            "false" | "no" => Ok(Some(false)),
+            _ => Err(anyhow!("{key} should be true or false")),
+        };
+        let string = |v: &str| (!v.is_empty()).then(|| v.to_string());
+        match key {
+            "has_ec" => self.has_ec = parse_bool(value)?,
+            "has_servo_header" => self.has_servo_header = parse_bool(value)?,
+            "arc" => {
+                if !["", "container", "vm", "none"].contains(&value) {
+                    return Err(anyhow!("arc should be container, vm or none"));
+                }
+                self.arc = string(value)
+            }
+            "kernel" => self.kernel = string(value),
+            _ => {
+                return Err(anyhow!(
+                    "Unknown capability {key}. Available: {}",
+                    CAP_KEYS.join(", ")
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the names to look up for a board, from the most generic one
+/// (e.g. "brya-kernelnext" -> ["brya", "brya-kernelnext"])
+fn board_names(board: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    if let Some((base, _)) = board.split_once('-') {
+        names.push(base.trim_end_matches("64").to_string());
+    }
+    names.push(board.to_string());
+    names.dedup();
+    names
+}
+
+/// Returns the capabilities of the board (and the model), applying the overrides
+fn resolve(board: &str, model: Option<&str>, overrides: &HashMap<String, BoardCaps>) -> BoardCaps {
+    let mut result = BoardCaps::default();
+    let names = board_names(board)
+        .into_iter()
+        .chain(model.map(str::to_string));
+    for name in names {
+        if let Some(builtin) = BUILTIN_BOARDS.get(name.as_str()) {
+            result.merge(builtin);
+        }
+        if let Some(overridden) = overrides.get(&name) {
+            result.merge(overridden);
+        }
+    }
+    result
+}
+
+/// Returns the capabilities of the board (and the model)
+pub fn board_caps(board: &str, model: Option<&str>) -> Result<BoardCaps> {
+    Ok(resolve(board, model, &BOARD_OVERRIDES.entries()?))
+}
+
+/// Returns the boards in the built-in table
+pub fn builtin_boards() -> Vec<&'static str> {
+    let mut boards: Vec<&str> = BUILTIN_BOARDS.keys().cloned().collect();
+    boards.sort();
+    boards
+}
+
+/// Fails with an explanation if the board of the DUT is known to lack a CrOS EC, which `what`
+/// requires. The check is skipped if the board can not be read.
+pub fn ensure_ec(ssh: &SshInfo, what: &str) -> Result<()> {
+    let Ok(board) = ssh.get_board() else {
+        return Ok(());
+    };
+    let board = board.trim();
+    if board_caps(board, None)?.has_ec == Some(false) {
+        return Err(anyhow!(
+            "{what} needs a CrOS EC, but {board} does not have one (see `lium boards info {board}`)"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn caps_lookup() {
+        assert_eq!(
+            board_names("brya-kernelnext"),
+            vec!["brya", "brya-kernelnext"]
+        );
+        assert_eq!(board_names("octopus"), vec!["octopus"]);
+
+        let mut overrides = HashMap::new();
+        assert_eq!(resolve("reven", None, &overrides).has_ec, Some(false));
+        assert_eq!(
+            resolve("brya-kernelnext", Some("taniks"), &overrides)
+                .arc
+                .as_deref(),
+            Some("vm")
+        );
+        assert_eq!(resolve("unknown", None, &overrides), BoardCaps::default());
+
+        let mut taniks = BoardCaps::default();
+        taniks.set("has_servo_header=true").unwrap();
+        taniks.set("kernel=6.6").unwrap();
+        assert!(taniks.set("arc=maybe").is_err());
+        assert!(taniks.set("has_ec").is_err());
+        overrides.insert("taniks".to_string(), taniks);
+        let caps = resolve("brya", Some("taniks"), &overrides);
+        assert_eq!(caps.has_ec, Some(true));
+        assert_eq!(caps.has_servo_header, Some(true));
+        assert_eq!(caps.kernel.as_deref(), Some("6.6"));
+    }
+}
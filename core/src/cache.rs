@@ -0,0 +1,372 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::config::Config;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::sha256sum;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::Read;
+use std::io::Seek;
+use std::io::Write;
+use std::marker::PhantomData;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+pub struct KvCache<T: Serialize + DeserializeOwned + Sized + Clone + Debug> {
+    name: &'static str,
+    /// Only the owner can read the file (mode 0600 in a directory with mode 0700)
+    private: bool,
+    map: Mutex<Option<HashMap<String, T>>>,
+    file: Mutex<Option<File>>,
+    //
+    _value_type: PhantomData<T>,
+}
+impl<T: Serialize + DeserializeOwned + Sized + Clone + Debug> KvCache<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            private: false,
+            map: Mutex::new(None),
+            file: Mutex::new(None),
+            _value_type: PhantomData::<T>,
+        }
+    }
+    /// new() for secrets. `name` should be in a directory that holds only private caches.
+    pub const fn new_private(name: &'static str) -> Self {
+        Self {
+            name,
+            private: true,
+            map: Mutex::new(None),
+            file: Mutex::new(None),
+            _value_type: PhantomData::<T>,
+        }
+    }
+    pub fn clear(&self) -> Result<()> {
+        self.load_cache_file()?;
+        {
+            let mut map = self.map.lock().unwrap();
+            let map = map.as_mut().unwrap();
+            map.clear();
+        }
+        self.sync()
+    }
+    fn create_file(&self, remove: bool) -> Result<()> {
+        let path =
+            gen_path_in_lium_dir(self.name).context("Failed to generate a cache file path")?;
+        if remove {
+            std::fs::remove_file(&path).context("Failed to remove the file")?;
+        }
+        if self.private {
+            if let Some(dir) = path.parent() {
+                fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+            }
+        }
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .mode(if self.private { 0o600 } else { 0o666 })
+            .open(path)?;
+        if self.private {
+            // The file may have been created before it became private
+            f.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        if f.metadata()?.len() == 0 {
+            f.write_all(serde_json::to_string(&Map::<String, Value>::new())?.as_bytes())?;
+            f.sync_all()?;
+        }
+        let mut file = self.file.lock().expect("lock failed");
+        *file = Some(f);
+        Ok(())
+    }
+    fn load_cache_file(&self) -> Result<()> {
+        let file = self.file.lock().expect("lock failed");
+        let has_file = file.is_some();
+        drop(file);
+        if !has_file {
+            self.create_file(false)?;
+        }
+        let mut file_lock = self.file.lock().expect("lock failed");
+        let file = file_lock.as_mut().expect("File is not initialized yet");
+        file.rewind()?;
+        let mut json = String::new();
+        file.read_to_string(&mut json)?;
+        drop(file_lock);
+        match serde_json::from_str(&json) {
+            Ok(data) => {
+                *self.map.lock().unwrap() = data;
+                Ok(())
+            }
+            Err(e) => {
+                eprintln!("Failed to parse the cache: {e:?}");
+                eprintln!("Creating a cache file again...");
+                self.create_file(true)?;
+                *self.map.lock().unwrap() = Some(HashMap::new());
+                Ok(())
+            }
+        }
+    }
+    pub fn get(&self, key: &str) -> Result<Option<T>> {
+        self.load_cache_file()?;
+        let mut map = self.map.lock().unwrap();
+        let map = map.as_mut().unwrap();
+        Ok(map.get(key).cloned())
+    }
+    pub fn set(&self, key: &str, value: T) -> Result<()> {
+        self.load_cache_file()?;
+        {
+            let mut map = self.map.lock().unwrap();
+            let map = map.as_mut().unwrap();
+            map.insert(key.to_string(), value);
+        }
+        self.sync()?;
+        Ok(())
+    }
+    pub fn remove(&self, key: &str) -> Result<Option<T>> {
+        self.load_cache_file()?;
+        let old = {
+            let mut map = self.map.lock().unwrap();
+            let map = map.as_mut().unwrap();
+            map.remove(key)
+        };
+        self.sync()?;
+        Ok(old)
+    }
+    pub fn sync(&self) -> Result<()> {
+        let mut map = self.map.lock().unwrap();
+        let map = map.as_mut().unwrap();
+        let mut file = self.file.lock().expect("lock failed");
+        let file = file.as_mut().expect("File is not initialized yet");
+        file.set_len(0)?;
+        file.rewind()?;
+        file.write_all(serde_json::to_string(map)?.as_bytes())?;
+        file.sync_all().context("failed to sync backed file")
+    }
+    pub fn entries(&self) -> Result<HashMap<String, T>> {
+        self.load_cache_file()?;
+        let map = self.map.lock().unwrap();
+        Ok((*map).clone().unwrap())
+    }
+    pub fn get_or_else(&self, key: &str, f: &dyn Fn(&str) -> Result<T>) -> Result<T> {
+        if let Some(s) = self.get(key)? {
+            Ok(s)
+        } else {
+            let value = f(key);
+            if let Ok(value) = &value {
+                self.set(key, value.clone())?;
+            }
+            value
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ArtifactEntry {
+    /// sha256 of the content
+    pub hash: String,
+    pub size: u64,
+    /// UNIX time (in seconds) of the last access
+    pub last_used: u64,
+}
+
+/// Content-addressed store for downloaded artifacts (images, symbols, test bundles...).
+///
+/// Payloads are stored once under `<dir>/objects/<sha256>` and exposed as hard links at
+/// `<dir>/files/<name>`, so identical payloads are not duplicated across versions.
+pub struct ArtifactStore {
+    dir: &'static str,
+    index: KvCache<ArtifactEntry>,
+}
+pub static ARTIFACT_STORE: ArtifactStore = ArtifactStore::new("artifacts", "artifacts/index");
+impl ArtifactStore {
+    pub const fn new(dir: &'static str, index: &'static str) -> Self {
+        Self {
+            dir,
+            index: KvCache::new(index),
+        }
+    }
+    fn object_path(&self, hash: &str) -> Result<PathBuf> {
+        gen_path_in_lium_dir(&format!("{}/objects/{hash}", self.dir))
+    }
+    fn file_path(&self, name: &str) -> Result<PathBuf> {
+        if name
+            .split('/')
+            .any(|c| c.is_empty() || c == "." || c == "..")
+        {
+            return Err(anyhow!("Invalid artifact name: {name}"));
+        }
+        gen_path_in_lium_dir(&format!("{}/files/{name}", self.dir))
+    }
+    /// Adds a copy of `src` as `name` and returns the path of the stored artifact
+    pub fn add(&self, name: &str, src: &Path) -> Result<PathBuf> {
+        let hash = sha256sum(src)?;
+        let object = self.object_path(&hash)?;
+        // Fall back to copying if src is on another filesystem
+        if !object.exists() && fs::hard_link(src, &object).is_err() {
+            fs::copy(src, &object).context("Failed to copy an artifact")?;
+        }
+        let file = self.file_path(name)?;
+        if file.exists() {
+            fs::remove_file(&file)?;
+        }
+        fs::hard_link(&object, &file).context("Failed to create a hard link")?;
+        self.index.set(
+            name,
+            ArtifactEntry {
+                hash,
+                size: fs::metadata(&object)?.len(),
+                last_used: now_secs()?,
+            },
+        )?;
+        self.gc_inner(Config::read()?.artifact_cache_max_bytes(), Some(name))?;
+        Ok(file)
+    }
+    /// Returns the path of the artifact, updating its last access time
+    pub fn get(&self, name: &str) -> Result<Option<PathBuf>> {
+        let mut entry = match self.index.get(name)? {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        let file = self.file_path(name)?;
+        if !file.exists() {
+            self.index.remove(name)?;
+            return Ok(None);
+        }
+        entry.last_used = now_secs()?;
+        self.index.set(name, entry)?;
+        Ok(Some(file))
+    }
+    /// Returns the path of the payload with the hash if it is referenced by the store
+    pub fn object(&self, hash: &str) -> Result<Option<PathBuf>> {
+        if !self.entries()?.values().any(|e| e.hash == hash) {
+            return Ok(None);
+        }
+        let object = self.object_path(hash)?;
+        Ok(object.exists().then_some(object))
+    }
+    /// Returns the path of the artifact, calling `fetch` with a temporary path to download it
+    /// if it is not in the store yet.
+    pub fn get_or_fetch(&self, name: &str, fetch: &dyn Fn(&Path) -> Result<()>) -> Result<PathBuf> {
+        if let Some(path) = self.get(name)? {
+            return Ok(path);
+        }
+        let tmp = gen_path_in_lium_dir(&format!("{}/tmp/{}", self.dir, name.replace('/', "_")))?;
+        fetch(&tmp)?;
+        let path = self.add(name, &tmp);
+        fs::remove_file(&tmp)?;
+        path
+    }
+    pub fn entries(&self) -> Result<HashMap<String, ArtifactEntry>> {
+        self.index.entries()
+    }
+    pub fn remove(&self, name: &str) -> Result<()> {
+        let entry = self
+            .index
+            .remove(name)?
+            .context(anyhow!("{name} is not in the artifact store"))?;
+        drop(fs::remove_file(self.file_path(name)?));
+        if !self.entries()?.values().any(|e| e.hash == entry.hash) {
+            drop(fs::remove_file(self.object_path(&entry.hash)?));
+        }
+        Ok(())
+    }
+    /// Evicts least recently used artifacts until the total size of the payloads is under
+    /// `max_bytes`, and removes objects not referenced anymore. Returns the evicted names.
+    pub fn gc(&self, max_bytes: u64) -> Result<Vec<String>> {
+        self.gc_inner(max_bytes, None)
+    }
+    fn gc_inner(&self, max_bytes: u64, keep: Option<&str>) -> Result<Vec<String>> {
+        let mut entries = self.entries()?;
+        let kept = keep.and_then(|name| entries.remove_entry(name));
+        let max_bytes = max_bytes.saturating_sub(kept.map(|(_, e)| e.size).unwrap_or(0));
+        let evicted = select_evictions(&entries, max_bytes);
+        for name in &evicted {
+            self.remove(name)?;
+        }
+        let entries = self.entries()?;
+        let objects_dir = gen_path_in_lium_dir(&format!("{}/objects", self.dir))?;
+        fs::create_dir_all(&objects_dir)?;
+        for object in fs::read_dir(objects_dir)? {
+            let object = object?;
+            let hash = object.file_name().to_string_lossy().to_string();
+            if !entries.values().any(|e| e.hash == hash) {
+                fs::remove_file(object.path())?;
+            }
+        }
+        Ok(evicted)
+    }
+}
+
+/// Returns the total size of unique payloads
+pub fn total_artifact_size(entries: &HashMap<String, ArtifactEntry>) -> u64 {
+    let mut seen = HashMap::new();
+    for e in entries.values() {
+        seen.insert(&e.hash, e.size);
+    }
+    seen.values().sum()
+}
+
+fn select_evictions(entries: &HashMap<String, ArtifactEntry>, max_bytes: u64) -> Vec<String> {
+    let mut entries = entries.clone();
+    let mut lru: Vec<(String, u64)> = entries
+        .iter()
+        .map(|(name, e)| (name.clone(), e.last_used))
+        .collect();
+    lru.sort_by_key(|(name, last_used)| (*last_used, name.clone()));
+    let mut evicted = Vec::new();
+    for (name, _) in lru {
+        if total_artifact_size(&entries) <= max_bytes {
+            break;
+        }
+        entries.remove(&name);
+        evicted.push(name);
+    }
+    evicted
+}
+
+fn now_secs() -> Result<u64> {
+    Ok(SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lru_evictions() {
+        let entry = |hash: &str, size, last_used| ArtifactEntry {
+            hash: hash.to_string(),
+            size,
+            last_used,
+        };
+        let entries = HashMap::from([
+            ("old".to_string(), entry("a", 100, 1)),
+            ("dup".to_string(), entry("b", 50, 2)),
+            ("new".to_string(), entry("b", 50, 3)),
+        ]);
+        assert_eq!(total_artifact_size(&entries), 150);
+        assert!(select_evictions(&entries, 150).is_empty());
+        assert_eq!(select_evictions(&entries, 100), vec!["old"]);
+        // Evicting "dup" alone does not free anything since "new" shares the payload
+        assert_eq!(select_evictions(&entries, 10), vec!["old", "dup", "new"]);
+    }
+}
@@ -0,0 +1,87 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Cooperative cancellation on Ctrl-C. Long-running commands call `install_handler()` and then
+//! poll `check()` (or use `sleep()`) in their loops, so that they can stop child processes,
+//! remove temp files via the usual drops and print partial results before exiting.
+//! A second Ctrl-C exits immediately.
+
+use anyhow::anyhow;
+use anyhow::Result;
+use nix::sys::signal::sigaction;
+use nix::sys::signal::SaFlags;
+use nix::sys::signal::SigAction;
+use nix::sys::signal::SigHandler;
+use nix::sys::signal::SigSet;
+use nix::sys::signal::Signal;
+use std::fmt;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+/// The exit code of a process terminated by SIGINT
+pub const EXIT_CODE: i32 = 130;
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// The error returned when the command is cancelled
+#[derive(Debug)]
+pub struct Cancelled;
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Cancelled")
+    }
+}
+impl std::error::Error for Cancelled {}
+
+extern "C" fn on_signal(_: nix::libc::c_int) {
+    if CANCELLED.swap(true, Ordering::SeqCst) {
+        // Only async-signal-safe calls are allowed here
+        unsafe { nix::libc::_exit(EXIT_CODE) };
+    }
+}
+
+/// Makes SIGINT and SIGTERM request the cancellation instead of killing the process
+pub fn install_handler() -> Result<()> {
+    let action = SigAction::new(
+        SigHandler::Handler(on_signal),
+        SaFlags::SA_RESTART,
+        SigSet::empty(),
+    );
+    for signal in [Signal::SIGINT, Signal::SIGTERM] {
+        unsafe { sigaction(signal, &action) }
+            .map_err(|e| anyhow!("Failed to install the handler of {signal}: {e}"))?;
+    }
+    Ok(())
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Returns `Err(Cancelled)` if the cancellation is requested
+pub fn check() -> Result<()> {
+    if is_cancelled() {
+        Err(Cancelled.into())
+    } else {
+        Ok(())
+    }
+}
+
+/// Sleeps for the duration, returning early with `Err(Cancelled)` if cancelled
+pub fn sleep(duration: Duration) -> Result<()> {
+    let until = Instant::now() + duration;
+    loop {
+        check()?;
+        let now = Instant::now();
+        if now >= until {
+            return Ok(());
+        }
+        thread::sleep((until - now).min(Duration::from_millis(100)));
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Test CA certificates on DUTs (e.g. for staging servers). Certificates are installed to the
+//! system store used by the system services (/etc/ssl/certs) and/or the NSS DB of the logged-in
+//! user used by Chrome, and can be removed later.
+
+use crate::cache::KvCache;
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+
+const CERTS_DIR: &str = "/usr/local/lium_certs";
+const SYSTEM_BUNDLE: &str = "/etc/ssl/certs/ca-certificates.crt";
+const USER_NSSDB: &str = "/home/chronos/user/.pki/nssdb";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CertStore {
+    /// /etc/ssl/certs, used by system services. Needs the rootfs verification removed.
+    System,
+    /// NSS DB of the logged-in user, used by Chrome
+    NssDb,
+}
+
+/// A certificate installed on a DUT
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct InstalledCert {
+    pub name: String,
+    pub stores: Vec<CertStore>,
+    /// UNIX time in seconds
+    pub installed_at: i64,
+}
+/// Installed certificates keyed by DUT ID
+pub static DUT_CERTS: KvCache<Vec<InstalledCert>> = KvCache::new("dut_certs");
+
+/// Returns the name of the certificate from the file name (e.g. "lium-staging-ca" for staging-ca.pem)
+pub fn cert_name(path: &str) -> Result<String> {
+    let stem = Path::new(path)
+        .file_stem()
+        .context(anyhow!("Invalid certificate path {path:?}"))?
+        .to_string_lossy();
+    let name: String = stem
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    Ok(format!("lium-{name}"))
+}
+
+fn install_script(name: &str, store: CertStore) -> String {
+    let pem = format!("{CERTS_DIR}/{name}.pem");
+    match store {
+        CertStore::System => format!(
+            r#"set -e
+mount -o remount,rw / 2>/dev/null
+sed -i '/^# {name} begin$/,/^# {name} end$/d' {SYSTEM_BUNDLE}
+{{ echo '# {name} begin'; cat {pem}; echo '# {name} end'; }} >> {SYSTEM_BUNDLE}
+cp {pem} /etc/ssl/certs/{name}.pem
+if command -v openssl >/dev/null; then
+  ln -sf {name}.pem /etc/ssl/certs/$(openssl x509 -hash -noout -in {pem}).0
+fi
+"#
+        ),
+        CertStore::NssDb => format!(
+            r#"set -e
+test -d {USER_NSSDB} || {{ echo 'No user is logged in' >&2; exit 1; }}
+sudo -u chronos certutil -d sql:{USER_NSSDB} -D -n {name} 2>/dev/null || true
+sudo -u chronos certutil -d sql:{USER_NSSDB} -A -t 'CT,C,C' -n {name} -i {pem}
+"#
+        ),
+    }
+}
+
+fn remove_script(name: &str, store: CertStore) -> String {
+    match store {
+        CertStore::System => format!(
+            r#"mount -o remount,rw / 2>/dev/null
+sed -i '/^# {name} begin$/,/^# {name} end$/d' {SYSTEM_BUNDLE}
+find /etc/ssl/certs -lname {name}.pem -delete
+rm -f /etc/ssl/certs/{name}.pem
+true
+"#
+        ),
+        CertStore::NssDb => format!(
+            r#"test -d {USER_NSSDB} && sudo -u chronos certutil -d sql:{USER_NSSDB} -D -n {name}
+true
+"#
+        ),
+    }
+}
+
+/// Installs the certificate file to the stores of the DUT and returns its name
+pub fn install_cert(ssh: &SshInfo, id: &str, path: &str, stores: &[CertStore]) -> Result<String> {
+    let name = cert_name(path)?;
+    ssh.run_cmd_stdio(&format!("mkdir -p {CERTS_DIR}"))?;
+    ssh.send_files(
+        &[path.to_string()],
+        Some(&format!("{CERTS_DIR}/{name}.pem")),
+    )?;
+    for store in stores {
+        ssh.run_cmd_stdio(&install_script(&name, *store))
+            .context(match store {
+                CertStore::System => {
+                    "Failed to install to the system store (is the rootfs verification removed?)"
+                }
+                CertStore::NssDb => {
+                    "Failed to install to the NSS DB of the user (is a user logged in?)"
+                }
+            })?;
+    }
+    let mut certs = DUT_CERTS.get(id)?.unwrap_or_default();
+    certs.retain(|c| c.name != name);
+    certs.push(InstalledCert {
+        name: name.clone(),
+        stores: stores.to_vec(),
+        installed_at: Local::now().timestamp(),
+    });
+    DUT_CERTS.set(id, certs)?;
+    Ok(name)
+}
+
+/// Removes the certificate from all the stores it was installed to
+pub fn remove_cert(ssh: &SshInfo, id: &str, name: &str) -> Result<InstalledCert> {
+    let mut certs = DUT_CERTS.get(id)?.unwrap_or_default();
+    let i = certs
+        .iter()
+        .position(|c| c.name == name)
+        .context(anyhow!("{name} is not installed on {id} by lium"))?;
+    for store in &certs[i].stores {
+        ssh.run_cmd_stdio(&remove_script(name, *store))?;
+    }
+    ssh.run_cmd_stdio(&format!("rm -f {CERTS_DIR}/{name}.pem"))?;
+    let cert = certs.remove(i);
+    DUT_CERTS.set(id, certs)?;
+    Ok(cert)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cert_names() {
+        assert_eq!(cert_name("/tmp/staging-ca.pem").unwrap(), "lium-staging-ca");
+        assert_eq!(cert_name("my ca.v2.crt").unwrap(), "lium-my_ca_v2");
+        assert!(cert_name("").is_err());
+    }
+}
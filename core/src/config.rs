@@ -0,0 +1,522 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::connection::PreConnectStep;
+use crate::escalation::EscalationPolicy;
+use crate::notify::parse_notify_methods;
+use crate::notify::NotifyMethod;
+use crate::ssh_auth::SshAuth;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::run_bash_command;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::fs::write;
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct SshOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    shell_condition: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    ssh_options: Vec<String>,
+}
+impl SshOverride {
+    pub fn is_match_condition(&self) -> Result<bool> {
+        if let Some(cmd) = &self.shell_condition {
+            Ok(run_bash_command(cmd, None)?.status.success())
+        } else {
+            // Return true always if shell_condition is not specified
+            Ok(true)
+        }
+    }
+    pub fn ssh_options(&self) -> &Vec<String> {
+        &self.ssh_options
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    android_manifest_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    default_cros_checkout: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    default_cros_mirror: Option<String>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    ssh_overrides: HashMap<String, SshOverride>,
+    /// host regex -> authentication other than testing_rsa
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    ssh_auth: HashMap<String, SshAuth>,
+    /// host regex -> how to get elevated credentials for privileged actions
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    dut_escalation: HashMap<String, EscalationPolicy>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    tast_bundles: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    ssh_port_search_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    default_ipv6_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    artifact_cache_size_gb: Option<u64>,
+    /// URL prefix -> mirrors tried before it when downloading artifacts
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    artifact_mirrors: HashMap<String, Vec<String>>,
+    /// Maximum bytes downloaded in a download session, in MB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    download_quota_mb: Option<u64>,
+    /// host:port of workstations running `lium share serve`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    share_peers: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    notify: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    dut_groups: HashMap<String, Vec<String>>,
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    connection_profiles: HashMap<String, Vec<String>>,
+    /// DUT (ID or address) or DUT group -> connection profile name
+    #[serde(skip_serializing_if = "HashMap::is_empty")]
+    #[serde(default)]
+    connection_profile_targets: HashMap<String, String>,
+    /// gs:// or https:// URL of the releases for `lium self-update`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    release_url: Option<String>,
+    /// ssh allowed_signers file to verify the releases
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    release_signers: Option<String>,
+    /// URL to POST bug reports to with `lium report bug --file`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    issue_tracker_url: Option<String>,
+    /// Host names under these domains are masked by `--redact`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    redact_domains: Vec<String>,
+    /// Regexes of additional things masked by `--redact`
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    #[serde(default)]
+    redact_patterns: Vec<String>,
+}
+static CONFIG_FILE_NAME: &str = "config.json";
+impl Config {
+    pub fn read() -> Result<Self> {
+        let path = gen_path_in_lium_dir(CONFIG_FILE_NAME)?;
+        let config = read_to_string(&path);
+        match config {
+            Ok(config) => Ok(serde_json::from_str(&config)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                // Just create a default config
+                let config = Self::default();
+                config.write()?;
+                eprintln!("INFO: config file created at {:?}", path);
+                Ok(config)
+            }
+            e => Err(anyhow!("Failed to create a new config: {:?}", e)),
+        }
+    }
+    // This is private since write should happen on every updates transparently
+    fn write(&self) -> Result<()> {
+        let s = serde_json::to_string_pretty(&self)?;
+        write(gen_path_in_lium_dir(CONFIG_FILE_NAME)?, s.into_bytes())
+            .context("failed to write config")
+    }
+    pub fn set<K: AsRef<str>>(&mut self, key: &str, values: &[K]) -> Result<()> {
+        match key {
+            "android_manifest_url" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.android_manifest_url = Some(values[0].as_ref().to_string());
+            }
+            "default_cros_checkout" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.default_cros_checkout = Some(values[0].as_ref().to_string());
+            }
+            "default_cros_mirror" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.default_cros_mirror = Some(values[0].as_ref().to_string());
+            }
+            "ssh_override" => {
+                if values.len() < 3 {
+                    return Err(anyhow!("{key} takes 3+ parameters"));
+                }
+                let host_regex = values[0].as_ref().to_string();
+                Regex::new(&host_regex).context("Invalid regex is provided as a host_pattern")?;
+                let shell_condition = Some(values[1].as_ref().to_string());
+                let ssh_options: Vec<String> =
+                    values[2..].iter().map(|s| s.as_ref().to_string()).collect();
+                self.ssh_overrides.insert(
+                    host_regex,
+                    SshOverride {
+                        shell_condition,
+                        ssh_options,
+                    },
+                );
+            }
+            "ssh_auth" => {
+                if values.len() < 2 {
+                    return Err(anyhow!(
+                        "{key} takes 2+ parameters (host_regex and agent, user=<name>, cert=<path>, key=<path> or refresh=<command>)"
+                    ));
+                }
+                let host_regex = values[0].as_ref().to_string();
+                Regex::new(&host_regex).context("Invalid regex is provided as a host_pattern")?;
+                self.ssh_auth
+                    .insert(host_regex, SshAuth::parse(&values[1..])?);
+            }
+            "dut_escalation" => {
+                if values.len() != 2 && values.len() != 3 {
+                    return Err(anyhow!(
+                        "{key} takes 2 or 3 parameters (host_regex, broker_command and optionally a prefix command on the DUT)"
+                    ));
+                }
+                let host_regex = values[0].as_ref().to_string();
+                Regex::new(&host_regex).context("Invalid regex is provided as a host_pattern")?;
+                self.dut_escalation.insert(
+                    host_regex,
+                    EscalationPolicy::new(values[1].as_ref(), values.get(2).map(|s| s.as_ref())),
+                );
+            }
+            "tast_bundles" => {
+                let bundles: Vec<String> =
+                    values[0..].iter().map(|s| s.as_ref().to_string()).collect();
+                self.tast_bundles = Some(bundles);
+            }
+            "ssh_port_search_timeout" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.ssh_port_search_timeout = Some(values[0].as_ref().parse().unwrap());
+            }
+            "default_ipv6_prefix" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.default_ipv6_prefix = Some(values[0].as_ref().parse().unwrap());
+            }
+            "artifact_cache_size_gb" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.artifact_cache_size_gb = Some(
+                    values[0]
+                        .as_ref()
+                        .parse()
+                        .context("Please specify the size in GB")?,
+                );
+            }
+            "artifact_mirror" => {
+                if values.len() < 2 {
+                    return Err(anyhow!(
+                        "{key} takes 2+ parameters (a URL prefix and mirrors of it)"
+                    ));
+                }
+                for url in values {
+                    let url = url.as_ref();
+                    if !["gs://", "https://", "http://"]
+                        .iter()
+                        .any(|s| url.starts_with(s))
+                    {
+                        return Err(anyhow!("{url} should start with gs:// or http(s)://"));
+                    }
+                }
+                let mirrors: Vec<String> =
+                    values[1..].iter().map(|s| s.as_ref().to_string()).collect();
+                self.artifact_mirrors
+                    .insert(values[0].as_ref().to_string(), mirrors);
+            }
+            "download_quota_mb" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.download_quota_mb = Some(
+                    values[0]
+                        .as_ref()
+                        .parse()
+                        .context("Please specify the size in MB")?,
+                );
+            }
+            "share_peers" => {
+                self.share_peers = values.iter().map(|s| s.as_ref().to_string()).collect();
+            }
+            "dut_group" => {
+                if values.len() < 2 {
+                    return Err(anyhow!("{key} takes 2+ parameters (name and DUTs)"));
+                }
+                let duts: Vec<String> =
+                    values[1..].iter().map(|s| s.as_ref().to_string()).collect();
+                self.dut_groups.insert(values[0].as_ref().to_string(), duts);
+            }
+            "connection_profile" => {
+                if values.len() < 2 {
+                    return Err(anyhow!("{key} takes 2+ parameters (name and steps)"));
+                }
+                let steps: Vec<String> =
+                    values[1..].iter().map(|s| s.as_ref().to_string()).collect();
+                for step in &steps {
+                    PreConnectStep::parse(step)?;
+                }
+                self.connection_profiles
+                    .insert(values[0].as_ref().to_string(), steps);
+            }
+            "connection_profile_for" => {
+                if values.len() != 2 {
+                    return Err(anyhow!(
+                        "{key} takes 2 parameters (a DUT or a DUT group, and a profile name)"
+                    ));
+                }
+                let profile = values[1].as_ref();
+                if !self.connection_profiles.contains_key(profile) {
+                    return Err(anyhow!("Connection profile {profile} is not defined"));
+                }
+                self.connection_profile_targets
+                    .insert(values[0].as_ref().to_string(), profile.to_string());
+            }
+            "notify" => {
+                let methods: Vec<String> =
+                    values[0..].iter().map(|s| s.as_ref().to_string()).collect();
+                parse_notify_methods(&methods.join(","))?;
+                self.notify = Some(methods);
+            }
+            "release_url" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                let url = values[0].as_ref().trim_end_matches('/');
+                if !url.starts_with("gs://") && !url.starts_with("https://") {
+                    return Err(anyhow!("{key} should start with gs:// or https://"));
+                }
+                self.release_url = Some(url.to_string());
+            }
+            "release_signers" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                self.release_signers = Some(values[0].as_ref().to_string());
+            }
+            "issue_tracker_url" => {
+                if values.len() != 1 {
+                    return Err(anyhow!("{key} only takes 1 params"));
+                }
+                let url = values[0].as_ref();
+                if !url.starts_with("https://") && !url.starts_with("http://") {
+                    return Err(anyhow!("{key} should start with https:// or http://"));
+                }
+                self.issue_tracker_url = Some(url.to_string());
+            }
+            "redact_domains" => {
+                self.redact_domains = values.iter().map(|s| s.as_ref().to_string()).collect();
+            }
+            "redact_patterns" => {
+                for p in values {
+                    Regex::new(p.as_ref()).context(anyhow!("Invalid regex {:?}", p.as_ref()))?;
+                }
+                self.redact_patterns = values.iter().map(|s| s.as_ref().to_string()).collect();
+            }
+            _ => return Err(anyhow!("config key {key} is not valid")),
+        }
+        self.write()
+    }
+    pub fn clear(&mut self, key: &str) -> Result<()> {
+        match key {
+            "android_manifest_url" => {
+                self.android_manifest_url = None;
+            }
+            "default_cros_checkout" => {
+                self.default_cros_checkout = None;
+            }
+            "default_cros_mirror" => {
+                self.default_cros_mirror = None;
+            }
+            "ssh_overrides" => self.ssh_overrides.clear(),
+            "ssh_auth" => self.ssh_auth.clear(),
+            "dut_escalation" => self.dut_escalation.clear(),
+            "ssh_override" => {
+                return Err(anyhow!(
+                    "please use `lium config clear ssh_overrides` instead ;)"
+                ))
+            }
+            "tast_bundles" => {
+                self.tast_bundles = None;
+            }
+            "ssh_port_search_timeout" => {
+                self.ssh_port_search_timeout = None;
+            }
+            "default_ipv6_prefix" => {
+                self.default_ipv6_prefix = None;
+            }
+            "artifact_cache_size_gb" => {
+                self.artifact_cache_size_gb = None;
+            }
+            "artifact_mirrors" => self.artifact_mirrors.clear(),
+            "download_quota_mb" => {
+                self.download_quota_mb = None;
+            }
+            "share_peers" => self.share_peers.clear(),
+            "notify" => {
+                self.notify = None;
+            }
+            "release_url" => {
+                self.release_url = None;
+            }
+            "release_signers" => {
+                self.release_signers = None;
+            }
+            "issue_tracker_url" => {
+                self.issue_tracker_url = None;
+            }
+            "redact_domains" => self.redact_domains.clear(),
+            "redact_patterns" => self.redact_patterns.clear(),
+            "dut_groups" => self.dut_groups.clear(),
+            "connection_profiles" => {
+                self.connection_profiles.clear();
+                self.connection_profile_targets.clear();
+            }
+            _ => return Err(anyhow!("lium config clear for '{key}' is not implemented")),
+        }
+        self.write()?;
+        Ok(())
+    }
+    pub fn tast_bundles(&self) -> Vec<&str> {
+        if let Some(bundles) = &self.tast_bundles {
+            bundles.iter().map(|s| s as &str).collect()
+        } else {
+            Vec::new()
+        }
+    }
+    pub fn ssh_overrides(&self) -> &HashMap<String, SshOverride> {
+        &self.ssh_overrides
+    }
+    pub fn ssh_auth(&self) -> &HashMap<String, SshAuth> {
+        &self.ssh_auth
+    }
+    /// Returns the escalation policy for privileged actions on the host, if any
+    pub fn dut_escalation(&self, host: &str) -> Result<Option<EscalationPolicy>> {
+        let mut policies: Vec<_> = self.dut_escalation.iter().collect();
+        policies.sort_by(|a, b| a.0.cmp(b.0));
+        for (pattern, policy) in policies {
+            if Regex::new(pattern)
+                .context("Failed to compile regex for dut_escalation")?
+                .is_match(host)
+            {
+                return Ok(Some(policy.clone()));
+            }
+        }
+        Ok(None)
+    }
+    pub fn android_manifest_url(&self) -> Option<String> {
+        self.android_manifest_url.clone()
+    }
+    pub fn default_cros_checkout(&self) -> Option<String> {
+        self.default_cros_checkout.clone()
+    }
+    pub fn ssh_port_search_timeout(&self) -> Option<u64> {
+        self.ssh_port_search_timeout
+    }
+    pub fn default_ipv6_prefix(&self) -> Option<String> {
+        self.default_ipv6_prefix.clone()
+    }
+    pub fn artifact_mirrors(&self) -> &HashMap<String, Vec<String>> {
+        &self.artifact_mirrors
+    }
+    pub fn download_quota_bytes(&self) -> Option<u64> {
+        self.download_quota_mb.map(|mb| mb * 1024 * 1024)
+    }
+    pub fn share_peers(&self) -> &[String] {
+        &self.share_peers
+    }
+    pub fn release_url(&self) -> Option<String> {
+        self.release_url.clone()
+    }
+    pub fn release_signers(&self) -> Option<String> {
+        self.release_signers.clone()
+    }
+    pub fn issue_tracker_url(&self) -> Option<String> {
+        self.issue_tracker_url.clone()
+    }
+    pub fn redact_domains(&self) -> &[String] {
+        &self.redact_domains
+    }
+    pub fn redact_patterns(&self) -> &[String] {
+        &self.redact_patterns
+    }
+    /// Returns DUTs in a group defined with `lium config set dut_group <name> <DUT>...`
+    pub fn dut_group(&self, name: &str) -> Result<&Vec<String>> {
+        self.dut_groups.get(name).context(anyhow!(
+            "DUT group {name} is not defined. Use `lium config set dut_group {name} <DUT>...`"
+        ))
+    }
+    pub fn dut_groups(&self) -> &HashMap<String, Vec<String>> {
+        &self.dut_groups
+    }
+    /// Returns the pre-connect steps for a DUT known by any of `names` (e.g. the DUT ID and the
+    /// address). A profile attached to the DUT itself wins over one attached to its group.
+    pub fn pre_connect_steps(&self, names: &[String]) -> Result<Vec<PreConnectStep>> {
+        let direct = names
+            .iter()
+            .find_map(|n| self.connection_profile_targets.get(n));
+        let profile = direct.or_else(|| {
+            self.connection_profile_targets
+                .iter()
+                .filter(|(target, _)| {
+                    self.dut_groups
+                        .get(*target)
+                        .map(|duts| duts.iter().any(|d| names.contains(d)))
+                        .unwrap_or(false)
+                })
+                .map(|(_, profile)| profile)
+                .min()
+        });
+        let Some(profile) = profile else {
+            return Ok(Vec::new());
+        };
+        self.connection_profiles
+            .get(profile)
+            .context(anyhow!("Connection profile {profile} is not defined"))?
+            .iter()
+            .map(|s| PreConnectStep::parse(s))
+            .collect()
+    }
+    /// Methods to notify the end of long-running operations (default: none)
+    pub fn notify_methods(&self) -> Vec<NotifyMethod> {
+        self.notify
+            .as_ref()
+            .and_then(|m| parse_notify_methods(&m.join(",")).ok())
+            .unwrap_or_default()
+    }
+    /// Size limit of the artifact store in bytes (default: 50GB)
+    pub fn artifact_cache_max_bytes(&self) -> u64 {
+        self.artifact_cache_size_gb.unwrap_or(50) * 1024 * 1024 * 1024
+    }
+}
@@ -0,0 +1,142 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::util::run_bash_command;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use macaddr::MacAddr6;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::net::UdpSocket;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+/// A step to run before connecting to a DUT (e.g. behind a NAT or a firewall)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PreConnectStep {
+    /// send a Wake-on-LAN magic packet to the MAC address via the broadcast address
+    Wol { mac: MacAddr6, broadcast: String },
+    /// connect to the ports in order to open the firewall
+    Knock { host: String, ports: Vec<u16> },
+    /// run a shell command and fail if it fails (e.g. to check if the VPN is up)
+    Check(String),
+}
+impl PreConnectStep {
+    /// Parses "wol=<mac>[@<broadcast>]", "knock=<host>:<port>[,<port>...]" or "check=<cmd>"
+    pub fn parse(s: &str) -> Result<Self> {
+        let (kind, value) = s
+            .split_once('=')
+            .context(anyhow!("Invalid pre-connect step {s:?}"))?;
+        match kind {
+            "wol" => {
+                let (mac, broadcast) = value
+                    .split_once('@')
+                    .unwrap_or((value, "255.255.255.255"));
+                let mac = MacAddr6::from_str(mac).context(anyhow!("Invalid MAC address {mac:?}"))?;
+                Ok(Self::Wol {
+                    mac,
+                    broadcast: broadcast.to_string(),
+                })
+            }
+            "knock" => {
+                let (host, ports) = value
+                    .rsplit_once(':')
+                    .context(anyhow!("Use knock=<host>:<port>[,<port>...]"))?;
+                let ports = ports
+                    .split(',')
+                    .map(|p| p.parse().context(anyhow!("Invalid port {p:?}")))
+                    .collect::<Result<Vec<u16>>>()?;
+                Ok(Self::Knock {
+                    host: host
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .to_string(),
+                    ports,
+                })
+            }
+            "check" if !value.is_empty() => Ok(Self::Check(value.to_string())),
+            _ => Err(anyhow!(
+                "Unknown pre-connect step {s:?}. Use wol=<mac>[@<broadcast>], knock=<host>:<ports> or check=<cmd>"
+            )),
+        }
+    }
+    pub fn run(&self) -> Result<()> {
+        match self {
+            Self::Wol { mac, broadcast } => send_wol(mac, broadcast),
+            Self::Knock { host, ports } => {
+                for port in ports {
+                    if let Some(addr) = (host.as_str(), *port).to_socket_addrs()?.next() {
+                        // The knock is the SYN itself, so the result does not matter
+                        let _ = TcpStream::connect_timeout(&addr, Duration::from_millis(300));
+                    }
+                }
+                Ok(())
+            }
+            Self::Check(cmd) => run_bash_command(cmd, None)?
+                .status
+                .exit_ok()
+                .context(anyhow!("Pre-connect check {cmd:?} failed (is the VPN up?)")),
+        }
+    }
+}
+
+/// Sends a Wake-on-LAN magic packet (6 x 0xFF followed by the MAC address 16 times)
+pub fn send_wol(mac: &MacAddr6, broadcast: &str) -> Result<()> {
+    let mut packet = vec![0xFFu8; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac.as_bytes());
+    }
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    socket
+        .send_to(&packet, (broadcast, 9))
+        .context(anyhow!("Failed to send a magic packet to {broadcast}"))?;
+    Ok(())
+}
+
+/// Runs the steps of a connection profile. Waits for `settle` after the steps so that the
+/// DUT or the firewall can react.
+pub fn run_pre_connect_steps(steps: &[PreConnectStep], settle: Duration) -> Result<()> {
+    for step in steps {
+        step.run()
+            .context(anyhow!("Pre-connect step {step:?} failed"))?;
+    }
+    if steps.iter().any(|s| !matches!(s, PreConnectStep::Check(_))) {
+        thread::sleep(settle);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_steps() {
+        assert_eq!(
+            PreConnectStep::parse("wol=00:11:22:33:44:55").unwrap(),
+            PreConnectStep::Wol {
+                mac: MacAddr6::new(0, 0x11, 0x22, 0x33, 0x44, 0x55),
+                broadcast: "255.255.255.255".to_string()
+            }
+        );
+        assert_eq!(
+            PreConnectStep::parse("knock=[fe80::1]:1000,2000").unwrap(),
+            PreConnectStep::Knock {
+                host: "fe80::1".to_string(),
+                ports: vec![1000, 2000]
+            }
+        );
+        assert_eq!(
+            PreConnectStep::parse("check=ip link show tun0").unwrap(),
+            PreConnectStep::Check("ip link show tun0".to_string())
+        );
+        assert!(PreConnectStep::parse("wol=zz").is_err());
+        assert!(PreConnectStep::parse("vpn").is_err());
+    }
+}
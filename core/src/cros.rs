@@ -4,14 +4,21 @@
 // license that can be found in the LICENSE file or at
 // https://developers.google.com/open-source/licenses/bsd
 
+use crate::util::ensure_online;
 use crate::util::run_bash_command;
 use anyhow::anyhow;
 use anyhow::Context;
 use anyhow::Result;
 use regex_macro::regex;
+use std::env;
+use std::path::Path;
 use std::process::Command;
 
 pub fn ensure_testing_rsa_is_there() -> Result<()> {
+    if Path::new(&format!("{}/.ssh/testing_rsa", env::var("HOME")?)).exists() {
+        return Ok(());
+    }
+    ensure_online("Downloading testing_rsa")?;
     let cmd = "
 if ! [ -f ~/.ssh/testing_rsa ]; then
     curl -s https://chromium.googlesource.com/chromiumos/chromite/+/master/ssh_keys/testing_rsa?format=TEXT | base64 --decode > ~/.ssh/testing_rsa
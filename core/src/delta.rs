@@ -0,0 +1,327 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Delta flashing (`lium flash --dut <dut> --delta <image.bin>`). The kernel and the rootfs of
+//! the image are written to the inactive slot of the DUT, and only a binary delta (zstd
+//! --patch-from) against the ones flashed last time is transferred. Copies of the last flashed
+//! partitions are kept on the DUT under /usr/local/lium_delta and locally in the artifact
+//! store as the bases of the next delta. The stateful partition is kept as is.
+
+use crate::cache::KvCache;
+use crate::cache::ARTIFACT_STORE;
+use crate::dut::SshInfo;
+use crate::status;
+use crate::util::command_exists;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::sha256sum;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+
+const DUT_DELTA_DIR: &str = "/usr/local/lium_delta";
+const SECTOR_SIZE: u64 = 512;
+
+/// Hashes of the partitions flashed last time, keyed by host:port of the DUT
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DeltaBase {
+    pub kernel: String,
+    pub rootfs: String,
+}
+pub static DELTA_BASES: KvCache<DeltaBase> = KvCache::new("delta_bases");
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Kernel,
+    Rootfs,
+}
+impl Part {
+    fn name(&self) -> &'static str {
+        match self {
+            Part::Kernel => "kernel",
+            Part::Rootfs => "rootfs",
+        }
+    }
+    /// Name of the partition in the GPT of images
+    fn label(&self) -> &'static str {
+        match self {
+            Part::Kernel => "KERN-A",
+            Part::Rootfs => "ROOT-A",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SfdiskPartition {
+    start: u64,
+    size: u64,
+    #[serde(default)]
+    name: String,
+}
+#[derive(Deserialize)]
+struct SfdiskTable {
+    partitions: Vec<SfdiskPartition>,
+}
+#[derive(Deserialize)]
+struct SfdiskOutput {
+    partitiontable: SfdiskTable,
+}
+
+/// Returns the (offset, length) in bytes of the partition with the label in `sfdisk --json`
+fn find_partition(sfdisk_json: &str, label: &str) -> Result<(u64, u64)> {
+    let output: SfdiskOutput =
+        serde_json::from_str(sfdisk_json).context("Invalid sfdisk output")?;
+    output
+        .partitiontable
+        .partitions
+        .iter()
+        .find(|p| p.name == label)
+        .map(|p| (p.start * SECTOR_SIZE, p.size * SECTOR_SIZE))
+        .context(anyhow!("{label} is not found in the image"))
+}
+
+fn extract_partition(image: &Path, part: Part, dest: &Path) -> Result<()> {
+    let output = Command::new("sfdisk")
+        .arg("--json")
+        .arg(image)
+        .output()
+        .context("Failed to run sfdisk")?;
+    output
+        .status
+        .exit_ok()
+        .context(anyhow!("Failed to read the partition table of {image:?}"))?;
+    let (offset, len) = find_partition(&String::from_utf8_lossy(&output.stdout), part.label())?;
+    let mut src = File::open(image)?;
+    src.seek(SeekFrom::Start(offset))?;
+    std::io::copy(&mut src.take(len), &mut File::create(dest)?)?;
+    Ok(())
+}
+
+/// Returns the partition devices of the kernel and the rootfs of the slot not running now
+fn inactive_slot(ssh: &SshInfo) -> Result<(String, String, u32)> {
+    let disk = ssh.run_cmd_stdio("rootdev -s -d")?;
+    let root = ssh.run_cmd_stdio("rootdev -s")?;
+    let disk = disk.trim();
+    let root_num: u32 = root
+        .trim()
+        .trim_start_matches(disk)
+        .trim_start_matches('p')
+        .parse()
+        .context(anyhow!("Unexpected root device {root}"))?;
+    // KERN-A/ROOT-A are 2/3, and KERN-B/ROOT-B are 4/5
+    let (kern, root) = if root_num == 3 { (4, 5) } else { (2, 3) };
+    let sep = if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        "p"
+    } else {
+        ""
+    };
+    Ok((
+        format!("{disk}{sep}{kern}"),
+        format!("{disk}{sep}{root}"),
+        kern,
+    ))
+}
+
+fn file_size_mb(path: &Path) -> f64 {
+    fs::metadata(path).map(|m| m.len()).unwrap_or(0) as f64 / 1024.0 / 1024.0
+}
+
+/// Sends the partition to {DUT_DELTA_DIR}/<name>.new as a delta if the base is there
+fn send_partition(
+    ssh: &SshInfo,
+    part: Part,
+    new: &Path,
+    new_hash: &str,
+    base: Option<(&PathBuf, &str)>,
+    work_dir: &Path,
+) -> Result<()> {
+    let name = part.name();
+    let remote_base = format!("{DUT_DELTA_DIR}/{name}.bin");
+    let base = match base {
+        Some((path, hash))
+            if ssh
+                .run_cmd_stdio(&format!("sha256sum {remote_base} | cut -d ' ' -f 1"))
+                .map_or(false, |h| h.trim() == hash) =>
+        {
+            Some(path)
+        }
+        Some(_) => {
+            status!("The base of the {name} delta on the DUT is missing or modified");
+            None
+        }
+        None => None,
+    };
+    let (payload, apply) = match base {
+        Some(base) => {
+            let delta = work_dir.join(format!("{name}.delta.zst"));
+            Command::new("zstd")
+                .args(["-q", "-f", "-T0", "--long=31"])
+                .arg(format!("--patch-from={}", base.to_string_lossy()))
+                .arg(new)
+                .arg("-o")
+                .arg(&delta)
+                .status()?
+                .exit_ok()
+                .context("Failed to make a delta")?;
+            let apply = format!(
+                "zstd -d -q -f --long=31 --patch-from={remote_base} {DUT_DELTA_DIR}/{name}.delta.zst -o {DUT_DELTA_DIR}/{name}.new"
+            );
+            (delta, apply)
+        }
+        None => {
+            let full = work_dir.join(format!("{name}.full.zst"));
+            Command::new("zstd")
+                .args(["-q", "-f", "-T0"])
+                .arg(new)
+                .arg("-o")
+                .arg(&full)
+                .status()?
+                .exit_ok()
+                .context("Failed to compress the partition")?;
+            let apply = format!(
+                "zstd -d -q -f {DUT_DELTA_DIR}/{name}.full.zst -o {DUT_DELTA_DIR}/{name}.new"
+            );
+            (full, apply)
+        }
+    };
+    status!(
+        "Sending {} of {name} ({:.1} MB of {:.1} MB)...",
+        if base.is_some() { "a delta" } else { "all" },
+        file_size_mb(&payload),
+        file_size_mb(new)
+    );
+    ssh.send_files(
+        &[payload.to_string_lossy().to_string()],
+        Some(&DUT_DELTA_DIR.to_string()),
+    )?;
+    let hash = ssh.run_cmd_stdio(&format!(
+        "set -e; {apply}; rm -f {DUT_DELTA_DIR}/{name}.*.zst; sha256sum {DUT_DELTA_DIR}/{name}.new | cut -d ' ' -f 1"
+    ))?;
+    if hash.trim() != new_hash {
+        return Err(anyhow!("The {name} reconstructed on the DUT is corrupted"));
+    }
+    fs::remove_file(payload)?;
+    Ok(())
+}
+
+/// Flashes the kernel and the rootfs of the image to the inactive slot of the DUT and reboots
+/// into it
+pub fn delta_flash(ssh: &SshInfo, image: &Path, enable_rootfs_verification: bool) -> Result<()> {
+    if !command_exists("zstd") || !command_exists("sfdisk") {
+        return Err(anyhow!("zstd and sfdisk are needed for delta flashing"));
+    }
+    ssh.run_cmd_stdio("command -v zstd")
+        .context("zstd is not found on the DUT")?;
+    let key = ssh.host_and_port();
+    let work_dir = gen_path_in_lium_dir(&format!(
+        "delta/{}/.keep",
+        key.replace([':', '[', ']'], "_")
+    ))?;
+    let work_dir = work_dir.parent().context("Invalid work dir")?.to_path_buf();
+    let bases = DELTA_BASES.get(&key)?;
+    ssh.run_cmd_stdio(&format!("mkdir -p {DUT_DELTA_DIR}"))?;
+    let mut hashes = DeltaBase::default();
+    for part in [Part::Kernel, Part::Rootfs] {
+        let name = part.name();
+        let new = work_dir.join(format!("{name}.bin"));
+        status!("Extracting the {name} from {image:?}...");
+        extract_partition(image, part, &new)?;
+        let new_hash = sha256sum(&new)?;
+        let artifact = format!("delta/{key}/{name}.bin");
+        let base_path = ARTIFACT_STORE.get(&artifact)?;
+        let base_hash = bases.as_ref().map(|b| match part {
+            Part::Kernel => b.kernel.as_str(),
+            Part::Rootfs => b.rootfs.as_str(),
+        });
+        let base = base_path.as_ref().zip(base_hash);
+        send_partition(ssh, part, &new, &new_hash, base, &work_dir)?;
+        match part {
+            Part::Kernel => hashes.kernel = new_hash,
+            Part::Rootfs => hashes.rootfs = new_hash,
+        }
+    }
+    let (kern_dev, root_dev, kern_num) = inactive_slot(ssh)?;
+    status!("Writing to {kern_dev} and {root_dev}...");
+    let mut script = format!(
+        "set -e
+dd if={DUT_DELTA_DIR}/kernel.new of={kern_dev} bs=4M conv=fsync status=none
+dd if={DUT_DELTA_DIR}/rootfs.new of={root_dev} bs=4M conv=fsync status=none
+disk=$(rootdev -s -d)
+cgpt add -i {kern_num} -S 0 -T 6 $disk
+cgpt prioritize -i {kern_num} $disk
+"
+    );
+    if !enable_rootfs_verification {
+        script += &format!(
+            "/usr/share/vboot/bin/make_dev_ssd.sh --remove_rootfs_verification --partitions {kern_num} --force >/dev/null\n"
+        );
+    }
+    script += &format!(
+        "mv {DUT_DELTA_DIR}/kernel.new {DUT_DELTA_DIR}/kernel.bin
+mv {DUT_DELTA_DIR}/rootfs.new {DUT_DELTA_DIR}/rootfs.bin
+"
+    );
+    ssh.run_cmd_stdio(&script)
+        .context("Failed to write the partitions")?;
+    // The partitions on the DUT are the bases of the next delta from now on
+    for part in [Part::Kernel, Part::Rootfs] {
+        let new = work_dir.join(format!("{}.bin", part.name()));
+        ARTIFACT_STORE.add(&format!("delta/{key}/{}.bin", part.name()), &new)?;
+        fs::remove_file(new)?;
+    }
+    DELTA_BASES.set(&key, hashes)?;
+    status!("Rebooting into the new slot...");
+    let boot_id = ssh.get_boot_id()?;
+    // ssh may exit with an error since the connection is closed by the reboot
+    drop(ssh.run_cmd_piped(&["reboot; exit"]));
+    ssh.wait_for_new_boot_id(&boot_id, Duration::from_secs(300))?;
+    let (_, new_inactive_root, _) = inactive_slot(ssh)?;
+    if new_inactive_root == root_dev {
+        return Err(anyhow!(
+            "The DUT booted from the old slot. The new kernel may have failed to boot"
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partitions_of_image() {
+        let json = r#"{
+   "partitiontable": {
+      "label": "gpt",
+      "unit": "sectors",
+      "partitions": [
+         {"node": "img.bin1", "start": 5586944, "size": 8192, "type": "0FC63DAF-8483-4772-8E79-3D69D8477DE4", "name": "STATE"},
+         {"node": "img.bin2", "start": 20480, "size": 32768, "type": "FE3A2A5D-4F32-41A7-B725-ACCC3285A309", "name": "KERN-A"},
+         {"node": "img.bin3", "start": 1339392, "size": 4247552, "type": "3CB8E202-3B7E-47DD-8A3C-7FF2A13CFCEC", "name": "ROOT-A"}
+      ]
+   }
+}"#;
+        assert_eq!(
+            find_partition(json, Part::Kernel.label()).unwrap(),
+            (20480 * 512, 32768 * 512)
+        );
+        assert_eq!(
+            find_partition(json, Part::Rootfs.label()).unwrap(),
+            (1339392 * 512, 4247552 * 512)
+        );
+        assert!(find_partition(json, "KERN-B").is_err());
+    }
+}
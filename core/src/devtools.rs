@@ -0,0 +1,363 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Chrome remote debugging (the DevTools protocol) on DUTs. It is enabled with
+//! `--remote-debugging-port` in /etc/chrome_dev.conf, and the inspectable targets are listed via
+//! the /json/list endpoint of Chrome on the DUT. JavaScript is evaluated in a target with a small
+//! WebSocket client in python3 on the DUT, like Tast does from the host.
+
+use crate::dut::SshInfo;
+use crate::status;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const CHROME_DEV_CONF: &str = "/etc/chrome_dev.conf";
+/// ID of the autotest private extension, which has chrome.autotestPrivate
+pub const AUTOTEST_EXT_ID: &str = "behllobkkfkfnphdnhnkndlbkcpglgmj";
+
+/// Runs Runtime.evaluate in the first target whose ID, URL or title contains argv[2] (the first
+/// page if empty) and prints {"type": ..., "value": ...} or {"error": ...}
+const EVAL_SCRIPT: &str = r#"
+import base64, json, os, socket, struct, sys, urllib.request
+port = int(sys.argv[1])
+match = base64.b64decode(sys.argv[2]).decode()
+expr = base64.b64decode(sys.argv[3]).decode()
+def done(obj):
+    print(json.dumps(obj))
+    sys.exit(0)
+targets = json.load(urllib.request.urlopen('http://127.0.0.1:%d/json/list' % port))
+targets = [t for t in targets if 'webSocketDebuggerUrl' in t]
+if match:
+    targets = [t for t in targets if match == t['id'] or match in t['url'] or match in t.get('title', '')]
+else:
+    targets = [t for t in targets if t['type'] == 'page']
+if not targets:
+    done({'error': 'no matching target'})
+path = targets[0]['webSocketDebuggerUrl'].split(':%d' % port, 1)[1]
+s = socket.create_connection(('127.0.0.1', port))
+key = base64.b64encode(os.urandom(16)).decode()
+s.sendall(('GET %s HTTP/1.1\r\nHost: 127.0.0.1:%d\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n'
+           'Sec-WebSocket-Key: %s\r\nSec-WebSocket-Version: 13\r\n\r\n' % (path, port, key)).encode())
+f = s.makefile('rb')
+if b' 101 ' not in f.readline():
+    done({'error': 'WebSocket handshake failed'})
+while f.readline() not in (b'\r\n', b''):
+    pass
+data = json.dumps({'id': 1, 'method': 'Runtime.evaluate', 'params': {
+    'expression': expr, 'awaitPromise': True, 'returnByValue': True}}).encode()
+header = bytes([0x81])
+if len(data) < 126:
+    header += bytes([0x80 | len(data)])
+elif len(data) < 65536:
+    header += bytes([0x80 | 126]) + struct.pack('>H', len(data))
+else:
+    header += bytes([0x80 | 127]) + struct.pack('>Q', len(data))
+mask = os.urandom(4)
+s.sendall(header + mask + bytes(b ^ mask[i % 4] for i, b in enumerate(data)))
+message = b''
+while True:
+    b0, b1 = f.read(2)
+    n = b1 & 0x7f
+    if n == 126:
+        n = struct.unpack('>H', f.read(2))[0]
+    elif n == 127:
+        n = struct.unpack('>Q', f.read(8))[0]
+    payload = f.read(n)
+    if b0 & 0x0f >= 8:
+        continue
+    message += payload
+    if not b0 & 0x80:
+        continue
+    response = json.loads(message)
+    message = b''
+    if response.get('id') == 1:
+        break
+result = response.get('result', {})
+if 'exceptionDetails' in result:
+    details = result['exceptionDetails']
+    done({'error': details.get('exception', {}).get('description') or details.get('text')})
+if 'error' in response:
+    done({'error': response['error'].get('message')})
+value = result.get('result', {})
+done({'type': value.get('subtype') or value.get('type'), 'value': value.get('value')})
+"#;
+
+/// Keeps a DevTools session on the browser target that overrides the geolocation of every page
+/// (argv[2..5] are the latitude, the longitude and the accuracy). Overrides are cleared by Chrome
+/// when the session is closed, so this runs in the background until it is killed.
+const GEO_SCRIPT: &str = r#"
+import base64, json, os, socket, struct, sys, urllib.request
+port = int(sys.argv[1])
+coords = {'latitude': float(sys.argv[2]), 'longitude': float(sys.argv[3]), 'accuracy': float(sys.argv[4])}
+version = json.load(urllib.request.urlopen('http://127.0.0.1:%d/json/version' % port))
+path = version['webSocketDebuggerUrl'].split(':%d' % port, 1)[1]
+s = socket.create_connection(('127.0.0.1', port))
+key = base64.b64encode(os.urandom(16)).decode()
+s.sendall(('GET %s HTTP/1.1\r\nHost: 127.0.0.1:%d\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n'
+           'Sec-WebSocket-Key: %s\r\nSec-WebSocket-Version: 13\r\n\r\n' % (path, port, key)).encode())
+f = s.makefile('rb')
+if b' 101 ' not in f.readline():
+    sys.exit('WebSocket handshake failed')
+while f.readline() not in (b'\r\n', b''):
+    pass
+last_id = 0
+def call(method, params, session=None):
+    global last_id
+    last_id += 1
+    msg = {'id': last_id, 'method': method, 'params': params}
+    if session:
+        msg['sessionId'] = session
+    data = json.dumps(msg).encode()
+    header = bytes([0x81])
+    if len(data) < 126:
+        header += bytes([0x80 | len(data)])
+    elif len(data) < 65536:
+        header += bytes([0x80 | 126]) + struct.pack('>H', len(data))
+    else:
+        header += bytes([0x80 | 127]) + struct.pack('>Q', len(data))
+    mask = os.urandom(4)
+    s.sendall(header + mask + bytes(b ^ mask[i % 4] for i, b in enumerate(data)))
+    return last_id
+def receive():
+    message = b''
+    while True:
+        head = f.read(2)
+        if len(head) < 2:
+            sys.exit('Chrome closed the connection')
+        b0, b1 = head
+        n = b1 & 0x7f
+        if n == 126:
+            n = struct.unpack('>H', f.read(2))[0]
+        elif n == 127:
+            n = struct.unpack('>Q', f.read(8))[0]
+        payload = f.read(n)
+        if b0 & 0x0f >= 8:
+            continue
+        message += payload
+        if b0 & 0x80:
+            return json.loads(message)
+call('Browser.grantPermissions', {'permissions': ['geolocation']})
+call('Target.setDiscoverTargets', {'discover': True})
+attaching = set()
+while True:
+    m = receive()
+    if m.get('method') == 'Target.targetCreated' and m['params']['targetInfo']['type'] == 'page':
+        attaching.add(call('Target.attachToTarget', {'targetId': m['params']['targetInfo']['targetId'], 'flatten': True}))
+    elif m.get('id') in attaching:
+        attaching.discard(m['id'])
+        if 'result' in m:
+            call('Emulation.setGeolocationOverride', coords, m['result']['sessionId'])
+"#;
+const GEO_PID_FILE: &str = "/run/lium_geo.pid";
+const GEO_ARGS_FILE: &str = "/run/lium_geo.args";
+
+/// Returns true if Chrome on the DUT serves the DevTools protocol on the port
+pub fn is_remote_debugging_enabled(ssh: &SshInfo, port: u16) -> bool {
+    ssh.run_cmd_stdio(&format!(
+        "curl -sf http://127.0.0.1:{port}/json/version >/dev/null"
+    ))
+    .is_ok()
+}
+
+/// Adds `--remote-debugging-port` to chrome_dev.conf. The UI needs to be restarted to apply it.
+pub fn enable_remote_debugging(ssh: &SshInfo, port: u16) -> Result<()> {
+    ssh.run_cmd_stdio(&format!(
+        r#"set -e
+mount -o remount,rw / 2>/dev/null
+grep -v '^--remote-debugging-port=' {CHROME_DEV_CONF} > /tmp/chrome_dev.conf.new || true
+echo '--remote-debugging-port={port}' >> /tmp/chrome_dev.conf.new
+mv /tmp/chrome_dev.conf.new {CHROME_DEV_CONF}
+"#
+    ))
+    .context("Failed to update chrome_dev.conf (is the rootfs verification removed?)")?;
+    Ok(())
+}
+
+/// Enables remote debugging if needed, restarting the UI unless `no_restart`
+pub fn ensure_remote_debugging(ssh: &SshInfo, port: u16, no_restart: bool) -> Result<()> {
+    if is_remote_debugging_enabled(ssh, port) {
+        return Ok(());
+    }
+    if no_restart {
+        return Err(anyhow!(
+            "Remote debugging is not enabled on port {port}. Run without --no-restart to enable it"
+        ));
+    }
+    enable_remote_debugging(ssh, port)?;
+    status!("Restarting the UI to enable remote debugging...");
+    ssh.run_cmd_stdio("restart ui")?;
+    let deadline = Instant::now() + Duration::from_secs(60);
+    while !is_remote_debugging_enabled(ssh, port) {
+        if Instant::now() > deadline {
+            return Err(anyhow!("Chrome did not start remote debugging in time"));
+        }
+        thread::sleep(Duration::from_secs(2));
+    }
+    Ok(())
+}
+
+/// Evaluates the JavaScript expression in the first target whose ID, URL or title contains
+/// `target` (the first page if None), awaiting promises. Returns {"type": ..., "value": ...}.
+pub fn evaluate(ssh: &SshInfo, port: u16, target: Option<&str>, expr: &str) -> Result<Value> {
+    let output = ssh.run_cmd_stdio(&format!(
+        "echo {} | base64 -d > /tmp/lium_cdp_eval.py && python3 /tmp/lium_cdp_eval.py {port} '{}' '{}'",
+        STANDARD.encode(EVAL_SCRIPT),
+        STANDARD.encode(target.unwrap_or_default()),
+        STANDARD.encode(expr)
+    ))?;
+    let result: Value =
+        serde_json::from_str(output.trim()).context(anyhow!("Invalid result: {output}"))?;
+    match result.get("error") {
+        Some(e) => Err(anyhow!(
+            "Evaluation failed: {}",
+            e.as_str().unwrap_or_default()
+        )),
+        None => Ok(result),
+    }
+}
+
+/// Opens the URL in a new tab and returns the target of it
+pub fn open_url(ssh: &SshInfo, port: u16, url: &str) -> Result<Value> {
+    // PUT is required by newer Chrome, and the URL is passed as is after '?'
+    let json = ssh.run_cmd_stdio(&format!(
+        "curl -sf -X PUT \"http://127.0.0.1:{port}/json/new?$(echo {} | base64 -d)\"",
+        STANDARD.encode(url)
+    ))?;
+    serde_json::from_str(&json).context(anyhow!("Invalid response of /json/new: {json}"))
+}
+
+/// Returns the inspectable targets (pages, workers, ...) of Chrome on the DUT
+pub fn list_targets(ssh: &SshInfo, port: u16) -> Result<Vec<Value>> {
+    let json = ssh.run_cmd_stdio(&format!("curl -sf http://127.0.0.1:{port}/json/list"))?;
+    let targets: Value = serde_json::from_str(&json).context("Invalid response of /json/list")?;
+    match targets {
+        Value::Array(targets) => Ok(targets),
+        _ => Err(anyhow!("Unexpected response of /json/list: {json}")),
+    }
+}
+
+/// Rewrites the URLs in the targets to go through the local port forwarded to `dut_port`, and
+/// adds "localFrontendUrl" that can be opened in the local Chrome
+pub fn localize_targets(targets: &mut [Value], dut_port: u16, local_port: u16) {
+    let rewrite = |s: &str| {
+        s.replace(
+            &format!("127.0.0.1:{dut_port}"),
+            &format!("127.0.0.1:{local_port}"),
+        )
+        .replace(
+            &format!("localhost:{dut_port}"),
+            &format!("127.0.0.1:{local_port}"),
+        )
+    };
+    for target in targets {
+        let Value::Object(target) = target else {
+            continue;
+        };
+        for key in ["webSocketDebuggerUrl", "devtoolsFrontendUrl"] {
+            if let Some(Value::String(url)) = target.get_mut(key) {
+                *url = rewrite(url);
+            }
+        }
+        if let Some(Value::String(url)) = target.get("devtoolsFrontendUrl") {
+            // The frontend is served by Chrome on the DUT if the URL is relative
+            let url = if url.starts_with('/') {
+                format!("http://127.0.0.1:{local_port}{url}")
+            } else {
+                url.clone()
+            };
+            target.insert("localFrontendUrl".to_string(), Value::String(url));
+        }
+    }
+}
+
+/// Overrides the geolocation of the pages in Chrome until stop_geolocation_override() is called
+/// or Chrome restarts
+pub fn start_geolocation_override(
+    ssh: &SshInfo,
+    port: u16,
+    latitude: f64,
+    longitude: f64,
+    accuracy: f64,
+) -> Result<()> {
+    stop_geolocation_override(ssh)?;
+    ssh.run_cmd_stdio(&format!(
+        "echo {} | base64 -d > /tmp/lium_geo.py && \
+         (setsid python3 /tmp/lium_geo.py {port} {latitude} {longitude} {accuracy} \
+           </dev/null >/tmp/lium_geo.log 2>&1 & echo $! > {GEO_PID_FILE}) && \
+         echo '{latitude},{longitude}' > {GEO_ARGS_FILE}",
+        STANDARD.encode(GEO_SCRIPT)
+    ))?;
+    // Catch early failures (e.g. the handshake) instead of reporting success
+    thread::sleep(Duration::from_secs(2));
+    if geolocation_override(ssh)?.is_none() {
+        let log = ssh
+            .run_cmd_stdio("cat /tmp/lium_geo.log")
+            .unwrap_or_default();
+        return Err(anyhow!("Failed to override the geolocation: {log}"));
+    }
+    Ok(())
+}
+
+/// Returns "latitude,longitude" if the geolocation is overridden
+pub fn geolocation_override(ssh: &SshInfo) -> Result<Option<String>> {
+    let output = ssh.run_cmd_stdio(&format!(
+        "kill -0 $(cat {GEO_PID_FILE} 2>/dev/null) 2>/dev/null && cat {GEO_ARGS_FILE} || true"
+    ))?;
+    let output = output.trim();
+    Ok((!output.is_empty()).then(|| output.to_string()))
+}
+
+/// Stops overriding the geolocation. Returns false if it was not overridden.
+pub fn stop_geolocation_override(ssh: &SshInfo) -> Result<bool> {
+    let was_overridden = geolocation_override(ssh)?.is_some();
+    ssh.run_cmd_stdio(&format!(
+        "kill $(cat {GEO_PID_FILE} 2>/dev/null) 2>/dev/null; rm -f {GEO_PID_FILE} {GEO_ARGS_FILE}"
+    ))?;
+    Ok(was_overridden)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn localize() {
+        let mut targets: Vec<Value> = serde_json::from_str(
+            r#"[{
+                "id": "A1",
+                "type": "page",
+                "devtoolsFrontendUrl": "/devtools/inspector.html?ws=127.0.0.1:9222/devtools/page/A1",
+                "webSocketDebuggerUrl": "ws://127.0.0.1:9222/devtools/page/A1"
+            }, {
+                "id": "B2",
+                "type": "service_worker",
+                "webSocketDebuggerUrl": "ws://localhost:9222/devtools/page/B2"
+            }]"#,
+        )
+        .unwrap();
+        localize_targets(&mut targets, 9222, 40000);
+        assert_eq!(
+            targets[0]["webSocketDebuggerUrl"],
+            "ws://127.0.0.1:40000/devtools/page/A1"
+        );
+        assert_eq!(
+            targets[0]["localFrontendUrl"],
+            "http://127.0.0.1:40000/devtools/inspector.html?ws=127.0.0.1:40000/devtools/page/A1"
+        );
+        assert_eq!(
+            targets[1]["webSocketDebuggerUrl"],
+            "ws://127.0.0.1:40000/devtools/page/B2"
+        );
+        assert!(targets[1].get("localFrontendUrl").is_none());
+    }
+}
@@ -0,0 +1,195 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! DNS overrides on DUTs. Host overrides are lines in /etc/hosts marked with a comment, and name
+//! servers are set as the static IP config of the default shill service. The original name
+//! servers are kept on the DUT, so that `clear_dns_overrides()` reverts exactly what lium changed.
+
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::net::IpAddr;
+
+const HOSTS: &str = "/etc/hosts";
+const MARKER: &str = "# lium-dns";
+const STATE_DIR: &str = "/usr/local/lium_dns";
+
+/// A host name resolved to a fixed address
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostOverride {
+    pub host: String,
+    pub addr: IpAddr,
+}
+impl HostOverride {
+    /// Parses "staging.example.com=10.1.2.3"
+    pub fn parse(s: &str) -> Result<Self> {
+        let (host, addr) = s
+            .split_once('=')
+            .context(anyhow!("Invalid override {s:?}. Use <host>=<addr>"))?;
+        validate_host(host)?;
+        Ok(Self {
+            host: host.to_string(),
+            addr: addr.parse().context(anyhow!("Invalid address in {s:?}"))?,
+        })
+    }
+}
+
+fn validate_host(host: &str) -> Result<()> {
+    if host.is_empty()
+        || !host
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+    {
+        return Err(anyhow!("Invalid host name {host:?}"));
+    }
+    Ok(())
+}
+
+// python3 with dbus is on test images. dbus-send cannot build a{sv} with an array in it.
+fn set_name_servers_script(servers: &[IpAddr]) -> String {
+    let servers: Vec<String> = servers.iter().map(|s| format!("'{s}'")).collect();
+    format!(
+        r#"set -e
+mkdir -p {STATE_DIR}
+python3 - <<'LIUM_EOF'
+import dbus, json, os
+bus = dbus.SystemBus()
+def iface(path, name):
+    return dbus.Interface(bus.get_object('org.chromium.flimflam', path), 'org.chromium.flimflam.' + name)
+state = '{STATE_DIR}/name_servers.json'
+if os.path.exists(state):
+    path = json.load(open(state))['service']
+else:
+    path = iface('/', 'Manager').GetProperties()['DefaultService']
+    if path == '/':
+        raise SystemExit('No network is connected')
+service = iface(path, 'Service')
+config = service.GetProperties().get('StaticIPConfig', {{}})
+if not os.path.exists(state):
+    json.dump({{'service': str(path), 'name_servers': [str(s) for s in config.get('NameServers', [])]}}, open(state, 'w'))
+config = dbus.Dictionary(config, signature='sv')
+config['NameServers'] = dbus.Array([{}], signature='s')
+service.SetProperty('StaticIPConfig', config)
+LIUM_EOF
+"#,
+        servers.join(", ")
+    )
+}
+
+fn restore_name_servers_script() -> String {
+    format!(
+        r#"test -f {STATE_DIR}/name_servers.json || exit 0
+set -e
+python3 - <<'LIUM_EOF'
+import dbus, json
+bus = dbus.SystemBus()
+state = json.load(open('{STATE_DIR}/name_servers.json'))
+service = dbus.Interface(bus.get_object('org.chromium.flimflam', state['service']), 'org.chromium.flimflam.Service')
+config = dbus.Dictionary(service.GetProperties().get('StaticIPConfig', {{}}), signature='sv')
+if state['name_servers']:
+    config['NameServers'] = dbus.Array(state['name_servers'], signature='s')
+else:
+    config.pop('NameServers', None)
+service.SetProperty('StaticIPConfig', config)
+LIUM_EOF
+rm -rf {STATE_DIR}
+"#
+    )
+}
+
+fn remove_hosts_script(hosts: &[&str]) -> String {
+    hosts
+        .iter()
+        .map(|h| {
+            format!(
+                "sed -i --follow-symlinks '/ {} {MARKER}$/d' {HOSTS}\n",
+                h.replace('.', r"\.")
+            )
+        })
+        .collect()
+}
+
+/// Adds or replaces host overrides in /etc/hosts
+pub fn add_host_overrides(ssh: &SshInfo, overrides: &[HostOverride]) -> Result<()> {
+    let hosts: Vec<&str> = overrides.iter().map(|o| o.host.as_str()).collect();
+    let mut script = "set -e\nmount -o remount,rw / 2>/dev/null || true\n".to_string();
+    script += &remove_hosts_script(&hosts);
+    for o in overrides {
+        script += &format!("echo '{} {} {MARKER}' >> {HOSTS}\n", o.addr, o.host);
+    }
+    ssh.run_cmd_stdio(&script)
+        .context("Failed to update /etc/hosts (is the rootfs verification removed?)")?;
+    Ok(())
+}
+
+pub fn remove_host_overrides(ssh: &SshInfo, hosts: &[String]) -> Result<()> {
+    for h in hosts {
+        validate_host(h)?;
+    }
+    let hosts: Vec<&str> = hosts.iter().map(|h| h.as_str()).collect();
+    ssh.run_cmd_stdio(&format!(
+        "set -e\nmount -o remount,rw / 2>/dev/null || true\n{}",
+        remove_hosts_script(&hosts)
+    ))?;
+    Ok(())
+}
+
+/// Sets the name servers of the default network. The original ones are restored on clear.
+pub fn set_name_servers(ssh: &SshInfo, servers: &[IpAddr]) -> Result<()> {
+    ssh.run_cmd_stdio(&set_name_servers_script(servers))
+        .context("Failed to set the name servers")?;
+    Ok(())
+}
+
+/// Reverts all the changes made by lium
+pub fn clear_dns_overrides(ssh: &SshInfo) -> Result<()> {
+    ssh.run_cmd_stdio(&format!(
+        "mount -o remount,rw / 2>/dev/null\nsed -i --follow-symlinks '/ {MARKER}$/d' {HOSTS}\n{}",
+        restore_name_servers_script()
+    ))?;
+    Ok(())
+}
+
+/// Returns the overrides made by lium in a human-readable form
+pub fn dns_overrides_status(ssh: &SshInfo) -> Result<String> {
+    ssh.run_cmd_stdio(&format!(
+        r#"grep ' {MARKER}$' {HOSTS} | sed 's/ {MARKER}$//'
+test -f {STATE_DIR}/name_servers.json || exit 0
+python3 - <<'LIUM_EOF'
+import dbus, json
+bus = dbus.SystemBus()
+state = json.load(open('{STATE_DIR}/name_servers.json'))
+service = dbus.Interface(bus.get_object('org.chromium.flimflam', state['service']), 'org.chromium.flimflam.Service')
+config = service.GetProperties().get('StaticIPConfig', {{}})
+print('name servers of %s: %s (originally: %s)' % (
+    state['service'],
+    ' '.join(str(s) for s in config.get('NameServers', [])),
+    ' '.join(state['name_servers']) or 'none'))
+LIUM_EOF
+"#
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_overrides() {
+        let o = HostOverride::parse("staging.example.com=10.1.2.3").unwrap();
+        assert_eq!(o.host, "staging.example.com");
+        assert_eq!(o.addr.to_string(), "10.1.2.3");
+        assert!(HostOverride::parse("a.example.com=2001:db8::1").is_ok());
+        assert!(HostOverride::parse("a.example.com").is_err());
+        assert!(HostOverride::parse("a'b=10.0.0.1").is_err());
+        assert!(HostOverride::parse("a=10.0.0").is_err());
+        assert_eq!(
+            remove_hosts_script(&["a.b"]),
+            "sed -i --follow-symlinks '/ a\\.b # lium-dns$/d' /etc/hosts\n"
+        );
+    }
+}
@@ -0,0 +1,2541 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::cache::KvCache;
+use crate::cancel;
+use crate::config::Config;
+use crate::connection::run_pre_connect_steps;
+use crate::connection::send_wol;
+use crate::cros::ensure_testing_rsa_is_there;
+use crate::dut_file;
+use crate::dut_id;
+use crate::dut_id::DutIdentifier;
+use crate::escalation;
+use crate::progress::Progress;
+use crate::shim;
+use crate::ssh_auth::SshAuth;
+use crate::status;
+use crate::statusd;
+use crate::ui::styled;
+use crate::ui::symbol;
+use crate::ui::Stream;
+use crate::ui::Style;
+use crate::util::ensure_online;
+use crate::util::get_async_lines;
+use crate::util::get_stderr;
+use crate::util::get_stdout;
+use crate::util::run_bash_command;
+use crate::util::run_concurrently;
+use crate::util::spawn_lium_in_background;
+use crate::util::with_timeout;
+use crate::warning;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use async_io::Async;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Local;
+use futures::executor::block_on;
+use futures::select;
+use futures::FutureExt;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use macaddr::MacAddr6;
+use rand::seq::SliceRandom;
+use rand::thread_rng;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::ffi::OsStr;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::net::ToSocketAddrs;
+use std::ops::Range;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const COMMON_SSH_OPTIONS: [&str; 16] = [
+    // Do not read ~/.ssh/config to avoid effects comes from ssh_config
+    "-F",
+    "none",
+    // Use CrOS testing_rsa as a key
+    "-i",
+    "~/.ssh/testing_rsa",
+    // Enable batchmode to disable interactive auth
+    "-o",
+    "BatchMode=yes",
+    // Do not check host key since it can change easily during development
+    "-o",
+    "StrictHostKeyChecking=no",
+    // Do not record or verify the known hosts
+    "-o",
+    "UserKnownHostsFile=/dev/null",
+    // Silence the "Warning: Permanently added ... to the list of known hosts" message
+    "-o",
+    "LogLevel=ERROR",
+    // Set connection timeout to give up quickly
+    "-o",
+    "ConnectTimeout=5",
+    // Try pubkey auth only
+    "-o",
+    "PreferredAuthentications=publickey",
+];
+const COMMON_PORT_FORWARD_TOKEN: &str = "lium-ssh-portforward";
+/// Max number of ssh processes to check DUTs concurrently
+pub const MAX_PARALLEL_SSH: usize = 64;
+/// Time limit to check if a host is a DUT, including the ssh handshake
+pub const DUT_PROBE_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref RE_IPV6_WITH_BRACKETS: Regex = Regex::new(r"^\[(?P<addr>[0-9a-fA-F:]+(%.*)?)\]$").unwrap();
+    // based on https://url.spec.whatwg.org/#host-miscellaneous
+    static ref RE_DUT_HOST_NAME: Regex =
+        Regex::new(r"^(([0-9.]+)|([0-9a-fA-F:]+(%.*)?)|([^\t\n\r #/:<>?@\[\]^|]+))$").unwrap();
+    static ref RE_GBB_FLAGS: Regex =
+        Regex::new(r"^0x[0-9a-fA-F]+$").unwrap();
+    /// DUTs whose pre-connect steps have been run in this process
+    static ref PRE_CONNECT_DONE: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+    /// Host patterns of ssh_auth whose pre-flight check passed in this process
+    static ref SSH_AUTH_CHECKED: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+pub static SSH_CACHE: KvCache<SshInfo> = KvCache::new("ssh_cache");
+
+/// Where an entry of SSH_CACHE came from, to trace unexpected entries
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DutProvenance {
+    /// the command that added the entry (e.g. "lium dut discover")
+    pub discovered_by: String,
+    /// the network interface the DUT was found on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub interface: Option<String>,
+    /// UNIX time in seconds
+    pub timestamp: i64,
+}
+impl DutProvenance {
+    /// Returns the provenance for entries added by this process now
+    pub fn now(interface: Option<String>) -> Self {
+        let mut args: Vec<String> = std::env::args().collect();
+        if let Some(name) = args
+            .first()
+            .and_then(|a| std::path::Path::new(a).file_name())
+        {
+            args[0] = name.to_string_lossy().to_string();
+        }
+        Self {
+            discovered_by: args.join(" "),
+            interface,
+            timestamp: Local::now().timestamp(),
+        }
+    }
+}
+impl std::fmt::Display for DutProvenance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let time = chrono::NaiveDateTime::from_timestamp_opt(self.timestamp, 0)
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_default();
+        write!(f, "added by `{}`", self.discovered_by)?;
+        if let Some(interface) = &self.interface {
+            write!(f, " on {interface}")?;
+        }
+        write!(f, " at {time} UTC")
+    }
+}
+/// Provenance of the entries of SSH_CACHE keyed by DUT ID
+pub static DUT_PROVENANCE: KvCache<DutProvenance> = KvCache::new("dut_provenance");
+
+/// A free-text note about a DUT (e.g. "replaced battery 2024-05")
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DutNote {
+    pub text: String,
+    /// UNIX time in seconds
+    pub timestamp: i64,
+}
+impl std::fmt::Display for DutNote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let date = chrono::NaiveDateTime::from_timestamp_opt(self.timestamp, 0)
+            .map(|t| t.format("%Y-%m-%d").to_string())
+            .unwrap_or_default();
+        write!(f, "{date} {}", self.text)
+    }
+}
+/// Notes keyed by DUT ID, oldest first. Kept even if the DUT is removed from the list.
+pub static DUT_NOTES: KvCache<Vec<DutNote>> = KvCache::new("dut_notes");
+/// Path of the file written by `lium dut ssh-config --out`, to be kept in sync
+static SSH_CONFIG_EXPORT_CACHE: KvCache<String> = KvCache::new("ssh_config_export_cache");
+
+/// Returns a name usable as a Host alias in ssh_config(5) (no ':' or brackets)
+pub fn ssh_config_alias(dut: &str) -> String {
+    dut.replace(
+        |c: char| !(c.is_ascii_alphanumeric() || "._-".contains(c)),
+        "_",
+    )
+}
+/// Renders Host blocks for all cached DUTs
+pub fn render_ssh_config() -> Result<String> {
+    let mut duts: Vec<(String, SshInfo)> = SSH_CACHE.entries()?.into_iter().collect();
+    duts.sort_by(|a, b| a.0.cmp(&b.0));
+    let mut config = "# Generated by `lium dut ssh-config`. Do not edit.\n\n".to_string();
+    for (id, ssh) in duts {
+        config += &ssh.ssh_config_entry(&ssh_config_alias(&id))?;
+        config += "\n";
+    }
+    Ok(config)
+}
+/// Writes the ssh config for all cached DUTs to `path`, and remembers it for
+/// `update_exported_ssh_config`
+pub fn export_ssh_config(path: &str) -> Result<()> {
+    std::fs::write(path, render_ssh_config()?).context(anyhow!("Failed to write {path}"))?;
+    SSH_CONFIG_EXPORT_CACHE.set("path", path.to_string())
+}
+/// Returns the path of the file written by `export_ssh_config`, if any
+pub fn exported_ssh_config_path() -> Result<Option<String>> {
+    SSH_CONFIG_EXPORT_CACHE.get("path")
+}
+/// Re-renders the file previously written by `export_ssh_config`, if any
+pub fn update_exported_ssh_config() -> Result<()> {
+    if let Some(path) = SSH_CONFIG_EXPORT_CACHE.get("path")? {
+        export_ssh_config(&path)?;
+    }
+    Ok(())
+}
+
+/// A value of a DUT attribute and when it was fetched
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedValue {
+    pub value: String,
+    /// UNIX time in seconds
+    pub fetched_at: i64,
+}
+impl CachedValue {
+    pub fn age(&self) -> Duration {
+        Duration::from_secs((Local::now().timestamp() - self.fetched_at).max(0) as u64)
+    }
+}
+/// Last known DutInfo values keyed by DUT ID, used by `--cached`, `--max-age` and the offline mode
+pub static DUT_INFO_CACHE: KvCache<HashMap<String, CachedValue>> = KvCache::new("dut_info_cache");
+/// Cached values older than this are refreshed in the background when used with `--cached`
+pub const DUT_INFO_DEFAULT_MAX_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// A file pushed to a DUT by lium
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct PushedFile {
+    pub path: String,
+    /// a copy of the file overwritten by the push, if any
+    pub backup: Option<String>,
+    /// UNIX time in seconds
+    pub pushed_at: i64,
+}
+/// Files pushed to DUTs keyed by host_and_port(), used by `dut sanitize`
+pub static DUT_PUSH_AUDIT_LOG: KvCache<Vec<PushedFile>> = KvCache::new("dut_push_audit_log");
+/// File name of the archive of logs made by `SshInfo::collect_logs()`
+pub const LOG_BUNDLE_NAME: &str = "lium_logs.tar.gz";
+/// Suffix of the backups of files overwritten by pushes
+pub const PUSH_BACKUP_SUFFIX: &str = ".lium-backup";
+
+/// Number of RTT samples kept for each DUT to calculate percentiles
+const RTT_SAMPLES_MAX: usize = 100;
+
+/// Returns the value at the given percentile (0.0 - 100.0) of sorted samples
+fn percentile(sorted: &[Duration], p: f64) -> Option<Duration> {
+    if sorted.is_empty() {
+        return None;
+    }
+    let rank = (p / 100.0 * (sorted.len() - 1) as f64).round() as usize;
+    sorted.get(rank).cloned()
+}
+
+/// LatencyStats holds RTT statistics calculated from the recent probes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LatencyStats {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    /// Mean absolute difference between consecutive samples
+    pub jitter: Duration,
+    /// Ratio of failed probes in the recent probes (0.0 - 1.0)
+    pub loss: f64,
+}
+impl LatencyStats {
+    fn from_samples(samples: &VecDeque<Option<Duration>>) -> Option<Self> {
+        let rtts: Vec<Duration> = samples.iter().flatten().cloned().collect();
+        let mut sorted = rtts.clone();
+        sorted.sort();
+        let jitter = if rtts.len() < 2 {
+            Duration::ZERO
+        } else {
+            rtts.windows(2)
+                .map(|w| {
+                    if w[0] > w[1] {
+                        w[0] - w[1]
+                    } else {
+                        w[1] - w[0]
+                    }
+                })
+                .sum::<Duration>()
+                / (rtts.len() - 1) as u32
+        };
+        Some(Self {
+            p50: percentile(&sorted, 50.0)?,
+            p90: percentile(&sorted, 90.0)?,
+            p99: percentile(&sorted, 99.0)?,
+            jitter,
+            loss: (samples.len() - rtts.len()) as f64 / samples.len() as f64,
+        })
+    }
+}
+
+/// MonitoredDut holds connection to a monitoring Dut
+#[derive(Debug)]
+pub struct MonitoredDut {
+    ssh: SshInfo,
+    dut: String,
+    port: u16,
+    child: Option<async_process::Child>,
+    reconnecting: bool,
+    rtt_samples: Arc<Mutex<VecDeque<Option<Duration>>>>,
+    /// stops the latency probe thread when the DUT is dropped
+    probe_stop: Arc<AtomicBool>,
+    /// send WoL packets while the DUT is not reachable
+    auto_wake: bool,
+    last_wake: Option<Instant>,
+    /// statusd reports the DUT as offline
+    known_offline: bool,
+}
+impl MonitoredDut {
+    pub fn new(dut: &str, port: u16, probe_interval: Duration, auto_wake: bool) -> Result<Self> {
+        let ssh = SshInfo::new(dut).context("failed to create SshInfo")?;
+        let dut = MonitoredDut {
+            ssh: ssh.clone(),
+            dut: dut.to_string(),
+            port,
+            child: ssh.start_ssh_forwarding(port).ok(),
+            reconnecting: false,
+            rtt_samples: Arc::new(Mutex::new(VecDeque::new())),
+            probe_stop: Arc::new(AtomicBool::new(false)),
+            auto_wake,
+            last_wake: None,
+            known_offline: false,
+        };
+        dut.start_latency_probe(probe_interval);
+        Ok(dut)
+    }
+    /// Probe the RTT to the sshd of the DUT periodically, independently from the status check.
+    fn start_latency_probe(&self, interval: Duration) {
+        let ssh = self.ssh.clone();
+        let samples = self.rtt_samples.clone();
+        let stop = self.probe_stop.clone();
+        thread::spawn(move || {
+            while !stop.load(Ordering::Relaxed) {
+                let rtt = ssh.probe_tcp_rtt(interval).ok();
+                {
+                    let mut samples = samples.lock().unwrap();
+                    samples.push_back(rtt);
+                    while samples.len() > RTT_SAMPLES_MAX {
+                        samples.pop_front();
+                    }
+                }
+                thread::sleep(interval);
+            }
+        });
+    }
+    pub fn latency_stats(&self) -> Option<LatencyStats> {
+        LatencyStats::from_samples(&self.rtt_samples.lock().unwrap())
+    }
+    pub fn reconnecting(&self) -> bool {
+        self.reconnecting
+    }
+    pub fn dut(&self) -> &str {
+        &self.dut
+    }
+    pub fn address(&self) -> String {
+        self.ssh.host_and_port()
+    }
+    /// Returns true if the DUT was connected at the last status check
+    pub fn is_online(&self) -> bool {
+        self.child.is_some() && !self.reconnecting
+    }
+    /// Takes the status from statusd into account to avoid reconnecting to offline DUTs
+    pub fn update_from_statusd(&mut self, states: &HashMap<String, statusd::DutState>) {
+        self.known_offline = states
+            .get(&self.dut)
+            .map_or(false, |s| s.status == "Offline");
+    }
+    async fn reconnect(&mut self) -> Result<Vec<String>> {
+        if self.known_offline && !self.auto_wake {
+            self.child = None;
+            self.reconnecting = true;
+            return Ok(vec![
+                self.dut.clone(),
+                "Offline (reported by statusd)".to_string(),
+            ]);
+        }
+        if self.auto_wake
+            && self
+                .last_wake
+                .map(|t| t.elapsed() > Duration::from_secs(60))
+                .unwrap_or(true)
+            && self
+                .ssh
+                .probe_tcp_rtt_async(Duration::from_secs(1))
+                .await
+                .is_err()
+            && self.ssh.is_on_local_segment()
+        {
+            self.last_wake = Some(Instant::now());
+            if let Err(e) = self.ssh.send_wake_packet() {
+                warning!("Failed to wake {}: {e:#}", self.dut);
+            }
+        }
+        let new_child = self.ssh.start_ssh_forwarding(self.port);
+        if let Err(e) = &new_child {
+            warning!("Failed to reconnect: {e:?}");
+        };
+        self.child = new_child.ok();
+        self.reconnecting = true;
+        Ok(vec![self.dut.clone(), "Reconnecting...".to_string()])
+    }
+    pub fn get_status_header() -> Vec<String> {
+        [
+            "DUT",
+            "Forward Addr",
+            "IP Addr",
+            "RTT ms (p50/p90/p99 jitter loss)",
+        ]
+        .map(String::from)
+        .to_vec()
+    }
+    fn format_latency(&self) -> String {
+        if let Some(stats) = self.latency_stats() {
+            format!(
+                "{:.1}/{:.1}/{:.1} {:.1} {:.0}%",
+                stats.p50.as_secs_f64() * 1000.0,
+                stats.p90.as_secs_f64() * 1000.0,
+                stats.p99.as_secs_f64() * 1000.0,
+                stats.jitter.as_secs_f64() * 1000.0,
+                stats.loss * 100.0
+            )
+        } else {
+            "-".to_string()
+        }
+    }
+    /// Returns the status as a map for `--format` (keys: dut, forward, address, status, rtt)
+    pub async fn get_status_fields(&mut self) -> Result<HashMap<String, String>> {
+        // Updates the connection and self.reconnecting
+        self.get_status().await?;
+        let (status, forward) = if self.reconnecting {
+            ("Reconnecting", String::new())
+        } else {
+            ("Connected", format!("127.0.0.1:{}", self.port))
+        };
+        Ok(HashMap::from([
+            ("dut".to_string(), self.dut.clone()),
+            ("forward".to_string(), forward),
+            ("address".to_string(), self.ssh.host_and_port()),
+            ("status".to_string(), status.to_string()),
+            ("rtt".to_string(), self.format_latency()),
+        ]))
+    }
+    /// Returns the cells of the status row. This is async so that the DUTs can be checked
+    /// concurrently.
+    pub async fn get_status(&mut self) -> Result<Vec<String>> {
+        if let Some(child) = &mut self.child {
+            match child.try_status()? {
+                None => {
+                    self.reconnecting = false;
+                    Ok(vec![
+                        self.dut.clone(),
+                        format!("127.0.0.1:{}", self.port),
+                        self.ssh.host_and_port(),
+                        self.format_latency(),
+                    ])
+                }
+                Some(_status) => self.reconnect().await,
+            }
+        } else {
+            self.reconnect().await
+        }
+    }
+}
+impl Drop for MonitoredDut {
+    fn drop(&mut self) {
+        self.probe_stop.store(true, Ordering::Relaxed);
+    }
+}
+
+lazy_static! {
+    // We cannot use `grep -Po` here since some machines have grep built with --disable-perl-regexp
+    static ref DUT_ATTRIBUTE_CMDS: HashMap<&'static str, &'static str> = {
+        let mut m: HashMap<&'static str, &'static str> = HashMap::new();
+        m.insert("board", r"cat /etc/lsb-release | grep CHROMEOS_RELEASE_BOARD | cut -d '=' -f 2 | cut -d '-' -f 1");
+        m.insert("hwid", r"crossystem hwid");
+        m.insert("arch", r"crossystem arch");
+        m.insert("serial", r"vpd -g serial_number");
+        m.insert("model_from_cros_config", r"cros_config / name");
+        m.insert("model_from_mosys", r"mosys platform name");
+        m.insert("ectool_temps_all", r"ectool temps all");
+        m.insert(
+            "gbb_flags_from_futility",
+            r"/usr/bin/futility gbb --flash --get --flags | grep 'flags: ' | cut -d : -f 2",
+        );
+        m.insert(
+            "gbb_flags_from_shell",
+            // get_gbb_flags.sh is deprecated but keeping this usage for backwards compatibility.
+            // Newer scripts should use futility instead (see above). b/269179419 for more info.
+            r"/usr/share/vboot/bin/get_gbb_flags.sh | grep 'Chrome OS GBB' | cut -d : -f 2",
+        );
+        m.insert(
+            "host_kernel_config",
+            r"modprobe configs; zcat /proc/config.gz",
+        );
+        m.insert("lshw", r"lshw -json");
+        m.insert("lsb_release", r"cat /etc/lsb-release");
+        m.insert("ipv6_addr", concat!(r"ip -6 address show dev `lium_get_default_iface` mngtmpaddr | grep inet6 | sed -E 's/\s+/ /g' | tr '/' ' ' | cut -d ' ' -f 3"));
+        m.insert("ipv4_addr", r"ip -4 address show dev `lium_get_default_iface` scope global | grep inet | sed -E 's/\s+/ /g' | tr '/' ' ' | cut -d ' ' -f 3");
+        m.insert("ipv6_addrs", r"ip -6 address show dev `lium_get_default_iface` mngtmpaddr | grep inet6 | sed -E 's/\s+/ /g' | tr '/' ' ' | cut -d ' ' -f 3");
+        m.insert("mac", r"ip addr show dev `lium_get_default_iface` | grep ether | grep -E -o '([0-9a-z]{2}:){5}([0-9a-z]{2})' | head -n 1");
+        m.insert("release", r"cat /etc/lsb-release | grep CHROMEOS_RELEASE_DESCRIPTION | sed -e 's/CHROMEOS_RELEASE_DESCRIPTION=//'");
+        // "<release track>,<keys>" where keys is set if sshd and authorized keys (the testing
+        // keys on test images) are installed in the rootfs
+        m.insert("image_type", r"echo $(grep '^CHROMEOS_RELEASE_TRACK=' /etc/lsb-release | cut -d = -f 2),$(test -s /root/.ssh/authorized_keys && test -x /usr/sbin/sshd && echo keys)");
+        m.insert("dev_boot_usb", r"crossystem dev_boot_usb");
+        m.insert("dev_default_boot", r"crossystem dev_default_boot");
+        m.insert("fwid", r"crossystem fwid");
+        m.insert("ro_fwid", r"crossystem ro_fwid");
+        m.insert("uptime", r"cat /proc/uptime | cut -d ' ' -f 2");
+        // SoC details for triaging platform bugs. /proc/cpuinfo has "model name" and "microcode"
+        // only on x86, so ARM SoCs are identified via soc0 or the device tree instead.
+        m.insert("soc", r#"m=$(grep -m 1 '^model name' /proc/cpuinfo | cut -d : -f 2-); if [ -n "$m" ]; then echo $m; elif [ -r /sys/devices/soc0/machine ]; then cat /sys/devices/soc0/machine; else tr '\0' ' ' < /proc/device-tree/compatible; fi"#);
+        m.insert("microcode", r#"m=$(grep -m 1 '^microcode' /proc/cpuinfo | cut -d : -f 2); if [ -n "$m" ]; then echo $m; else cat /sys/devices/system/cpu/cpu0/regs/identification/revidr_el1 /sys/devices/system/cpu/cpu0/regs/identification/midr_el1 2>/dev/null | paste -sd ' '; fi"#);
+        m.insert("mitigations", r"grep . /sys/devices/system/cpu/vulnerabilities/* | sed 's|^/sys/devices/system/cpu/vulnerabilities/||'");
+        m.insert("crypto", r"grep -m 1 -E '^(flags|Features)' /proc/cpuinfo | cut -d : -f 2 | tr ' ' '\n' | grep -xE 'aes|vaes|pclmulqdq|vpclmulqdq|sha_ni|pmull|sha1|sha2|sha3|sha512|sm3|sm4|crc32' | sort -u | paste -sd ' '");
+        m.insert("ectool_temps_all", r"ectool temps all");
+        m.insert("tpm_version", r"f=$(tpm_manager_client get_version_info | grep -m 1 family | grep -o -E '0x[0-9a-f]+'); case $f in 0x322e3000) echo 2.0;; 0x312e3200) echo 1.2;; *) echo $f;; esac");
+        m.insert("tpm_owned", r"tpm_manager_client status --nonsensitive | grep -m 1 is_owned | grep -o -E 'true|false'");
+        m.insert("attestation_status", r"attestation_client status | grep -E 'prepared_for_enrollment|enrolled|verified_boot' | tr -d ' ' | paste -sd ' '");
+        m
+    };
+}
+
+/// Timeout of the command for each key in DutInfo::fetch_keys()
+const KEY_TIMEOUT_SECS: u64 = 10;
+const CMD_GET_DEFAULT_IFACE: &str =
+    r"ip route get 8.8.8.8 | sed -E 's/^.* dev ([^ ]+) .*$/\1/' | head -n 1";
+
+// Only keys that are always available can be listed here
+const DEFAULT_DUT_INFO_KEYS: [&str; 7] = [
+    "timestamp",
+    "dut_id",
+    "hwid",
+    "release",
+    "model",
+    "serial",
+    "board",
+];
+
+/// Kind of the OS image on a DUT
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImageType {
+    /// A test image, which has sshd with the testing keys, autotest and dev tools
+    Test,
+    /// A base (release or developer) image, with its release track (e.g. stable-channel)
+    Base(String),
+}
+impl ImageType {
+    /// Parses the value of the image_type attribute
+    pub fn parse(value: &str) -> Self {
+        let (track, keys) = value.trim().split_once(',').unwrap_or((value.trim(), ""));
+        if track.starts_with("testimage") && keys == "keys" {
+            ImageType::Test
+        } else {
+            ImageType::Base(track.to_string())
+        }
+    }
+}
+
+/// DutInfo holds information around a DUT
+#[derive(Debug, Clone)]
+pub struct DutInfo {
+    key: KeyInfo,
+    ssh: SshInfo,
+    info: HashMap<String, String>,
+}
+impl DutInfo {
+    /// Fetches the info of the DUT without updating SSH_CACHE
+    async fn fetch_from_ssh(ssh: &SshInfo, extra_attr: &[String]) -> Result<Self> {
+        let info = Self::fetch_keys_async(
+            ssh,
+            &[
+                DEFAULT_DUT_INFO_KEYS.to_vec(),
+                extra_attr.iter().map(|s| s.as_str()).collect(),
+            ]
+            .concat(),
+        )
+        .await?;
+        let key = KeyInfo::from_raw_dut_info(&info)
+            .await
+            .context("failed to get key")?;
+        Ok(DutInfo {
+            key,
+            ssh: ssh.clone(),
+            info,
+        })
+    }
+    /// Merges the DUT into SSH_CACHE
+    async fn save_to_cache(mut self) -> Result<Self> {
+        if dut_file::contains_address(&self.ssh.host_and_port()) {
+            return Ok(self);
+        }
+        if let Ok(Some(prev)) = SSH_CACHE.get(self.id()) {
+            // Keep the other known addresses of the DUT as fallbacks
+            self.ssh.merge_alternatives(&prev);
+        }
+        SSH_CACHE.set(self.id(), self.ssh.clone())?;
+        if Self::cached_keys(self.id(), &["mac"]).is_err() {
+            // Remember the MAC address to wake the DUT up later. Some DUTs have no ethernet.
+            let _ = Self::fetch_keys_async(&self.ssh, &["mac"]).await;
+        }
+        Ok(self)
+    }
+    async fn from_ssh(ssh: &SshInfo, extra_attr: &[String]) -> Result<Self> {
+        Self::fetch_from_ssh(ssh, extra_attr)
+            .await?
+            .save_to_cache()
+            .await
+    }
+    /// new should be fast enough (less than a sec per a DUT)
+    pub fn new(dut: &str) -> Result<Self> {
+        block_on(Self::new_async(dut))
+    }
+    /// new() for checking many DUTs concurrently on one thread
+    pub async fn new_async(dut: &str) -> Result<Self> {
+        let ssh = SshInfo::new(dut).context("failed to create SshInfo")?;
+        Self::from_ssh(&ssh, &Vec::new()).await
+    }
+    pub fn new_host_and_port(host: &str, port: u16) -> Result<Self> {
+        let ssh = SshInfo::new_host_and_port(host, port).context("failed to create SshInfo")?;
+        block_on(Self::from_ssh(&ssh, &Vec::new()))
+    }
+    pub fn id(&self) -> &str {
+        self.key.key()
+    }
+    pub fn ssh(&self) -> &SshInfo {
+        &self.ssh
+    }
+    pub fn info(&self) -> &HashMap<String, String> {
+        &self.info
+    }
+    /// Returns whether the DUT runs a test image or a base image
+    pub fn image_type(&self) -> Result<ImageType> {
+        let value = match self.info.get("image_type") {
+            Some(value) => value.clone(),
+            None => Self::fetch_keys(&self.ssh, &["image_type"])?
+                .remove("image_type")
+                .context("Failed to detect the image type")?,
+        };
+        Ok(ImageType::parse(&value))
+    }
+    /// Returns a script that runs the commands for the keys and prints a line
+    /// `<key>,<exit code>,<base64 stdout>,<base64 stderr>` for each of them. Each command runs in
+    /// its own process with a timeout, so that a failing or hanging one does not affect the
+    /// others. The commands are embedded in base64 to avoid problems around shell escapes.
+    fn gen_script_for_keys(cmds: &[(&str, &str)]) -> String {
+        let mut script = format!(
+            r##"lium_get_default_iface() {{ {CMD_GET_DEFAULT_IFACE} ; }}
+export -f lium_get_default_iface
+tmp="$(mktemp -d)"
+trap 'rm -rf "$tmp"' EXIT
+lium_run_key() {{
+  echo "$2" | base64 -d > "$tmp/cmd"
+  if command -v timeout >/dev/null; then
+    timeout {KEY_TIMEOUT_SECS} bash "$tmp/cmd" < /dev/null > "$tmp/stdout" 2> "$tmp/stderr"
+  else
+    bash "$tmp/cmd" < /dev/null > "$tmp/stdout" 2> "$tmp/stderr"
+  fi
+  code=$?
+  echo "$1,$code,$(base64 -w 0 < "$tmp/stdout"),$(base64 -w 0 < "$tmp/stderr")"
+}}
+"##
+        );
+        for (key, cmd) in cmds {
+            script += &format!("lium_run_key {key} {}\n", STANDARD.encode(cmd));
+        }
+        script
+    }
+    /// Parses the output of the script from gen_script_for_keys(). Keys without a line (e.g. the
+    /// script was killed) are errors of their own.
+    fn decode_results(result: &str, keys: &[&str]) -> HashMap<String, Result<String>> {
+        keys.iter()
+            .map(|key| {
+                let value = match result.lines().find(|l| l.split(',').next() == Some(*key)) {
+                    Some(line) => Self::decode_result_line(line, key),
+                    None => Err(anyhow!("key {key} was not found in the output")),
+                };
+                (key.to_string(), value)
+            })
+            .collect()
+    }
+    fn decode_result_line(s: &str, key: &str) -> Result<String> {
+        let s = s.split(',').collect::<Vec<&str>>();
+        if s.len() != 4 {
+            return Err(anyhow!("4 elements are expected in a row but got: {s:?}"));
+        }
+        if s[0] == key {
+            let key = s[0].to_string();
+            let exit_code = u8::from_str(s[1])?;
+            let value = String::from_utf8(STANDARD.decode(s[2])?)?
+                .trim()
+                .to_string();
+            let stderr = String::from_utf8(STANDARD.decode(s[3])?)?
+                .trim()
+                .to_string();
+            if exit_code == 124 {
+                Err(anyhow!("Command for key {key} timed out"))
+            } else if exit_code != 0 {
+                Err(anyhow!(
+                    "Command for key {key} exited with code {exit_code}. stderr: {stderr}"
+                ))
+            } else if value.is_empty() {
+                Err(anyhow!("key {key} found but was empty. stderr: {stderr}"))
+            } else {
+                Ok(value)
+            }
+        } else {
+            Err(anyhow!("key {key} was not found in the output"))
+        }
+    }
+    fn parse_values(
+        keys: &[&str],
+        mut values: HashMap<String, Result<String>>,
+    ) -> Result<HashMap<String, String>> {
+        // Construct values based on values
+        if keys.contains(&"timestamp") {
+            values.insert("timestamp".to_string(), Ok(Local::now().to_string()));
+        }
+        if keys.contains(&"model") {
+            if let Some(Ok(model)) = values.get("model_from_cros_config") {
+                values.insert("model".to_string(), Ok(model.clone()));
+            } else if let Some(Ok(model)) = values.get("model_from_mosys") {
+                values.insert("model".to_string(), Ok(model.clone()));
+            } else {
+                return Err(anyhow!("Failed to get model"));
+            }
+        }
+        if keys.contains(&"gbb_flags") {
+            let gbb_flags = if let Some(Ok(v)) = values.get("gbb_flags_from_futility") {
+                v
+            } else if let Some(Ok(v)) = values.get("gbb_flags_from_shell") {
+                v
+            } else {
+                return Err(anyhow!("Failed to get model"));
+            };
+            if RE_GBB_FLAGS.is_match(gbb_flags) {
+                values.insert("gbb_flags".to_string(), Ok(gbb_flags.clone()));
+            } else {
+                return Err(anyhow!(
+                    "Model should match regex RE_GBB_FLAGS but got {gbb_flags:?}"
+                ));
+            }
+        }
+        if keys.contains(&"dut_id") {
+            let model = values.get("model");
+            let serial = values.get("serial");
+            if let (Some(Ok(model)), Some(Ok(serial))) = (model, serial) {
+                let dut_id = format!("{model}_{serial}");
+                values.insert("dut_id".to_string(), Ok(dut_id));
+            } else {
+                return Err(anyhow!("model and serial is needed for dut_id but got: (model: {model:?}, serial: {serial:?})"));
+            }
+        }
+        // Collect all values for given keys
+        keys.iter()
+            .map(|&k| {
+                let v = values.get(k);
+                if let Some(Ok(v)) = v {
+                    Ok((k.to_string(), v.clone()))
+                } else {
+                    Err(anyhow!("failed to get key {k}: {v:?}"))
+                }
+            })
+            .collect()
+    }
+    /// Returns the keys to be retrieved from the DUT and the command to print them line by line
+    fn gen_cmd_for_keys<'a>(keys: &[&'a str]) -> Result<(Vec<&'a str>, String)> {
+        ensure_testing_rsa_is_there()?;
+        // First, list up all the keys to retrieve from a DUT
+        let mut keys_from_dut = HashSet::new();
+        // Dependent variables
+        for k in keys {
+            match *k {
+                "timestamp" => continue,
+                "gbb_flags" => {
+                    keys_from_dut.insert("gbb_flags_from_futility");
+                    keys_from_dut.insert("gbb_flags_from_shell");
+                }
+                "dut_id" => {
+                    keys_from_dut.insert("ipv6_addr");
+                    keys_from_dut.insert("serial");
+                }
+                "model" => {
+                    keys_from_dut.insert("model_from_cros_config");
+                    keys_from_dut.insert("model_from_mosys");
+                }
+                k => {
+                    keys_from_dut.insert(k);
+                }
+            }
+        }
+        let keys_from_dut: Vec<&str> = keys_from_dut.into_iter().collect();
+        let cmds = keys_from_dut
+            .iter()
+            .map(|k| {
+                DUT_ATTRIBUTE_CMDS
+                    .get(k)
+                    .map(|cmd| (*k, *cmd))
+                    .context(anyhow!("Unknown DUT attribute: {k}"))
+            })
+            .collect::<Result<Vec<(&str, &str)>>>()?;
+        let cmds = Self::gen_script_for_keys(&cmds);
+        Ok((keys_from_dut, cmds))
+    }
+    pub fn fetch_keys(ssh: &SshInfo, keys: &[&str]) -> Result<HashMap<String, String>> {
+        let (keys_from_dut, cmds) = Self::gen_cmd_for_keys(keys)?;
+        status!("Fetching info for {:?}...", ssh);
+        // Queries in the same process share the channel of the shim
+        let result = shim::run_cmd_stdio(ssh, &cmds)?;
+        Self::save_fetched_values(ssh, keys, &keys_from_dut, &result)
+    }
+    /// fetch_keys() that does not block the thread while waiting for the DUT
+    pub async fn fetch_keys_async(ssh: &SshInfo, keys: &[&str]) -> Result<HashMap<String, String>> {
+        let (keys_from_dut, cmds) = Self::gen_cmd_for_keys(keys)?;
+        status!("Fetching info for {:?}...", ssh);
+        let result = ssh.run_cmd_stdio_async(&cmds).await?;
+        Self::save_fetched_values(ssh, keys, &keys_from_dut, &result)
+    }
+    /// Parses the output of the command from gen_cmd_for_keys() and caches the values
+    fn save_fetched_values(
+        ssh: &SshInfo,
+        keys: &[&str],
+        keys_from_dut: &[&str],
+        result: &str,
+    ) -> Result<HashMap<String, String>> {
+        let values = Self::decode_results(result, keys_from_dut);
+        let values = Self::parse_values(keys, values)?;
+        let id = values
+            .get("dut_id")
+            .cloned()
+            .or_else(|| Self::find_cached_id(ssh));
+        if let Some(id) = id {
+            let fetched_at = Local::now().timestamp();
+            let mut cached = DUT_INFO_CACHE.get(&id)?.unwrap_or_default();
+            cached.extend(values.iter().map(|(k, v)| {
+                (
+                    k.clone(),
+                    CachedValue {
+                        value: v.clone(),
+                        fetched_at,
+                    },
+                )
+            }));
+            DUT_INFO_CACHE.set(&id, cached)?;
+        }
+        Ok(values)
+    }
+    /// Returns the ID of a DUT in SSH_CACHE which has the same address
+    pub fn find_cached_id(ssh: &SshInfo) -> Option<String> {
+        SSH_CACHE
+            .entries()
+            .ok()?
+            .into_iter()
+            .find(|(_, e)| e.host_and_port() == ssh.host_and_port())
+            .map(|(id, _)| id)
+    }
+    /// Returns the last known values of a DUT without accessing it
+    pub fn cached_info(dut: &str) -> Result<HashMap<String, CachedValue>> {
+        if let Some(info) = DUT_INFO_CACHE.get(dut)? {
+            return Ok(info);
+        }
+        let ssh = SshInfo::new(dut)?;
+        let id = Self::find_cached_id(&ssh).context(anyhow!("{dut} is not a known DUT"))?;
+        DUT_INFO_CACHE.get(&id)?.context(anyhow!(
+            "No cached info for {id}. Please run `lium dut info` online first"
+        ))
+    }
+    /// Returns the last known values for the keys, and the age of the oldest one
+    pub fn cached_keys(dut: &str, keys: &[&str]) -> Result<(HashMap<String, String>, Duration)> {
+        let cached = Self::cached_info(dut)?;
+        let mut oldest = Duration::ZERO;
+        let values = keys
+            .iter()
+            .map(|k| {
+                let v = cached
+                    .get(*k)
+                    .context(anyhow!("{k} is not cached for {dut}"))?;
+                oldest = oldest.max(v.age());
+                Ok((k.to_string(), v.value.clone()))
+            })
+            .collect::<Result<HashMap<String, String>>>()?;
+        Ok((values, oldest))
+    }
+    /// Fetches the keys in a detached lium process to update DUT_INFO_CACHE for later use
+    pub fn refresh_in_background(dut: &str, keys: &[&str]) -> Result<()> {
+        spawn_lium_in_background(&[&["dut", "info", "--dut", dut], keys].concat())
+    }
+}
+
+/// SshDiagnosis holds a result of one step of ssh connection diagnostics
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshDiagnosis {
+    pub step: String,
+    pub ok: bool,
+    pub detail: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub suggestion: Option<String>,
+}
+impl SshDiagnosis {
+    fn ok(step: &str, detail: &str) -> Self {
+        Self {
+            step: step.to_string(),
+            ok: true,
+            detail: detail.to_string(),
+            suggestion: None,
+        }
+    }
+    fn fail(step: &str, detail: &str, suggestion: &str) -> Self {
+        Self {
+            step: step.to_string(),
+            ok: false,
+            detail: detail.to_string(),
+            suggestion: Some(suggestion.to_string()),
+        }
+    }
+}
+
+/// SshInfo holds information needed to establish an ssh connection
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SshInfo {
+    /// An IPv4 address, an IPv6 address or DNS name.
+    /// IPv6 address MUST NOT not have brackets.
+    host: String,
+    port: u16,
+    /// A DUT to connect through (for "outer>inner" identifiers)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    jump: Option<Box<SshInfo>>,
+    /// Other addresses of the same DUT (e.g. a DNS name, a lab IP or a link-local address),
+    /// tried in this order if the primary one is not reachable
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    alternatives: Vec<SshAddress>,
+}
+
+/// A candidate address of a DUT with its recent health
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SshAddress {
+    pub host: String,
+    pub port: u16,
+    /// consecutive failures to connect, used to try healthier addresses first
+    #[serde(default)]
+    pub failures: u32,
+}
+
+/// Quotes a string for POSIX shells
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+impl SshInfo {
+    /// Measure the time to establish a TCP connection to the sshd of the DUT.
+    pub fn probe_tcp_rtt(&self, timeout: Duration) -> Result<Duration> {
+        if let Some(jump) = &self.jump {
+            // The inner DUT is not reachable directly, so approximate with the first hop
+            return jump.probe_tcp_rtt(timeout);
+        }
+        let addr = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .context("Failed to resolve the DUT address")?
+            .next()
+            .context("No address found for the DUT")?;
+        let start = Instant::now();
+        TcpStream::connect_timeout(&addr, timeout).context("Failed to connect")?;
+        Ok(start.elapsed())
+    }
+    /// probe_tcp_rtt() that does not block the thread
+    pub async fn probe_tcp_rtt_async(&self, timeout: Duration) -> Result<Duration> {
+        // The inner DUT is not reachable directly, so approximate with the first hop
+        let mut first_hop = self;
+        while let Some(jump) = &first_hop.jump {
+            first_hop = jump;
+        }
+        let addr = (first_hop.host.as_str(), first_hop.port)
+            .to_socket_addrs()
+            .context("Failed to resolve the DUT address")?
+            .next()
+            .context("No address found for the DUT")?;
+        let start = Instant::now();
+        with_timeout(timeout, async {
+            Async::<TcpStream>::connect(addr)
+                .await
+                .context("Failed to connect")
+        })
+        .await?;
+        Ok(start.elapsed())
+    }
+    /// Check the ssh connection step by step and explain what is wrong.
+    /// Steps after a failed step are skipped since they will fail as well.
+    pub fn diagnose(&self) -> Vec<SshDiagnosis> {
+        let mut results = Vec::new();
+        let host = &self.host;
+        let port = self.port;
+
+        // 1. Name resolution
+        let addr = match (host.as_str(), port).to_socket_addrs() {
+            Ok(mut addrs) => addrs.next(),
+            Err(e) => {
+                results.push(SshDiagnosis::fail(
+                    "resolve",
+                    &format!("{host}: {e}"),
+                    "Check the host name, or use an IP address instead",
+                ));
+                return results;
+            }
+        };
+        let addr = if let Some(addr) = addr {
+            results.push(SshDiagnosis::ok("resolve", &format!("{host} -> {addr}")));
+            addr
+        } else {
+            results.push(SshDiagnosis::fail(
+                "resolve",
+                &format!("{host} resolved to no addresses"),
+                "Check the host name, or use an IP address instead",
+            ));
+            return results;
+        };
+
+        // 2. TCP reachability and 3. sshd banner
+        let stream = match TcpStream::connect_timeout(&addr, Duration::from_secs(5)) {
+            Ok(stream) => {
+                results.push(SshDiagnosis::ok("tcp", &format!("connected to {addr}")));
+                stream
+            }
+            Err(e) => {
+                let suggestion = match e.kind() {
+                    std::io::ErrorKind::ConnectionRefused => {
+                        "The host is up but sshd is not listening on the port. Check the port number or if the DUT is booted into the OS"
+                    }
+                    _ => "Check if the DUT is powered on and on the same network (try `ping`), or if a VPN / port forwarding is needed",
+                };
+                results.push(SshDiagnosis::fail(
+                    "tcp",
+                    &format!("{addr}: {e}"),
+                    suggestion,
+                ));
+                return results;
+            }
+        };
+        drop(stream.set_read_timeout(Some(Duration::from_secs(5))));
+        let mut banner = String::new();
+        match BufReader::new(stream).read_line(&mut banner) {
+            Ok(_) if banner.starts_with("SSH-") => {
+                results.push(SshDiagnosis::ok("banner", banner.trim()));
+            }
+            Ok(_) => {
+                results.push(SshDiagnosis::fail(
+                    "banner",
+                    &format!("unexpected banner: {:?}", banner.trim()),
+                    "Something other than sshd is listening on the port. Check the port number",
+                ));
+                return results;
+            }
+            Err(e) => {
+                results.push(SshDiagnosis::fail(
+                    "banner",
+                    &format!("no banner received: {e}"),
+                    "sshd may be hung or a firewall is dropping the connection",
+                ));
+                return results;
+            }
+        }
+
+        // 4. Local testing key
+        if let Err(e) = ensure_testing_rsa_is_there() {
+            results.push(SshDiagnosis::fail(
+                "testing_rsa",
+                &format!("{e:#}"),
+                "Place the CrOS testing_rsa at ~/.ssh/testing_rsa with mode 0600",
+            ));
+            return results;
+        }
+        results.push(SshDiagnosis::ok(
+            "testing_rsa",
+            "~/.ssh/testing_rsa is there",
+        ));
+
+        // 4'. Credentials for hosts that do not take testing_rsa
+        let auths = Config::read().and_then(|config| self.ssh_auth(&config));
+        for (pattern, auth) in auths.unwrap_or_default() {
+            match auth.preflight() {
+                Ok(()) => results.push(SshDiagnosis::ok(
+                    "ssh_auth",
+                    &format!("credentials for {pattern} are ready"),
+                )),
+                Err(e) => {
+                    results.push(SshDiagnosis::fail(
+                        "ssh_auth",
+                        &format!("{e:#}"),
+                        "Refresh the credentials, or check ssh_auth in `lium config show`",
+                    ));
+                    return results;
+                }
+            }
+        }
+
+        // 5. Key auth with testing_rsa
+        let output = self
+            .ssh_cmd(Some(&["-v"]))
+            .and_then(|mut cmd| cmd.arg("true").output().context("Failed to run ssh"));
+        match output {
+            Ok(output) if output.status.success() => {
+                results.push(SshDiagnosis::ok(
+                    "auth",
+                    "logged in as root with testing_rsa",
+                ));
+            }
+            Ok(output) => {
+                let stderr = get_stderr(&output);
+                let last_line = stderr.lines().last().unwrap_or_default().to_string();
+                let suggestion = if stderr.contains("Permission denied") {
+                    "The DUT does not accept the testing key. Is it running a test image? If not, flash a test image or install the key with `lium dut authorize`"
+                } else if stderr.contains("Host key verification failed") {
+                    "Check ssh_overrides in `lium config show`"
+                } else {
+                    "Run `ssh -v` manually to see the details"
+                };
+                results.push(SshDiagnosis::fail("auth", &last_line, suggestion));
+            }
+            Err(e) => {
+                results.push(SshDiagnosis::fail(
+                    "auth",
+                    &format!("{e:#}"),
+                    "Check if ssh is installed on this machine",
+                ));
+            }
+        }
+        results
+    }
+    pub fn ping(&self) -> Result<()> {
+        let host = &self.host;
+        if let Some(jump) = &self.jump {
+            return jump
+                .run_cmd_stdio(&format!("ping -c 1 -W 1 {host} 1>/dev/null 2>&1"))
+                .map(|_| ())
+                .context("Failed to ping");
+        }
+        let output = run_bash_command(&format!("ping -c 1 -W 0.5 {host} 1>/dev/null 2>&1"), None)?;
+        output.status.exit_ok().context("Failed to ping")
+    }
+    /// Picks a reachable address among the candidates, trying healthier ones first.
+    /// The reachable one becomes the primary address and the order is saved in SSH_CACHE
+    /// under `id`, so that the working path is tried first next time.
+    pub fn connect(&self, id: &str) -> Self {
+        if self.alternatives.is_empty() || self.jump.is_some() {
+            return self.clone();
+        }
+        let mut candidates = vec![SshAddress {
+            host: self.host.clone(),
+            port: self.port,
+            failures: 0,
+        }];
+        candidates.extend(self.alternatives.iter().cloned());
+        // Stable, so the primary one is tried first among the healthy ones
+        candidates.sort_by_key(|c| c.failures);
+        let timeout = Duration::from_secs(1);
+        let Some(i) = candidates.iter_mut().position(|c| {
+            let reachable = Self::new_host_and_port(&c.host, c.port)
+                .and_then(|ssh| ssh.probe_tcp_rtt(timeout))
+                .is_ok();
+            if !reachable {
+                c.failures = c.failures.saturating_add(1);
+            }
+            reachable
+        }) else {
+            // The DUT may be just offline. Keep the cache as is.
+            return self.clone();
+        };
+        let chosen = candidates.remove(i);
+        let mut ssh = self.clone();
+        ssh.host = chosen.host;
+        ssh.port = chosen.port;
+        ssh.alternatives = candidates;
+        if ssh.host != self.host || ssh.port != self.port {
+            status!(
+                "{} is not reachable. Using {} instead.",
+                self.host_and_port(),
+                ssh.host_and_port()
+            );
+        }
+        let changed =
+            ssh.host != self.host || ssh.port != self.port || ssh.alternatives != self.alternatives;
+        if changed && !dut_file::contains(id) {
+            if let Err(e) = SSH_CACHE.set(id, ssh.clone()) {
+                warning!("Failed to update SSH_CACHE for {id}: {e:#}");
+            }
+        }
+        ssh
+    }
+    /// Returns all the candidate addresses, the primary one first
+    pub fn candidates(&self) -> Vec<SshAddress> {
+        let mut result = vec![SshAddress {
+            host: self.host.clone(),
+            port: self.port,
+            failures: 0,
+        }];
+        result.extend(self.alternatives.iter().cloned());
+        result
+    }
+    /// Adds the addresses of `other` (e.g. the previous entry in SSH_CACHE) as alternatives
+    pub fn merge_alternatives(&mut self, other: &SshInfo) {
+        if self.jump.is_some() || other.jump.is_some() {
+            return;
+        }
+        for c in other.candidates() {
+            if !self
+                .candidates()
+                .iter()
+                .any(|e| e.host == c.host && e.port == c.port)
+            {
+                self.alternatives.push(c);
+            }
+        }
+    }
+    /// Adds an alternative address unless it is already a candidate
+    pub fn add_alternative(&mut self, host: &str, port: u16) {
+        if self
+            .candidates()
+            .iter()
+            .any(|c| c.host == host && c.port == port)
+        {
+            return;
+        }
+        self.alternatives.push(SshAddress {
+            host: host.to_string(),
+            port,
+            failures: 0,
+        });
+    }
+    /// Removes an alternative address. Returns false if it was not there.
+    pub fn remove_alternative(&mut self, host: &str, port: u16) -> bool {
+        let len = self.alternatives.len();
+        self.alternatives
+            .retain(|c| !(c.host == host && c.port == port));
+        len != self.alternatives.len()
+    }
+    pub fn new(dut: &str) -> Result<Self> {
+        if let Some(listed) = dut_file::lookup(dut)? {
+            return Ok(listed.connect(dut));
+        }
+        if let Ok(Some(resolved)) = SSH_CACHE.get(dut) {
+            return Ok(resolved.connect(dut));
+        }
+        if let Some((outer, inner)) = dut.rsplit_once('>') {
+            // e.g. "gateway>192.168.0.2" for a DUT only reachable from the gateway DUT.
+            // Hops can be chained like "a>b>c".
+            dut_id::parse(dut)?;
+            let mut ssh = Self::new(inner.trim())?;
+            ssh.jump = Some(Box::new(Self::new(outer.trim())?));
+            return Ok(ssh);
+        }
+        match dut_id::parse_hop(dut)? {
+            DutIdentifier::Address { host, port } => {
+                Self::new_host_and_port(&host, port.unwrap_or(22))
+            }
+            DutIdentifier::Cached(id) => {
+                let ids = SSH_CACHE.entries().unwrap_or_default();
+                let hint = match dut_id::suggest(&id, ids.keys().map(|s| s.as_str())) {
+                    Some(s) => format!("Did you mean {s:?}? "),
+                    None => String::new(),
+                };
+                Err(anyhow!(
+                    "DUT {id} is not cached yet. {hint}Please run `lium dut info ${{DUT_IP}}` first to add it."
+                ))
+            }
+        }
+    }
+    pub fn new_host_and_port(host: &str, port: u16) -> Result<Self> {
+        let host = if let Some(c) = RE_IPV6_WITH_BRACKETS.captures(host) {
+            c.name("addr").context("no addr matched")?.as_str()
+        } else {
+            host
+        };
+        if !RE_DUT_HOST_NAME.is_match(host) {
+            Err(anyhow!(
+                "Invalid hostname {:?}. A host name should match with: {:?}",
+                host,
+                RE_DUT_HOST_NAME.to_string()
+            ))
+        } else {
+            Ok(Self {
+                host: host.to_string(),
+                port,
+                jump: None,
+                alternatives: Vec::new(),
+            })
+        }
+    }
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    pub fn needs_port_forwarding_in_chroot(&self) -> bool {
+        (self.host != "localhost" && self.host != "127.0.0.1") || self.jump.is_some()
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    pub fn host_and_port(&self) -> String {
+        let port = self.port;
+        let host = &self.host;
+        // If the address has ':', it must be enclosed in square brackets.
+        if host.find(':').is_some() {
+            format!("[{host}]:{port}")
+        } else {
+            format!("{host}:{port}")
+        }
+    }
+
+    fn gen_ssh_options(&self) -> Result<Vec<String>> {
+        ensure_online(&format!("Connecting to {}", self.host_and_port()))?;
+        let mut args: Vec<String> = Vec::from(COMMON_SSH_OPTIONS)
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        let host = &self.host;
+        let config = Config::read()?;
+        self.run_pre_connect_steps(&config)?;
+        for (k, v) in config.ssh_overrides() {
+            if !Regex::new(k)
+                .context("Failed to compile regex for ssh overrides")?
+                .is_match(host)
+                || !v.is_match_condition()?
+            {
+                continue;
+            }
+            args.extend(v.ssh_options().iter().map(|e| e.to_owned()));
+        }
+        // Before the overrides in the config since ssh uses the first value of each option
+        args.extend(dut_file::ssh_options_for(&self.host_and_port()));
+        for (pattern, auth) in self.ssh_auth(&config)? {
+            if !SSH_AUTH_CHECKED.lock().unwrap().contains(&pattern) {
+                auth.preflight()
+                    .context(anyhow!("Pre-flight check of ssh_auth for {host} failed"))?;
+                SSH_AUTH_CHECKED.lock().unwrap().insert(pattern);
+            }
+            args.extend(auth.ssh_options());
+        }
+        if let Some(options) = statusd::control_path_options() {
+            args.extend(options);
+        }
+        if let Some(jump) = &self.jump {
+            // Use ProxyCommand instead of ProxyJump to apply the same options to the jump host
+            let mut jump_args = jump.gen_ssh_args(Some(&["-W", &self.host_and_port()]))?;
+            // Drop the trailing "--"
+            jump_args.pop();
+            // '%' is a token in ProxyCommand (e.g. in IPv6 zone IDs or nested ProxyCommands)
+            let jump_args: Vec<String> = jump_args
+                .iter()
+                .map(|a| shell_quote(&a.replace('%', "%%")))
+                .collect();
+            args.extend_from_slice(&[
+                "-o".to_string(),
+                format!("ProxyCommand=ssh {}", jump_args.join(" ")),
+            ]);
+        }
+        Ok(args)
+    }
+    /// Returns the ssh_auth entries in the config that match the host, with their patterns
+    fn ssh_auth(&self, config: &Config) -> Result<Vec<(String, SshAuth)>> {
+        let mut auths = Vec::new();
+        for (pattern, auth) in config.ssh_auth() {
+            if Regex::new(pattern)
+                .context("Failed to compile regex for ssh_auth")?
+                .is_match(&self.host)
+            {
+                auths.push((pattern.clone(), auth.clone()));
+            }
+        }
+        auths.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(auths)
+    }
+    /// Returns true if the DUT is on a directly connected network (no router in between),
+    /// where WoL broadcasts can reach it
+    pub fn is_on_local_segment(&self) -> bool {
+        if self.jump.is_some() {
+            return false;
+        }
+        let Some(addr) = (self.host.as_str(), self.port)
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut a| a.next())
+        else {
+            return false;
+        };
+        match addr.ip() {
+            IpAddr::V6(ip) if ip.segments()[0] & 0xffc0 == 0xfe80 => true,
+            ip => run_bash_command(&format!("ip route get {ip}"), None)
+                .map(|o| o.status.success() && !get_stdout(&o).contains(" via "))
+                .unwrap_or(false),
+        }
+    }
+    /// Sends a WoL magic packet to the MAC address cached by `lium dut info`
+    pub fn send_wake_packet(&self) -> Result<()> {
+        let id = DutInfo::find_cached_id(self)
+            .context(anyhow!("{} is not a known DUT", self.host_and_port()))?;
+        let (values, _) = DutInfo::cached_keys(&id, &["mac"]).context(anyhow!(
+            "MAC address of {id} is not known. Please run `lium dut info --dut {id} mac` while it is online"
+        ))?;
+        let mac = &values["mac"];
+        let mac = MacAddr6::from_str(mac).context(anyhow!("Invalid MAC address: {mac}"))?;
+        status!("Sending a WoL packet to {id} ({mac})...");
+        send_wol(&mac, "255.255.255.255")
+    }
+    /// Wakes the DUT up if sshd is not reachable, and waits for it up to `timeout`
+    pub fn wake_and_wait(&self, timeout: Duration) -> Result<()> {
+        let probe_timeout = Duration::from_secs(1);
+        if self.probe_tcp_rtt(probe_timeout).is_ok() {
+            return Ok(());
+        }
+        self.send_wake_packet()?;
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if self.probe_tcp_rtt(probe_timeout).is_ok() {
+                return Ok(());
+            }
+            cancel::sleep(Duration::from_secs(2))?;
+        }
+        Err(anyhow!(
+            "{} did not wake up in {timeout:?}",
+            self.host_and_port()
+        ))
+    }
+    /// Runs the steps of the connection profile for this DUT (`lium config set
+    /// connection_profile_for ...`) once per process
+    fn run_pre_connect_steps(&self, config: &Config) -> Result<()> {
+        let addr = self.host_and_port();
+        let mut names = vec![self.host.clone(), addr.clone()];
+        names.extend(DutInfo::find_cached_id(self));
+        let steps = config.pre_connect_steps(&names)?;
+        if steps.is_empty() || !PRE_CONNECT_DONE.lock().unwrap().insert(addr.clone()) {
+            return Ok(());
+        }
+        status!("Running pre-connect steps for {addr}...");
+        run_pre_connect_steps(&steps, Duration::from_secs(1))
+    }
+    /// Returns the identifier to reach this DUT, including the jump hosts (e.g. "a:22>b:22")
+    pub fn route(&self) -> String {
+        match &self.jump {
+            Some(jump) => format!("{}>{}", jump.route(), self.host_and_port()),
+            None => self.host_and_port(),
+        }
+    }
+
+    fn gen_ssh_args(&self, optional_args: Option<&[&str]>) -> Result<Vec<String>> {
+        let mut args = self.gen_ssh_options()?;
+
+        let host = &self.host.replace(['[', ']'], "");
+        let port = self.port;
+        let user = "root";
+        let user_at_host = format!("{user}@{host}");
+        let port = port.to_string();
+        args.extend_from_slice(&["-p".to_string(), port]);
+        if let Some(optional_args) = optional_args {
+            args.extend(optional_args.iter().map(|s| s.to_string()));
+        }
+        args.extend_from_slice(&[user_at_host, "--".to_string()]);
+        Ok(args)
+    }
+
+    fn gen_scp_get_args(&self, files: &[String], dest: Option<&String>) -> Result<Vec<String>> {
+        let mut args = self.gen_ssh_options()?;
+
+        let port = self.port.to_string();
+        args.extend_from_slice(&["-P".to_string(), port]);
+
+        // Recursive by default
+        args.push("-r".to_string());
+
+        let host = &self.host.replace(['[', ']'], "");
+        let user = "root";
+        let prefix = if host.find(':').is_some() {
+            format!("{user}@[{host}]")
+        } else {
+            format!("{user}@{host}")
+        };
+
+        let mut args: Vec<String> = args.iter().map(|s| s.into()).collect();
+        for src in files.iter().map(|p| format!("{prefix}:{p}")) {
+            args.push(src);
+        }
+
+        let destdir = if let Some(d) = dest { d } else { "." };
+        args.push(destdir.to_string());
+
+        Ok(args)
+    }
+    fn gen_scp_send_args(&self, files: &[String], dest: Option<&String>) -> Result<Vec<String>> {
+        let mut args = self.gen_ssh_options()?;
+
+        args.extend_from_slice(&["-P".to_string(), self.port.to_string()]);
+
+        // Recursive by default
+        args.push("-r".to_string());
+
+        let host = &self.host.replace(['[', ']'], "");
+        let user = "root";
+        let prefix = if host.find(':').is_some() {
+            format!("{user}@[{host}]")
+        } else {
+            format!("{user}@{host}")
+        };
+
+        let mut args: Vec<String> = args.iter().map(|s| s.into()).collect();
+        args.append(files.to_owned().as_mut());
+
+        let destdir = if let Some(d) = dest { d } else { "~/" };
+        args.push(format!("{prefix}:{destdir}"));
+
+        Ok(args)
+    }
+    pub fn scp_get_cmd(&self, files: &[String], dest: Option<&String>) -> Result<Command> {
+        let mut cmd = Command::new("scp");
+        cmd.args(self.gen_scp_get_args(files, dest)?);
+        Ok(cmd)
+    }
+    pub fn scp_send_cmd(&self, files: &[String], dest: Option<&String>) -> Result<Command> {
+        let mut cmd = Command::new("scp");
+        cmd.args(self.gen_scp_send_args(files, dest)?);
+        Ok(cmd)
+    }
+
+    /// Returns a ssh command that authenticates with a password instead of testing_rsa.
+    /// If password is None, ssh will prompt the password interactively.
+    pub fn ssh_cmd_with_password_auth(&self, password: Option<&str>) -> Result<Command> {
+        let mut args = self.gen_ssh_args(Some(&[
+            "-o",
+            "PreferredAuthentications=password,keyboard-interactive",
+        ]))?;
+        // ssh uses the first value for each option, so remove the ones that prevent password auth
+        for opt in ["BatchMode=yes", "PreferredAuthentications=publickey"] {
+            if let Some(i) = args.iter().position(|a| a == opt) {
+                args.drain(i - 1..=i);
+            }
+        }
+        let mut cmd = if let Some(password) = password {
+            let mut cmd = Command::new("sshpass");
+            cmd.args(["-p", password, "ssh"]);
+            cmd
+        } else {
+            Command::new("ssh")
+        };
+        cmd.args(args);
+        Ok(cmd)
+    }
+    /// Append public keys to authorized_keys of root on the DUT via password auth.
+    pub fn install_authorized_keys(&self, keys: &[String], password: Option<&str>) -> Result<()> {
+        let mut script = "mkdir -p ~/.ssh && chmod 700 ~/.ssh && touch ~/.ssh/authorized_keys && chmod 600 ~/.ssh/authorized_keys".to_string();
+        for key in keys {
+            let key = key.trim();
+            if key.contains('\'') || key.contains('\n') {
+                return Err(anyhow!("Invalid public key: {key:?}"));
+            }
+            script += &format!(
+                " && (grep -qxF '{key}' ~/.ssh/authorized_keys || echo '{key}' >> ~/.ssh/authorized_keys)"
+            );
+        }
+        let mut cmd = self.ssh_cmd_with_password_auth(password)?;
+        let status = cmd
+            .arg(script)
+            .status()
+            .context(anyhow!("Failed to run {cmd:?}"))?;
+        status.exit_ok().context(anyhow!(
+            "Failed to install keys to authorized_keys with password auth"
+        ))
+    }
+    pub fn ssh_cmd(&self, additional_ssh_args: Option<&[&str]>) -> Result<Command> {
+        let mut cmd = Command::new("ssh");
+        cmd.args(self.gen_ssh_args(additional_ssh_args)?);
+        Ok(cmd)
+    }
+    pub fn ssh_cmd_async(
+        &self,
+        additional_ssh_args: Option<&[&str]>,
+    ) -> Result<async_process::Command> {
+        let mut cmd = async_process::Command::new("ssh");
+        cmd.args(self.gen_ssh_args(additional_ssh_args)?);
+        Ok(cmd)
+    }
+    /// run_cmd_piped will execute the given cmd on a remote machine.
+    /// stdio will be pass-throughed to lium's stdio.
+    pub fn run_cmd_piped<T: AsRef<str> + AsRef<OsStr> + std::fmt::Debug>(
+        &self,
+        arg: &[T],
+    ) -> Result<()> {
+        let mut ssh = self.ssh_cmd(None)?;
+        ssh.args(arg);
+        let cmd = ssh.spawn()?;
+        let result = cmd.wait_with_output()?;
+        result.status.exit_ok().context(anyhow!(
+            "run_cmd_piped failed with {:?}. cmd = {:?}",
+            result.status.code(),
+            arg
+        ))
+    }
+    /// Runs a privileged command (e.g. a firmware write) with stdio passed through. On DUTs with
+    /// a `dut_escalation` policy, an elevated credential is obtained first and passed to the
+    /// prefix command via stdin.
+    pub fn run_privileged_cmd_piped(&self, action: &str, cmd: &str) -> Result<()> {
+        let Some(policy) = Config::read()?.dut_escalation(&self.host)? else {
+            return self.run_cmd_piped(&[cmd]);
+        };
+        let route = self.route();
+        let cred = escalation::credential_for(policy.broker().as_ref(), &route, action)?;
+        let mut child = self
+            .ssh_cmd(None)?
+            .arg(format!("{} sh -c {}", policy.prefix(), shell_quote(cmd)))
+            .stdin(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            writeln!(stdin, "{}", cred.token)?;
+        }
+        let status = child.wait()?;
+        if !status.success() {
+            // The credential may have been revoked, so get a new one next time
+            escalation::forget_credential(&route)?;
+        }
+        status.exit_ok().context(anyhow!(
+            "Failed to {action} with elevated credentials. cmd = {cmd:?}"
+        ))
+    }
+    fn run_cmd_captured(&self, cmd: &str) -> Result<Output> {
+        let mut ssh = self.ssh_cmd(None)?;
+        ssh.arg(cmd).stdout(Stdio::piped()).stderr(Stdio::piped());
+        let cmd = ssh.spawn()?;
+        let output = cmd
+            .wait_with_output()
+            .context("wait_with_output failed in run_cmd_captured")?;
+        if output.status.success() {
+            Ok(output)
+        } else {
+            let stdout = get_stdout(&output);
+            let stderr = get_stderr(&output);
+            Err(anyhow!("run_cmd_captured failed: {} {}", stdout, stderr))
+        }
+    }
+    /// Returns a Host entry for ssh_config(5) that connects to this DUT as `name`
+    pub fn ssh_config_entry(&self, name: &str) -> Result<String> {
+        let mut entry = format!(
+            "Host {name}\n  HostName {}\n  Port {}\n  User root\n",
+            self.host.replace(['[', ']'], ""),
+            self.port
+        );
+        let options = self.gen_ssh_options()?;
+        let mut it = options.iter();
+        while let Some(opt) = it.next() {
+            match (opt.as_str(), it.next()) {
+                ("-o", Some(kv)) => {
+                    if let Some((k, v)) = kv.split_once('=') {
+                        entry += &format!("  {k} {v}\n");
+                    }
+                }
+                ("-i", Some(path)) => entry += &format!("  IdentityFile {path}\n"),
+                ("-J", Some(jump)) => entry += &format!("  ProxyJump {jump}\n"),
+                // -F is meaningless in a config file
+                ("-F", Some(_)) => {}
+                (opt, arg) => entry += &format!("  # unsupported option: {opt} {arg:?}\n"),
+            }
+        }
+        Ok(entry)
+    }
+    pub fn open_ssh(&self) -> Result<()> {
+        let cmd = self.ssh_cmd(None)?.spawn()?;
+        let exit_status = cmd.wait_with_output()?.status;
+        // stdout and stderr is not captured so printing them here is useless
+        exit_status.exit_ok().or(Err(anyhow!(
+            "Failed to establish ssh connection. code = {:?}",
+            exit_status.code()
+        )))
+    }
+
+    pub fn start_port_forwarding(
+        &self,
+        port: u16,
+        dut_port: u16,
+        command: &str,
+    ) -> Result<async_process::Child> {
+        let child = self
+            .ssh_cmd_async(Some(&[
+                // Forwardings via a master connection of statusd outlive this process
+                "-S",
+                "none",
+                "-L",
+                &format!("{}:127.0.0.1:{}", port, dut_port),
+                "-o",
+                "ExitOnForwardFailure yes",
+                "-o",
+                "ServerAliveInterval=5",
+                "-o",
+                "ServerAliveCountMax=1",
+            ]))?
+            .arg(command)
+            .kill_on_drop(true)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        Ok(child)
+    }
+    /// Forwards `dut_port` to a free local port and returns the port once it is ready. The
+    /// forwarding is stopped when the child is dropped.
+    pub fn start_local_forwarding(&self, dut_port: u16) -> Result<(async_process::Child, u16)> {
+        // Let the kernel pick a free port. It can be taken by someone else before ssh listens on
+        // it, but then ssh fails thanks to ExitOnForwardFailure.
+        let port = TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let mut child = self.start_port_forwarding(port, dut_port, "sleep 8h")?;
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            if let Some(status) = child.try_status()? {
+                return Err(anyhow!(
+                    "Failed to forward the port: ssh exited with {status}"
+                ));
+            }
+            if Instant::now() > deadline {
+                return Err(anyhow!("Timed out waiting for the port forwarding"));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        Ok((child, port))
+    }
+    /// Starts a master connection for the ControlPath of statusd. It is closed when the child is
+    /// dropped.
+    pub fn start_control_master(&self) -> Result<async_process::Child> {
+        let child = self
+            .ssh_cmd_async(Some(&["-M", "-N", "-o", "ControlPersist=no"]))?
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        Ok(child)
+    }
+    /// Forwards `dut_port` on the DUT to `port` on this machine until the child is dropped
+    pub fn start_reverse_port_forwarding(
+        &self,
+        dut_port: u16,
+        port: u16,
+    ) -> Result<async_process::Child> {
+        self.start_reverse_port_forwarding_to(dut_port, "127.0.0.1", port)
+    }
+    /// Forwards `dut_port` on the DUT to `host:port` reachable from this machine
+    pub fn start_reverse_port_forwarding_to(
+        &self,
+        dut_port: u16,
+        host: &str,
+        port: u16,
+    ) -> Result<async_process::Child> {
+        let child = self
+            .ssh_cmd_async(Some(&[
+                "-S",
+                "none",
+                "-N",
+                "-R",
+                &format!("{dut_port}:{host}:{port}"),
+                "-o",
+                "ExitOnForwardFailure yes",
+                "-o",
+                "ServerAliveInterval=5",
+            ]))?
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        Ok(child)
+    }
+    pub fn start_ssh_forwarding(&self, port: u16) -> Result<async_process::Child> {
+        self.start_port_forwarding(port, 22, "sleep 8h")
+    }
+    // Start SSH port forwarding in a given range without timeout.
+    // Since this doesn't check the timeout, user should use
+    // SshInfo::start_ssh_forwarding_range_background() instead.
+    fn start_ssh_forwarding_range(
+        &self,
+        port_range: Range<u16>,
+    ) -> Result<(async_process::Child, u16)> {
+        let sshcmd = &format!("echo {COMMON_PORT_FORWARD_TOKEN}; sleep 8h");
+        block_on(async {
+            let mut ports: Vec<u16> = port_range.into_iter().collect::<Vec<u16>>();
+            let mut rng = thread_rng();
+            ports.shuffle(&mut rng);
+            for port in ports {
+                // Try to establish port forwarding
+                let mut child = self.start_port_forwarding(port, 22, sshcmd)?;
+                let (mut ssh_stdout, mut ssh_stderr) = get_async_lines(&mut child);
+                loop {
+                    let mut ssh_stdout = ssh_stdout.next().fuse();
+                    let mut ssh_stderr = ssh_stderr.next().fuse();
+                    select! {
+                        line = ssh_stderr => {
+                            if let Some(line) = line {
+                                let line = line?;
+                                if line.contains("cannot listen to port") {
+                                    break;
+                                }
+                            } else {
+                                return Err(anyhow!("ssh failed unexpectedly"));
+                            }
+                        }
+                        line = ssh_stdout => {
+                            if let Some(line) = line {
+                                let line = line?;
+                                if line.contains(COMMON_PORT_FORWARD_TOKEN) {
+                                    return Ok((child, port));
+                                }
+                            } else {
+                                return Err(anyhow!("ssh failed unexpectedly"));
+                            }
+                        }
+                    }
+                }
+            }
+            return Err(anyhow!("Do not find any vacant port"));
+        })
+    }
+    // Keep forwarding in background. This refers config.ssh_port_search_timeout.
+    pub fn start_ssh_forwarding_range_background(&self, port_range: Range<u16>) -> Result<u16> {
+        let timeout = if let Some(timeout) = Config::read()?.ssh_port_search_timeout() {
+            timeout
+        } else {
+            // Default timeout is 1 hour. This should be enough long.
+            3600
+        };
+
+        let est_port = Arc::new((Mutex::new(0_u16), Condvar::new()));
+        let est_port2 = est_port.clone();
+
+        let ssh = self.clone();
+        thread::spawn(move || {
+            let (lock, cvar) = &*est_port2;
+            let (mut child, port) =
+                if let Ok((child, port)) = ssh.start_ssh_forwarding_range(port_range) {
+                    (child, port)
+                } else {
+                    warning!("Failed to establish ssh port forwarding");
+                    return;
+                };
+
+            {
+                // NOTE: We have to unlock mutex after updating the object
+                let mut fwport = lock.lock().unwrap();
+                *fwport = port;
+            };
+            cvar.notify_all(); // notify the port forwarding is established.
+
+            block_on(async move {
+                loop {
+                    let _ = child.status().await; // Ignore the result.
+                    loop {
+                        // Stop reconnecting once the command is cancelled
+                        if cancel::sleep(Duration::from_secs(5)).is_err() {
+                            return;
+                        }
+                        let ret = ssh.start_ssh_forwarding(port);
+                        if let Ok(new_child) = ret {
+                            child = new_child;
+                            break;
+                        }
+                    }
+                }
+            });
+        });
+
+        let (lock, cvar) = &*est_port;
+        let port = lock.lock().unwrap();
+        let result = cvar
+            .wait_timeout(port, Duration::from_secs(timeout))
+            .unwrap();
+
+        if result.1.timed_out() {
+            Err(anyhow!("SSH vacant port search timed out"))
+        } else {
+            Ok(*result.0)
+        }
+    }
+
+    pub fn run_cmd_stdio(&self, cmd: &str) -> Result<String> {
+        let output = self.run_cmd_captured(cmd)?;
+        if output.status.success() {
+            Ok(crate::util::get_stdout(&output))
+        } else {
+            Err(anyhow!(
+                "run_cmd_stdio failed: {} {}",
+                get_stderr(&output),
+                get_stdout(&output)
+            ))
+        }
+    }
+    /// run_cmd_stdio() that does not block the thread. ssh is killed if the future is dropped
+    /// (e.g. on a timeout).
+    pub async fn run_cmd_stdio_async(&self, cmd: &str) -> Result<String> {
+        let output = self
+            .ssh_cmd_async(None)?
+            .arg(cmd)
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .output()
+            .await
+            .context("Failed to run ssh")?;
+        if output.status.success() {
+            Ok(get_stdout(&output))
+        } else {
+            Err(anyhow!(
+                "run_cmd_stdio failed: {} {}",
+                get_stderr(&output),
+                get_stdout(&output)
+            ))
+        }
+    }
+    /// Fails with an explanation if the DUT does not run a test image, which `what` requires.
+    /// The check is skipped if the image type can not be detected.
+    pub fn ensure_test_image(&self, what: &str) -> Result<()> {
+        let value = match DutInfo::fetch_keys(self, &["image_type"]) {
+            Ok(mut values) => values.remove("image_type"),
+            Err(e) => {
+                warning!("Failed to detect the image type of {self:?}: {e:#}");
+                None
+            }
+        };
+        match value.map(|v| ImageType::parse(&v)) {
+            Some(ImageType::Base(track)) => Err(anyhow!(
+                "{what} needs a test image, but {} runs a base image (track: {}), which lacks sshd with the testing keys, autotest and a writable rootfs. Flash a test image with `lium flash --dut {}` (without --recovery) first.",
+                self.host_and_port(),
+                if track.is_empty() { "unknown" } else { &track },
+                self.host_and_port()
+            )),
+            _ => Ok(()),
+        }
+    }
+    pub fn run_autologin(&self) -> Result<()> {
+        self.ensure_test_image("autologin")?;
+        self.run_cmd_piped(&["/usr/local/autotest/bin/autologin.py", "-a", "-d"])
+    }
+    pub fn get_host_kernel_config(&self) -> Result<String> {
+        self.run_cmd_stdio("modprobe configs; zcat /proc/config.gz")
+    }
+    pub fn get_board(&self) -> Result<String> {
+        self.run_cmd_stdio("cat /etc/lsb-release | grep CHROMEOS_RELEASE_BOARD | cut -d '=' -f 2")
+    }
+    pub fn get_arch(&self) -> Result<String> {
+        // Return "x86_64" or "arm64"
+        self.run_cmd_stdio("uname -m | sed s/aarch64/arm64/")
+    }
+    pub fn get_arc_version(&self) -> Result<String> {
+        self.run_cmd_stdio("cat /etc/lsb-release | grep CHROMEOS_ARC_VERSION= | cut -d '=' -f 2")
+    }
+    pub fn get_arc_device(&self) -> Result<String> {
+        // Return "cheets" or "bertha"
+        self.run_cmd_stdio("test -d /opt/google/vms/android && echo bertha || echo cheets")
+    }
+    pub fn get_uptime(&self) -> Result<Duration> {
+        let uptime = self
+            .run_cmd_stdio("cat /proc/uptime")
+            .context("Failed to read /proc/uptime")?;
+        let uptime = uptime
+            .split(' ')
+            .next()
+            .ok_or(anyhow!("Failed to parse uptime"))?;
+        Ok(Duration::from_secs_f64(f64::from_str(uptime)?))
+    }
+    pub fn get_boot_id(&self) -> Result<String> {
+        self.run_cmd_stdio("cat /proc/sys/kernel/random/boot_id")
+    }
+    /// Wait until the DUT is reachable again with a boot_id different from prev_boot_id.
+    /// Returns the new boot_id.
+    pub fn wait_for_new_boot_id(&self, prev_boot_id: &str, timeout: Duration) -> Result<String> {
+        let start = Instant::now();
+        while start.elapsed() < timeout {
+            if let Ok(boot_id) = self.get_boot_id() {
+                if boot_id != prev_boot_id {
+                    return Ok(boot_id);
+                }
+            }
+            cancel::sleep(Duration::from_secs(1))?;
+        }
+        Err(anyhow!(
+            "DUT did not come back with a new boot_id within {} sec",
+            timeout.as_secs()
+        ))
+    }
+    /// Collect logs that are useful for debugging (e.g. reboot failures) into dest dir.
+    /// Copies the logs of the DUT to dest/LOG_BUNDLE_NAME
+    pub fn collect_logs(&self, dest: &str) -> Result<()> {
+        let archive = format!("/tmp/{LOG_BUNDLE_NAME}");
+        self.run_cmd_stdio(&format!(
+            "tar -czf {archive} --ignore-failed-read /var/log/messages /var/log/eventlog.txt /var/log/bios_info.txt /sys/fs/pstore 2>/dev/null; test -f {archive}"
+        ))
+        .context("Failed to archive logs on the DUT")?;
+        std::fs::create_dir_all(dest).context("Failed to create a dir for logs")?;
+        self.get_files(&[archive], Some(&dest.to_string()))
+    }
+    pub fn get_arc_image_type(&self) -> Result<String> {
+        let arc_dir = if self.get_arc_device()? == "cheets" {
+            "arc"
+        } else {
+            "arcvm"
+        };
+        self.run_cmd_stdio(&format!(
+            "grep ro.build.type /usr/share/{}/properties/build.prop | cut -d '=' -f 2",
+            arc_dir
+        ))
+    }
+    pub fn get_files(&self, files: &[String], dest: Option<&String>) -> Result<()> {
+        let mut cmd = self.scp_get_cmd(files, dest)?;
+        let chd = cmd.stderr(Stdio::piped()).spawn()?;
+        let result = chd.wait_with_output()?;
+        let stderr = get_stderr(&result);
+        result.status.exit_ok().context(anyhow!(
+            r#"Failed to run scp {cmd:?}:
+stderr:
+    {}"#,
+            stderr
+        ))
+    }
+    pub fn send_files(&self, files: &[String], dest: Option<&String>) -> Result<()> {
+        if files.len() > 1 {
+            let progress = Progress::new("Sending files", files.len() as u64);
+            if progress.is_shown() {
+                // Send one by one to show the progress
+                for f in files {
+                    cancel::check()?;
+                    progress.set_detail(f);
+                    self.send_files(&[f.clone()], dest)?;
+                    progress.inc(1);
+                }
+                return Ok(());
+            }
+        }
+        let mut cmd = self.scp_send_cmd(files, dest)?;
+        let chd = cmd.stderr(Stdio::piped()).spawn()?;
+        let result = chd.wait_with_output()?;
+        let stderr = get_stderr(&result);
+        result.status.exit_ok().context(anyhow!(
+            "Failed to run scp {cmd:?}:\nstderr:\n    {}",
+            stderr
+        ))
+    }
+    /// send_files() that records the pushed files in DUT_PUSH_AUDIT_LOG. Existing files to be
+    /// overwritten are backed up on the DUT first so that `dut sanitize` can restore them.
+    pub fn send_files_audited(&self, files: &[String], dest: Option<&String>) -> Result<()> {
+        let mut names = Vec::new();
+        for f in files {
+            let name = f.rsplit('/').next().unwrap_or(f);
+            if name.contains('\'') {
+                return Err(anyhow!("Invalid file name: {f:?}"));
+            }
+            names.push(format!("'{name}'"));
+        }
+        let dest_dir = match dest {
+            Some(dest) if dest.contains('\'') => return Err(anyhow!("Invalid dest: {dest:?}")),
+            Some(dest) => format!("'{dest}'"),
+            None => "\"$HOME\"".to_string(),
+        };
+        // Prints "<remote path>\t<backup>" for each file
+        let script = format!(
+            r#"d={dest_dir}; d="${{d/#\~/$HOME}}"; for f in {}; do
+  if [ -d "$d" ]; then p="${{d%/}}/$f"; else p="$d"; fi
+  b=""
+  if [ -f "$p" ] && [ ! -e "$p{PUSH_BACKUP_SUFFIX}" ]; then cp -a "$p" "$p{PUSH_BACKUP_SUFFIX}" && b="$p{PUSH_BACKUP_SUFFIX}"; fi
+  printf '%s\t%s\n' "$p" "$b"
+done"#,
+            names.join(" ")
+        );
+        let targets = self.run_cmd_stdio(&script)?;
+        self.send_files(files, dest)?;
+        let key = self.host_and_port();
+        let mut log = DUT_PUSH_AUDIT_LOG.get(&key)?.unwrap_or_default();
+        let pushed_at = Local::now().timestamp();
+        for line in targets.lines() {
+            let (path, backup) = line.split_once('\t').unwrap_or((line, ""));
+            if let Some(e) = log.iter_mut().find(|e| e.path == path) {
+                // Keep the backup of the original file
+                e.pushed_at = pushed_at;
+                continue;
+            }
+            log.push(PushedFile {
+                path: path.to_string(),
+                backup: (!backup.is_empty()).then(|| backup.to_string()),
+                pushed_at,
+            });
+        }
+        DUT_PUSH_AUDIT_LOG.set(&key, log)
+    }
+}
+
+/// KeyInfo holds values that can identify a physical DUT uniquely
+#[derive(Clone, Debug)]
+pub struct KeyInfo {
+    key: String,
+    model: String,
+    serial: String,
+}
+impl KeyInfo {
+    pub async fn from_raw_dut_info(info: &HashMap<String, String>) -> Result<Self> {
+        let model = info.get("model").context("failed to get model")?.clone();
+        let serial = info.get("serial").context("failed to get serial")?.clone();
+        let key = model.clone() + "_" + &serial;
+        Ok(Self { key, model, serial })
+    }
+    pub fn key(&self) -> &str {
+        &self.key
+    }
+    pub fn model(&self) -> &str {
+        &self.model
+    }
+    pub fn serial(&self) -> &str {
+        &self.serial
+    }
+}
+
+/// Returns the DUTs given with --dut-file, or the ones in SSH_CACHE
+pub fn known_duts() -> Result<HashMap<String, SshInfo>> {
+    if let Some(duts) = dut_file::entries()? {
+        return Ok(duts);
+    }
+    SSH_CACHE
+        .entries()
+        .context(anyhow!("SSH_CACHE is not initialized yet"))
+}
+
+/// Returns the DUTs in a group: the ones with the label in --dut-file, or a DUT group in the
+/// config
+pub fn dut_group(name: &str) -> Result<Vec<String>> {
+    if let Some(duts) = dut_file::group(name) {
+        return Ok(duts);
+    }
+    Ok(Config::read()?.dut_group(name)?.clone())
+}
+
+pub fn pingable_duts() -> Result<Vec<SshInfo>> {
+    Ok(known_duts()?
+        .iter()
+        .flat_map(|it| {
+            let ssh = it.1;
+            ssh.ping().and(Ok(ssh.clone()))
+        })
+        .collect())
+}
+
+/// Checks if the address is a DUT, and records it in SSH_CACHE if `provenance` is given
+async fn probe_dut(
+    addr: &str,
+    extra_attr: &[String],
+    provenance: Option<&DutProvenance>,
+) -> Result<DutInfo> {
+    // Since we are listing the DUTs on the same network
+    // so assume that port 22 is open for ssh unless given
+    let ssh = match dut_id::parse_hop(addr)? {
+        DutIdentifier::Address { host, port } => {
+            SshInfo::new_host_and_port(&host, port.unwrap_or(22))?
+        }
+        DutIdentifier::Cached(_) => return Err(anyhow!("{addr} is not an address")),
+    };
+    let dut = with_timeout(DUT_PROBE_TIMEOUT, DutInfo::fetch_from_ssh(&ssh, extra_attr)).await?;
+    let Some(provenance) = provenance else {
+        return Ok(dut);
+    };
+    let dut = dut.save_to_cache().await?;
+    let mut provenance = provenance.clone();
+    // Link-local addresses tell the interface (e.g. fe80::1%eth0)
+    if let Some((_, zone)) = ssh.host.split_once('%') {
+        provenance.interface = Some(zone.to_string());
+    }
+    DUT_PROVENANCE.set(dut.id(), provenance)?;
+    Ok(dut)
+}
+
+/// Fetches the info of the DUTs in addrs. `on_found` is called as soon as a DUT is found.
+/// If `provenance` is given, the DUTs are merged into SSH_CACHE with it. `progress` is advanced
+/// for each address, with the number of DUTs found so far.
+pub fn fetch_dut_info_in_parallel(
+    addrs: &Vec<String>,
+    extra_attr: &[String],
+    provenance: Option<&DutProvenance>,
+    progress: &Progress,
+    on_found: &(dyn Fn(&DutInfo) + Sync),
+) -> Result<Vec<DutInfo>> {
+    let found = &AtomicUsize::new(0);
+    let duts = run_concurrently(addrs, MAX_PARALLEL_SSH, |addr| async move {
+        // Skip the rest quickly on Ctrl-C
+        cancel::check()?;
+        let dut = probe_dut(addr, extra_attr, provenance).await;
+        if dut.is_ok() {
+            found.fetch_add(1, Ordering::SeqCst);
+        }
+        progress.set_detail(&format!("{} found, {addr}", found.load(Ordering::SeqCst)));
+        progress.inc(1);
+        match &dut {
+            Ok(dut) => {
+                let mark = styled(symbol("✓", "+"), Style::Good, Stream::Stderr);
+                status!("{mark} {} is a DUT :)", addr);
+                on_found(dut);
+            }
+            Err(e) => {
+                let mark = styled(symbol("✗", "-"), Style::Dim, Stream::Stderr);
+                status!("{mark} {} is not a DUT...(ToT) : {:#}", addr, e)
+            }
+        }
+        dut
+    });
+    Ok(duts.into_iter().flatten().collect())
+}
+
+/// Largest IPv4 network (in the prefix length) to be expanded by expand_targets()
+const MAX_SCAN_PREFIX: u32 = 16;
+
+/// Expands IPv4 networks (e.g. 192.168.0.0/24) in the targets of a discovery into their host
+/// addresses. Other targets are kept as is.
+pub fn expand_targets(targets: &[String]) -> Result<Vec<String>> {
+    let mut addrs = Vec::new();
+    for target in targets {
+        let Some((net, prefix)) = target.split_once('/') else {
+            addrs.push(target.clone());
+            continue;
+        };
+        let net = Ipv4Addr::from_str(net).context(anyhow!("{target} is not an IPv4 network"))?;
+        let prefix: u32 = prefix
+            .parse()
+            .ok()
+            .filter(|p| *p <= 32)
+            .context(anyhow!("Invalid prefix length in {target}"))?;
+        if prefix < MAX_SCAN_PREFIX {
+            return Err(anyhow!(
+                "{target} is too large to scan. Split it into /{MAX_SCAN_PREFIX} or smaller networks"
+            ));
+        }
+        let size = 1u32 << (32 - prefix);
+        let first = u32::from(net) & !(size - 1);
+        let hosts = if size <= 2 {
+            first..first + size
+        } else {
+            // Skip the network and the broadcast addresses
+            first + 1..first + size - 1
+        };
+        addrs.extend(hosts.map(|a| Ipv4Addr::from(a).to_string()));
+    }
+    Ok(addrs)
+}
+
+pub fn discover_local_nodes(iface: Option<String>) -> Result<Vec<String>> {
+    ensure_testing_rsa_is_there()?;
+    status!("Detecting DUTs on the same network...");
+    let iface = iface
+        .ok_or(())
+        .or_else(|_| -> Result<String, anyhow::Error> {
+            let r = run_bash_command(CMD_GET_DEFAULT_IFACE, None)
+                .context("failed to determine interface to scan from ip route")?;
+            r.status.exit_ok()?;
+            Ok(get_stdout(&r).trim().to_string())
+        })
+        .context("Failed to determine interface to scan")?;
+    status!("Using {iface} to scan...");
+    let output = run_bash_command(&format!(
+        "ping6 -c 3 -I {iface} ff02::1 | grep 'bytes from' | cut -d ' ' -f 4 | tr -d ',' | sort | uniq"),
+        None,
+    )?;
+    let stdout = get_stdout(&output);
+    let addrs = stdout
+        .split('\n')
+        .filter_map(|addr| canonical_discovered_addr(addr, &iface))
+        .collect::<Vec<String>>();
+    Ok(addrs)
+}
+
+/// Converts an address printed by ping6 (e.g. "fe80::1%eth0:") into the canonical form of
+/// DUT identifiers (e.g. "[fe80::1%eth0]:22"). A zone ID is added to link-local addresses.
+fn canonical_discovered_addr(addr: &str, iface: &str) -> Option<String> {
+    let addr = addr.trim().trim_end_matches(':');
+    if addr.is_empty() {
+        return None;
+    }
+    let addr = if addr.to_lowercase().starts_with("fe80:") && !addr.contains('%') {
+        format!("{addr}%{iface}")
+    } else {
+        addr.to_string()
+    };
+    SshInfo::new_host_and_port(&addr, 22)
+        .ok()
+        .map(|s| s.host_and_port())
+}
+
+pub fn register_dut(dut: &str) -> Result<DutInfo> {
+    status!("Checking: {dut:?}...");
+    let spinner = Progress::spinner(&format!("Connecting to {dut}"));
+    let info = DutInfo::new(dut)?;
+    drop(spinner);
+    let id = info.id();
+    let ssh = info.ssh();
+    SSH_CACHE.set(id, ssh.clone())?;
+    DUT_PROVENANCE.set(id, DutProvenance::now(None))?;
+    println!("Added: {:32} {}", id, serde_json::to_string(ssh)?);
+    Ok(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn regex() {
+        // bracketed ipv6 address is prohibited as an internal representation
+        assert!(!RE_DUT_HOST_NAME.is_match("[fe00::]"));
+        // but allowed for inputs
+        assert!(RE_IPV6_WITH_BRACKETS.is_match("[fe00::]"));
+        assert_eq!(
+            &RE_IPV6_WITH_BRACKETS.captures("[fe00::]").unwrap()["addr"],
+            "fe00::"
+        );
+        // %ifname should be supported for IPv6 link local addresses
+        assert_eq!(
+            &RE_IPV6_WITH_BRACKETS.captures("[fe00::%eth0]").unwrap()["addr"],
+            "fe00::%eth0"
+        );
+        assert!(!RE_IPV6_WITH_BRACKETS.is_match("fe00::]"));
+        assert!(!RE_IPV6_WITH_BRACKETS.is_match("[fe00::"));
+
+        assert!(RE_DUT_HOST_NAME.is_match("1.2.3.4"));
+        assert!(!RE_DUT_HOST_NAME.is_match(" 1.2.3.4"));
+
+        assert!(RE_DUT_HOST_NAME.is_match("fe00::"));
+        assert!(RE_DUT_HOST_NAME.is_match("fe00::"));
+        // %ifname should be supported for IPv6 link local addresses
+        assert!(RE_DUT_HOST_NAME.is_match("fe00::%eth0"));
+        assert!(RE_DUT_HOST_NAME.is_match("a:"));
+        assert!(!RE_DUT_HOST_NAME.is_match("fe00:: "));
+        assert!(!RE_DUT_HOST_NAME.is_match(" fe00::"));
+
+        assert!(RE_DUT_HOST_NAME.is_match("chromium.org"));
+        assert!(RE_DUT_HOST_NAME.is_match("a"));
+        assert!(!RE_DUT_HOST_NAME.is_match(" chromium.org "));
+        assert!(!RE_DUT_HOST_NAME.is_match(""));
+        assert!(!RE_DUT_HOST_NAME.is_match(" "));
+        assert!(!RE_DUT_HOST_NAME.is_match("a\t"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a\n"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a\r"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a "));
+        assert!(!RE_DUT_HOST_NAME.is_match("a#"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a/"));
+        assert!(!RE_DUT_HOST_NAME.is_match("g:"));
+        assert!(!RE_DUT_HOST_NAME.is_match("g<"));
+        assert!(!RE_DUT_HOST_NAME.is_match("g>"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a?"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a@"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a["));
+        assert!(!RE_DUT_HOST_NAME.is_match("a]"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a^"));
+        assert!(!RE_DUT_HOST_NAME.is_match("a|"));
+
+        assert!(RE_GBB_FLAGS.is_match("0x00000000"));
+        assert!(RE_GBB_FLAGS.is_match("0x00000019"));
+        assert!(!RE_GBB_FLAGS.is_match("0x00000019 "));
+        assert!(!RE_GBB_FLAGS.is_match(" 0x00000019"));
+        assert!(!RE_GBB_FLAGS.is_match("flags: 0x00000019"));
+    }
+
+    #[test]
+    fn ipv6_with_zone() {
+        for (dut, route) in [
+            ("[fe80::1%eth0]:2222", "[fe80::1%eth0]:2222"),
+            ("[fe80::1%eth0]", "[fe80::1%eth0]:22"),
+            ("fe80::1%eth0", "[fe80::1%eth0]:22"),
+            ("[fe80::1]:2222", "[fe80::1]:2222"),
+            ("127.0.0.1>[fe80::1%en0]", "127.0.0.1:22>[fe80::1%en0]:22"),
+        ] {
+            assert_eq!(SshInfo::new(dut).unwrap().route(), route);
+        }
+        assert!(SshInfo::new("[fe80::1%eth0").is_err());
+        assert_eq!(
+            canonical_discovered_addr("fe80::1:", "eth0").as_deref(),
+            Some("[fe80::1%eth0]:22")
+        );
+        assert_eq!(
+            canonical_discovered_addr("fe80::1%en0:", "eth0").as_deref(),
+            Some("[fe80::1%en0]:22")
+        );
+        assert_eq!(canonical_discovered_addr("", "eth0"), None);
+    }
+
+    #[test]
+    fn multi_hop() {
+        let ssh = SshInfo::new("10.0.0.1>192.168.0.2:2222").unwrap();
+        assert_eq!(ssh.host_and_port(), "192.168.0.2:2222");
+        assert_eq!(ssh.route(), "10.0.0.1:22>192.168.0.2:2222");
+        assert!(ssh.needs_port_forwarding_in_chroot());
+        let ssh = SshInfo::new("a > b > localhost").unwrap();
+        assert_eq!(ssh.route(), "a:22>b:22>localhost:22");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn alternatives() {
+        let mut ssh = SshInfo::new("dut.example.com").unwrap();
+        let mut prev = SshInfo::new("192.168.0.2").unwrap();
+        prev.add_alternative("dut.example.com", 22);
+        prev.add_alternative("fe80::1%eth0", 22);
+        prev.add_alternative("fe80::1%eth0", 22);
+        ssh.merge_alternatives(&prev);
+        let hosts: Vec<String> = ssh.candidates().into_iter().map(|c| c.host).collect();
+        assert_eq!(
+            hosts,
+            vec!["dut.example.com", "192.168.0.2", "fe80::1%eth0"]
+        );
+        assert!(ssh.remove_alternative("192.168.0.2", 22));
+        assert!(!ssh.remove_alternative("192.168.0.2", 22));
+        // Without alternatives, connect() does not probe
+        let ssh = SshInfo::new("192.168.0.3").unwrap();
+        assert_eq!(ssh.connect("id").host(), "192.168.0.3");
+    }
+
+    #[test]
+    fn latency_stats() {
+        let ms = Duration::from_millis;
+        assert_eq!(LatencyStats::from_samples(&VecDeque::new()), None);
+        assert_eq!(LatencyStats::from_samples(&VecDeque::from([None])), None);
+        let samples = VecDeque::from([Some(ms(10)), None, Some(ms(30)), Some(ms(20))]);
+        let stats = LatencyStats::from_samples(&samples).unwrap();
+        assert_eq!(stats.p50, ms(20));
+        assert_eq!(stats.p99, ms(30));
+        assert_eq!(stats.jitter, ms(15));
+        assert_eq!(stats.loss, 0.25);
+    }
+
+    #[test]
+    fn info_dut_id_failure() {
+        let keys = vec!["dut_id"];
+        let values = HashMap::new();
+        let result_actual = DutInfo::parse_values(&keys, values);
+        assert!(result_actual.is_err());
+    }
+    #[test]
+    fn info_dut_id() {
+        let keys = vec!["dut_id"];
+        let mut values = HashMap::new();
+        values.insert("model".to_string(), Ok("MODEL".to_string()));
+        values.insert("serial".to_string(), Ok("SERIAL".to_string()));
+        let result_actual = DutInfo::parse_values(&keys, values);
+        let mut result_expected = HashMap::new();
+        result_expected.insert("dut_id".to_string(), "MODEL_SERIAL".to_string());
+        let result_expected: HashMap<String, String> = result_expected;
+        assert_eq!(result_actual.expect("result should be Ok"), result_expected);
+    }
+    #[test]
+    fn default_dut_info_has_no_env_specific_keys() {
+        assert!(!DEFAULT_DUT_INFO_KEYS
+            .iter()
+            .any(|&k| { k == "ipv6_addr" || k == "ipv4_addr" }));
+    }
+    #[test]
+    fn provenance() {
+        let mut p = DutProvenance {
+            discovered_by: "lium dut discover".to_string(),
+            interface: Some("eth0".to_string()),
+            timestamp: 1700000000,
+        };
+        assert_eq!(
+            p.to_string(),
+            "added by `lium dut discover` on eth0 at 2023-11-14 22:13:20 UTC"
+        );
+        p.interface = None;
+        assert!(!p.to_string().contains(" on "));
+    }
+    #[test]
+    fn image_type() {
+        assert_eq!(
+            ImageType::parse("testimage-channel,keys\n"),
+            ImageType::Test
+        );
+        assert_eq!(
+            ImageType::parse("testimage-channel,"),
+            ImageType::Base("testimage-channel".to_string())
+        );
+        assert_eq!(
+            ImageType::parse("stable-channel,keys"),
+            ImageType::Base("stable-channel".to_string())
+        );
+        assert_eq!(ImageType::parse(""), ImageType::Base(String::new()));
+    }
+    #[test]
+    fn script_for_keys() {
+        // Run the script locally in place of the DUT
+        let script = DutInfo::gen_script_for_keys(&[
+            ("a", "echo 'a,\nb'"),
+            ("fail", "echo oops >&2; exit 3"),
+            ("b", "echo b"),
+        ]);
+        let output = Command::new("bash").args(["-c", &script]).output().unwrap();
+        let values = DutInfo::decode_results(&get_stdout(&output), &["a", "fail", "b", "gone"]);
+        assert_eq!(values["a"].as_ref().unwrap(), "a,\nb");
+        assert!(format!("{:#}", values["fail"].as_ref().unwrap_err()).contains("oops"));
+        assert_eq!(values["b"].as_ref().unwrap(), "b");
+        assert!(values["gone"].is_err());
+    }
+    #[test]
+    fn mitigations() {
+        // Run the command of the key locally on a copy of the sysfs directory
+        let dir = tempdir::TempDir::new("lium_vulnerabilities").unwrap();
+        std::fs::write(
+            dir.path().join("spectre_v1"),
+            "Mitigation: usercopy/swapgs barriers and __user pointer sanitization\n",
+        )
+        .unwrap();
+        std::fs::write(dir.path().join("meltdown"), "Not affected\n").unwrap();
+        let cmd = DUT_ATTRIBUTE_CMDS["mitigations"].replace(
+            "/sys/devices/system/cpu/vulnerabilities/",
+            &format!("{}/", dir.path().display()),
+        );
+        let output = Command::new("bash").args(["-c", &cmd]).output().unwrap();
+        assert_eq!(
+            get_stdout(&output),
+            "meltdown:Not affected\n\
+             spectre_v1:Mitigation: usercopy/swapgs barriers and __user pointer sanitization"
+        );
+    }
+    #[test]
+    fn scan_targets() {
+        let targets =
+            |t: &[&str]| expand_targets(&t.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+        assert_eq!(
+            targets(&["10.0.0.5/30", "dut1", "[fe80::1%eth0]:22"]).unwrap(),
+            vec!["10.0.0.5", "10.0.0.6", "dut1", "[fe80::1%eth0]:22"]
+        );
+        assert_eq!(targets(&["10.0.0.1/32"]).unwrap(), vec!["10.0.0.1"]);
+        assert_eq!(targets(&["10.1.0.0/16"]).unwrap().len(), 65534);
+        assert!(targets(&["10.0.0.0/8"]).is_err());
+        assert!(targets(&["10.0.0.0/33"]).is_err());
+        assert!(targets(&["fe80::/64"]).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! DUTs given with `lium --dut-file targets.yaml`. They are used in place of the DUT list
+//! (SSH_CACHE) and are never written to it, so ephemeral sets of DUTs (e.g. for a CI job) do not
+//! pollute it:
+//!
+//! ```yaml
+//! duts:
+//!   eve1:
+//!     host: 192.168.0.2
+//!     port: 2222                     # default: 22
+//!     jump: gateway                  # another DUT in this file, or a DUT identifier
+//!     alternatives: ["eve1.lab"]     # other addresses tried if the host is not reachable
+//!     labels: [production]           # usable as groups (e.g. `lium dut shell --group`)
+//!     identity_file: ~/.ssh/lab_rsa  # in addition to testing_rsa
+//!     ssh_options: ["-o", "ConnectTimeout=30"]
+//! ```
+//!
+//! The path is exported as $LIUM_DUT_FILE so that lium processes spawned by this one see the
+//! same DUTs.
+
+use crate::dut::SshInfo;
+use crate::dut_id;
+use crate::dut_id::DutIdentifier;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::sync::RwLock;
+
+pub const DUT_FILE_ENV: &str = "LIUM_DUT_FILE";
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct DutFileEntry {
+    host: String,
+    port: Option<u16>,
+    jump: Option<String>,
+    #[serde(default)]
+    alternatives: Vec<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+    identity_file: Option<String>,
+    #[serde(default)]
+    ssh_options: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct DutFile {
+    duts: BTreeMap<String, DutFileEntry>,
+}
+impl DutFile {
+    fn parse(s: &str) -> Result<Self> {
+        let file: Self = serde_yaml::from_str(s)?;
+        for (id, entry) in &file.duts {
+            dut_id::parse_hop(id).context(anyhow!("Invalid DUT ID {id:?}"))?;
+            entry
+                .addresses()
+                .context(anyhow!("Invalid address of {id}"))?;
+            // Reject loops of jump hosts
+            let mut hop = entry;
+            for _ in 0..=file.duts.len() {
+                match hop.jump.as_ref().and_then(|j| file.duts.get(j)) {
+                    Some(next) => hop = next,
+                    None => break,
+                }
+            }
+            if hop
+                .jump
+                .as_ref()
+                .map_or(false, |j| file.duts.contains_key(j))
+            {
+                return Err(anyhow!("The jump hosts of {id} form a loop"));
+            }
+        }
+        Ok(file)
+    }
+}
+impl DutFileEntry {
+    /// Returns the host:port of the host and the alternatives
+    fn addresses(&self) -> Result<Vec<(String, u16)>> {
+        let mut result = vec![(self.host.clone(), self.port.unwrap_or(22))];
+        for alt in &self.alternatives {
+            match dut_id::parse_hop(alt)? {
+                DutIdentifier::Address { host, port } => result.push((host, port.unwrap_or(22))),
+                DutIdentifier::Cached(_) => {
+                    return Err(anyhow!("Alternative {alt:?} is not an address"))
+                }
+            }
+        }
+        for (host, port) in &result {
+            SshInfo::new_host_and_port(host, *port)?;
+        }
+        Ok(result)
+    }
+    fn to_ssh_info(&self) -> Result<SshInfo> {
+        let addresses = self.addresses()?;
+        let (host, port) = &addresses[0];
+        let mut ssh = SshInfo::new_host_and_port(host, *port)?;
+        if let Some(jump) = &self.jump {
+            // The jump host is resolved by SshInfo::new(), possibly from this file again
+            ssh = SshInfo::new(&format!("{jump}>{}", ssh.host_and_port()))?;
+        }
+        for (host, port) in &addresses[1..] {
+            ssh.add_alternative(host, *port);
+        }
+        Ok(ssh)
+    }
+}
+
+lazy_static! {
+    static ref DUT_FILE: RwLock<Option<DutFile>> = RwLock::new(None);
+}
+
+/// Loads the DUTs from the file. Returns an error if it is invalid.
+pub fn load(path: &str) -> Result<()> {
+    let s = read_to_string(path).context(anyhow!("Failed to read {path}"))?;
+    let file = DutFile::parse(&s).context(anyhow!("Invalid DUT file {path}"))?;
+    *DUT_FILE.write().unwrap() = Some(file);
+    let path = std::fs::canonicalize(path)?;
+    std::env::set_var(DUT_FILE_ENV, path);
+    Ok(())
+}
+
+/// Returns true if the DUT ID is in the DUT file
+pub fn contains(id: &str) -> bool {
+    DUT_FILE
+        .read()
+        .unwrap()
+        .as_ref()
+        .map_or(false, |f| f.duts.contains_key(id))
+}
+
+/// Returns true if the host:port is one of the addresses of a DUT in the DUT file
+pub fn contains_address(host_and_port: &str) -> bool {
+    entry_with_address(host_and_port).is_some()
+}
+
+fn entry_with_address(host_and_port: &str) -> Option<DutFileEntry> {
+    let file = DUT_FILE.read().unwrap();
+    file.as_ref()?
+        .duts
+        .values()
+        .find(|e| {
+            e.addresses().unwrap_or_default().iter().any(|(h, p)| {
+                SshInfo::new_host_and_port(h, *p)
+                    .map_or(false, |s| s.host_and_port() == host_and_port)
+            })
+        })
+        .cloned()
+}
+
+/// Returns the DUT in the DUT file, if any
+pub fn lookup(id: &str) -> Result<Option<SshInfo>> {
+    let entry = DUT_FILE
+        .read()
+        .unwrap()
+        .as_ref()
+        .and_then(|f| f.duts.get(id).cloned());
+    entry.map(|e| e.to_ssh_info()).transpose()
+}
+
+/// Returns all the DUTs in the DUT file, or None if it is not given
+pub fn entries() -> Result<Option<HashMap<String, SshInfo>>> {
+    let file = DUT_FILE.read().unwrap().clone();
+    let Some(file) = file else {
+        return Ok(None);
+    };
+    file.duts
+        .iter()
+        .map(|(id, e)| Ok((id.clone(), e.to_ssh_info()?)))
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
+/// Returns the DUTs with the label, or None if no DUT has it
+pub fn group(label: &str) -> Option<Vec<String>> {
+    let file = DUT_FILE.read().unwrap();
+    let duts: Vec<String> = file
+        .as_ref()?
+        .duts
+        .iter()
+        .filter(|(_, e)| e.labels.iter().any(|l| l == label))
+        .map(|(id, _)| id.clone())
+        .collect();
+    (!duts.is_empty()).then_some(duts)
+}
+
+/// Returns the labels of the DUT known by any of `names` (IDs or host:port)
+pub fn labels_of(names: &[String]) -> Vec<String> {
+    let mut labels = Vec::new();
+    for name in names {
+        let listed = DUT_FILE
+            .read()
+            .unwrap()
+            .as_ref()
+            .and_then(|f| f.duts.get(name).cloned());
+        let entry = listed.or_else(|| entry_with_address(name));
+        labels.extend(entry.map(|e| e.labels).unwrap_or_default());
+    }
+    labels.sort();
+    labels.dedup();
+    labels
+}
+
+/// Returns the ssh options for the address given in the DUT file
+pub fn ssh_options_for(host_and_port: &str) -> Vec<String> {
+    let Some(entry) = entry_with_address(host_and_port) else {
+        return Vec::new();
+    };
+    let mut options = Vec::new();
+    if let Some(identity) = &entry.identity_file {
+        let identity = match identity.strip_prefix("~/") {
+            Some(rest) => dirs::home_dir()
+                .map(|h| h.join(rest).to_string_lossy().to_string())
+                .unwrap_or_else(|| identity.clone()),
+            None => identity.clone(),
+        };
+        options.extend(["-i".to_string(), identity]);
+    }
+    options.extend(entry.ssh_options.iter().cloned());
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dut_file() {
+        let file = DutFile::parse(
+            r#"
+duts:
+  eve1:
+    host: 192.168.0.2
+    port: 2222
+    jump: gw
+    alternatives: ["eve1.lab", "[fe80::1%eth0]:22"]
+    labels: [production]
+  gw:
+    host: 10.0.0.1
+"#,
+        )
+        .unwrap();
+        let eve1 = &file.duts["eve1"];
+        assert_eq!(
+            eve1.addresses().unwrap(),
+            vec![
+                ("192.168.0.2".to_string(), 2222),
+                ("eve1.lab".to_string(), 22),
+                ("fe80::1%eth0".to_string(), 22)
+            ]
+        );
+        assert_eq!(eve1.labels, vec!["production"]);
+        assert!(DutFile::parse("duts:\n  a:\n    host: 10.0.0.1\n    user: x\n").is_err());
+        assert!(DutFile::parse("duts:\n  a:\n    host: 'bad host'\n").is_err());
+        assert!(DutFile::parse(
+            "duts:\n  a:\n    host: 10.0.0.1\n    jump: b\n  b:\n    host: 10.0.0.2\n    jump: a\n"
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,263 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Parser of DUT identifiers given on the command line, to tell what exactly is wrong with an
+//! invalid one instead of failing later with an opaque error. A DUT identifier is one of:
+//! - an ID of a cached DUT (e.g. eve_XXXXXXXX)
+//! - host or host:port (e.g. 192.168.0.2, dut.example.com:2222)
+//! - an IPv6 address, with a port in brackets (e.g. [fe80::1%eth0]:2222)
+//! - hops chained with '>' (e.g. gateway>192.168.0.2)
+//!
+//! user@host and usb:serial are recognized to explain that they are not supported.
+
+use anyhow::anyhow;
+use anyhow::Result;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DutIdentifier {
+    /// ID of a DUT in the cache (contains '_', which is not allowed in host names)
+    Cached(String),
+    /// Host name, IPv4 address or IPv6 address (without brackets, maybe with a zone)
+    Address { host: String, port: Option<u16> },
+}
+
+/// Characters that are not allowed in host names (based on
+/// https://url.spec.whatwg.org/#host-miscellaneous)
+const FORBIDDEN_HOST_CHARS: &[char] = &[
+    '\t', '\n', '\r', ' ', '#', '/', ':', '<', '>', '?', '@', '[', ']', '^', '|',
+];
+
+fn invalid(dut: &str, reason: impl std::fmt::Display) -> anyhow::Error {
+    anyhow!("Invalid DUT identifier {dut:?}: {reason}")
+}
+
+fn parse_port(dut: &str, port: &str) -> Result<u16> {
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 => Ok(port),
+        _ if port.is_empty() => Err(invalid(dut, "the port is empty after ':'")),
+        _ => Err(invalid(
+            dut,
+            format!("port {port:?} is not a number in 1-65535"),
+        )),
+    }
+}
+
+fn check_ipv6(dut: &str, addr: &str) -> Result<()> {
+    let (addr, zone) = match addr.split_once('%') {
+        Some((addr, zone)) => (addr, Some(zone)),
+        None => (addr, None),
+    };
+    if zone == Some("") {
+        return Err(invalid(dut, "the zone is empty after '%'"));
+    }
+    if let Some(c) = addr.chars().find(|c| !c.is_ascii_hexdigit() && *c != ':') {
+        return Err(invalid(
+            dut,
+            format!("{c:?} is not allowed in an IPv6 address"),
+        ));
+    }
+    if addr.matches(':').count() < 2 {
+        return Err(invalid(dut, format!("{addr:?} is not an IPv6 address")));
+    }
+    Ok(())
+}
+
+/// Parses one hop of a DUT identifier (without '>')
+pub fn parse_hop(dut: &str) -> Result<DutIdentifier> {
+    if dut.is_empty() {
+        return Err(anyhow!("The DUT identifier is empty"));
+    }
+    if let Some(serial) = dut.strip_prefix("usb:") {
+        return Err(invalid(
+            dut,
+            format!(
+                "DUTs are accessed via SSH, not USB. Use the servo of the DUT with `lium servo` \
+                 or the network address of the DUT instead of usb:{serial}"
+            ),
+        ));
+    }
+    if let Some((user, host)) = dut.split_once('@') {
+        return Err(invalid(
+            dut,
+            format!(
+                "the user cannot be given in the DUT identifier. Use `lium config set ssh_auth \
+                 {host} user={user}` and {host:?} instead"
+            ),
+        ));
+    }
+    if let Some(rest) = dut.strip_prefix('[') {
+        let Some((addr, after)) = rest.split_once(']') else {
+            return Err(invalid(dut, "missing ']' after the IPv6 address"));
+        };
+        check_ipv6(dut, addr)?;
+        let port = match after {
+            "" => None,
+            _ => match after.strip_prefix(':') {
+                Some(port) => Some(parse_port(dut, port)?),
+                None => {
+                    return Err(invalid(
+                        dut,
+                        format!("unexpected {after:?} after ']'. Use [addr]:port"),
+                    ))
+                }
+            },
+        };
+        return Ok(DutIdentifier::Address {
+            host: addr.to_string(),
+            port,
+        });
+    }
+    if dut.matches(':').count() >= 2 {
+        // A bare IPv6 address cannot have a port since it would be ambiguous
+        check_ipv6(dut, dut).map_err(|e| {
+            anyhow!("{e}. Enclose IPv6 addresses in brackets to give a port (e.g. [fe80::1]:22)")
+        })?;
+        return Ok(DutIdentifier::Address {
+            host: dut.to_string(),
+            port: None,
+        });
+    }
+    let (host, port) = match dut.split_once(':') {
+        Some((host, port)) => (host, Some(parse_port(dut, port)?)),
+        None => (dut, None),
+    };
+    if host.is_empty() {
+        return Err(invalid(dut, "the host is empty"));
+    }
+    if let Some((i, c)) = host
+        .char_indices()
+        .find(|(_, c)| FORBIDDEN_HOST_CHARS.contains(c))
+    {
+        return Err(invalid(
+            dut,
+            format!("{c:?} at position {} is not allowed in a host name", i + 1),
+        ));
+    }
+    if host.contains('_') {
+        if port.is_some() {
+            return Err(invalid(
+                dut,
+                "a DUT ID cannot have a port. Use the address of the DUT instead",
+            ));
+        }
+        return Ok(DutIdentifier::Cached(host.to_string()));
+    }
+    Ok(DutIdentifier::Address {
+        host: host.to_string(),
+        port,
+    })
+}
+
+/// Parses a DUT identifier into its hops, the first one (outermost) first
+pub fn parse(dut: &str) -> Result<Vec<DutIdentifier>> {
+    let hops: Vec<&str> = dut.split('>').map(|s| s.trim()).collect();
+    if hops.len() > 1 {
+        if let Some(i) = hops.iter().position(|h| h.is_empty()) {
+            return Err(invalid(
+                dut,
+                format!("hop {} is empty. Chain hops like gateway>dut", i + 1),
+            ));
+        }
+    }
+    hops.into_iter().map(parse_hop).collect()
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut cur = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = usize::from(ca != *cb);
+            cur.push((prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1));
+        }
+        prev = cur;
+    }
+    prev[b.len()]
+}
+
+/// Returns the candidate closest to the DUT identifier if it is likely a typo of it (case
+/// differences, a few wrong characters or a unique prefix)
+pub fn suggest<'a>(dut: &str, candidates: impl IntoIterator<Item = &'a str>) -> Option<&'a str> {
+    let dut_lower = dut.to_lowercase();
+    let candidates: Vec<&str> = candidates.into_iter().collect();
+    let prefixed: Vec<&str> = candidates
+        .iter()
+        .copied()
+        .filter(|c| c.to_lowercase().starts_with(&dut_lower))
+        .collect();
+    if let [only] = prefixed[..] {
+        return Some(only);
+    }
+    let max_distance = (dut.chars().count() / 4).max(1);
+    candidates
+        .into_iter()
+        .map(|c| (edit_distance(&dut_lower, &c.to_lowercase()), c))
+        .filter(|(d, _)| *d <= max_distance)
+        .min()
+        .map(|(_, c)| c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn addr(host: &str, port: Option<u16>) -> DutIdentifier {
+        DutIdentifier::Address {
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    #[test]
+    fn parse_identifiers() {
+        assert_eq!(parse_hop("192.168.0.2").unwrap(), addr("192.168.0.2", None));
+        assert_eq!(
+            parse_hop("localhost:2222").unwrap(),
+            addr("localhost", Some(2222))
+        );
+        assert_eq!(
+            parse_hop("[fe80::1%eth0]:22").unwrap(),
+            addr("fe80::1%eth0", Some(22))
+        );
+        assert_eq!(parse_hop("fe80::1").unwrap(), addr("fe80::1", None));
+        assert_eq!(
+            parse_hop("eve_ABC").unwrap(),
+            DutIdentifier::Cached("eve_ABC".to_string())
+        );
+        assert_eq!(
+            parse("a > b:23").unwrap(),
+            vec![addr("a", None), addr("b", Some(23))]
+        );
+        for (dut, error) in [
+            ("", "empty"),
+            ("host:", "port is empty"),
+            ("host:99999", "not a number in 1-65535"),
+            ("[fe80::1", "missing ']'"),
+            ("[fe80::1]x", "Use [addr]:port"),
+            ("[fe80::g]", "'g' is not allowed"),
+            ("fe80::1:x:22", "Enclose IPv6 addresses in brackets"),
+            ("root@dut", "ssh_auth dut user=root"),
+            ("usb:123", "not USB"),
+            ("a/b", "'/' at position 2"),
+            ("eve_ABC:22", "DUT ID cannot have a port"),
+            ("a>>b", "hop 2 is empty"),
+        ] {
+            let e = parse(dut).unwrap_err().to_string();
+            assert!(e.contains(error), "{dut:?}: {e}");
+        }
+    }
+
+    #[test]
+    fn suggestions() {
+        let ids = ["eve_ABC123", "soraka_XYZ", "soraka_XYW"];
+        assert_eq!(suggest("eve_ABC124", ids), Some("eve_ABC123"));
+        assert_eq!(suggest("EVE_abc123", ids), Some("eve_ABC123"));
+        assert_eq!(suggest("eve", ids), Some("eve_ABC123"));
+        assert_eq!(suggest("soraka", ids), None);
+        assert_eq!(suggest("nami_000", ids), None);
+    }
+}
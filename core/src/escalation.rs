@@ -0,0 +1,174 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Escalation for DUTs with restricted shells. Privileged actions (e.g. firmware writes) on DUTs
+//! matching `lium config set dut_escalation <host_regex> <broker_command> [<prefix>]` get a
+//! short-lived credential from an AuthBroker first and pass it to the prefix command on the DUT
+//! (sudo by default) via stdin. Unprivileged actions are not affected.
+
+use crate::cache::KvCache;
+use crate::status;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::process::Command;
+use std::process::Stdio;
+
+/// Lifetime of credentials from brokers that do not tell it
+const DEFAULT_LIFETIME_SECS: i64 = 5 * 60;
+/// Credentials expiring sooner than this are requested again
+const EXPIRY_MARGIN_SECS: i64 = 30;
+const DEFAULT_PREFIX: &str = "sudo -S -p ''";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ElevatedCredential {
+    pub token: String,
+    /// UNIX time in seconds
+    pub expires_at: i64,
+}
+impl ElevatedCredential {
+    pub fn is_fresh(&self, now: i64) -> bool {
+        self.expires_at - EXPIRY_MARGIN_SECS > now
+    }
+}
+/// Credentials keyed by the route of the DUT, reused until they expire. Only the user can read
+/// the file.
+static ELEVATED_CREDENTIALS: KvCache<ElevatedCredential> =
+    KvCache::new_private("private/elevated_credentials");
+
+/// Something that issues elevated credentials for a DUT (e.g. after a touch of a security key)
+pub trait AuthBroker {
+    fn request(&self, dut: &str, action: &str) -> Result<ElevatedCredential>;
+}
+
+/// Runs a user-provided command with LIUM_DUT and LIUM_ACTION set. It can interact with the user
+/// via the terminal and prints either {"token": "...", "expires_in": <secs>} or a token.
+pub struct CommandBroker {
+    command: String,
+}
+impl CommandBroker {
+    pub fn new(command: &str) -> Self {
+        Self {
+            command: command.to_string(),
+        }
+    }
+}
+impl AuthBroker for CommandBroker {
+    fn request(&self, dut: &str, action: &str) -> Result<ElevatedCredential> {
+        let output = Command::new("bash")
+            .arg("-c")
+            .arg(&self.command)
+            .env("LIUM_DUT", dut)
+            .env("LIUM_ACTION", action)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()
+            .context(anyhow!("Failed to run the auth broker {:?}", self.command))?;
+        output
+            .status
+            .exit_ok()
+            .context(anyhow!("The auth broker {:?} failed", self.command))?;
+        parse_broker_output(
+            &String::from_utf8_lossy(&output.stdout),
+            Local::now().timestamp(),
+        )
+    }
+}
+
+fn parse_broker_output(stdout: &str, now: i64) -> Result<ElevatedCredential> {
+    #[derive(Deserialize)]
+    struct BrokerResponse {
+        token: String,
+        expires_in: Option<i64>,
+    }
+    let stdout = stdout.trim();
+    let (token, lifetime) = match serde_json::from_str::<BrokerResponse>(stdout) {
+        Ok(r) => (r.token, r.expires_in.unwrap_or(DEFAULT_LIFETIME_SECS)),
+        Err(_) if !stdout.is_empty() && !stdout.contains('\n') => {
+            (stdout.to_string(), DEFAULT_LIFETIME_SECS)
+        }
+        Err(_) => return Err(anyhow!("The auth broker returned no token")),
+    };
+    Ok(ElevatedCredential {
+        token,
+        expires_at: now + lifetime,
+    })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct EscalationPolicy {
+    /// Command of the CommandBroker
+    broker: String,
+    /// Command on the DUT that reads the credential from stdin and runs the given command
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    prefix: Option<String>,
+}
+impl EscalationPolicy {
+    pub fn new(broker: &str, prefix: Option<&str>) -> Self {
+        Self {
+            broker: broker.to_string(),
+            prefix: prefix.map(|s| s.to_string()),
+        }
+    }
+    pub fn broker(&self) -> Box<dyn AuthBroker> {
+        Box::new(CommandBroker::new(&self.broker))
+    }
+    pub fn prefix(&self) -> &str {
+        self.prefix.as_deref().unwrap_or(DEFAULT_PREFIX)
+    }
+}
+
+/// Returns a fresh credential for the DUT, asking the broker only if the cached one is stale
+pub fn credential_for(
+    broker: &dyn AuthBroker,
+    dut: &str,
+    action: &str,
+) -> Result<ElevatedCredential> {
+    let now = Local::now().timestamp();
+    if let Some(cred) = ELEVATED_CREDENTIALS.get(dut)? {
+        if cred.is_fresh(now) {
+            return Ok(cred);
+        }
+    }
+    status!("Requesting elevated credentials to {action} on {dut}...");
+    let cred = broker.request(dut, action)?;
+    if !cred.is_fresh(now) {
+        return Err(anyhow!("The auth broker returned an expired credential"));
+    }
+    ELEVATED_CREDENTIALS.set(dut, cred.clone())?;
+    Ok(cred)
+}
+
+/// Drops the cached credential, e.g. after it was rejected
+pub fn forget_credential(dut: &str) -> Result<()> {
+    ELEVATED_CREDENTIALS.remove(dut).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broker_output() {
+        let cred = parse_broker_output(r#"{"token": "abc", "expires_in": 60}"#, 1000).unwrap();
+        assert_eq!(
+            cred,
+            ElevatedCredential {
+                token: "abc".to_string(),
+                expires_at: 1060
+            }
+        );
+        assert!(cred.is_fresh(1000));
+        assert!(!cred.is_fresh(1040));
+        assert_eq!(parse_broker_output("xyz\n", 0).unwrap().expires_at, 300);
+        assert!(parse_broker_output("", 0).is_err());
+        assert!(parse_broker_output("a\nb", 0).is_err());
+    }
+}
@@ -0,0 +1,73 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Events about DUTs from background processes (e.g. the supervisors of `lium forward` and
+//! `lium dut agent watch`). Each line of ~/.lium/events.jsonl is a DutEvent, so that other
+//! processes can follow them with `lium dut events --follow`.
+
+use crate::status;
+use crate::util::gen_path_in_lium_dir;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs::read_to_string;
+use std::fs::write;
+use std::fs::OpenOptions;
+use std::io::Write;
+
+const EVENT_LOG: &str = "events.jsonl";
+/// The log is trimmed to the latest half of this when it gets longer
+const MAX_EVENTS: usize = 2000;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct DutEvent {
+    /// UNIX time in seconds
+    pub at: i64,
+    pub dut: String,
+    /// e.g. "rebooted", "tunnel_down", "tunnel_restored"
+    pub kind: String,
+    pub message: String,
+}
+
+/// Records the event and prints it as a status message
+pub fn emit(dut: &str, kind: &str, message: &str) -> Result<()> {
+    status!("{dut}: {message}");
+    let event = DutEvent {
+        at: Local::now().timestamp(),
+        dut: dut.to_string(),
+        kind: kind.to_string(),
+        message: message.to_string(),
+    };
+    let path = gen_path_in_lium_dir(EVENT_LOG)?;
+    let mut f = OpenOptions::new().create(true).append(true).open(&path)?;
+    writeln!(f, "{}", serde_json::to_string(&event)?)?;
+    drop(f);
+    let log = read_to_string(&path)?;
+    let lines: Vec<&str> = log.lines().collect();
+    if lines.len() > MAX_EVENTS {
+        write(
+            &path,
+            lines[lines.len() - MAX_EVENTS / 2..].join("\n") + "\n",
+        )?;
+    }
+    Ok(())
+}
+
+/// Returns the recorded events, oldest first
+pub fn read_events() -> Result<Vec<DutEvent>> {
+    let path = gen_path_in_lium_dir(EVENT_LOG)?;
+    let log = match read_to_string(path) {
+        Ok(log) => log,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    // Skip broken lines (e.g. written by two processes at once)
+    Ok(log
+        .lines()
+        .filter_map(|l| serde_json::from_str(l).ok())
+        .collect())
+}
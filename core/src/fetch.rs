@@ -0,0 +1,354 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Downloads of artifacts (symbols, releases...) from gs:// or http(s)://.
+//!
+//! - Mirrors set with `lium config set artifact_mirror <prefix> <mirror>...` (e.g. a regional
+//!   bucket) are tried before the original URL.
+//! - gs:// objects are fetched with sliced (parallel) downloads of gsutil, which resumes them
+//!   with its tracker files when the download is run again.
+//! - Large http(s):// files are fetched in parallel ranges if the server supports them. The
+//!   parts are kept on failures, so the next attempt continues from where it stopped instead
+//!   of starting from zero.
+//! - Downloaded bytes are accounted per session ($LIUM_DOWNLOAD_SESSION, or the date) and
+//!   limited by `lium config set download_quota_mb`. See `lium cache downloads`.
+
+use crate::cache::KvCache;
+use crate::config::Config;
+use crate::status;
+use crate::util::ensure_online;
+use crate::warning;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread;
+use std::time::Instant;
+
+pub const SESSION_ENV: &str = "LIUM_DOWNLOAD_SESSION";
+const MIN_PART_SIZE: u64 = 16 * 1024 * 1024;
+const MAX_PARTS: u64 = 8;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct DownloadStats {
+    pub bytes: u64,
+    pub files: u64,
+    pub seconds: f64,
+    /// Bytes per source (e.g. gs://bucket or https://host)
+    pub by_source: BTreeMap<String, u64>,
+}
+/// Download stats keyed by session
+pub static DOWNLOAD_STATS: KvCache<DownloadStats> = KvCache::new("download_stats");
+
+/// Returns the current download session
+pub fn session_id() -> String {
+    std::env::var(SESSION_ENV).unwrap_or_else(|_| Local::now().format("%Y-%m-%d").to_string())
+}
+
+/// Returns the URLs to try for `url`: the mirrors of the longest matching prefix, then itself
+pub fn candidate_urls(url: &str, mirrors: &HashMap<String, Vec<String>>) -> Vec<String> {
+    let mut candidates: Vec<String> = mirrors
+        .iter()
+        .filter(|(prefix, _)| url.starts_with(prefix.as_str()))
+        .max_by_key(|(prefix, _)| prefix.len())
+        .map(|(prefix, mirrors)| {
+            mirrors
+                .iter()
+                .map(|m| format!("{m}{}", &url[prefix.len()..]))
+                .collect()
+        })
+        .unwrap_or_default();
+    candidates.push(url.to_string());
+    candidates
+}
+
+/// Returns the scheme and the bucket or the host of the URL
+fn source_of(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    format!("{scheme}://{}", rest.split('/').next().unwrap_or_default())
+}
+
+/// Returns the last Content-Length in the headers (of the final response after redirects)
+fn parse_content_length(headers: &str) -> Option<u64> {
+    headers
+        .lines()
+        .filter_map(|l| l.trim().split_once(':'))
+        .filter(|(k, _)| k.trim().eq_ignore_ascii_case("content-length"))
+        .filter_map(|(_, v)| v.trim().parse().ok())
+        .last()
+}
+
+fn accepts_ranges(headers: &str) -> bool {
+    headers
+        .lines()
+        .filter_map(|l| l.split_once(':'))
+        .any(|(k, v)| k.trim().eq_ignore_ascii_case("accept-ranges") && v.trim() == "bytes")
+}
+
+/// Splits `size` bytes into `parts` inclusive ranges
+fn split_ranges(size: u64, parts: u64) -> Vec<(u64, u64)> {
+    let part_size = (size + parts - 1) / parts;
+    (0..parts)
+        .map(|i| (i * part_size, ((i + 1) * part_size).min(size) - 1))
+        .filter(|(start, end)| start <= end)
+        .collect()
+}
+
+/// Returns the MD5 (in hex) published by GCS, from `gsutil stat` ("Hash (md5): <base64>") or
+/// the x-goog-hash header of HTTP responses
+fn parse_md5(headers: &str) -> Option<String> {
+    let encoded = headers.lines().find_map(|l| {
+        let (k, v) = l.trim().split_once(':')?;
+        if k.trim() == "Hash (md5)" {
+            Some(v.trim())
+        } else if k.trim().eq_ignore_ascii_case("x-goog-hash") {
+            v.split(',').find_map(|h| h.trim().strip_prefix("md5="))
+        } else {
+            None
+        }
+    })?;
+    let bytes = STANDARD.decode(encoded).ok()?;
+    Some(bytes.iter().map(|b| format!("{b:02x}")).collect())
+}
+
+/// Returns the output of `gsutil stat` or the HTTP headers of the URL
+fn remote_headers(url: &str) -> Result<String> {
+    let output = if url.starts_with("gs://") {
+        Command::new("gsutil.py").args(["stat", url]).output()
+    } else {
+        Command::new("curl").args(["-sfIL", url]).output()
+    }
+    .context("Failed to run gsutil.py or curl")?;
+    output.status.exit_ok().context("Not found")?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Returns the size of the file if known, and whether range requests are supported. The size is
+/// unknown for e.g. servers that reject HEAD or send chunked responses.
+fn remote_size(url: &str) -> (Option<u64>, bool) {
+    match remote_headers(url) {
+        Ok(headers) => (
+            parse_content_length(&headers),
+            url.starts_with("gs://") || accepts_ranges(&headers),
+        ),
+        Err(_) => (None, false),
+    }
+}
+
+/// Returns the MD5 (in hex) of the object that the origin (not a mirror) publishes, if any
+pub fn remote_md5(url: &str) -> Result<Option<String>> {
+    ensure_online("Checking artifacts")?;
+    Ok(parse_md5(&remote_headers(url)?))
+}
+
+fn fetch_gs(url: &str, dest: &Path) -> Result<()> {
+    Command::new("gsutil.py")
+        .args(["-o", "GSUtil:sliced_object_download_threshold=64M"])
+        .args([
+            "-o",
+            &format!("GSUtil:sliced_object_download_max_components={MAX_PARTS}"),
+        ])
+        .args(["-q", "cp", url])
+        .arg(dest)
+        .status()
+        .context("Failed to run gsutil.py (maybe you need depot_tools)")?
+        .exit_ok()
+        .context("gsutil.py failed")
+}
+
+fn part_path(dest: &Path, i: usize) -> PathBuf {
+    let name = dest.file_name().unwrap_or_default().to_string_lossy();
+    dest.with_file_name(format!(".{name}.part{i}"))
+}
+
+/// Appends the rest of the range to the part, which may have been fetched partially before
+fn fetch_range(url: &str, part: &Path, (start, end): (u64, u64)) -> Result<()> {
+    let len = end - start + 1;
+    let mut have = fs::metadata(part).map(|m| m.len()).unwrap_or(0);
+    if have > len {
+        fs::remove_file(part)?;
+        have = 0;
+    }
+    if have < len {
+        let file = OpenOptions::new().create(true).append(true).open(part)?;
+        Command::new("curl")
+            .args(["-fsSL", "-r", &format!("{}-{end}", start + have), url])
+            .stdout(file)
+            .status()
+            .context("Failed to run curl")?
+            .exit_ok()
+            .context(anyhow!("Failed to fetch bytes {start}-{end}"))?;
+    }
+    if fs::metadata(part)?.len() != len {
+        return Err(anyhow!(
+            "The server returned an unexpected size for {start}-{end}"
+        ));
+    }
+    Ok(())
+}
+
+fn fetch_http(url: &str, dest: &Path, size: Option<u64>, ranges: bool) -> Result<()> {
+    let size = match size {
+        Some(size) if ranges && size >= MIN_PART_SIZE * 2 => size,
+        _ => {
+            return Command::new("curl")
+                .args(["-fsSL", "-o"])
+                .arg(dest)
+                .arg(url)
+                .status()
+                .context("Failed to run curl")?
+                .exit_ok()
+                .context("curl failed");
+        }
+    };
+    let ranges = split_ranges(size, (size / MIN_PART_SIZE).min(MAX_PARTS));
+    thread::scope(|s| {
+        let handles: Vec<_> = ranges
+            .iter()
+            .enumerate()
+            .map(|(i, range)| s.spawn(move || fetch_range(url, &part_path(dest, i), *range)))
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| {
+                h.join()
+                    .unwrap_or_else(|_| Err(anyhow!("A download thread panicked")))
+            })
+            .collect::<Result<Vec<()>>>()
+    })?;
+    let mut file = fs::File::create(dest)?;
+    for i in 0..ranges.len() {
+        std::io::copy(&mut fs::File::open(part_path(dest, i))?, &mut file)?;
+    }
+    for i in 0..ranges.len() {
+        fs::remove_file(part_path(dest, i))?;
+    }
+    Ok(())
+}
+
+fn record(session: &str, source: &str, bytes: u64, seconds: f64) -> Result<()> {
+    let mut stats = DOWNLOAD_STATS.get(session)?.unwrap_or_default();
+    stats.bytes += bytes;
+    stats.files += 1;
+    stats.seconds += seconds;
+    *stats.by_source.entry(source.to_string()).or_default() += bytes;
+    DOWNLOAD_STATS.set(session, stats)
+}
+
+/// Downloads the file at the URL (gs:// or http(s)://) to `dest`, trying its mirrors first
+pub fn download(url: &str, dest: &Path) -> Result<()> {
+    ensure_online("Downloading artifacts")?;
+    let config = Config::read()?;
+    let session = session_id();
+    let mut errors = Vec::new();
+    for candidate in candidate_urls(url, config.artifact_mirrors()) {
+        let (size, ranges) = remote_size(&candidate);
+        let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+        if let Some(quota) = config.download_quota_bytes() {
+            let used = DOWNLOAD_STATS.get(&session)?.unwrap_or_default().bytes;
+            // A file of unknown size is accounted after the download
+            if used + size.unwrap_or(0) > quota {
+                return Err(anyhow!(
+                    "Downloading {candidate} ({:.1} MB) exceeds the quota of session {session} ({:.1} of {:.1} MB used). Raise it with `lium config set download_quota_mb <MB>` or set ${SESSION_ENV}",
+                    mb(size.unwrap_or(0)),
+                    mb(used),
+                    mb(quota)
+                ));
+            }
+        }
+        match size {
+            Some(size) => status!("Downloading {candidate} ({:.1} MB)...", mb(size)),
+            None => status!("Downloading {candidate}..."),
+        }
+        let start = Instant::now();
+        let result = if candidate.starts_with("gs://") {
+            fetch_gs(&candidate, dest)
+        } else {
+            fetch_http(&candidate, dest, size, ranges)
+        };
+        match result {
+            Ok(()) => {
+                let seconds = start.elapsed().as_secs_f64();
+                let size = fs::metadata(dest)?.len();
+                record(&session, &source_of(&candidate), size, seconds)?;
+                status!(
+                    "Downloaded {:.1} MB in {seconds:.1} sec ({:.1} MB/s)",
+                    mb(size),
+                    mb(size) / seconds.max(0.001)
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                warning!("Failed to download {candidate}: {e:#}");
+                errors.push(format!("{candidate}: {e:#}"));
+            }
+        }
+    }
+    Err(anyhow!(
+        "Failed to download {url}. Run the command again to resume.\n{}",
+        errors.join("\n")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fetch_helpers() {
+        let mirrors = HashMap::from([
+            (
+                "gs://chromeos-image-archive/".to_string(),
+                vec!["gs://archive-asia/".to_string()],
+            ),
+            (
+                "gs://chromeos-image-archive/eve-release/".to_string(),
+                vec!["https://mirror.lab/eve/".to_string()],
+            ),
+        ]);
+        assert_eq!(
+            candidate_urls("gs://chromeos-image-archive/eve-release/R1/a.tgz", &mirrors),
+            vec![
+                "https://mirror.lab/eve/R1/a.tgz",
+                "gs://chromeos-image-archive/eve-release/R1/a.tgz"
+            ]
+        );
+        assert_eq!(
+            candidate_urls("https://example.com/a", &mirrors),
+            vec!["https://example.com/a"]
+        );
+        assert_eq!(source_of("gs://bucket/a/b"), "gs://bucket");
+        let headers = "HTTP/1.1 302 Found\r\nContent-Length: 0\r\n\r\nHTTP/1.1 200 OK\r\ncontent-length: 1234\r\nAccept-Ranges: bytes\r\n";
+        assert_eq!(parse_content_length(headers), Some(1234));
+        assert!(accepts_ranges(headers));
+        assert_eq!(
+            parse_content_length("    Content-Length:         5678\n"),
+            Some(5678)
+        );
+        assert_eq!(
+            parse_md5("    Hash (crc32c):          AAAAAA==\n    Hash (md5):             1B2M2Y8AsgTpgAmY7PhCfg==\n"),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        assert_eq!(
+            parse_md5("x-goog-hash: crc32c=AAAAAA==, md5=1B2M2Y8AsgTpgAmY7PhCfg==\r\n"),
+            Some("d41d8cd98f00b204e9800998ecf8427e".to_string())
+        );
+        assert_eq!(parse_md5(headers), None);
+        assert_eq!(split_ranges(10, 3), vec![(0, 3), (4, 7), (8, 9)]);
+        assert_eq!(split_ranges(4, 8), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+    }
+}
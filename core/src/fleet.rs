@@ -0,0 +1,168 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Snapshots of the status of all the DUTs in SSH_CACHE for long-term analytics of the lab
+//! (`lium fleet snapshot`). A snapshot is a JSON document with a schema version, or rows of a
+//! BigQuery table (one per DUT).
+
+use crate::dut::known_duts;
+use crate::dut::MAX_PARALLEL_SSH;
+use crate::statusd;
+use crate::statusd::DutState;
+use crate::util::ensure_online;
+use crate::util::run_concurrently;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use chrono::TimeZone;
+use chrono::Utc;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_json::json;
+use std::io::Write;
+use std::process::Command;
+use std::process::Stdio;
+
+/// Incremented on incompatible changes of FleetSnapshot and the BigQuery rows
+pub const SNAPSHOT_SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DutRecord {
+    pub id: String,
+    /// host:port in SSH_CACHE
+    pub address: String,
+    #[serde(flatten)]
+    pub state: DutState,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FleetSnapshot {
+    pub schema_version: u32,
+    /// UNIX time in seconds
+    pub taken_at: i64,
+    /// Host name of the machine that took the snapshot
+    pub taken_by: String,
+    /// Sorted by the ID
+    pub duts: Vec<DutRecord>,
+}
+impl FleetSnapshot {
+    /// Returns a file name for the snapshot (e.g. fleet_20231114T221320Z.json)
+    pub fn file_name(&self) -> String {
+        let at = Utc
+            .timestamp_opt(self.taken_at, 0)
+            .single()
+            .unwrap_or_default();
+        format!("fleet_{}.json", at.format("%Y%m%dT%H%M%SZ"))
+    }
+    /// Returns the rows for BigQuery. The info is kept as a JSON string since its keys vary.
+    pub fn bigquery_rows(&self) -> Vec<serde_json::Value> {
+        self.duts
+            .iter()
+            .map(|d| {
+                json!({
+                    "schema_version": self.schema_version,
+                    "taken_at": self.taken_at,
+                    "taken_by": self.taken_by,
+                    "id": d.id,
+                    "address": d.address,
+                    "status": d.state.status,
+                    "checked_at": d.state.checked_at,
+                    "board": d.state.info.get("board"),
+                    "model": d.state.info.get("model"),
+                    "release": d.state.info.get("release"),
+                    "info": serde_json::to_string(&d.state.info).unwrap_or_default(),
+                })
+            })
+            .collect()
+    }
+}
+
+/// Checks all the DUTs in SSH_CACHE. The states in statusd are used if it is running.
+pub fn take_snapshot() -> Result<FleetSnapshot> {
+    let duts = known_duts()?;
+    let known = statusd::query(None).unwrap_or_default();
+    if known.is_empty() {
+        ensure_online("Checking status of DUTs")?;
+    }
+    let mut records: Vec<DutRecord> = run_concurrently(&duts, MAX_PARALLEL_SSH, |(id, ssh)| {
+        let known = &known;
+        async move {
+            let state = match known.get(id) {
+                Some(state) => state.clone(),
+                None => statusd::check(id).await,
+            };
+            DutRecord {
+                id: id.clone(),
+                address: ssh.host_and_port(),
+                state,
+            }
+        }
+    });
+    records.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(FleetSnapshot {
+        schema_version: SNAPSHOT_SCHEMA_VERSION,
+        taken_at: Local::now().timestamp(),
+        taken_by: nix::unistd::gethostname()?.to_string_lossy().to_string(),
+        duts: records,
+    })
+}
+
+/// Inserts the rows of the snapshot into a BigQuery table (e.g. lab.fleet_status) with `bq`
+pub fn insert_into_bigquery(snapshot: &FleetSnapshot, table: &str) -> Result<()> {
+    ensure_online("Inserting the snapshot into BigQuery")?;
+    let rows: Vec<String> = snapshot
+        .bigquery_rows()
+        .iter()
+        .map(|r| r.to_string())
+        .collect();
+    let mut child = Command::new("bq")
+        .args(["insert", table])
+        .stdin(Stdio::piped())
+        .spawn()
+        .context("Failed to run bq (install the Google Cloud SDK)")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to get stdin")?
+        .write_all((rows.join("\n") + "\n").as_bytes())?;
+    child
+        .wait()?
+        .exit_ok()
+        .context(anyhow!("Failed to insert the snapshot into {table}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn snapshot_format() {
+        let snapshot = FleetSnapshot {
+            schema_version: SNAPSHOT_SCHEMA_VERSION,
+            taken_at: 1700000000,
+            taken_by: "ws".to_string(),
+            duts: vec![DutRecord {
+                id: "eve_XXX".to_string(),
+                address: "10.0.0.1:22".to_string(),
+                state: DutState {
+                    status: "Online".to_string(),
+                    info: HashMap::from([("board".to_string(), "eve".to_string())]),
+                    checked_at: 1699999990,
+                },
+            }],
+        };
+        assert_eq!(snapshot.file_name(), "fleet_20231114T221320Z.json");
+        let json = serde_json::to_value(&snapshot).unwrap();
+        assert_eq!(json["schema_version"], 1);
+        assert_eq!(json["duts"][0]["status"], "Online");
+        let rows = snapshot.bigquery_rows();
+        assert_eq!(rows[0]["board"], "eve");
+        assert_eq!(rows[0]["model"], serde_json::Value::Null);
+        assert_eq!(rows[0]["info"], r#"{"board":"eve"}"#);
+    }
+}
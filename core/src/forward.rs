@@ -0,0 +1,251 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Named port forwardings that keep running after `lium forward add` exits. Each forward has a
+//! supervisor process (`lium forward supervise`) that runs ssh -L and restarts it with a backoff
+//! when it fails. The supervisor reports its state in ~/.lium/forward_states/<name>.json for
+//! `lium forward list`.
+
+use crate::cache::KvCache;
+use crate::cancel;
+use crate::dut::SshInfo;
+use crate::events;
+use crate::ports::PortAllocator;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::is_process_alive;
+use crate::watchdog::wait_for_ssh;
+use crate::watchdog::RebootWatch;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use futures::executor::block_on;
+use futures::AsyncReadExt;
+use nix::sys::signal::signal;
+use nix::sys::signal::SigHandler;
+use nix::sys::signal::Signal;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fmt;
+use std::fs;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(2);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A forwarding that stayed up this long resets the backoff
+const STABLE_DURATION: Duration = Duration::from_secs(60);
+/// How long to wait for a rebooting DUT before counting it as a failure
+const SSH_WAIT_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForwardSpec {
+    pub dut: String,
+    pub local: u16,
+    pub remote: u16,
+}
+/// Forwards keyed by the name
+pub static FORWARDS: KvCache<ForwardSpec> = KvCache::new("forwards");
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ForwardStatus {
+    Connecting,
+    Up,
+    /// Waiting for the backoff after a failure
+    Restarting,
+}
+impl fmt::Display for ForwardStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Connecting => "connecting",
+            Self::Up => "up",
+            Self::Restarting => "restarting",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// The state reported by the supervisor of a forward
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct ForwardState {
+    pub pid: u32,
+    pub status: ForwardStatus,
+    pub restarts: u32,
+    /// UNIX time in seconds of the last change of the status
+    pub since: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+fn state_path(name: &str) -> Result<PathBuf> {
+    gen_path_in_lium_dir(&format!("forward_states/{name}.json"))
+}
+
+/// Returns the state of the forward if its supervisor is running
+pub fn read_state(name: &str) -> Option<ForwardState> {
+    let state: ForwardState =
+        serde_json::from_str(&fs::read_to_string(state_path(name).ok()?).ok()?).ok()?;
+    is_process_alive(state.pid).then_some(state)
+}
+
+pub fn remove_state(name: &str) -> Result<()> {
+    match fs::remove_file(state_path(name)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn write_state(name: &str, state: &ForwardState) -> Result<()> {
+    // Rename to avoid showing a partially written state
+    let path = state_path(name)?;
+    let tmp = path.with_extension("json.tmp");
+    fs::write(&tmp, serde_json::to_string(state)?)?;
+    fs::rename(tmp, path)?;
+    Ok(())
+}
+
+fn set_status(name: &str, state: &mut ForwardState, status: ForwardStatus) -> Result<()> {
+    state.status = status;
+    state.since = Local::now().timestamp();
+    write_state(name, state)
+}
+
+/// Returns the backoff before the next restart
+fn next_backoff(prev: Duration, uptime: Duration) -> Duration {
+    if uptime >= STABLE_DURATION {
+        MIN_BACKOFF
+    } else {
+        (prev * 2).clamp(MIN_BACKOFF, MAX_BACKOFF)
+    }
+}
+
+/// Runs the forwarding until cancelled (SIGTERM from `lium forward rm`), restarting it on
+/// failures
+pub fn supervise(name: &str) -> Result<()> {
+    let spec = FORWARDS
+        .get(name)?
+        .context(anyhow!("Forward {name} does not exist"))?;
+    cancel::install_handler()?;
+    // Survive the terminal that ran `lium forward add`
+    unsafe { signal(Signal::SIGHUP, SigHandler::SigIgn) }?;
+    let mut state = ForwardState {
+        pid: std::process::id(),
+        status: ForwardStatus::Connecting,
+        restarts: 0,
+        since: Local::now().timestamp(),
+        last_error: None,
+    };
+    set_status(name, &mut state, ForwardStatus::Connecting)?;
+    let allocator = PortAllocator::new(spec.local..spec.local.saturating_add(1))?;
+    let mut backoff = Duration::ZERO;
+    let mut reboot = RebootWatch::new(&spec.dut);
+    let result = loop {
+        let started = Instant::now();
+        let error = match allocator.try_allocate(spec.local, &format!("forward {name}")) {
+            Ok(Some(_allocation)) => {
+                // The allocation is held while ssh is running
+                match run_once(name, &spec, &mut state, &mut reboot) {
+                    Ok(error) => error,
+                    Err(e) => break Err(e),
+                }
+            }
+            Ok(None) => format!("Local port {} is in use", spec.local),
+            Err(e) => break Err(e),
+        };
+        if cancel::is_cancelled() {
+            break Ok(());
+        }
+        if state.status == ForwardStatus::Up {
+            events::emit(
+                &spec.dut,
+                "forward_down",
+                &format!("Forward {name} is down: {error}"),
+            )?;
+        }
+        state.restarts += 1;
+        state.last_error = Some(error);
+        set_status(name, &mut state, ForwardStatus::Restarting)?;
+        backoff = next_backoff(backoff, started.elapsed());
+        if cancel::sleep(backoff).is_err() {
+            break Ok(());
+        }
+        set_status(name, &mut state, ForwardStatus::Connecting)?;
+    };
+    remove_state(name)?;
+    result
+}
+
+/// Runs ssh until it exits or the supervisor is cancelled. Returns the reason of the exit.
+fn run_once(
+    name: &str,
+    spec: &ForwardSpec,
+    state: &mut ForwardState,
+    reboot: &mut RebootWatch,
+) -> Result<String> {
+    let ssh = match SshInfo::new(&spec.dut) {
+        Ok(ssh) => ssh,
+        Err(e) => return Ok(format!("{e:#}")),
+    };
+    // Wait for a rebooting DUT instead of burning the backoff
+    if !wait_for_ssh(&ssh, SSH_WAIT_TIMEOUT)? {
+        return Ok(format!("{} is not reachable", spec.dut));
+    }
+    let mut child = match ssh.start_port_forwarding(spec.local, spec.remote, "sleep 8h") {
+        Ok(child) => child,
+        Err(e) => return Ok(format!("{e:#}")),
+    };
+    let mut is_up = false;
+    loop {
+        if let Some(status) = child.try_status()? {
+            let mut stderr = String::new();
+            if let Some(mut s) = child.stderr.take() {
+                let _ = block_on(s.read_to_string(&mut stderr));
+            }
+            let stderr = stderr.trim();
+            return Ok(if stderr.is_empty() {
+                format!("ssh exited with {status}")
+            } else {
+                format!("ssh exited with {status}: {stderr}")
+            });
+        }
+        if !is_up && TcpStream::connect(("127.0.0.1", spec.local)).is_ok() {
+            is_up = true;
+            if state.restarts > 0 {
+                events::emit(
+                    &spec.dut,
+                    "forward_restored",
+                    &format!("Forward {name} is up"),
+                )?;
+            }
+            set_status(name, state, ForwardStatus::Up)?;
+            if let Err(e) = reboot.observe(&ssh) {
+                state.last_error = Some(format!("{e:#}"));
+                write_state(name, state)?;
+            }
+        }
+        if cancel::sleep(Duration::from_secs(1)).is_err() {
+            // Dropping the child stops ssh
+            return Ok(format!("{name} is stopped"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff() {
+        let s = Duration::from_secs;
+        assert_eq!(next_backoff(Duration::ZERO, s(0)), s(2));
+        assert_eq!(next_backoff(s(2), s(1)), s(4));
+        assert_eq!(next_backoff(s(40), s(1)), s(60));
+        assert_eq!(next_backoff(s(60), s(120)), s(2));
+    }
+}
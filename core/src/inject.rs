@@ -0,0 +1,168 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Simulated hardware states on DUTs (`lium dut inject`). The AC and battery states are faked
+//! by the EC (ectool chargecontrol / battfake), and an upstart job applies them again after
+//! reboots until the session is ended. The lid is closed via the EC console of servo.
+
+use crate::boards::ensure_ec;
+use crate::cache::KvCache;
+use crate::dut::SshInfo;
+use crate::servo::get_cr50_attached_to_servo;
+use crate::servo::LocalServo;
+use crate::servo::ServoList;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct InjectSession {
+    /// false to discharge the battery as if the AC was unplugged
+    pub power_ac: Option<bool>,
+    /// battery level in percent
+    pub battery: Option<u8>,
+    pub lid_closed: Option<bool>,
+    /// serial of the servo used for the lid
+    pub servo: Option<String>,
+    /// UNIX time in seconds
+    pub started_at: i64,
+}
+/// Active sessions keyed by host:port of the DUT
+pub static INJECT_SESSIONS: KvCache<InjectSession> = KvCache::new("inject_sessions");
+
+const INJECT_DIR: &str = "/usr/local/lium_inject";
+const INJECT_JOB: &str = "lium-inject";
+
+/// Parses a battery level like "15%" or "15"
+pub fn parse_battery(s: &str) -> Result<u8> {
+    let level: u8 = s
+        .trim_end_matches('%')
+        .parse()
+        .context(anyhow!("Invalid battery level {s:?} (e.g. 15%)"))?;
+    if level > 100 {
+        return Err(anyhow!("Battery level {s:?} is out of range"));
+    }
+    Ok(level)
+}
+
+impl InjectSession {
+    /// Overwrites the states given in `other`
+    pub fn merge(&mut self, other: &InjectSession) {
+        self.power_ac = other.power_ac.or(self.power_ac);
+        self.battery = other.battery.or(self.battery);
+        self.lid_closed = other.lid_closed.or(self.lid_closed);
+        self.servo = other.servo.clone().or_else(|| self.servo.clone());
+    }
+    /// Returns the shell commands that apply the states handled by the EC
+    fn apply_script(&self) -> String {
+        let mut script = String::new();
+        match self.power_ac {
+            Some(false) => script += "ectool chargecontrol discharge\n",
+            Some(true) => script += "ectool chargecontrol normal\n",
+            None => {}
+        }
+        if let Some(level) = self.battery {
+            script += &format!("ectool battfake {level}\n");
+        }
+        script
+    }
+    fn install_script(&self) -> String {
+        let apply = self.apply_script();
+        format!(
+            r#"set -e
+mount -o remount,rw / 2>/dev/null || true
+mkdir -p {INJECT_DIR}
+cat > {INJECT_DIR}/apply.sh <<'LIUM_EOF'
+#!/bin/sh
+# Generated by lium. Applies the states of `lium dut inject` again after reboots.
+{apply}LIUM_EOF
+chmod 755 {INJECT_DIR}/apply.sh
+cat > /etc/init/{INJECT_JOB}.conf <<'LIUM_EOF'
+description "lium dut inject"
+start on started system-services
+task
+exec {INJECT_DIR}/apply.sh
+LIUM_EOF
+{INJECT_DIR}/apply.sh
+"#
+        )
+    }
+    /// Returns the shell commands that restore the real states
+    fn restore_script(&self) -> String {
+        format!(
+            r#"mount -o remount,rw / 2>/dev/null
+rm -f /etc/init/{INJECT_JOB}.conf
+rm -rf {INJECT_DIR}
+ectool chargecontrol normal
+ectool battfake -1
+true
+"#
+        )
+    }
+    fn ec_console(&self) -> Result<LocalServo> {
+        let serial = self
+            .servo
+            .as_ref()
+            .context("Simulating the lid needs --servo")?;
+        let servo = ServoList::read()?.find_by_serial(serial)?.clone();
+        if servo.is_cr50() {
+            Ok(servo)
+        } else {
+            get_cr50_attached_to_servo(&servo)
+        }
+    }
+    /// Applies the states to the DUT
+    pub fn apply(&self, ssh: &SshInfo) -> Result<()> {
+        if !self.apply_script().is_empty() {
+            ensure_ec(ssh, "Simulating the AC and battery states")?;
+            ssh.run_cmd_stdio(&self.install_script())
+                .context("Failed to apply the states (does the DUT have an EC?)")?;
+        }
+        if let Some(closed) = self.lid_closed {
+            let cmd = if closed { "lidclose" } else { "lidopen" };
+            self.ec_console()?.run_cmd("EC", cmd)?;
+        }
+        Ok(())
+    }
+    /// Restores the real states of the DUT
+    pub fn restore(&self, ssh: &SshInfo) -> Result<()> {
+        ssh.run_cmd_stdio(&self.restore_script())?;
+        if self.lid_closed == Some(true) {
+            self.ec_console()?.run_cmd("EC", "lidopen")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inject_session() {
+        assert_eq!(parse_battery("15%").unwrap(), 15);
+        assert_eq!(parse_battery("100").unwrap(), 100);
+        assert!(parse_battery("101%").is_err());
+        assert!(parse_battery("-1").is_err());
+
+        let mut session = InjectSession {
+            power_ac: Some(false),
+            battery: Some(50),
+            ..Default::default()
+        };
+        session.merge(&InjectSession {
+            battery: Some(15),
+            ..Default::default()
+        });
+        assert_eq!(session.power_ac, Some(false));
+        assert_eq!(
+            session.apply_script(),
+            "ectool chargecontrol discharge\nectool battfake 15\n"
+        );
+    }
+}
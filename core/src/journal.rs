@@ -0,0 +1,110 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A journal of multi-step operations (e.g. `lium dut powerwash`), so that an operation
+//! interrupted by a network drop or Ctrl-C can be continued with `lium resume <op-id>` instead
+//! of starting over.
+//!
+//! `lium resume` runs the recorded command again with $LIUM_RESUME_OP set, and the steps
+//! completed in the previous runs are skipped.
+
+use crate::cache::KvCache;
+use crate::status;
+use anyhow::anyhow;
+use anyhow::Result;
+use chrono::Local;
+use rand::Rng;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+
+pub const RESUME_ENV: &str = "LIUM_RESUME_OP";
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// Arguments of lium to run the operation again
+    pub command: Vec<String>,
+    pub dut: Option<String>,
+    /// UNIX time in seconds
+    pub started_at: i64,
+    pub updated_at: i64,
+    pub done_steps: Vec<String>,
+    /// Values that the remaining steps depend on (e.g. the boot ID before a reboot)
+    #[serde(default)]
+    pub values: HashMap<String, String>,
+}
+
+/// Unfinished operations keyed by the op-id
+pub static OP_JOURNAL: KvCache<JournalEntry> = KvCache::new("op_journal");
+
+pub struct Journal {
+    id: String,
+    entry: JournalEntry,
+}
+impl Journal {
+    /// Starts a journal for the running command, or continues the one given to `lium resume`
+    pub fn start(dut: Option<&str>) -> Result<Self> {
+        if let Ok(id) = std::env::var(RESUME_ENV) {
+            let entry = OP_JOURNAL
+                .get(&id)?
+                .ok_or_else(|| anyhow!("Operation {id} is not in the journal"))?;
+            status!(
+                "Resuming operation {id} ({} steps done)",
+                entry.done_steps.len()
+            );
+            return Ok(Self { id, entry });
+        }
+        let now = Local::now();
+        let id = format!(
+            "{}-{:04x}",
+            now.format("%Y%m%d%H%M%S"),
+            rand::thread_rng().gen::<u16>()
+        );
+        let entry = JournalEntry {
+            command: std::env::args().skip(1).collect(),
+            dut: dut.map(str::to_string),
+            started_at: now.timestamp(),
+            updated_at: now.timestamp(),
+            done_steps: Vec::new(),
+            values: HashMap::new(),
+        };
+        OP_JOURNAL.set(&id, entry.clone())?;
+        status!("Started operation {id}. Run `lium resume {id}` if it is interrupted.");
+        Ok(Self { id, entry })
+    }
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+    fn save(&mut self) -> Result<()> {
+        self.entry.updated_at = Local::now().timestamp();
+        OP_JOURNAL.set(&self.id, self.entry.clone())
+    }
+    /// Runs the step unless it is done in a previous run. Names must be unique in an operation.
+    pub fn step(&mut self, name: &str, f: impl FnOnce() -> Result<()>) -> Result<()> {
+        if self.entry.done_steps.iter().any(|s| s == name) {
+            status!("Skipping {name} (done)");
+            return Ok(());
+        }
+        f()?;
+        self.entry.done_steps.push(name.to_string());
+        self.save()
+    }
+    /// Returns the value recorded in a previous run, or records the value returned by `f`
+    pub fn value(&mut self, key: &str, f: impl FnOnce() -> Result<String>) -> Result<String> {
+        if let Some(v) = self.entry.values.get(key) {
+            return Ok(v.clone());
+        }
+        let v = f()?;
+        self.entry.values.insert(key.to_string(), v.clone());
+        self.save()?;
+        Ok(v)
+    }
+    /// Removes the operation from the journal. Must be called once all the steps are done.
+    pub fn finish(self) -> Result<()> {
+        OP_JOURNAL.remove(&self.id)?;
+        Ok(())
+    }
+}
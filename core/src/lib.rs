@@ -0,0 +1,66 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! yet another wrapper for CrOS developers
+
+#![feature(addr_parse_ascii)]
+#![feature(exit_status_error)]
+#![feature(hash_drain_filter)]
+#![feature(result_option_inspect)]
+
+pub mod agent;
+pub mod arc;
+pub mod audit;
+pub mod baseline;
+pub mod boards;
+pub mod cache;
+pub mod cancel;
+pub mod certs;
+pub mod chroot;
+pub mod config;
+pub mod connection;
+pub mod cros;
+pub mod delta;
+pub mod devtools;
+pub mod dns;
+pub mod dut;
+pub mod dut_file;
+pub mod dut_id;
+pub mod escalation;
+pub mod events;
+pub mod fetch;
+pub mod fleet;
+pub mod forward;
+pub mod inject;
+pub mod journal;
+pub mod metrics;
+pub mod mitm;
+pub mod monitor_db;
+pub mod netns;
+pub mod notify;
+pub mod output;
+pub mod parser;
+pub mod policy;
+pub mod ports;
+pub mod progress;
+pub mod proxy;
+pub mod query;
+pub mod redact;
+pub mod repo;
+pub mod results;
+pub mod servo;
+pub mod share;
+pub mod shim;
+pub mod ssh_auth;
+pub mod statusd;
+pub mod summary;
+pub mod symbols;
+pub mod toolchain;
+pub mod tui;
+pub mod ui;
+pub mod util;
+pub mod watchdog;
+pub mod webhook;
@@ -0,0 +1,162 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Opt-in usage metrics (`lium metrics enable`). Only the name of the subcommand (e.g.
+//! "dut info"), its duration and whether it succeeded are recorded: never the arguments, so no
+//! DUT identities. The stats are always kept locally. In the upload mode, events are buffered
+//! locally and sent to the configured endpoint in batches.
+
+use crate::cache::KvCache;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::is_offline_mode;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::fs::remove_file;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::Command;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const EVENT_BUFFER: &str = "metrics_buffer.jsonl";
+/// Buffered events are sent when there are this many of them
+pub const FLUSH_THRESHOLD: usize = 50;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub enum MetricsMode {
+    /// Only update the local stats
+    LocalOnly,
+    /// Also send the events to the URL
+    Upload(String),
+}
+static METRICS_SETTINGS: KvCache<MetricsMode> = KvCache::new("metrics_settings");
+const MODE_KEY: &str = "mode";
+
+/// Usage of a subcommand on this machine
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+pub struct CommandStats {
+    pub count: u64,
+    pub failures: u64,
+    pub total_secs: f64,
+    /// UNIX time in seconds
+    pub last_used: i64,
+}
+/// Stats keyed by subcommand (e.g. "dut info")
+pub static METRICS_STATS: KvCache<CommandStats> = KvCache::new("metrics_stats");
+
+/// One use of a subcommand, as sent in the upload mode
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct UsageEvent {
+    pub command: String,
+    pub duration_secs: f64,
+    pub success: bool,
+    pub version: String,
+    /// e.g. "2023-05-01". The time of the day is not recorded.
+    pub date: String,
+}
+
+/// Returns None if the metrics are disabled (default)
+pub fn metrics_mode() -> Result<Option<MetricsMode>> {
+    METRICS_SETTINGS.get(MODE_KEY)
+}
+
+pub fn set_metrics_mode(mode: Option<MetricsMode>) -> Result<()> {
+    match mode {
+        Some(mode) => METRICS_SETTINGS.set(MODE_KEY, mode),
+        None => {
+            METRICS_SETTINGS.remove(MODE_KEY)?;
+            clear_buffered_events()
+        }
+    }
+}
+
+/// Records a use of the subcommand if enabled. Returns true if the buffered events should be
+/// flushed.
+pub fn record_usage(command: &str, duration_secs: f64, success: bool) -> Result<bool> {
+    let Some(mode) = metrics_mode()? else {
+        return Ok(false);
+    };
+    let mut stats = METRICS_STATS.get(command)?.unwrap_or_default();
+    stats.count += 1;
+    stats.failures += u64::from(!success);
+    stats.total_secs += duration_secs;
+    stats.last_used = Local::now().timestamp();
+    METRICS_STATS.set(command, stats)?;
+    if mode == MetricsMode::LocalOnly {
+        return Ok(false);
+    }
+    let event = UsageEvent {
+        command: command.to_string(),
+        duration_secs,
+        success,
+        version: VERSION.to_string(),
+        date: Local::now().format("%Y-%m-%d").to_string(),
+    };
+    let mut f = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(gen_path_in_lium_dir(EVENT_BUFFER)?)?;
+    writeln!(f, "{}", serde_json::to_string(&event)?)?;
+    Ok(buffered_events()?.len() >= FLUSH_THRESHOLD)
+}
+
+pub fn buffered_events() -> Result<Vec<UsageEvent>> {
+    match read_to_string(gen_path_in_lium_dir(EVENT_BUFFER)?) {
+        Ok(s) => Ok(s
+            .lines()
+            .filter_map(|l| serde_json::from_str(l).ok())
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn clear_buffered_events() -> Result<()> {
+    match remove_file(gen_path_in_lium_dir(EVENT_BUFFER)?) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+/// Sends the buffered events as a JSON array to the URL of the upload mode and returns how many
+/// were sent
+pub fn flush_events() -> Result<usize> {
+    let Some(MetricsMode::Upload(url)) = metrics_mode()? else {
+        return Err(anyhow!("Uploading the metrics is not enabled"));
+    };
+    if is_offline_mode() {
+        return Err(anyhow!(
+            "Uploading the metrics is skipped in the offline mode"
+        ));
+    }
+    let events = buffered_events()?;
+    if events.is_empty() {
+        return Ok(0);
+    }
+    let output = Command::new("curl")
+        .args(["-sSf", "-X", "POST", "-H", "Content-Type: application/json"])
+        .arg("--data-binary")
+        .arg(serde_json::to_string(&events)?)
+        .arg(&url)
+        .output()
+        .context("Failed to run curl")?;
+    output.status.exit_ok().context(anyhow!(
+        "Failed to send the metrics to {url}: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    ))?;
+    clear_buffered_events()?;
+    Ok(events.len())
+}
+
+/// Returns the local stats
+pub fn command_stats() -> Result<HashMap<String, CommandStats>> {
+    METRICS_STATS.entries()
+}
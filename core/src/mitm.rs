@@ -0,0 +1,168 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Traffic interception for `lium dut mitm`. mitmproxy is used if it is installed. Otherwise, a
+//! built-in recorder logs the destinations of the requests (CONNECT targets and plain HTTP URLs)
+//! without decrypting TLS.
+
+use crate::progress::println_above;
+use crate::util::command_exists;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use dirs::home_dir;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::Shutdown;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::thread;
+
+/// Returns "mitmweb" or "mitmdump" if installed
+pub fn find_mitmproxy(web: bool) -> Option<&'static str> {
+    let cmd = if web { "mitmweb" } else { "mitmdump" };
+    command_exists(cmd).then_some(cmd)
+}
+
+/// The CA certificate that mitmproxy generates on the first run
+pub fn mitmproxy_ca_path() -> Result<PathBuf> {
+    Ok(home_dir()
+        .context("Failed to get the home dir")?
+        .join(".mitmproxy")
+        .join("mitmproxy-ca-cert.pem"))
+}
+
+/// Returns the method and the "host:port" to connect to from the request line of a proxy request
+/// (e.g. "CONNECT example.com:443 HTTP/1.1" or "GET http://example.com/ HTTP/1.1")
+fn parse_request_line(line: &str) -> Result<(String, String)> {
+    let mut parts = line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Err(anyhow!("Invalid request: {line:?}"));
+    };
+    if method == "CONNECT" {
+        return Ok((method.to_string(), target.to_string()));
+    }
+    let rest = target
+        .strip_prefix("http://")
+        .context(anyhow!("Not a proxy request: {line:?}"))?;
+    let authority = rest.split('/').next().unwrap_or_default();
+    let host = if authority.contains(':') && !authority.ends_with(']') {
+        authority.to_string()
+    } else {
+        format!("{authority}:80")
+    };
+    Ok((method.to_string(), host))
+}
+
+fn pipe(mut from: TcpStream, mut to: TcpStream) {
+    let _ = std::io::copy(&mut from, &mut to);
+    let _ = to.shutdown(Shutdown::Write);
+}
+
+fn handle_client(client: TcpStream) -> Result<()> {
+    let mut reader = BufReader::new(client.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let (method, host) = parse_request_line(request.trim_end())?;
+    let mut headers = String::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line == "\r\n" || line == "\n" {
+            break;
+        }
+        headers += &line;
+    }
+    let target = if method == "CONNECT" {
+        host.as_str()
+    } else {
+        request.split_whitespace().nth(1).unwrap_or_default()
+    };
+    println_above(&format!(
+        "{} {method} {target}",
+        Local::now().format("%H:%M:%S")
+    ));
+    let mut upstream = match TcpStream::connect(&host) {
+        Ok(s) => s,
+        Err(e) => {
+            write!(&client, "HTTP/1.1 502 Bad Gateway\r\n\r\n")?;
+            return Err(e).context(anyhow!("Failed to connect to {host}"));
+        }
+    };
+    if method == "CONNECT" {
+        write!(&client, "HTTP/1.1 200 Connection established\r\n\r\n")?;
+    } else {
+        write!(upstream, "{request}{headers}\r\n")?;
+    }
+    // Forward what the reader has buffered beyond the headers
+    let buffered = reader.buffer().to_vec();
+    upstream.write_all(&buffered)?;
+    let (c, u) = (client.try_clone()?, upstream.try_clone()?);
+    let t = thread::spawn(move || pipe(c, u));
+    pipe(upstream, client);
+    let _ = t.join();
+    Ok(())
+}
+
+/// Starts the built-in recorder on the listener in background threads
+pub fn start_recorder(listener: TcpListener) {
+    thread::spawn(move || {
+        for client in listener.incoming().flatten() {
+            thread::spawn(move || {
+                let _ = handle_client(client);
+            });
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn proxy_requests() {
+        assert_eq!(
+            parse_request_line("CONNECT example.com:443 HTTP/1.1").unwrap(),
+            ("CONNECT".to_string(), "example.com:443".to_string())
+        );
+        assert_eq!(
+            parse_request_line("GET http://example.com/a/b HTTP/1.1").unwrap(),
+            ("GET".to_string(), "example.com:80".to_string())
+        );
+        assert_eq!(
+            parse_request_line("GET http://[::1]:8000/ HTTP/1.1").unwrap(),
+            ("GET".to_string(), "[::1]:8000".to_string())
+        );
+        assert!(parse_request_line("GET /local HTTP/1.1").is_err());
+        assert!(parse_request_line("").is_err());
+    }
+
+    #[test]
+    fn recorder_relays_http() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server_addr = server.local_addr().unwrap();
+        thread::spawn(move || {
+            let (s, _) = server.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(s.try_clone().unwrap())
+                .read_line(&mut line)
+                .unwrap();
+            write!(&s, "HTTP/1.0 200 OK\r\n\r\n{line}").unwrap();
+        });
+        let proxy = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_addr = proxy.local_addr().unwrap();
+        start_recorder(proxy);
+        let mut client = TcpStream::connect(proxy_addr).unwrap();
+        write!(&client, "GET http://{server_addr}/x HTTP/1.0\r\n\r\n").unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.0 200 OK"), "{response}");
+        assert!(response.contains(&format!("GET http://{server_addr}/x")));
+    }
+}
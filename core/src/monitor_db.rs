@@ -0,0 +1,175 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Recording of `lium dut monitor` polls in a sqlite database (`--record`), so that the state of
+//! the DUTs around an incident can be inspected later (`--replay`).
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::Row;
+use std::path::Path;
+
+const SCHEMA: &str = r"
+CREATE TABLE IF NOT EXISTS samples (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    dut TEXT NOT NULL,
+    address TEXT NOT NULL,
+    online INTEGER NOT NULL,
+    rtt_ms REAL,
+    loss REAL,
+    recorded_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS samples_recorded_at ON samples (recorded_at);
+";
+
+/// The state of a DUT at a poll
+#[derive(Debug, Clone, PartialEq)]
+pub struct MonitorSample {
+    pub dut: String,
+    pub address: String,
+    pub online: bool,
+    /// p50 of the recent probes
+    pub rtt_ms: Option<f64>,
+    /// Ratio of failed probes (0.0 - 1.0)
+    pub loss: Option<f64>,
+    /// UNIX time in seconds
+    pub recorded_at: i64,
+}
+impl MonitorSample {
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            dut: row.get("dut")?,
+            address: row.get("address")?,
+            online: row.get("online")?,
+            rtt_ms: row.get("rtt_ms")?,
+            loss: row.get("loss")?,
+            recorded_at: row.get("recorded_at")?,
+        })
+    }
+}
+
+pub struct MonitorDb {
+    conn: Connection,
+}
+impl MonitorDb {
+    /// Opens the db, creating it if needed
+    pub fn open(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).context(anyhow!("Failed to open {path:?}"))?;
+        Self::init(conn)
+    }
+    /// Opens an existing db without creating it
+    pub fn open_existing(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Err(anyhow!("{path:?} does not exist"));
+        }
+        Self::open(path)
+    }
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("Failed to initialize the monitor db")?;
+        Ok(Self { conn })
+    }
+    pub fn record(&mut self, samples: &[MonitorSample]) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        for s in samples {
+            tx.execute(
+                "INSERT INTO samples (dut, address, online, rtt_ms, loss, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+                params![s.dut, s.address, s.online, s.rtt_ms, s.loss, s.recorded_at],
+            )?;
+        }
+        tx.commit()?;
+        Ok(())
+    }
+    /// Returns the last sample of each DUT at or before the time, sorted by DUT
+    pub fn state_at(&self, at: i64) -> Result<Vec<MonitorSample>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM samples WHERE id IN (
+                 SELECT MAX(id) FROM samples WHERE recorded_at <= ?1 GROUP BY dut
+             ) ORDER BY dut",
+        )?;
+        let rows = stmt.query_map([at], MonitorSample::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+    /// Returns the samples where a DUT went online or offline in the range, oldest first
+    pub fn transitions(&self, from: i64, to: i64) -> Result<Vec<MonitorSample>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT * FROM samples WHERE recorded_at <= ?2 AND dut IN (
+                 SELECT DISTINCT dut FROM samples WHERE recorded_at BETWEEN ?1 AND ?2
+             ) ORDER BY dut, id",
+        )?;
+        let rows = stmt.query_map([from, to], MonitorSample::from_row)?;
+        let samples = rows.collect::<rusqlite::Result<Vec<_>>>()?;
+        let mut transitions: Vec<MonitorSample> = samples
+            .windows(2)
+            .filter(|w| w[0].dut == w[1].dut && w[0].online != w[1].online)
+            .map(|w| w[1].clone())
+            .filter(|s| s.recorded_at >= from)
+            .collect();
+        transitions.sort_by_key(|s| s.recorded_at);
+        Ok(transitions)
+    }
+    /// Returns the time range of the samples, or None if there is no sample
+    pub fn time_range(&self) -> Result<Option<(i64, i64)>> {
+        let range: (Option<i64>, Option<i64>) = self.conn.query_row(
+            "SELECT MIN(recorded_at), MAX(recorded_at) FROM samples",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        Ok(range.0.zip(range.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(dut: &str, online: bool, recorded_at: i64) -> MonitorSample {
+        MonitorSample {
+            dut: dut.to_string(),
+            address: "127.0.0.1".to_string(),
+            online,
+            rtt_ms: online.then_some(1.5),
+            loss: None,
+            recorded_at,
+        }
+    }
+
+    #[test]
+    fn replay_samples() {
+        let mut db = MonitorDb::open_in_memory().unwrap();
+        assert_eq!(db.time_range().unwrap(), None);
+        db.record(&[
+            sample("a", true, 100),
+            sample("b", true, 100),
+            sample("a", false, 105),
+            sample("b", true, 105),
+            sample("a", true, 110),
+            sample("b", false, 110),
+        ])
+        .unwrap();
+        assert_eq!(db.time_range().unwrap(), Some((100, 110)));
+        let state = db.state_at(107).unwrap();
+        assert_eq!(state, vec![sample("a", false, 105), sample("b", true, 105)]);
+        assert!(db.state_at(99).unwrap().is_empty());
+        let transitions = db.transitions(104, 110).unwrap();
+        assert_eq!(
+            transitions,
+            vec![
+                sample("a", false, 105),
+                sample("a", true, 110),
+                sample("b", false, 110)
+            ]
+        );
+        assert_eq!(db.transitions(106, 110).unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,212 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A network namespace on this machine for `lium net sandbox`. It is connected to the host with a
+//! veth pair and NATed, so that the traffic going out of it can be firewalled and captured without
+//! affecting the rest of the machine. Needs sudo, iproute2 and iptables.
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::env::current_exe;
+use std::fs::read_to_string;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::ToSocketAddrs;
+use std::process::Command;
+
+const NAME: &str = "lium-sandbox";
+const VETH_HOST: &str = "lium-sandbox-h";
+const VETH_NS: &str = "lium-sandbox-n";
+const SUBNET: &str = "10.213.0.0/30";
+/// Address of the host side of the veth pair
+pub const HOST_ADDR: Ipv4Addr = Ipv4Addr::new(10, 213, 0, 1);
+/// Address of the sandbox side of the veth pair
+pub const SANDBOX_ADDR: Ipv4Addr = Ipv4Addr::new(10, 213, 0, 2);
+const IP_FORWARD: &str = "/proc/sys/net/ipv4/ip_forward";
+
+/// Destinations blocked in the sandbox
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockRule {
+    /// What the user gave (e.g. "gstatic.com:443")
+    pub target: String,
+    /// IPv4 addresses or CIDRs
+    pub addrs: Vec<String>,
+    pub port: Option<u16>,
+}
+impl BlockRule {
+    /// Parses "host", "host:port", "1.2.3.4" or "10.0.0.0/8". Host names are resolved now, so
+    /// only the addresses they resolve to at this point are blocked.
+    pub fn parse(target: &str) -> Result<Self> {
+        let (host, port) = split_port(target)?;
+        let addrs = if is_ipv4_or_cidr(host) {
+            vec![host.to_string()]
+        } else {
+            let mut addrs: Vec<String> = (host, 0)
+                .to_socket_addrs()
+                .context(anyhow!("Failed to resolve {host}"))?
+                .filter_map(|a| match a.ip() {
+                    IpAddr::V4(ip) => Some(ip.to_string()),
+                    IpAddr::V6(_) => None,
+                })
+                .collect();
+            addrs.sort();
+            addrs.dedup();
+            if addrs.is_empty() {
+                return Err(anyhow!("{host} has no IPv4 address"));
+            }
+            addrs
+        };
+        Ok(Self {
+            target: target.to_string(),
+            addrs,
+            port,
+        })
+    }
+}
+
+fn split_port(target: &str) -> Result<(&str, Option<u16>)> {
+    match target.rsplit_once(':') {
+        Some((host, port)) => Ok((
+            host,
+            Some(
+                port.parse()
+                    .context(anyhow!("Invalid port in {target:?}"))?,
+            ),
+        )),
+        None => Ok((target, None)),
+    }
+}
+
+fn is_ipv4_or_cidr(s: &str) -> bool {
+    let (addr, prefix) = s.split_once('/').unwrap_or((s, "32"));
+    addr.parse::<Ipv4Addr>().is_ok() && prefix.parse::<u8>().map_or(false, |p| p <= 32)
+}
+
+fn sudo_bash(script: &str) -> Result<()> {
+    let status = Command::new("sudo")
+        .args(["bash", "-c", script])
+        .status()
+        .context("Failed to run sudo")?;
+    status
+        .exit_ok()
+        .context("Failed to configure the network sandbox")?;
+    Ok(())
+}
+
+fn teardown_script() -> String {
+    format!(
+        r#"for p in $(ip netns pids {NAME} 2>/dev/null); do kill $p; done
+ip netns del {NAME} 2>/dev/null
+ip link del {VETH_HOST} 2>/dev/null
+iptables -t nat -D POSTROUTING -s {SUBNET} -j MASQUERADE 2>/dev/null
+iptables -D FORWARD -i {VETH_HOST} -j ACCEPT 2>/dev/null
+iptables -D FORWARD -o {VETH_HOST} -j ACCEPT 2>/dev/null
+rm -rf /etc/netns/{NAME}
+true
+"#
+    )
+}
+
+/// The network namespace. It is removed on drop.
+pub struct Sandbox {
+    ip_forward: String,
+}
+impl Sandbox {
+    /// Creates the namespace, removing the one left by a previous run if any
+    pub fn create(name_server: IpAddr) -> Result<Self> {
+        let ip_forward = read_to_string(IP_FORWARD)?.trim().to_string();
+        // The resolver of the host (e.g. 127.0.0.53) is not reachable from the namespace
+        sudo_bash(&format!(
+            r#"{}
+set -e
+ip netns add {NAME}
+ip link add {VETH_HOST} type veth peer name {VETH_NS}
+ip link set {VETH_NS} netns {NAME}
+ip addr add {HOST_ADDR}/30 dev {VETH_HOST}
+ip link set {VETH_HOST} up
+ip netns exec {NAME} ip addr add {SANDBOX_ADDR}/30 dev {VETH_NS}
+ip netns exec {NAME} ip link set {VETH_NS} up
+ip netns exec {NAME} ip link set lo up
+ip netns exec {NAME} ip route add default via {HOST_ADDR}
+echo 1 > {IP_FORWARD}
+iptables -t nat -A POSTROUTING -s {SUBNET} -j MASQUERADE
+iptables -I FORWARD -i {VETH_HOST} -j ACCEPT
+iptables -I FORWARD -o {VETH_HOST} -j ACCEPT
+mkdir -p /etc/netns/{NAME}
+echo 'nameserver {name_server}' > /etc/netns/{NAME}/resolv.conf
+"#,
+            teardown_script()
+        ))?;
+        Ok(Self { ip_forward })
+    }
+    /// Rejects (or silently drops, to simulate timeouts) the traffic to the destination
+    pub fn block(&self, rule: &BlockRule, drop: bool) -> Result<()> {
+        let port = rule
+            .port
+            .map(|p| format!("-p tcp --dport {p}"))
+            .unwrap_or_default();
+        let action = if drop { "DROP" } else { "REJECT" };
+        let script: String = rule
+            .addrs
+            .iter()
+            .map(|a| format!("ip netns exec {NAME} iptables -A OUTPUT -d {a} {port} -j {action}\n"))
+            .collect();
+        sudo_bash(&format!("set -e\n{script}")).context(anyhow!("Failed to block {}", rule.target))
+    }
+    /// Returns a command that runs in the namespace as root
+    pub fn command(&self, args: &[&str]) -> Command {
+        let mut c = Command::new("sudo");
+        c.args(["ip", "netns", "exec", NAME]).args(args);
+        c
+    }
+    /// Returns a command that runs lium in the namespace as the current user
+    pub fn lium_command(&self, args: &[&str]) -> Result<Command> {
+        let user = std::env::var("USER").context("USER is not set")?;
+        let exe = current_exe()?;
+        let mut c = self.command(&["sudo", "-u", &user, "--preserve-env=HOME,PATH"]);
+        c.arg(exe).args(args);
+        Ok(c)
+    }
+    /// Returns a command that captures the traffic of the namespace to the pcap file
+    pub fn capture_command(&self, path: &str) -> Command {
+        self.command(&["tcpdump", "-i", VETH_NS, "-U", "-w", path])
+    }
+}
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        let _ = sudo_bash(&format!(
+            "{}echo {} > {IP_FORWARD}",
+            teardown_script(),
+            self.ip_forward
+        ));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn block_rules() {
+        assert_eq!(
+            BlockRule::parse("10.0.0.0/8:443").unwrap(),
+            BlockRule {
+                target: "10.0.0.0/8:443".to_string(),
+                addrs: vec!["10.0.0.0/8".to_string()],
+                port: Some(443),
+            }
+        );
+        assert!(BlockRule::parse("localhost")
+            .unwrap()
+            .addrs
+            .contains(&"127.0.0.1".to_string()));
+        assert!(BlockRule::parse("1.2.3.4:https").is_err());
+        assert!(is_ipv4_or_cidr("1.2.3.4"));
+        assert!(!is_ipv4_or_cidr("1.2.3.4/33"));
+        assert!(!is_ipv4_or_cidr("gstatic.com"));
+    }
+}
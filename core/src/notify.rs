@@ -0,0 +1,133 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::config::Config;
+use crate::util::is_offline_mode;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::io::Write;
+use std::process::Command;
+
+/// A way to tell the user that a long-running operation has finished
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotifyMethod {
+    /// freedesktop notification via notify-send
+    Desktop,
+    /// terminal bell
+    Bell,
+    /// POST a JSON ({"title": ..., "body": ..., "success": ...}) to the URL
+    Webhook(String),
+}
+impl NotifyMethod {
+    /// Parses "desktop", "bell" or "webhook=<url>"
+    pub fn parse(s: &str) -> Result<Self> {
+        match s.trim() {
+            "desktop" => Ok(Self::Desktop),
+            "bell" => Ok(Self::Bell),
+            s => match s.split_once('=') {
+                Some(("webhook", url)) if !url.is_empty() => Ok(Self::Webhook(url.to_string())),
+                _ => Err(anyhow!(
+                    "Unknown notify method {s:?}. Use desktop, bell or webhook=<url>"
+                )),
+            },
+        }
+    }
+    fn send(&self, title: &str, body: &str, success: bool) -> Result<()> {
+        match self {
+            Self::Desktop => {
+                let urgency = if success { "normal" } else { "critical" };
+                Command::new("notify-send")
+                    .args(["-u", urgency, title, body])
+                    .status()
+                    .context("Failed to run notify-send")?
+                    .exit_ok()
+                    .context("notify-send failed")?;
+            }
+            Self::Bell => {
+                eprint!("\x07");
+                std::io::stderr().flush()?;
+            }
+            Self::Webhook(url) => {
+                if is_offline_mode() {
+                    return Err(anyhow!("Webhook is skipped in the offline mode"));
+                }
+                let json = serde_json::json!({
+                    "title": title,
+                    "body": body,
+                    "success": success,
+                });
+                Command::new("curl")
+                    .args(["-sf", "-X", "POST", "-H", "Content-Type: application/json"])
+                    .arg("-d")
+                    .arg(json.to_string())
+                    .arg(url)
+                    .status()
+                    .context("Failed to run curl")?
+                    .exit_ok()
+                    .context(anyhow!("Failed to post to {url}"))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Parses a comma-separated list of methods. "none" disables notifications.
+pub fn parse_notify_methods(s: &str) -> Result<Vec<NotifyMethod>> {
+    if s.trim() == "none" {
+        return Ok(Vec::new());
+    }
+    s.split(',').map(NotifyMethod::parse).collect()
+}
+
+/// Notifies the result of an operation with the methods given by `--notify`, or the ones in
+/// the config (`lium config set notify ...`) if not given.
+/// Errors during notifications are printed but not returned.
+pub fn notify_result<T>(operation: &str, result: &Result<T>, methods: &Option<String>) {
+    let methods = match methods {
+        Some(methods) => parse_notify_methods(methods),
+        None => Config::read().map(|c| c.notify_methods()),
+    };
+    let methods = match methods {
+        Ok(methods) => methods,
+        Err(e) => {
+            eprintln!("Failed to get notify methods: {e:#}");
+            return;
+        }
+    };
+    let (title, body) = match result {
+        Ok(_) => (
+            format!("lium: {operation} finished"),
+            "Succeeded".to_string(),
+        ),
+        Err(e) => (format!("lium: {operation} failed"), format!("{e:#}")),
+    };
+    for m in methods {
+        if let Err(e) = m.send(&title, &body, result.is_ok()) {
+            eprintln!("Failed to notify via {m:?}: {e:#}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_methods() {
+        assert_eq!(
+            parse_notify_methods("desktop,bell,webhook=https://example.com/hook").unwrap(),
+            vec![
+                NotifyMethod::Desktop,
+                NotifyMethod::Bell,
+                NotifyMethod::Webhook("https://example.com/hook".to_string())
+            ]
+        );
+        assert!(parse_notify_methods("none").unwrap().is_empty());
+        assert!(parse_notify_methods("webhook=").is_err());
+        assert!(parse_notify_methods("email").is_err());
+    }
+}
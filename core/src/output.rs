@@ -0,0 +1,169 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Destinations of the results of commands given with `--output`:
+//!
+//! - (not given): stdout
+//! - a path: the file is replaced when the command completes
+//! - gs://bucket/key: uploaded with gsutil.py when the command completes
+//! - http(s)://collector: POSTed with curl when the command completes
+//!
+//! Nothing is written to files, buckets or collectors until `Sink::finish()` is called, so that a
+//! failed command does not replace the previous results.
+
+use crate::progress::println_above;
+use crate::util::ensure_online;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Stdio;
+
+pub trait Sink: Send {
+    fn write_line(&mut self, line: &str) -> Result<()>;
+    /// Delivers the lines written so far. Must be called once the results are complete.
+    fn finish(self: Box<Self>) -> Result<()>;
+}
+
+struct StdoutSink;
+impl Sink for StdoutSink {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        println_above(line);
+        Ok(())
+    }
+    fn finish(self: Box<Self>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes to a temporary file next to the destination, which replaces the destination on finish
+struct FileSink {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: fs::File,
+}
+impl Sink for FileSink {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        writeln!(self.file, "{line}")?;
+        Ok(())
+    }
+    fn finish(self: Box<Self>) -> Result<()> {
+        self.file.sync_all()?;
+        fs::rename(&self.tmp, &self.path).context(anyhow!("Failed to write {:?}", self.path))
+    }
+}
+impl Drop for FileSink {
+    fn drop(&mut self) {
+        // No-op after finish()
+        let _ = fs::remove_file(&self.tmp);
+    }
+}
+
+/// Buffers the lines and sends them with a command that reads them from stdin on finish
+struct UploadSink {
+    dest: String,
+    cmd: Command,
+    buf: String,
+}
+impl Sink for UploadSink {
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        self.buf += line;
+        self.buf += "\n";
+        Ok(())
+    }
+    fn finish(mut self: Box<Self>) -> Result<()> {
+        ensure_online(&format!("Writing the results to {}", self.dest))?;
+        let program = self.cmd.get_program().to_string_lossy().to_string();
+        let mut child = self
+            .cmd
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .spawn()
+            .context(anyhow!("Failed to run {program}"))?;
+        child
+            .stdin
+            .take()
+            .context("Failed to get stdin")?
+            .write_all(self.buf.as_bytes())?;
+        child
+            .wait()?
+            .exit_ok()
+            .context(anyhow!("Failed to write the results to {}", self.dest))
+    }
+}
+
+/// Returns the sink for the destination given with --output (stdout if None)
+pub fn open(dest: Option<&str>) -> Result<Box<dyn Sink>> {
+    let Some(dest) = dest else {
+        return Ok(Box::new(StdoutSink));
+    };
+    if dest.starts_with("gs://") {
+        let mut cmd = Command::new("gsutil.py");
+        cmd.args(["-q", "cp", "-", dest]);
+        return Ok(Box::new(UploadSink {
+            dest: dest.to_string(),
+            cmd,
+            buf: String::new(),
+        }));
+    }
+    if dest.starts_with("http://") || dest.starts_with("https://") {
+        let mut cmd = Command::new("curl");
+        cmd.args(["-sSf", "-X", "POST", "-H", "Content-Type: text/plain"]);
+        if let Ok(token) = std::env::var("LIUM_OUTPUT_TOKEN") {
+            cmd.arg("-H").arg(format!("Authorization: Bearer {token}"));
+        }
+        cmd.args(["--data-binary", "@-", dest]);
+        return Ok(Box::new(UploadSink {
+            dest: dest.to_string(),
+            cmd,
+            buf: String::new(),
+        }));
+    }
+    if dest.contains("://") {
+        return Err(anyhow!(
+            "Unsupported output {dest:?}. Use a path, gs://bucket/key or http(s)://..."
+        ));
+    }
+    let path = PathBuf::from(dest);
+    let name = path
+        .file_name()
+        .context(anyhow!("{dest:?} is not a file path"))?
+        .to_string_lossy();
+    let tmp = path.with_file_name(format!(".{name}.{}.tmp", std::process::id()));
+    let file = fs::File::create(&tmp).context(anyhow!("Failed to create {tmp:?}"))?;
+    Ok(Box::new(FileSink { path, tmp, file }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn file_sink() {
+        let dir = TempDir::new("lium_output").unwrap();
+        let path = dir.path().join("out.txt");
+        let dest = path.to_string_lossy().to_string();
+        let mut sink = open(Some(&dest)).unwrap();
+        sink.write_line("a").unwrap();
+        sink.write_line("b").unwrap();
+        assert!(!path.exists());
+        sink.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+
+        // Dropped without finish()
+        let mut sink = open(Some(&dest)).unwrap();
+        sink.write_line("c").unwrap();
+        drop(sink);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\nb\n");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+
+        assert!(open(Some("ftp://example.com/x")).is_err());
+    }
+}
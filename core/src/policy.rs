@@ -0,0 +1,206 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Restrictions on destructive operations, to protect shared lab devices. The rules are read from
+//! ~/.config/lium/policy.toml and enforced before a command runs:
+//!
+//! ```toml
+//! [[rule]]
+//! operations = ["firmware_write", "powerwash"]
+//! # DUT groups (see `lium config set dut_group`). The rule applies to everything if omitted.
+//! labels = ["production"]
+//! action = "deny" # or "confirm"
+//! ```
+
+use crate::config::Config;
+use crate::dut::DutInfo;
+use crate::dut::SshInfo;
+use crate::dut_file;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::io::BufRead;
+use std::io::Write;
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Operation {
+    /// e.g. `lium dut ec flash`
+    FirmwareWrite,
+    /// `lium flash`, `lium dut recover`, `lium arc flash`
+    OsFlash,
+    /// `lium dut powerwash`, `lium dut sanitize --powerwash`
+    Powerwash,
+    /// `lium dut tpm clear`
+    TpmClear,
+    /// `lium dut sanitize`, `lium dut profiles remove`
+    UserDataWipe,
+    /// `lium dut list --clear`, `lium cache rm`
+    CacheClear,
+}
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::FirmwareWrite => "firmware_write",
+            Self::OsFlash => "os_flash",
+            Self::Powerwash => "powerwash",
+            Self::TpmClear => "tpm_clear",
+            Self::UserDataWipe => "user_data_wipe",
+            Self::CacheClear => "cache_clear",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum PolicyAction {
+    Confirm,
+    Deny,
+}
+
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+struct Rule {
+    operations: Vec<Operation>,
+    #[serde(default)]
+    labels: Vec<String>,
+    action: PolicyAction,
+}
+
+#[derive(Debug, Clone, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct Policy {
+    #[serde(default)]
+    rule: Vec<Rule>,
+}
+impl Policy {
+    fn path() -> Result<PathBuf> {
+        Ok(dirs::config_dir()
+            .context("Failed to get the config dir")?
+            .join("lium")
+            .join("policy.toml"))
+    }
+    /// Reads the policy file. No restrictions if it does not exist.
+    pub fn read() -> Result<Self> {
+        let path = Self::path()?;
+        match fs::read_to_string(&path) {
+            Ok(s) => Self::parse(&s).context(anyhow!("Invalid policy in {path:?}")),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+    fn parse(s: &str) -> Result<Self> {
+        Ok(toml::from_str(s)?)
+    }
+    /// Returns the strictest action of the rules for the operation on a DUT with the labels
+    fn decide(&self, op: Operation, labels: &[String]) -> Option<PolicyAction> {
+        self.rule
+            .iter()
+            .filter(|r| r.operations.contains(&op))
+            .filter(|r| r.labels.is_empty() || r.labels.iter().any(|l| labels.contains(l)))
+            .map(|r| r.action)
+            .max()
+    }
+}
+
+/// Returns the labels (DUT groups, and labels in --dut-file) of the DUT, known by its ID or its
+/// address
+fn labels_of(dut: &str) -> Result<Vec<String>> {
+    let mut names = vec![dut.to_string()];
+    if let Ok(ssh) = SshInfo::new(dut) {
+        names.push(ssh.host_and_port());
+        names.extend(DutInfo::find_cached_id(&ssh));
+    }
+    let mut labels = dut_file::labels_of(&names);
+    labels.extend(
+        Config::read()?
+            .dut_groups()
+            .iter()
+            .filter(|(_, duts)| duts.iter().any(|d| names.contains(d)))
+            .map(|(name, _)| name.clone()),
+    );
+    Ok(labels)
+}
+
+/// Fails if the policy denies the operation, or asks for a confirmation on the terminal if
+/// required
+pub fn enforce(op: Operation, dut: Option<&str>) -> Result<()> {
+    let policy = Policy::read()?;
+    if policy.rule.is_empty() {
+        return Ok(());
+    }
+    let labels = dut.map(labels_of).transpose()?.unwrap_or_default();
+    let target = dut.map(|d| format!(" on {d}")).unwrap_or_default();
+    match policy.decide(op, &labels) {
+        None => Ok(()),
+        Some(PolicyAction::Deny) => Err(anyhow!(
+            "{op}{target} is denied by the policy in {:?}",
+            Policy::path()?
+        )),
+        Some(PolicyAction::Confirm) => {
+            if !termion::is_tty(&std::io::stdin()) {
+                return Err(anyhow!(
+                    "{op}{target} requires a confirmation by the policy. Run it on a terminal"
+                ));
+            }
+            eprint!("The policy requires a confirmation for {op}{target}. Type 'yes' to proceed: ");
+            std::io::stderr().flush()?;
+            let mut answer = String::new();
+            std::io::stdin().lock().read_line(&mut answer)?;
+            if answer.trim() == "yes" {
+                Ok(())
+            } else {
+                Err(anyhow!("Cancelled {op}{target}"))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decide() {
+        let policy = Policy::parse(
+            r#"
+[[rule]]
+operations = ["firmware_write", "powerwash"]
+labels = ["production"]
+action = "deny"
+
+[[rule]]
+operations = ["firmware_write", "cache_clear"]
+action = "confirm"
+"#,
+        )
+        .unwrap();
+        let prod = vec!["production".to_string()];
+        assert_eq!(
+            policy.decide(Operation::FirmwareWrite, &prod),
+            Some(PolicyAction::Deny)
+        );
+        assert_eq!(
+            policy.decide(Operation::FirmwareWrite, &[]),
+            Some(PolicyAction::Confirm)
+        );
+        assert_eq!(policy.decide(Operation::Powerwash, &[]), None);
+        assert_eq!(
+            policy.decide(Operation::CacheClear, &[]),
+            Some(PolicyAction::Confirm)
+        );
+        assert_eq!(policy.decide(Operation::OsFlash, &prod), None);
+        assert!(
+            Policy::parse("[[rule]]\noperations = [\"format_disk\"]\naction = \"deny\"").is_err()
+        );
+        assert_eq!(Policy::parse("").unwrap(), Policy::default());
+    }
+}
@@ -0,0 +1,246 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Allocation of local ports for forwardings, so that lium instances running at the same time
+//! (e.g. two `lium dut monitor`) do not try to listen on the same port. An allocated port is
+//! recorded as a file in ~/.lium/ports/ until the allocation is dropped. Records left by
+//! processes that are gone are reclaimed.
+
+use crate::util::gen_path_in_lium_dir;
+use crate::util::is_process_alive;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use nix::fcntl::flock;
+use nix::fcntl::FlockArg;
+use serde::Deserialize;
+use serde::Serialize;
+use std::fs;
+use std::fs::File;
+use std::io::ErrorKind;
+use std::net::TcpListener;
+use std::ops::Range;
+use std::os::unix::fs::MetadataExt;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::path::PathBuf;
+
+/// Ports of `lium dut monitor`
+pub const MONITOR_PORTS: Range<u16> = 4022..4122;
+/// Ports of `lium dut vnc`
+pub const VNC_PORTS: Range<u16> = 5900..5950;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PortRecord {
+    pub port: u16,
+    pub pid: u32,
+    /// What the port is used for (e.g. "monitor eve_XXX")
+    pub purpose: String,
+    /// UNIX time in seconds
+    pub allocated_at: i64,
+}
+impl PortRecord {
+    fn is_stale(&self) -> bool {
+        !is_process_alive(self.pid)
+    }
+}
+
+/// A port allocated to this process. The record is removed when this is dropped.
+#[derive(Debug)]
+pub struct PortAllocation {
+    port: u16,
+    record: PathBuf,
+}
+impl PortAllocation {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+impl Drop for PortAllocation {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.record);
+    }
+}
+
+pub struct PortAllocator {
+    range: Range<u16>,
+    dir: PathBuf,
+}
+impl PortAllocator {
+    pub fn new(range: Range<u16>) -> Result<Self> {
+        Ok(Self::with_dir(range, &ports_dir()?))
+    }
+    fn with_dir(range: Range<u16>, dir: &Path) -> Self {
+        Self {
+            range,
+            dir: dir.to_path_buf(),
+        }
+    }
+    /// Allocates the first port in the range that is neither recorded by a live process nor
+    /// used by anything else
+    pub fn allocate(&self, purpose: &str) -> Result<PortAllocation> {
+        for port in self.range.clone() {
+            if let Some(allocation) = self.try_allocate(port, purpose)? {
+                return Ok(allocation);
+            }
+        }
+        Err(anyhow!(
+            "No free local port in {}-{}. See `lium dut ports` for the ports used by lium",
+            self.range.start,
+            self.range.end - 1
+        ))
+    }
+    /// Allocates the port if it is free
+    pub fn try_allocate(&self, port: u16, purpose: &str) -> Result<Option<PortAllocation>> {
+        let path = self.dir.join(port.to_string());
+        let record = PortRecord {
+            port,
+            pid: std::process::id(),
+            purpose: purpose.to_string(),
+            allocated_at: Local::now().timestamp(),
+        };
+        // Linking a complete record into place makes the check and the record atomic among lium
+        // instances, and other instances never see a partially written record
+        let tmp = self.dir.join(format!(".{port}.{}.tmp", record.pid));
+        fs::write(&tmp, serde_json::to_string(&record)?)
+            .context(anyhow!("Failed to write {tmp:?}"))?;
+        let linked = fs::hard_link(&tmp, &path);
+        let _ = fs::remove_file(&tmp);
+        match linked {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                if remove_if_stale(&path)? {
+                    return self.try_allocate(port, purpose);
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(e).context(anyhow!("Failed to record {path:?}")),
+        }
+        let allocation = PortAllocation { port, record: path };
+        if TcpListener::bind(("127.0.0.1", port)).is_err() {
+            // Used by something other than lium
+            return Ok(None);
+        }
+        Ok(Some(allocation))
+    }
+}
+
+/// Removes the record if its process is gone, and returns whether the port can be tried again.
+/// The record is locked so that only one of the instances reclaiming it at the same time
+/// removes it, and a fresh record linked in its place is never removed.
+fn remove_if_stale(path: &Path) -> Result<bool> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e).context(anyhow!("Failed to open {path:?}")),
+    };
+    flock(file.as_raw_fd(), FlockArg::LockExclusive).context(anyhow!("Failed to lock {path:?}"))?;
+    // Another instance may have replaced the record while this one waited for the lock
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(m) if m.dev() == locked.dev() && m.ino() == locked.ino() => {}
+        _ => return Ok(true),
+    }
+    // A record that can not be read is treated as live
+    if !read_record(path).map_or(false, |r| r.is_stale()) {
+        return Ok(false);
+    }
+    fs::remove_file(path).context(anyhow!("Failed to remove {path:?}"))?;
+    Ok(true)
+}
+
+fn ports_dir() -> Result<PathBuf> {
+    Ok(gen_path_in_lium_dir("ports/.keep")?
+        .parent()
+        .context("no parent")?
+        .to_path_buf())
+}
+
+fn records_in(dir: &Path) -> Result<Vec<PortRecord>> {
+    let mut records: Vec<PortRecord> = fs::read_dir(dir)?
+        .flatten()
+        // Skip the records being written
+        .filter(|e| !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| read_record(&e.path()))
+        .filter(|r| !r.is_stale())
+        .collect();
+    records.sort_by_key(|r| r.port);
+    Ok(records)
+}
+
+/// Returns the ports allocated by the running lium instances
+pub fn active_ports() -> Result<Vec<PortRecord>> {
+    records_in(&ports_dir()?)
+}
+
+fn read_record(path: &Path) -> Option<PortRecord> {
+    serde_json::from_str(&fs::read_to_string(path).ok()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    #[test]
+    fn allocate_ports() {
+        let dir = TempDir::new("lium_ports").unwrap();
+        // Find a range whose first port is free to make the test stable
+        let start = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let allocator = PortAllocator::with_dir(start..start.saturating_add(50), dir.path());
+        let a = allocator.allocate("a").unwrap();
+        let b = allocator.allocate("b").unwrap();
+        assert_ne!(a.port(), b.port());
+        assert_eq!(records_in(dir.path()).unwrap().len(), 2);
+        assert!(allocator.try_allocate(a.port(), "c").unwrap().is_none());
+        let port = a.port();
+        drop(a);
+        assert_eq!(records_in(dir.path()).unwrap().len(), 1);
+        assert!(allocator.try_allocate(port, "c").unwrap().is_some());
+
+        // A record of a process that is gone is reclaimed
+        let stale = PortRecord {
+            port,
+            pid: i32::MAX as u32,
+            purpose: "gone".to_string(),
+            allocated_at: 0,
+        };
+        fs::write(
+            dir.path().join(port.to_string()),
+            serde_json::to_string(&stale).unwrap(),
+        )
+        .unwrap();
+        assert!(records_in(dir.path())
+            .unwrap()
+            .iter()
+            .all(|r| r.port != port));
+        let d = allocator.try_allocate(port, "d").unwrap();
+        assert!(d.is_some());
+        drop(d);
+
+        // An empty record (e.g. from an older lium) is not reclaimed
+        fs::write(dir.path().join(port.to_string()), "").unwrap();
+        assert!(allocator.try_allocate(port, "e").unwrap().is_none());
+
+        // Only records of processes that are gone are removed
+        let record = dir.path().join(port.to_string());
+        fs::write(&record, serde_json::to_string(&stale).unwrap()).unwrap();
+        assert!(remove_if_stale(&record).unwrap());
+        assert!(!record.exists());
+        let live = PortRecord {
+            pid: std::process::id(),
+            ..stale
+        };
+        fs::write(&record, serde_json::to_string(&live).unwrap()).unwrap();
+        assert!(!remove_if_stale(&record).unwrap());
+        assert!(record.exists());
+    }
+}
@@ -0,0 +1,249 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Progress bars and spinners for long commands. They are drawn on stderr only if it is a
+//! terminal and the output is not quiet, so that piped output stays clean. Bars can be nested
+//! with `child()`, and are removed when dropped.
+
+use crate::ui::is_quiet;
+use crate::ui::symbol;
+use lazy_static::lazy_static;
+use std::io::Write;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+const BAR_WIDTH: usize = 30;
+const TICK: Duration = Duration::from_millis(100);
+
+struct Bar {
+    id: u64,
+    depth: usize,
+    label: String,
+    /// None for spinners
+    total: Option<u64>,
+    done: u64,
+    detail: String,
+}
+impl Bar {
+    fn render(&self, started: Instant) -> String {
+        let indent = "  ".repeat(self.depth);
+        match self.total {
+            Some(total) => {
+                let filled = if total == 0 {
+                    BAR_WIDTH
+                } else {
+                    (self.done.min(total) as usize * BAR_WIDTH) / total as usize
+                };
+                format!(
+                    "{indent}{} [{}{}] {}/{} {}",
+                    self.label,
+                    symbol("█", "#").repeat(filled),
+                    symbol("░", "-").repeat(BAR_WIDTH - filled),
+                    self.done,
+                    total,
+                    self.detail
+                )
+            }
+            None => {
+                let frames: Vec<&str> = symbol("⠋⠙⠹⠸⠼⠴⠦⠧⠇⠏", r"|/-\")
+                    .split("")
+                    .filter(|s| !s.is_empty())
+                    .collect();
+                let frame = frames
+                    [(started.elapsed().as_millis() / TICK.as_millis()) as usize % frames.len()];
+                format!("{indent}{frame} {} {}", self.label, self.detail)
+            }
+        }
+    }
+}
+
+struct State {
+    bars: Vec<Bar>,
+    /// lines drawn last time, to be cleared on the next draw
+    drawn: usize,
+    started: Instant,
+}
+impl State {
+    fn clear(&mut self, out: &mut impl Write) {
+        for _ in 0..self.drawn {
+            let _ = write!(
+                out,
+                "{}\r{}",
+                termion::cursor::Up(1),
+                termion::clear::CurrentLine
+            );
+        }
+        self.drawn = 0;
+    }
+    fn draw(&mut self, out: &mut impl Write) {
+        self.clear(out);
+        let width = match termion::terminal_size() {
+            Ok((w, _)) if w > 0 => w as usize,
+            _ => 80,
+        };
+        for bar in &self.bars {
+            let line: String = bar
+                .render(self.started)
+                .chars()
+                .take(width.saturating_sub(1))
+                .collect();
+            let _ = writeln!(out, "{line}");
+        }
+        self.drawn = self.bars.len();
+        let _ = out.flush();
+    }
+}
+
+lazy_static! {
+    static ref STATE: Mutex<State> = Mutex::new(State {
+        bars: Vec::new(),
+        drawn: 0,
+        started: Instant::now(),
+    });
+}
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static TICKER_RUNNING: AtomicBool = AtomicBool::new(false);
+
+fn is_enabled() -> bool {
+    !is_quiet() && termion::is_tty(&std::io::stderr())
+}
+
+fn redraw() {
+    let mut state = STATE.lock().unwrap();
+    state.draw(&mut std::io::stderr());
+}
+
+/// Redraws the bars periodically to animate the spinners while any bar exists
+fn start_ticker() {
+    if TICKER_RUNNING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    thread::spawn(|| loop {
+        thread::sleep(TICK);
+        let mut state = STATE.lock().unwrap();
+        if state.bars.is_empty() {
+            TICKER_RUNNING.store(false, Ordering::SeqCst);
+            return;
+        }
+        state.draw(&mut std::io::stderr());
+    });
+}
+
+/// Prints a line to stderr above the bars
+pub fn eprintln_above(line: &str) {
+    let mut state = STATE.lock().unwrap();
+    let mut err = std::io::stderr();
+    state.clear(&mut err);
+    let _ = writeln!(err, "{line}");
+    if !state.bars.is_empty() {
+        state.draw(&mut err);
+    }
+}
+
+/// Prints a line to stdout above the bars, for results printed while the bars are shown
+pub fn println_above(line: &str) {
+    let mut state = STATE.lock().unwrap();
+    let mut err = std::io::stderr();
+    state.clear(&mut err);
+    let mut out = std::io::stdout();
+    let _ = writeln!(out, "{line}");
+    let _ = out.flush();
+    if !state.bars.is_empty() {
+        state.draw(&mut err);
+    }
+}
+
+/// A progress bar or a spinner. Updates are no-ops if the progress is not shown.
+pub struct Progress {
+    id: Option<u64>,
+}
+impl Progress {
+    fn add(label: &str, total: Option<u64>, parent: Option<u64>) -> Self {
+        if !is_enabled() {
+            return Self { id: None };
+        }
+        let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+        {
+            let mut state = STATE.lock().unwrap();
+            // Put a child after the last descendant of the parent
+            let (pos, depth) = match parent.and_then(|p| state.bars.iter().position(|b| b.id == p))
+            {
+                Some(i) => {
+                    let depth = state.bars[i].depth + 1;
+                    let end = state.bars[i + 1..]
+                        .iter()
+                        .position(|b| b.depth < depth)
+                        .map_or(state.bars.len(), |n| i + 1 + n);
+                    (end, depth)
+                }
+                None => (state.bars.len(), 0),
+            };
+            state.bars.insert(
+                pos,
+                Bar {
+                    id,
+                    depth,
+                    label: label.to_string(),
+                    total,
+                    done: 0,
+                    detail: String::new(),
+                },
+            );
+            state.draw(&mut std::io::stderr());
+        }
+        start_ticker();
+        Self { id: Some(id) }
+    }
+    /// A bar of `total` steps (e.g. "checking 124 DUTs")
+    pub fn new(label: &str, total: u64) -> Self {
+        Self::add(label, Some(total), None)
+    }
+    /// A spinner for a step of unknown length (e.g. an ssh connection)
+    pub fn spinner(label: &str) -> Self {
+        Self::add(label, None, None)
+    }
+    /// A bar nested under this one
+    pub fn child(&self, label: &str, total: u64) -> Self {
+        match self.id {
+            Some(id) => Self::add(label, Some(total), Some(id)),
+            None => Self { id: None },
+        }
+    }
+    fn update(&self, f: impl FnOnce(&mut Bar)) {
+        let Some(id) = self.id else {
+            return;
+        };
+        if let Some(bar) = STATE.lock().unwrap().bars.iter_mut().find(|b| b.id == id) {
+            f(bar);
+        }
+        redraw();
+    }
+    /// Returns true if this is drawn on the terminal
+    pub fn is_shown(&self) -> bool {
+        self.id.is_some()
+    }
+    pub fn inc(&self, n: u64) {
+        self.update(|b| b.done += n);
+    }
+    /// Sets the text after the bar (e.g. the current item)
+    pub fn set_detail(&self, detail: &str) {
+        self.update(|b| b.detail = detail.to_string());
+    }
+}
+impl Drop for Progress {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut state = STATE.lock().unwrap();
+            state.bars.retain(|b| b.id != id);
+            state.draw(&mut std::io::stderr());
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! HTTP(S) proxy settings of DUTs. A proxy is configured in three places at once:
+//!
+//! - the ProxyConfig property of the default shill service, used by Chrome and system services
+//! - `--proxy-server` in /etc/chrome_dev.conf, so that it also applies on the login screen
+//! - http_proxy and https_proxy in /etc/profile.d, for shells and tools like curl
+//!
+//! Either all of them are changed or none of them, and the previous settings are restored on
+//! unset.
+
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+
+const STATE_DIR: &str = "/usr/local/lium_proxy";
+const PROFILE: &str = "/etc/profile.d/lium-proxy.sh";
+const CHROME_DEV_CONF: &str = "/etc/chrome_dev.conf";
+
+const SHELL_FUNCTIONS: &str = r#"
+shill() { dbus-send --system --print-reply --dest=org.chromium.flimflam "$@"; }
+default_service() {
+  shill / org.chromium.flimflam.Manager.GetProperties | grep -A 1 '"DefaultService"' | grep -o '/service/[0-9]*'
+}
+proxy_config() {
+  shill "$1" org.chromium.flimflam.Service.GetProperties | grep -A 1 '"ProxyConfig"' | tail -n 1 | sed -E 's/^.*string "(.*)"$/\1/'
+}
+"#;
+
+/// Returns an error if the URL cannot be used as a proxy server
+pub fn validate_proxy_url(url: &str) -> Result<()> {
+    let (scheme, rest) = url
+        .split_once("://")
+        .context(anyhow!("Invalid proxy URL {url:?}. e.g. http://host:3128"))?;
+    if !["http", "https", "socks4", "socks5"].contains(&scheme) {
+        return Err(anyhow!("Unsupported proxy scheme {scheme:?}"));
+    }
+    if rest.is_empty()
+        || rest
+            .chars()
+            .any(|c| c.is_whitespace() || "'\"\\`$;&|<>".contains(c))
+    {
+        return Err(anyhow!("Invalid proxy URL {url:?}"));
+    }
+    Ok(())
+}
+
+fn set_script(url: &str) -> String {
+    let config = format!(r#"{{"mode":"fixed_servers","server":"{url}"}}"#);
+    format!(
+        r#"set -e
+{SHELL_FUNCTIONS}
+mount -o remount,rw / 2>/dev/null
+svc=$(default_service || true)
+test -n "$svc" || {{ echo 'No network is connected' >&2; exit 1; }}
+mkdir -p {STATE_DIR}
+# Keep the original state of the first set to restore it on unset
+if [ ! -f {STATE_DIR}/service ]; then
+  echo "$svc" > {STATE_DIR}/service
+  proxy_config "$svc" > {STATE_DIR}/proxy_config
+  cp {CHROME_DEV_CONF} {STATE_DIR}/chrome_dev.conf.orig
+fi
+grep -v '^--proxy-server=' {STATE_DIR}/chrome_dev.conf.orig > {STATE_DIR}/chrome_dev.conf.new || true
+echo '--proxy-server={url}' >> {STATE_DIR}/chrome_dev.conf.new
+printf 'export http_proxy=%s https_proxy=%s\n' '{url}' '{url}' > {PROFILE}.new
+# Apply all the changes only after all of them are prepared
+shill "$svc" org.chromium.flimflam.Service.SetProperty string:ProxyConfig variant:string:'{config}' >/dev/null
+mv {STATE_DIR}/chrome_dev.conf.new {CHROME_DEV_CONF}
+mv {PROFILE}.new {PROFILE}
+"#
+    )
+}
+
+fn unset_script() -> String {
+    format!(
+        r#"set -e
+{SHELL_FUNCTIONS}
+mount -o remount,rw / 2>/dev/null
+test -f {STATE_DIR}/service || {{ echo 'No proxy is set by lium' >&2; exit 1; }}
+svc=$(cat {STATE_DIR}/service)
+prev=$(cat {STATE_DIR}/proxy_config)
+if [ -n "$prev" ]; then
+  shill "$svc" org.chromium.flimflam.Service.SetProperty string:ProxyConfig variant:string:"$prev" >/dev/null
+else
+  shill "$svc" org.chromium.flimflam.Service.ClearProperty string:ProxyConfig >/dev/null
+fi
+cp {STATE_DIR}/chrome_dev.conf.orig {CHROME_DEV_CONF}
+rm -f {PROFILE}
+rm -rf {STATE_DIR}
+"#
+    )
+}
+
+fn status_script() -> String {
+    format!(
+        r#"{SHELL_FUNCTIONS}
+svc=$(default_service)
+echo "shill ($svc): $(proxy_config "$svc")"
+echo "chrome_dev.conf: $(grep -- '^--proxy-server=' {CHROME_DEV_CONF})"
+echo "env: $(cat {PROFILE} 2>/dev/null)"
+if [ -f {STATE_DIR}/service ] && [ "$(cat {STATE_DIR}/service)" != "$svc" ]; then
+  echo "WARNING: the proxy was set for $(cat {STATE_DIR}/service), but the default network is $svc now"
+fi
+true
+"#
+    )
+}
+
+/// Sets the proxy. The UI needs to be restarted to apply it to Chrome.
+pub fn set_proxy(ssh: &SshInfo, url: &str) -> Result<()> {
+    validate_proxy_url(url)?;
+    ssh.run_cmd_stdio(&set_script(url))
+        .context("Failed to set the proxy (is the rootfs verification removed?)")?;
+    Ok(())
+}
+
+/// Restores the settings before `set_proxy()`
+pub fn unset_proxy(ssh: &SshInfo) -> Result<()> {
+    ssh.run_cmd_stdio(&unset_script())
+        .context("Failed to unset the proxy")?;
+    Ok(())
+}
+
+/// Returns the human-readable proxy settings of the DUT
+pub fn proxy_status(ssh: &SshInfo) -> Result<String> {
+    ssh.run_cmd_stdio(&status_script())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn proxy_url() {
+        assert!(validate_proxy_url("http://proxy.example.com:3128").is_ok());
+        assert!(validate_proxy_url("socks5://[::1]:1080").is_ok());
+        assert!(validate_proxy_url("proxy:3128").is_err());
+        assert!(validate_proxy_url("ftp://proxy:21").is_err());
+        assert!(validate_proxy_url("http://a' ; reboot").is_err());
+    }
+}
@@ -0,0 +1,226 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A small query syntax to filter and project DUT info maps, e.g.
+//! `--where 'release>=15400 && model==soraka' --select id,ip,release`.
+//!
+//! A filter is conditions joined with `&&` and `||` (`&&` binds tighter, no parentheses).
+//! A condition is `key op value` where op is one of `==`, `!=`, `>=`, `<=`, `>`, `<` and `=~`
+//! (regex match). Values starting with a number (e.g. 15400, 15662.0.0) are compared as
+//! versions. Values can be quoted with '...' or "...".
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use regex_macro::regex;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Aliases of keys, tried in this order
+fn key_aliases(key: &str) -> &[&str] {
+    match key {
+        "id" => &["id", "dut_id"],
+        "ip" => &["ip", "address", "ipv4_addr", "ipv6_addr"],
+        _ => &[],
+    }
+}
+
+/// Returns the DUT attribute to fetch for the key (e.g. "dut_id" for "id")
+pub fn attribute_of(key: &str) -> &str {
+    match key {
+        "id" => "dut_id",
+        "ip" => "ipv4_addr",
+        _ => key,
+    }
+}
+
+/// Returns the value of the key (or one of its aliases) in the map
+pub fn lookup<'a>(map: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    map.get(key).map(|v| v.as_str()).or_else(|| {
+        key_aliases(key)
+            .iter()
+            .find_map(|k| map.get(*k).map(|v| v.as_str()))
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Eq,
+    Ne,
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Match,
+}
+
+#[derive(Debug, Clone)]
+struct Condition {
+    key: String,
+    op: Op,
+    value: String,
+    regex: Option<Regex>,
+}
+
+/// Returns the leading version (e.g. [15662, 0, 0] for "15662.0.0 (Test Build)")
+fn version_of(s: &str) -> Option<Vec<u64>> {
+    let m = regex!(r"^\d+(\.\d+)*").find(s.trim())?;
+    m.as_str().split('.').map(|n| n.parse().ok()).collect()
+}
+
+fn compare(a: &str, b: &str) -> Ordering {
+    match (version_of(a), version_of(b)) {
+        (Some(mut a), Some(mut b)) => {
+            let len = a.len().max(b.len());
+            a.resize(len, 0);
+            b.resize(len, 0);
+            a.cmp(&b)
+        }
+        _ => a.cmp(b),
+    }
+}
+
+impl Condition {
+    fn matches(&self, map: &HashMap<String, String>) -> bool {
+        let Some(actual) = lookup(map, &self.key) else {
+            return self.op == Op::Ne;
+        };
+        let ord = compare(actual, &self.value);
+        match self.op {
+            Op::Eq => actual == self.value || ord == Ordering::Equal,
+            Op::Ne => actual != self.value && ord != Ordering::Equal,
+            Op::Ge => ord != Ordering::Less,
+            Op::Le => ord != Ordering::Greater,
+            Op::Gt => ord == Ordering::Greater,
+            Op::Lt => ord == Ordering::Less,
+            Op::Match => self.regex.as_ref().map_or(false, |r| r.is_match(actual)),
+        }
+    }
+}
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let c = regex!(r"^\s*([A-Za-z0-9_]+)\s*(==|!=|>=|<=|=~|>|<)\s*(.*?)\s*$")
+            .captures(s)
+            .context(anyhow!("Invalid condition {s:?}. Use `key op value`"))?;
+        let value = c[3].to_string();
+        let value = match (value.chars().next(), value.chars().last()) {
+            (Some(q @ ('\'' | '"')), Some(e)) if value.len() >= 2 && q == e => {
+                value[1..value.len() - 1].to_string()
+            }
+            _ => value,
+        };
+        let op = match &c[2] {
+            "==" => Op::Eq,
+            "!=" => Op::Ne,
+            ">=" => Op::Ge,
+            "<=" => Op::Le,
+            ">" => Op::Gt,
+            "<" => Op::Lt,
+            _ => Op::Match,
+        };
+        let regex = if op == Op::Match {
+            Some(Regex::new(&value).context(anyhow!("Invalid regex in {s:?}"))?)
+        } else {
+            None
+        };
+        Ok(Self {
+            key: c[1].to_string(),
+            op,
+            value,
+            regex,
+        })
+    }
+}
+
+/// A filter given with `--where`
+#[derive(Debug, Clone)]
+pub struct Filter {
+    /// OR of ANDs
+    any_of: Vec<Vec<Condition>>,
+}
+impl Filter {
+    pub fn matches(&self, map: &HashMap<String, String>) -> bool {
+        self.any_of
+            .iter()
+            .any(|all_of| all_of.iter().all(|c| c.matches(map)))
+    }
+    /// Returns the keys used in the filter
+    pub fn keys(&self) -> Vec<&str> {
+        self.any_of
+            .iter()
+            .flatten()
+            .map(|c| c.key.as_str())
+            .collect()
+    }
+}
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let any_of = s
+            .split("||")
+            .map(|all_of| all_of.split("&&").map(Condition::from_str).collect())
+            .collect::<Result<Vec<Vec<Condition>>>>()?;
+        Ok(Self { any_of })
+    }
+}
+
+/// Parses the keys given with `--select` (e.g. "id,ip,release")
+pub fn parse_select(s: &str) -> Vec<String> {
+    s.split(',')
+        .map(|k| k.trim().to_string())
+        .filter(|k| !k.is_empty())
+        .collect()
+}
+
+/// Returns the values of the keys. Missing values are empty.
+pub fn project(map: &HashMap<String, String>, keys: &[String]) -> Vec<(String, String)> {
+    keys.iter()
+        .map(|k| (k.clone(), lookup(map, k).unwrap_or_default().to_string()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter() {
+        let dut = HashMap::from([
+            ("dut_id".to_string(), "soraka_XXX".to_string()),
+            ("model".to_string(), "soraka".to_string()),
+            (
+                "release".to_string(),
+                "15662.0.0 (Test Build) developer-build soraka".to_string(),
+            ),
+            ("address".to_string(), "[fe80::1%eth0]:22".to_string()),
+        ]);
+        let f = |s: &str| Filter::from_str(s).unwrap().matches(&dut);
+        assert!(f("release>=15400 && model==soraka"));
+        assert!(!f("release<15400"));
+        assert!(f("release==15662"));
+        assert!(f("model=='eve' || release>15000.1"));
+        assert!(f("model =~ ^sor"));
+        assert!(f("board != eve"));
+        assert!(!f("board == eve"));
+        assert!(f("model > eve"));
+        assert!(Filter::from_str("model soraka").is_err());
+        assert_eq!(
+            Filter::from_str("a==1 && b>2 || c<3").unwrap().keys(),
+            vec!["a", "b", "c"]
+        );
+        assert_eq!(
+            project(&dut, &parse_select("id, ip,hwid")),
+            vec![
+                ("id".to_string(), "soraka_XXX".to_string()),
+                ("ip".to_string(), "[fe80::1%eth0]:22".to_string()),
+                ("hwid".to_string(), "".to_string()),
+            ]
+        );
+    }
+}
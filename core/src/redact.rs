@@ -0,0 +1,179 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Redaction of artifacts shared outside the team (`--redact`). Addresses, emails, serial
+//! numbers, the user name and the home directory are masked, as well as host names in
+//! `redact_domains` and matches of `redact_patterns` in the config.
+
+use crate::config::Config;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use regex::Regex;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempdir::TempDir;
+
+/// (pattern, replacement) applied in this order. Addresses go before emails so that
+/// "root@192.168.0.2" is not taken as an email.
+const BUILTIN_RULES: [(&str, &str); 5] = [
+    (r"\b([0-9a-fA-F]{2}:){5}[0-9a-fA-F]{2}\b", "<mac>"),
+    (
+        r"\b[0-9a-fA-F]{1,4}(:[0-9a-fA-F]{1,4})*::([0-9a-fA-F]{1,4}(:[0-9a-fA-F]{1,4})*)?\b|\b([0-9a-fA-F]{1,4}:){7}[0-9a-fA-F]{1,4}\b",
+        "<ipv6>",
+    ),
+    (r"\b(\d{1,3}\.){3}\d{1,3}\b", "<ipv4>"),
+    (r"\b[\w.+-]+@[\w-]+(\.[\w-]+)+\b", "<email>"),
+    (
+        r#"(?i)(serial(_?number)?["']?\s*[:=]\s*["']?)[\w-]+"#,
+        "${1}<serial>",
+    ),
+];
+
+pub struct Redactor {
+    rules: Vec<(Regex, String)>,
+    /// Literal values to mask (e.g. the serial number of a DUT)
+    secrets: Vec<(String, String)>,
+}
+impl Redactor {
+    /// Creates a redactor with the built-in rules and the given domains and patterns
+    pub fn new(domains: &[String], patterns: &[String]) -> Result<Self> {
+        let mut rules = Vec::new();
+        for (pattern, replacement) in BUILTIN_RULES {
+            rules.push((Regex::new(pattern)?, replacement.to_string()));
+        }
+        for domain in domains {
+            let domain = regex::escape(domain.trim_start_matches('.'));
+            rules.push((
+                Regex::new(&format!(r"\b([\w-]+\.)*{domain}\b"))?,
+                "<host>".to_string(),
+            ));
+        }
+        for pattern in patterns {
+            rules.push((
+                Regex::new(pattern).context(anyhow!("Invalid redact pattern {pattern:?}"))?,
+                "<redacted>".to_string(),
+            ));
+        }
+        Ok(Self {
+            rules,
+            secrets: Vec::new(),
+        })
+    }
+    /// Creates a redactor with the domains and the patterns in the config, which also masks the
+    /// home directory and the name of the current user
+    pub fn from_config() -> Result<Self> {
+        let config = Config::read()?;
+        let mut redactor = Self::new(config.redact_domains(), config.redact_patterns())?;
+        if let Some(home) = dirs::home_dir() {
+            redactor.add_secret(&home.to_string_lossy(), "~");
+        }
+        if let Ok(user) = std::env::var("USER") {
+            // Short names like "me" would mask too many unrelated words
+            if user.len() > 2 {
+                redactor.add_pattern(&format!(r"\b{}\b", regex::escape(&user)), "<user>")?;
+            }
+        }
+        Ok(redactor)
+    }
+    pub fn add_pattern(&mut self, pattern: &str, replacement: &str) -> Result<()> {
+        self.rules
+            .push((Regex::new(pattern)?, replacement.to_string()));
+        Ok(())
+    }
+    /// Masks the value wherever it appears (e.g. in DUT IDs containing the serial number)
+    pub fn add_secret(&mut self, value: &str, replacement: &str) {
+        // "/" as the home directory would mask all the paths
+        if value.trim().len() > 1 {
+            self.secrets
+                .push((value.trim().to_string(), replacement.to_string()));
+        }
+    }
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for (value, replacement) in &self.secrets {
+            text = text.replace(value, replacement);
+        }
+        for (re, replacement) in &self.rules {
+            text = re.replace_all(&text, replacement.as_str()).to_string();
+        }
+        text
+    }
+    /// Redacts the text files in the .tar.gz in place. Binary files are removed, since they
+    /// cannot be checked.
+    pub fn redact_log_bundle(&self, archive: &Path) -> Result<()> {
+        let dir = TempDir::new("lium_redact")?;
+        let status = Command::new("tar")
+            .arg("-xzf")
+            .arg(archive)
+            .arg("-C")
+            .arg(dir.path())
+            .status()?;
+        status
+            .exit_ok()
+            .context(anyhow!("Failed to extract {archive:?}"))?;
+        self.redact_dir(dir.path())?;
+        let status = Command::new("tar")
+            .arg("-czf")
+            .arg(archive)
+            .arg("-C")
+            .arg(dir.path())
+            .arg(".")
+            .status()?;
+        status
+            .exit_ok()
+            .context(anyhow!("Failed to archive {archive:?}"))?;
+        Ok(())
+    }
+    fn redact_dir(&self, dir: &Path) -> Result<()> {
+        for e in fs::read_dir(dir)? {
+            let path = e?.path();
+            if path.is_symlink() {
+                fs::remove_file(&path)?;
+            } else if path.is_dir() {
+                self.redact_dir(&path)?;
+            } else {
+                match fs::read_to_string(&path) {
+                    Ok(s) => fs::write(&path, self.redact(&s))?,
+                    Err(_) => fs::remove_file(&path)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redact_text() {
+        let mut r = Redactor::new(
+            &["corp.example.com".to_string()],
+            &[r"token=\w+".to_string()],
+        )
+        .unwrap();
+        r.add_secret("NXHKDSJ0031", "<serial>");
+        assert_eq!(
+            r.redact("ssh root@192.168.0.2 at 12:34:56 by alice@example.com"),
+            "ssh root@<ipv4> at 12:34:56 by <email>"
+        );
+        assert_eq!(
+            r.redact("[fe80::8a54:1fff:fe0f:72a5%en0] 2001:db8:1:2:3:4:5:6 aa:bb:cc:dd:ee:ff"),
+            "[<ipv6>%en0] <ipv6> <mac>"
+        );
+        assert_eq!(
+            r.redact(r#"{"dut_id":"droid_NXHKDSJ0031","serial":"X1Y2"} serial_number=AB-12"#),
+            r#"{"dut_id":"droid_<serial>","serial":"<serial>"} serial_number=<serial>"#
+        );
+        assert_eq!(
+            r.redact("build1.corp.example.com example.com token=abc"),
+            "<host> example.com <redacted>"
+        );
+    }
+}
@@ -5,6 +5,7 @@
 // https://developers.google.com/open-source/licenses/bsd
 
 use crate::config::Config;
+use crate::util::ensure_online;
 use crate::util::get_stdout;
 use crate::util::run_bash_command;
 use anyhow::anyhow;
@@ -78,6 +79,7 @@ pub fn get_current_synced_arc_version(repo: &str) -> Result<String> {
 }
 
 pub fn repo_sync(repo: &str, force: bool) -> Result<()> {
+    ensure_online("repo sync")?;
     let mut last_failed_repos = None;
 
     loop {
@@ -0,0 +1,302 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! History of test results (tast, gtest and stress runs) per DUT and OS version, kept in a sqlite
+//! database at ~/.lium/results.db so that questions like "has this test ever passed on this
+//! model?" can be answered locally.
+
+use crate::dut::DutInfo;
+use crate::util::gen_path_in_lium_dir;
+use crate::warning;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use rusqlite::params;
+use rusqlite::Connection;
+use rusqlite::Row;
+use serde::Deserialize;
+use serde::Serialize;
+use std::path::Path;
+
+const SCHEMA: &str = r"
+CREATE TABLE IF NOT EXISTS results (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    kind TEXT NOT NULL,
+    test TEXT NOT NULL,
+    status TEXT NOT NULL,
+    dut TEXT NOT NULL,
+    model TEXT NOT NULL,
+    board TEXT NOT NULL,
+    version TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    recorded_at INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS results_test ON results (test);
+";
+
+/// The DUT and the OS version that a test ran against
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TestTarget {
+    pub dut: String,
+    pub model: String,
+    pub board: String,
+    pub version: String,
+}
+impl TestTarget {
+    /// Fetches the model and the version of the DUT
+    pub fn from_dut(dut: &str) -> Result<Self> {
+        let info = DutInfo::new(dut)?;
+        let info = info.info();
+        let get = |k: &str| info.get(k).cloned().unwrap_or_default();
+        Ok(Self {
+            dut: get("dut_id"),
+            model: get("model"),
+            board: get("board"),
+            version: get("release"),
+        })
+    }
+    /// from_dut() that falls back to only the given DUT identifier, not to fail the test run
+    pub fn from_dut_or_default(dut: &str) -> Self {
+        Self::from_dut(dut).unwrap_or_else(|e| {
+            warning!("Failed to get the version of {dut}: {e:#}");
+            Self {
+                dut: dut.to_string(),
+                ..Default::default()
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TestResult {
+    /// Assigned when recorded
+    pub id: i64,
+    /// "tast", "gtest", "reboot-loop", "suspend-stress", ...
+    pub kind: String,
+    pub test: String,
+    /// "PASS", "FAIL" or "SKIP"
+    pub status: String,
+    #[serde(flatten)]
+    pub target: TestTarget,
+    /// Errors or counters of the run
+    pub detail: String,
+    /// UNIX time in seconds
+    pub recorded_at: i64,
+}
+impl TestResult {
+    pub fn new(kind: &str, test: &str, status: &str, target: &TestTarget, detail: &str) -> Self {
+        Self {
+            id: 0,
+            kind: kind.to_string(),
+            test: test.to_string(),
+            status: status.to_string(),
+            target: target.clone(),
+            detail: detail.to_string(),
+            recorded_at: Local::now().timestamp(),
+        }
+    }
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        Ok(Self {
+            id: row.get("id")?,
+            kind: row.get("kind")?,
+            test: row.get("test")?,
+            status: row.get("status")?,
+            target: TestTarget {
+                dut: row.get("dut")?,
+                model: row.get("model")?,
+                board: row.get("board")?,
+                version: row.get("version")?,
+            },
+            detail: row.get("detail")?,
+            recorded_at: row.get("recorded_at")?,
+        })
+    }
+}
+
+/// Conditions to list results. Fields are glob patterns (e.g. "tast.*") and None matches all.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    pub test: Option<String>,
+    pub kind: Option<String>,
+    pub status: Option<String>,
+    pub dut: Option<String>,
+    pub model: Option<String>,
+    pub version: Option<String>,
+}
+
+pub struct ResultsDb {
+    conn: Connection,
+}
+impl ResultsDb {
+    /// Opens ~/.lium/results.db, creating it if needed
+    pub fn open() -> Result<Self> {
+        Self::open_path(&gen_path_in_lium_dir("results.db")?)
+    }
+    pub fn open_path(path: &Path) -> Result<Self> {
+        let conn = Connection::open(path).context(anyhow!("Failed to open {path:?}"))?;
+        Self::init(conn)
+    }
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+    fn init(conn: Connection) -> Result<Self> {
+        conn.execute_batch(SCHEMA)
+            .context("Failed to initialize the results db")?;
+        Ok(Self { conn })
+    }
+    /// Records the results and returns their IDs
+    pub fn record(&mut self, results: &[TestResult]) -> Result<Vec<i64>> {
+        let tx = self.conn.transaction()?;
+        let mut ids = Vec::new();
+        for r in results {
+            tx.execute(
+                "INSERT INTO results (kind, test, status, dut, model, board, version, detail, recorded_at)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    r.kind,
+                    r.test,
+                    r.status,
+                    r.target.dut,
+                    r.target.model,
+                    r.target.board,
+                    r.target.version,
+                    r.detail,
+                    r.recorded_at
+                ],
+            )?;
+            ids.push(tx.last_insert_rowid());
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+    /// Returns the matching results, newest first
+    pub fn list(&self, filter: &Filter, limit: Option<usize>) -> Result<Vec<TestResult>> {
+        let conditions = [
+            ("test", &filter.test),
+            ("kind", &filter.kind),
+            ("status", &filter.status),
+            ("dut", &filter.dut),
+            ("model", &filter.model),
+            ("version", &filter.version),
+        ];
+        let mut sql = "SELECT * FROM results WHERE 1".to_string();
+        let mut values = Vec::new();
+        for (column, pattern) in conditions {
+            if let Some(pattern) = pattern {
+                values.push(pattern.clone());
+                sql += &format!(" AND {column} GLOB ?{}", values.len());
+            }
+        }
+        sql += " ORDER BY recorded_at DESC, id DESC";
+        if let Some(limit) = limit {
+            sql += &format!(" LIMIT {limit}");
+        }
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(values), TestResult::from_row)?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+    pub fn get(&self, id: i64) -> Result<TestResult> {
+        self.conn
+            .query_row(
+                "SELECT * FROM results WHERE id = ?1",
+                [id],
+                TestResult::from_row,
+            )
+            .context(anyhow!("Result {id} is not found"))
+    }
+}
+
+/// Records the results in the default db. Failures are reported as warnings, since they should
+/// not fail the test run itself.
+pub fn record_results(results: &[TestResult]) {
+    if results.is_empty() {
+        return;
+    }
+    if let Err(e) = ResultsDb::open().and_then(|mut db| db.record(results)) {
+        warning!("Failed to record the test results: {e:#}");
+    }
+}
+
+/// Parses results.json written by `tast run`
+pub fn parse_tast_results(json: &str, target: &TestTarget) -> Result<Vec<TestResult>> {
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TastError {
+        reason: String,
+    }
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct TastTest {
+        name: String,
+        #[serde(default)]
+        errors: Option<Vec<TastError>>,
+        #[serde(default)]
+        skip_reason: String,
+    }
+    let tests: Vec<TastTest> =
+        serde_json::from_str(json).context("Failed to parse the tast results")?;
+    Ok(tests
+        .into_iter()
+        .map(|t| {
+            let errors: Vec<String> = t
+                .errors
+                .unwrap_or_default()
+                .into_iter()
+                .map(|e| e.reason)
+                .collect();
+            let (status, detail) = if !errors.is_empty() {
+                ("FAIL", errors.join("\n"))
+            } else if !t.skip_reason.is_empty() {
+                ("SKIP", t.skip_reason)
+            } else {
+                ("PASS", String::new())
+            };
+            TestResult::new("tast", &t.name, status, target, &detail)
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_and_list() {
+        let mut db = ResultsDb::open_in_memory().unwrap();
+        let target = TestTarget {
+            dut: "eve_X".to_string(),
+            model: "eve".to_string(),
+            board: "eve".to_string(),
+            version: "R120-15662.0.0".to_string(),
+        };
+        let results = parse_tast_results(
+            r#"[
+                {"name": "tast.A", "errors": null, "skipReason": ""},
+                {"name": "tast.B", "errors": [{"reason": "timed out"}]},
+                {"name": "tast.C", "skipReason": "missing deps"}
+            ]"#,
+            &target,
+        )
+        .unwrap();
+        let ids = db.record(&results).unwrap();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(db.get(ids[1]).unwrap().status, "FAIL");
+        assert_eq!(db.get(ids[1]).unwrap().detail, "timed out");
+        assert_eq!(db.get(ids[2]).unwrap().status, "SKIP");
+        let filter = Filter {
+            test: Some("tast.[AB]".to_string()),
+            model: Some("eve".to_string()),
+            status: Some("PASS".to_string()),
+            ..Default::default()
+        };
+        let passed = db.list(&filter, None).unwrap();
+        assert_eq!(passed.len(), 1);
+        assert_eq!(passed[0].test, "tast.A");
+        assert_eq!(db.list(&Filter::default(), Some(2)).unwrap().len(), 2);
+        assert!(db.get(100).is_err());
+    }
+}
@@ -386,6 +386,88 @@ impl LocalServo {
         })
         .or(Err(anyhow!("Failed to get EC version after retries")))
     }
+    /// Returns the CCD state reported by the GSC console (e.g. "Locked", "Opened")
+    pub fn ccd_state(&self) -> Result<String> {
+        if !self.is_cr50() {
+            return get_cr50_attached_to_servo(self)?.ccd_state();
+        }
+        let output = self.run_cmd("Shell", "ccd")?;
+        let state = output
+            .split('\n')
+            .rev()
+            .find_map(|line| line.trim().strip_prefix("State: "))
+            .context("Could not detect CCD state")?;
+        Ok(state.trim().to_string())
+    }
+    pub fn is_ccd_opened(&self) -> Result<bool> {
+        let ccd_state = self.ccd_state()?;
+        if ccd_state == "Locked" {
+            Ok(false)
+        } else if ccd_state == "Opened" {
+            Ok(true)
+        } else {
+            Err(anyhow!("Unexpected ccd state: {}", ccd_state))
+        }
+    }
+    /// Open CCD (Closed Case Debugging). rma_auth is used if needed, which requires
+    /// an unlock code pasted by the user.
+    pub fn open_ccd(&self) -> Result<()> {
+        if !self.is_cr50() {
+            return get_cr50_attached_to_servo(self)?.open_ccd();
+        }
+        if self.is_ccd_opened()? {
+            eprintln!("CCD is Opened");
+            return Ok(());
+        }
+        // Get rma_auth_challenge first, to get the code correctly
+        let rma_auth_challenge = self.run_cmd("Shell", "rma_auth")?;
+        // Try ccd open first since pre-MP devices may be able to open ccd without rma_auth
+        self.run_cmd("Shell", "ccd open")?;
+        for _ in 0..3 {
+            // Generate rma_auth URL to unlock and abort
+            let rma_auth_challenge: Vec<&str> = rma_auth_challenge
+                .split('\n')
+                .map(|s| s.trim())
+                .filter(|s| !s.is_empty())
+                .collect();
+            eprintln!("{:?}", rma_auth_challenge);
+            let rma_auth_challenge = rma_auth_challenge
+                .iter()
+                .skip_while(|s| *s != &"generated challenge:")
+                .nth(1)
+                .context("Could not get rma_auth challenge")?;
+            if !rma_auth_challenge.starts_with("RMA Auth error") {
+                eprintln!("CCD unlock is required.");
+                eprintln!(
+                    r#"If you are eligible, visit https://chromeos.google.com/partner/console/cr50reset?challenge={rma_auth_challenge} to get the unlock code and paste the output below. ( For Googlers, go/rma-auth has more details. )"#,
+                );
+                eprintln!("If not, follow https://chromium.googlesource.com/chromiumos/platform/ec/+/cr50_stab/docs/case_closed_debugging_cr50.md#ccd-open to do this manually.");
+                let mut input = String::new();
+                std::io::stdin()
+                    .read_line(&mut input)
+                    .context("Failed to read a line")?;
+                let response = self
+                    .run_cmd(
+                        "Shell",
+                        &format!(
+                            "rma_auth {}",
+                            input
+                                .trim()
+                                .split(':')
+                                .last()
+                                .context("code is invalid")?
+                                .trim()
+                        ),
+                    )
+                    .context("Failed to run rma_auth command")?;
+                return Err(anyhow!("response: {response}"));
+            }
+            eprintln!("Failed: {rma_auth_challenge}");
+            eprintln!("retrying in 3 sec...");
+            std::thread::sleep(std::time::Duration::from_secs(3));
+        }
+        Err(anyhow!("Failed to get rma_auth code."))
+    }
     pub fn read_mac_addr(&self) -> Result<String> {
         if !self.is_servo() {
             return Err(anyhow!(
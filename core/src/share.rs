@@ -0,0 +1,337 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Sharing the artifact store between workstations over HTTP (`lium share serve`), so that
+//! artifacts a teammate has already downloaded can be pulled over the LAN instead of from GS:
+//!
+//! ```text
+//! GET /index             # {"<name>": {"hash": "<sha256>", "size": <bytes>, ...}, ...}
+//! GET /objects/<sha256>  # the payload
+//! ```
+//!
+//! The server requires a bearer token, from $LIUM_SHARE_TOKEN or ~/.lium/share_token, which
+//! must be the same on the server and the clients. It listens on localhost unless --bind is
+//! given. Payloads are verified with their SHA-256 after downloads.
+//!
+//! Peers set with `lium config set share_peers <host:port>...` are tried for artifacts that are
+//! not in the local store only if the origin publishes an MD5 of the object (e.g. GS), which
+//! the payload from the peer must match. Otherwise, the artifact is downloaded from the origin.
+
+use crate::cache::ArtifactEntry;
+use crate::cache::ARTIFACT_STORE;
+use crate::config::Config;
+use crate::fetch::download;
+use crate::fetch::remote_md5;
+use crate::status;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::md5sum;
+use crate::util::sha256sum;
+use crate::warning;
+use crate::webhook::load_or_create_token;
+use crate::webhook::token_matches;
+use crate::webhook::write_response;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde_json::json;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::process::Stdio;
+use std::thread;
+use std::time::Duration;
+
+pub const SHARE_TOKEN_ENV: &str = "LIUM_SHARE_TOKEN";
+pub const DEFAULT_SHARE_PORT: u16 = 9100;
+
+fn share_token() -> Result<String> {
+    load_or_create_token(SHARE_TOKEN_ENV, "share_token")
+}
+
+/// What a GET request asks for
+#[derive(Debug, PartialEq, Eq)]
+enum ShareRequest {
+    Index,
+    Object(String),
+}
+
+/// Reads a GET request. Returns Err((status, message)) for invalid ones.
+fn read_share_request(
+    reader: &mut impl BufRead,
+    token: &str,
+) -> std::result::Result<ShareRequest, (u16, String)> {
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|_| (400, "Failed to read the request".to_string()))?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut authorized = false;
+    loop {
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|_| (400, "Failed to read the headers".to_string()))?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("authorization") {
+                authorized = value
+                    .trim()
+                    .strip_prefix("Bearer ")
+                    .map_or(false, |t| token_matches(t.trim(), token));
+            }
+        }
+    }
+    if !authorized {
+        return Err((401, "Missing or wrong bearer token".to_string()));
+    }
+    if method != "GET" {
+        return Err((405, "Only GET is supported".to_string()));
+    }
+    if path == "/index" {
+        return Ok(ShareRequest::Index);
+    }
+    match path.strip_prefix("/objects/") {
+        Some(hash) if hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit()) => {
+            Ok(ShareRequest::Object(hash.to_string()))
+        }
+        _ => Err((404, "Use /index or /objects/<sha256>".to_string())),
+    }
+}
+
+fn handle_client(stream: TcpStream, token: &str) -> Result<()> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut writer = stream.try_clone()?;
+    let request = read_share_request(&mut BufReader::new(stream), token);
+    let object = match request {
+        Ok(ShareRequest::Index) => {
+            let index = serde_json::to_value(ARTIFACT_STORE.entries()?)?;
+            return write_response(&mut writer, 200, &index);
+        }
+        Ok(ShareRequest::Object(hash)) => ARTIFACT_STORE.object(&hash)?,
+        Err((status, message)) => {
+            return write_response(&mut writer, status, &json!({ "error": message }));
+        }
+    };
+    let Some(object) = object else {
+        return write_response(&mut writer, 404, &json!({ "error": "Not in the store" }));
+    };
+    status!("{peer}: sending {object:?}");
+    let mut file = fs::File::open(&object)?;
+    write!(
+        writer,
+        "HTTP/1.1 200 OK\r\nContent-Type: application/octet-stream\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        file.metadata()?.len()
+    )?;
+    std::io::copy(&mut file, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Serves the artifact store until interrupted
+pub fn serve(bind: &str, port: u16) -> Result<()> {
+    let token = share_token()?;
+    let listener =
+        TcpListener::bind((bind, port)).context(anyhow!("Failed to listen on {bind}:{port}"))?;
+    status!(
+        "Sharing the artifact store on {bind}:{port}. Clients need the token in ~/.lium/share_token or ${SHARE_TOKEN_ENV}"
+    );
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                warning!("Failed to accept a connection: {e}");
+                continue;
+            }
+        };
+        let token = token.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_client(stream, &token) {
+                warning!("Failed to respond to a client: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Returns the URL of the path on the peer ("host", "host:port", "[ipv6]:port" or "ipv6")
+fn peer_url(peer: &str, path: &str) -> String {
+    let has_port = if peer.starts_with('[') {
+        !peer.ends_with(']')
+    } else {
+        peer.matches(':').count() == 1
+    };
+    let peer = if has_port {
+        peer.to_string()
+    } else if peer.contains(':') && !peer.starts_with('[') {
+        format!("[{peer}]:{DEFAULT_SHARE_PORT}")
+    } else {
+        format!("{peer}:{DEFAULT_SHARE_PORT}")
+    };
+    format!("http://{peer}{path}")
+}
+
+/// Runs curl with the share token. The header is passed via stdin so that other users cannot
+/// see the token in the process list.
+fn curl(url: &str, args: &[&OsStr]) -> Result<Output> {
+    let header = format!("Authorization: Bearer {}\n", share_token()?);
+    let mut child = Command::new("curl")
+        .args(["-fsS", "--connect-timeout", "5", "-H", "@-"])
+        .args(args)
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("Failed to run curl")?;
+    child
+        .stdin
+        .take()
+        .context("Failed to get the stdin of curl")?
+        .write_all(header.as_bytes())?;
+    child.wait_with_output().context("Failed to run curl")
+}
+
+/// Returns the artifacts in the store of the peer
+pub fn peer_index(peer: &str) -> Result<HashMap<String, ArtifactEntry>> {
+    let output = curl(&peer_url(peer, "/index"), &[])?;
+    output
+        .status
+        .exit_ok()
+        .context(anyhow!("Failed to get the index of {peer}"))?;
+    serde_json::from_slice(&output.stdout).context(anyhow!("Invalid index from {peer}"))
+}
+
+/// Downloads the artifact from the peer into the local store, verifying its SHA-256 in the index
+/// of the peer and `trusted_md5` (an MD5 published by the origin) if given
+pub fn fetch_from_peer(
+    peer: &str,
+    name: &str,
+    entry: &ArtifactEntry,
+    trusted_md5: Option<&str>,
+) -> Result<PathBuf> {
+    let tmp = gen_path_in_lium_dir(&format!("share/{}.tmp", entry.hash))?;
+    let result = (|| {
+        curl(
+            &peer_url(peer, &format!("/objects/{}", entry.hash)),
+            &[OsStr::new("-o"), tmp.as_os_str()],
+        )?
+        .status
+        .exit_ok()
+        .context(anyhow!("Failed to download {name} from {peer}"))?;
+        let hash = sha256sum(&tmp)?;
+        if hash != entry.hash {
+            return Err(anyhow!(
+                "Checksum mismatch of {name} from {peer}: {hash} != {}",
+                entry.hash
+            ));
+        }
+        if let Some(trusted_md5) = trusted_md5 {
+            let md5 = md5sum(&tmp)?;
+            if md5 != trusted_md5 {
+                return Err(anyhow!(
+                    "{name} from {peer} does not match the origin: MD5 {md5} != {trusted_md5}"
+                ));
+            }
+        }
+        ARTIFACT_STORE.add(name, &tmp)
+    })();
+    drop(fs::remove_file(&tmp));
+    result
+}
+
+/// Fetches the artifact into the local store from the first of the configured peers that has
+/// it with the MD5. Returns the path of the stored artifact, or None if no peer has it.
+fn fetch_from_peers(name: &str, trusted_md5: &str) -> Result<Option<PathBuf>> {
+    for peer in Config::read()?.share_peers() {
+        let entry = match peer_index(peer) {
+            Ok(index) => index.get(name).cloned(),
+            Err(e) => {
+                warning!("Skipping peer {peer}: {e:#}");
+                continue;
+            }
+        };
+        let Some(entry) = entry else {
+            continue;
+        };
+        status!("Fetching {name} from {peer}...");
+        match fetch_from_peer(peer, name, &entry, Some(trusted_md5)) {
+            Ok(path) => return Ok(Some(path)),
+            Err(e) => warning!("{e:#}"),
+        }
+    }
+    Ok(None)
+}
+
+/// Returns the path of the artifact in the store. If it is not there yet, it is fetched from
+/// the share peers (verified with the MD5 published by the origin), or downloaded from `url`.
+pub fn fetch_artifact(name: &str, url: &str) -> Result<PathBuf> {
+    if let Some(path) = ARTIFACT_STORE.get(name)? {
+        return Ok(path);
+    }
+    if !Config::read()?.share_peers().is_empty() {
+        match remote_md5(url) {
+            Ok(Some(md5)) => {
+                if let Some(path) = fetch_from_peers(name, &md5)? {
+                    return Ok(path);
+                }
+            }
+            Ok(None) => warning!("{url} has no MD5 to verify copies of the share peers"),
+            Err(e) => warning!("Failed to get the MD5 of {url}: {e:#}"),
+        }
+    }
+    ARTIFACT_STORE.get_or_fetch(name, &|tmp| download(url, tmp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn share_requests() {
+        let read = |raw: &str| read_share_request(&mut BufReader::new(raw.as_bytes()), "secret");
+        let auth = |raw: &str| raw.replacen("\r\n", "\r\nAuthorization: Bearer secret\r\n", 1);
+        let hash = "a".repeat(64);
+        assert_eq!(
+            read(&auth("GET /index HTTP/1.1\r\n\r\n")),
+            Ok(ShareRequest::Index)
+        );
+        assert_eq!(
+            read(&auth(&format!("GET /objects/{hash} HTTP/1.1\r\n\r\n"))),
+            Ok(ShareRequest::Object(hash))
+        );
+        let status = |raw: &str| read(raw).unwrap_err().0;
+        assert_eq!(status(&auth("GET /objects/../x HTTP/1.1\r\n\r\n")), 404);
+        assert_eq!(status(&auth("POST /index HTTP/1.1\r\n\r\n")), 405);
+        assert_eq!(status("GET /index HTTP/1.1\r\n\r\n"), 401);
+        assert_eq!(
+            status("GET /index HTTP/1.1\r\nAuthorization: Bearer wrong!\r\n\r\n"),
+            401
+        );
+        assert_eq!(peer_url("ws1", "/index"), "http://ws1:9100/index");
+        assert_eq!(peer_url("ws1:8000", "/index"), "http://ws1:8000/index");
+        assert_eq!(peer_url("fd00::1", "/index"), "http://[fd00::1]:9100/index");
+        assert_eq!(
+            peer_url("[fd00::1]", "/index"),
+            "http://[fd00::1]:9100/index"
+        );
+        assert_eq!(
+            peer_url("[fd00::1]:8000", "/index"),
+            "http://[fd00::1]:8000/index"
+        );
+    }
+}
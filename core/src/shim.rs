@@ -0,0 +1,278 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A persistent helper on DUTs (`lium-shim`) that runs commands sent over a single ssh channel,
+//! so that repeated queries to a DUT in a lium process skip the ssh handshake.
+//!
+//! A request is the length of the command in bytes on a line, followed by the command. The
+//! response is a line `LIUM_SHIM <exit code> <stdout length> <stderr length>`, followed by
+//! stdout and stderr.
+
+use crate::dut::SshInfo;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use lazy_static::lazy_static;
+use nix::poll::poll;
+use nix::poll::PollFd;
+use nix::poll::PollFlags;
+use std::collections::HashMap;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::os::unix::io::AsRawFd;
+use std::process::Child;
+use std::process::ChildStdin;
+use std::process::ChildStdout;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
+
+const SHIM_PATH: &str = "/usr/local/lium_shim/lium-shim";
+/// How long a command run by the shim can take until the shim is considered stuck
+const SHIM_READ_TIMEOUT: Duration = Duration::from_secs(60);
+const SHIM_SCRIPT: &str = r#"#!/bin/bash
+# Generated by lium. Runs commands from stdin and frames their results (see shim.rs of lium).
+export LC_ALL=C
+t="$(mktemp -d)"
+trap 'rm -rf "$t"' EXIT
+while IFS= read -r len; do
+  IFS= read -r -N "$len" cmd || exit 1
+  bash -c "$cmd" </dev/null >"$t/out" 2>"$t/err"
+  echo "LIUM_SHIM $? $(stat -c %s "$t/out") $(stat -c %s "$t/err")"
+  cat "$t/out" "$t/err"
+done
+"#;
+
+/// The result of a command run by the shim
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ShimOutput {
+    pub code: i32,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+impl ShimOutput {
+    /// Returns the trimmed stdout, or an error like SshInfo::run_cmd_stdio() does
+    pub fn stdout_or_error(&self) -> Result<String> {
+        let stdout = String::from_utf8_lossy(&self.stdout).trim().to_string();
+        if self.code == 0 {
+            Ok(stdout)
+        } else {
+            Err(anyhow!(
+                "run_cmd_stdio failed: {} {}",
+                String::from_utf8_lossy(&self.stderr).trim(),
+                stdout
+            ))
+        }
+    }
+}
+
+/// The stdout of the shim that fails reads with TimedOut after the deadline
+struct DeadlineReader {
+    inner: ChildStdout,
+    deadline: Instant,
+}
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        let mut fds = [PollFd::new(self.inner.as_raw_fd(), PollFlags::POLLIN)];
+        if poll(
+            &mut fds,
+            remaining.as_millis().try_into().unwrap_or(i32::MAX),
+        )? == 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "lium-shim did not respond in time",
+            ));
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// A running shim on a DUT. ssh is killed when this is dropped.
+pub struct Shim {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<DeadlineReader>,
+    timeout: Duration,
+}
+impl Shim {
+    fn from_child(mut child: Child) -> Result<Self> {
+        let stdin = child.stdin.take().context("No stdin")?;
+        let stdout = child.stdout.take().context("No stdout")?;
+        Ok(Self {
+            child,
+            stdin,
+            stdout: BufReader::new(DeadlineReader {
+                inner: stdout,
+                deadline: Instant::now(),
+            }),
+            timeout: SHIM_READ_TIMEOUT,
+        })
+    }
+    /// Pushes the shim to the DUT and starts it in the same ssh session
+    pub fn start(ssh: &SshInfo) -> Result<Self> {
+        let cmd = format!(
+            "mkdir -p {dir} && cat > {SHIM_PATH}.tmp <<'LIUM_EOF' && chmod 755 {SHIM_PATH}.tmp && mv {SHIM_PATH}.tmp {SHIM_PATH} && exec {SHIM_PATH}\n{SHIM_SCRIPT}LIUM_EOF\n",
+            dir = SHIM_PATH.rsplit_once('/').map(|(d, _)| d).unwrap_or("."),
+        );
+        let child = ssh
+            .ssh_cmd(None)?
+            .arg(cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .context("Failed to start lium-shim")?;
+        let mut shim = Self::from_child(child)?;
+        shim.run("true").context("lium-shim did not respond")?;
+        Ok(shim)
+    }
+    /// Runs the command. An error leaves the channel in an unknown state, so the shim should be
+    /// dropped (e.g. after a timeout).
+    pub fn run(&mut self, cmd: &str) -> Result<ShimOutput> {
+        self.stdout.get_mut().deadline = Instant::now() + self.timeout;
+        write!(self.stdin, "{}\n{cmd}", cmd.len())?;
+        self.stdin.flush()?;
+        let mut header = String::new();
+        if self.stdout.read_line(&mut header)? == 0 {
+            return Err(anyhow!("lium-shim exited"));
+        }
+        let (code, stdout_len, stderr_len) = parse_header(&header)?;
+        let mut stdout = vec![0; stdout_len];
+        self.stdout.read_exact(&mut stdout)?;
+        let mut stderr = vec![0; stderr_len];
+        self.stdout.read_exact(&mut stderr)?;
+        Ok(ShimOutput {
+            code,
+            stdout,
+            stderr,
+        })
+    }
+}
+impl Drop for Shim {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn parse_header(header: &str) -> Result<(i32, usize, usize)> {
+    let err = || anyhow!("Unexpected response from lium-shim: {header:?}");
+    let fields: Vec<&str> = header.split_whitespace().collect();
+    let [magic, code, stdout_len, stderr_len] = fields[..] else {
+        return Err(err());
+    };
+    if magic != "LIUM_SHIM" {
+        return Err(err());
+    }
+    Ok((
+        code.parse().map_err(|_| err())?,
+        stdout_len.parse().map_err(|_| err())?,
+        stderr_len.parse().map_err(|_| err())?,
+    ))
+}
+
+/// The shim of a DUT in this process
+#[derive(Default)]
+enum ShimState {
+    #[default]
+    NotStarted,
+    Running(Shim),
+    /// The shim could not be started (e.g. a DUT without bash or a read-only /usr/local), so
+    /// commands go to ssh directly
+    Disabled,
+}
+
+lazy_static! {
+    /// Shims of this process keyed by host:port. Each DUT has its own lock so that commands to
+    /// different DUTs run concurrently.
+    static ref SHIMS: Mutex<HashMap<String, Arc<Mutex<ShimState>>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Runs the command via the shim of the DUT, starting it on the first use. A shim that fails
+/// is dropped so that the next call starts a new one, and a shim that can not be started is not
+/// tried again.
+pub fn run_cmd(ssh: &SshInfo, cmd: &str) -> Result<ShimOutput> {
+    let state = SHIMS
+        .lock()
+        .unwrap()
+        .entry(ssh.host_and_port())
+        .or_default()
+        .clone();
+    let mut state = state.lock().unwrap();
+    if let ShimState::NotStarted = *state {
+        match Shim::start(ssh) {
+            Ok(shim) => *state = ShimState::Running(shim),
+            Err(e) => {
+                *state = ShimState::Disabled;
+                return Err(anyhow!("lium-shim is not available: {e:#}"));
+            }
+        }
+    }
+    let ShimState::Running(shim) = &mut *state else {
+        return Err(anyhow!("lium-shim is not available"));
+    };
+    let result = shim.run(cmd);
+    if result.is_err() {
+        *state = ShimState::NotStarted;
+    }
+    result
+}
+
+fn is_timeout(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<io::Error>()
+        .map_or(false, |e| e.kind() == io::ErrorKind::TimedOut)
+}
+
+/// Runs the command like SshInfo::run_cmd_stdio(), via the shim if possible
+pub fn run_cmd_stdio(ssh: &SshInfo, cmd: &str) -> Result<String> {
+    match run_cmd(ssh, cmd) {
+        Ok(output) => output.stdout_or_error(),
+        // The command has already run on the DUT, so running it again would double the wait
+        // and its side effects
+        Err(e) if is_timeout(&e) => Err(e),
+        Err(_) => ssh.run_cmd_stdio(cmd),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn shim_protocol() {
+        assert_eq!(parse_header("LIUM_SHIM 1 2 3\n").unwrap(), (1, 2, 3));
+        assert!(parse_header("Welcome!\n").is_err());
+        assert!(parse_header("LIUM_SHIM 1 2\n").is_err());
+
+        // Run the script locally in place of the DUT
+        let child = Command::new("bash")
+            .args(["-c", SHIM_SCRIPT])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut shim = Shim::from_child(child).unwrap();
+        let output = shim.run("echo 'a\nb' && echo err >&2").unwrap();
+        assert_eq!(output.stdout, b"a\nb\n");
+        assert_eq!(output.stderr, b"err\n");
+        assert_eq!(output.stdout_or_error().unwrap(), "a\nb");
+        let output = shim.run("printf é; exit 3").unwrap();
+        assert_eq!(output.code, 3);
+        assert_eq!(output.stdout, "é".as_bytes());
+        assert!(output.stdout_or_error().is_err());
+        shim.timeout = Duration::from_millis(100);
+        assert!(is_timeout(&shim.run("sleep 5").unwrap_err()));
+    }
+}
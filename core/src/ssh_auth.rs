@@ -0,0 +1,198 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Authentication other than the static testing key for hosts that do not accept it (e.g.
+//! cloudtops or jump hosts that require security-key backed SSO certificates). Configured per
+//! host pattern with `lium config set ssh_auth <host_regex> [agent] [user=<name>] [cert=<path>]
+//! [key=<path>] [refresh=<command>]`.
+
+use crate::util::get_stdout;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use serde::Serialize;
+use std::process::Command;
+use std::time::Duration;
+
+/// Certificates expiring sooner than this are treated as stale, so that they do not expire in
+/// the middle of a command
+pub const CERT_MIN_VALIDITY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default, PartialEq, Eq)]
+pub struct SshAuth {
+    /// Forward the local ssh-agent (e.g. for the keys loaded by the SSO tool)
+    #[serde(default)]
+    agent: bool,
+    /// User to log in as instead of root
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    user: Option<String>,
+    /// SSO certificate (e.g. ~/.ssh/id_ecdsa_sk-cert.pub)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    cert: Option<String>,
+    /// Private key of the certificate. Defaults to the certificate path without "-cert.pub".
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    key: Option<String>,
+    /// Command to get a fresh certificate (e.g. gcert), suggested when it is stale
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[serde(default)]
+    refresh: Option<String>,
+}
+impl SshAuth {
+    /// Parses the values of `lium config set ssh_auth <host_regex> ...` after the regex
+    pub fn parse<K: AsRef<str>>(values: &[K]) -> Result<Self> {
+        let mut auth = Self::default();
+        for v in values {
+            let v = v.as_ref();
+            match v.split_once('=') {
+                None if v == "agent" => auth.agent = true,
+                Some(("user", user)) => auth.user = Some(user.to_string()),
+                Some(("cert", cert)) => auth.cert = Some(cert.to_string()),
+                Some(("key", key)) => auth.key = Some(key.to_string()),
+                Some(("refresh", cmd)) => auth.refresh = Some(cmd.to_string()),
+                _ => {
+                    return Err(anyhow!(
+                    "Invalid ssh_auth parameter {v:?}. Use agent, user=, cert=, key= or refresh="
+                ))
+                }
+            }
+        }
+        if auth == Self::default() {
+            return Err(anyhow!(
+                "ssh_auth takes at least one of agent, user=, cert="
+            ));
+        }
+        Ok(auth)
+    }
+    fn key(&self) -> Option<String> {
+        self.key.clone().or_else(|| {
+            self.cert
+                .as_ref()
+                .map(|cert| cert.trim_end_matches("-cert.pub").to_string())
+        })
+    }
+    /// Returns the options for ssh
+    pub fn ssh_options(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if self.agent {
+            args.extend(["-o".to_string(), "ForwardAgent=yes".to_string()]);
+        }
+        if let Some(user) = &self.user {
+            args.extend(["-o".to_string(), format!("User={user}")]);
+        }
+        if let Some(cert) = &self.cert {
+            args.extend(["-o".to_string(), format!("CertificateFile={cert}")]);
+        }
+        if let Some(key) = self.key() {
+            args.extend(["-i".to_string(), key]);
+        }
+        args
+    }
+    fn refresh_hint(&self) -> String {
+        match &self.refresh {
+            Some(cmd) => format!("Run `{cmd}` to get a fresh one"),
+            None => "Please renew it".to_string(),
+        }
+    }
+    /// Checks that the agent has keys and the certificate does not expire soon
+    pub fn preflight(&self) -> Result<()> {
+        if self.agent {
+            if std::env::var_os("SSH_AUTH_SOCK").is_none() {
+                return Err(anyhow!(
+                    "ssh-agent forwarding is configured but SSH_AUTH_SOCK is not set"
+                ));
+            }
+            let output = Command::new("ssh-add")
+                .arg("-l")
+                .output()
+                .context("Failed to run ssh-add")?;
+            if !output.status.success() {
+                return Err(anyhow!(
+                    "ssh-agent has no identities. {}",
+                    self.refresh_hint()
+                ));
+            }
+        }
+        let Some(cert) = &self.cert else {
+            return Ok(());
+        };
+        let path = cert.replacen(
+            '~',
+            &dirs::home_dir().unwrap_or_default().to_string_lossy(),
+            1,
+        );
+        let output = Command::new("ssh-keygen")
+            .args(["-L", "-f", &path])
+            .output()
+            .context("Failed to run ssh-keygen")?;
+        output.status.exit_ok().context(anyhow!(
+            "Failed to read the certificate {cert}. {}",
+            self.refresh_hint()
+        ))?;
+        if let Some(valid_to) = parse_cert_valid_to(&get_stdout(&output)) {
+            let deadline =
+                Local::now().naive_local() + chrono::Duration::from_std(CERT_MIN_VALIDITY)?;
+            if valid_to < deadline {
+                return Err(anyhow!(
+                    "The certificate {cert} expires at {valid_to}. {}",
+                    self.refresh_hint()
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Returns the end of the validity (local time) in the output of `ssh-keygen -L`, or None if the
+/// certificate is valid forever
+pub fn parse_cert_valid_to(output: &str) -> Option<NaiveDateTime> {
+    output
+        .lines()
+        .find_map(|l| l.trim().strip_prefix("Valid: "))
+        .and_then(|v| v.rsplit_once(" to "))
+        .and_then(|(_, to)| NaiveDateTime::parse_from_str(to.trim(), "%Y-%m-%dT%H:%M:%S").ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_ssh_auth() {
+        let auth =
+            SshAuth::parse(&["agent", "user=alice", "cert=~/.ssh/id_ecdsa_sk-cert.pub"]).unwrap();
+        assert_eq!(
+            auth.ssh_options(),
+            [
+                "-o",
+                "ForwardAgent=yes",
+                "-o",
+                "User=alice",
+                "-o",
+                "CertificateFile=~/.ssh/id_ecdsa_sk-cert.pub",
+                "-i",
+                "~/.ssh/id_ecdsa_sk"
+            ]
+        );
+        assert!(SshAuth::parse(&["agnet"]).is_err());
+        assert!(SshAuth::parse::<&str>(&[]).is_err());
+    }
+
+    #[test]
+    fn cert_validity() {
+        let output = "id-cert.pub:\n        Key ID: \"me\"\n        Valid: from 2024-06-01T10:00:00 to 2024-06-01T22:00:00\n";
+        assert_eq!(
+            parse_cert_valid_to(output),
+            NaiveDateTime::parse_from_str("2024-06-01 22:00:00", "%Y-%m-%d %H:%M:%S").ok()
+        );
+        assert_eq!(parse_cert_valid_to("        Valid: forever\n"), None);
+    }
+}
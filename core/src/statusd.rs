@@ -0,0 +1,198 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! `lium statusd` keeps the status and the info of the cached DUTs fresh, and keeps an ssh
+//! master connection to each online DUT. While it is running:
+//!
+//! - other lium commands reuse the master connections via ControlPath, which skips the ssh
+//!   handshake.
+//! - `dut list --status`, `dut info` and `dut monitor` query it over a unix socket at
+//!   ~/.lium/statusd.sock instead of accessing the DUTs.
+//!
+//! The protocol is one JSON request per connection (e.g. `{"dut":"eve_XXX"}` or `{}` for all)
+//! and a JSON map from DUT IDs to DutState as the response.
+
+use crate::cancel;
+use crate::dut::DutInfo;
+use crate::dut::DUT_PROBE_TIMEOUT;
+use crate::dut::MAX_PARALLEL_SSH;
+use crate::dut::SSH_CACHE;
+use crate::status;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::run_concurrently;
+use crate::util::with_timeout;
+use crate::warning;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use chrono::Local;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Write;
+use std::os::unix::net::UnixListener;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+const QUERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The last known state of a DUT
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DutState {
+    /// "Online", "Offline" or "AddressReused" (another DUT is at the address)
+    pub status: String,
+    /// Values of the default keys of DutInfo. Empty unless online.
+    pub info: HashMap<String, String>,
+    /// UNIX time in seconds
+    pub checked_at: i64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Request {
+    /// All DUTs if None
+    #[serde(default)]
+    dut: Option<String>,
+}
+
+pub fn socket_path() -> Result<PathBuf> {
+    gen_path_in_lium_dir("statusd.sock")
+}
+
+/// Returns the ssh options to reuse the master connections of statusd if it is running
+pub fn control_path_options() -> Option<[String; 2]> {
+    if !socket_path().ok()?.exists() {
+        return None;
+    }
+    let dir = gen_path_in_lium_dir("ssh_control/.keep").ok()?;
+    let dir = dir.parent()?.to_str()?;
+    // %C is a hash of the host, port and user. ssh connects directly if no master is there.
+    Some(["-o".to_string(), format!("ControlPath={dir}/%C")])
+}
+
+/// Queries the running statusd. Returns None if it is not running.
+pub fn query(dut: Option<&str>) -> Option<HashMap<String, DutState>> {
+    let mut stream = UnixStream::connect(socket_path().ok()?).ok()?;
+    stream.set_read_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    stream.set_write_timeout(Some(QUERY_TIMEOUT)).ok()?;
+    let request = Request {
+        dut: dut.map(|s| s.to_string()),
+    };
+    writeln!(stream, "{}", serde_json::to_string(&request).ok()?).ok()?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line).ok()?;
+    serde_json::from_str(&line).ok()
+}
+
+/// Returns the values of the keys known to statusd, if all of them are there
+pub fn query_keys(dut: &str, keys: &[&str]) -> Option<HashMap<String, String>> {
+    let state = query(Some(dut))?.remove(dut)?;
+    keys.iter()
+        .map(|k| Some((k.to_string(), state.info.get(*k)?.clone())))
+        .collect()
+}
+
+type States = Arc<Mutex<HashMap<String, DutState>>>;
+
+fn handle_client(stream: UnixStream, states: &States) -> Result<()> {
+    stream.set_read_timeout(Some(QUERY_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let request: Request = serde_json::from_str(&line).unwrap_or_default();
+    let states = states.lock().unwrap();
+    let response: HashMap<&String, &DutState> = states
+        .iter()
+        .filter(|(id, _)| request.dut.as_ref().map_or(true, |dut| dut == *id))
+        .collect();
+    writeln!(&stream, "{}", serde_json::to_string(&response)?)?;
+    Ok(())
+}
+
+/// Checks the DUT in SSH_CACHE
+pub async fn check(id: &str) -> DutState {
+    let info = with_timeout(DUT_PROBE_TIMEOUT, DutInfo::new_async(id)).await;
+    let (status, info) = match info {
+        Ok(dut) if dut.id() == id => ("Online", dut.info().clone()),
+        Ok(_) => ("AddressReused", HashMap::new()),
+        Err(_) => ("Offline", HashMap::new()),
+    };
+    DutState {
+        status: status.to_string(),
+        info,
+        checked_at: Local::now().timestamp(),
+    }
+}
+
+/// Runs the service until Ctrl-C, checking the DUTs every `interval`
+pub fn serve(interval: Duration) -> Result<()> {
+    let path = socket_path()?;
+    if query(None).is_some() {
+        return Err(anyhow!("statusd is already running at {path:?}"));
+    }
+    // The socket of a previous instance that was killed
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).context(anyhow!("Failed to bind {path:?}"))?;
+    cancel::install_handler()?;
+    let states: States = Arc::new(Mutex::new(HashMap::new()));
+    {
+        let states = states.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if let Err(e) = handle_client(stream, &states) {
+                    warning!("Failed to respond to a client: {e:#}");
+                }
+            }
+        });
+    }
+    status!("statusd is listening at {path:?}");
+    let result = run_checks(&states, interval);
+    let _ = fs::remove_file(&path);
+    if cancel::is_cancelled() {
+        status!("statusd is stopped");
+        return Ok(());
+    }
+    result
+}
+
+fn run_checks(states: &States, interval: Duration) -> Result<()> {
+    let mut masters = HashMap::new();
+    loop {
+        let duts = SSH_CACHE.entries()?;
+        let checked = run_concurrently(duts.keys(), MAX_PARALLEL_SSH, |id| async move {
+            (id.clone(), check(id).await)
+        });
+        cancel::check()?;
+        let online = checked.iter().filter(|(_, s)| s.status == "Online").count();
+        status!("{online} of {} DUTs are online", checked.len());
+        for (id, state) in &checked {
+            let alive = masters
+                .get_mut(id)
+                .map_or(false, |c: &mut async_process::Child| {
+                    matches!(c.try_status(), Ok(None))
+                });
+            if state.status != "Online" {
+                // Killed on drop
+                masters.remove(id);
+            } else if !alive {
+                match duts[id].start_control_master() {
+                    Ok(child) => {
+                        masters.insert(id.clone(), child);
+                    }
+                    Err(e) => warning!("Failed to connect to {id}: {e:#}"),
+                }
+            }
+        }
+        masters.retain(|id, _| duts.contains_key(id));
+        *states.lock().unwrap() = checked.into_iter().collect();
+        cancel::sleep(interval)?;
+    }
+}
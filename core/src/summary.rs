@@ -0,0 +1,213 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Summaries of test results for CI systems and code review comments, written with
+//! `--report <format>:<destination>`:
+//!
+//! - junit:out.xml: JUnit XML with a testsuite per DUT
+//! - md:summary.md: a Markdown table
+//!
+//! The destination can be anything `--output` accepts (a path, gs://bucket/key or
+//! http(s)://collector).
+
+use crate::output;
+use crate::results::TestResult;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    Junit,
+    Markdown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReportSpec {
+    pub format: ReportFormat,
+    pub dest: String,
+}
+impl FromStr for ReportSpec {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (format, dest) = s
+            .split_once(':')
+            .filter(|(_, dest)| !dest.is_empty())
+            .ok_or_else(|| anyhow!("Invalid report {s:?}. Use junit:<path> or md:<path>"))?;
+        let format = match format {
+            "junit" => ReportFormat::Junit,
+            "md" | "markdown" => ReportFormat::Markdown,
+            _ => return Err(anyhow!("Unknown report format {format:?}. Use junit or md")),
+        };
+        Ok(Self {
+            format,
+            dest: dest.to_string(),
+        })
+    }
+}
+
+/// Parses the values of --report, to fail before running anything
+pub fn parse_report_specs(specs: &[String]) -> Result<Vec<ReportSpec>> {
+    specs.iter().map(|s| s.parse()).collect()
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn count(results: &[&TestResult], status: &str) -> usize {
+    results.iter().filter(|r| r.status == status).count()
+}
+
+/// Returns JUnit XML with a testsuite per DUT
+pub fn junit(title: &str, results: &[TestResult]) -> String {
+    let mut by_dut: BTreeMap<&str, Vec<&TestResult>> = BTreeMap::new();
+    for r in results {
+        by_dut.entry(&r.target.dut).or_default().push(r);
+    }
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"{}\" tests=\"{}\" failures=\"{}\">\n",
+        xml_escape(title),
+        results.len(),
+        results.iter().filter(|r| r.status == "FAIL").count()
+    );
+    for (dut, results) in by_dut {
+        xml += &format!(
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\">\n",
+            xml_escape(dut),
+            results.len(),
+            count(&results, "FAIL"),
+            count(&results, "SKIP")
+        );
+        for r in results {
+            xml += &format!(
+                "    <testcase classname=\"{}\" name=\"{}\"",
+                xml_escape(&r.kind),
+                xml_escape(&r.test)
+            );
+            match r.status.as_str() {
+                "FAIL" => {
+                    xml += &format!(
+                        ">\n      <failure message=\"{}\">{}</failure>\n    </testcase>\n",
+                        xml_escape(r.detail.lines().next().unwrap_or_default()),
+                        xml_escape(&r.detail)
+                    )
+                }
+                "SKIP" => xml += ">\n      <skipped/>\n    </testcase>\n",
+                _ if r.detail.is_empty() => xml += "/>\n",
+                _ => {
+                    xml += &format!(
+                        ">\n      <system-out>{}</system-out>\n    </testcase>\n",
+                        xml_escape(&r.detail)
+                    )
+                }
+            }
+        }
+        xml += "  </testsuite>\n";
+    }
+    xml += "</testsuites>\n";
+    xml
+}
+
+/// Returns a Markdown summary with a table of the results, failures first
+pub fn markdown(title: &str, results: &[TestResult]) -> String {
+    let cell = |s: &str| s.lines().next().unwrap_or_default().replace('|', "\\|");
+    let all: Vec<&TestResult> = results.iter().collect();
+    let mut md = format!(
+        "## {title}\n\n{} passed, {} failed, {} skipped\n\n",
+        count(&all, "PASS"),
+        count(&all, "FAIL"),
+        count(&all, "SKIP")
+    );
+    if results.is_empty() {
+        return md;
+    }
+    md += "| Status | Test | DUT | Model | Version | Detail |\n";
+    md += "|---|---|---|---|---|---|\n";
+    let mut sorted = all;
+    sorted.sort_by_key(|r| (r.status != "FAIL", r.status != "SKIP"));
+    for r in sorted {
+        let status = if r.status == "FAIL" {
+            format!("**{}**", r.status)
+        } else {
+            r.status.clone()
+        };
+        md += &format!(
+            "| {status} | {} | {} | {} | {} | {} |\n",
+            cell(&r.test),
+            cell(&r.target.dut),
+            cell(&r.target.model),
+            cell(&r.target.version),
+            cell(&r.detail)
+        );
+    }
+    md
+}
+
+/// Writes the results in the formats to the destinations
+pub fn write_reports(specs: &[ReportSpec], title: &str, results: &[TestResult]) -> Result<()> {
+    for spec in specs {
+        let content = match spec.format {
+            ReportFormat::Junit => junit(title, results),
+            ReportFormat::Markdown => markdown(title, results),
+        };
+        let mut sink = output::open(Some(&spec.dest))?;
+        sink.write_line(content.trim_end())?;
+        sink.finish()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::results::TestTarget;
+
+    #[test]
+    fn summaries() {
+        assert_eq!(
+            "junit:out.xml".parse::<ReportSpec>().unwrap(),
+            ReportSpec {
+                format: ReportFormat::Junit,
+                dest: "out.xml".to_string()
+            }
+        );
+        assert_eq!(
+            "md:gs://b/summary.md".parse::<ReportSpec>().unwrap().dest,
+            "gs://b/summary.md"
+        );
+        assert!("out.xml".parse::<ReportSpec>().is_err());
+        assert!("html:out.html".parse::<ReportSpec>().is_err());
+        assert!("md:".parse::<ReportSpec>().is_err());
+
+        let target = TestTarget {
+            dut: "eve_1".to_string(),
+            model: "eve".to_string(),
+            board: "eve".to_string(),
+            version: "15662.0.0".to_string(),
+        };
+        let results = vec![
+            TestResult::new("tast", "a.Pass", "PASS", &target, ""),
+            TestResult::new("tast", "a.Fail", "FAIL", &target, "got <1> | want 2\nstack"),
+        ];
+        let xml = junit("tast run", &results);
+        assert!(xml.contains(r#"<testsuite name="eve_1" tests="2" failures="1" skipped="0">"#));
+        assert!(xml.contains(r#"<testcase classname="tast" name="a.Pass"/>"#));
+        assert!(xml.contains(r#"<failure message="got &lt;1&gt; | want 2">"#));
+        let md = markdown("tast run", &results);
+        assert!(md.contains("1 passed, 1 failed, 0 skipped"));
+        let rows: Vec<&str> = md.lines().filter(|l| l.starts_with("| ")).collect();
+        assert_eq!(
+            rows[1],
+            r"| **FAIL** | a.Fail | eve_1 | eve | 15662.0.0 | got <1> \| want 2 |"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::dut::SshInfo;
+use crate::share::fetch_artifact;
+use crate::util::gen_path_in_lium_dir;
+use crate::util::lium_dir;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::fs;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Kinds of debug symbols archived for each build
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// split DWARF debug info for gdb (debug/usr/...)
+    Debug,
+    /// breakpad symbols for minidump_stackwalk (debug/breakpad/...)
+    Breakpad,
+}
+impl SymbolKind {
+    fn archive_name(&self) -> &'static str {
+        match self {
+            SymbolKind::Debug => "debug.tgz",
+            SymbolKind::Breakpad => "debug_breakpad.tar.xz",
+        }
+    }
+}
+
+/// Returns the builder path of the build on the DUT (e.g. brya-release/R120-15662.0.0)
+pub fn builder_path_of_dut(ssh: &SshInfo) -> Result<String> {
+    let path = ssh.run_cmd_stdio(
+        "cat /etc/lsb-release | grep CHROMEOS_RELEASE_BUILDER_PATH= | cut -d '=' -f 2",
+    )?;
+    if path.is_empty() {
+        return Err(anyhow!(
+            "The DUT does not have a builder path (is it a local build?)"
+        ));
+    }
+    Ok(path)
+}
+
+/// Returns the local dir for the symbols of the build. debug/ and debug/breakpad/ are
+/// extracted under it.
+pub fn symbols_dir(builder_path: &str) -> Result<PathBuf> {
+    let dir = gen_path_in_lium_dir(&format!("symbols/{builder_path}/.keep"))?;
+    Ok(dir.parent().context("Invalid symbols dir")?.to_path_buf())
+}
+
+fn done_marker(dir: &Path, kind: SymbolKind) -> PathBuf {
+    dir.join(format!(".{}.done", kind.archive_name()))
+}
+
+/// Downloads and extracts the symbols of the build from GS if they are not cached yet.
+/// Returns the dir to be used as debug-file-directory for Debug, or the breakpad symbol dir
+/// for Breakpad.
+pub fn fetch_symbols(builder_path: &str, kind: SymbolKind) -> Result<PathBuf> {
+    let dir = symbols_dir(builder_path)?;
+    let result = match kind {
+        SymbolKind::Debug => dir.join("debug"),
+        SymbolKind::Breakpad => dir.join("debug").join("breakpad"),
+    };
+    if done_marker(&dir, kind).exists() {
+        return Ok(result);
+    }
+    let archive = kind.archive_name();
+    let url = format!("gs://chromeos-image-archive/{builder_path}/{archive}");
+    // Keep the archive in the artifact store so that it can be shared with `lium share serve`
+    let stored = fetch_artifact(&format!("symbols/{builder_path}/{archive}"), &url)?;
+    eprintln!("Extracting {archive}...");
+    Command::new("tar")
+        .arg("-xf")
+        .arg(&stored)
+        .arg("-C")
+        .arg(&dir)
+        .status()
+        .context("Failed to run tar")?
+        .exit_ok()
+        .context(anyhow!("Failed to extract {archive}"))?;
+    fs::write(done_marker(&dir, kind), "")?;
+    Ok(result)
+}
+
+/// Returns the builder paths and the kinds of symbols in the local cache
+pub fn cached_symbols() -> Result<Vec<(String, Vec<SymbolKind>)>> {
+    let root = PathBuf::from(lium_dir()?).join("symbols");
+    let mut result = Vec::new();
+    // Builder paths are <builder>/<version>
+    for builder in fs::read_dir(&root).into_iter().flatten().flatten() {
+        for version in fs::read_dir(builder.path()).into_iter().flatten().flatten() {
+            let kinds: Vec<SymbolKind> = [SymbolKind::Debug, SymbolKind::Breakpad]
+                .into_iter()
+                .filter(|k| done_marker(&version.path(), *k).exists())
+                .collect();
+            result.push((
+                format!(
+                    "{}/{}",
+                    builder.file_name().to_string_lossy(),
+                    version.file_name().to_string_lossy()
+                ),
+                kinds,
+            ));
+        }
+    }
+    result.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(result)
+}
+
+pub fn remove_symbols(builder_path: &str) -> Result<()> {
+    if builder_path.contains("..") {
+        return Err(anyhow!("Invalid builder path: {builder_path}"));
+    }
+    let dir = PathBuf::from(lium_dir()?)
+        .join("symbols")
+        .join(builder_path);
+    if !dir.is_dir() {
+        return Err(anyhow!("No symbols for {builder_path} in the cache"));
+    }
+    fs::remove_dir_all(&dir)?;
+    Ok(())
+}
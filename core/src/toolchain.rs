@@ -0,0 +1,185 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use crate::chroot::Chroot;
+use crate::dut::SshInfo;
+use crate::util::command_exists;
+use crate::util::ensure_online;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use std::env;
+use std::path::Path;
+use std::process::Command;
+
+/// Returns the host path of /build/<board> in the chroot of the checkout, if it exists.
+/// It is out/build or chroot/build depending on the chroot layout.
+pub fn board_sysroot_on_host(repo: &str, board: &str) -> Option<String> {
+    ["out", "chroot"]
+        .iter()
+        .map(|d| format!("{repo}/{d}/build/{board}"))
+        .find(|p| Path::new(p).is_dir())
+}
+
+/// A cross toolchain to build programs for a DUT
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toolchain {
+    /// arch as returned by SshInfo::get_arch()
+    pub arch: String,
+    /// cros toolchain target (e.g. aarch64-cros-linux-gnu)
+    pub cros_target: &'static str,
+    /// Rust target triple that produces static binaries (e.g. aarch64-unknown-linux-musl)
+    pub rust_target: &'static str,
+    /// board of the DUT, if resolved from a DUT
+    pub board: Option<String>,
+    /// board sysroot on the host (e.g. ~/chromiumos/out/build/brya)
+    pub sysroot: Option<String>,
+}
+impl Toolchain {
+    pub fn for_arch(arch: &str) -> Result<Self> {
+        let (cros_target, rust_target) = match arch {
+            "x86_64" => ("x86_64-cros-linux-gnu", "x86_64-unknown-linux-musl"),
+            "arm64" => ("aarch64-cros-linux-gnu", "aarch64-unknown-linux-musl"),
+            "armv7l" => (
+                "armv7a-cros-linux-gnueabihf",
+                "armv7-unknown-linux-musleabihf",
+            ),
+            _ => return Err(anyhow!("Unsupported arch: {arch}")),
+        };
+        Ok(Self {
+            arch: arch.to_string(),
+            cros_target,
+            rust_target,
+            board: None,
+            sysroot: None,
+        })
+    }
+    /// Resolves the toolchain for the DUT. The board sysroot is looked up in `repo` if given.
+    pub fn for_dut(ssh: &SshInfo, repo: Option<&str>) -> Result<Self> {
+        let mut arch = ssh.get_arch()?;
+        if arch == "arm64"
+            && ssh
+                .run_cmd_stdio("file -L /bin/sh")
+                .map(|s| s.contains("32-bit"))
+                .unwrap_or(false)
+        {
+            // 32-bit userland on a 64-bit kernel
+            arch = "armv7l".to_string();
+        }
+        let mut toolchain = Self::for_arch(&arch)?;
+        let board = ssh.get_board()?;
+        if let Some(repo) = repo {
+            toolchain.sysroot = board_sysroot_on_host(repo, &board);
+        }
+        toolchain.board = Some(board);
+        Ok(toolchain)
+    }
+    /// Returns the gdb for this arch in the chroot
+    pub fn gdb(&self) -> String {
+        format!("{}-gdb", self.cros_target)
+    }
+    /// Returns the C compiler (without the leading "CC="). The cros cross compiler is used if
+    /// it is in PATH (e.g. in the chroot). Otherwise, the host clang is used with the sysroot.
+    pub fn cc(&self) -> Result<String> {
+        self.compiler("clang")
+    }
+    pub fn cxx(&self) -> Result<String> {
+        self.compiler("clang++")
+    }
+    fn compiler(&self, name: &str) -> Result<String> {
+        let cros = format!("{}-{name}", self.cros_target);
+        if command_exists(&cros) {
+            return Ok(cros);
+        }
+        let sysroot = self.sysroot.as_ref().context(anyhow!(
+            "{cros} is not found and no board sysroot is available. Run this in the chroot, or specify --repo with a board set up (setup_board)"
+        ))?;
+        if !command_exists(name) {
+            return Err(anyhow!("Neither {cros} nor {name} is found"));
+        }
+        Ok(format!(
+            "{name} --target={} --sysroot={sysroot}",
+            self.cros_target
+        ))
+    }
+    /// Returns environment variables to build for this toolchain
+    pub fn env(&self) -> Vec<(String, String)> {
+        let mut env = vec![
+            (
+                "CARGO_BUILD_TARGET".to_string(),
+                self.rust_target.to_string(),
+            ),
+            (
+                // Static musl binaries can be linked without a cross linker
+                format!(
+                    "CARGO_TARGET_{}_LINKER",
+                    self.rust_target.to_uppercase().replace('-', "_")
+                ),
+                "rust-lld".to_string(),
+            ),
+        ];
+        if let Ok(cc) = self.cc() {
+            env.push(("CC".to_string(), cc));
+        }
+        if let Ok(cxx) = self.cxx() {
+            env.push(("CXX".to_string(), cxx));
+        }
+        if let Some(sysroot) = &self.sysroot {
+            env.push(("SYSROOT".to_string(), sysroot.clone()));
+        }
+        env
+    }
+    /// Applies env() to the command, without overriding the ones set by the user
+    pub fn apply_env(&self, cmd: &mut Command) {
+        for (k, v) in self.env() {
+            if env::var_os(&k).is_none() {
+                cmd.env(k, v);
+            }
+        }
+    }
+    /// Installs missing pieces: the Rust target via rustup, and the cros toolchain and the
+    /// board sysroot in the chroot if given
+    pub fn install(&self, chroot: Option<&Chroot>) -> Result<()> {
+        ensure_online("installing toolchains")?;
+        Command::new("rustup")
+            .args(["target", "add", self.rust_target])
+            .status()
+            .context("Failed to run rustup")?
+            .exit_ok()
+            .context("rustup target add failed")?;
+        if let Some(chroot) = chroot {
+            let board = self
+                .board
+                .as_ref()
+                .context("Board is unknown. Please resolve the toolchain from a DUT")?;
+            chroot.run_bash_script_in_chroot(
+                "setup_toolchains",
+                &format!(
+                    "sudo cros_setup_toolchains --targets=boards --include-boards={board} && \
+                     (test -d /build/{board} || setup_board --board={board})"
+                ),
+                None,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn toolchain_env() {
+        let t = Toolchain::for_arch("arm64").unwrap();
+        assert_eq!(t.gdb(), "aarch64-cros-linux-gnu-gdb");
+        assert!(t.env().contains(&(
+            "CARGO_TARGET_AARCH64_UNKNOWN_LINUX_MUSL_LINKER".to_string(),
+            "rust-lld".to_string()
+        )));
+        assert!(Toolchain::for_arch("mips").is_err());
+    }
+}
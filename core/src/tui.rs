@@ -0,0 +1,249 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A small full-screen TUI layer on crossterm and tui for views that redraw periodically (e.g.
+//! `lium dut monitor`). A frame is drawn as lines that are clipped to the current size of the
+//! terminal, so that resizing never wraps or scrolls the screen, and the body scrolls with the
+//! wheel or keys. Keys and mouse events are read without blocking, and non-ASCII characters are
+//! replaced on terminals that are not UTF-8.
+
+use crate::cancel;
+use crate::ui::use_unicode;
+use anyhow::Result;
+use crossterm::event;
+use crossterm::event::DisableMouseCapture;
+use crossterm::event::EnableMouseCapture;
+use crossterm::event::Event as TermEvent;
+use crossterm::event::KeyCode;
+use crossterm::event::KeyEvent;
+use crossterm::event::KeyEventKind;
+use crossterm::event::KeyModifiers;
+use crossterm::event::MouseButton;
+use crossterm::event::MouseEvent;
+use crossterm::event::MouseEventKind;
+use crossterm::execute;
+use crossterm::terminal;
+use crossterm::terminal::EnterAlternateScreen;
+use crossterm::terminal::LeaveAlternateScreen;
+use std::io::stdout;
+use std::io::Stdout;
+use std::time::Duration;
+use std::time::Instant;
+use tui::backend::CrosstermBackend;
+use tui::layout::Rect;
+use tui::text::Spans;
+use tui::widgets::Paragraph;
+use tui::Terminal;
+use unicode_width::UnicodeWidthChar;
+
+const TAB_WIDTH: usize = 8;
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A key pressed on the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    /// A character with the Ctrl key (e.g. `Ctrl('c')`)
+    Ctrl(char),
+    Up,
+    Down,
+    PageUp,
+    PageDown,
+    Other,
+}
+impl From<KeyEvent> for Key {
+    fn from(key: KeyEvent) -> Self {
+        match key.code {
+            KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => Key::Ctrl(c),
+            KeyCode::Char(c) => Key::Char(c),
+            KeyCode::Up => Key::Up,
+            KeyCode::Down => Key::Down,
+            KeyCode::PageUp => Key::PageUp,
+            KeyCode::PageDown => Key::PageDown,
+            _ => Key::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Key(Key),
+    /// Left click on the line of the body at the index
+    Click(usize),
+    /// The terminal was resized or the body was scrolled, and the frame needs to be drawn again
+    Redraw,
+}
+
+/// The terminal in the alternate screen and the raw mode with the mouse reporting. The terminal is
+/// restored when this is dropped. Ctrl-C does not raise SIGINT in the raw mode, and is returned as
+/// `Key::Ctrl('c')` instead.
+pub struct Screen {
+    terminal: Terminal<CrosstermBackend<Stdout>>,
+    size: (u16, u16),
+    header_len: usize,
+    /// Index of the first line of the body on the screen
+    scroll: usize,
+    body_len: usize,
+}
+impl Screen {
+    pub fn new() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        if let Err(e) = execute!(out, EnterAlternateScreen, EnableMouseCapture) {
+            let _ = terminal::disable_raw_mode();
+            return Err(e.into());
+        }
+        Ok(Self {
+            terminal: Terminal::new(CrosstermBackend::new(out))?,
+            size: terminal::size().unwrap_or((80, 24)),
+            header_len: 0,
+            scroll: 0,
+            body_len: 0,
+        })
+    }
+    fn body_height(&self) -> usize {
+        (self.size.1 as usize).saturating_sub(self.header_len)
+    }
+    /// Scrolls the body by the lines, within the lines drawn last
+    pub fn scroll_by(&mut self, lines: isize) {
+        let max = self.body_len.saturating_sub(self.body_height());
+        self.scroll = self.scroll.saturating_add_signed(lines).min(max);
+    }
+    /// Draws the header lines fixed at the top and the body lines below them
+    pub fn draw(&mut self, header: &[String], body: &[String]) -> Result<()> {
+        self.header_len = header.len();
+        self.body_len = body.len();
+        self.scroll_by(0);
+        let (width, height) = self.size;
+        let lines: Vec<Spans> = header
+            .iter()
+            .chain(body.iter().skip(self.scroll))
+            .take(height as usize)
+            .map(|l| Spans::from(fit_line(l, width as usize)))
+            .collect();
+        self.terminal.draw(|f| {
+            let area = Rect::new(0, 0, width, height).intersection(f.size());
+            f.render_widget(Paragraph::new(lines), area);
+        })?;
+        Ok(())
+    }
+    /// Waits for an event up to the timeout. Wheel and page keys scroll the body without
+    /// returning.
+    pub fn next_event(&mut self, timeout: Duration) -> Result<Option<Event>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            cancel::check()?;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if !event::poll(remaining.min(POLL_INTERVAL))? {
+                if remaining.is_zero() {
+                    return Ok(None);
+                }
+                continue;
+            }
+            let page = self.body_height().max(1) as isize;
+            let scroll = match event::read()? {
+                TermEvent::Resize(width, height) => {
+                    self.size = (width, height);
+                    return Ok(Some(Event::Redraw));
+                }
+                TermEvent::Key(key) if key.kind == KeyEventKind::Release => continue,
+                TermEvent::Key(key) => match Key::from(key) {
+                    Key::Up => -1,
+                    Key::Down => 1,
+                    Key::PageUp => -page,
+                    Key::PageDown => page,
+                    key => return Ok(Some(Event::Key(key))),
+                },
+                TermEvent::Mouse(MouseEvent { kind, row, .. }) => match kind {
+                    MouseEventKind::ScrollUp => -3,
+                    MouseEventKind::ScrollDown => 3,
+                    MouseEventKind::Down(MouseButton::Left) => {
+                        // row is 0-based
+                        match (row as usize).checked_sub(self.header_len) {
+                            Some(i) if self.scroll + i < self.body_len => {
+                                return Ok(Some(Event::Click(self.scroll + i)))
+                            }
+                            _ => continue,
+                        }
+                    }
+                    _ => continue,
+                },
+                _ => continue,
+            };
+            self.scroll_by(scroll);
+            return Ok(Some(Event::Redraw));
+        }
+    }
+}
+impl Drop for Screen {
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.terminal.backend_mut(),
+            DisableMouseCapture,
+            LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+        let _ = self.terminal.show_cursor();
+    }
+}
+
+/// Expands tabs and cuts the line at the display width, replacing non-ASCII characters with '?' if the
+/// terminal is not UTF-8
+pub fn fit_line(line: &str, width: usize) -> String {
+    fit_line_with(line, width, use_unicode())
+}
+
+fn fit_line_with(line: &str, width: usize, unicode: bool) -> String {
+    let mut out = String::new();
+    let mut col = 0;
+    for c in line.chars() {
+        if col >= width {
+            break;
+        }
+        if c == '\t' {
+            let next = (col / TAB_WIDTH + 1) * TAB_WIDTH;
+            let next = next.min(width);
+            out.extend(std::iter::repeat(' ').take(next - col));
+            col = next;
+        } else if !c.is_control() {
+            let c = if unicode || c.is_ascii() { c } else { '?' };
+            let w = UnicodeWidthChar::width(c).unwrap_or(0);
+            if col + w > width {
+                break;
+            }
+            out.push(c);
+            col += w;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fit() {
+        assert_eq!(fit_line_with("a\tb", 20, true), "a       b");
+        assert_eq!(fit_line_with("abcdef", 4, true), "abcd");
+        assert_eq!(fit_line_with("ab\tcd", 5, true), "ab   ");
+        assert_eq!(fit_line_with("✓ ok\r", 10, true), "✓ ok");
+        assert_eq!(fit_line_with("✓ ok", 10, false), "? ok");
+        assert_eq!(fit_line_with("東京タワー", 5, true), "東京");
+    }
+
+    #[test]
+    fn keys() {
+        let key = |code, modifiers| Key::from(KeyEvent::new(code, modifiers));
+        assert_eq!(key(KeyCode::Char('q'), KeyModifiers::NONE), Key::Char('q'));
+        assert_eq!(
+            key(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Key::Ctrl('c')
+        );
+        assert_eq!(key(KeyCode::PageDown, KeyModifiers::NONE), Key::PageDown);
+        assert_eq!(key(KeyCode::F(1), KeyModifiers::NONE), Key::Other);
+    }
+}
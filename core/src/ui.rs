@@ -0,0 +1,443 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Helpers for human-readable output. Progress messages go through `status!` and warnings
+//! through `warning!`, so that `--quiet` and `--porcelain` apply consistently. Porcelain mode
+//! keeps stdout stable for scripts: no colors, no unicode symbols and no progress messages.
+
+use crate::progress::eprintln_above;
+use crate::query::lookup;
+use anyhow::anyhow;
+use anyhow::Result;
+use std::collections::HashMap;
+use std::env;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU8;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use termion::color;
+use unicode_width::UnicodeWidthStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+impl FromStr for ColorMode {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => Err(anyhow!(
+                "Invalid color mode {s:?}. Use auto, always or never"
+            )),
+        }
+    }
+}
+
+static COLOR_MODE: AtomicU8 = AtomicU8::new(ColorMode::Auto as u8);
+static QUIET: AtomicBool = AtomicBool::new(false);
+static PORCELAIN: AtomicBool = AtomicBool::new(false);
+/// 0 for no limit
+static MAX_COLUMN_WIDTH: AtomicUsize = AtomicUsize::new(0);
+static NO_ELLIPSIS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_color_mode(mode: ColorMode) {
+    COLOR_MODE.store(mode as u8, Ordering::SeqCst);
+}
+fn color_mode() -> ColorMode {
+    match COLOR_MODE.load(Ordering::SeqCst) {
+        x if x == ColorMode::Always as u8 => ColorMode::Always,
+        x if x == ColorMode::Never as u8 => ColorMode::Never,
+        _ => ColorMode::Auto,
+    }
+}
+/// Suppresses progress messages
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::SeqCst);
+}
+/// Returns true if `--quiet` or `--porcelain` is given
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::SeqCst) || is_porcelain()
+}
+/// Makes the output stable for scripts (implies --quiet and no colors)
+pub fn set_porcelain(porcelain: bool) {
+    PORCELAIN.store(porcelain, Ordering::SeqCst);
+}
+pub fn is_porcelain() -> bool {
+    PORCELAIN.load(Ordering::SeqCst)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+/// Returns true if the output to the stream should be colored
+pub fn use_color(stream: Stream) -> bool {
+    match color_mode() {
+        ColorMode::Always => true,
+        ColorMode::Never => false,
+        ColorMode::Auto => {
+            let is_tty = match stream {
+                Stream::Stdout => termion::is_tty(&std::io::stdout()),
+                Stream::Stderr => termion::is_tty(&std::io::stderr()),
+            };
+            is_tty
+                && !is_porcelain()
+                && env::var_os("NO_COLOR").is_none()
+                && env::var("TERM").map_or(true, |t| t != "dumb")
+        }
+    }
+}
+/// Returns true if the terminal can show unicode symbols
+pub fn use_unicode() -> bool {
+    !is_porcelain()
+        && ["LC_ALL", "LC_CTYPE", "LANG"]
+            .iter()
+            .find_map(|k| env::var(k).ok().filter(|v| !v.is_empty()))
+            .map_or(false, |v| {
+                let v = v.to_lowercase();
+                v.contains("utf-8") || v.contains("utf8")
+            })
+}
+/// Returns `unicode` if the terminal supports it, `ascii` otherwise
+pub fn symbol<'a>(unicode: &'a str, ascii: &'a str) -> &'a str {
+    if use_unicode() {
+        unicode
+    } else {
+        ascii
+    }
+}
+
+/// Truncates the cells of tables wider than the width
+pub fn set_max_column_width(width: Option<usize>) {
+    MAX_COLUMN_WIDTH.store(width.unwrap_or(0), Ordering::SeqCst);
+}
+/// Cuts truncated cells without the ellipsis
+pub fn set_no_ellipsis(no_ellipsis: bool) {
+    NO_ELLIPSIS.store(no_ellipsis, Ordering::SeqCst);
+}
+/// Splits the text into escape sequences for colors (true) and characters (false)
+fn split_escapes(text: &str) -> Vec<(bool, String)> {
+    let mut parts = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' && chars.peek() == Some(&'[') {
+            let mut seq = String::from(c);
+            for c in chars.by_ref() {
+                seq.push(c);
+                if ('@'..='~').contains(&c) && c != '[' {
+                    break;
+                }
+            }
+            parts.push((true, seq));
+        } else {
+            parts.push((false, c.to_string()));
+        }
+    }
+    parts
+}
+/// Returns the number of columns the text takes on terminals (e.g. 2 for each CJK character).
+/// Escape sequences for colors take no column.
+pub fn display_width(text: &str) -> usize {
+    split_escapes(text)
+        .iter()
+        .filter(|(is_escape, _)| !is_escape)
+        .map(|(_, c)| UnicodeWidthStr::width(c.as_str()))
+        .sum()
+}
+/// Cuts the text to fit in the width, ending it with `ellipsis` if it is cut. Escape sequences
+/// are kept so that the colors are reset.
+pub fn truncate_to_width(text: &str, width: usize, ellipsis: &str) -> String {
+    if display_width(text) <= width {
+        return text.to_string();
+    }
+    let width = width.saturating_sub(display_width(ellipsis));
+    let mut out = String::new();
+    let mut used = 0;
+    let mut cut = false;
+    for (is_escape, part) in split_escapes(text) {
+        if is_escape {
+            out += &part;
+            continue;
+        }
+        let w = UnicodeWidthStr::width(part.as_str());
+        cut |= used + w > width;
+        if !cut {
+            out += &part;
+            used += w;
+        }
+    }
+    out + ellipsis
+}
+/// Pads the text with spaces to the display width
+pub fn pad_to_width(text: &str, width: usize) -> String {
+    format!(
+        "{text}{}",
+        " ".repeat(width.saturating_sub(display_width(text)))
+    )
+}
+
+/// Rows of cells aligned by the display width of the widest cell in each column, so that wide
+/// characters and long values (e.g. IPv6 addresses) do not break the alignment. Cells are
+/// truncated by `--max-column-width`.
+#[derive(Debug, Clone, Default)]
+pub struct Table {
+    rows: Vec<Vec<String>>,
+    min_widths: Vec<usize>,
+}
+impl Table {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Keeps the columns at least as wide as the widths, e.g. for the output that used to be
+    /// fixed-width
+    pub fn with_min_widths(min_widths: &[usize]) -> Self {
+        Self {
+            rows: Vec::new(),
+            min_widths: min_widths.to_vec(),
+        }
+    }
+    pub fn push<I: IntoIterator<Item = S>, S: Into<String>>(&mut self, row: I) {
+        self.rows.push(row.into_iter().map(|c| c.into()).collect());
+    }
+    /// Returns the lines of the rows with the cells separated by `sep`. The last cell of a row
+    /// is not padded.
+    pub fn lines(&self, sep: &str) -> Vec<String> {
+        let max = MAX_COLUMN_WIDTH.load(Ordering::SeqCst);
+        let ellipsis = if NO_ELLIPSIS.load(Ordering::SeqCst) {
+            ""
+        } else {
+            symbol("…", "...")
+        };
+        let rows: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|c| {
+                        if max == 0 {
+                            c.clone()
+                        } else {
+                            truncate_to_width(c, max, ellipsis)
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+        let mut widths = self.min_widths.clone();
+        for row in &rows {
+            for (i, c) in row.iter().enumerate() {
+                if widths.len() <= i {
+                    widths.push(0);
+                }
+                widths[i] = widths[i].max(display_width(c));
+            }
+        }
+        rows.iter()
+            .map(|row| {
+                row.iter()
+                    .enumerate()
+                    .map(|(i, c)| {
+                        if i + 1 == row.len() {
+                            c.clone()
+                        } else {
+                            pad_to_width(c, widths[i])
+                        }
+                    })
+                    .collect::<Vec<String>>()
+                    .join(sep)
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Style {
+    Good,
+    Warning,
+    Bad,
+    Dim,
+}
+/// Returns the text colored for the stream if colors are enabled
+pub fn styled(text: &str, style: Style, stream: Stream) -> String {
+    if !use_color(stream) {
+        return text.to_string();
+    }
+    let fg = match style {
+        Style::Good => color::Fg(color::Green).to_string(),
+        Style::Warning => color::Fg(color::Yellow).to_string(),
+        Style::Bad => color::Fg(color::Red).to_string(),
+        Style::Dim => color::Fg(color::LightBlack).to_string(),
+    };
+    format!("{fg}{text}{}", color::Fg(color::Reset))
+}
+
+#[doc(hidden)]
+pub fn print_status(args: fmt::Arguments) {
+    if !is_quiet() {
+        eprintln_above(&args.to_string());
+    }
+}
+#[doc(hidden)]
+pub fn print_warning(args: fmt::Arguments) {
+    eprintln_above(&format!(
+        "{}: {args}",
+        styled("WARNING", Style::Warning, Stream::Stderr)
+    ));
+}
+
+/// Prints a progress message to stderr unless `--quiet` or `--porcelain` is given
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        $crate::ui::print_status(format_args!($($arg)*))
+    };
+}
+/// Prints a warning to stderr
+#[macro_export]
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::ui::print_warning(format_args!($($arg)*))
+    };
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Key(String),
+}
+
+/// A template given with `--format` (e.g. '{id}\t{model}\t{release}').
+/// `\t` and `\n` are unescaped to make it easy to pass them from shells, and `{{` and `}}` are
+/// literal braces. Keys not in the values are rendered as empty strings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    parts: Vec<Part>,
+}
+impl Template {
+    /// Returns the keys used in the template
+    pub fn keys(&self) -> Vec<&str> {
+        self.parts
+            .iter()
+            .filter_map(|p| match p {
+                Part::Key(k) => Some(k.as_str()),
+                Part::Text(_) => None,
+            })
+            .collect()
+    }
+    pub fn render(&self, values: &HashMap<String, String>) -> String {
+        self.parts
+            .iter()
+            .map(|p| match p {
+                Part::Text(t) => t.as_str(),
+                Part::Key(k) => lookup(values, k).unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+impl FromStr for Template {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                }
+                ('\\', Some('t')) => {
+                    chars.next();
+                    text.push('\t');
+                }
+                ('\\', Some('n')) => {
+                    chars.next();
+                    text.push('\n');
+                }
+                ('{', _) => {
+                    let mut key = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        key.push(c);
+                    }
+                    let key = key.trim();
+                    if !closed || key.is_empty() || key.contains('{') {
+                        return Err(anyhow!("Invalid key in the format {s:?}"));
+                    }
+                    if !text.is_empty() {
+                        parts.push(Part::Text(std::mem::take(&mut text)));
+                    }
+                    parts.push(Part::Key(key.to_string()));
+                }
+                ('}', _) => return Err(anyhow!("Unmatched '}}' in the format {s:?}")),
+                _ => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        Ok(Self { parts })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn template() {
+        let values = HashMap::from([
+            ("dut_id".to_string(), "eve_X".to_string()),
+            ("model".to_string(), "eve".to_string()),
+        ]);
+        let t = Template::from_str(r"{id}\t{model}\t{release} {{x}}").unwrap();
+        assert_eq!(t.keys(), vec!["id", "model", "release"]);
+        assert_eq!(t.render(&values), "eve_X\teve\t {x}");
+        assert!(Template::from_str("{}").is_err());
+        assert!(Template::from_str("a}").is_err());
+        assert!(Template::from_str("{id").is_err());
+    }
+
+    #[test]
+    fn color_mode() {
+        assert_eq!(ColorMode::from_str("never").unwrap(), ColorMode::Never);
+        assert!(ColorMode::from_str("yes").is_err());
+        set_color_mode(ColorMode::Never);
+        assert_eq!(styled("x", Style::Bad, Stream::Stdout), "x");
+        set_color_mode(ColorMode::Auto);
+    }
+
+    #[test]
+    fn table() {
+        assert_eq!(display_width("東京"), 4);
+        assert_eq!(truncate_to_width("東京タワー", 7, "..."), "東京...");
+        assert_eq!(truncate_to_width("abc", 3, "..."), "abc");
+        assert_eq!(display_width("\x1b[32mok\x1b[39m"), 2);
+        assert_eq!(
+            truncate_to_width("\x1b[32mOnline\x1b[39m", 4, "~"),
+            "\x1b[32mOnl\x1b[39m~"
+        );
+        let mut t = Table::with_min_widths(&[4]);
+        t.push(["東京", "x", "1"]);
+        t.push(["a", "[2001:db8::1]:22", "2"]);
+        assert_eq!(
+            t.lines(" "),
+            ["東京 x                1", "a    [2001:db8::1]:22 2"]
+        );
+    }
+}
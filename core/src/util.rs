@@ -0,0 +1,228 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use async_io::Timer;
+use async_process::Child;
+use async_process::ChildStderr;
+use async_process::ChildStdout;
+use async_process::Stdio;
+use dirs::home_dir;
+use futures::executor::block_on;
+use futures::future;
+use futures::future::Either;
+use futures::io::BufReader;
+use futures::io::Lines;
+use futures::stream;
+use futures::AsyncBufReadExt;
+use futures::Future;
+use futures::StreamExt;
+use nix::errno::Errno;
+use nix::sys::signal::kill;
+use nix::unistd::Pid;
+use std::env::current_exe;
+use std::fs::create_dir_all;
+use std::io::ErrorKind;
+use std::path::Path;
+use std::path::PathBuf;
+use std::process::Command;
+use std::process::Output;
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+static OFFLINE_MODE: AtomicBool = AtomicBool::new(false);
+
+/// Enables the offline mode, in which operations that need the network fail fast.
+pub fn set_offline_mode(offline: bool) {
+    OFFLINE_MODE.store(offline, Ordering::SeqCst);
+}
+/// Returns true if `--offline` is given or LIUM_OFFLINE is set
+pub fn is_offline_mode() -> bool {
+    OFFLINE_MODE.load(Ordering::SeqCst) || std::env::var("LIUM_OFFLINE").is_ok()
+}
+/// Returns an error if lium is in the offline mode. `what` describes the operation.
+pub fn ensure_online(what: &str) -> Result<()> {
+    if is_offline_mode() {
+        Err(anyhow!(
+            "{what} needs the network, but lium is in the offline mode (--offline or LIUM_OFFLINE)"
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn has_root_privilege() -> Result<bool> {
+    let output = run_bash_command("id -u", None)?;
+    output
+        .status
+        .exit_ok()
+        .context("Failed to get current uid")?;
+    Ok(get_stdout(&output).trim() == "0")
+}
+/// Usage of this should be minimized, to avoid environment variable related issues.
+/// Current use cases are:
+/// - Resetting servo by writing to sysfs
+pub fn run_lium_with_sudo(args: &[&str]) -> Result<()> {
+    let mut c = Command::new("sudo");
+    let status = c
+        .arg("--preserve-env=HOME,PATH")
+        .arg(current_exe()?)
+        .args(args)
+        .status()
+        .context("Failed to run lium with sudo")?;
+    status.exit_ok().context(anyhow!(
+        "`sudo lium {} exited with {:?}`",
+        args.join(" "),
+        status.code()
+    ))
+}
+
+/// Runs lium with the args in a detached process, ignoring its output.
+pub fn spawn_lium_in_background(args: &[&str]) -> Result<()> {
+    Command::new(current_exe()?)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .context(anyhow!("Failed to spawn `lium {}`", args.join(" ")))?;
+    Ok(())
+}
+
+/// Returns true if the process exists, even if it is owned by someone else
+pub fn is_process_alive(pid: u32) -> bool {
+    matches!(
+        kill(Pid::from_raw(pid as i32), None),
+        Ok(_) | Err(Errno::EPERM)
+    )
+}
+
+pub fn lium_dir() -> Result<String> {
+    gen_path_in_lium_dir(".keep").and_then(|mut path| {
+        path.pop();
+        Ok(path.to_str().context("Failed to get lium dir")?.to_string())
+    })
+}
+
+pub fn gen_path_in_lium_dir(name: &str) -> Result<PathBuf> {
+    const WORKING_DIR_NAME: &str = ".lium";
+
+    let path = &home_dir().context("Failed to determine home dir")?;
+    let path = Path::new(path);
+    let path = path.join(WORKING_DIR_NAME);
+    let path = path.join(name);
+
+    let mut dir = path.clone();
+    dir.pop();
+    if let Err(e) = create_dir_all(&dir) {
+        if e.kind() != ErrorKind::AlreadyExists {
+            return Err(e).context("Failed to create a dir");
+        }
+    }
+
+    Ok(path)
+}
+
+fn checksum(tool: &str, path: &Path) -> Result<String> {
+    let output = Command::new(tool)
+        .arg(path)
+        .output()
+        .context(anyhow!("Failed to run {tool}"))?;
+    output.status.exit_ok().context(anyhow!("{tool} failed"))?;
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .next()
+        .context(anyhow!("Unexpected output from {tool}"))?
+        .to_string())
+}
+
+/// Returns the SHA-256 of the file as a hex string
+pub fn sha256sum(path: &Path) -> Result<String> {
+    checksum("sha256sum", path)
+}
+
+/// Returns the MD5 of the file as a hex string
+pub fn md5sum(path: &Path) -> Result<String> {
+    checksum("md5sum", path)
+}
+
+pub fn get_stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout)
+        .to_string()
+        .trim()
+        .to_string()
+}
+pub fn get_stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr)
+        .to_string()
+        .trim()
+        .to_string()
+}
+pub fn get_async_lines(
+    child: &mut Child,
+) -> (Lines<BufReader<ChildStdout>>, Lines<BufReader<ChildStderr>>) {
+    let lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let lines_err = BufReader::new(child.stderr.take().unwrap()).lines();
+    (lines, lines_err)
+}
+
+/// Returns an error if the future does not complete in the duration. The future is dropped
+/// then, which kills child processes spawned with kill_on_drop(true).
+pub async fn with_timeout<T>(duration: Duration, f: impl Future<Output = Result<T>>) -> Result<T> {
+    match future::select(Box::pin(f), Timer::after(duration)).await {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Err(anyhow!("Timed out after {} sec", duration.as_secs_f64())),
+    }
+}
+
+/// Runs `f` for each item on the current thread with up to `limit` futures in flight, and
+/// returns the results in the order of completion. This does not need a thread per item, so
+/// that checking hundreds of hosts does not spawn hundreds of threads.
+pub fn run_concurrently<T, R, F: Future<Output = R>>(
+    items: impl IntoIterator<Item = T>,
+    limit: usize,
+    f: impl FnMut(T) -> F,
+) -> Vec<R> {
+    block_on(stream::iter(items).map(f).buffer_unordered(limit).collect())
+}
+
+/// Returns true if the command is in PATH
+pub fn command_exists(cmd: &str) -> bool {
+    run_bash_command(&format!("command -v {cmd}"), None)
+        .map(|o| o.status.success())
+        .unwrap_or(false)
+}
+
+pub fn run_bash_command(cmd: &str, dir: Option<&str>) -> Result<Output> {
+    let mut c = Command::new("bash");
+    let c = if let Some(dir) = dir {
+        c.current_dir(dir)
+    } else {
+        &mut c
+    };
+    c.arg("-c")
+        .arg(cmd)
+        .output()
+        .context("Failed to execute cmd")
+}
+
+pub fn run_bash_command_async(cmd: &str, dir: Option<&str>) -> Result<async_process::Child> {
+    let mut c = async_process::Command::new("bash");
+    let c = if let Some(dir) = dir {
+        c.current_dir(dir)
+    } else {
+        &mut c
+    };
+    c.arg("-c")
+        .arg(cmd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Failed to spawn bash command")
+}
@@ -0,0 +1,229 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Recovery of long-lived connections to DUTs across reboots. A RebootWatch remembers the
+//! boot_id of the DUT at each (re)connection, so that a reboot is noticed even if the connection
+//! came back on its own, and then re-establishes what does not come back by itself (the sshfs
+//! mounts of `lium dut code --sshfs`). Reboots are reported as events (see events.rs).
+//! The tunnels themselves are re-established by their owners: the supervisor of `lium forward`
+//! and the agent on the DUT, which is watched by `lium dut agent watch`.
+
+use crate::agent::DUT_AGENTS;
+use crate::cache::KvCache;
+use crate::cancel;
+use crate::dut::SshInfo;
+use crate::events;
+use crate::util::gen_path_in_lium_dir;
+use crate::warning;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use serde::Deserialize;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::Duration;
+use std::time::Instant;
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SshfsMount {
+    /// Path on the DUT
+    pub path: String,
+}
+/// sshfs mounts keyed by DUT ID
+pub static SSHFS_MOUNTS: KvCache<SshfsMount> = KvCache::new("sshfs_mounts");
+
+/// Returns the local directory where the DUT is mounted
+pub fn sshfs_mountpoint(id: &str) -> Result<PathBuf> {
+    Ok(gen_path_in_lium_dir(&format!("sshfs/{id}/.keep"))?
+        .parent()
+        .context("Failed to get a mountpoint")?
+        .to_path_buf())
+}
+
+/// Mounts the path on the DUT with sshfs and records it to be remounted after reboots
+pub fn mount_sshfs(ssh: &SshInfo, id: &str, path: &str) -> Result<PathBuf> {
+    let mountpoint = sshfs_mountpoint(id)?;
+    let host = if ssh.host().contains(':') {
+        format!("[{}]", ssh.host())
+    } else {
+        ssh.host().to_string()
+    };
+    Command::new("sshfs")
+        .args([
+            "-p",
+            &ssh.port().to_string(),
+            "-o",
+            "IdentityFile=~/.ssh/testing_rsa,StrictHostKeyChecking=no,UserKnownHostsFile=/dev/null,reconnect",
+        ])
+        .arg(format!("root@{host}:{path}"))
+        .arg(&mountpoint)
+        .status()
+        .context("Failed to run sshfs")?
+        .exit_ok()
+        .context("sshfs failed")?;
+    SSHFS_MOUNTS.set(
+        id,
+        SshfsMount {
+            path: path.to_string(),
+        },
+    )?;
+    Ok(mountpoint)
+}
+
+fn is_mounted(mountpoint: &str) -> bool {
+    fs::read_to_string("/proc/mounts").map_or(false, |mounts| {
+        mounts
+            .lines()
+            .any(|l| l.split_whitespace().nth(1) == Some(mountpoint))
+    })
+}
+
+/// Remounts the sshfs mount of the DUT if sshfs gave up on it (e.g. the DUT came back with a
+/// new host key). Mounts unmounted by the user are left as is. Returns true if remounted.
+pub fn remount_sshfs_if_broken(ssh: &SshInfo, id: &str) -> Result<bool> {
+    let Some(mount) = SSHFS_MOUNTS.get(id)? else {
+        return Ok(false);
+    };
+    let mountpoint = sshfs_mountpoint(id)?;
+    let mountpoint_str = mountpoint.to_string_lossy().to_string();
+    if !is_mounted(&mountpoint_str) {
+        SSHFS_MOUNTS.remove(id)?;
+        return Ok(false);
+    }
+    // A dead sshfs leaves the mount that fails with ENOTCONN
+    if fs::read_dir(&mountpoint).is_ok() {
+        return Ok(false);
+    }
+    Command::new("fusermount")
+        .args(["-u", "-z", &mountpoint_str])
+        .status()
+        .context("Failed to run fusermount")?
+        .exit_ok()
+        .context("Failed to unmount the broken sshfs mount")?;
+    mount_sshfs(ssh, id, &mount.path)?;
+    events::emit(
+        id,
+        "sshfs_remounted",
+        &format!("Remounted {} at {mountpoint_str}", mount.path),
+    )?;
+    Ok(true)
+}
+
+/// Waits until the sshd of the DUT accepts connections. Returns false on timeout.
+pub fn wait_for_ssh(ssh: &SshInfo, timeout: Duration) -> Result<bool> {
+    let deadline = Instant::now() + timeout;
+    while ssh.probe_tcp_rtt(Duration::from_secs(2)).is_err() {
+        if Instant::now() > deadline {
+            return Ok(false);
+        }
+        cancel::sleep(Duration::from_secs(2))?;
+    }
+    Ok(true)
+}
+
+/// Tracks the boot_id of a DUT over reconnections
+pub struct RebootWatch {
+    dut: String,
+    boot_id: Option<String>,
+}
+impl RebootWatch {
+    pub fn new(dut: &str) -> Self {
+        Self {
+            dut: dut.to_string(),
+            boot_id: None,
+        }
+    }
+    /// Reads the boot_id of the DUT after a (re)connection. If it changed, reports the reboot
+    /// and remounts the sshfs mount of the DUT. Returns true if the DUT rebooted.
+    pub fn observe(&mut self, ssh: &SshInfo) -> Result<bool> {
+        let boot_id = ssh
+            .get_boot_id()
+            .map_err(|e| anyhow!("Failed to get the boot_id of {}: {e:#}", self.dut))?;
+        let boot_id = boot_id.trim().to_string();
+        let rebooted = self.boot_id.as_ref().map_or(false, |prev| *prev != boot_id);
+        self.boot_id = Some(boot_id);
+        if rebooted {
+            events::emit(&self.dut, "rebooted", "The DUT rebooted")?;
+            if let Err(e) = remount_sshfs_if_broken(ssh, &self.dut) {
+                events::emit(
+                    &self.dut,
+                    "sshfs_remount_failed",
+                    &format!("Failed to remount sshfs: {e:#}"),
+                )?;
+            }
+        }
+        Ok(rebooted)
+    }
+}
+
+/// A tunnel that stays down this long while the DUT is reachable directly gets its agent
+/// restarted
+const AGENT_RESTART_DELAY: Duration = Duration::from_secs(120);
+const CMD_RESTART_AGENT: &str =
+    "initctl restart lium-agent 2>/dev/null || initctl start lium-agent 2>/dev/null || systemctl restart lium-agent";
+
+struct AgentWatch {
+    reboot: RebootWatch,
+    down_since: Option<Instant>,
+}
+
+/// Watches the tunnels of the installed agents until cancelled. When a tunnel comes back, a
+/// reboot of the DUT is detected via its boot_id. An agent whose tunnel stays down while the DUT
+/// is reachable by other means is restarted.
+pub fn watch_agents(interval: Duration) -> Result<()> {
+    cancel::install_handler()?;
+    let mut watches: HashMap<String, AgentWatch> = HashMap::new();
+    loop {
+        let agents = DUT_AGENTS.entries()?;
+        watches.retain(|id, _| agents.contains_key(id));
+        for (id, agent) in agents {
+            let watch = watches.entry(id.clone()).or_insert_with(|| AgentWatch {
+                reboot: RebootWatch::new(&id),
+                down_since: None,
+            });
+            let tunnel = SshInfo::new_host_and_port("127.0.0.1", agent.port)?;
+            if tunnel.probe_tcp_rtt(Duration::from_secs(2)).is_ok() {
+                if watch.down_since.take().is_some() {
+                    events::emit(&id, "tunnel_restored", "The tunnel of the agent is up")?;
+                }
+                if let Err(e) = watch.reboot.observe(&tunnel) {
+                    warning!("{e:#}");
+                }
+                continue;
+            }
+            let Some(down_since) = watch.down_since else {
+                watch.down_since = Some(Instant::now());
+                events::emit(&id, "tunnel_down", "The tunnel of the agent is down")?;
+                continue;
+            };
+            if down_since.elapsed() < AGENT_RESTART_DELAY {
+                continue;
+            }
+            // Try the other ways to reach the DUT
+            let Ok(ssh) = SshInfo::new(&id) else {
+                continue;
+            };
+            if ssh.probe_tcp_rtt(Duration::from_secs(2)).is_err() {
+                continue;
+            }
+            watch.down_since = Some(Instant::now());
+            match ssh.run_cmd_stdio(CMD_RESTART_AGENT) {
+                Ok(_) => events::emit(&id, "agent_restarted", "Restarted the agent")?,
+                Err(e) => events::emit(
+                    &id,
+                    "agent_restart_failed",
+                    &format!("Failed to restart the agent: {e:#}"),
+                )?,
+            }
+        }
+        if cancel::sleep(interval).is_err() {
+            return Ok(());
+        }
+    }
+}
@@ -0,0 +1,235 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! A minimal HTTP endpoint for `lium listen`, which lets CI or chat-ops trigger allowed DUT
+//! actions through this machine:
+//!
+//! ```text
+//! curl -H "Authorization: Bearer $(cat ~/.lium/listen_token)" \
+//!     -d '{"dut":"eve_XXX"}' http://ws:9000/actions/reboot
+//! ```
+//!
+//! Only one request is served per connection, and only the DUTs in SSH_CACHE can be targeted.
+
+use crate::util::gen_path_in_lium_dir;
+use anyhow::anyhow;
+use anyhow::Context;
+use anyhow::Result;
+use rand::distributions::Alphanumeric;
+use rand::thread_rng;
+use rand::Rng;
+use serde::Deserialize;
+use std::fs;
+use std::io::BufRead;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+
+const MAX_BODY_LEN: usize = 64 * 1024;
+const TOKEN_LEN: usize = 32;
+
+/// Returns the token that clients must send, from $LIUM_LISTEN_TOKEN or ~/.lium/listen_token
+/// (generated on the first use)
+pub fn listen_token() -> Result<String> {
+    load_or_create_token("LIUM_LISTEN_TOKEN", "listen_token")
+}
+
+/// Returns the token in the environment variable, or in the file in the lium dir, which is
+/// generated with mode 0600 on the first use
+pub fn load_or_create_token(env: &str, file: &str) -> Result<String> {
+    if let Ok(token) = std::env::var(env) {
+        if !token.is_empty() {
+            return Ok(token);
+        }
+    }
+    let path = gen_path_in_lium_dir(file)?;
+    if let Ok(token) = fs::read_to_string(&path) {
+        if !token.trim().is_empty() {
+            return Ok(token.trim().to_string());
+        }
+    }
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LEN)
+        .map(char::from)
+        .collect();
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&path)
+        .context(anyhow!("Failed to create {path:?}"))?
+        .write_all(token.as_bytes())?;
+    Ok(token)
+}
+
+/// Compares the tokens in a constant time
+pub fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct ActionRequest {
+    pub action: String,
+    pub dut: String,
+}
+
+#[derive(Deserialize)]
+struct ActionBody {
+    dut: String,
+}
+
+/// An error with the HTTP status to respond with
+#[derive(Debug, PartialEq, Eq)]
+pub struct HttpError {
+    pub status: u16,
+    pub message: String,
+}
+fn http_error(status: u16, message: impl Into<String>) -> HttpError {
+    HttpError {
+        status,
+        message: message.into(),
+    }
+}
+
+/// Reads a request of `POST /actions/<action>` with a JSON body `{"dut": "<DUT ID>"}`
+pub fn read_request(
+    reader: &mut impl BufRead,
+    token: &str,
+) -> std::result::Result<ActionRequest, HttpError> {
+    let bad_request = |m: &str| http_error(400, m);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .map_err(|_| bad_request("Failed to read the request"))?;
+    let mut parts = request_line.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    let mut content_length = 0;
+    let mut authorized = false;
+    loop {
+        let mut line = String::new();
+        reader
+            .read_line(&mut line)
+            .map_err(|_| bad_request("Failed to read the headers"))?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(bad_request("Invalid header"));
+        };
+        let value = value.trim();
+        match name.to_ascii_lowercase().as_str() {
+            "content-length" => {
+                content_length = value
+                    .parse()
+                    .map_err(|_| bad_request("Invalid Content-Length"))?
+            }
+            "authorization" => {
+                authorized = value
+                    .strip_prefix("Bearer ")
+                    .map_or(false, |t| token_matches(t.trim(), token))
+            }
+            _ => {}
+        }
+    }
+    if !authorized {
+        return Err(http_error(401, "Missing or wrong bearer token"));
+    }
+    if method != "POST" {
+        return Err(http_error(405, "Only POST is supported"));
+    }
+    let Some(action) = path.strip_prefix("/actions/").filter(|a| !a.is_empty()) else {
+        return Err(http_error(404, "Use /actions/<action>"));
+    };
+    if content_length > MAX_BODY_LEN {
+        return Err(http_error(413, "The body is too large"));
+    }
+    let mut body = vec![0; content_length];
+    reader
+        .read_exact(&mut body)
+        .map_err(|_| bad_request("Failed to read the body"))?;
+    let body: ActionBody = serde_json::from_slice(&body)
+        .map_err(|e| bad_request(&format!("The body must be {{\"dut\": \"<DUT ID>\"}}: {e}")))?;
+    Ok(ActionRequest {
+        action: action.to_string(),
+        dut: body.dut,
+    })
+}
+
+/// Writes a response with a JSON body
+pub fn write_response(
+    writer: &mut impl Write,
+    status: u16,
+    body: &serde_json::Value,
+) -> Result<()> {
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        _ => "Internal Server Error",
+    };
+    let body = body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {status} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::BufReader;
+
+    fn read(raw: &str) -> std::result::Result<ActionRequest, HttpError> {
+        read_request(&mut BufReader::new(raw.as_bytes()), "secret")
+    }
+
+    #[test]
+    fn parse_requests() {
+        let body = r#"{"dut":"eve_XXX"}"#;
+        assert_eq!(
+            read(&format!(
+                "POST /actions/reboot HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: {}\r\n\r\n{body}",
+                body.len()
+            )),
+            Ok(ActionRequest {
+                action: "reboot".to_string(),
+                dut: "eve_XXX".to_string()
+            })
+        );
+        let status = |raw: &str| read(raw).unwrap_err().status;
+        assert_eq!(status("POST /actions/reboot HTTP/1.1\r\n\r\n"), 401);
+        assert_eq!(
+            status("POST /actions/reboot HTTP/1.1\r\nAuthorization: Bearer secreT\r\n\r\n"),
+            401
+        );
+        assert_eq!(
+            status("GET /actions/reboot HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n"),
+            405
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n"),
+            404
+        );
+        assert_eq!(
+            status("POST /actions/reboot HTTP/1.1\r\nAuthorization: Bearer secret\r\nContent-Length: 2\r\n\r\n{}"),
+            400
+        );
+    }
+}
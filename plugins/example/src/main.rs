@@ -0,0 +1,36 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! An example of a plugin crate. Installing the `lium-example` binary in PATH (e.g. with
+//! `cargo install --path plugins/example`) or in ~/.lium/plugins makes it available as
+//! `lium example`. Plugins can use lium-core for the DUT registry and ssh, and call back into
+//! lium via $LIUM.
+
+use anyhow::Result;
+use argh::FromArgs;
+use lium::dut::SSH_CACHE;
+
+#[derive(FromArgs, PartialEq, Debug)]
+/// an example plugin that lists the DUTs known to lium
+struct Args {
+    /// print only the DUTs whose ID contains the string
+    #[argh(option)]
+    filter: Option<String>,
+}
+
+fn main() -> Result<()> {
+    let args: Args = argh::from_env();
+    let mut duts: Vec<_> = SSH_CACHE
+        .entries()?
+        .into_iter()
+        .filter(|(id, _)| args.filter.as_ref().map_or(true, |f| id.contains(f)))
+        .collect();
+    duts.sort_by(|a, b| a.0.cmp(&b.0));
+    for (id, ssh) in duts {
+        println!("{id} {}", ssh.host_and_port());
+    }
+    Ok(())
+}
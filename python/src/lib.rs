@@ -0,0 +1,96 @@
+// Copyright 2023 The ChromiumOS Authors
+//
+// Use of this source code is governed by a BSD-style
+// license that can be found in the LICENSE file or at
+// https://developers.google.com/open-source/licenses/bsd
+
+//! Python bindings of lium, to reuse the DUT registry and the ssh connection logic of lium
+//! from lab automation written in Python. Build with `maturin develop` in this dir.
+//!
+//! ```python
+//! import lium
+//! dut = lium.SshInfo("brya_XXXXXXXX")
+//! print(dut.run("cat /etc/lsb-release"))
+//! dut.push(["./out/foo"], "/usr/local/bin/foo")
+//! ```
+
+use lium_core::dut::register_dut;
+use lium_core::dut::DutInfo;
+use lium_core::dut::SSH_CACHE;
+use pyo3::exceptions::PyKeyError;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+/// A connection to a DUT. The DUT identifier is resolved like `lium dut shell --dut`.
+#[pyclass]
+struct SshInfo(lium_core::dut::SshInfo);
+
+#[pymethods]
+impl SshInfo {
+    #[new]
+    fn new(dut: &str) -> PyResult<Self> {
+        Ok(Self(lium_core::dut::SshInfo::new(dut)?))
+    }
+    fn host_and_port(&self) -> String {
+        self.0.host_and_port()
+    }
+    /// Runs a shell command on the DUT and returns its stdout
+    fn run(&self, py: Python<'_>, cmd: &str) -> PyResult<String> {
+        Ok(py.allow_threads(|| self.0.run_cmd_stdio(cmd))?)
+    }
+    /// Sends files to the DUT. They are recorded to be removed by `lium dut sanitize`.
+    fn push(&self, py: Python<'_>, files: Vec<String>, dest: Option<String>) -> PyResult<()> {
+        Ok(py.allow_threads(|| self.0.send_files_audited(&files, dest.as_ref()))?)
+    }
+    /// Copies files from the DUT
+    fn pull(&self, py: Python<'_>, files: Vec<String>, dest: Option<String>) -> PyResult<()> {
+        Ok(py.allow_threads(|| self.0.get_files(&files, dest.as_ref()))?)
+    }
+    fn __repr__(&self) -> String {
+        format!("SshInfo({:?})", self.0.route())
+    }
+}
+
+/// Returns the cached DUTs as a dict of DUT ID -> address
+#[pyfunction]
+fn list_duts() -> PyResult<HashMap<String, String>> {
+    Ok(SSH_CACHE
+        .entries()?
+        .into_iter()
+        .map(|(id, ssh)| (id, ssh.host_and_port()))
+        .collect())
+}
+
+/// Adds a DUT to the cache and returns its ID, like `lium dut info`
+#[pyfunction]
+fn register(py: Python<'_>, dut: &str) -> PyResult<String> {
+    let info = py.allow_threads(|| register_dut(dut))?;
+    Ok(info.id().to_string())
+}
+
+/// Fetches the values of the keys (e.g. "release", "board") from the DUT
+#[pyfunction]
+fn fetch_info(py: Python<'_>, dut: &str, keys: Vec<String>) -> PyResult<HashMap<String, String>> {
+    let ssh = lium_core::dut::SshInfo::new(dut)?;
+    let keys: Vec<&str> = keys.iter().map(|s| s.as_str()).collect();
+    Ok(py.allow_threads(|| DutInfo::fetch_keys(&ssh, &keys))?)
+}
+
+/// Returns the last known value of a key without accessing the DUT
+#[pyfunction]
+fn cached_info(dut: &str, key: &str) -> PyResult<String> {
+    let info = DutInfo::cached_info(dut)?;
+    info.get(key)
+        .map(|v| v.value.clone())
+        .ok_or_else(|| PyKeyError::new_err(format!("{key} is not cached for {dut}")))
+}
+
+#[pymodule]
+fn lium(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<SshInfo>()?;
+    m.add_function(wrap_pyfunction!(list_duts, m)?)?;
+    m.add_function(wrap_pyfunction!(register, m)?)?;
+    m.add_function(wrap_pyfunction!(fetch_info, m)?)?;
+    m.add_function(wrap_pyfunction!(cached_info, m)?)?;
+    Ok(())
+}